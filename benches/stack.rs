@@ -0,0 +1,73 @@
+//! Pushing and popping many states, to measure the cost of the stack storage.
+//! Compare `cargo bench` with `cargo bench --features smallvec`.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use game_state_machine::*;
+
+struct Menu;
+
+impl State<u32> for Menu {
+    fn on_start(&mut self, data: &mut u32) -> StateTransition<u32> {
+        *data += 1;
+        StateTransition::None
+    }
+}
+
+fn push_pop(c: &mut Criterion) {
+    c.bench_function("push and pop 10k states", |b| {
+        let mut sm = StateMachine::<u32>::default();
+        let mut data = 0;
+        b.iter(|| {
+            for _ in 0..10_000 {
+                sm.push(Box::new(Menu), &mut data);
+                sm.push(Box::new(Menu), &mut data);
+                sm.pop(&mut data);
+                sm.pop(&mut data);
+            }
+            black_box(data)
+        });
+    });
+
+    c.bench_function("push 10k states then stop", |b| {
+        let mut sm = StateMachine::<u32>::with_capacity(10_000);
+        let mut data = 0;
+        b.iter(|| {
+            for _ in 0..10_000 {
+                sm.push(Box::new(Menu), &mut data);
+            }
+            sm.stop(&mut data);
+            black_box(data)
+        });
+    });
+}
+
+fn reserve(c: &mut Criterion) {
+    c.bench_function("push 1k states on a stack freed when stopped", |b| {
+        let mut sm = StateMachine::<u32>::default();
+        sm.set_shrink_on_stop(true);
+        let mut data = 0;
+        b.iter(|| {
+            for _ in 0..1_000 {
+                sm.push(Box::new(Menu), &mut data);
+            }
+            sm.stop(&mut data);
+            black_box(data)
+        });
+    });
+
+    c.bench_function("push 1k states on a reserved stack", |b| {
+        let mut sm = StateMachine::<u32>::default();
+        sm.reserve(1_000);
+        let mut data = 0;
+        b.iter(|| {
+            for _ in 0..1_000 {
+                sm.push(Box::new(Menu), &mut data);
+            }
+            sm.stop(&mut data);
+            black_box(data)
+        });
+    });
+}
+
+criterion_group!(benches, push_pop, reserve);
+criterion_main!(benches);
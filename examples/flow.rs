@@ -0,0 +1,80 @@
+//! A menu, a game and a pause screen, with the transitions between them declared by
+//! `state_machine!`.
+
+use game_state_machine::*;
+
+#[derive(Default)]
+struct GameData {
+    frame: u32,
+    log: Vec<String>,
+}
+
+struct Menu;
+struct Playing;
+struct Paused;
+
+state_machine! {
+    enum Flow;
+    data: GameData;
+    states { Menu, Playing, Paused }
+    transitions { Menu => Playing, Playing => Paused, Paused => Playing | Menu }
+}
+
+impl State<GameData> for Menu {
+    fn on_start(&mut self, data: &mut GameData) -> StateTransition<GameData> {
+        data.log.push("menu".to_string());
+        StateTransition::None
+    }
+
+    fn update(&mut self, data: &mut GameData) -> StateTransition<GameData> {
+        if data.frame < 10 {
+            self.switch_to(Playing)
+        } else {
+            StateTransition::Quit
+        }
+    }
+}
+
+impl State<GameData> for Playing {
+    fn on_start(&mut self, data: &mut GameData) -> StateTransition<GameData> {
+        data.log.push("playing".to_string());
+        StateTransition::None
+    }
+
+    fn update(&mut self, data: &mut GameData) -> StateTransition<GameData> {
+        if matches!(data.frame, 3 | 6 | 9) {
+            self.push_to(Paused)
+        } else {
+            StateTransition::None
+        }
+    }
+}
+
+impl State<GameData> for Paused {
+    fn on_start(&mut self, data: &mut GameData) -> StateTransition<GameData> {
+        data.log.push("paused".to_string());
+        StateTransition::None
+    }
+
+    fn update(&mut self, data: &mut GameData) -> StateTransition<GameData> {
+        if data.frame > 6 {
+            self.switch_to(Menu)
+        } else {
+            StateTransition::Pop
+        }
+    }
+}
+
+fn main() {
+    let mut data = GameData::default();
+    let mut sm = StateMachine::<GameData>::default();
+    Flow::install(&mut sm);
+    sm.push(Box::new(Menu), &mut data);
+    while sm.is_running() {
+        data.frame += 1;
+        sm.update(&mut data);
+        let active = sm.active().and_then(Flow::of);
+        println!("frame {}: {:?}", data.frame, active);
+    }
+    println!("{}", data.log.join(" -> "));
+}
@@ -0,0 +1,76 @@
+//! A menu and a square moving across the window, run in the game loop of macroquad.
+//! Run with `cargo run --example macroquad --features macroquad`.
+
+use game_state_machine::*;
+use macroquad::prelude::*;
+
+/// How many times the square was started.
+#[derive(Default)]
+struct Game {
+    runs: u32,
+}
+
+struct Menu;
+
+impl State<Game> for Menu {
+    fn update(&mut self, _game: &mut Game) -> StateTransition<Game> {
+        if is_key_pressed(KeyCode::Enter) {
+            StateTransition::push(Square { x: 0.0, speed: 4.0 })
+        } else if is_key_pressed(KeyCode::Escape) {
+            StateTransition::Quit
+        } else {
+            StateTransition::None
+        }
+    }
+
+    fn render(&mut self, game: &mut Game) {
+        clear_background(DARKGRAY);
+        draw_text("Enter to play, Escape to quit", 20.0, 40.0, 30.0, WHITE);
+        draw_text(
+            format!("Played {} times", game.runs),
+            20.0,
+            80.0,
+            30.0,
+            WHITE,
+        );
+    }
+}
+
+struct Square {
+    x: f32,
+    speed: f32,
+}
+
+impl State<Game> for Square {
+    fn on_start(&mut self, game: &mut Game) -> StateTransition<Game> {
+        game.runs += 1;
+        StateTransition::None
+    }
+
+    fn update(&mut self, _game: &mut Game) -> StateTransition<Game> {
+        if is_key_pressed(KeyCode::Escape) {
+            StateTransition::Pop
+        } else {
+            StateTransition::None
+        }
+    }
+
+    fn fixed_update(&mut self, _game: &mut Game) -> StateTransition<Game> {
+        self.x += self.speed;
+        if self.x < 0.0 || self.x > screen_width() - 40.0 {
+            self.speed = -self.speed;
+        }
+        StateTransition::None
+    }
+
+    fn render(&mut self, _game: &mut Game) {
+        clear_background(BLACK);
+        draw_rectangle(self.x, screen_height() / 2.0 - 20.0, 40.0, 40.0, ORANGE);
+    }
+}
+
+#[macroquad::main("game_state_machine")]
+async fn main() {
+    let (reason, game) = run_macroquad(Box::new(Menu), Game::default()).await;
+    println!("Stopped with {:?} after {} runs", reason, game.runs);
+}
@@ -0,0 +1,72 @@
+//! A window run by a state machine in the event loop of `winit`, closing once its state quits
+//! when Escape is pressed.
+//! Run with `cargo run --example winit --features winit`.
+
+use game_state_machine::*;
+use winit::event::{ElementState, KeyEvent, WindowEvent};
+use winit::event_loop::EventLoop;
+use winit::keyboard::{Key, NamedKey};
+use winit::window::Window;
+
+struct Game {
+    window: Window,
+    frames: u64,
+}
+
+enum Input {
+    Escape,
+}
+
+struct Running;
+
+impl State<Game, Input> for Running {
+    fn update(&mut self, game: &mut Game) -> StateTransition<Game, Input> {
+        game.frames += 1;
+        StateTransition::None
+    }
+
+    fn render(&mut self, game: &mut Game) {
+        game.window
+            .set_title(&format!("{} frames, Escape to quit", game.frames));
+    }
+
+    fn handle_event(&mut self, event: &Input, _game: &mut Game) -> EventOutcome<Game, Input> {
+        match event {
+            Input::Escape => EventOutcome::Consumed(StateTransition::Quit),
+        }
+    }
+}
+
+fn translate(event: WinitEvent) -> Option<Input> {
+    match event {
+        WinitEvent::Window(
+            _,
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        logical_key: Key::Named(NamedKey::Escape),
+                        state: ElementState::Pressed,
+                        ..
+                    },
+                ..
+            },
+        ) => Some(Input::Escape),
+        _ => None,
+    }
+}
+
+fn main() {
+    let event_loop = EventLoop::new().expect("The event loop can be created.");
+    let mut app = StateMachineApp::new(
+        Box::new(Running),
+        |event_loop| Game {
+            window: event_loop
+                .create_window(Window::default_attributes())
+                .expect("The window can be created."),
+            frames: 0,
+        },
+        translate,
+    );
+    event_loop.run_app(&mut app).expect("The event loop runs.");
+    println!("Stopped with {:?}", app.state_machine().exit_reason());
+}
@@ -0,0 +1,422 @@
+//! The `#[derive(State)]` macro of game_state_machine, enabled by its `derive` feature.
+#![deny(missing_docs)]
+
+use proc_macro2::TokenStream;
+use quote::{quote, quote_spanned};
+use syn::spanned::Spanned;
+use syn::{parse_macro_input, Data, DeriveInput, Fields, Type};
+
+/// Implements `State` for an enum by forwarding every method to the state held by the current
+/// variant, each variant holding one state, like `Menu(MenuState)`.
+///
+/// The state data and event types are pinned with `#[state(data = GameData, event = Input)]`,
+/// the events defaulting to `()`. Without `data`, the enum is a state for every data and event
+/// types that all its variants are states for.
+///
+/// `supports_restart` and `on_restart` are not forwarded, since the states of all the variants
+/// have the type of the enum, so the enum is never restarted.
+#[proc_macro_derive(State, attributes(state))]
+pub fn derive_state(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+/// The types given by the `#[state(...)]` attribute.
+#[derive(Default)]
+struct Options {
+    data: Option<Type>,
+    event: Option<Type>,
+}
+
+fn options(input: &DeriveInput) -> syn::Result<Options> {
+    let mut options = Options::default();
+    for attr in input
+        .attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("state"))
+    {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("data") {
+                options.data = Some(meta.value()?.parse()?);
+                Ok(())
+            } else if meta.path.is_ident("event") {
+                options.event = Some(meta.value()?.parse()?);
+                Ok(())
+            } else {
+                Err(meta.error("expected `data` or `event`"))
+            }
+        })?;
+    }
+    if let (None, Some(event)) = (&options.data, &options.event) {
+        return Err(syn::Error::new(
+            event.span(),
+            "`event` needs the `data` type to be given too",
+        ));
+    }
+    Ok(options)
+}
+
+fn expand(input: DeriveInput) -> syn::Result<TokenStream> {
+    let options = options(&input)?;
+    let data = match &input.data {
+        Data::Enum(data) => data,
+        _ => {
+            return Err(syn::Error::new(
+                input.ident.span(),
+                "`#[derive(State)]` only supports enums, with a state in each variant",
+            ))
+        }
+    };
+    if data.variants.is_empty() {
+        return Err(syn::Error::new(
+            input.ident.span(),
+            "`#[derive(State)]` needs at least one variant",
+        ));
+    }
+
+    let mut variants = Vec::new();
+    for variant in &data.variants {
+        match &variant.fields {
+            Fields::Unnamed(fields) if fields.unnamed.len() == 1 => {
+                variants.push((&variant.ident, &fields.unnamed[0].ty));
+            }
+            _ => {
+                return Err(syn::Error::new(
+                    variant.span(),
+                    "each variant must hold exactly one state, like `Menu(MenuState)`",
+                ))
+            }
+        }
+    }
+
+    let krate = quote!(::game_state_machine);
+    let mut generics = input.generics.clone();
+    let (state_data, event) = match options.data {
+        Some(data) => (
+            quote!(#data),
+            match options.event {
+                Some(event) => quote!(#event),
+                None => quote!(()),
+            },
+        ),
+        None => {
+            generics.params.push(syn::parse_quote!(__S: ?Sized));
+            generics.params.push(syn::parse_quote!(__E));
+            (quote!(__S), quote!(__E))
+        }
+    };
+    // The bounds make the compiler point at the variant holding a type which is not a state.
+    let where_clause = generics.make_where_clause();
+    for (_, ty) in &variants {
+        where_clause
+            .predicates
+            .push(syn::parse_quote_spanned! {ty.span()=>
+                #ty: ::game_state_machine::State<#state_data, #event>
+            });
+    }
+
+    let state = quote!(#krate::State::<#state_data, #event>);
+    let arms = |call: TokenStream| {
+        let arms = variants.iter().map(|(ident, ty)| {
+            quote_spanned! {ty.span()=> Self::#ident(state) => #call, }
+        });
+        quote!(match self { #(#arms)* })
+    };
+    let transition = quote!(#krate::StateTransition<#state_data, #event>);
+    let name = arms(quote!(#state::name(state)));
+    let is_transparent = arms(quote!(#state::is_transparent(state)));
+    let tags = arms(quote!(#state::tags(state)));
+    let required_below = arms(quote!(#state::required_below(state)));
+    let pops_group = arms(quote!(#state::pops_group(state)));
+    let on_start = arms(quote!(#state::on_start(state, state_data)));
+    let try_start = arms(quote!(#state::try_start(state, state_data)));
+    let on_stop_requested = arms(quote!(#state::on_stop_requested(state, state_data)));
+    let on_stop = arms(quote!(#state::on_stop(state, state_data)));
+    let on_quit = arms(quote!(#state::on_quit(state, state_data)));
+    let on_pause = arms(quote!(#state::on_pause(state, state_data)));
+    let on_resume = arms(quote!(#state::on_resume(state, state_data)));
+    let on_pause_with_reason = arms(quote!(
+        #state::on_pause_with_reason(state, reason, state_data)
+    ));
+    let on_resume_with_reason = arms(quote!(
+        #state::on_resume_with_reason(state, reason, state_data)
+    ));
+    let on_resume_with = arms(quote!(#state::on_resume_with(state, payload, state_data)));
+    let update = arms(quote!(#state::update(state, state_data)));
+    let try_update = arms(quote!(#state::try_update(state, state_data)));
+    let pre_update = arms(quote!(#state::pre_update(state, state_data)));
+    let post_update = arms(quote!(#state::post_update(state, state_data, result)));
+    let on_error = arms(quote!(#state::on_error(state, error, state_data)));
+    let fixed_update = arms(quote!(#state::fixed_update(state, state_data)));
+    let fixed_update_with_extra = arms(quote!(
+        #state::fixed_update_with_extra(state, extra, state_data)
+    ));
+    let update_interval = arms(quote!(#state::update_interval(state)));
+    let counts_covered_time = arms(quote!(#state::counts_covered_time(state)));
+    let shadow_update = arms(quote!(#state::shadow_update(state, state_data)));
+    let par_shadow_update = arms(quote!(#state::par_shadow_update(state, state_data)));
+    let render = arms(quote!(#state::render(state, state_data)));
+    let try_update_with_context = arms(quote!(
+        #state::try_update_with_context(state, context, state_data)
+    ));
+    let shadow_update_with_context = arms(quote!(
+        #state::shadow_update_with_context(state, context, state_data)
+    ));
+    let shadow_update_with_transition = arms(quote!(
+        #state::shadow_update_with_transition(state, context, state_data)
+    ));
+    let render_with_context = arms(quote!(#state::render_with_context(state, context, state_data)));
+    let try_update_with_extra = arms(quote!(
+        #state::try_update_with_extra(state, extra, context, state_data)
+    ));
+    let blocks_rendering = arms(quote!(#state::blocks_rendering(state)));
+    let save = arms(quote!(#state::save(state)));
+    let on_save = arms(quote!(#state::on_save(state, state_data)));
+    let on_load = arms(quote!(#state::on_load(state, state_data)));
+    let handle_event = arms(quote!(#state::handle_event(state, event, state_data)));
+    let handle_event_with_extra = arms(quote!(
+        #state::handle_event_with_extra(state, event, extra, state_data)
+    ));
+    let on_message = arms(quote!(#state::on_message(state, message, state_data)));
+    let declared_transitions = arms(quote!(#state::declared_transitions(state)));
+
+    let ident = &input.ident;
+    let (impl_generics, _, where_clause) = generics.split_for_impl();
+    let (_, ty_generics, _) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics #krate::State<#state_data, #event> for #ident #ty_generics
+        #where_clause
+        {
+            fn name(&self) -> &str {
+                #name
+            }
+
+            fn is_transparent(&self) -> bool {
+                #is_transparent
+            }
+
+            fn tags(&self) -> &[&'static str] {
+                #tags
+            }
+
+            fn required_below(&self) -> &[::std::any::TypeId] {
+                #required_below
+            }
+
+            fn pops_group(&self) -> bool {
+                #pops_group
+            }
+
+            fn on_start(&mut self, state_data: &mut #state_data) -> #transition {
+                #on_start
+            }
+
+            fn try_start(
+                &mut self,
+                state_data: &mut #state_data,
+            ) -> ::std::result::Result<#transition, #krate::BoxError> {
+                #try_start
+            }
+
+            fn on_stop_requested(
+                &mut self,
+                state_data: &mut #state_data,
+            ) -> #krate::StopDecision<#state_data, #event> {
+                #on_stop_requested
+            }
+
+            fn on_stop(&mut self, state_data: &mut #state_data) -> #transition {
+                #on_stop
+            }
+
+            fn on_quit(&mut self, state_data: &mut #state_data) {
+                #on_quit
+            }
+
+            fn on_pause(&mut self, state_data: &mut #state_data) {
+                #on_pause
+            }
+
+            fn on_resume(&mut self, state_data: &mut #state_data) -> #transition {
+                #on_resume
+            }
+
+            fn on_pause_with_reason(
+                &mut self,
+                reason: #krate::PauseReason,
+                state_data: &mut #state_data,
+            ) {
+                #on_pause_with_reason
+            }
+
+            fn on_resume_with_reason(
+                &mut self,
+                reason: #krate::ResumeReason,
+                state_data: &mut #state_data,
+            ) -> #transition {
+                #on_resume_with_reason
+            }
+
+            fn on_resume_with(
+                &mut self,
+                payload: #krate::Payload,
+                state_data: &mut #state_data,
+            ) -> #transition {
+                #on_resume_with
+            }
+
+            fn update(&mut self, state_data: &mut #state_data) -> #transition {
+                #update
+            }
+
+            fn try_update(
+                &mut self,
+                state_data: &mut #state_data,
+            ) -> ::std::result::Result<#transition, #krate::BoxError> {
+                #try_update
+            }
+
+            fn pre_update(&mut self, state_data: &mut #state_data) {
+                #pre_update
+            }
+
+            fn post_update(&mut self, state_data: &mut #state_data, result: &#transition) {
+                #post_update
+            }
+
+            fn on_error(
+                &mut self,
+                error: &dyn ::std::error::Error,
+                state_data: &mut #state_data,
+            ) -> #krate::ErrorOutcome<#state_data, #event> {
+                #on_error
+            }
+
+            #krate::__with_time! {
+                fn fixed_update(&mut self, state_data: &mut #state_data) -> #transition {
+                    #fixed_update
+                }
+
+                fn fixed_update_with_extra(
+                    &mut self,
+                    extra: &dyn ::std::any::Any,
+                    state_data: &mut #state_data,
+                ) -> #transition {
+                    #fixed_update_with_extra
+                }
+
+                fn update_interval(&self) -> ::std::option::Option<::std::time::Duration> {
+                    #update_interval
+                }
+
+                fn counts_covered_time(&self) -> bool {
+                    #counts_covered_time
+                }
+            }
+
+            fn shadow_update(&mut self, state_data: &mut #state_data) {
+                #shadow_update
+            }
+
+            #krate::__with_parallel! {
+                fn par_shadow_update(&mut self, state_data: &#state_data) {
+                    #par_shadow_update
+                }
+            }
+
+            fn render(&mut self, state_data: &mut #state_data) {
+                #render
+            }
+
+            fn try_update_with_context(
+                &mut self,
+                context: &#krate::StateContext,
+                state_data: &mut #state_data,
+            ) -> ::std::result::Result<#transition, #krate::BoxError> {
+                #try_update_with_context
+            }
+
+            fn shadow_update_with_context(
+                &mut self,
+                context: &#krate::StateContext,
+                state_data: &mut #state_data,
+            ) {
+                #shadow_update_with_context
+            }
+
+            fn shadow_update_with_transition(
+                &mut self,
+                context: &#krate::StateContext,
+                state_data: &mut #state_data,
+            ) -> #transition {
+                #shadow_update_with_transition
+            }
+
+            fn render_with_context(
+                &mut self,
+                context: &#krate::StateContext,
+                state_data: &mut #state_data,
+            ) {
+                #render_with_context
+            }
+
+            fn try_update_with_extra(
+                &mut self,
+                extra: &dyn ::std::any::Any,
+                context: &#krate::StateContext,
+                state_data: &mut #state_data,
+            ) -> ::std::result::Result<#transition, #krate::BoxError> {
+                #try_update_with_extra
+            }
+
+            fn blocks_rendering(&self) -> bool {
+                #blocks_rendering
+            }
+
+            #krate::__with_serde! {
+                fn save(&self) -> ::std::option::Option<::std::boxed::Box<dyn #krate::SavedState>> {
+                    #save
+                }
+            }
+
+            fn on_save(&mut self, state_data: &mut #state_data) {
+                #on_save
+            }
+
+            fn on_load(&mut self, state_data: &mut #state_data) {
+                #on_load
+            }
+
+            fn handle_event(
+                &mut self,
+                event: &#event,
+                state_data: &mut #state_data,
+            ) -> #krate::EventOutcome<#state_data, #event> {
+                #handle_event
+            }
+
+            fn handle_event_with_extra(
+                &mut self,
+                event: &#event,
+                extra: &dyn ::std::any::Any,
+                state_data: &mut #state_data,
+            ) -> #krate::EventOutcome<#state_data, #event> {
+                #handle_event_with_extra
+            }
+
+            fn on_message(
+                &mut self,
+                message: &dyn ::std::any::Any,
+                state_data: &mut #state_data,
+            ) -> #krate::MessageOutcome {
+                #on_message
+            }
+
+            fn declared_transitions(&self) -> ::std::vec::Vec<#krate::TransitionDecl> {
+                #declared_transitions
+            }
+        }
+    })
+}
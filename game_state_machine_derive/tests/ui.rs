@@ -0,0 +1,7 @@
+//! Checks the errors given by `#[derive(State)]` when it is misused.
+
+#[test]
+fn errors() {
+    let tests = trybuild::TestCases::new();
+    tests.compile_fail("tests/ui/*.rs");
+}
@@ -0,0 +1,16 @@
+use game_state_machine::*;
+
+struct Menu;
+
+impl State<()> for Menu {}
+
+struct Credits;
+
+#[derive(State)]
+#[state(data = ())]
+enum Screen {
+    Menu(Menu),
+    Credits(Credits),
+}
+
+fn main() {}
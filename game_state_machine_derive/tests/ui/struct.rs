@@ -0,0 +1,10 @@
+use game_state_machine::*;
+
+struct Menu;
+
+impl State<()> for Menu {}
+
+#[derive(State)]
+struct Screen(Menu);
+
+fn main() {}
@@ -0,0 +1,13 @@
+use game_state_machine::*;
+
+struct Menu;
+
+impl State<()> for Menu {}
+
+#[derive(State)]
+#[state(data = (), input = u32)]
+enum Screen {
+    Menu(Menu),
+}
+
+fn main() {}
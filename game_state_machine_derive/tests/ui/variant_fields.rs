@@ -0,0 +1,14 @@
+use game_state_machine::*;
+
+struct Menu;
+
+impl State<()> for Menu {}
+
+#[derive(State)]
+#[state(data = ())]
+enum Screen {
+    Menu(Menu),
+    Empty,
+}
+
+fn main() {}
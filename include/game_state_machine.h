@@ -0,0 +1,195 @@
+#ifndef GAME_STATE_MACHINE_H
+#define GAME_STATE_MACHINE_H
+
+/* Generated by cbindgen from src/ffi.rs, do not edit. */
+
+#include <stdbool.h>
+#include <stdint.h>
+
+/**
+ * The kinds of a `GSM_Transition`.
+ */
+enum GSM_TransitionKind {
+  /**
+   * Stay in the current state.
+   */
+  GSM_TRANSITION_KIND_NONE = 0,
+  /**
+   * Remove the top state.
+   */
+  GSM_TRANSITION_KIND_POP = 1,
+  /**
+   * Pause the top state and push the given state over it.
+   */
+  GSM_TRANSITION_KIND_PUSH = 2,
+  /**
+   * Remove the top state and push the given state instead, leaving the states below it.
+   */
+  GSM_TRANSITION_KIND_REPLACE = 3,
+  /**
+   * Remove all the states.
+   */
+  GSM_TRANSITION_KIND_QUIT = 4,
+  /**
+   * Remove all the states and push the given state.
+   */
+  GSM_TRANSITION_KIND_SWITCH = 5,
+};
+typedef uint32_t GSM_TransitionKind;
+
+/**
+ * What `gsm_machine_update` did.
+ */
+typedef enum GSM_Status {
+  /**
+   * The machine is still running.
+   */
+  GSM_STATUS_RUNNING,
+  /**
+   * The machine has no state anymore.
+   */
+  GSM_STATUS_STOPPED,
+  /**
+   * The machine was null.
+   */
+  GSM_STATUS_INVALID,
+  /**
+   * The machine panicked, and should be destroyed.
+   */
+  GSM_STATUS_PANICKED,
+  /**
+   * A callback returned a transition of an unknown kind, which did nothing. The machine may
+   * still be running, see `gsm_machine_is_running`.
+   */
+  GSM_STATUS_UNKNOWN_TRANSITION,
+} GSM_Status;
+
+/**
+ * A state machine driven from the host, created with `gsm_machine_new`.
+ */
+typedef struct GSM_Machine GSM_Machine;
+
+/**
+ * A transition returned by the callbacks of a state.
+ *
+ * The machine owns the user data of the state to push, replace or switch to from then on, and
+ * frees it with its `drop` callback. Otherwise, like for the other kinds, for a null `state`, or
+ * for an unknown kind, the transition does nothing and the user data stays owned by the host.
+ */
+typedef struct GSM_Transition {
+  /**
+   * What to do, one of the `GSM_TransitionKind` values. A value which is not one makes
+   * `gsm_machine_update` return `GSM_Status::UnknownTransition`.
+   */
+  uint32_t kind;
+  /**
+   * The callbacks of the state to push, replace or switch to, copied once the callback
+   * returns. A push, replace or switch without callbacks does nothing.
+   */
+  const struct GSM_StateVTable *state;
+  /**
+   * The user data of the state to push, replace or switch to.
+   */
+  void *user;
+} GSM_Transition;
+
+/**
+ * A callback of a state returning a transition, given the user data of the state and the
+ * state data.
+ */
+typedef struct GSM_Transition (*GSM_TransitionFn)(void *user, void *data);
+
+/**
+ * A callback of a state, given the user data of the state and the state data.
+ */
+typedef void (*GSM_HookFn)(void *user, void *data);
+
+/**
+ * The callbacks of a state, each of which can be null to do nothing.
+ */
+typedef struct GSM_StateVTable {
+  /**
+   * Called when the state is pushed on the stack, see `State::on_start`.
+   */
+  GSM_TransitionFn on_start;
+  /**
+   * Called when the state is removed from the stack, see `State::on_stop`.
+   */
+  GSM_TransitionFn on_stop;
+  /**
+   * Called when a state is pushed over this one, see `State::on_pause`.
+   */
+  GSM_HookFn on_pause;
+  /**
+   * Called when the state becomes the top state again, see `State::on_resume`.
+   */
+  GSM_TransitionFn on_resume;
+  /**
+   * Called on the top state each update, see `State::update`.
+   */
+  GSM_TransitionFn update;
+  /**
+   * Called on the visible states each render, see `State::render`.
+   */
+  GSM_HookFn render;
+  /**
+   * Called once the machine is done with the state, to free its user data.
+   */
+  void (*drop)(void *user);
+} GSM_StateVTable;
+
+/**
+ * Creates a state machine without states, to free with `gsm_machine_destroy`.
+ * Returns null if it could not be created.
+ */
+struct GSM_Machine *gsm_machine_new(void);
+
+/**
+ * Stops the states left without letting them cancel it, then frees the machine.
+ * Does nothing if the machine is null.
+ *
+ * # Safety
+ * `machine` is null or was created by `gsm_machine_new` and not destroyed yet.
+ */
+void gsm_machine_destroy(struct GSM_Machine *machine, void *data);
+
+/**
+ * Queues a state to be pushed over the top state during the next update, which is when it
+ * starts, owning the user data from then on.
+ * Returns `false` if the machine is null, in which case the user data stays owned by the host,
+ * or if the machine panicked, in which case the user data was freed with the `drop` callback.
+ *
+ * # Safety
+ * `machine` is null or valid, and the callbacks are null or valid as long as the state is
+ * alive.
+ */
+bool gsm_machine_push(struct GSM_Machine *machine, struct GSM_StateVTable vtable, void *user);
+
+/**
+ * Performs the queued transitions, updates the top state, then renders the visible states,
+ * passing them the state data.
+ *
+ * # Safety
+ * `machine` is null or valid, and `data` is what the callbacks expect.
+ */
+enum GSM_Status gsm_machine_update(struct GSM_Machine *machine, void *data);
+
+/**
+ * Returns the number of states on the stack of the machine, 0 for a null machine.
+ * The states pushed with `gsm_machine_push` are only on the stack once the machine is updated.
+ *
+ * # Safety
+ * `machine` is null or valid.
+ */
+uintptr_t gsm_machine_len(const struct GSM_Machine *machine);
+
+/**
+ * Returns if the machine has states, which is not the case of a null machine.
+ * The states pushed with `gsm_machine_push` are only on the stack once the machine is updated.
+ *
+ * # Safety
+ * `machine` is null or valid.
+ */
+bool gsm_machine_is_running(const struct GSM_Machine *machine);
+
+#endif  /* GAME_STATE_MACHINE_H */
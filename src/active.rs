@@ -0,0 +1,224 @@
+//! Keeping more than one state active at the top of the stack, like two gameplay states side by
+//! side for a split-screen co-op.
+
+use crate::{Allocator, ScopedStateMachine};
+
+/// Which of the active states can return a transition from their update, when the machine keeps
+/// more than one state active, see `StateMachine::set_active_depth`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ActiveTransitions {
+    /// Only the states that would be updated with an active depth of 1, which are the top state
+    /// and the states seen through it if it is transparent. The transitions returned by the other
+    /// active states are ignored.
+    Top,
+    /// The topmost active state returning a transition, like for the states seen through
+    /// transparent states.
+    Topmost,
+}
+
+impl<'a, S: ?Sized, E, A: Allocator> ScopedStateMachine<'a, S, E, A> {
+    /// Sets how many states at the top of the stack are active, 1 by default. The active states
+    /// do not pause each other, and are all updated each frame, bottom-up. Pushing a state over
+    /// them pauses the one that falls out of the active states, and popping one resumes the one
+    /// that becomes active again. The states that are active or not anymore are paused or resumed
+    /// right away.
+    ///
+    /// The active states are counted before transparency: when the lowest of them is
+    /// transparent, the states seen through it are active too, like with a single active state.
+    /// The states covered by the active ones get their shadow update as usual, and only the top
+    /// state gets the fixed updates and is reported as active in its context, see
+    /// `StateContext::is_active`. Which active states can return transitions is set with
+    /// `set_active_transitions`.
+    ///
+    /// ## Panics
+    /// Panics if the depth is zero.
+    pub fn set_active_depth(&mut self, depth: usize, state_data: &mut S) {
+        assert!(depth > 0, "The active depth must not be zero.");
+        self.active_depth = depth;
+        self.refresh_visible(state_data);
+    }
+
+    /// Returns how many states at the top of the stack are active, see `set_active_depth`.
+    pub fn active_depth(&self) -> usize {
+        self.active_depth
+    }
+
+    /// Sets which of the active states can return a transition from their update, see
+    /// `set_active_depth`. Defaults to `ActiveTransitions::Top`.
+    ///
+    /// This only applies to the transitions returned by `update`: the errors of all the active
+    /// states are handled, and all of them can consume events.
+    pub fn set_active_transitions(&mut self, policy: ActiveTransitions) {
+        self.active_transitions = policy;
+    }
+
+    /// Returns the index of the lowest state whose update can return a transition.
+    pub(crate) fn transitions_start(&self) -> usize {
+        match self.active_transitions {
+            ActiveTransitions::Top => self.visible_start_at(1),
+            ActiveTransitions::Topmost => 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    type Log = Vec<String>;
+
+    struct View {
+        name: &'static str,
+        transparent: bool,
+        pops: bool,
+    }
+
+    fn view(name: &'static str) -> Box<View> {
+        Box::new(View {
+            name,
+            transparent: false,
+            pops: false,
+        })
+    }
+
+    impl State<Log> for View {
+        fn name(&self) -> &str {
+            self.name
+        }
+
+        fn is_transparent(&self) -> bool {
+            self.transparent
+        }
+
+        fn on_start(&mut self, log: &mut Log) -> StateTransition<Log> {
+            log.push(format!("{}:start", self.name));
+            StateTransition::None
+        }
+
+        fn on_stop(&mut self, log: &mut Log) -> StateTransition<Log> {
+            log.push(format!("{}:stop", self.name));
+            StateTransition::None
+        }
+
+        fn on_pause(&mut self, log: &mut Log) {
+            log.push(format!("{}:pause", self.name));
+        }
+
+        fn on_resume(&mut self, log: &mut Log) -> StateTransition<Log> {
+            log.push(format!("{}:resume", self.name));
+            StateTransition::None
+        }
+
+        fn update(&mut self, log: &mut Log) -> StateTransition<Log> {
+            log.push(format!("{}:update", self.name));
+            if self.pops {
+                StateTransition::Pop
+            } else {
+                StateTransition::None
+            }
+        }
+
+        fn shadow_update(&mut self, log: &mut Log) {
+            log.push(format!("{}:shadow", self.name));
+        }
+    }
+
+    fn names(sm: &StateMachine<Log>) -> Vec<&str> {
+        sm.iter().map(|state| state.name()).collect()
+    }
+
+    #[test]
+    fn two_active_states() {
+        let mut log = Log::new();
+        let mut sm = StateMachine::<Log>::default();
+        sm.push(view("world"), &mut log);
+        sm.set_active_depth(2, &mut log);
+        sm.push(view("left"), &mut log);
+        sm.push(view("right"), &mut log);
+        assert_eq!(
+            log,
+            ["world:start", "left:start", "world:pause", "right:start"]
+        );
+
+        log.clear();
+        sm.update(&mut log);
+        assert_eq!(log, ["world:shadow", "left:update", "right:update"]);
+
+        log.clear();
+        sm.pop(&mut log);
+        assert_eq!(log, ["right:stop", "world:resume"]);
+
+        log.clear();
+        sm.push(view("right"), &mut log);
+        sm.set_active_depth(1, &mut log);
+        sm.set_active_depth(3, &mut log);
+        assert_eq!(
+            log,
+            [
+                "world:pause",
+                "right:start",
+                "left:pause",
+                "world:resume",
+                "left:resume"
+            ]
+        );
+    }
+
+    #[test]
+    fn transitions_of_the_active_states() {
+        let mut log = Log::new();
+        let mut sm = StateMachine::<Log>::default();
+        sm.set_active_depth(2, &mut log);
+        sm.push(view("world"), &mut log);
+        sm.push(
+            Box::new(View {
+                name: "left",
+                transparent: false,
+                pops: true,
+            }),
+            &mut log,
+        );
+        sm.push(view("right"), &mut log);
+
+        sm.update(&mut log);
+        assert_eq!(names(&sm), ["world", "left", "right"]);
+
+        sm.set_active_transitions(ActiveTransitions::Topmost);
+        sm.update(&mut log);
+        assert_eq!(names(&sm), ["world", "left"]);
+    }
+
+    #[test]
+    fn seen_through_a_transparent_state() {
+        let mut log = Log::new();
+        let mut sm = StateMachine::<Log>::default();
+        sm.push(view("world"), &mut log);
+        sm.push(
+            Box::new(View {
+                name: "hud",
+                transparent: true,
+                pops: false,
+            }),
+            &mut log,
+        );
+        sm.push(view("right"), &mut log);
+        log.clear();
+        sm.update(&mut log);
+        assert_eq!(log, ["world:shadow", "hud:shadow", "right:update"]);
+
+        // The lowest active state is transparent, so the world is active too.
+        log.clear();
+        sm.set_active_depth(2, &mut log);
+        sm.update(&mut log);
+        assert_eq!(
+            log,
+            [
+                "world:resume",
+                "hud:resume",
+                "world:update",
+                "hud:update",
+                "right:update"
+            ]
+        );
+    }
+}
@@ -0,0 +1,161 @@
+//! Boxing the states and growing the stack of a state machine in a given allocator, like a frame
+//! arena, instead of the global heap, see `StateMachine::new_in`.
+
+use crate::{AsAny, CustomTransition, ScopedStateMachine, State, StateHandle};
+use allocator_api2::alloc::{AllocError, Allocator, Global};
+use std::alloc::Layout;
+use std::any::Any;
+use std::ptr::NonNull;
+
+/// The allocator of a state on the stack: the global heap for the states boxed by `Box::new`,
+/// like the ones carried by `StateTransition`, or the allocator of the machine for the states
+/// given to `StateMachine::push_in`.
+#[derive(Clone)]
+pub(crate) enum StateAlloc<A> {
+    Global,
+    In(A),
+    /// The global heap too, for a state given to `ScopedStateMachine::push_scoped`, which may
+    /// borrow data, so it cannot be downcast.
+    Scoped,
+}
+
+unsafe impl<A: Allocator> Allocator for StateAlloc<A> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        match self {
+            StateAlloc::Global | StateAlloc::Scoped => Global.allocate(layout),
+            StateAlloc::In(alloc) => alloc.allocate(layout),
+        }
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        match self {
+            StateAlloc::Global | StateAlloc::Scoped => Global.deallocate(ptr, layout),
+            StateAlloc::In(alloc) => alloc.deallocate(ptr, layout),
+        }
+    }
+}
+
+/// A state on the stack of a `ScopedStateMachine` using the allocator `A`.
+pub(crate) type StateBox<'a, S, E, A> =
+    allocator_api2::boxed::Box<dyn State<S, E> + 'a, StateAlloc<A>>;
+
+/// Performs a `StateTransition::Custom`, which takes a machine on the global heap.
+pub(crate) type CustomRunner<'a, S, E, A> =
+    fn(&mut ScopedStateMachine<'a, S, E, A>, CustomTransition<S, E>, &mut S);
+
+/// Moves a state boxed on the global heap to the stack, without reallocating it.
+pub(crate) fn from_global<'a, S: ?Sized, E, A: Allocator>(
+    state: Box<dyn State<S, E>>,
+) -> StateBox<'a, S, E, A> {
+    // Safety: the state was allocated by `Box::new`, which `Global` frees the same way.
+    unsafe { StateBox::from_raw_in(Box::into_raw(state), StateAlloc::Global) }
+}
+
+/// Moves a state of a machine on the global heap back into a `Box`, without reallocating it.
+pub(crate) fn into_global<S: ?Sized, E>(
+    state: StateBox<'static, S, E, Global>,
+) -> Box<dyn State<S, E>> {
+    // Safety: all the variants of the allocator free the state with `Global`, like `Box` does.
+    unsafe { Box::from_raw(StateBox::into_raw(state)) }
+}
+
+/// Returns the state, if it can be downcast: all of them but the ones borrowing data, given to
+/// `ScopedStateMachine::push_scoped`.
+pub(crate) fn downcastable<'s, S: ?Sized, E, A: Allocator>(
+    state: &'s StateBox<'_, S, E, A>,
+) -> Option<&'s (dyn State<S, E> + 'static)> {
+    match StateBox::allocator(state) {
+        StateAlloc::Scoped => None,
+        // Safety: only `push_scoped` boxes states which may not be `'static`, the others are
+        // coerced from a `Box<dyn State<S, E>>` or from a `T: 'static`.
+        _ => Some(unsafe {
+            std::mem::transmute::<&(dyn State<S, E> + '_), &'s (dyn State<S, E> + 'static)>(
+                &**state,
+            )
+        }),
+    }
+}
+
+/// Returns the state, if it can be downcast, see `downcastable`.
+pub(crate) fn downcastable_mut<'s, S: ?Sized, E, A: Allocator>(
+    state: &'s mut StateBox<'_, S, E, A>,
+) -> Option<&'s mut (dyn State<S, E> + 'static)> {
+    match StateBox::allocator(state) {
+        StateAlloc::Scoped => None,
+        // Safety: see `downcastable`.
+        _ => Some(unsafe {
+            std::mem::transmute::<&mut (dyn State<S, E> + '_), &'s mut (dyn State<S, E> + 'static)>(
+                &mut **state,
+            )
+        }),
+    }
+}
+
+/// Returns the state as `Any`, if it can be downcast, see `downcastable`.
+pub(crate) fn any_of<'s, S: ?Sized, E, A: Allocator>(
+    state: &'s StateBox<'_, S, E, A>,
+) -> Option<&'s dyn Any> {
+    downcastable(state).map(|state| <dyn AsAny>::as_any(state))
+}
+
+/// Returns the state as mutable `Any`, if it can be downcast, see `downcastable`.
+pub(crate) fn any_of_mut<'s, S: ?Sized, E, A: Allocator>(
+    state: &'s mut StateBox<'_, S, E, A>,
+) -> Option<&'s mut dyn Any> {
+    downcastable_mut(state).map(|state| <dyn AsAny>::as_any_mut(state))
+}
+
+impl<'a, S: ?Sized, E, A: Allocator> ScopedStateMachine<'a, S, E, A> {
+    /// Pushes a state which may borrow data living for `'a`, like `push`. It cannot be
+    /// downcast, so it is not found by its type, see `ScopedStateMachine`.
+    pub fn push_scoped(
+        &mut self,
+        state: Box<dyn State<S, E> + 'a>,
+        state_data: &mut S,
+    ) -> StateHandle {
+        // Safety: the state was allocated by `Box::new`, which `Global` frees the same way.
+        let state = unsafe { StateBox::from_raw_in(Box::into_raw(state), StateAlloc::Scoped) };
+        self.push_entry(state, state_data)
+    }
+}
+
+impl<'a, S: ?Sized, E, A: Allocator + Clone> ScopedStateMachine<'a, S, E, A> {
+    /// Creates a state machine growing its stack in this allocator, like a frame arena.
+    /// The states given to `push_in` are boxed in it too, while the ones carried by a
+    /// `StateTransition` stay on the global heap, where `Box::new` put them.
+    ///
+    /// With the `smallvec` feature, the first states of the stack are kept inline and the stack
+    /// spills to the global heap instead of the allocator.
+    /// The operations moving the states out of the machine as a `Box`, like `pop_retained` or
+    /// `drain`, are only available on the global heap, and a `StateTransition::Custom` fails
+    /// with `TransitionError::KindNotSupported`.
+    pub fn new_in(alloc: A) -> Self {
+        Self::with_capacity_in(0, alloc)
+    }
+
+    /// Creates a state machine growing its stack in this allocator, with room for this many
+    /// states before it needs to reallocate, see `new_in`.
+    pub fn with_capacity_in(capacity: usize, alloc: A) -> Self {
+        Self::build(capacity, alloc)
+    }
+
+    /// Returns the allocator of the state machine.
+    pub fn allocator(&self) -> &A {
+        &self.alloc
+    }
+
+    /// Boxes this state in the allocator of the machine and pushes it, like `push`.
+    pub fn push_in<T: State<S, E> + 'static>(
+        &mut self,
+        state: T,
+        state_data: &mut S,
+    ) -> StateHandle {
+        let state = self.box_in(state);
+        self.push_entry(state, state_data)
+    }
+
+    fn box_in<T: State<S, E> + 'static>(&self, state: T) -> StateBox<'a, S, E, A> {
+        let state = allocator_api2::boxed::Box::new_in(state, StateAlloc::In(self.alloc.clone()));
+        allocator_api2::unsize_box!(state)
+    }
+}
@@ -0,0 +1,449 @@
+//! A state machine holding its states inline, for targets without an allocator.
+
+use crate::{MachineStatus, StopReason, TransitionError, TransitionKind};
+
+/// Trait that states of an `ArrayStateMachine` must implement.
+/// It has the same lifecycle as `State`, but transitions refer to states living outside of the
+/// machine instead of boxing them, for example in statics or in an arena.
+///
+/// ## Generics
+/// - 'a: How long the states outlive the state machine.
+/// - S: State data, the data that is sent to states for them to do their operations.
+pub trait ArrayState<'a, S: ?Sized> {
+    /// Called when the state is first inserted on the stack.
+    fn on_start(&mut self, _state_data: &mut S) {}
+    /// Called when the state is popped from the stack.
+    fn on_stop(&mut self, _state_data: &mut S) {}
+    /// Called on every state of the stack, from the bottom up, when the machine quits or is
+    /// stopped, before any of them is stopped, like `State::on_quit`.
+    fn on_quit(&mut self, _state_data: &mut S) {}
+    /// Called when a state is pushed over this one in the stack.
+    fn on_pause(&mut self, _state_data: &mut S) {}
+    /// Called when the state just on top of this one in the stack is popped.
+    fn on_resume(&mut self, _state_data: &mut S) {}
+    /// Executed on every frame immediately, as fast as the engine will allow.
+    fn update(&mut self, _state_data: &mut S) -> ArrayTransition<'a, S> {
+        ArrayTransition::None
+    }
+}
+
+/// A transition from one state to the other in an `ArrayStateMachine`.
+/// Behaves like the `StateTransition` variants of the same name.
+pub enum ArrayTransition<'a, S: ?Sized> {
+    /// Stay in the current state.
+    None,
+    /// End the current state and go to the previous state on the stack, if any.
+    Pop,
+    /// Push a new state on the stack.
+    Push(&'a mut dyn ArrayState<'a, S>),
+    /// Pop all states on the stack and insert this one.
+    Switch(&'a mut dyn ArrayState<'a, S>),
+    /// End the current state and insert this one in its place.
+    Replace(&'a mut dyn ArrayState<'a, S>),
+    /// Pop all states and exit the state machine.
+    Quit,
+}
+
+/// A state machine holding up to N states inline, without ever allocating.
+/// It follows the same lifecycle order as `StateMachine`, but fails instead of growing when a
+/// state is pushed on a full stack.
+///
+/// The states are not `'static`, so they may borrow from their environment, like a cache of
+/// assets, as long as it outlives the machine:
+///
+/// ```rust
+/// use game_state_machine::*;
+///
+/// struct Assets(Vec<&'static str>);
+///
+/// struct Level<'a>(&'a Assets);
+///
+/// impl<'a> ArrayState<'a, Vec<&'static str>> for Level<'a> {
+///     fn on_start(&mut self, loaded: &mut Vec<&'static str>) {
+///         loaded.extend(&self.0 .0);
+///     }
+/// }
+///
+/// let assets = Assets(vec!["tiles", "music"]);
+/// let mut level = Level(&assets);
+/// let mut loaded = Vec::new();
+/// let mut sm = ArrayStateMachine::<_, 1>::default();
+/// sm.push(&mut level, &mut loaded).unwrap();
+/// assert_eq!(loaded, ["tiles", "music"]);
+/// ```
+///
+/// Dropping what the states borrow while the machine still holds them does not compile:
+///
+/// ```rust,compile_fail
+/// use game_state_machine::*;
+///
+/// struct Assets(Vec<&'static str>);
+///
+/// struct Level<'a>(&'a Assets);
+///
+/// impl<'a> ArrayState<'a, ()> for Level<'a> {}
+///
+/// let mut sm = ArrayStateMachine::<(), 1>::default();
+/// {
+///     let assets = Assets(vec!["tiles"]);
+///     let level = Box::leak(Box::new(Level(&assets)));
+///     sm.push(level, &mut ()).unwrap();
+/// }
+/// sm.update(&mut ()).unwrap();
+/// ```
+///
+/// ## Generics
+/// - 'a: How long the states outlive the state machine.
+/// - S: State data, the data that is sent to states for them to do their operations.
+/// - N: The maximum number of states on the stack.
+pub struct ArrayStateMachine<'a, S: ?Sized, const N: usize> {
+    state_stack: [Option<&'a mut dyn ArrayState<'a, S>>; N],
+    len: usize,
+}
+
+impl<'a, S: ?Sized, const N: usize> Default for ArrayStateMachine<'a, S, N> {
+    fn default() -> Self {
+        Self {
+            state_stack: std::array::from_fn(|_| None),
+            len: 0,
+        }
+    }
+}
+
+impl<'a, S: ?Sized, const N: usize> ArrayStateMachine<'a, S, N> {
+    /// Returns if the state machine still has states in its stack.
+    pub fn is_running(&self) -> bool {
+        self.len > 0
+    }
+
+    /// Returns the number of states on the stack.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns if there are no states on the stack.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Updates the state at the top of the stack with the provided data.
+    /// If the states returns a transition, perform it.
+    /// Fails if the transition pushes a state on a full stack, in which case it is not started
+    /// and the stack is left untouched.
+    pub fn update(&mut self, state_data: &mut S) -> Result<MachineStatus, TransitionError> {
+        let trans = match self.top() {
+            Some(state) => state.update(state_data),
+            None => ArrayTransition::None,
+        };
+
+        let last = match trans {
+            ArrayTransition::None => None,
+            ArrayTransition::Pop => {
+                self.pop(state_data);
+                Some(TransitionKind::Pop)
+            }
+            ArrayTransition::Push(state) => {
+                self.push(state, state_data)?;
+                Some(TransitionKind::Push)
+            }
+            ArrayTransition::Switch(state) => {
+                self.switch(state, state_data);
+                Some(TransitionKind::Switch)
+            }
+            ArrayTransition::Replace(state) => {
+                self.replace(state, state_data);
+                Some(TransitionKind::Replace)
+            }
+            ArrayTransition::Quit => {
+                self.stop(state_data);
+                Some(TransitionKind::Quit)
+            }
+        };
+
+        Ok(if self.is_running() {
+            match last {
+                Some(kind) => MachineStatus::Transitioned(kind),
+                None => MachineStatus::Running,
+            }
+        } else if last == Some(TransitionKind::Quit) {
+            MachineStatus::Stopped(StopReason::Quit)
+        } else {
+            MachineStatus::Stopped(StopReason::EmptyStack)
+        })
+    }
+
+    /// Push a state on the stack and start it.
+    /// Pauses any previously active state.
+    /// Fails without starting the state if the stack is full.
+    pub fn push(
+        &mut self,
+        state: &'a mut dyn ArrayState<'a, S>,
+        state_data: &mut S,
+    ) -> Result<(), TransitionError> {
+        if self.len == N {
+            return Err(TransitionError::CapacityExceeded);
+        }
+
+        if let Some(top) = self.top() {
+            top.on_pause(state_data);
+        }
+
+        state.on_start(state_data);
+        self.state_stack[self.len] = Some(state);
+        self.len += 1;
+        Ok(())
+    }
+
+    /// Stops the state on top of the stack and removes it, then resumes the state below it.
+    /// Returns `false` and does nothing if the stack is empty.
+    pub fn pop(&mut self, state_data: &mut S) -> bool {
+        match self.take_top() {
+            Some(state) => state.on_stop(state_data),
+            None => return false,
+        }
+
+        if let Some(top) = self.top() {
+            top.on_resume(state_data);
+        }
+        true
+    }
+
+    /// Stops all the states on the stack, top-down, then pushes this state and starts it.
+    pub fn switch(&mut self, state: &'a mut dyn ArrayState<'a, S>, state_data: &mut S) {
+        self.stop_states(state_data);
+
+        state.on_start(state_data);
+        self.state_stack[0] = Some(state);
+        self.len = 1;
+    }
+
+    fn replace(&mut self, state: &'a mut dyn ArrayState<'a, S>, state_data: &mut S) {
+        if let Some(top) = self.take_top() {
+            top.on_stop(state_data);
+        }
+
+        // The stack cannot be full after taking the top, unless N is zero.
+        if self.len < N {
+            state.on_start(state_data);
+            self.state_stack[self.len] = Some(state);
+            self.len += 1;
+        }
+    }
+
+    /// Removes all currently running states from the stack, calling their `on_quit` first.
+    pub fn stop(&mut self, state_data: &mut S) {
+        for state in self.state_stack[..self.len].iter_mut().flatten() {
+            state.on_quit(state_data);
+        }
+        self.stop_states(state_data);
+    }
+
+    /// Stops all the states on the stack, top-down.
+    fn stop_states(&mut self, state_data: &mut S) {
+        while let Some(state) = self.take_top() {
+            state.on_stop(state_data);
+        }
+    }
+
+    fn top(&mut self) -> Option<&mut (dyn ArrayState<'a, S> + 'a)> {
+        match self.len.checked_sub(1) {
+            Some(index) => self.state_stack[index].as_deref_mut(),
+            None => None,
+        }
+    }
+
+    fn take_top(&mut self) -> Option<&'a mut dyn ArrayState<'a, S>> {
+        let index = self.len.checked_sub(1)?;
+        self.len = index;
+        self.state_stack[index].take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    type Log = Vec<&'static str>;
+
+    pub struct Pusher<'a> {
+        next: Option<&'a mut dyn ArrayState<'a, Log>>,
+    }
+
+    impl<'a> ArrayState<'a, Log> for Pusher<'a> {
+        fn on_start(&mut self, log: &mut Log) {
+            log.push("start");
+        }
+
+        fn on_pause(&mut self, log: &mut Log) {
+            log.push("pause");
+        }
+
+        fn on_resume(&mut self, log: &mut Log) {
+            log.push("resume");
+        }
+
+        fn on_stop(&mut self, log: &mut Log) {
+            log.push("stop");
+        }
+
+        fn update(&mut self, _log: &mut Log) -> ArrayTransition<'a, Log> {
+            match self.next.take() {
+                Some(next) => ArrayTransition::Push(next),
+                None => ArrayTransition::Pop,
+            }
+        }
+    }
+
+    #[test]
+    fn lifecycle() {
+        let mut log = Log::new();
+        let mut top = Pusher { next: None };
+        let mut bottom = Pusher {
+            next: Some(&mut top),
+        };
+        let mut sm = ArrayStateMachine::<Log, 2>::default();
+
+        sm.push(&mut bottom, &mut log).unwrap();
+        assert_eq!(
+            sm.update(&mut log),
+            Ok(MachineStatus::Transitioned(TransitionKind::Push))
+        );
+        assert_eq!(sm.len(), 2);
+        assert_eq!(
+            sm.update(&mut log),
+            Ok(MachineStatus::Transitioned(TransitionKind::Pop))
+        );
+        assert_eq!(
+            sm.update(&mut log),
+            Ok(MachineStatus::Stopped(StopReason::EmptyStack))
+        );
+        assert_eq!(
+            log,
+            vec!["start", "pause", "start", "stop", "resume", "stop"]
+        );
+    }
+
+    type Calls = Vec<(String, LifecycleCall)>;
+
+    /// Records its hooks like a `RecordingState`, and returns its transition from its first update.
+    struct Recording<'a> {
+        name: &'static str,
+        trans: Option<ArrayTransition<'a, Calls>>,
+    }
+
+    fn recording<'a>(name: &'static str, trans: ArrayTransition<'a, Calls>) -> Recording<'a> {
+        Recording {
+            name,
+            trans: Some(trans),
+        }
+    }
+
+    impl<'a> Recording<'a> {
+        fn record(&self, calls: &mut Calls, call: LifecycleCall) {
+            calls.push((self.name.to_string(), call));
+        }
+    }
+
+    impl<'a> ArrayState<'a, Calls> for Recording<'a> {
+        fn on_start(&mut self, calls: &mut Calls) {
+            self.record(calls, LifecycleCall::Start);
+        }
+
+        fn on_pause(&mut self, calls: &mut Calls) {
+            self.record(calls, LifecycleCall::Pause);
+        }
+
+        fn on_resume(&mut self, calls: &mut Calls) {
+            self.record(calls, LifecycleCall::Resume);
+        }
+
+        fn on_stop(&mut self, calls: &mut Calls) {
+            self.record(calls, LifecycleCall::Stop);
+        }
+
+        fn on_quit(&mut self, calls: &mut Calls) {
+            self.record(calls, LifecycleCall::Quit);
+        }
+
+        fn update(&mut self, calls: &mut Calls) -> ArrayTransition<'a, Calls> {
+            self.record(calls, LifecycleCall::Update);
+            self.trans.take().unwrap_or(ArrayTransition::None)
+        }
+    }
+
+    /// Runs the same script of pushes, pops, replaces, switches and quits on both machines, and
+    /// checks they call the hooks in the same order.
+    #[test]
+    fn same_lifecycle_as_state_machine() {
+        let mut array_calls = Calls::new();
+        let mut f = recording("f", ArrayTransition::Quit);
+        let mut e = recording("e", ArrayTransition::None);
+        let mut d = recording("d", ArrayTransition::Switch(&mut e));
+        let mut c = recording("c", ArrayTransition::Pop);
+        let mut b = recording("b", ArrayTransition::Replace(&mut c));
+        let mut a = recording("a", ArrayTransition::Push(&mut b));
+        let mut array = ArrayStateMachine::<Calls, 3>::default();
+        array.push(&mut a, &mut array_calls).unwrap();
+        for _ in 0..3 {
+            array.update(&mut array_calls).unwrap();
+        }
+        array.push(&mut d, &mut array_calls).unwrap();
+        array.update(&mut array_calls).unwrap();
+        array.push(&mut f, &mut array_calls).unwrap();
+        assert_eq!(
+            array.update(&mut array_calls),
+            Ok(MachineStatus::Stopped(StopReason::Quit))
+        );
+
+        let calls = CallLog::default();
+        let recording = |name: &str| RecordingState::new(name, &calls);
+        let mut sm = StateMachine::<()>::default();
+        sm.push(
+            Box::new(recording("a").returning(StateTransition::Push(Box::new(
+                recording("b").returning(StateTransition::Replace(Box::new(
+                    recording("c").returning(StateTransition::Pop),
+                ))),
+            )))),
+            &mut (),
+        );
+        for _ in 0..3 {
+            sm.update(&mut ());
+        }
+        sm.push(
+            Box::new(recording("d").returning(StateTransition::Switch(Box::new(recording("e"))))),
+            &mut (),
+        );
+        sm.update(&mut ());
+        sm.push(
+            Box::new(recording("f").returning(StateTransition::Quit)),
+            &mut (),
+        );
+        assert_eq!(sm.update(&mut ()), MachineStatus::Stopped(StopReason::Quit));
+
+        // The array states have no shadow updates.
+        calls
+            .lock()
+            .unwrap()
+            .retain(|(_, call)| *call != LifecycleCall::ShadowUpdate);
+        let expected: Vec<_> = array_calls
+            .iter()
+            .map(|(name, call)| (name.as_str(), *call))
+            .collect();
+        assert_calls(&calls, &expected);
+    }
+
+    #[test]
+    fn capacity_exceeded() {
+        let mut log = Log::new();
+        let mut extra = Pusher { next: None };
+        let mut full = Pusher {
+            next: Some(&mut extra),
+        };
+        let mut sm = ArrayStateMachine::<Log, 1>::default();
+
+        sm.push(&mut full, &mut log).unwrap();
+        assert_eq!(sm.update(&mut log), Err(TransitionError::CapacityExceeded));
+        assert_eq!(sm.len(), 1);
+        assert_eq!(log, vec!["start"]);
+    }
+}
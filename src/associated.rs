@@ -0,0 +1,353 @@
+//! States naming their state data with an associated type instead of a generic parameter, to
+//! write `impl StateWithData for Menu` instead of repeating the data type in every signature.
+
+use crate::{
+    BoxError, ErrorOutcome, EventOutcome, MaybeSend, MessageOutcome, Payload, State,
+    StateTransition,
+};
+use std::any::Any;
+use std::error::Error;
+
+/// A state whose state data is an associated type, pushed on a `StateMachine<Self::Data, E>` by
+/// wrapping it in a `DataState`. It has the hooks of `State` a game usually implements, and its
+/// events are `E`, `()` by default.
+///
+/// ```rust
+/// use game_state_machine::*;
+///
+/// struct Menu;
+///
+/// impl StateWithData for Menu {
+///     type Data = u32;
+///
+///     fn update(&mut self, frames: &mut u32) -> StateTransition<u32> {
+///         *frames += 1;
+///         StateTransition::Pop
+///     }
+/// }
+///
+/// let mut frames = 0;
+/// let mut sm = StateMachine::<u32>::default();
+/// sm.push(Menu.boxed(), &mut frames);
+/// sm.update(&mut frames);
+/// assert_eq!(frames, 1);
+/// ```
+pub trait StateWithData<E = ()> {
+    /// State data, the data that is sent to the state for it to do its operations.
+    type Data: ?Sized;
+
+    /// The name of the state, see `State::name`.
+    fn name(&self) -> &str {
+        std::any::type_name::<Self>()
+    }
+    /// If the states below this one stay active while it is on top of them, see
+    /// `State::is_transparent`.
+    fn is_transparent(&self) -> bool {
+        false
+    }
+    /// The groups the state belongs to, see `State::tags`.
+    fn tags(&self) -> &[&'static str] {
+        &[]
+    }
+    /// Called when the state is first inserted on the stack, see `State::on_start`.
+    fn on_start(&mut self, _state_data: &mut Self::Data) -> StateTransition<Self::Data, E> {
+        StateTransition::None
+    }
+    /// Called when the state is popped from the stack, see `State::on_stop`.
+    fn on_stop(&mut self, _state_data: &mut Self::Data) -> StateTransition<Self::Data, E> {
+        StateTransition::None
+    }
+    /// Called on every state of the stack when the machine quits or is stopped, see
+    /// `State::on_quit`.
+    fn on_quit(&mut self, _state_data: &mut Self::Data) {}
+    /// Called when a state is pushed over this one in the stack.
+    fn on_pause(&mut self, _state_data: &mut Self::Data) {}
+    /// Called when the state just on top of this one in the stack is popped, see
+    /// `State::on_resume`.
+    fn on_resume(&mut self, _state_data: &mut Self::Data) -> StateTransition<Self::Data, E> {
+        StateTransition::None
+    }
+    /// Called instead of `on_resume` when the state above pops with a payload, see
+    /// `State::on_resume_with`. By default, calls `on_resume`.
+    fn on_resume_with(
+        &mut self,
+        _payload: Payload,
+        state_data: &mut Self::Data,
+    ) -> StateTransition<Self::Data, E> {
+        self.on_resume(state_data)
+    }
+    /// Executed on every frame immediately, as fast as the engine will allow.
+    fn update(&mut self, _state_data: &mut Self::Data) -> StateTransition<Self::Data, E> {
+        StateTransition::None
+    }
+    /// Fallible version of `update`, see `State::try_update`. By default, calls `update`.
+    fn try_update(
+        &mut self,
+        state_data: &mut Self::Data,
+    ) -> Result<StateTransition<Self::Data, E>, BoxError> {
+        Ok(self.update(state_data))
+    }
+    /// Called when a state above this one failed, see `State::on_error`.
+    fn on_error(
+        &mut self,
+        _error: &dyn Error,
+        _state_data: &mut Self::Data,
+    ) -> ErrorOutcome<Self::Data, E> {
+        ErrorOutcome::Unhandled
+    }
+    /// Executed at a fixed interval, see `State::fixed_update`.
+    #[cfg(feature = "time")]
+    fn fixed_update(&mut self, _state_data: &mut Self::Data) -> StateTransition<Self::Data, E> {
+        StateTransition::None
+    }
+    /// Executed on every frame while another state is on top of this one in the stack.
+    fn shadow_update(&mut self, _state_data: &mut Self::Data) {}
+    /// Executed by `StateMachine::render` to draw the state, paused or not.
+    fn render(&mut self, _state_data: &mut Self::Data) {}
+    /// Called before the host saves the state data, see `State::on_save`.
+    fn on_save(&mut self, _state_data: &mut Self::Data) {}
+    /// Called once the host loaded the state data, see `State::on_load`.
+    fn on_load(&mut self, _state_data: &mut Self::Data) {}
+    /// Called when an event is sent to the state machine, see `State::handle_event`.
+    fn handle_event(
+        &mut self,
+        _event: &E,
+        _state_data: &mut Self::Data,
+    ) -> EventOutcome<Self::Data, E> {
+        EventOutcome::Ignored
+    }
+    /// Called when a message targeting this state is delivered, see `State::on_message`.
+    fn on_message(&mut self, _message: &dyn Any, _state_data: &mut Self::Data) -> MessageOutcome {
+        MessageOutcome::Ignored
+    }
+
+    /// Wraps the state in a `DataState` and boxes it, to push it on a machine.
+    fn boxed(self) -> Box<dyn State<Self::Data, E>>
+    where
+        Self: Sized + MaybeSend + 'static,
+    {
+        Box::new(DataState(self))
+    }
+}
+
+/// Makes a `StateWithData` a `State` of its data, so it can be pushed on a `StateMachine`
+/// along with the other states.
+/// A blanket implementation of `State` would conflict with the one of the closures, hence this
+/// wrapper. Use `StateMachine::contains_state::<DataState<T>>` to find the state `T`.
+pub struct DataState<T>(pub T);
+
+impl<T, E> State<T::Data, E> for DataState<T>
+where
+    T: StateWithData<E> + MaybeSend + 'static,
+{
+    fn name(&self) -> &str {
+        self.0.name()
+    }
+
+    fn is_transparent(&self) -> bool {
+        self.0.is_transparent()
+    }
+
+    fn tags(&self) -> &[&'static str] {
+        self.0.tags()
+    }
+
+    fn on_start(&mut self, state_data: &mut T::Data) -> StateTransition<T::Data, E> {
+        self.0.on_start(state_data)
+    }
+
+    fn on_stop(&mut self, state_data: &mut T::Data) -> StateTransition<T::Data, E> {
+        self.0.on_stop(state_data)
+    }
+
+    fn on_quit(&mut self, state_data: &mut T::Data) {
+        self.0.on_quit(state_data);
+    }
+
+    fn on_pause(&mut self, state_data: &mut T::Data) {
+        self.0.on_pause(state_data);
+    }
+
+    fn on_resume(&mut self, state_data: &mut T::Data) -> StateTransition<T::Data, E> {
+        self.0.on_resume(state_data)
+    }
+
+    fn on_resume_with(
+        &mut self,
+        payload: Payload,
+        state_data: &mut T::Data,
+    ) -> StateTransition<T::Data, E> {
+        self.0.on_resume_with(payload, state_data)
+    }
+
+    fn update(&mut self, state_data: &mut T::Data) -> StateTransition<T::Data, E> {
+        self.0.update(state_data)
+    }
+
+    fn try_update(
+        &mut self,
+        state_data: &mut T::Data,
+    ) -> Result<StateTransition<T::Data, E>, BoxError> {
+        self.0.try_update(state_data)
+    }
+
+    fn on_error(
+        &mut self,
+        error: &dyn Error,
+        state_data: &mut T::Data,
+    ) -> ErrorOutcome<T::Data, E> {
+        self.0.on_error(error, state_data)
+    }
+
+    #[cfg(feature = "time")]
+    fn fixed_update(&mut self, state_data: &mut T::Data) -> StateTransition<T::Data, E> {
+        self.0.fixed_update(state_data)
+    }
+
+    fn shadow_update(&mut self, state_data: &mut T::Data) {
+        self.0.shadow_update(state_data);
+    }
+
+    fn render(&mut self, state_data: &mut T::Data) {
+        self.0.render(state_data);
+    }
+
+    fn on_save(&mut self, state_data: &mut T::Data) {
+        self.0.on_save(state_data);
+    }
+
+    fn on_load(&mut self, state_data: &mut T::Data) {
+        self.0.on_load(state_data);
+    }
+
+    fn handle_event(&mut self, event: &E, state_data: &mut T::Data) -> EventOutcome<T::Data, E> {
+        self.0.handle_event(event, state_data)
+    }
+
+    fn on_message(&mut self, message: &dyn Any, state_data: &mut T::Data) -> MessageOutcome {
+        self.0.on_message(message, state_data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    type Log = Vec<String>;
+
+    /// Pushes a game once, then pops.
+    struct Menu(bool);
+
+    impl StateWithData for Menu {
+        type Data = Log;
+
+        fn on_resume(&mut self, log: &mut Log) -> StateTransition<Log> {
+            log.push("menu:resume".to_string());
+            StateTransition::None
+        }
+
+        fn update(&mut self, log: &mut Log) -> StateTransition<Log> {
+            log.push("menu:update".to_string());
+            if self.0 {
+                StateTransition::Pop
+            } else {
+                self.0 = true;
+                StateTransition::Push(Box::new(Game))
+            }
+        }
+
+        fn shadow_update(&mut self, log: &mut Log) {
+            log.push("menu:shadow".to_string());
+        }
+    }
+
+    struct Game;
+
+    impl State<Log> for Game {
+        fn on_start(&mut self, log: &mut Log) -> StateTransition<Log> {
+            log.push("game:start".to_string());
+            StateTransition::None
+        }
+
+        fn update(&mut self, log: &mut Log) -> StateTransition<Log> {
+            log.push("game:update".to_string());
+            StateTransition::Pop
+        }
+    }
+
+    #[test]
+    fn both_flavors() {
+        let mut log = Log::new();
+        let mut sm = StateMachine::<Log>::default();
+        sm.push(Menu(false).boxed(), &mut log);
+        assert!(sm.contains_state::<DataState<Menu>>());
+        assert_eq!(
+            sm.active().map(|state| state.name()),
+            Some(std::any::type_name::<Menu>())
+        );
+        while sm.is_running() {
+            sm.update(&mut log);
+        }
+        assert_eq!(
+            log,
+            [
+                "menu:update",
+                "game:start",
+                "menu:shadow",
+                "game:update",
+                "menu:resume",
+                "menu:update",
+            ]
+        );
+    }
+
+    /// Pops when given its key, and logs the payloads and quits it is given.
+    struct Lobby;
+
+    impl StateWithData<char> for Lobby {
+        type Data = Log;
+
+        fn on_quit(&mut self, log: &mut Log) {
+            log.push("lobby:quit".to_string());
+        }
+
+        fn on_resume_with(
+            &mut self,
+            payload: Payload,
+            log: &mut Log,
+        ) -> StateTransition<Log, char> {
+            log.push(format!(
+                "lobby:joined {}",
+                payload.downcast::<u32>().unwrap()
+            ));
+            StateTransition::None
+        }
+
+        fn handle_event(&mut self, key: &char, log: &mut Log) -> EventOutcome<Log, char> {
+            log.push(format!("lobby:key {}", key));
+            EventOutcome::Consumed(StateTransition::Push(Box::new(Join)))
+        }
+    }
+
+    /// Pops with the room joined on its first update.
+    struct Join;
+
+    impl State<Log, char> for Join {
+        fn update(&mut self, _log: &mut Log) -> StateTransition<Log, char> {
+            StateTransition::PopWith(Box::new(7_u32))
+        }
+    }
+
+    #[test]
+    fn events_and_payloads() {
+        let mut log = Log::new();
+        let mut sm = StateMachine::<Log, char>::default();
+        sm.push(Lobby.boxed(), &mut log);
+        sm.handle_event(&'j', &mut log);
+        assert_eq!(sm.len(), 2);
+        sm.update(&mut log);
+        sm.queue_transition(StateTransition::Quit);
+        sm.update(&mut log);
+        assert_eq!(log, ["lobby:key j", "lobby:joined 7", "lobby:quit"]);
+    }
+}
@@ -0,0 +1,392 @@
+//! A state machine whose states can await, for loading states that fetch assets or wait on the
+//! network without stalling the frame. It works with any executor.
+
+use crate::{MachineStatus, State, StateTransition, StopReason, TransitionKind};
+use std::future::Future;
+use std::pin::Pin;
+
+/// A future returned by the callbacks of an `AsyncState`.
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + 'a>>;
+
+/// Trait that states of an `AsyncStateMachine` must implement.
+/// It has the same lifecycle as `State`, with callbacks returning futures.
+///
+/// ## Generics
+/// - S: State data, the data that is sent to states for them to do their operations.
+pub trait AsyncState<S: ?Sized> {
+    /// Called when the state is first inserted on the stack.
+    fn on_start<'a>(&'a mut self, _state_data: &'a mut S) -> BoxFuture<'a, ()> {
+        Box::pin(async {})
+    }
+    /// Called when the state is popped from the stack.
+    fn on_stop<'a>(&'a mut self, _state_data: &'a mut S) -> BoxFuture<'a, ()> {
+        Box::pin(async {})
+    }
+    /// Called when a state is pushed over this one in the stack.
+    fn on_pause<'a>(&'a mut self, _state_data: &'a mut S) -> BoxFuture<'a, ()> {
+        Box::pin(async {})
+    }
+    /// Called when the state just on top of this one in the stack is popped.
+    fn on_resume<'a>(&'a mut self, _state_data: &'a mut S) -> BoxFuture<'a, ()> {
+        Box::pin(async {})
+    }
+    /// Executed on every frame, the machine waits for it to complete before doing anything else.
+    fn update<'a>(&'a mut self, _state_data: &'a mut S) -> BoxFuture<'a, AsyncTransition<S>> {
+        Box::pin(async { AsyncTransition::None })
+    }
+}
+
+/// A transition from one state to the other in an `AsyncStateMachine`.
+/// Behaves like the `StateTransition` variants of the same name.
+pub enum AsyncTransition<S: ?Sized> {
+    /// Stay in the current state.
+    None,
+    /// End the current state and go to the previous state on the stack, if any.
+    Pop,
+    /// End the given number of states, top-down, in one go.
+    PopN(usize),
+    /// Push a new state on the stack.
+    Push(Box<dyn AsyncState<S>>),
+    /// Pop all states on the stack and insert this one.
+    Switch(Box<dyn AsyncState<S>>),
+    /// End the current state and insert this one in its place.
+    Replace(Box<dyn AsyncState<S>>),
+    /// Pop all states and exit the state machine.
+    Quit,
+    /// Apply each transition in order. A Quit ends the sequence.
+    Sequence(Vec<AsyncTransition<S>>),
+}
+
+impl<S: ?Sized> AsyncTransition<S> {
+    fn kind(&self) -> Option<TransitionKind> {
+        match self {
+            AsyncTransition::None => None,
+            AsyncTransition::Pop => Some(TransitionKind::Pop),
+            AsyncTransition::PopN(_) => Some(TransitionKind::PopN),
+            AsyncTransition::Push(_) => Some(TransitionKind::Push),
+            AsyncTransition::Switch(_) => Some(TransitionKind::Switch),
+            AsyncTransition::Replace(_) => Some(TransitionKind::Replace),
+            AsyncTransition::Quit => Some(TransitionKind::Quit),
+            AsyncTransition::Sequence(_) => Some(TransitionKind::Sequence),
+        }
+    }
+}
+
+impl<S: ?Sized + 'static> From<StateTransition<S>> for AsyncTransition<S> {
+    /// Converts the transition of a synchronous state, wrapping the states it carries in
+    /// `SyncState`. `PopWith`, `PopUntil`, `PopTagged`, `PopGroup`, `PushNamed`, `PushWith`,
+    /// `SwitchWith` and `Custom` have no asynchronous equivalent and become `None`, `QuitWith`
+    /// becomes `Quit`, dropping its value, and `PopRetain` becomes `Pop`, dropping the state.
+    /// `ReplaceAll` becomes a switch to its first state followed by pushes of the others, or
+    /// `Quit` if it has none.
+    fn from(trans: StateTransition<S>) -> Self {
+        match trans {
+            StateTransition::Pop | StateTransition::PopRetain => AsyncTransition::Pop,
+            StateTransition::PopN(n) => AsyncTransition::PopN(n),
+            StateTransition::Push(state) => AsyncTransition::Push(Box::new(SyncState(state))),
+            StateTransition::PushMany(states) => AsyncTransition::Sequence(
+                states
+                    .into_iter()
+                    .map(|state| AsyncTransition::Push(Box::new(SyncState(state))))
+                    .collect(),
+            ),
+            StateTransition::Switch(state) => AsyncTransition::Switch(Box::new(SyncState(state))),
+            StateTransition::ReplaceAll(states) if states.is_empty() => AsyncTransition::Quit,
+            StateTransition::ReplaceAll(states) => AsyncTransition::Sequence(
+                states
+                    .into_iter()
+                    .enumerate()
+                    .map(|(index, state)| match index {
+                        0 => AsyncTransition::Switch(Box::new(SyncState(state))),
+                        _ => AsyncTransition::Push(Box::new(SyncState(state))),
+                    })
+                    .collect(),
+            ),
+            StateTransition::Replace(state) => AsyncTransition::Replace(Box::new(SyncState(state))),
+            StateTransition::Quit | StateTransition::QuitWith(_) => AsyncTransition::Quit,
+            StateTransition::Sequence(transitions) => {
+                AsyncTransition::Sequence(transitions.into_iter().map(Into::into).collect())
+            }
+            _ => AsyncTransition::None,
+        }
+    }
+}
+
+/// Adapts a synchronous `State` so it can live in an `AsyncStateMachine`.
+/// Its callbacks complete immediately, and the transitions returned by its `on_start`,
+/// `on_stop` and `on_resume` are ignored.
+pub struct SyncState<S: ?Sized>(pub Box<dyn State<S>>);
+
+impl<S: ?Sized + 'static> AsyncState<S> for SyncState<S> {
+    fn on_start<'a>(&'a mut self, state_data: &'a mut S) -> BoxFuture<'a, ()> {
+        self.0.on_start(state_data);
+        Box::pin(async {})
+    }
+
+    fn on_stop<'a>(&'a mut self, state_data: &'a mut S) -> BoxFuture<'a, ()> {
+        self.0.on_stop(state_data);
+        Box::pin(async {})
+    }
+
+    fn on_pause<'a>(&'a mut self, state_data: &'a mut S) -> BoxFuture<'a, ()> {
+        self.0.on_pause(state_data);
+        Box::pin(async {})
+    }
+
+    fn on_resume<'a>(&'a mut self, state_data: &'a mut S) -> BoxFuture<'a, ()> {
+        self.0.on_resume(state_data);
+        Box::pin(async {})
+    }
+
+    fn update<'a>(&'a mut self, state_data: &'a mut S) -> BoxFuture<'a, AsyncTransition<S>> {
+        let trans = self.0.update(state_data).into();
+        Box::pin(async { trans })
+    }
+}
+
+/// A state machine that holds a stack of `AsyncState` and performs transitions between them,
+/// awaiting each callback before moving on.
+///
+/// ## Generics
+/// - S: State data, the data that is sent to states for them to do their operations.
+pub struct AsyncStateMachine<S: ?Sized> {
+    state_stack: Vec<Box<dyn AsyncState<S>>>,
+}
+
+impl<S: ?Sized> Default for AsyncStateMachine<S> {
+    fn default() -> Self {
+        Self {
+            state_stack: Vec::default(),
+        }
+    }
+}
+
+impl<S: ?Sized> AsyncStateMachine<S> {
+    /// Returns if the state machine still has states in its stack.
+    pub fn is_running(&self) -> bool {
+        !self.state_stack.is_empty()
+    }
+
+    /// Returns the number of states on the stack.
+    pub fn len(&self) -> usize {
+        self.state_stack.len()
+    }
+
+    /// Returns if there are no states on the stack.
+    pub fn is_empty(&self) -> bool {
+        self.state_stack.is_empty()
+    }
+
+    /// Updates the state at the top of the stack with the provided data.
+    /// If the states returns a transition, perform it.
+    /// Returns the status of the state machine after the update.
+    pub async fn update(&mut self, state_data: &mut S) -> MachineStatus {
+        let trans = match self.state_stack.last_mut() {
+            Some(state) => state.update(state_data).await,
+            None => AsyncTransition::None,
+        };
+
+        let mut last = trans.kind();
+        let mut pending = vec![trans];
+        while let Some(trans) = pending.pop() {
+            match trans {
+                AsyncTransition::None => (),
+                AsyncTransition::Pop => self.pop_n(1, state_data).await,
+                AsyncTransition::PopN(n) => self.pop_n(n, state_data).await,
+                AsyncTransition::Push(state) => self.push(state, state_data).await,
+                AsyncTransition::Switch(state) => self.switch(state, state_data).await,
+                AsyncTransition::Replace(state) => {
+                    if let Some(mut top) = self.state_stack.pop() {
+                        top.on_stop(state_data).await;
+                    }
+                    self.start(state, state_data).await;
+                }
+                AsyncTransition::Quit => {
+                    self.stop(state_data).await;
+                    pending.clear();
+                    last = Some(TransitionKind::Quit);
+                }
+                AsyncTransition::Sequence(transitions) => {
+                    pending.extend(transitions.into_iter().rev());
+                }
+            }
+        }
+
+        if self.is_running() {
+            match last {
+                Some(kind) => MachineStatus::Transitioned(kind),
+                None => MachineStatus::Running,
+            }
+        } else if last == Some(TransitionKind::Quit) {
+            MachineStatus::Stopped(StopReason::Quit)
+        } else {
+            MachineStatus::Stopped(StopReason::EmptyStack)
+        }
+    }
+
+    /// Push a state on the stack and start it.
+    /// Pauses any previously active state.
+    pub async fn push(&mut self, state: Box<dyn AsyncState<S>>, state_data: &mut S) {
+        if let Some(top) = self.state_stack.last_mut() {
+            top.on_pause(state_data).await;
+        }
+
+        self.start(state, state_data).await;
+    }
+
+    /// Stops the state on top of the stack and removes it, then resumes the state below it.
+    /// Returns `false` and does nothing if the stack is empty.
+    pub async fn pop(&mut self, state_data: &mut S) -> bool {
+        let popped = self.is_running();
+        self.pop_n(1, state_data).await;
+        popped
+    }
+
+    /// Stops all the states on the stack, top-down, then pushes this state and starts it.
+    pub async fn switch(&mut self, state: Box<dyn AsyncState<S>>, state_data: &mut S) {
+        self.stop(state_data).await;
+        self.start(state, state_data).await;
+    }
+
+    /// Removes all currently running states from the stack.
+    pub async fn stop(&mut self, state_data: &mut S) {
+        while let Some(mut state) = self.state_stack.pop() {
+            state.on_stop(state_data).await;
+        }
+    }
+
+    async fn start(&mut self, mut state: Box<dyn AsyncState<S>>, state_data: &mut S) {
+        state.on_start(state_data).await;
+        self.state_stack.push(state);
+    }
+
+    async fn pop_n(&mut self, n: usize, state_data: &mut S) {
+        if n == 0 {
+            return;
+        }
+
+        for _ in 0..n {
+            match self.state_stack.pop() {
+                Some(mut state) => state.on_stop(state_data).await,
+                None => break,
+            }
+        }
+
+        if let Some(top) = self.state_stack.last_mut() {
+            top.on_resume(state_data).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::sync::Arc;
+    use std::task::{Context, Poll, Wake, Waker};
+    use std::thread::{self, Thread};
+
+    type Log = Vec<String>;
+
+    struct ThreadWaker(Thread);
+
+    impl Wake for ThreadWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    /// The most minimal executor, to show the machine does not depend on one.
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let mut future = Box::pin(future);
+        let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        loop {
+            match future.as_mut().poll(&mut cx) {
+                Poll::Ready(output) => return output,
+                Poll::Pending => thread::park(),
+            }
+        }
+    }
+
+    /// Yields once before completing, like a future waiting on IO would.
+    struct YieldOnce(bool);
+
+    impl Future for YieldOnce {
+        type Output = ();
+
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+            if self.0 {
+                Poll::Ready(())
+            } else {
+                self.0 = true;
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }
+        }
+    }
+
+    pub struct Loading;
+
+    impl AsyncState<Log> for Loading {
+        fn on_start<'a>(&'a mut self, log: &'a mut Log) -> BoxFuture<'a, ()> {
+            Box::pin(async move { log.push("loading:start".to_string()) })
+        }
+
+        fn update<'a>(&'a mut self, log: &'a mut Log) -> BoxFuture<'a, AsyncTransition<Log>> {
+            Box::pin(async move {
+                YieldOnce(false).await;
+                log.push("loading:loaded".to_string());
+                AsyncTransition::Replace(Box::new(SyncState(Box::new(Playing))))
+            })
+        }
+    }
+
+    pub struct Playing;
+
+    impl State<Log> for Playing {
+        fn on_start(&mut self, log: &mut Log) -> StateTransition<Log> {
+            log.push("playing:start".to_string());
+            StateTransition::None
+        }
+
+        fn update(&mut self, _log: &mut Log) -> StateTransition<Log> {
+            StateTransition::Sequence(vec![StateTransition::Quit, StateTransition::Pop])
+        }
+    }
+
+    async fn run(log: &mut Log) -> Vec<MachineStatus> {
+        let mut sm = AsyncStateMachine::<Log>::default();
+        sm.push(Box::new(Loading), log).await;
+        vec![sm.update(log).await, sm.update(log).await]
+    }
+
+    fn expected() -> (Log, Vec<MachineStatus>) {
+        (
+            vec![
+                "loading:start".to_string(),
+                "loading:loaded".to_string(),
+                "playing:start".to_string(),
+            ],
+            vec![
+                MachineStatus::Transitioned(TransitionKind::Replace),
+                MachineStatus::Stopped(StopReason::Quit),
+            ],
+        )
+    }
+
+    #[test]
+    fn minimal_executor() {
+        let mut log = Log::new();
+        let statuses = block_on(run(&mut log));
+        assert_eq!((log, statuses), expected());
+    }
+
+    #[tokio::test]
+    async fn tokio_executor() {
+        let mut log = Log::new();
+        let statuses = run(&mut log).await;
+        assert_eq!((log, statuses), expected());
+    }
+}
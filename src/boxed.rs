@@ -0,0 +1,311 @@
+//! Using a boxed state where a state is expected, like in the functions taking `impl State`.
+//!
+//! There is no implementation for `&mut T`, since a state is `Any` to be downcast, which a
+//! borrow is not.
+
+#[cfg(feature = "serde")]
+use crate::SavedState;
+use crate::{
+    BoxError, Checkpointable, ErrorOutcome, EventOutcome, MessageOutcome, PauseReason, Payload,
+    ResumeReason, State, StateContext, StateTransition, StopDecision, TransitionDecl,
+};
+use std::any::{Any, TypeId};
+use std::error::Error;
+#[cfg(feature = "time")]
+use std::time::Duration;
+
+/// Forwards all the calls to the boxed state, once each.
+///
+/// Boxing it again, like with `StateTransition::Push(Box::new(boxed))`, works but adds a level of
+/// indirection, and the state is then a `Box<dyn State>` for `as_any`, so downcasting it to its
+/// type fails. Use `StateTransition::push_boxed` to push a boxed state as it is.
+///
+/// The methods called on a boxed state resolve to this implementation, which needs the state
+/// data and the events to be `'static`. The code generic over them without this bound calls the
+/// boxed state through `as_ref` or `as_mut`.
+///
+/// `supports_restart` and `on_restart` are not forwarded, since all the boxed states have the
+/// same type, so a boxed state is never restarted.
+impl<S: ?Sized + 'static, E: 'static> State<S, E> for Box<dyn State<S, E>> {
+    fn name(&self) -> &str {
+        (**self).name()
+    }
+
+    fn is_transparent(&self) -> bool {
+        (**self).is_transparent()
+    }
+
+    fn tags(&self) -> &[&'static str] {
+        (**self).tags()
+    }
+
+    fn required_below(&self) -> &[TypeId] {
+        (**self).required_below()
+    }
+
+    fn pops_group(&self) -> bool {
+        (**self).pops_group()
+    }
+
+    fn on_start(&mut self, state_data: &mut S) -> StateTransition<S, E> {
+        (**self).on_start(state_data)
+    }
+
+    fn try_start(&mut self, state_data: &mut S) -> Result<StateTransition<S, E>, BoxError> {
+        (**self).try_start(state_data)
+    }
+
+    fn on_stop_requested(&mut self, state_data: &mut S) -> StopDecision<S, E> {
+        (**self).on_stop_requested(state_data)
+    }
+
+    fn on_stop(&mut self, state_data: &mut S) -> StateTransition<S, E> {
+        (**self).on_stop(state_data)
+    }
+
+    fn on_quit(&mut self, state_data: &mut S) {
+        (**self).on_quit(state_data)
+    }
+
+    fn on_pause(&mut self, state_data: &mut S) {
+        (**self).on_pause(state_data)
+    }
+
+    fn on_pause_with_reason(&mut self, reason: PauseReason, state_data: &mut S) {
+        (**self).on_pause_with_reason(reason, state_data)
+    }
+
+    fn on_resume(&mut self, state_data: &mut S) -> StateTransition<S, E> {
+        (**self).on_resume(state_data)
+    }
+
+    fn on_resume_with_reason(
+        &mut self,
+        reason: ResumeReason,
+        state_data: &mut S,
+    ) -> StateTransition<S, E> {
+        (**self).on_resume_with_reason(reason, state_data)
+    }
+
+    fn on_resume_with(&mut self, payload: Payload, state_data: &mut S) -> StateTransition<S, E> {
+        (**self).on_resume_with(payload, state_data)
+    }
+
+    fn update(&mut self, state_data: &mut S) -> StateTransition<S, E> {
+        (**self).update(state_data)
+    }
+
+    fn try_update(&mut self, state_data: &mut S) -> Result<StateTransition<S, E>, BoxError> {
+        (**self).try_update(state_data)
+    }
+
+    fn pre_update(&mut self, state_data: &mut S) {
+        (**self).pre_update(state_data)
+    }
+
+    fn post_update(&mut self, state_data: &mut S, result: &StateTransition<S, E>) {
+        (**self).post_update(state_data, result)
+    }
+
+    fn on_error(&mut self, error: &dyn Error, state_data: &mut S) -> ErrorOutcome<S, E> {
+        (**self).on_error(error, state_data)
+    }
+
+    #[cfg(feature = "time")]
+    fn fixed_update(&mut self, state_data: &mut S) -> StateTransition<S, E> {
+        (**self).fixed_update(state_data)
+    }
+
+    #[cfg(feature = "time")]
+    fn fixed_update_with_extra(
+        &mut self,
+        extra: &dyn Any,
+        state_data: &mut S,
+    ) -> StateTransition<S, E> {
+        (**self).fixed_update_with_extra(extra, state_data)
+    }
+
+    #[cfg(feature = "time")]
+    fn update_interval(&self) -> Option<Duration> {
+        (**self).update_interval()
+    }
+
+    #[cfg(feature = "time")]
+    fn counts_covered_time(&self) -> bool {
+        (**self).counts_covered_time()
+    }
+
+    fn shadow_update(&mut self, state_data: &mut S) {
+        (**self).shadow_update(state_data)
+    }
+
+    #[cfg(feature = "parallel")]
+    fn par_shadow_update(&mut self, state_data: &S) {
+        (**self).par_shadow_update(state_data)
+    }
+
+    fn render(&mut self, state_data: &mut S) {
+        (**self).render(state_data)
+    }
+
+    fn try_update_with_context(
+        &mut self,
+        context: &StateContext,
+        state_data: &mut S,
+    ) -> Result<StateTransition<S, E>, BoxError> {
+        (**self).try_update_with_context(context, state_data)
+    }
+
+    fn shadow_update_with_context(&mut self, context: &StateContext, state_data: &mut S) {
+        (**self).shadow_update_with_context(context, state_data)
+    }
+
+    fn shadow_update_with_transition(
+        &mut self,
+        context: &StateContext,
+        state_data: &mut S,
+    ) -> StateTransition<S, E> {
+        (**self).shadow_update_with_transition(context, state_data)
+    }
+
+    fn render_with_context(&mut self, context: &StateContext, state_data: &mut S) {
+        (**self).render_with_context(context, state_data)
+    }
+
+    fn try_update_with_extra(
+        &mut self,
+        extra: &dyn Any,
+        context: &StateContext,
+        state_data: &mut S,
+    ) -> Result<StateTransition<S, E>, BoxError> {
+        (**self).try_update_with_extra(extra, context, state_data)
+    }
+
+    fn blocks_rendering(&self) -> bool {
+        (**self).blocks_rendering()
+    }
+
+    #[cfg(feature = "serde")]
+    fn save(&self) -> Option<Box<dyn SavedState>> {
+        (**self).save()
+    }
+
+    fn clone_state(&self) -> Option<Box<dyn State<S, E>>> {
+        (**self).clone_state()
+    }
+
+    fn as_checkpointable(&self) -> Option<&dyn Checkpointable<S, E>> {
+        (**self).as_checkpointable()
+    }
+
+    fn as_checkpointable_mut(&mut self) -> Option<&mut dyn Checkpointable<S, E>> {
+        (**self).as_checkpointable_mut()
+    }
+
+    fn on_save(&mut self, state_data: &mut S) {
+        (**self).on_save(state_data)
+    }
+
+    fn on_load(&mut self, state_data: &mut S) {
+        (**self).on_load(state_data)
+    }
+
+    fn handle_event(&mut self, event: &E, state_data: &mut S) -> EventOutcome<S, E> {
+        (**self).handle_event(event, state_data)
+    }
+
+    fn handle_event_with_extra(
+        &mut self,
+        event: &E,
+        extra: &dyn Any,
+        state_data: &mut S,
+    ) -> EventOutcome<S, E> {
+        (**self).handle_event_with_extra(event, extra, state_data)
+    }
+
+    fn on_message(&mut self, message: &dyn Any, state_data: &mut S) -> MessageOutcome {
+        (**self).on_message(message, state_data)
+    }
+
+    fn declared_transitions(&self) -> Vec<TransitionDecl> {
+        (**self).declared_transitions()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    type Log = Vec<String>;
+
+    struct Counted(&'static str);
+
+    impl State<Log> for Counted {
+        fn name(&self) -> &str {
+            self.0
+        }
+
+        fn on_start(&mut self, log: &mut Log) -> StateTransition<Log> {
+            log.push(format!("{}:start", self.0));
+            StateTransition::None
+        }
+
+        fn on_pause(&mut self, log: &mut Log) {
+            log.push(format!("{}:pause", self.0));
+        }
+
+        fn on_resume(&mut self, log: &mut Log) -> StateTransition<Log> {
+            log.push(format!("{}:resume", self.0));
+            StateTransition::None
+        }
+
+        fn update(&mut self, log: &mut Log) -> StateTransition<Log> {
+            log.push(format!("{}:update", self.0));
+            StateTransition::None
+        }
+
+        fn on_stop(&mut self, log: &mut Log) -> StateTransition<Log> {
+            log.push(format!("{}:stop", self.0));
+            StateTransition::None
+        }
+    }
+
+    fn named(state: impl State<Log>) -> String {
+        state.name().to_string()
+    }
+
+    #[test]
+    fn forwarded_once() {
+        let boxed: Box<dyn State<Log>> = Box::new(Counted("menu"));
+        assert_eq!(named(boxed), "menu");
+
+        let mut log = Log::new();
+        let mut sm = StateMachine::<Log>::default();
+        let inner: Box<dyn State<Log>> = Box::new(Counted("game"));
+        sm.push(Box::new(inner), &mut log);
+        assert_eq!(sm.active_name(), Some("game"));
+        assert!(sm.active_state_as::<Counted>().is_none());
+        sm.transition(
+            StateTransition::push_boxed(Box::new(Counted("pause"))),
+            &mut log,
+        );
+        assert!(sm.active_state_as::<Counted>().is_some());
+        sm.update(&mut log);
+        sm.pop(&mut log);
+        sm.update(&mut log);
+        sm.stop(&mut log);
+        assert_eq!(
+            log,
+            [
+                "game:start",
+                "game:pause",
+                "pause:start",
+                "pause:update",
+                "pause:stop",
+                "game:resume",
+                "game:update",
+                "game:stop",
+            ]
+        );
+    }
+}
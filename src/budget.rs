@@ -0,0 +1,143 @@
+//! Limiting the transitions a single update performs, so a burst of queued and chained
+//! transitions is spread over several frames instead of stalling one.
+
+use crate::{Allocator, MachineStatus, ScopedStateMachine, StateTransition};
+
+impl<'a, S: ?Sized, E, A: Allocator> ScopedStateMachine<'a, S, E, A> {
+    /// Sets how many transitions one update may perform, counting the queued transitions, the
+    /// ones returned by the states and the ones returned by their lifecycle callbacks. The
+    /// transitions of a `StateTransition::Sequence` are counted one by one, not the sequence.
+    ///
+    /// The transitions over the budget are not performed: they are queued for the next update,
+    /// in the order they were returned, ahead of the transitions queued since. The update then
+    /// returns `MachineStatus::BudgetExhausted`. The other operations of the machine, like
+    /// `push` or `handle_event`, are not limited.
+    ///
+    /// Defaults to `usize::MAX`, no limit.
+    pub fn set_transition_budget(&mut self, budget: usize) {
+        self.transition_budget = budget;
+    }
+
+    /// Returns how many transitions one update may perform, see `set_transition_budget`.
+    pub fn transition_budget(&self) -> usize {
+        self.transition_budget
+    }
+
+    /// Counts the transition against the budget of the update, or keeps it for the next update
+    /// if the budget is spent.
+    pub(crate) fn spend_budget(
+        &mut self,
+        request: StateTransition<S, E>,
+    ) -> Option<StateTransition<S, E>> {
+        let left = match &mut self.budget_left {
+            Some(left) => left,
+            None => return Some(request),
+        };
+        if *left == 0 {
+            self.over_budget.push_back(request);
+            return None;
+        }
+        match request {
+            // Their transitions are counted once they are performed.
+            StateTransition::Sequence(_)
+            | StateTransition::Delayed {
+                after_updates: 0, ..
+            } => (),
+            _ => *left -= 1,
+        }
+        Some(request)
+    }
+
+    /// Ends the budget of the update, queuing the transitions over it ahead of the others.
+    pub(crate) fn carry_over(&mut self, status: MachineStatus) -> MachineStatus {
+        self.budget_left = None;
+        if self.over_budget.is_empty() {
+            return status;
+        }
+        while let Some(request) = self.over_budget.pop_back() {
+            self.queued_transitions.push_front(request);
+        }
+        MachineStatus::BudgetExhausted {
+            remaining: self.queued_transitions.len(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    type Log = Vec<String>;
+
+    struct Step(usize);
+
+    impl State<Log> for Step {
+        fn on_start(&mut self, log: &mut Log) -> StateTransition<Log> {
+            log.push(format!("{}:start", self.0));
+            StateTransition::None
+        }
+    }
+
+    fn started(log: &Log) -> Vec<usize> {
+        log.iter()
+            .map(|line| line.trim_end_matches(":start").parse().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn spread_over_frames() {
+        let mut log = Log::new();
+        let mut sm = StateMachine::<Log>::default();
+        sm.set_transition_budget(3);
+        for step in 0..10 {
+            sm.queue_transition(StateTransition::Push(Box::new(Step(step))));
+        }
+
+        let mut statuses = Vec::new();
+        for _ in 0..4 {
+            statuses.push(sm.update(&mut log));
+        }
+        assert_eq!(
+            statuses,
+            [
+                MachineStatus::BudgetExhausted { remaining: 7 },
+                MachineStatus::BudgetExhausted { remaining: 4 },
+                MachineStatus::BudgetExhausted { remaining: 1 },
+                MachineStatus::Transitioned(TransitionKind::Push),
+            ]
+        );
+        assert_eq!(started(&log), (0..10).collect::<Vec<_>>());
+        assert_eq!(sm.len(), 10);
+    }
+
+    #[test]
+    fn sequence_entries_are_counted() {
+        let mut log = Log::new();
+        let mut sm = StateMachine::<Log>::default();
+        sm.set_transition_budget(2);
+        sm.queue_transition(StateTransition::Sequence(
+            (0..3)
+                .map(|step| StateTransition::Push(Box::new(Step(step))))
+                .collect(),
+        ));
+        sm.queue_transition(StateTransition::Push(Box::new(Step(3))));
+
+        assert_eq!(
+            sm.update(&mut log),
+            MachineStatus::BudgetExhausted { remaining: 2 }
+        );
+        assert_eq!(started(&log), [0, 1]);
+        assert_eq!(
+            sm.update(&mut log),
+            MachineStatus::Transitioned(TransitionKind::Push)
+        );
+        assert_eq!(started(&log), [0, 1, 2, 3]);
+
+        // Pushing is not limited outside of the updates.
+        sm.push_many(
+            (4..7).map(|step| Box::new(Step(step)) as _).collect(),
+            &mut log,
+        );
+        assert_eq!(sm.len(), 7);
+    }
+}
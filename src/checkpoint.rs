@@ -0,0 +1,369 @@
+//! Checkpointing the stack of a state machine every frame and rolling back to it, for rollback
+//! netcode, without starting or stopping the states.
+//!
+//! States opt into it by implementing `Checkpointable` and returning themselves from
+//! `State::as_checkpointable` and `State::as_checkpointable_mut`:
+//! ```rust,ignore
+//! #[derive(Default)]
+//! struct Race {
+//!     lap: u32,
+//! }
+//!
+//! impl State<GameData> for Race {
+//!     fn as_checkpointable(&self) -> Option<&dyn Checkpointable<GameData>> {
+//!         Some(self)
+//!     }
+//!
+//!     fn as_checkpointable_mut(&mut self) -> Option<&mut dyn Checkpointable<GameData>> {
+//!         Some(self)
+//!     }
+//! }
+//!
+//! impl Checkpointable<GameData> for Race {
+//!     fn snapshot(&self) -> Box<dyn Any> {
+//!         Box::new(self.lap)
+//!     }
+//!
+//!     fn restore(&mut self, snapshot: &dyn Any) {
+//!         self.lap = *snapshot.downcast_ref::<u32>().unwrap();
+//!     }
+//!
+//!     fn fresh(&self) -> Box<dyn State<GameData>> {
+//!         Box::new(Race::default())
+//!     }
+//! }
+//! ```
+
+use crate::{from_global, Allocator, GroupId, ScopedStateMachine, StackEntry, State, StateHandle};
+use std::any::Any;
+use std::error::Error;
+use std::fmt;
+#[cfg(feature = "time")]
+use std::time::Duration;
+
+/// A state which can be checkpointed, see `StateMachine::checkpoint`.
+pub trait Checkpointable<S: ?Sized, E = ()>: State<S, E> {
+    /// Returns what is needed to restore the state as it is now.
+    fn snapshot(&self) -> Box<dyn Any>;
+    /// Restores the state as it was when this snapshot was taken.
+    fn restore(&mut self, snapshot: &dyn Any);
+    /// Creates a state of the same type, which a snapshot is then restored into. It does not
+    /// need to copy this state.
+    fn fresh(&self) -> Box<dyn State<S, E>>;
+}
+
+/// A state of a checkpoint, with where it was on the stack.
+struct Checkpointed<S: ?Sized, E> {
+    state: Box<dyn State<S, E>>,
+    snapshot: Box<dyn Any>,
+    handle: StateHandle,
+    interrupt: bool,
+    paused: bool,
+    group: Option<GroupId>,
+    #[cfg(feature = "time")]
+    since_update: Duration,
+    #[cfg(feature = "time")]
+    active_time: Duration,
+    #[cfg(feature = "time")]
+    age: Duration,
+}
+
+/// The states of the stack of a state machine at a point in time, see
+/// `StateMachine::checkpoint`.
+pub struct MachineCheckpoint<S: ?Sized, E = ()> {
+    states: Vec<Checkpointed<S, E>>,
+}
+
+impl<S: ?Sized, E> MachineCheckpoint<S, E> {
+    /// Returns the number of states in the checkpoint.
+    pub fn len(&self) -> usize {
+        self.states.len()
+    }
+
+    /// Returns if the checkpoint has no states.
+    pub fn is_empty(&self) -> bool {
+        self.states.is_empty()
+    }
+}
+
+/// A stack that could not be checkpointed or rolled back.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum CheckpointError {
+    /// The state at this index of the stack, or the state created for it by
+    /// `Checkpointable::fresh`, does not implement `Checkpointable`.
+    NotCheckpointable {
+        /// The index of the state in the stack, from the bottom.
+        index: usize,
+        /// The name of the state.
+        name: String,
+    },
+}
+
+impl fmt::Display for CheckpointError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CheckpointError::NotCheckpointable { index, name } => write!(
+                f,
+                "the state {} at index {} cannot be checkpointed",
+                name, index
+            ),
+        }
+    }
+}
+
+impl Error for CheckpointError {}
+
+fn not_checkpointable<S: ?Sized, E>(index: usize, state: &dyn State<S, E>) -> CheckpointError {
+    CheckpointError::NotCheckpointable {
+        index,
+        name: state.name().to_string(),
+    }
+}
+
+impl<'a, S: ?Sized, E, A: Allocator> ScopedStateMachine<'a, S, E, A> {
+    /// Takes a checkpoint of the states of the stack, with their snapshots and whether they are
+    /// paused, to roll back to it later.
+    /// Fails if one of the states does not implement `Checkpointable`.
+    pub fn checkpoint(&self) -> Result<MachineCheckpoint<S, E>, CheckpointError> {
+        let states = self
+            .state_stack
+            .iter()
+            .enumerate()
+            .map(|(index, entry)| {
+                let state = entry
+                    .state
+                    .as_ref()
+                    .as_checkpointable()
+                    .ok_or_else(|| not_checkpointable(index, entry.state.as_ref()))?;
+                Ok(Checkpointed {
+                    state: state.fresh(),
+                    snapshot: state.snapshot(),
+                    handle: entry.handle,
+                    interrupt: entry.interrupt,
+                    paused: entry.paused,
+                    group: entry.group,
+                    #[cfg(feature = "time")]
+                    since_update: entry.since_update,
+                    #[cfg(feature = "time")]
+                    active_time: entry.active_time,
+                    #[cfg(feature = "time")]
+                    age: entry.age,
+                })
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(MachineCheckpoint { states })
+    }
+
+    /// Replaces the stack with the states of the checkpoint, created with
+    /// `Checkpointable::fresh` and restored from their snapshots. The states keep their handles
+    /// and the states paused in the checkpoint stay paused.
+    ///
+    /// Nothing is called on the states but `fresh` and `restore`: the states of the stack are
+    /// dropped without being stopped, and the restored ones are not started, paused or resumed.
+    /// The transitions of `StateTransition::Delayed` held by the states and the values kept for
+    /// them, see `StateMachine::local_mut`, are dropped, while the queued, requested and
+    /// scheduled transitions of the machine are kept.
+    /// Fails without changing the stack if a state created by `fresh` is not checkpointable.
+    pub fn rollback(
+        &mut self,
+        checkpoint: &MachineCheckpoint<S, E>,
+    ) -> Result<(), CheckpointError> {
+        let entries = checkpoint
+            .states
+            .iter()
+            .enumerate()
+            .map(|(index, checkpointed)| {
+                let template = checkpointed
+                    .state
+                    .as_ref()
+                    .as_checkpointable()
+                    .ok_or_else(|| not_checkpointable(index, checkpointed.state.as_ref()))?;
+                let mut state = template.fresh();
+                match state.as_mut().as_checkpointable_mut() {
+                    Some(restored) => restored.restore(checkpointed.snapshot.as_ref()),
+                    None => return Err(not_checkpointable(index, state.as_ref())),
+                }
+                let mut entry = StackEntry::new(from_global(state), checkpointed.handle);
+                entry.interrupt = checkpointed.interrupt;
+                entry.paused = checkpointed.paused;
+                entry.group = checkpointed.group;
+                #[cfg(feature = "time")]
+                {
+                    entry.since_update = checkpointed.since_update;
+                    entry.active_time = checkpointed.active_time;
+                    entry.age = checkpointed.age;
+                }
+                Ok(entry)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        self.state_stack.clear();
+        self.state_stack.extend(entries);
+        if !self.state_stack.is_empty() {
+            self.exit_reason = None;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use std::any::Any;
+
+    type Log = Vec<String>;
+
+    /// Counts its laps, and pushes a pit stop on its third.
+    #[derive(Default)]
+    struct Race {
+        lap: u32,
+    }
+
+    impl State<Log> for Race {
+        fn on_start(&mut self, log: &mut Log) -> StateTransition<Log> {
+            log.push("race:start".to_string());
+            StateTransition::None
+        }
+
+        fn update(&mut self, log: &mut Log) -> StateTransition<Log> {
+            self.lap += 1;
+            log.push(format!("lap {}", self.lap));
+            if self.lap == 3 {
+                StateTransition::Push(Box::new(PitStop::default()))
+            } else {
+                StateTransition::None
+            }
+        }
+
+        fn as_checkpointable(&self) -> Option<&dyn Checkpointable<Log>> {
+            Some(self)
+        }
+
+        fn as_checkpointable_mut(&mut self) -> Option<&mut dyn Checkpointable<Log>> {
+            Some(self)
+        }
+    }
+
+    impl Checkpointable<Log> for Race {
+        fn snapshot(&self) -> Box<dyn Any> {
+            Box::new(self.lap)
+        }
+
+        fn restore(&mut self, snapshot: &dyn Any) {
+            self.lap = *snapshot.downcast_ref::<u32>().unwrap();
+        }
+
+        fn fresh(&self) -> Box<dyn State<Log>> {
+            Box::new(Race::default())
+        }
+    }
+
+    /// Pops itself after two updates.
+    #[derive(Default)]
+    struct PitStop {
+        updates: u32,
+    }
+
+    impl State<Log> for PitStop {
+        fn on_stop(&mut self, log: &mut Log) -> StateTransition<Log> {
+            log.push("pit stop:stop".to_string());
+            StateTransition::None
+        }
+
+        fn update(&mut self, _: &mut Log) -> StateTransition<Log> {
+            self.updates += 1;
+            if self.updates == 2 {
+                StateTransition::Pop
+            } else {
+                StateTransition::None
+            }
+        }
+
+        fn as_checkpointable(&self) -> Option<&dyn Checkpointable<Log>> {
+            Some(self)
+        }
+
+        fn as_checkpointable_mut(&mut self) -> Option<&mut dyn Checkpointable<Log>> {
+            Some(self)
+        }
+    }
+
+    impl Checkpointable<Log> for PitStop {
+        fn snapshot(&self) -> Box<dyn Any> {
+            Box::new(self.updates)
+        }
+
+        fn restore(&mut self, snapshot: &dyn Any) {
+            self.updates = *snapshot.downcast_ref::<u32>().unwrap();
+        }
+
+        fn fresh(&self) -> Box<dyn State<Log>> {
+            Box::new(PitStop::default())
+        }
+    }
+
+    fn run(sm: &mut StateMachine<Log>, updates: usize) -> Log {
+        let mut log = Log::new();
+        for _ in 0..updates {
+            sm.update(&mut log);
+        }
+        log
+    }
+
+    #[test]
+    fn rollback_replays_the_same() {
+        let mut log = Log::new();
+        let mut sm = StateMachine::<Log>::default();
+        let race = sm.push(Box::new(Race::default()), &mut log);
+        run(&mut sm, 2);
+        let checkpoint = sm.checkpoint().unwrap();
+        assert_eq!(checkpoint.len(), 1);
+
+        let first = run(&mut sm, 4);
+        assert_eq!(first, ["lap 3", "pit stop:stop", "lap 4"]);
+
+        sm.push(Box::new(PitStop::default()), &mut log);
+        sm.rollback(&checkpoint).unwrap();
+        assert_eq!(sm.len(), 1);
+        assert!(sm.is_alive(race));
+        assert_eq!(run(&mut sm, 4), first);
+
+        // Rolling back into a pit stop restores the paused race under it.
+        sm.rollback(&checkpoint).unwrap();
+        run(&mut sm, 2);
+        let checkpoint = sm.checkpoint().unwrap();
+        assert_eq!(checkpoint.len(), 2);
+        let first = run(&mut sm, 3);
+        sm.rollback(&checkpoint).unwrap();
+        assert_eq!(run(&mut sm, 3), first);
+        assert_eq!(log, ["race:start"]);
+    }
+
+    struct Menu;
+
+    impl State<Log> for Menu {
+        fn name(&self) -> &str {
+            "menu"
+        }
+    }
+
+    #[test]
+    fn not_checkpointable() {
+        let mut log = Log::new();
+        let mut sm = StateMachine::<Log>::default();
+        sm.push(Box::new(Race::default()), &mut log);
+        sm.push(Box::new(Menu), &mut log);
+        let error = sm.checkpoint().err().unwrap();
+        assert_eq!(
+            error,
+            CheckpointError::NotCheckpointable {
+                index: 1,
+                name: "menu".to_string(),
+            }
+        );
+        assert_eq!(
+            error.to_string(),
+            "the state menu at index 1 cannot be checkpointed"
+        );
+    }
+}
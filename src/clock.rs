@@ -0,0 +1,215 @@
+//! Keeping how long the states of a state machine have been running, for their timeouts and
+//! animations, and reading the time where `std::time::Instant` panics, like on the web.
+
+use crate::{Allocator, MachineStatus, MaybeSend, ScopedStateMachine, StateHandle};
+use std::convert::TryFrom;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
+
+/// Tells the time to a state machine, to time the callbacks of the states and to measure the
+/// frames of `update_with_clock`, see `StateMachine::set_clock`.
+/// The machines driven by `update_with_dt` only read it for the telemetry.
+pub trait Clock: MaybeSend {
+    /// Returns the time elapsed since an origin picked by the clock, which never goes back.
+    fn now(&self) -> Duration;
+}
+
+/// Reads the time with `std::time::Instant`, which panics on `wasm32-unknown-unknown`.
+/// This is the default clock without the `wasm` feature.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StdClock;
+
+impl Clock for StdClock {
+    fn now(&self) -> Duration {
+        static ORIGIN: OnceLock<Instant> = OnceLock::new();
+        ORIGIN.get_or_init(Instant::now).elapsed()
+    }
+}
+
+/// Reads the time with the `web-time` crate, which uses `performance.now()` on the web and
+/// `std::time::Instant` elsewhere.
+/// This is the default clock with the `wasm` feature.
+#[cfg(feature = "wasm")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct WebClock;
+
+#[cfg(feature = "wasm")]
+impl Clock for WebClock {
+    fn now(&self) -> Duration {
+        static ORIGIN: OnceLock<web_time::Instant> = OnceLock::new();
+        ORIGIN.get_or_init(web_time::Instant::now).elapsed()
+    }
+}
+
+#[cfg(feature = "wasm")]
+pub(crate) type DefaultClock = WebClock;
+
+#[cfg(not(feature = "wasm"))]
+pub(crate) type DefaultClock = StdClock;
+
+/// A clock that only moves when it is advanced, for the tests and the replays.
+/// Its clones share the same time, so a clone given to a machine is advanced through the
+/// original.
+#[derive(Debug, Clone, Default)]
+pub struct ManualClock {
+    nanos: Arc<AtomicU64>,
+}
+
+impl ManualClock {
+    /// Creates a clock at zero.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Moves the clock forward.
+    pub fn advance(&self, dt: Duration) {
+        self.nanos.fetch_add(nanos(dt), Ordering::Relaxed);
+    }
+
+    /// Moves the clock to this time, which should not be before the current one.
+    pub fn set(&self, now: Duration) {
+        self.nanos.store(nanos(now), Ordering::Relaxed);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Duration {
+        Duration::from_nanos(self.nanos.load(Ordering::Relaxed))
+    }
+}
+
+/// Converts a duration to nanoseconds, saturating after about 584 years.
+fn nanos(duration: Duration) -> u64 {
+    u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX)
+}
+
+impl<'a, S: ?Sized, E, A: Allocator> ScopedStateMachine<'a, S, E, A> {
+    /// Replaces the clock of the machine, which is `StdClock` by default, or `WebClock` with the
+    /// `wasm` feature. The next `update_with_clock` starts measuring again from the new clock.
+    pub fn set_clock(&mut self, clock: impl Clock + 'static) {
+        self.telemetry.set_clock(Box::new(clock));
+        self.last_tick = None;
+    }
+
+    /// Does an `update_with_dt` with the time elapsed since the last call, read from the clock
+    /// of the machine, see `set_clock`. The first call is given no time.
+    ///
+    /// The time spent while the machine is frozen is then not counted, like with
+    /// `update_with_dt`.
+    pub fn update_with_clock(&mut self, state_data: &mut S) -> MachineStatus {
+        let now = self.telemetry.now();
+        let dt = match self.last_tick.replace(now) {
+            Some(last) => now.saturating_sub(last),
+            None => Duration::ZERO,
+        };
+        self.update_with_dt(dt, state_data)
+    }
+
+    /// Returns how long the state with this handle was active, if it is still on the stack.
+    ///
+    /// The time is only counted by `update_with_dt`, `update` does not know how long the frame
+    /// took. A state is active while it is not covered, being the top state or under transparent
+    /// states. The time it spends covered does not count, unless it asked for it with
+    /// `State::counts_covered_time`.
+    pub fn active_time(&self, handle: StateHandle) -> Option<Duration> {
+        let index = self.index_of(handle)?;
+        Some(self.state_stack[index].active_time)
+    }
+
+    /// Returns how long ago the state with this handle was started, if it is still on the stack.
+    /// Like `active_time`, the time is only counted by `update_with_dt`.
+    pub fn time_since_start(&self, handle: StateHandle) -> Option<Duration> {
+        let index = self.index_of(handle)?;
+        Some(self.state_stack[index].age)
+    }
+
+    /// Returns how long the top state was active, see `active_time`.
+    pub fn time_in_active_state(&self) -> Option<Duration> {
+        self.state_stack.last().map(|entry| entry.active_time)
+    }
+
+    /// Adds the time elapsed since the last frame to the states on the stack.
+    pub(crate) fn advance_clocks(&mut self, dt: Duration) {
+        let visible = self.visible_start();
+        for (index, entry) in self.state_stack.iter_mut().enumerate() {
+            entry.age += dt;
+            if index >= visible || entry.state.counts_covered_time() {
+                entry.active_time += dt;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use std::time::Duration;
+
+    /// A state which can count the time it spends covered, and can be transparent.
+    struct Clocked {
+        covered: bool,
+        transparent: bool,
+    }
+
+    impl State<()> for Clocked {
+        fn counts_covered_time(&self) -> bool {
+            self.covered
+        }
+
+        fn is_transparent(&self) -> bool {
+            self.transparent
+        }
+    }
+
+    fn clocked(covered: bool, transparent: bool) -> Box<Clocked> {
+        Box::new(Clocked {
+            covered,
+            transparent,
+        })
+    }
+
+    #[test]
+    fn active_time() {
+        let frame = Duration::from_millis(10);
+        let mut sm = StateMachine::<()>::default();
+        let music = sm.push(clocked(true, false), &mut ());
+        let game = sm.push(clocked(false, false), &mut ());
+        sm.update_with_dt(frame, &mut ());
+        let pause = sm.push(clocked(false, false), &mut ());
+        sm.update_with_dt(frame, &mut ());
+        sm.update(&mut ());
+        sm.pop(&mut ());
+        let hud = sm.push(clocked(false, true), &mut ());
+        sm.update_with_dt(frame, &mut ());
+
+        assert_eq!(sm.active_time(music), Some(frame * 3));
+        assert_eq!(sm.active_time(game), Some(frame * 2));
+        assert_eq!(sm.time_since_start(game), Some(frame * 3));
+        assert_eq!(sm.time_in_active_state(), sm.active_time(hud));
+        assert_eq!(sm.active_time(hud), Some(frame));
+        assert_eq!(sm.active_time(pause), None);
+        assert_eq!(sm.frame(), 4);
+    }
+
+    #[test]
+    fn manual_clock() {
+        let clock = ManualClock::new();
+        let mut sm = StateMachine::<()>::default();
+        sm.set_clock(clock.clone());
+        sm.set_fixed_timestep(Duration::from_millis(10));
+        let game = sm.push(clocked(false, false), &mut ());
+
+        sm.update_with_clock(&mut ());
+        assert_eq!(sm.active_time(game), Some(Duration::ZERO));
+        clock.advance(Duration::from_millis(25));
+        sm.update_with_clock(&mut ());
+        assert_eq!(sm.active_time(game), Some(Duration::from_millis(25)));
+        assert_eq!(sm.interpolation_alpha(), 0.5);
+
+        clock.set(Duration::from_millis(40));
+        sm.update_with_clock(&mut ());
+        assert_eq!(sm.active_time(game), Some(Duration::from_millis(40)));
+        assert_eq!(sm.frame(), 3);
+    }
+}
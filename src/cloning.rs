@@ -0,0 +1,256 @@
+//! Cloning a state machine, to run what-if simulations on the copy, like an AI trying a move
+//! before making it.
+
+use crate::{from_global, StackEntry, StateMachine};
+use std::error::Error;
+use std::fmt;
+
+/// A state machine that could not be cloned, see `StateMachine::try_clone`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum CloneError {
+    /// The state at this index of the stack does not support being cloned.
+    NotCloneable {
+        /// The index of the state in the stack, from the bottom.
+        index: usize,
+        /// The name of the state.
+        name: String,
+    },
+    /// An initial state, not started yet, does not support being cloned.
+    InitialNotCloneable {
+        /// The name of the state.
+        name: String,
+    },
+}
+
+impl fmt::Display for CloneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CloneError::NotCloneable { index, name } => {
+                write!(f, "the state {} at index {} cannot be cloned", name, index)
+            }
+            CloneError::InitialNotCloneable { name } => {
+                write!(f, "the initial state {} cannot be cloned", name)
+            }
+        }
+    }
+}
+
+impl Error for CloneError {}
+
+impl<S: ?Sized, E: Clone> StateMachine<S, E> {
+    /// Clones the machine, with a copy of each state made by `State::clone_state`, so the copy
+    /// and the original change independently.
+    /// Fails if one of the states cannot be cloned.
+    ///
+    /// The copy keeps the handles of the states, the queued events, the settings, the
+    /// transition table, the metrics, the history, the frame and idle update counts, the
+    /// overlays and whether the machine is frozen. What holds closures or transitions is
+    /// dropped instead: the observers, the global hooks, the interceptor, the quit handler, the
+    /// transition check, the registry, the transition effect, the instrument writer, the
+    /// snapshot migrator, the fallback, the idle handler, the stall detector, the queued,
+    /// requested, scheduled and delayed transitions, the retained state, the error and the
+    /// payloads. The requesters and the subscribers of the original do not reach the copy. The
+    /// telemetry of the copy and the values kept for its states, see `local_mut`, start empty,
+    /// and the copy reads the time from the default clock.
+    pub fn try_clone(&self) -> Result<Self, CloneError> {
+        let mut clone = Self::default();
+        for (index, entry) in self.state_stack.iter().enumerate() {
+            let state = entry
+                .state
+                .clone_state()
+                .ok_or_else(|| CloneError::NotCloneable {
+                    index,
+                    name: entry.state.name().to_string(),
+                })?;
+            let mut copy = StackEntry::new(from_global(state), entry.handle);
+            copy.interrupt = entry.interrupt;
+            copy.paused = entry.paused;
+            copy.group = entry.group;
+            #[cfg(feature = "time")]
+            {
+                copy.since_update = entry.since_update;
+                copy.active_time = entry.active_time;
+                copy.age = entry.age;
+            }
+            clone.state_stack.push(copy);
+        }
+        for initial in &self.initial_states {
+            let state = initial
+                .clone_state()
+                .ok_or_else(|| CloneError::InitialNotCloneable {
+                    name: initial.name().to_string(),
+                })?;
+            clone.initial_states.push(state);
+        }
+        if let Some(overlays) = &self.overlays {
+            clone.overlays = Some(Box::new(overlays.try_clone()?));
+        }
+        clone.events = self.events.clone();
+        clone.max_start_chain = self.max_start_chain;
+        clone.max_depth = self.max_depth;
+        clone.transition_budget = self.transition_budget;
+        clone.depth_policy = self.depth_policy;
+        clone.duplicate_policy = self.duplicate_policy;
+        clone.stop_vetoes = self.stop_vetoes;
+        clone.leak_check = self.leak_check;
+        clone.shrink_on_stop = self.shrink_on_stop;
+        clone.switch_ordering = self.switch_ordering;
+        clone.active_depth = self.active_depth;
+        clone.active_transitions = self.active_transitions;
+        clone.transition_resolution = self.transition_resolution;
+        clone.frozen = self.frozen;
+        clone.exit_reason = self.exit_reason;
+        clone.transition_table = self.transition_table.clone();
+        clone.metrics = self.metrics.clone();
+        clone.history = self.history.clone();
+        clone.frame = self.frame;
+        clone.idle_updates = self.idle_updates;
+        clone.next_handle = self.next_handle;
+        clone.next_group = self.next_group;
+        #[cfg(feature = "time")]
+        {
+            clone.fixed_timestep = self.fixed_timestep;
+            clone.max_fixed_steps = self.max_fixed_steps;
+            clone.accumulator = self.accumulator;
+        }
+        #[cfg(feature = "catch_unwind")]
+        {
+            clone.panic_policy = self.panic_policy;
+            clone.stop_after_panic = self.stop_after_panic;
+        }
+        Ok(clone)
+    }
+}
+
+/// Clones the machine with `try_clone`.
+///
+/// # Panics
+/// Panics if one of the states cannot be cloned.
+#[cfg(feature = "clone")]
+impl<S: ?Sized, E: Clone> Clone for StateMachine<S, E> {
+    fn clone(&self) -> Self {
+        match self.try_clone() {
+            Ok(clone) => clone,
+            Err(error) => panic!("{}", error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    /// Walks through a maze, turning right at its third step.
+    #[derive(Clone)]
+    struct Walker {
+        steps: u32,
+        turned: bool,
+    }
+
+    impl State<Vec<u32>> for Walker {
+        fn name(&self) -> &str {
+            "walker"
+        }
+
+        fn update(&mut self, path: &mut Vec<u32>) -> StateTransition<Vec<u32>> {
+            self.steps += 1;
+            path.push(self.steps);
+            if self.steps == 3 && !self.turned {
+                self.turned = true;
+                StateTransition::Push(Box::new(Turn))
+            } else {
+                StateTransition::None
+            }
+        }
+
+        fn clone_state(&self) -> Option<Box<dyn State<Vec<u32>>>> {
+            Some(Box::new(self.clone()))
+        }
+    }
+
+    #[derive(Clone)]
+    struct Turn;
+
+    impl State<Vec<u32>> for Turn {
+        fn name(&self) -> &str {
+            "turn"
+        }
+
+        fn update(&mut self, _: &mut Vec<u32>) -> StateTransition<Vec<u32>> {
+            StateTransition::Pop
+        }
+
+        fn clone_state(&self) -> Option<Box<dyn State<Vec<u32>>>> {
+            Some(Box::new(self.clone()))
+        }
+    }
+
+    fn names(sm: &StateMachine<Vec<u32>>) -> Vec<&str> {
+        sm.iter().map(|state| state.name()).collect()
+    }
+
+    #[test]
+    fn clones_diverge() {
+        let mut path = Vec::new();
+        let mut sm = StateMachine::<Vec<u32>>::default();
+        let walker = sm.push(
+            Box::new(Walker {
+                steps: 0,
+                turned: false,
+            }),
+            &mut path,
+        );
+        sm.update(&mut path);
+        sm.update(&mut path);
+
+        let mut clone = sm.try_clone().unwrap();
+        let mut simulated = path.clone();
+        clone.update(&mut simulated);
+        assert_eq!(names(&clone), ["walker", "turn"]);
+        assert_eq!(names(&sm), ["walker"]);
+        clone.update(&mut simulated);
+        clone.update(&mut simulated);
+        assert_eq!(simulated, [1, 2, 3, 4]);
+        assert_eq!(clone.frame(), 5);
+
+        sm.update(&mut path);
+        assert_eq!(path, [1, 2, 3]);
+        sm.remove(walker, &mut path);
+        assert!(!sm.is_alive(walker));
+        assert!(clone.is_alive(walker));
+    }
+
+    #[cfg(feature = "clone")]
+    #[test]
+    fn clone_impl() {
+        let mut path = Vec::new();
+        let mut sm = StateMachine::<Vec<u32>>::default();
+        sm.push(Box::new(Turn), &mut path);
+        let mut clone = sm.clone();
+        clone.update(&mut path);
+        assert!(!clone.is_running());
+        assert!(sm.is_running());
+    }
+
+    struct Menu;
+
+    impl State<Vec<u32>> for Menu {
+        fn name(&self) -> &str {
+            "menu"
+        }
+    }
+
+    #[test]
+    fn not_cloneable() {
+        let mut path = Vec::new();
+        let mut sm = StateMachine::<Vec<u32>>::default();
+        sm.push(Box::new(Menu), &mut path);
+        assert_eq!(
+            sm.try_clone().err(),
+            Some(CloneError::NotCloneable {
+                index: 0,
+                name: "menu".to_string(),
+            })
+        );
+    }
+}
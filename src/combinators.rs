@@ -0,0 +1,635 @@
+//! Small states to build scripted sequences with, like cutscenes, by pushing them with
+//! `StateTransition::PushMany` or running them in a `SequenceState`.
+
+use crate::{
+    BoxError, ErrorOutcome, EventOutcome, MaybeSend, MessageOutcome, PauseReason, Payload,
+    ResumeReason, State, StateContext, StateTransition, StopDecision, TransitionDecl,
+};
+use std::any::{Any, TypeId};
+use std::error::Error;
+#[cfg(feature = "time")]
+use std::time::Duration;
+
+/// What a `WaitState` waits for.
+enum Wait<S: ?Sized> {
+    Frames(u64),
+    Until(Box<maybe_send!(dyn FnMut(&S) -> bool)>),
+    Forever,
+}
+
+/// A state which waits, then pops itself or performs the transition given to `then`.
+///
+/// ```rust,ignore
+/// StateTransition::PushMany(vec![
+///     Box::new(WaitState::until(|data: &Data| data.loading_done).then(StateTransition::switch(Game))),
+///     Box::new(WaitState::frames(120)),
+/// ])
+/// ```
+pub struct WaitState<S: ?Sized, E = ()> {
+    wait: Wait<S>,
+    then: Option<StateTransition<S, E>>,
+}
+
+impl<S: ?Sized, E> WaitState<S, E> {
+    /// Waits to be updated this many times, and finishes on the last of these updates.
+    pub fn frames(frames: u64) -> Self {
+        Self::new(Wait::Frames(frames))
+    }
+
+    /// Waits until the predicate holds for the state data, checking it on each update.
+    pub fn until(predicate: impl FnMut(&S) -> bool + MaybeSend + 'static) -> Self {
+        Self::new(Wait::Until(Box::new(predicate)))
+    }
+
+    /// Never finishes, as a placeholder doing nothing.
+    pub fn forever() -> Self {
+        Self::new(Wait::Forever)
+    }
+
+    /// Performs this transition when the wait is over, instead of popping.
+    pub fn then(mut self, transition: StateTransition<S, E>) -> Self {
+        self.then = Some(transition);
+        self
+    }
+
+    fn new(wait: Wait<S>) -> Self {
+        Self { wait, then: None }
+    }
+
+    /// Advances the wait by one update, and returns if it is over.
+    fn over(&mut self, state_data: &S) -> bool {
+        match &mut self.wait {
+            Wait::Frames(frames) => {
+                *frames = frames.saturating_sub(1);
+                *frames == 0
+            }
+            Wait::Until(predicate) => predicate(state_data),
+            Wait::Forever => false,
+        }
+    }
+}
+
+impl<S: ?Sized + 'static, E: 'static> State<S, E> for WaitState<S, E> {
+    fn update(&mut self, state_data: &mut S) -> StateTransition<S, E> {
+        if self.over(state_data) {
+            self.then.take().unwrap_or(StateTransition::Pop)
+        } else {
+            StateTransition::None
+        }
+    }
+}
+
+/// A state running other states one after the other as a single entry of the stack, like the
+/// steps of a cutscene.
+///
+/// Starting it starts the first child. When the current child pops, from any of its hooks, it is
+/// stopped and the next one is started, and the sequence pops after the last one. The other
+/// transitions of the children are performed on the stack, as if the sequence returned them,
+/// along with the ones the children return when they are stopped and started. Every hook of the
+/// sequence is passed on to its current child, like its events and the payloads it is resumed
+/// with, and the sequence has the name, tags and transparency of its current child.
+pub struct SequenceState<S: ?Sized, E = ()> {
+    children: Vec<Box<dyn State<S, E>>>,
+    current: usize,
+}
+
+impl<S: ?Sized, E> SequenceState<S, E> {
+    /// Creates a sequence running these states in order.
+    pub fn new(children: Vec<Box<dyn State<S, E>>>) -> Self {
+        Self {
+            children,
+            current: 0,
+        }
+    }
+
+    /// Returns the index of the child running now, which is the number of children when the
+    /// sequence is over.
+    pub fn current(&self) -> usize {
+        self.current
+    }
+
+    fn child(&mut self) -> Option<&mut Box<dyn State<S, E>>> {
+        self.children.get_mut(self.current)
+    }
+}
+
+impl<S: ?Sized + 'static, E: 'static> SequenceState<S, E> {
+    /// Performs a transition returned by the current child: a pop stops it and starts the next
+    /// one, until a child does not pop right away, and pops the sequence after the last one.
+    /// The transitions returned by the stopped children are performed after the sequence's own.
+    fn advance(
+        &mut self,
+        mut trans: StateTransition<S, E>,
+        state_data: &mut S,
+    ) -> StateTransition<S, E> {
+        let mut stopped = Vec::new();
+        while let StateTransition::Pop = trans {
+            let child = match self.child() {
+                Some(child) => child,
+                None => break,
+            };
+            stopped.push(child.on_stop(state_data));
+            self.current += 1;
+            trans = match self.child() {
+                Some(next) => next.on_start(state_data),
+                None => StateTransition::Pop,
+            };
+        }
+        stopped.retain(|stopped| !matches!(stopped, StateTransition::None));
+        if stopped.is_empty() {
+            return trans;
+        }
+        if !matches!(trans, StateTransition::None) {
+            stopped.insert(0, trans);
+        }
+        match stopped.len() {
+            1 => stopped.remove(0),
+            _ => StateTransition::Sequence(stopped),
+        }
+    }
+}
+
+impl<S: ?Sized + 'static, E: 'static> State<S, E> for SequenceState<S, E> {
+    fn name(&self) -> &str {
+        match self.children.get(self.current) {
+            Some(child) => child.name(),
+            None => std::any::type_name::<Self>(),
+        }
+    }
+
+    fn is_transparent(&self) -> bool {
+        self.children
+            .get(self.current)
+            .is_some_and(|child| child.is_transparent())
+    }
+
+    fn tags(&self) -> &[&'static str] {
+        match self.children.get(self.current) {
+            Some(child) => child.tags(),
+            None => &[],
+        }
+    }
+
+    fn required_below(&self) -> &[TypeId] {
+        match self.children.get(self.current) {
+            Some(child) => child.required_below(),
+            None => &[],
+        }
+    }
+
+    fn pops_group(&self) -> bool {
+        self.children
+            .get(self.current)
+            .is_some_and(|child| child.pops_group())
+    }
+
+    fn on_start(&mut self, state_data: &mut S) -> StateTransition<S, E> {
+        let trans = match self.child() {
+            Some(child) => child.on_start(state_data),
+            None => StateTransition::None,
+        };
+        self.advance(trans, state_data)
+    }
+
+    fn try_start(&mut self, state_data: &mut S) -> Result<StateTransition<S, E>, BoxError> {
+        let trans = match self.child() {
+            Some(child) => child.try_start(state_data)?,
+            None => StateTransition::None,
+        };
+        Ok(self.advance(trans, state_data))
+    }
+
+    fn on_stop_requested(&mut self, state_data: &mut S) -> StopDecision<S, E> {
+        match self.child() {
+            Some(child) => child.on_stop_requested(state_data),
+            None => StopDecision::Allow,
+        }
+    }
+
+    fn on_stop(&mut self, state_data: &mut S) -> StateTransition<S, E> {
+        match self.child() {
+            Some(child) => child.on_stop(state_data),
+            None => StateTransition::None,
+        }
+    }
+
+    fn on_quit(&mut self, state_data: &mut S) {
+        if let Some(child) = self.child() {
+            child.on_quit(state_data);
+        }
+    }
+
+    fn on_pause(&mut self, state_data: &mut S) {
+        if let Some(child) = self.child() {
+            child.on_pause(state_data);
+        }
+    }
+
+    fn on_resume(&mut self, state_data: &mut S) -> StateTransition<S, E> {
+        let trans = match self.child() {
+            Some(child) => child.on_resume(state_data),
+            None => StateTransition::None,
+        };
+        self.advance(trans, state_data)
+    }
+
+    fn on_pause_with_reason(&mut self, reason: PauseReason, state_data: &mut S) {
+        if let Some(child) = self.child() {
+            child.on_pause_with_reason(reason, state_data);
+        }
+    }
+
+    fn on_resume_with_reason(
+        &mut self,
+        reason: ResumeReason,
+        state_data: &mut S,
+    ) -> StateTransition<S, E> {
+        let trans = match self.child() {
+            Some(child) => child.on_resume_with_reason(reason, state_data),
+            None => StateTransition::None,
+        };
+        self.advance(trans, state_data)
+    }
+
+    fn on_resume_with(&mut self, payload: Payload, state_data: &mut S) -> StateTransition<S, E> {
+        let trans = match self.child() {
+            Some(child) => child.on_resume_with(payload, state_data),
+            None => StateTransition::None,
+        };
+        self.advance(trans, state_data)
+    }
+
+    fn pre_update(&mut self, state_data: &mut S) {
+        if let Some(child) = self.child() {
+            child.pre_update(state_data);
+        }
+    }
+
+    fn post_update(&mut self, state_data: &mut S, result: &StateTransition<S, E>) {
+        if let Some(child) = self.child() {
+            child.post_update(state_data, result);
+        }
+    }
+
+    fn try_update_with_context(
+        &mut self,
+        context: &StateContext,
+        state_data: &mut S,
+    ) -> Result<StateTransition<S, E>, BoxError> {
+        let trans = match self.child() {
+            Some(child) => child.try_update_with_context(context, state_data)?,
+            None => StateTransition::Pop,
+        };
+        Ok(self.advance(trans, state_data))
+    }
+
+    fn try_update_with_extra(
+        &mut self,
+        extra: &dyn Any,
+        context: &StateContext,
+        state_data: &mut S,
+    ) -> Result<StateTransition<S, E>, BoxError> {
+        let trans = match self.child() {
+            Some(child) => child.try_update_with_extra(extra, context, state_data)?,
+            None => StateTransition::Pop,
+        };
+        Ok(self.advance(trans, state_data))
+    }
+
+    fn on_error(&mut self, error: &dyn Error, state_data: &mut S) -> ErrorOutcome<S, E> {
+        match self.child().map(|child| child.on_error(error, state_data)) {
+            Some(ErrorOutcome::Handled(trans)) => {
+                ErrorOutcome::Handled(self.advance(trans, state_data))
+            }
+            _ => ErrorOutcome::Unhandled,
+        }
+    }
+
+    #[cfg(feature = "time")]
+    fn fixed_update(&mut self, state_data: &mut S) -> StateTransition<S, E> {
+        let trans = match self.child() {
+            Some(child) => child.fixed_update(state_data),
+            None => StateTransition::None,
+        };
+        self.advance(trans, state_data)
+    }
+
+    #[cfg(feature = "time")]
+    fn fixed_update_with_extra(
+        &mut self,
+        extra: &dyn Any,
+        state_data: &mut S,
+    ) -> StateTransition<S, E> {
+        let trans = match self.child() {
+            Some(child) => child.fixed_update_with_extra(extra, state_data),
+            None => StateTransition::None,
+        };
+        self.advance(trans, state_data)
+    }
+
+    #[cfg(feature = "time")]
+    fn update_interval(&self) -> Option<Duration> {
+        self.children
+            .get(self.current)
+            .and_then(|child| child.update_interval())
+    }
+
+    fn shadow_update_with_context(&mut self, context: &StateContext, state_data: &mut S) {
+        if let Some(child) = self.child() {
+            child.shadow_update_with_context(context, state_data);
+        }
+    }
+
+    fn shadow_update_with_transition(
+        &mut self,
+        context: &StateContext,
+        state_data: &mut S,
+    ) -> StateTransition<S, E> {
+        let trans = match self.child() {
+            Some(child) => child.shadow_update_with_transition(context, state_data),
+            None => StateTransition::None,
+        };
+        self.advance(trans, state_data)
+    }
+
+    #[cfg(feature = "parallel")]
+    fn par_shadow_update(&mut self, state_data: &S) {
+        if let Some(child) = self.child() {
+            child.par_shadow_update(state_data);
+        }
+    }
+
+    fn render_with_context(&mut self, context: &StateContext, state_data: &mut S) {
+        if let Some(child) = self.child() {
+            child.render_with_context(context, state_data);
+        }
+    }
+
+    fn blocks_rendering(&self) -> bool {
+        self.children
+            .get(self.current)
+            .is_some_and(|child| child.blocks_rendering())
+    }
+
+    fn on_save(&mut self, state_data: &mut S) {
+        if let Some(child) = self.child() {
+            child.on_save(state_data);
+        }
+    }
+
+    fn on_load(&mut self, state_data: &mut S) {
+        if let Some(child) = self.child() {
+            child.on_load(state_data);
+        }
+    }
+
+    fn handle_event(&mut self, event: &E, state_data: &mut S) -> EventOutcome<S, E> {
+        match self
+            .child()
+            .map(|child| child.handle_event(event, state_data))
+        {
+            Some(EventOutcome::Consumed(trans)) => {
+                EventOutcome::Consumed(self.advance(trans, state_data))
+            }
+            _ => EventOutcome::Ignored,
+        }
+    }
+
+    fn handle_event_with_extra(
+        &mut self,
+        event: &E,
+        extra: &dyn Any,
+        state_data: &mut S,
+    ) -> EventOutcome<S, E> {
+        match self
+            .child()
+            .map(|child| child.handle_event_with_extra(event, extra, state_data))
+        {
+            Some(EventOutcome::Consumed(trans)) => {
+                EventOutcome::Consumed(self.advance(trans, state_data))
+            }
+            _ => EventOutcome::Ignored,
+        }
+    }
+
+    fn on_message(&mut self, message: &dyn Any, state_data: &mut S) -> MessageOutcome {
+        match self.child() {
+            Some(child) => child.on_message(message, state_data),
+            None => MessageOutcome::Ignored,
+        }
+    }
+
+    fn declared_transitions(&self) -> Vec<TransitionDecl> {
+        self.children
+            .iter()
+            .flat_map(|child| child.declared_transitions())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    #[derive(Default)]
+    struct Loading {
+        done: bool,
+    }
+
+    struct Finale;
+
+    impl State<Loading> for Finale {}
+
+    #[test]
+    fn chained() {
+        let mut data = Loading::default();
+        let mut sm = StateMachine::<Loading>::default();
+        sm.push(Box::new(WaitState::forever()), &mut data);
+        let finale = StateTransition::Push(Box::new(Finale));
+        sm.queue_transition(StateTransition::PushMany(vec![
+            Box::new(WaitState::until(|data: &Loading| data.done).then(finale)),
+            Box::new(WaitState::frames(2)),
+        ]));
+
+        let mut depths = Vec::new();
+        for frame in 0..5 {
+            data.done = frame == 3;
+            sm.update(&mut data);
+            depths.push(sm.len());
+        }
+        assert_eq!(depths, vec![3, 2, 2, 3, 3]);
+        assert!(sm.active_state_as::<Finale>().is_some());
+    }
+
+    #[test]
+    fn sequence() {
+        let calls = CallLog::default();
+        let step = |name: &str| -> Box<dyn State<()>> {
+            Box::new(RecordingState::new(name, &calls).returning(StateTransition::Pop))
+        };
+        let children = vec![step("intro"), step("pan"), step("wave")];
+        let mut sm = StateMachine::<()>::default();
+        sm.push(Box::new(SequenceState::new(children)), &mut ());
+
+        let mut depths = Vec::new();
+        while sm.is_running() {
+            sm.update(&mut ());
+            depths.push(sm.len());
+        }
+        assert_eq!(depths, vec![1, 1, 0]);
+        assert_calls(
+            &calls,
+            &[
+                ("intro", LifecycleCall::Start),
+                ("intro", LifecycleCall::Update),
+                ("intro", LifecycleCall::Stop),
+                ("pan", LifecycleCall::Start),
+                ("pan", LifecycleCall::Update),
+                ("pan", LifecycleCall::Stop),
+                ("wave", LifecycleCall::Start),
+                ("wave", LifecycleCall::Update),
+                ("wave", LifecycleCall::Stop),
+            ],
+        );
+    }
+
+    #[test]
+    fn sequence_forwards() {
+        let calls = CallLog::default();
+        let menu = RecordingState::new("menu", &calls);
+        let intro =
+            RecordingState::new("intro", &calls).returning(StateTransition::Push(Box::new(menu)));
+        let mut sm = StateMachine::<()>::default();
+        sm.push(Box::new(SequenceState::new(vec![Box::new(intro)])), &mut ());
+        sm.update(&mut ());
+        assert_eq!(sm.len(), 2);
+        assert_calls(
+            &calls,
+            &[
+                ("intro", LifecycleCall::Start),
+                ("intro", LifecycleCall::Update),
+                ("intro", LifecycleCall::Pause),
+                ("menu", LifecycleCall::Start),
+            ],
+        );
+        sm.pop(&mut ());
+        assert_calls(
+            &calls,
+            &[
+                ("menu", LifecycleCall::Stop),
+                ("intro", LifecycleCall::Resume),
+            ],
+        );
+    }
+
+    type Log = Vec<String>;
+
+    /// Logs its hooks, and pops when given its event or a payload.
+    struct Step(&'static str, u32);
+
+    impl State<Log, u32> for Step {
+        fn name(&self) -> &str {
+            self.0
+        }
+
+        fn on_start(&mut self, log: &mut Log) -> StateTransition<Log, u32> {
+            log.push(format!("{}:start", self.0));
+            StateTransition::None
+        }
+
+        fn on_stop(&mut self, log: &mut Log) -> StateTransition<Log, u32> {
+            log.push(format!("{}:stop", self.0));
+            StateTransition::None
+        }
+
+        fn on_quit(&mut self, log: &mut Log) {
+            log.push(format!("{}:quit", self.0));
+        }
+
+        fn on_resume_with(&mut self, payload: Payload, log: &mut Log) -> StateTransition<Log, u32> {
+            let picked = payload.downcast::<&str>().unwrap();
+            log.push(format!("{}:picked {}", self.0, picked));
+            StateTransition::Pop
+        }
+
+        fn handle_event(&mut self, event: &u32, log: &mut Log) -> EventOutcome<Log, u32> {
+            log.push(format!("{}:event {}", self.0, event));
+            if *event == self.1 {
+                EventOutcome::Consumed(StateTransition::Pop)
+            } else {
+                EventOutcome::Ignored
+            }
+        }
+    }
+
+    /// Pops with its pick on its first update.
+    struct Picker(&'static str);
+
+    impl State<Log, u32> for Picker {
+        fn update(&mut self, _log: &mut Log) -> StateTransition<Log, u32> {
+            StateTransition::PopWith(Box::new(self.0))
+        }
+    }
+
+    #[test]
+    fn sequence_hooks() {
+        let mut log = Log::new();
+        let children: Vec<Box<dyn State<Log, u32>>> = vec![
+            Box::new(Step("intro", 1)),
+            Box::new(Step("choice", 1)),
+            Box::new(Step("outro", 1)),
+        ];
+        let mut sm = StateMachine::<Log, u32>::default();
+        sm.push(Box::new(SequenceState::new(children)), &mut log);
+        assert_eq!(sm.active().map(|state| state.name()), Some("intro"));
+
+        sm.handle_event(&2, &mut log);
+        sm.handle_event(&1, &mut log);
+        assert_eq!(
+            log,
+            [
+                "intro:start",
+                "intro:event 2",
+                "intro:event 1",
+                "intro:stop",
+                "choice:start"
+            ]
+        );
+
+        log.clear();
+        sm.push(Box::new(Picker("left")), &mut log);
+        sm.update(&mut log);
+        assert_eq!(log, ["choice:picked left", "choice:stop", "outro:start"]);
+
+        log.clear();
+        sm.queue_transition(StateTransition::Quit);
+        sm.update(&mut log);
+        assert_eq!(log, ["outro:quit", "outro:stop"]);
+        assert!(!sm.is_running());
+    }
+
+    /// Pops on its first update, and pushes a toast when it is stopped.
+    struct Leaving;
+
+    impl State<Log, u32> for Leaving {
+        fn update(&mut self, _log: &mut Log) -> StateTransition<Log, u32> {
+            StateTransition::Pop
+        }
+
+        fn on_stop(&mut self, _log: &mut Log) -> StateTransition<Log, u32> {
+            StateTransition::Push(Box::new(Step("toast", 0)))
+        }
+    }
+
+    #[test]
+    fn sequence_keeps_stop_transitions() {
+        let mut log = Log::new();
+        let children: Vec<Box<dyn State<Log, u32>>> =
+            vec![Box::new(Leaving), Box::new(Step("outro", 1))];
+        let mut sm = StateMachine::<Log, u32>::default();
+        sm.push(Box::new(SequenceState::new(children)), &mut log);
+        sm.update(&mut log);
+        assert_eq!(log, ["outro:start", "toast:start"]);
+        assert_eq!(sm.len(), 2);
+    }
+}
@@ -0,0 +1,4 @@
+//! Adapters for the states written for other state machines, to migrate a project to this one
+//! with few changes.
+
+pub mod amethyst_like;
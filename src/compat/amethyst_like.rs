@@ -0,0 +1,519 @@
+//! Running the states written for the `State` trait of Amethyst, changing only their imports to
+//! take `AmethystStyleState` as `State`, along with `StateData` and `Trans` from this module.
+//!
+//! The machine runs on an `AmethystData`, which holds the world and the game data the states
+//! get in their `StateData`, and each state is pushed wrapped in an `AmethystAdapter`.
+//!
+//! ```rust
+//! use game_state_machine::compat::amethyst_like::{
+//!     AmethystData, AmethystStyleState as State, StateData, Trans, World,
+//! };
+//! use game_state_machine::StateMachine;
+//!
+//! struct Score(u32);
+//!
+//! struct Gameplay;
+//!
+//! impl State<(), ()> for Gameplay {
+//!     fn on_start(&mut self, data: StateData<'_, ()>) {
+//!         data.world.insert(Score(0));
+//!     }
+//!
+//!     fn update(&mut self, data: StateData<'_, ()>) -> Trans<(), ()> {
+//!         data.world.write_resource::<Score>().0 += 1;
+//!         Trans::Quit
+//!     }
+//! }
+//!
+//! let mut game = AmethystData::new(World::new(), ());
+//! let mut sm = StateMachine::<AmethystData<()>>::default();
+//! sm.push(Gameplay.boxed(), &mut game);
+//! sm.update(&mut game);
+//! assert_eq!(game.world.read_resource::<Score>().0, 1);
+//! assert!(!sm.is_running());
+//! ```
+
+use crate::{EventOutcome, MaybeSend, State, StateTransition};
+use std::any::{type_name, Any, TypeId};
+use std::collections::HashMap;
+
+/// The resources shared by the states, like the `World` of Amethyst without its entities and
+/// components.
+#[derive(Default)]
+pub struct World {
+    resources: HashMap<TypeId, Box<maybe_send!(dyn Any)>>,
+}
+
+impl World {
+    /// Creates a world without resources.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a resource, replacing the one of the same type.
+    pub fn insert<R: Any + MaybeSend>(&mut self, resource: R) {
+        self.resources.insert(TypeId::of::<R>(), Box::new(resource));
+    }
+
+    /// Removes the resource of this type, returning it.
+    pub fn remove<R: Any + MaybeSend>(&mut self) -> Option<R> {
+        let resource = self.resources.remove(&TypeId::of::<R>())?;
+        resource.downcast().ok().map(|resource| *resource)
+    }
+
+    /// Returns if there is a resource of this type.
+    pub fn has_value<R: Any>(&self) -> bool {
+        self.resources.contains_key(&TypeId::of::<R>())
+    }
+
+    /// Returns the resource of this type, if any.
+    pub fn try_fetch<R: Any>(&self) -> Option<&R> {
+        self.resources.get(&TypeId::of::<R>())?.downcast_ref()
+    }
+
+    /// Returns the resource of this type mutably, if any.
+    pub fn try_fetch_mut<R: Any>(&mut self) -> Option<&mut R> {
+        self.resources.get_mut(&TypeId::of::<R>())?.downcast_mut()
+    }
+
+    /// Returns the resource of this type.
+    ///
+    /// ## Panics
+    /// Panics if there is none, like in Amethyst.
+    pub fn read_resource<R: Any>(&self) -> &R {
+        match self.try_fetch() {
+            Some(resource) => resource,
+            None => panic!("The world has no resource {}.", type_name::<R>()),
+        }
+    }
+
+    /// Returns the resource of this type mutably.
+    ///
+    /// ## Panics
+    /// Panics if there is none, like in Amethyst.
+    pub fn write_resource<R: Any>(&mut self) -> &mut R {
+        match self.try_fetch_mut() {
+            Some(resource) => resource,
+            None => panic!("The world has no resource {}.", type_name::<R>()),
+        }
+    }
+}
+
+/// The state data of a machine running `AmethystStyleState`s: the world, and the game data, like
+/// the dispatchers of the `GameData` of Amethyst.
+pub struct AmethystData<S> {
+    /// The resources shared by the states.
+    pub world: World,
+    /// The game data.
+    pub data: S,
+}
+
+impl<S> AmethystData<S> {
+    /// Bundles the world with the game data.
+    pub fn new(world: World, data: S) -> Self {
+        Self { world, data }
+    }
+
+    /// Borrows the world and the game data, as given to the states.
+    pub fn state_data(&mut self) -> StateData<'_, S> {
+        StateData::new(&mut self.world, &mut self.data)
+    }
+}
+
+/// What the hooks of an `AmethystStyleState` get, borrowed from the `AmethystData` of the
+/// machine.
+pub struct StateData<'a, S> {
+    /// The resources shared by the states.
+    pub world: &'a mut World,
+    /// The game data.
+    pub data: &'a mut S,
+}
+
+impl<'a, S> StateData<'a, S> {
+    /// Bundles the world with the game data.
+    pub fn new(world: &'a mut World, data: &'a mut S) -> Self {
+        Self { world, data }
+    }
+}
+
+/// A transition returned by an `AmethystStyleState`, performed as the `StateTransition` it is
+/// converted to. `Switch` and `Replace` mean the opposite of the variants of the same name of
+/// `StateTransition`.
+pub enum Trans<S, E = ()> {
+    /// Stay in the current state, `StateTransition::None`.
+    None,
+    /// End the current state and go to the previous state on the stack, `StateTransition::Pop`.
+    Pop,
+    /// Pause the current state and push this one, `StateTransition::Push`.
+    Push(Box<dyn AmethystStyleState<S, E>>),
+    /// End the current state and insert this one in its place, `StateTransition::Replace`.
+    Switch(Box<dyn AmethystStyleState<S, E>>),
+    /// End all the states and insert this one, `StateTransition::Switch`.
+    Replace(Box<dyn AmethystStyleState<S, E>>),
+    /// End all the states and insert these ones, from the bottom to the top,
+    /// `StateTransition::ReplaceAll`.
+    NewStack(Vec<Box<dyn AmethystStyleState<S, E>>>),
+    /// Apply each transition in order, `StateTransition::Sequence`.
+    Sequence(Vec<Trans<S, E>>),
+    /// End all the states and exit the state machine, `StateTransition::Quit`.
+    Quit,
+}
+
+impl<S: 'static, E: Clone + 'static> From<Trans<S, E>> for StateTransition<AmethystData<S>, E> {
+    /// Converts the transition, wrapping the states it carries in `AmethystAdapter`.
+    fn from(trans: Trans<S, E>) -> Self {
+        match trans {
+            Trans::None => StateTransition::None,
+            Trans::Pop => StateTransition::Pop,
+            Trans::Push(state) => StateTransition::Push(state.boxed()),
+            Trans::Switch(state) => StateTransition::Replace(state.boxed()),
+            Trans::Replace(state) => StateTransition::Switch(state.boxed()),
+            Trans::NewStack(states) => StateTransition::ReplaceAll(
+                states.into_iter().map(AmethystStyleState::boxed).collect(),
+            ),
+            Trans::Sequence(transitions) => {
+                StateTransition::Sequence(transitions.into_iter().map(Into::into).collect())
+            }
+            Trans::Quit => StateTransition::Quit,
+        }
+    }
+}
+
+/// A state with the shape of the `State` trait of Amethyst, pushed on a
+/// `StateMachine<AmethystData<S>, E>` by wrapping it in an `AmethystAdapter`.
+///
+/// ## Generics
+/// - S: Game data, given to the hooks along with the world.
+/// - E: Events, given to `handle_event` by value.
+pub trait AmethystStyleState<S, E = ()>: MaybeSend {
+    /// The name of the state, see `State::name`.
+    fn name(&self) -> &str {
+        type_name::<Self>()
+    }
+    /// Called when the state is first inserted on the stack.
+    fn on_start(&mut self, _data: StateData<'_, S>) {}
+    /// Called when the state is popped from the stack.
+    fn on_stop(&mut self, _data: StateData<'_, S>) {}
+    /// Called when a state is pushed over this one in the stack.
+    fn on_pause(&mut self, _data: StateData<'_, S>) {}
+    /// Called when the state just on top of this one in the stack is popped.
+    fn on_resume(&mut self, _data: StateData<'_, S>) {}
+    /// Called with the events sent to the machine while the state is on top of the stack.
+    /// Like in Amethyst, the events do not reach the states below it, even when it returns
+    /// `Trans::None`.
+    fn handle_event(&mut self, _data: StateData<'_, S>, _event: E) -> Trans<S, E> {
+        Trans::None
+    }
+    /// Executed at a fixed interval while the state is on top of the stack, see
+    /// `State::fixed_update`. It is only called with the `time` feature, which the fixed
+    /// updates of the machine need.
+    fn fixed_update(&mut self, _data: StateData<'_, S>) -> Trans<S, E> {
+        Trans::None
+    }
+    /// Executed on every frame while the state is on top of the stack.
+    fn update(&mut self, _data: StateData<'_, S>) -> Trans<S, E> {
+        Trans::None
+    }
+    /// Executed at a fixed interval while another state is on top of this one in Amethyst.
+    /// It is never called, since the machine only gives the fixed updates to the top state.
+    fn shadow_fixed_update(&mut self, _data: StateData<'_, S>) {}
+    /// Executed on every frame while another state is on top of this one in the stack.
+    fn shadow_update(&mut self, _data: StateData<'_, S>) {}
+
+    /// Wraps the state in an `AmethystAdapter` and boxes it, to push it on a machine.
+    fn boxed(self) -> Box<dyn State<AmethystData<S>, E>>
+    where
+        Self: Sized + 'static,
+        S: 'static,
+        E: Clone + 'static,
+    {
+        Box::new(AmethystAdapter(self))
+    }
+}
+
+impl<S, E> AmethystStyleState<S, E> for Box<dyn AmethystStyleState<S, E>> {
+    fn name(&self) -> &str {
+        (**self).name()
+    }
+
+    fn on_start(&mut self, data: StateData<'_, S>) {
+        (**self).on_start(data)
+    }
+
+    fn on_stop(&mut self, data: StateData<'_, S>) {
+        (**self).on_stop(data)
+    }
+
+    fn on_pause(&mut self, data: StateData<'_, S>) {
+        (**self).on_pause(data)
+    }
+
+    fn on_resume(&mut self, data: StateData<'_, S>) {
+        (**self).on_resume(data)
+    }
+
+    fn handle_event(&mut self, data: StateData<'_, S>, event: E) -> Trans<S, E> {
+        (**self).handle_event(data, event)
+    }
+
+    fn fixed_update(&mut self, data: StateData<'_, S>) -> Trans<S, E> {
+        (**self).fixed_update(data)
+    }
+
+    fn update(&mut self, data: StateData<'_, S>) -> Trans<S, E> {
+        (**self).update(data)
+    }
+
+    fn shadow_fixed_update(&mut self, data: StateData<'_, S>) {
+        (**self).shadow_fixed_update(data)
+    }
+
+    fn shadow_update(&mut self, data: StateData<'_, S>) {
+        (**self).shadow_update(data)
+    }
+}
+
+/// Makes an `AmethystStyleState` a `State` of its `AmethystData`, so it can be pushed on a
+/// `StateMachine` along with the other states. The events are cloned to be given by value.
+/// Use `StateMachine::contains_state::<AmethystAdapter<T>>` to find the state `T`.
+pub struct AmethystAdapter<T>(pub T);
+
+impl<S: 'static, E: Clone + 'static, T: AmethystStyleState<S, E> + 'static>
+    State<AmethystData<S>, E> for AmethystAdapter<T>
+{
+    fn name(&self) -> &str {
+        self.0.name()
+    }
+
+    fn on_start(
+        &mut self,
+        state_data: &mut AmethystData<S>,
+    ) -> StateTransition<AmethystData<S>, E> {
+        self.0.on_start(state_data.state_data());
+        StateTransition::None
+    }
+
+    fn on_stop(&mut self, state_data: &mut AmethystData<S>) -> StateTransition<AmethystData<S>, E> {
+        self.0.on_stop(state_data.state_data());
+        StateTransition::None
+    }
+
+    fn on_pause(&mut self, state_data: &mut AmethystData<S>) {
+        self.0.on_pause(state_data.state_data());
+    }
+
+    fn on_resume(
+        &mut self,
+        state_data: &mut AmethystData<S>,
+    ) -> StateTransition<AmethystData<S>, E> {
+        self.0.on_resume(state_data.state_data());
+        StateTransition::None
+    }
+
+    fn handle_event(
+        &mut self,
+        event: &E,
+        state_data: &mut AmethystData<S>,
+    ) -> EventOutcome<AmethystData<S>, E> {
+        let trans = self.0.handle_event(state_data.state_data(), event.clone());
+        EventOutcome::Consumed(trans.into())
+    }
+
+    #[cfg(feature = "time")]
+    fn fixed_update(
+        &mut self,
+        state_data: &mut AmethystData<S>,
+    ) -> StateTransition<AmethystData<S>, E> {
+        self.0.fixed_update(state_data.state_data()).into()
+    }
+
+    fn update(&mut self, state_data: &mut AmethystData<S>) -> StateTransition<AmethystData<S>, E> {
+        self.0.update(state_data.state_data()).into()
+    }
+
+    fn shadow_update(&mut self, state_data: &mut AmethystData<S>) {
+        self.0.shadow_update(state_data.state_data());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::compat::amethyst_like::{
+        AmethystData, AmethystStyleState as State, StateData, Trans, World,
+    };
+    use crate::{ExitReason, MachineStatus, StateMachine, StopReason};
+
+    /// The events of the game, like the `StateEvent` of Amethyst.
+    #[derive(Clone)]
+    enum StateEvent {
+        Key(char),
+    }
+
+    /// The game data, which the states log to.
+    type GameData = Vec<String>;
+
+    struct Score(u32);
+
+    /// Sets the world up, then switches to the gameplay.
+    struct Loading;
+
+    impl State<GameData, StateEvent> for Loading {
+        fn on_start(&mut self, data: StateData<'_, GameData>) {
+            data.world.insert(Score(0));
+            data.data.push("loading:start".to_string());
+        }
+
+        fn on_stop(&mut self, data: StateData<'_, GameData>) {
+            data.data.push("loading:stop".to_string());
+        }
+
+        fn update(&mut self, data: StateData<'_, GameData>) -> Trans<GameData, StateEvent> {
+            if data.world.has_value::<Score>() {
+                Trans::Switch(Box::new(Gameplay))
+            } else {
+                Trans::None
+            }
+        }
+    }
+
+    /// Scores on every update, pauses on `p` and quits on `q`.
+    struct Gameplay;
+
+    impl State<GameData, StateEvent> for Gameplay {
+        fn on_start(&mut self, data: StateData<'_, GameData>) {
+            data.data.push("game:start".to_string());
+        }
+
+        fn on_stop(&mut self, data: StateData<'_, GameData>) {
+            data.data.push("game:stop".to_string());
+        }
+
+        fn on_pause(&mut self, data: StateData<'_, GameData>) {
+            data.data.push("game:pause".to_string());
+        }
+
+        fn on_resume(&mut self, data: StateData<'_, GameData>) {
+            data.data.push("game:resume".to_string());
+        }
+
+        fn handle_event(
+            &mut self,
+            _data: StateData<'_, GameData>,
+            event: StateEvent,
+        ) -> Trans<GameData, StateEvent> {
+            match event {
+                StateEvent::Key('p') => Trans::Push(Box::new(Paused)),
+                StateEvent::Key('q') => Trans::Quit,
+                StateEvent::Key(_) => Trans::None,
+            }
+        }
+
+        fn fixed_update(&mut self, data: StateData<'_, GameData>) -> Trans<GameData, StateEvent> {
+            data.data.push("game:fixed".to_string());
+            Trans::None
+        }
+
+        fn update(&mut self, data: StateData<'_, GameData>) -> Trans<GameData, StateEvent> {
+            data.world.write_resource::<Score>().0 += 1;
+            Trans::None
+        }
+
+        fn shadow_update(&mut self, data: StateData<'_, GameData>) {
+            data.data.push("game:shadow".to_string());
+        }
+    }
+
+    /// Resumes the gameplay on `p`.
+    struct Paused;
+
+    impl State<GameData, StateEvent> for Paused {
+        fn on_start(&mut self, data: StateData<'_, GameData>) {
+            data.data.push("paused:start".to_string());
+        }
+
+        fn on_stop(&mut self, data: StateData<'_, GameData>) {
+            data.data.push("paused:stop".to_string());
+        }
+
+        fn handle_event(
+            &mut self,
+            _data: StateData<'_, GameData>,
+            event: StateEvent,
+        ) -> Trans<GameData, StateEvent> {
+            match event {
+                StateEvent::Key('p') => Trans::Pop,
+                StateEvent::Key(_) => Trans::None,
+            }
+        }
+    }
+
+    fn game() -> (
+        StateMachine<AmethystData<GameData>, StateEvent>,
+        AmethystData<GameData>,
+    ) {
+        let mut game = AmethystData::new(World::new(), GameData::new());
+        let mut sm = StateMachine::default();
+        sm.push(Loading.boxed(), &mut game);
+        sm.update(&mut game);
+        (sm, game)
+    }
+
+    #[test]
+    fn push_pop_quit() {
+        let (mut sm, mut game) = game();
+        assert_eq!(game.data, ["loading:start", "loading:stop", "game:start"]);
+        assert_eq!(sm.len(), 1);
+        sm.update(&mut game);
+        assert_eq!(game.world.read_resource::<Score>().0, 1);
+
+        game.data.clear();
+        sm.handle_event(&StateEvent::Key('p'), &mut game);
+        sm.update(&mut game);
+        assert_eq!(game.world.read_resource::<Score>().0, 1);
+
+        // The gameplay below does not get the events.
+        assert_eq!(
+            sm.handle_event(&StateEvent::Key('q'), &mut game),
+            MachineStatus::Running
+        );
+        sm.handle_event(&StateEvent::Key('p'), &mut game);
+        assert_eq!(
+            sm.handle_event(&StateEvent::Key('q'), &mut game),
+            MachineStatus::Stopped(StopReason::Quit)
+        );
+        assert_eq!(
+            game.data,
+            [
+                "game:pause",
+                "paused:start",
+                "game:shadow",
+                "paused:stop",
+                "game:resume",
+                "game:stop"
+            ]
+        );
+        assert_eq!(sm.exit_reason(), Some(ExitReason::Quit));
+    }
+
+    #[cfg(feature = "time")]
+    #[test]
+    fn fixed_update() {
+        let (mut sm, mut game) = game();
+        game.data.clear();
+        sm.set_fixed_timestep(std::time::Duration::from_millis(10));
+        assert_eq!(
+            sm.update_with_dt(std::time::Duration::from_millis(20), &mut game),
+            MachineStatus::Running
+        );
+        assert_eq!(game.data, ["game:fixed", "game:fixed"]);
+        assert_eq!(game.world.read_resource::<Score>().0, 1);
+
+        // `Trans::Replace` stops all the states.
+        sm.queue_transition(Trans::Replace(Box::new(Paused)).into());
+        assert_eq!(
+            sm.update(&mut game),
+            MachineStatus::Transitioned(crate::TransitionKind::Switch)
+        );
+        assert_eq!(sm.len(), 1);
+    }
+}
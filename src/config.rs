@@ -0,0 +1,202 @@
+//! Loading the initial state stack from a JSON document, to change the starting stack without
+//! rebuilding.
+//!
+//! The document lists the names of the states in a `StateRegistry`, from the bottom of the stack
+//! to the top, optionally with the parameters given to their constructor:
+//! ```json
+//! {
+//!     "states": [
+//!         "world",
+//!         { "name": "level", "params": { "id": 3, "hard": true } },
+//!         "pause"
+//!     ]
+//! }
+//! ```
+//! Parameter values can be strings, numbers or booleans, and are given to the constructors as
+//! strings.
+
+use crate::{InstallPolicy, StateMachine, StateParams, StateRegistry, TransitionError};
+use serde::Deserialize;
+use serde_json::Value;
+use std::error::Error;
+use std::fmt;
+use std::io::Read;
+
+#[derive(Deserialize)]
+struct Config {
+    states: Vec<ConfigEntry>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ConfigEntry {
+    Name(String),
+    WithParams {
+        name: String,
+        #[serde(default)]
+        params: serde_json::Map<String, Value>,
+    },
+}
+
+/// A configuration document that could not be loaded.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ConfigError {
+    /// The document is not a valid configuration.
+    Parse(String),
+    /// The state entry at this index of the document could not be created.
+    Entry {
+        /// The index of the entry in the document, from the bottom of the stack.
+        index: usize,
+        /// Why the state could not be created.
+        error: TransitionError,
+    },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Parse(reason) => write!(f, "invalid configuration: {}", reason),
+            ConfigError::Entry { index, error } => write!(f, "state entry {}: {}", index, error),
+        }
+    }
+}
+
+impl Error for ConfigError {}
+
+impl<S: ?Sized, E> StateMachine<S, E> {
+    /// Creates a state machine with the stack described by the JSON configuration document.
+    /// All the states are created before any is started, then they are pushed from the bottom to
+    /// the top, so that only the top state ends up not paused.
+    pub fn from_config(
+        reader: impl Read,
+        registry: &StateRegistry<S, E>,
+        state_data: &mut S,
+    ) -> Result<Self, ConfigError> {
+        let config: Config =
+            serde_json::from_reader(reader).map_err(|e| ConfigError::Parse(e.to_string()))?;
+
+        let states = config
+            .states
+            .into_iter()
+            .enumerate()
+            .map(|(index, entry)| {
+                let entry_error = |error| ConfigError::Entry { index, error };
+                let (name, params) = match entry {
+                    ConfigEntry::Name(name) => (name, StateParams::default()),
+                    ConfigEntry::WithParams { name, params } => {
+                        let params = params
+                            .into_iter()
+                            .map(|(key, value)| match value {
+                                Value::String(value) => Ok((key, value)),
+                                Value::Number(_) | Value::Bool(_) => Ok((key, value.to_string())),
+                                _ => Err(entry_error(TransitionError::InvalidParams {
+                                    state: name.clone(),
+                                    reason: format!("the parameter {} is not a scalar", key),
+                                })),
+                            })
+                            .collect::<Result<_, _>>()?;
+                        (name, params)
+                    }
+                };
+                registry
+                    .create_with_params(&name, &params)
+                    .map_err(entry_error)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut machine = Self::default();
+        let policy = InstallPolicy::Start {
+            keep_previous: false,
+        };
+        machine.set_stack(states, state_data, policy);
+        Ok(machine)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    type Log = Vec<String>;
+
+    pub struct Named(String);
+
+    impl State<Log> for Named {
+        fn on_start(&mut self, log: &mut Log) -> StateTransition<Log> {
+            log.push(format!("{}:start", self.0));
+            StateTransition::None
+        }
+
+        fn on_pause(&mut self, log: &mut Log) {
+            log.push(format!("{}:pause", self.0));
+        }
+    }
+
+    fn registry() -> StateRegistry<Log> {
+        let mut registry = StateRegistry::default();
+        registry.register("world", || Box::new(Named("world".to_string())));
+        registry.register("pause", || Box::new(Named("pause".to_string())));
+        registry.register_with_params("level", |params| {
+            let id: u32 = params
+                .get("id")
+                .ok_or("missing id")?
+                .parse()
+                .map_err(|_| "id is not a number")?;
+            Ok(Box::new(Named(format!("level{}", id))))
+        });
+        registry
+    }
+
+    #[test]
+    fn three_states() {
+        let document =
+            r#"{ "states": ["world", { "name": "level", "params": { "id": 3 } }, "pause"] }"#;
+        let mut log = Log::new();
+
+        let sm = StateMachine::from_config(document.as_bytes(), &registry(), &mut log).unwrap();
+        assert_eq!(sm.len(), 3);
+        assert_eq!(
+            log,
+            vec![
+                "world:start",
+                "world:pause",
+                "level3:start",
+                "level3:pause",
+                "pause:start"
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_documents() {
+        let mut log = Log::new();
+
+        let malformed = r#"{ "states": ["world", "#;
+        match StateMachine::from_config(malformed.as_bytes(), &registry(), &mut log) {
+            Err(ConfigError::Parse(_)) => (),
+            _ => panic!("the document should not parse"),
+        }
+
+        let unknown = r#"{ "states": ["world", "shop"] }"#;
+        assert_eq!(
+            StateMachine::from_config(unknown.as_bytes(), &registry(), &mut log).err(),
+            Some(ConfigError::Entry {
+                index: 1,
+                error: TransitionError::UnknownState("shop".to_string()),
+            })
+        );
+
+        let bad_param = r#"{ "states": [{ "name": "level", "params": { "id": "three" } }] }"#;
+        assert_eq!(
+            StateMachine::from_config(bad_param.as_bytes(), &registry(), &mut log).err(),
+            Some(ConfigError::Entry {
+                index: 0,
+                error: TransitionError::InvalidParams {
+                    state: "level".to_string(),
+                    reason: "id is not a number".to_string(),
+                },
+            })
+        );
+        assert!(log.is_empty());
+    }
+}
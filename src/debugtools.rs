@@ -0,0 +1,348 @@
+//! Rendering the flow of the states as Graphviz graphs, to document it or to see what happened
+//! during a play session.
+
+use crate::{State, StateMachine, TransitionEvent, TransitionKind, TransitionObserver};
+use std::fmt::Write;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// A transition a state declares it may return, see `State::declared_transitions`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TransitionDecl {
+    /// The kind of the transition.
+    pub kind: TransitionKind,
+    /// The name of the state it goes to.
+    pub to: String,
+}
+
+impl TransitionDecl {
+    /// Declares a transition of this kind to the state with this name.
+    pub fn new(kind: TransitionKind, to: impl Into<String>) -> Self {
+        Self {
+            kind,
+            to: to.into(),
+        }
+    }
+
+    /// Declares a push of the state with this name.
+    pub fn push(to: impl Into<String>) -> Self {
+        Self::new(TransitionKind::Push, to)
+    }
+
+    /// Declares a switch to the state with this name.
+    pub fn switch(to: impl Into<String>) -> Self {
+        Self::new(TransitionKind::Switch, to)
+    }
+
+    /// Declares a replacement by the state with this name.
+    pub fn replace(to: impl Into<String>) -> Self {
+        Self::new(TransitionKind::Replace, to)
+    }
+}
+
+/// Quotes a name as a Graphviz identifier.
+pub(crate) fn dot_id(name: &str) -> String {
+    let mut id = String::with_capacity(name.len() + 2);
+    id.push('"');
+    for c in name.chars() {
+        match c {
+            '"' => id.push_str("\\\""),
+            '\\' => id.push_str("\\\\"),
+            '\n' => id.push_str("\\n"),
+            _ => id.push(c),
+        }
+    }
+    id.push('"');
+    id
+}
+
+impl<S: ?Sized, E> StateMachine<S, E> {
+    /// Renders the transitions these states declare as a Graphviz digraph, with a node per
+    /// state, in order, then an edge per declared transition, labelled with its kind.
+    /// The states are not run, so this can be used in a test or a build script.
+    pub fn export_dot(states: &[&dyn State<S, E>]) -> String {
+        let mut dot = String::from("digraph states {\n");
+        for state in states {
+            let _ = writeln!(dot, "    {};", dot_id(state.name()));
+        }
+        for state in states {
+            for decl in state.declared_transitions() {
+                let _ = writeln!(
+                    dot,
+                    "    {} -> {} [label=\"{:?}\"];",
+                    dot_id(state.name()),
+                    dot_id(&decl.to),
+                    decl.kind
+                );
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+/// A transition between two states seen by a `TransitionGraphRecorder`, `None` being the empty
+/// stack.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TransitionEdge {
+    /// The name of the active state before the transition.
+    pub from: Option<String>,
+    /// The name of the active state after the transition.
+    pub to: Option<String>,
+    /// The kind of the transition.
+    pub kind: TransitionKind,
+    /// How many times this transition was performed.
+    pub count: u64,
+}
+
+/// Records the transitions performed by a state machine as a graph, with the number of times
+/// each one was performed, to render it when the session ends.
+///
+/// It is an observer, so it only sees the transitions returned by the states, see
+/// `StateMachine::add_observer`. Each transition is an edge from the active state before it to
+/// the active state after it, so a switch clearing the stack is a single edge from the state
+/// that was on top.
+/// The clones of a recorder share its graph, so a clone can be given to the state machine.
+///
+/// ```rust
+/// use game_state_machine::*;
+///
+/// let recorder = TransitionGraphRecorder::new();
+/// let mut sm = StateMachine::<()>::default();
+/// sm.add_observer(Box::new(recorder.clone()));
+/// sm.push_state(|_: &mut ()| StateTransition::Pop, &mut ());
+/// sm.update(&mut ());
+/// assert_eq!(recorder.edges().len(), 1);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct TransitionGraphRecorder {
+    edges: Arc<Mutex<Vec<TransitionEdge>>>,
+}
+
+impl TransitionGraphRecorder {
+    /// Creates a recorder with an empty graph.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a transition to the graph.
+    pub fn record(&self, event: &TransitionEvent) {
+        self.add(&event.from, &event.to, event.kind, 1);
+    }
+
+    /// Adds the transitions recorded by the other recorder to this one, like those of another
+    /// session.
+    pub fn merge(&self, other: &TransitionGraphRecorder) {
+        let edges = other.edges();
+        for edge in edges {
+            self.add(&edge.from, &edge.to, edge.kind, edge.count);
+        }
+    }
+
+    /// Returns the edges of the graph, in the order they were first recorded.
+    pub fn edges(&self) -> Vec<TransitionEdge> {
+        self.lock().clone()
+    }
+
+    /// Removes all the edges of the graph.
+    pub fn clear(&self) {
+        self.lock().clear();
+    }
+
+    /// Locks the graph, which a panic while it was locked cannot leave half updated.
+    fn lock(&self) -> MutexGuard<'_, Vec<TransitionEdge>> {
+        self.edges
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn add(&self, from: &Option<String>, to: &Option<String>, kind: TransitionKind, count: u64) {
+        let mut edges = self.lock();
+        match edges
+            .iter_mut()
+            .find(|edge| edge.kind == kind && edge.from == *from && edge.to == *to)
+        {
+            Some(edge) => edge.count += count,
+            None => edges.push(TransitionEdge {
+                from: from.clone(),
+                to: to.clone(),
+                kind,
+                count,
+            }),
+        }
+    }
+
+    /// Renders the graph as a Graphviz digraph, with an edge per transition labelled with its
+    /// kind and counted. The empty stack is the node `[*]`.
+    pub fn to_dot(&self) -> String {
+        let node = |name: &Option<String>| dot_id(name.as_deref().unwrap_or("[*]"));
+        let mut dot = String::from("digraph transitions {\n");
+        for edge in self.lock().iter() {
+            let _ = writeln!(
+                dot,
+                "    {} -> {} [label=\"{:?}\" count={}];",
+                node(&edge.from),
+                node(&edge.to),
+                edge.kind,
+                edge.count
+            );
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    /// Renders the graph as a Mermaid state diagram, with an edge per transition labelled with
+    /// its kind and count. The empty stack is the start and end state of the diagram.
+    pub fn to_mermaid(&self) -> String {
+        let edges = self.lock();
+        let mut names: Vec<&str> = Vec::new();
+        for edge in edges.iter() {
+            for name in edge.from.iter().chain(edge.to.iter()) {
+                if !names.contains(&name.as_str()) {
+                    names.push(name);
+                }
+            }
+        }
+        let node = |name: &Option<String>| match name {
+            Some(name) => format!("s{}", names.iter().position(|n| n == name).unwrap_or(0)),
+            None => "[*]".to_string(),
+        };
+
+        let mut mermaid = String::from("stateDiagram-v2\n");
+        for (index, name) in names.iter().enumerate() {
+            let name = name.replace('"', "#quot;");
+            let _ = writeln!(mermaid, "    state \"{}\" as s{}", name, index);
+        }
+        for edge in edges.iter() {
+            let _ = writeln!(
+                mermaid,
+                "    {} --> {}: {:?} x{}",
+                node(&edge.from),
+                node(&edge.to),
+                edge.kind,
+                edge.count
+            );
+        }
+        mermaid
+    }
+}
+
+impl<S: ?Sized> TransitionObserver<S> for TransitionGraphRecorder {
+    fn on_transition(&mut self, event: &TransitionEvent, _state_data: &mut S) {
+        self.record(event);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    struct Screen(&'static str, Vec<TransitionDecl>);
+
+    impl State<()> for Screen {
+        fn name(&self) -> &str {
+            self.0
+        }
+
+        fn declared_transitions(&self) -> Vec<TransitionDecl> {
+            self.1.clone()
+        }
+    }
+
+    #[test]
+    fn export_dot() {
+        let menu = Screen(
+            "menu",
+            vec![
+                TransitionDecl::switch("game"),
+                TransitionDecl::push("options"),
+            ],
+        );
+        let game = Screen(
+            "game",
+            vec![
+                TransitionDecl::push("pause \"esc\""),
+                TransitionDecl::new(TransitionKind::PopWith, "menu"),
+            ],
+        );
+        let pause = Screen("pause \"esc\"", Vec::new());
+        let options = Screen("options\\1", Vec::new());
+        let dot = StateMachine::export_dot(&[&menu, &game, &pause, &options]);
+        assert_eq!(
+            dot,
+            r#"digraph states {
+    "menu";
+    "game";
+    "pause \"esc\"";
+    "options\\1";
+    "menu" -> "game" [label="Switch"];
+    "menu" -> "options" [label="Push"];
+    "game" -> "pause \"esc\"" [label="Push"];
+    "game" -> "menu" [label="PopWith"];
+}
+"#
+        );
+    }
+
+    /// Pushes a state at most this many times, then pops.
+    struct Level(&'static str, u32, Option<StateTransition<()>>);
+
+    impl State<()> for Level {
+        fn name(&self) -> &str {
+            self.0
+        }
+
+        fn update(&mut self, _: &mut ()) -> StateTransition<()> {
+            match self.2.take() {
+                Some(trans) => trans,
+                None if self.1 > 0 => {
+                    self.1 -= 1;
+                    StateTransition::Push(Box::new(Level("pause", 0, None)))
+                }
+                None => StateTransition::Pop,
+            }
+        }
+    }
+
+    fn session(recorder: &TransitionGraphRecorder, pauses: u32) {
+        let mut sm = StateMachine::<()>::default();
+        sm.add_observer(Box::new(recorder.clone()));
+        let game = StateTransition::Switch(Box::new(Level("game \"1\"", pauses, None)));
+        sm.push(Box::new(Level("menu", 0, Some(game))), &mut ());
+        while sm.is_running() {
+            sm.update(&mut ());
+        }
+    }
+
+    #[test]
+    fn recorder() {
+        let recorder = TransitionGraphRecorder::new();
+        session(&recorder, 2);
+        let other = TransitionGraphRecorder::new();
+        session(&other, 1);
+        recorder.merge(&other);
+        assert_eq!(
+            recorder.to_dot(),
+            r#"digraph transitions {
+    "menu" -> "game \"1\"" [label="Switch" count=2];
+    "game \"1\"" -> "pause" [label="Push" count=3];
+    "pause" -> "game \"1\"" [label="Pop" count=3];
+    "game \"1\"" -> "[*]" [label="Pop" count=2];
+}
+"#
+        );
+        assert_eq!(
+            recorder.to_mermaid(),
+            r#"stateDiagram-v2
+    state "menu" as s0
+    state "game #quot;1#quot;" as s1
+    state "pause" as s2
+    s0 --> s1: Switch x2
+    s1 --> s2: Push x3
+    s2 --> s1: Pop x3
+    s1 --> [*]: Pop x2
+"#
+        );
+        recorder.clear();
+        assert!(recorder.edges().is_empty());
+    }
+}
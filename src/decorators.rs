@@ -0,0 +1,330 @@
+//! States wrapping another state to change how it behaves on the stack.
+
+use crate::{
+    BoxError, ErrorOutcome, EventOutcome, MessageOutcome, PauseReason, Payload, ResumeReason,
+    State, StateContext, StateTransition, StopDecision, TransitionDecl,
+};
+use std::any::{Any, TypeId};
+use std::error::Error;
+#[cfg(feature = "time")]
+use std::time::Duration;
+
+/// When a `TimedState` pops itself.
+enum Budget {
+    Frames(u64),
+    #[cfg(feature = "time")]
+    Duration(Duration),
+}
+
+/// Wraps a state and pops it once it was updated for long enough, for splash screens, toasts and
+/// countdowns.
+///
+/// All the hooks are given to the wrapped state. When its update returns `StateTransition::None`
+/// after the budget is spent, the timed state returns `StateTransition::Pop` instead, so the
+/// transitions of the wrapped state are always performed first. The budget is only spent while
+/// the state is updated as an active state, so it is frozen while the state is paused.
+/// The wrapped state is not saved in snapshots, nor restarted, see `State::supports_restart`.
+pub struct TimedState<S: ?Sized, E = ()> {
+    inner: Box<dyn State<S, E>>,
+    budget: Budget,
+    updates: u64,
+}
+
+impl<S: ?Sized, E> TimedState<S, E> {
+    /// Wraps a state which pops on its update number `frames`.
+    pub fn frames(inner: Box<dyn State<S, E>>, frames: u64) -> Self {
+        Self {
+            inner,
+            budget: Budget::Frames(frames),
+            updates: 0,
+        }
+    }
+
+    /// Wraps a state which pops on the first update after it was active for this long.
+    /// The time is only counted when the machine is driven by `update_with_dt`, see
+    /// `StateMachine::active_time`.
+    #[cfg(feature = "time")]
+    pub fn duration(inner: Box<dyn State<S, E>>, duration: Duration) -> Self {
+        Self {
+            inner,
+            budget: Budget::Duration(duration),
+            updates: 0,
+        }
+    }
+
+    /// Returns the wrapped state.
+    pub fn inner(&self) -> &(dyn State<S, E> + 'static) {
+        self.inner.as_ref()
+    }
+
+    /// Returns if the budget is spent, counting the update of this context.
+    fn expired(&self, _context: &StateContext) -> bool {
+        match self.budget {
+            Budget::Frames(frames) => self.updates >= frames,
+            #[cfg(feature = "time")]
+            Budget::Duration(duration) => _context.active_time >= duration,
+        }
+    }
+}
+
+impl<S: ?Sized + 'static, E: 'static> State<S, E> for TimedState<S, E> {
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    fn is_transparent(&self) -> bool {
+        self.inner.is_transparent()
+    }
+
+    fn tags(&self) -> &[&'static str] {
+        self.inner.tags()
+    }
+
+    fn required_below(&self) -> &[TypeId] {
+        self.inner.required_below()
+    }
+
+    fn pops_group(&self) -> bool {
+        self.inner.pops_group()
+    }
+
+    fn on_start(&mut self, state_data: &mut S) -> StateTransition<S, E> {
+        self.inner.on_start(state_data)
+    }
+
+    fn try_start(&mut self, state_data: &mut S) -> Result<StateTransition<S, E>, BoxError> {
+        self.inner.try_start(state_data)
+    }
+
+    fn on_stop_requested(&mut self, state_data: &mut S) -> StopDecision<S, E> {
+        self.inner.on_stop_requested(state_data)
+    }
+
+    fn on_stop(&mut self, state_data: &mut S) -> StateTransition<S, E> {
+        self.inner.on_stop(state_data)
+    }
+
+    fn on_quit(&mut self, state_data: &mut S) {
+        self.inner.on_quit(state_data);
+    }
+
+    fn on_pause(&mut self, state_data: &mut S) {
+        self.inner.on_pause(state_data);
+    }
+
+    fn on_resume(&mut self, state_data: &mut S) -> StateTransition<S, E> {
+        self.inner.on_resume(state_data)
+    }
+
+    fn on_pause_with_reason(&mut self, reason: PauseReason, state_data: &mut S) {
+        self.inner.on_pause_with_reason(reason, state_data);
+    }
+
+    fn on_resume_with_reason(
+        &mut self,
+        reason: ResumeReason,
+        state_data: &mut S,
+    ) -> StateTransition<S, E> {
+        self.inner.on_resume_with_reason(reason, state_data)
+    }
+
+    fn on_resume_with(&mut self, payload: Payload, state_data: &mut S) -> StateTransition<S, E> {
+        self.inner.on_resume_with(payload, state_data)
+    }
+
+    fn pre_update(&mut self, state_data: &mut S) {
+        self.inner.pre_update(state_data)
+    }
+
+    fn post_update(&mut self, state_data: &mut S, result: &StateTransition<S, E>) {
+        self.inner.post_update(state_data, result)
+    }
+
+    fn try_update_with_context(
+        &mut self,
+        context: &StateContext,
+        state_data: &mut S,
+    ) -> Result<StateTransition<S, E>, BoxError> {
+        self.updates += 1;
+        Ok(
+            match self.inner.try_update_with_context(context, state_data)? {
+                StateTransition::None if self.expired(context) => StateTransition::Pop,
+                trans => trans,
+            },
+        )
+    }
+
+    fn try_update_with_extra(
+        &mut self,
+        extra: &dyn Any,
+        context: &StateContext,
+        state_data: &mut S,
+    ) -> Result<StateTransition<S, E>, BoxError> {
+        self.updates += 1;
+        Ok(
+            match self
+                .inner
+                .try_update_with_extra(extra, context, state_data)?
+            {
+                StateTransition::None if self.expired(context) => StateTransition::Pop,
+                trans => trans,
+            },
+        )
+    }
+
+    fn on_error(&mut self, error: &dyn Error, state_data: &mut S) -> ErrorOutcome<S, E> {
+        self.inner.on_error(error, state_data)
+    }
+
+    #[cfg(feature = "time")]
+    fn fixed_update(&mut self, state_data: &mut S) -> StateTransition<S, E> {
+        self.inner.fixed_update(state_data)
+    }
+
+    #[cfg(feature = "time")]
+    fn fixed_update_with_extra(
+        &mut self,
+        extra: &dyn Any,
+        state_data: &mut S,
+    ) -> StateTransition<S, E> {
+        self.inner.fixed_update_with_extra(extra, state_data)
+    }
+
+    #[cfg(feature = "time")]
+    fn update_interval(&self) -> Option<Duration> {
+        self.inner.update_interval()
+    }
+
+    fn shadow_update_with_context(&mut self, context: &StateContext, state_data: &mut S) {
+        self.inner.shadow_update_with_context(context, state_data);
+    }
+
+    fn shadow_update_with_transition(
+        &mut self,
+        context: &StateContext,
+        state_data: &mut S,
+    ) -> StateTransition<S, E> {
+        self.inner
+            .shadow_update_with_transition(context, state_data)
+    }
+
+    #[cfg(feature = "parallel")]
+    fn par_shadow_update(&mut self, state_data: &S) {
+        self.inner.par_shadow_update(state_data);
+    }
+
+    fn render_with_context(&mut self, context: &StateContext, state_data: &mut S) {
+        self.inner.render_with_context(context, state_data);
+    }
+
+    fn blocks_rendering(&self) -> bool {
+        self.inner.blocks_rendering()
+    }
+
+    fn on_save(&mut self, state_data: &mut S) {
+        self.inner.on_save(state_data);
+    }
+
+    fn on_load(&mut self, state_data: &mut S) {
+        self.inner.on_load(state_data);
+    }
+
+    fn handle_event(&mut self, event: &E, state_data: &mut S) -> EventOutcome<S, E> {
+        self.inner.handle_event(event, state_data)
+    }
+
+    fn handle_event_with_extra(
+        &mut self,
+        event: &E,
+        extra: &dyn Any,
+        state_data: &mut S,
+    ) -> EventOutcome<S, E> {
+        self.inner.handle_event_with_extra(event, extra, state_data)
+    }
+
+    fn on_message(&mut self, message: &dyn Any, state_data: &mut S) -> MessageOutcome {
+        self.inner.on_message(message, state_data)
+    }
+
+    fn declared_transitions(&self) -> Vec<TransitionDecl> {
+        self.inner.declared_transitions()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    type Log = Vec<String>;
+
+    /// Logs its updates, and returns its transition on the given update.
+    struct Splash(u64, Option<StateTransition<Log>>);
+
+    impl State<Log> for Splash {
+        fn update(&mut self, log: &mut Log) -> StateTransition<Log> {
+            log.push("splash".to_string());
+            self.0 = self.0.saturating_sub(1);
+            match self.0 {
+                0 => self.1.take().unwrap_or(StateTransition::None),
+                _ => StateTransition::None,
+            }
+        }
+    }
+
+    fn timed(frames: u64, trans: Option<StateTransition<Log>>) -> Box<TimedState<Log>> {
+        Box::new(TimedState::frames(Box::new(Splash(2, trans)), frames))
+    }
+
+    #[test]
+    fn frames() {
+        let mut log = Log::new();
+        let mut sm = StateMachine::<Log>::default();
+        sm.push(timed(3, None), &mut log);
+        assert_eq!(sm.update_n(10, &mut log), 3);
+        assert_eq!(log.len(), 3);
+    }
+
+    #[test]
+    fn inner_transition_wins() {
+        let mut log = Log::new();
+        let mut sm = StateMachine::<Log>::default();
+        sm.push(Box::new(Splash(0, None)), &mut log);
+        let menu = StateTransition::Switch(Box::new(Splash(0, None)));
+        sm.push(timed(2, Some(menu)), &mut log);
+        sm.update(&mut log);
+        sm.update(&mut log);
+        assert_eq!(sm.len(), 1);
+        assert!(!sm.contains_state::<TimedState<Log>>());
+    }
+
+    #[test]
+    fn frozen_while_paused() {
+        let mut log = Log::new();
+        let mut sm = StateMachine::<Log>::default();
+        sm.push(timed(2, None), &mut log);
+        sm.update(&mut log);
+        sm.push(Box::new(Splash(10, None)), &mut log);
+        sm.update_n(3, &mut log);
+        sm.pop(&mut log);
+        assert!(sm.contains_state::<TimedState<Log>>());
+        sm.update(&mut log);
+        assert!(!sm.is_running());
+    }
+
+    #[cfg(feature = "time")]
+    #[test]
+    fn duration() {
+        let mut log = Log::new();
+        let mut sm = StateMachine::<Log>::default();
+        let splash = Box::new(Splash(10, None));
+        sm.push(
+            Box::new(TimedState::duration(splash, Duration::from_secs(3))),
+            &mut log,
+        );
+        sm.update(&mut log);
+        sm.update_with_dt(Duration::from_secs(2), &mut log);
+        assert!(sm.is_running());
+        sm.update_with_dt(Duration::from_secs(1), &mut log);
+        assert!(!sm.is_running());
+    }
+}
@@ -0,0 +1,180 @@
+//! Keeping a single instance of each type of state on the stack, so mashing the inventory key
+//! does not push three inventories.
+
+use crate::{any_of, Allocator, AsAny, ScopedStateMachine, State};
+
+/// What a state machine does when a state is pushed while a state of the same type is on the
+/// stack, see `StateMachine::set_duplicate_policy`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DuplicatePolicy {
+    /// The state is pushed anyway.
+    Allow,
+    /// The state is dropped without being started, and the stack is left untouched.
+    Ignore,
+    /// The state is dropped without being started, and the state of the same type closest to
+    /// the top is moved to the top instead. The states it then covers are paused, and it is
+    /// resumed.
+    RaiseExisting,
+}
+
+impl<'a, S: ?Sized, E, A: Allocator> ScopedStateMachine<'a, S, E, A> {
+    /// Sets what is done with the states pushed while a state of the same type is on the stack.
+    /// The types are compared with their `TypeId`, so the decorators boxing a state, like
+    /// `TimedState`, are duplicates of each other whatever state they wrap.
+    /// Defaults to `DuplicatePolicy::Allow`.
+    ///
+    /// The policy applies to `push` and to the `Push` and `PushNamed` transitions. An ignored
+    /// push is not reported as performed, and is logged with the `log` feature. A raise is
+    /// reported as a `Push` to the observers and in the status of the update. Both are counted
+    /// by `StateMachineMetrics::duplicates`.
+    pub fn set_duplicate_policy(&mut self, policy: DuplicatePolicy) {
+        self.duplicate_policy = policy;
+    }
+
+    /// Returns what is done with duplicate states, see `set_duplicate_policy`.
+    pub fn duplicate_policy(&self) -> DuplicatePolicy {
+        self.duplicate_policy
+    }
+
+    /// Returns the index of the state closest to the top with the same type as this one, if the
+    /// policy does not allow duplicates. The states borrowing data have no duplicates.
+    pub(crate) fn duplicate_of(&self, state: &(dyn State<S, E> + 'static)) -> Option<usize> {
+        if self.duplicate_policy == DuplicatePolicy::Allow {
+            return None;
+        }
+        let id = <dyn AsAny>::as_any(state).type_id();
+        self.state_stack
+            .iter()
+            .rposition(|entry| any_of(&entry.state).map(|state| state.type_id()) == Some(id))
+    }
+
+    /// Applies the policy to the push of a duplicate of the state at this index.
+    /// Returns if the stack changed.
+    pub(crate) fn push_duplicate(&mut self, index: usize, state_data: &mut S) -> bool {
+        self.metrics.record_duplicate();
+        log_debug!(
+            "duplicate push of {}: {:?}",
+            self.state_stack[index].state.as_ref().name(),
+            self.duplicate_policy
+        );
+        match self.duplicate_policy {
+            DuplicatePolicy::RaiseExisting if index + 1 < self.state_stack.len() => {
+                self.pause_visible(state_data);
+                let entry = self.state_stack.remove(index);
+                self.state_stack.push(entry);
+                self.resume_visible(state_data);
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    struct Inventory(&'static str);
+
+    impl State<Vec<String>> for Inventory {
+        fn name(&self) -> &str {
+            self.0
+        }
+
+        fn on_start(&mut self, log: &mut Vec<String>) -> StateTransition<Vec<String>> {
+            log.push(format!("{}:start", self.0));
+            StateTransition::None
+        }
+
+        fn on_pause(&mut self, log: &mut Vec<String>) {
+            log.push(format!("{}:pause", self.0));
+        }
+
+        fn on_resume(&mut self, log: &mut Vec<String>) -> StateTransition<Vec<String>> {
+            log.push(format!("{}:resume", self.0));
+            StateTransition::None
+        }
+    }
+
+    struct Game;
+
+    impl State<Vec<String>> for Game {
+        fn name(&self) -> &str {
+            "game"
+        }
+
+        fn on_pause(&mut self, log: &mut Vec<String>) {
+            log.push("game:pause".to_string());
+        }
+
+        fn update(&mut self, _: &mut Vec<String>) -> StateTransition<Vec<String>> {
+            StateTransition::Push(Box::new(Inventory("second")))
+        }
+    }
+
+    fn machine(policy: DuplicatePolicy, log: &mut Vec<String>) -> StateMachine<Vec<String>> {
+        let mut sm = StateMachine::default();
+        sm.enable_metrics();
+        sm.set_duplicate_policy(policy);
+        sm.push(Box::new(Inventory("first")), log);
+        sm.push(Box::new(Game), log);
+        log.clear();
+        sm
+    }
+
+    fn names(sm: &StateMachine<Vec<String>>) -> Vec<&str> {
+        sm.iter().map(|state| state.name()).collect()
+    }
+
+    #[test]
+    fn allow() {
+        let mut log = Vec::new();
+        let mut sm = machine(DuplicatePolicy::Allow, &mut log);
+        sm.update(&mut log);
+        assert_eq!(names(&sm), ["first", "game", "second"]);
+        assert_eq!(sm.metrics().duplicates(), 0);
+    }
+
+    #[test]
+    fn ignore() {
+        let mut log = Vec::new();
+        let mut sm = machine(DuplicatePolicy::Ignore, &mut log);
+        assert_eq!(sm.update(&mut log), MachineStatus::Running);
+        let handle = sm.push(Box::new(Inventory("third")), &mut log);
+        assert_eq!(names(&sm), ["first", "game"]);
+        assert_eq!(sm.state(handle).map(|state| state.name()), Some("first"));
+        assert!(log.is_empty());
+        assert_eq!(sm.metrics().duplicates(), 2);
+    }
+
+    #[test]
+    fn raise_existing() {
+        let mut log = Vec::new();
+        let mut sm = machine(DuplicatePolicy::RaiseExisting, &mut log);
+        let events = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let observed = events.clone();
+        sm.add_observer(Box::new(
+            move |event: &TransitionEvent, _: &mut Vec<String>| {
+                observed
+                    .lock()
+                    .unwrap()
+                    .push((event.kind, event.to.clone()));
+            },
+        ));
+        assert_eq!(
+            sm.update(&mut log),
+            MachineStatus::Transitioned(TransitionKind::Push)
+        );
+        assert_eq!(names(&sm), ["game", "first"]);
+        assert_eq!(log, ["game:pause", "first:resume"]);
+        assert_eq!(
+            *events.lock().unwrap(),
+            [(TransitionKind::Push, Some("first".to_string()))]
+        );
+
+        log.clear();
+        sm.push(Box::new(Inventory("third")), &mut log);
+        assert_eq!(names(&sm), ["game", "first"]);
+        assert!(log.is_empty());
+    }
+}
@@ -0,0 +1,214 @@
+//! Playing a state like a fade before the pushes, switches and pops of a state machine, instead
+//! of pushing it at every place a state returns one of these transitions.
+
+use crate::{Allocator, ScopedStateMachine, State, StateHandle, StateTransition, TransitionKind};
+
+/// Builds the effect state played before a transition of this kind, see
+/// `StateMachine::set_transition_effect`.
+pub type EffectFactory<S, E = ()> =
+    Box<maybe_send!(dyn Fn(TransitionKind) -> Box<dyn State<S, E>>)>;
+
+/// The effect factory of a state machine and the transition held by the playing effect.
+pub(crate) struct Effects<S: ?Sized, E> {
+    factory: Option<EffectFactory<S, E>>,
+    held: Option<(StateHandle, StateTransition<S, E>)>,
+}
+
+impl<S: ?Sized, E> Default for Effects<S, E> {
+    fn default() -> Self {
+        Self {
+            factory: None,
+            held: None,
+        }
+    }
+}
+
+impl<S: ?Sized, E> Effects<S, E> {
+    /// Drops the held transition.
+    pub(crate) fn clear(&mut self) {
+        self.held = None;
+    }
+}
+
+impl<'a, S: ?Sized, E, A: Allocator> ScopedStateMachine<'a, S, E, A> {
+    /// Sets the factory of the effect state pushed before the `Push`, `Switch` and `Pop`
+    /// transitions returned by the states, along with their variants like `PushWith`.
+    ///
+    /// The transition is held until the effect state leaves the stack, usually by popping
+    /// itself once its fade is done, then performed. It is performed even if the effect state
+    /// is removed by something else, like a switch. Once it is performed, the next transition
+    /// plays an effect again.
+    ///
+    /// While an effect plays, the transitions are performed without effects, like the pop of the
+    /// effect state itself. `Quit` never plays an effect, and drops the held transition. The
+    /// operations called on the machine, such as `push`, do not play effects either.
+    pub fn set_transition_effect(&mut self, factory: EffectFactory<S, E>) {
+        self.effects.factory = Some(factory);
+    }
+
+    /// Removes the effect factory. A playing effect still performs its transition.
+    pub fn clear_transition_effect(&mut self) {
+        self.effects.factory = None;
+    }
+
+    /// Returns if an effect state is holding a transition.
+    pub fn is_playing_effect(&self) -> bool {
+        self.effects.held.is_some()
+    }
+
+    /// Pushes the effect state and holds the transition, if an effect plays before it.
+    /// Gives the transition back otherwise.
+    pub(crate) fn play_effect(
+        &mut self,
+        request: StateTransition<S, E>,
+        state_data: &mut S,
+    ) -> Result<Option<TransitionKind>, StateTransition<S, E>> {
+        let kind =
+            match request.kind() {
+                Some(
+                    kind @ (TransitionKind::Push | TransitionKind::Switch | TransitionKind::Pop),
+                ) if self.is_running() && self.effects.held.is_none() => kind,
+                _ => return Err(request),
+            };
+        let effect = match &self.effects.factory {
+            Some(factory) => factory(kind),
+            None => return Err(request),
+        };
+        let handle = self.next_handle();
+        self.recorded(TransitionKind::Push, |sm| sm.push_boxed(effect, state_data));
+        self.effects.held = Some((handle, request));
+        Ok(Some(TransitionKind::Push))
+    }
+
+    /// Performs the held transition once its effect state left the stack.
+    pub(crate) fn release_effect(&mut self, state_data: &mut S) -> Option<TransitionKind> {
+        match &self.effects.held {
+            Some((handle, _)) if !self.is_alive(*handle) => {
+                let (_, held) = self.effects.held.take()?;
+                self.transition(held, state_data)
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    type Log = Vec<String>;
+
+    /// Fades for two updates then pops itself.
+    struct Fade(u32);
+
+    impl State<Log> for Fade {
+        fn name(&self) -> &str {
+            "fade"
+        }
+
+        fn is_transparent(&self) -> bool {
+            true
+        }
+
+        fn on_start(&mut self, log: &mut Log) -> StateTransition<Log> {
+            log.push("fade:start".to_string());
+            StateTransition::None
+        }
+
+        fn on_stop(&mut self, log: &mut Log) -> StateTransition<Log> {
+            log.push("fade:stop".to_string());
+            StateTransition::None
+        }
+
+        fn update(&mut self, _: &mut Log) -> StateTransition<Log> {
+            self.0 += 1;
+            if self.0 == 2 {
+                StateTransition::Pop
+            } else {
+                StateTransition::None
+            }
+        }
+    }
+
+    /// A scene which returns the transition it is given once.
+    struct Scene(&'static str, Option<StateTransition<Log>>);
+
+    impl State<Log> for Scene {
+        fn name(&self) -> &str {
+            self.0
+        }
+
+        fn on_start(&mut self, log: &mut Log) -> StateTransition<Log> {
+            log.push(format!("{}:start", self.0));
+            StateTransition::None
+        }
+
+        fn on_stop(&mut self, log: &mut Log) -> StateTransition<Log> {
+            log.push(format!("{}:stop", self.0));
+            StateTransition::None
+        }
+
+        fn update(&mut self, _: &mut Log) -> StateTransition<Log> {
+            self.1.take().unwrap_or(StateTransition::None)
+        }
+    }
+
+    fn machine(first: StateTransition<Log>, log: &mut Log) -> StateMachine<Log> {
+        let mut sm = StateMachine::default();
+        sm.set_transition_effect(Box::new(|_| Box::new(Fade(0))));
+        sm.push(Box::new(Scene("menu", Some(first))), log);
+        log.clear();
+        sm
+    }
+
+    fn names(sm: &StateMachine<Log>) -> Vec<&str> {
+        sm.iter().map(|state| state.name()).collect()
+    }
+
+    #[test]
+    fn held_until_the_effect_pops() {
+        let mut log = Log::new();
+        let game = Box::new(Scene("game", None));
+        let mut sm = machine(StateTransition::Switch(game), &mut log);
+        assert_eq!(
+            sm.update(&mut log),
+            MachineStatus::Transitioned(TransitionKind::Push)
+        );
+        assert!(sm.is_playing_effect());
+        assert_eq!(names(&sm), ["menu", "fade"]);
+        sm.update(&mut log);
+        assert_eq!(names(&sm), ["menu", "fade"]);
+
+        assert_eq!(
+            sm.update(&mut log),
+            MachineStatus::Transitioned(TransitionKind::Switch)
+        );
+        assert!(!sm.is_playing_effect());
+        assert_eq!(names(&sm), ["game"]);
+        assert_eq!(log, ["fade:start", "fade:stop", "menu:stop", "game:start"]);
+    }
+
+    #[test]
+    fn held_when_the_effect_is_removed() {
+        let mut log = Log::new();
+        let mut sm = machine(
+            StateTransition::Push(Box::new(Scene("pause", None))),
+            &mut log,
+        );
+        sm.update(&mut log);
+        sm.switch(Box::new(Scene("title", None)), &mut log);
+        assert!(!sm.is_playing_effect());
+        assert_eq!(names(&sm), ["title", "pause"]);
+    }
+
+    #[test]
+    fn quit_bypasses_effects() {
+        let mut log = Log::new();
+        let mut sm = machine(StateTransition::Quit, &mut log);
+        assert_eq!(
+            sm.update(&mut log),
+            MachineStatus::Stopped(StopReason::Quit)
+        );
+        assert_eq!(log, ["menu:stop"]);
+    }
+}
@@ -0,0 +1,269 @@
+//! A state machine over an enum of states, stored by value without boxing them, for tight
+//! loops where the allocation and dynamic call of each `Box<dyn State>` matter.
+
+use std::marker::PhantomData;
+
+/// A transition of an `EnumStateMachine`, carrying the states by value.
+pub enum EnumTransition<T> {
+    /// Stay in the current state.
+    None,
+    /// End the current state and resume the one below it, if any.
+    Pop,
+    /// Pause the current state and start this one on top of it.
+    Push(T),
+    /// End all the states, top-down, then start this one.
+    Switch(T),
+    /// End the current state and start this one in its place, without pausing or resuming the
+    /// state below it.
+    Replace(T),
+    /// End all the states, top-down.
+    Quit,
+}
+
+/// A state of an `EnumStateMachine`, usually an enum with a variant per state.
+/// The hooks are called in the same order as the ones of `State` by a `StateMachine`.
+pub trait EnumState<S: ?Sized>: Sized {
+    /// Called when the state is pushed on the stack.
+    fn on_start(&mut self, _state_data: &mut S) {}
+    /// Called when the state is removed from the stack.
+    fn on_stop(&mut self, _state_data: &mut S) {}
+    /// Called when a state is pushed over this one.
+    fn on_pause(&mut self, _state_data: &mut S) {}
+    /// Called when the state above this one is popped.
+    fn on_resume(&mut self, _state_data: &mut S) {}
+    /// Called on every frame while this state is on top of the stack.
+    fn update(&mut self, _state_data: &mut S) -> EnumTransition<Self> {
+        EnumTransition::None
+    }
+    /// Called on every frame while another state is on top of this one.
+    fn shadow_update(&mut self, _state_data: &mut S) {}
+}
+
+/// A stack-based state machine storing its states by value, in a vector that only allocates
+/// when it grows.
+///
+/// It behaves like a `StateMachine` for the transitions it supports, see `EnumTransition`.
+pub struct EnumStateMachine<S: ?Sized, T> {
+    state_stack: Vec<T>,
+    state_data: PhantomData<fn(&mut S)>,
+}
+
+impl<S: ?Sized, T> Default for EnumStateMachine<S, T> {
+    fn default() -> Self {
+        Self {
+            state_stack: Vec::new(),
+            state_data: PhantomData,
+        }
+    }
+}
+
+impl<S: ?Sized, T: EnumState<S>> EnumStateMachine<S, T> {
+    /// Creates a state machine with room for this many states on the stack before it needs to
+    /// allocate.
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            state_stack: Vec::with_capacity(capacity),
+            state_data: PhantomData,
+        }
+    }
+
+    /// Returns if the state machine still has states in its stack.
+    pub fn is_running(&self) -> bool {
+        !self.state_stack.is_empty()
+    }
+
+    /// Returns the number of states on the stack.
+    pub fn len(&self) -> usize {
+        self.state_stack.len()
+    }
+
+    /// Returns if there are no states on the stack.
+    pub fn is_empty(&self) -> bool {
+        self.state_stack.is_empty()
+    }
+
+    /// Returns the active state, the one on top of the stack, if any.
+    pub fn active(&self) -> Option<&T> {
+        self.state_stack.last()
+    }
+
+    /// Returns the active state, to change it.
+    pub fn active_mut(&mut self) -> Option<&mut T> {
+        self.state_stack.last_mut()
+    }
+
+    /// Iterates over the states of the stack, from the bottom to the top.
+    pub fn iter(&self) -> impl Iterator<Item = &T> + '_ {
+        self.state_stack.iter()
+    }
+
+    /// Shadow updates the states covered by the top of the stack, from the bottom up, then
+    /// updates the top state and performs the transition it returns.
+    pub fn update(&mut self, state_data: &mut S) {
+        let len = self.state_stack.len();
+        if len == 0 {
+            return;
+        }
+        for state in &mut self.state_stack[..len - 1] {
+            state.shadow_update(state_data);
+        }
+        let trans = self.state_stack[len - 1].update(state_data);
+        self.transition(trans, state_data);
+    }
+
+    /// Performs a transition on the stack.
+    pub fn transition(&mut self, request: EnumTransition<T>, state_data: &mut S) {
+        match request {
+            EnumTransition::None => (),
+            EnumTransition::Pop => self.pop(state_data),
+            EnumTransition::Push(state) => self.push(state, state_data),
+            EnumTransition::Switch(state) => self.switch(state, state_data),
+            EnumTransition::Replace(state) => {
+                if let Some(mut top) = self.state_stack.pop() {
+                    top.on_stop(state_data);
+                }
+                self.start(state, state_data);
+            }
+            EnumTransition::Quit => self.stop(state_data),
+        }
+    }
+
+    /// Pauses the current state, if any, then pushes this state and starts it.
+    pub fn push(&mut self, state: T, state_data: &mut S) {
+        if let Some(top) = self.state_stack.last_mut() {
+            top.on_pause(state_data);
+        }
+        self.start(state, state_data);
+    }
+
+    /// Stops the current state and resumes the one below it, if any.
+    pub fn pop(&mut self, state_data: &mut S) {
+        if let Some(mut top) = self.state_stack.pop() {
+            top.on_stop(state_data);
+            if let Some(next) = self.state_stack.last_mut() {
+                next.on_resume(state_data);
+            }
+        }
+    }
+
+    /// Stops all the states, top-down, then pushes this state and starts it.
+    pub fn switch(&mut self, state: T, state_data: &mut S) {
+        self.stop(state_data);
+        self.start(state, state_data);
+    }
+
+    /// Stops all the states, top-down.
+    pub fn stop(&mut self, state_data: &mut S) {
+        while let Some(mut top) = self.state_stack.pop() {
+            top.on_stop(state_data);
+        }
+    }
+
+    fn start(&mut self, mut state: T, state_data: &mut S) {
+        state.on_start(state_data);
+        self.state_stack.push(state);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    /// The calls made on the states, recorded in the state data.
+    type Calls = Vec<(String, LifecycleCall)>;
+
+    enum Screen {
+        Menu,
+        Game(u32),
+        Pause,
+    }
+
+    impl Screen {
+        fn name(&self) -> String {
+            match self {
+                Screen::Menu => "menu".to_string(),
+                Screen::Game(level) => format!("game{}", level),
+                Screen::Pause => "pause".to_string(),
+            }
+        }
+
+        fn record(&self, calls: &mut Calls, call: LifecycleCall) {
+            calls.push((self.name(), call));
+        }
+    }
+
+    impl EnumState<Calls> for Screen {
+        fn on_start(&mut self, calls: &mut Calls) {
+            self.record(calls, LifecycleCall::Start);
+        }
+
+        fn on_stop(&mut self, calls: &mut Calls) {
+            self.record(calls, LifecycleCall::Stop);
+        }
+
+        fn on_pause(&mut self, calls: &mut Calls) {
+            self.record(calls, LifecycleCall::Pause);
+        }
+
+        fn on_resume(&mut self, calls: &mut Calls) {
+            self.record(calls, LifecycleCall::Resume);
+        }
+
+        fn update(&mut self, calls: &mut Calls) -> EnumTransition<Self> {
+            self.record(calls, LifecycleCall::Update);
+            match self {
+                Screen::Game(1) => EnumTransition::Replace(Screen::Game(2)),
+                Screen::Pause => EnumTransition::Pop,
+                _ => EnumTransition::None,
+            }
+        }
+
+        fn shadow_update(&mut self, calls: &mut Calls) {
+            self.record(calls, LifecycleCall::ShadowUpdate);
+        }
+    }
+
+    /// The same scenario on a `StateMachine`, with the transitions the screens return.
+    fn recording(name: &str, calls: &CallLog) -> Box<dyn State<()>> {
+        let state = RecordingState::new(name, calls);
+        Box::new(match name {
+            "game1" => state.returning(StateTransition::Replace(recording("game2", calls))),
+            "pause" => state.returning(StateTransition::Pop),
+            _ => state,
+        })
+    }
+
+    #[test]
+    fn same_order_as_state_machine() {
+        let mut calls = Calls::new();
+        let mut sm = EnumStateMachine::with_capacity(4);
+        sm.push(Screen::Menu, &mut calls);
+        sm.push(Screen::Game(1), &mut calls);
+        sm.update(&mut calls);
+        sm.push(Screen::Pause, &mut calls);
+        sm.update(&mut calls);
+        sm.switch(Screen::Menu, &mut calls);
+        sm.stop(&mut calls);
+        assert!(!sm.is_running());
+
+        let log = CallLog::default();
+        let mut dyn_sm = StateMachine::<()>::default();
+        dyn_sm.push(recording("menu", &log), &mut ());
+        dyn_sm.push(recording("game1", &log), &mut ());
+        dyn_sm.update(&mut ());
+        dyn_sm.push(recording("pause", &log), &mut ());
+        dyn_sm.update(&mut ());
+        dyn_sm.switch(recording("menu", &log), &mut ());
+        dyn_sm.stop(&mut ());
+        // The enum states are not told that the machine quits before being stopped.
+        log.lock()
+            .unwrap()
+            .retain(|(_, call)| *call != LifecycleCall::Quit);
+
+        let expected = calls
+            .iter()
+            .map(|(name, call)| (name.as_str(), *call))
+            .collect::<Vec<_>>();
+        assert_calls(&log, &expected);
+    }
+}
@@ -0,0 +1,158 @@
+//! Landing on a safe state when the stack is left empty by accident, like a state popping once
+//! too many, instead of stopping the machine.
+
+use crate::{
+    from_global, Allocator, ExitReason, MaybeSend, ScopedStateMachine, State, TransitionKind,
+};
+
+/// Creates the fallback state of a state machine, see `StateMachine::set_fallback`.
+pub type FallbackFactory<S, E = ()> = Box<maybe_send!(dyn Fn() -> Box<dyn State<S, E>>)>;
+
+impl<'a, S: ?Sized, E, A: Allocator> ScopedStateMachine<'a, S, E, A> {
+    /// Sets the factory of the state started when the last state is popped or removed, like a
+    /// main menu, so the machine keeps running. The observers are told with
+    /// `TransitionKind::Fallback`.
+    ///
+    /// The machine still stops when asked to, by `Quit`, `stop` or `force_quit`, and when it
+    /// stops because of an error or a panic. If the fallback state empties the stack again
+    /// before the operation that started it is done, like by popping itself in `on_start`, the
+    /// machine stops with `ExitReason::LastStatePopped` instead of starting it again, until a
+    /// state is pushed.
+    pub fn set_fallback(
+        &mut self,
+        factory: impl Fn() -> Box<dyn State<S, E>> + MaybeSend + 'static,
+    ) {
+        self.fallback = Some(Box::new(factory));
+    }
+
+    /// Removes the fallback, so the machine stops when the last state is popped.
+    pub fn clear_fallback(&mut self) {
+        self.fallback = None;
+    }
+
+    /// Starts the fallback state if the operation that is not done yet popped the last state.
+    /// Returns `false` if it did not.
+    pub(crate) fn start_fallback(&mut self, state_data: &mut S) -> bool {
+        if !self.state_stack.is_empty() || self.exit_reason != Some(ExitReason::LastStatePopped) {
+            return false;
+        }
+        let factory = match &self.fallback {
+            Some(factory) => factory,
+            None => return false,
+        };
+        if self.fallback_started {
+            log_warn!("the fallback state emptied the stack, stopping the machine");
+            return false;
+        }
+        let state = factory();
+        log_warn!(
+            "the stack was left empty, starting {}",
+            state.as_ref().name()
+        );
+        self.fallback_started = true;
+        self.start_state(from_global(state), state_data);
+        self.metrics.record_transition(TransitionKind::Fallback);
+        self.notify(TransitionKind::Fallback, None, state_data);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use std::sync::{Arc, Mutex};
+
+    type Log = Vec<String>;
+
+    struct MainMenu;
+
+    impl State<Log> for MainMenu {
+        fn name(&self) -> &str {
+            "main menu"
+        }
+
+        fn on_start(&mut self, log: &mut Log) -> StateTransition<Log> {
+            log.push("menu:start".to_string());
+            StateTransition::None
+        }
+    }
+
+    /// Pops itself on its first update.
+    struct Popper;
+
+    impl State<Log> for Popper {
+        fn update(&mut self, _: &mut Log) -> StateTransition<Log> {
+            StateTransition::Pop
+        }
+    }
+
+    fn fallback_machine(log: &mut Log) -> (StateMachine<Log>, Arc<Mutex<Vec<TransitionKind>>>) {
+        let mut sm = StateMachine::<Log>::default();
+        sm.set_fallback(|| Box::new(MainMenu));
+        let kinds = Arc::new(Mutex::new(Vec::new()));
+        let observed = kinds.clone();
+        sm.add_observer(Box::new(move |event: &TransitionEvent, _: &mut Log| {
+            observed.lock().unwrap().push(event.kind);
+        }));
+        sm.push(Box::new(Popper), log);
+        (sm, kinds)
+    }
+
+    #[test]
+    fn popped_once_too_many() {
+        let mut log = Log::new();
+        let (mut sm, kinds) = fallback_machine(&mut log);
+        assert_eq!(
+            sm.update(&mut log),
+            MachineStatus::Transitioned(TransitionKind::Fallback)
+        );
+        assert!(sm.is_running());
+        assert_eq!(sm.active_name(), Some("main menu"));
+        assert_eq!(log, ["menu:start"]);
+        assert_eq!(
+            *kinds.lock().unwrap(),
+            [TransitionKind::Pop, TransitionKind::Fallback]
+        );
+
+        sm.remove_where(|_| true, &mut log);
+        assert_eq!(sm.active_name(), Some("main menu"));
+        assert_eq!(log, ["menu:start", "menu:start"]);
+    }
+
+    #[test]
+    fn quit_bypasses_fallback() {
+        let mut log = Log::new();
+        let (mut sm, _) = fallback_machine(&mut log);
+        sm.queue_transition(StateTransition::Quit);
+        sm.update(&mut log);
+        assert!(!sm.is_running());
+        assert_eq!(sm.exit_reason(), Some(ExitReason::Quit));
+
+        let (mut sm, _) = fallback_machine(&mut log);
+        sm.stop(&mut log);
+        assert!(!sm.is_running());
+        assert!(log.is_empty());
+    }
+
+    /// A fallback popping itself as soon as it starts.
+    struct Broken;
+
+    impl State<Log> for Broken {
+        fn on_start(&mut self, log: &mut Log) -> StateTransition<Log> {
+            log.push("broken:start".to_string());
+            StateTransition::Pop
+        }
+    }
+
+    #[test]
+    fn fallback_emptying_the_stack() {
+        let mut log = Log::new();
+        let mut sm = StateMachine::<Log>::default();
+        sm.set_fallback(|| Box::new(Broken));
+        sm.push(Box::new(Popper), &mut log);
+        sm.update(&mut log);
+        assert!(!sm.is_running());
+        assert_eq!(sm.exit_reason(), Some(ExitReason::LastStatePopped));
+        assert_eq!(log, ["broken:start"]);
+    }
+}
@@ -0,0 +1,332 @@
+//! Driving a state machine from C, or any language calling C functions, with states made of
+//! function pointers. The state data is a pointer given by the host, passed as it is to the
+//! states. The header is `include/game_state_machine.h`, generated with `cbindgen`.
+//!
+//! Every function catches the panics of the machine, so they do not unwind into the host.
+//! The callbacks must not unwind into the machine either, like a C++ exception would.
+
+#![allow(non_camel_case_types)]
+
+use crate::{State, StateMachine, StateTransition};
+use std::ffi::c_void;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::ptr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// A callback of a state returning a transition, given the user data of the state and the
+/// state data.
+pub type GSM_TransitionFn =
+    Option<unsafe extern "C" fn(user: *mut c_void, data: *mut c_void) -> GSM_Transition>;
+
+/// A callback of a state, given the user data of the state and the state data.
+pub type GSM_HookFn = Option<unsafe extern "C" fn(user: *mut c_void, data: *mut c_void)>;
+
+/// The callbacks of a state, each of which can be null to do nothing.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct GSM_StateVTable {
+    /// Called when the state is pushed on the stack, see `State::on_start`.
+    pub on_start: GSM_TransitionFn,
+    /// Called when the state is removed from the stack, see `State::on_stop`.
+    pub on_stop: GSM_TransitionFn,
+    /// Called when a state is pushed over this one, see `State::on_pause`.
+    pub on_pause: GSM_HookFn,
+    /// Called when the state becomes the top state again, see `State::on_resume`.
+    pub on_resume: GSM_TransitionFn,
+    /// Called on the top state each update, see `State::update`.
+    pub update: GSM_TransitionFn,
+    /// Called on the visible states each render, see `State::render`.
+    pub render: GSM_HookFn,
+    /// Called once the machine is done with the state, to free its user data.
+    pub drop: Option<unsafe extern "C" fn(user: *mut c_void)>,
+}
+
+/// The kinds of a `GSM_Transition`.
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum GSM_TransitionKind {
+    /// Stay in the current state.
+    None = 0,
+    /// Remove the top state.
+    Pop = 1,
+    /// Pause the top state and push the given state over it.
+    Push = 2,
+    /// Remove the top state and push the given state instead, leaving the states below it.
+    Replace = 3,
+    /// Remove all the states.
+    Quit = 4,
+    /// Remove all the states and push the given state.
+    Switch = 5,
+}
+
+impl GSM_TransitionKind {
+    /// Returns the kind of this value, if it is one.
+    fn from_raw(kind: u32) -> Option<Self> {
+        [
+            Self::None,
+            Self::Pop,
+            Self::Push,
+            Self::Replace,
+            Self::Quit,
+            Self::Switch,
+        ]
+        .iter()
+        .copied()
+        .find(|known| *known as u32 == kind)
+    }
+}
+
+/// A transition returned by the callbacks of a state.
+///
+/// The machine owns the user data of the state to push, replace or switch to from then on, and
+/// frees it with its `drop` callback. Otherwise, like for the other kinds, for a null `state`, or
+/// for an unknown kind, the transition does nothing and the user data stays owned by the host.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct GSM_Transition {
+    /// What to do, one of the `GSM_TransitionKind` values. A value which is not one makes
+    /// `gsm_machine_update` return `GSM_Status::UnknownTransition`.
+    pub kind: u32,
+    /// The callbacks of the state to push, replace or switch to, copied once the callback
+    /// returns. A push, replace or switch without callbacks does nothing.
+    pub state: *const GSM_StateVTable,
+    /// The user data of the state to push, replace or switch to.
+    pub user: *mut c_void,
+}
+
+impl GSM_Transition {
+    /// Sets `unknown` if the kind is not a `GSM_TransitionKind`.
+    ///
+    /// # Safety
+    /// `state`, if not null, points to valid callbacks.
+    unsafe fn into_transition(self, unknown: &Arc<AtomicBool>) -> StateTransition<*mut c_void> {
+        let kind = match GSM_TransitionKind::from_raw(self.kind) {
+            Some(kind) => kind,
+            None => {
+                unknown.store(true, Ordering::Relaxed);
+                return StateTransition::None;
+            }
+        };
+        let state = || {
+            Box::new(ForeignState {
+                vtable: *self.state,
+                user: self.user,
+                unknown: unknown.clone(),
+            })
+        };
+        match kind {
+            GSM_TransitionKind::None => StateTransition::None,
+            GSM_TransitionKind::Pop => StateTransition::Pop,
+            GSM_TransitionKind::Quit => StateTransition::Quit,
+            _ if self.state.is_null() => StateTransition::None,
+            GSM_TransitionKind::Push => StateTransition::Push(state()),
+            GSM_TransitionKind::Replace => StateTransition::Replace(state()),
+            GSM_TransitionKind::Switch => StateTransition::Switch(state()),
+        }
+    }
+}
+
+/// What `gsm_machine_update` did.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum GSM_Status {
+    /// The machine is still running.
+    Running,
+    /// The machine has no state anymore.
+    Stopped,
+    /// The machine was null.
+    Invalid,
+    /// The machine panicked, and should be destroyed.
+    Panicked,
+    /// A callback returned a transition of an unknown kind, which did nothing. The machine may
+    /// still be running, see `gsm_machine_is_running`.
+    UnknownTransition,
+}
+
+/// A state made of the callbacks given by the host.
+struct ForeignState {
+    vtable: GSM_StateVTable,
+    user: *mut c_void,
+    /// Set when a callback returns a transition of an unknown kind, shared with the machine.
+    unknown: Arc<AtomicBool>,
+}
+
+// The host is the one sharing the user data between threads, if it does.
+#[cfg(feature = "send")]
+unsafe impl Send for ForeignState {}
+
+impl ForeignState {
+    fn transition(
+        &self,
+        callback: GSM_TransitionFn,
+        data: *mut c_void,
+    ) -> StateTransition<*mut c_void> {
+        match callback {
+            // SAFETY: the host gave valid callbacks, and a valid state with a transition.
+            Some(callback) => unsafe { callback(self.user, data).into_transition(&self.unknown) },
+            None => StateTransition::None,
+        }
+    }
+
+    fn hook(&self, callback: GSM_HookFn, data: *mut c_void) {
+        if let Some(callback) = callback {
+            // SAFETY: the host gave valid callbacks.
+            unsafe { callback(self.user, data) }
+        }
+    }
+}
+
+impl State<*mut c_void> for ForeignState {
+    fn on_start(&mut self, data: &mut *mut c_void) -> StateTransition<*mut c_void> {
+        self.transition(self.vtable.on_start, *data)
+    }
+
+    fn on_stop(&mut self, data: &mut *mut c_void) -> StateTransition<*mut c_void> {
+        self.transition(self.vtable.on_stop, *data)
+    }
+
+    fn on_pause(&mut self, data: &mut *mut c_void) {
+        self.hook(self.vtable.on_pause, *data);
+    }
+
+    fn on_resume(&mut self, data: &mut *mut c_void) -> StateTransition<*mut c_void> {
+        self.transition(self.vtable.on_resume, *data)
+    }
+
+    fn update(&mut self, data: &mut *mut c_void) -> StateTransition<*mut c_void> {
+        self.transition(self.vtable.update, *data)
+    }
+
+    fn render(&mut self, data: &mut *mut c_void) {
+        self.hook(self.vtable.render, *data);
+    }
+}
+
+impl Drop for ForeignState {
+    fn drop(&mut self) {
+        if let Some(drop) = self.vtable.drop {
+            // SAFETY: the host gave a valid callback, and the user data is dropped only here.
+            unsafe { drop(self.user) }
+        }
+    }
+}
+
+/// A state machine driven from the host, created with `gsm_machine_new`.
+pub struct GSM_Machine {
+    state_machine: StateMachine<*mut c_void>,
+    /// Set when a callback returns a transition of an unknown kind, until the update returns.
+    unknown: Arc<AtomicBool>,
+}
+
+/// Creates a state machine without states, to free with `gsm_machine_destroy`.
+/// Returns null if it could not be created.
+#[no_mangle]
+pub extern "C" fn gsm_machine_new() -> *mut GSM_Machine {
+    catch_unwind(|| {
+        Box::into_raw(Box::new(GSM_Machine {
+            state_machine: StateMachine::default(),
+            unknown: Arc::default(),
+        }))
+    })
+    .unwrap_or(ptr::null_mut())
+}
+
+/// Stops the states left without letting them cancel it, then frees the machine.
+/// Does nothing if the machine is null.
+///
+/// # Safety
+/// `machine` is null or was created by `gsm_machine_new` and not destroyed yet.
+#[no_mangle]
+pub unsafe extern "C" fn gsm_machine_destroy(machine: *mut GSM_Machine, data: *mut c_void) {
+    if machine.is_null() {
+        return;
+    }
+    let machine = Box::from_raw(machine);
+    let mut data = data;
+    let _ = catch_unwind(AssertUnwindSafe(move || {
+        machine.state_machine.finalize(&mut data);
+    }));
+}
+
+/// Queues a state to be pushed over the top state during the next update, which is when it
+/// starts, owning the user data from then on.
+/// Returns `false` if the machine is null, in which case the user data stays owned by the host,
+/// or if the machine panicked, in which case the user data was freed with the `drop` callback.
+///
+/// # Safety
+/// `machine` is null or valid, and the callbacks are null or valid as long as the state is
+/// alive.
+#[no_mangle]
+pub unsafe extern "C" fn gsm_machine_push(
+    machine: *mut GSM_Machine,
+    vtable: GSM_StateVTable,
+    user: *mut c_void,
+) -> bool {
+    let machine = match machine.as_mut() {
+        Some(machine) => machine,
+        None => return false,
+    };
+    catch_unwind(AssertUnwindSafe(|| {
+        machine
+            .state_machine
+            .queue_transition(StateTransition::Push(Box::new(ForeignState {
+                vtable,
+                user,
+                unknown: machine.unknown.clone(),
+            })));
+    }))
+    .is_ok()
+}
+
+/// Performs the queued transitions, updates the top state, then renders the visible states,
+/// passing them the state data.
+///
+/// # Safety
+/// `machine` is null or valid, and `data` is what the callbacks expect.
+#[no_mangle]
+pub unsafe extern "C" fn gsm_machine_update(
+    machine: *mut GSM_Machine,
+    data: *mut c_void,
+) -> GSM_Status {
+    let machine = match machine.as_mut() {
+        Some(machine) => machine,
+        None => return GSM_Status::Invalid,
+    };
+    let mut data = data;
+    let updated = catch_unwind(AssertUnwindSafe(|| {
+        machine.state_machine.update(&mut data);
+        machine.state_machine.render(&mut data);
+        machine.state_machine.is_running()
+    }));
+    match updated {
+        Err(_) => GSM_Status::Panicked,
+        _ if machine.unknown.swap(false, Ordering::Relaxed) => GSM_Status::UnknownTransition,
+        Ok(true) => GSM_Status::Running,
+        Ok(false) => GSM_Status::Stopped,
+    }
+}
+
+/// Returns the number of states on the stack of the machine, 0 for a null machine.
+/// The states pushed with `gsm_machine_push` are only on the stack once the machine is updated.
+///
+/// # Safety
+/// `machine` is null or valid.
+#[no_mangle]
+pub unsafe extern "C" fn gsm_machine_len(machine: *const GSM_Machine) -> usize {
+    machine
+        .as_ref()
+        .map_or(0, |machine| machine.state_machine.len())
+}
+
+/// Returns if the machine has states, which is not the case of a null machine.
+/// The states pushed with `gsm_machine_push` are only on the stack once the machine is updated.
+///
+/// # Safety
+/// `machine` is null or valid.
+#[no_mangle]
+pub unsafe extern "C" fn gsm_machine_is_running(machine: *const GSM_Machine) -> bool {
+    machine
+        .as_ref()
+        .is_some_and(|machine| machine.state_machine.is_running())
+}
@@ -0,0 +1,154 @@
+//! Freezing a whole state machine while the window of the game is not focused, keeping its stack
+//! as it is.
+
+use crate::{Allocator, PauseReason, ResumeReason, ScopedStateMachine};
+
+impl<'a, S: ?Sized, E, A: Allocator> ScopedStateMachine<'a, S, E, A> {
+    /// Freezes the machine: pauses its visible states that are not paused, top-down, with
+    /// `PauseReason::MachinePaused`, then makes `update` and `update_with_dt` do nothing until
+    /// `thaw` is called. The states are not updated or fixed updated, and the frames and the
+    /// time elapsed are not counted, so the scheduled transitions are not due. Rendering still
+    /// renders the states.
+    ///
+    /// The transitions queued with `queue_transition`, requested or scheduled and the events
+    /// pushed while frozen are deferred, and performed by the first update after `thaw`.
+    /// The operations called on the machine, like `push`, are still performed right away,
+    /// since they are called by the host and not by the states.
+    ///
+    /// Does nothing if the machine is already frozen or is not running. Stopping or resetting
+    /// the machine thaws it.
+    pub fn freeze(&mut self, state_data: &mut S) {
+        if self.frozen || !self.is_running() {
+            return;
+        }
+        self.frozen = true;
+        self.pause_visible_for(PauseReason::MachinePaused, state_data);
+    }
+
+    /// Thaws a frozen machine: resumes its paused visible states, bottom-up, with
+    /// `ResumeReason::MachineResumed`, and performs the transitions they return. The next
+    /// update updates the states again.
+    ///
+    /// Does nothing if the machine is not frozen.
+    pub fn thaw(&mut self, state_data: &mut S) {
+        if !self.frozen {
+            return;
+        }
+        self.frozen = false;
+        self.resume_visible_with(None, ResumeReason::MachineResumed, state_data);
+        self.settle(state_data);
+    }
+
+    /// Returns if the machine is frozen, see `freeze`.
+    pub fn is_frozen(&self) -> bool {
+        self.frozen
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    type Log = Vec<String>;
+
+    /// Logs why it is paused and resumed, and how many times it is updated.
+    struct Game(u32);
+
+    impl State<Log> for Game {
+        fn on_pause_with_reason(&mut self, reason: PauseReason, log: &mut Log) {
+            log.push(format!("game:pause:{:?}", reason));
+        }
+
+        fn on_resume_with_reason(
+            &mut self,
+            reason: ResumeReason,
+            log: &mut Log,
+        ) -> StateTransition<Log> {
+            log.push(format!("game:resume:{:?}", reason));
+            StateTransition::None
+        }
+
+        fn update(&mut self, log: &mut Log) -> StateTransition<Log> {
+            self.0 += 1;
+            log.push(format!("game:update:{}", self.0));
+            StateTransition::None
+        }
+    }
+
+    struct Pause;
+
+    impl State<Log> for Pause {
+        fn on_start(&mut self, log: &mut Log) -> StateTransition<Log> {
+            log.push("pause:start".to_string());
+            StateTransition::None
+        }
+    }
+
+    #[test]
+    fn freeze_and_thaw() {
+        let mut log = Log::new();
+        let mut sm = StateMachine::<Log>::default();
+        sm.push(Box::new(Game(0)), &mut log);
+        sm.update(&mut log);
+
+        sm.freeze(&mut log);
+        sm.freeze(&mut log);
+        assert!(sm.is_frozen());
+        assert_eq!(sm.update(&mut log), MachineStatus::Running);
+        assert_eq!(sm.frame(), 1);
+        assert_eq!(log, ["game:update:1", "game:pause:MachinePaused"]);
+
+        log.clear();
+        sm.thaw(&mut log);
+        sm.thaw(&mut log);
+        assert!(!sm.is_frozen());
+        sm.update(&mut log);
+        assert_eq!(log, ["game:resume:MachineResumed", "game:update:2"]);
+    }
+
+    #[test]
+    fn deferred_transitions() {
+        let mut log = Log::new();
+        let mut sm = StateMachine::<Log>::default();
+        sm.push(Box::new(Game(0)), &mut log);
+        sm.freeze(&mut log);
+        sm.queue_transition(StateTransition::Push(Box::new(Pause)));
+        sm.requester()
+            .request(StateTransition::Push(Box::new(Pause)));
+        sm.schedule(1, StateTransition::Pop).unwrap();
+        sm.update(&mut log);
+        sm.update(&mut log);
+        assert_eq!(log, ["game:pause:MachinePaused"]);
+        assert_eq!(sm.len(), 1);
+        assert_eq!(sm.pending_transitions(), 1);
+
+        log.clear();
+        sm.thaw(&mut log);
+        assert_eq!(
+            sm.update(&mut log),
+            MachineStatus::Transitioned(TransitionKind::Pop)
+        );
+        assert_eq!(
+            log,
+            [
+                "game:resume:MachineResumed",
+                "game:pause:Covered",
+                "pause:start",
+                "pause:start",
+            ]
+        );
+        assert_eq!(sm.len(), 2);
+    }
+
+    #[test]
+    fn stop_thaws() {
+        let mut log = Log::new();
+        let mut sm = StateMachine::<Log>::default();
+        sm.freeze(&mut log);
+        assert!(!sm.is_frozen());
+        sm.push(Box::new(Game(0)), &mut log);
+        sm.freeze(&mut log);
+        sm.stop(&mut log);
+        assert!(!sm.is_frozen());
+    }
+}
@@ -0,0 +1,247 @@
+//! States built from closures, for prototypes, examples and tests, and closures used as states.
+
+use crate::{MaybeSend, State, StateTransition};
+
+/// A hook of a `FunctionalState`.
+type Hook<S> = Option<Box<maybe_send!(dyn FnMut(&mut S))>>;
+
+/// The update hook of a `FunctionalState`.
+type UpdateHook<S, E> = Option<Box<maybe_send!(dyn FnMut(&mut S) -> StateTransition<S, E>)>>;
+
+/// A state made of closures, one per hook, without defining a type for it.
+/// The hooks without a closure do nothing, and the lifecycle hooks return no transition.
+///
+/// ```rust
+/// use game_state_machine::*;
+///
+/// let mut log = Vec::new();
+/// let mut sm = StateMachine::<Vec<&'static str>>::default();
+/// let menu = FunctionalState::new()
+///     .named("menu")
+///     .on_resume(|log: &mut Vec<&'static str>| log.push("back to the menu"))
+///     .on_update(|_| {
+///         let game = FunctionalState::new()
+///             .on_start(|log: &mut Vec<&'static str>| log.push("playing"))
+///             .on_update(|_| StateTransition::Pop);
+///         StateTransition::push(game)
+///     });
+/// sm.push_state(menu, &mut log);
+/// sm.update(&mut log);
+/// sm.update(&mut log);
+/// assert_eq!(log, vec!["playing", "back to the menu"]);
+/// assert_eq!(sm.active().map(|state| state.name()), Some("menu"));
+/// ```
+pub struct FunctionalState<S: ?Sized, E = ()> {
+    name: Option<String>,
+    on_start: Hook<S>,
+    on_stop: Hook<S>,
+    on_pause: Hook<S>,
+    on_resume: Hook<S>,
+    on_update: UpdateHook<S, E>,
+    on_shadow_update: Hook<S>,
+    on_render: Hook<S>,
+}
+
+impl<S: ?Sized, E> Default for FunctionalState<S, E> {
+    fn default() -> Self {
+        Self {
+            name: None,
+            on_start: None,
+            on_stop: None,
+            on_pause: None,
+            on_resume: None,
+            on_update: None,
+            on_shadow_update: None,
+            on_render: None,
+        }
+    }
+}
+
+impl<S: ?Sized, E> FunctionalState<S, E> {
+    /// Creates a state doing nothing.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the name of the state, see `State::name`. Defaults to the name of the type.
+    pub fn named(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    /// Sets the closure called by `State::on_start`.
+    pub fn on_start(mut self, hook: impl FnMut(&mut S) + MaybeSend + 'static) -> Self {
+        self.on_start = Some(Box::new(hook));
+        self
+    }
+
+    /// Sets the closure called by `State::on_stop`.
+    pub fn on_stop(mut self, hook: impl FnMut(&mut S) + MaybeSend + 'static) -> Self {
+        self.on_stop = Some(Box::new(hook));
+        self
+    }
+
+    /// Sets the closure called by `State::on_pause`.
+    pub fn on_pause(mut self, hook: impl FnMut(&mut S) + MaybeSend + 'static) -> Self {
+        self.on_pause = Some(Box::new(hook));
+        self
+    }
+
+    /// Sets the closure called by `State::on_resume`.
+    pub fn on_resume(mut self, hook: impl FnMut(&mut S) + MaybeSend + 'static) -> Self {
+        self.on_resume = Some(Box::new(hook));
+        self
+    }
+
+    /// Sets the closure called by `State::update`, returning the transition of the state.
+    pub fn on_update(
+        mut self,
+        hook: impl FnMut(&mut S) -> StateTransition<S, E> + MaybeSend + 'static,
+    ) -> Self {
+        self.on_update = Some(Box::new(hook));
+        self
+    }
+
+    /// Sets the closure called by `State::shadow_update`.
+    pub fn on_shadow_update(mut self, hook: impl FnMut(&mut S) + MaybeSend + 'static) -> Self {
+        self.on_shadow_update = Some(Box::new(hook));
+        self
+    }
+
+    /// Sets the closure called by `State::render`.
+    pub fn on_render(mut self, hook: impl FnMut(&mut S) + MaybeSend + 'static) -> Self {
+        self.on_render = Some(Box::new(hook));
+        self
+    }
+}
+
+/// Calls a hook, if it is set.
+fn call<S: ?Sized>(hook: &mut Hook<S>, state_data: &mut S) {
+    if let Some(hook) = hook {
+        hook(state_data);
+    }
+}
+
+impl<S: ?Sized + 'static, E: 'static> State<S, E> for FunctionalState<S, E> {
+    fn name(&self) -> &str {
+        match &self.name {
+            Some(name) => name,
+            None => std::any::type_name::<Self>(),
+        }
+    }
+
+    fn on_start(&mut self, state_data: &mut S) -> StateTransition<S, E> {
+        call(&mut self.on_start, state_data);
+        StateTransition::None
+    }
+
+    fn on_stop(&mut self, state_data: &mut S) -> StateTransition<S, E> {
+        call(&mut self.on_stop, state_data);
+        StateTransition::None
+    }
+
+    fn on_pause(&mut self, state_data: &mut S) {
+        call(&mut self.on_pause, state_data);
+    }
+
+    fn on_resume(&mut self, state_data: &mut S) -> StateTransition<S, E> {
+        call(&mut self.on_resume, state_data);
+        StateTransition::None
+    }
+
+    fn update(&mut self, state_data: &mut S) -> StateTransition<S, E> {
+        match &mut self.on_update {
+            Some(hook) => hook(state_data),
+            None => StateTransition::None,
+        }
+    }
+
+    fn shadow_update(&mut self, state_data: &mut S) {
+        call(&mut self.on_shadow_update, state_data);
+    }
+
+    fn render(&mut self, state_data: &mut S) {
+        call(&mut self.on_render, state_data);
+    }
+}
+
+/// A closure is a state which only has an update, returning the transition of the state.
+/// Its other hooks do nothing, so it cannot set things up in `on_start` or clean them up in
+/// `on_stop`, use a `FunctionalState` for that. Its name is the name of the closure type.
+///
+/// ```rust
+/// use game_state_machine::*;
+///
+/// let mut frames = 0;
+/// let mut sm = StateMachine::<u32>::default();
+/// sm.push(
+///     Box::new(|frames: &mut u32| {
+///         *frames += 1;
+///         StateTransition::Pop
+///     }),
+///     &mut frames,
+/// );
+/// sm.update(&mut frames);
+/// assert_eq!(frames, 1);
+/// assert!(!sm.is_running());
+/// ```
+impl<S: ?Sized, E, F> State<S, E> for F
+where
+    S: 'static,
+    E: 'static,
+    F: FnMut(&mut S) -> StateTransition<S, E> + MaybeSend + 'static,
+{
+    fn update(&mut self, state_data: &mut S) -> StateTransition<S, E> {
+        self(state_data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    #[test]
+    fn hooks() {
+        let mut log = Vec::new();
+        let mut sm = StateMachine::<Vec<&'static str>>::default();
+        let state = FunctionalState::new()
+            .on_start(|log: &mut Vec<&'static str>| log.push("start"))
+            .on_pause(|log| log.push("pause"))
+            .on_shadow_update(|log| log.push("shadow"))
+            .on_stop(|log| log.push("stop"));
+        sm.push(Box::new(state), &mut log);
+        sm.push(Box::new(FunctionalState::new()), &mut log);
+        sm.update(&mut log);
+        sm.render(&mut log);
+        assert!(sm
+            .iter()
+            .all(|state| state.name().contains("FunctionalState")));
+        sm.stop(&mut log);
+        assert_eq!(log, vec!["start", "pause", "shadow", "stop"]);
+    }
+
+    #[test]
+    fn closure() {
+        let mut sm = StateMachine::<u32>::default();
+        let mut updates = 0;
+        sm.push(
+            Box::new(FunctionalState::new().on_resume(|updates: &mut u32| *updates += 10)),
+            &mut updates,
+        );
+        let mut left = 2;
+        sm.push(
+            Box::new(move |updates: &mut u32| {
+                *updates += 1;
+                left -= 1;
+                match left {
+                    0 => StateTransition::Pop,
+                    _ => StateTransition::None,
+                }
+            }),
+            &mut updates,
+        );
+        sm.update_n(2, &mut updates);
+        assert_eq!(updates, 12);
+        assert_eq!(sm.len(), 1);
+    }
+}
@@ -0,0 +1,159 @@
+//! Driving a state machine from the loop of a game framework, like macroquad or ggez, which
+//! tells how long each frame took and when the window is closed.
+
+use crate::{Allocator, ExitReason, ScopedStateMachine};
+#[cfg(feature = "macroquad")]
+use crate::{State, StateMachine};
+use std::time::Duration;
+
+/// What a state machine needs from the loop of a game framework to run a frame, see
+/// `StateMachine::frame_with`.
+pub trait GameLoopAdapter {
+    /// Returns how long the last frame took.
+    fn frame_time(&mut self) -> Duration;
+
+    /// Returns if the player asked to close the window since the last frame.
+    fn close_requested(&mut self) -> bool;
+}
+
+impl<'a, S: ?Sized, E, A: Allocator> ScopedStateMachine<'a, S, E, A> {
+    /// Runs one frame of a game loop: stops the machine if the window was asked to close,
+    /// otherwise updates it with the time the frame took, see `update_with_dt`, then renders the
+    /// states that are left, see `render`.
+    ///
+    /// The states can cancel the stop, see `State::on_stop_requested`, like a level with
+    /// unsaved changes, in which case the frame goes on as usual.
+    ///
+    /// Returns how the machine stopped, or `None` if it is still running, for the loop to wait
+    /// for the next frame.
+    pub fn frame_with(
+        &mut self,
+        adapter: &mut impl GameLoopAdapter,
+        state_data: &mut S,
+    ) -> Option<ExitReason> {
+        if adapter.close_requested() {
+            self.stop(state_data);
+        }
+        if self.is_running() {
+            self.update_with_dt(adapter.frame_time(), state_data);
+        }
+        if !self.is_running() {
+            return self.exit_reason();
+        }
+        self.render(state_data);
+        None
+    }
+}
+
+/// The loop of macroquad, timing the frames with `get_frame_time`.
+/// Closing the window is only reported once `prevent_quit` was called, see `run_macroquad`.
+#[cfg(feature = "macroquad")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MacroquadLoop;
+
+#[cfg(feature = "macroquad")]
+impl GameLoopAdapter for MacroquadLoop {
+    fn frame_time(&mut self) -> Duration {
+        Duration::from_secs_f32(macroquad::time::get_frame_time().max(0.0))
+    }
+
+    fn close_requested(&mut self) -> bool {
+        macroquad::input::is_quit_requested()
+    }
+}
+
+/// Runs a machine starting with this state in the macroquad loop, until it stops.
+/// Each frame updates and renders the machine with `frame_with`, then waits for the next one.
+/// Closing the window stops the machine, since the window would close without the states
+/// being stopped otherwise.
+///
+/// Returns how the machine stopped, along with the state data.
+#[cfg(feature = "macroquad")]
+pub async fn run_macroquad<S, E>(
+    initial_state: Box<dyn State<S, E>>,
+    mut state_data: S,
+) -> (Option<ExitReason>, S) {
+    macroquad::input::prevent_quit();
+    let mut state_machine = StateMachine::new(initial_state);
+    state_machine.start(&mut state_data);
+    let mut adapter = MacroquadLoop;
+    while state_machine.is_running() {
+        if let Some(reason) = state_machine.frame_with(&mut adapter, &mut state_data) {
+            return (Some(reason), state_data);
+        }
+        macroquad::window::next_frame().await;
+    }
+    (state_machine.exit_reason(), state_data)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use std::time::Duration;
+
+    /// Frames of 10 ms, with the window closed on the given frame.
+    struct Frames {
+        frame: u32,
+        closed_on: u32,
+    }
+
+    impl GameLoopAdapter for Frames {
+        fn frame_time(&mut self) -> Duration {
+            Duration::from_millis(10)
+        }
+
+        fn close_requested(&mut self) -> bool {
+            self.frame += 1;
+            self.frame == self.closed_on
+        }
+    }
+
+    #[derive(Default)]
+    struct Calls {
+        fixed: u32,
+        renders: u32,
+        stopped: bool,
+    }
+
+    struct Game;
+
+    impl State<Calls> for Game {
+        fn fixed_update(&mut self, calls: &mut Calls) -> StateTransition<Calls> {
+            calls.fixed += 1;
+            StateTransition::None
+        }
+
+        fn render(&mut self, calls: &mut Calls) {
+            calls.renders += 1;
+        }
+
+        fn on_stop(&mut self, calls: &mut Calls) -> StateTransition<Calls> {
+            calls.stopped = true;
+            StateTransition::None
+        }
+    }
+
+    #[test]
+    fn closed_window() {
+        let mut calls = Calls::default();
+        let mut sm = StateMachine::new(Box::new(Game));
+        sm.set_fixed_timestep(Duration::from_millis(10));
+        sm.start(&mut calls);
+        let mut frames = Frames {
+            frame: 0,
+            closed_on: 4,
+        };
+        let mut runs = 0;
+        let reason = loop {
+            match sm.frame_with(&mut frames, &mut calls) {
+                None => runs += 1,
+                Some(reason) => break reason,
+            }
+        };
+        assert_eq!(reason, ExitReason::StoppedExternally);
+        assert_eq!(runs, 3);
+        assert_eq!(calls.fixed, 3);
+        assert_eq!(calls.renders, 3);
+        assert!(calls.stopped);
+    }
+}
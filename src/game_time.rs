@@ -0,0 +1,74 @@
+//! Driving the time based features with the `Time` of `game_clock`, which the host already
+//! advances once per frame, instead of a clock of the machine.
+
+use crate::{Allocator, MachineStatus, ScopedStateMachine};
+use game_clock::Time;
+use std::time::Duration;
+
+impl<'a, S: ?Sized, E, A: Allocator> ScopedStateMachine<'a, S, E, A> {
+    /// Updates the machine like `update_with_dt`, with the time elapsed since the last frame
+    /// and the fixed timestep read from `time`, which becomes the fixed timestep of the machine,
+    /// see `set_fixed_timestep`.
+    ///
+    /// The time elapsed is `Time::delta_time`, so the time scale of `time` slows or speeds up
+    /// the fixed updates, the time spent in the states and the scheduled transitions alike.
+    /// The machine keeps its own fixed timestep accumulator, so the host does not step the fixed
+    /// updates of `time` for the machine.
+    pub fn update_with_time(&mut self, time: &Time, state_data: &mut S) -> MachineStatus {
+        if time.fixed_time() > Duration::default() {
+            self.fixed_timestep = time.fixed_time();
+        }
+        self.update_with_dt(time.delta_time(), state_data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use game_clock::Time;
+    use std::time::Duration;
+
+    #[derive(Default, Debug, PartialEq)]
+    struct Ticks {
+        fixed: u32,
+        updates: u32,
+    }
+
+    struct Level;
+
+    impl State<Ticks> for Level {
+        fn update(&mut self, ticks: &mut Ticks) -> StateTransition<Ticks> {
+            ticks.updates += 1;
+            StateTransition::None
+        }
+
+        fn fixed_update(&mut self, ticks: &mut Ticks) -> StateTransition<Ticks> {
+            ticks.fixed += 1;
+            StateTransition::None
+        }
+    }
+
+    #[test]
+    fn same_as_dt() {
+        let frames = [7, 12, 30, 3, 16, 41].map(Duration::from_millis);
+        let mut time = Time::default();
+        time.set_fixed_time(Duration::from_millis(10));
+        time.set_time_scale(2.0);
+        let (mut with_time, mut with_dt) = (Ticks::default(), Ticks::default());
+        let mut sm_time = StateMachine::default();
+        let mut sm_dt = StateMachine::default();
+        sm_dt.set_fixed_timestep(Duration::from_millis(10));
+        let level_time = sm_time.push_state(Level, &mut with_time);
+        let level_dt = sm_dt.push_state(Level, &mut with_dt);
+
+        for frame in frames {
+            time.advance_frame(frame);
+            sm_time.update_with_time(&time, &mut with_time);
+            sm_dt.update_with_dt(time.delta_time(), &mut with_dt);
+        }
+        assert_eq!(with_time, with_dt);
+        assert_eq!(with_time.fixed, 17);
+        assert_eq!(sm_time.active_time(level_time), sm_dt.active_time(level_dt));
+        assert_eq!(sm_time.interpolation_alpha(), sm_dt.interpolation_alpha());
+    }
+}
@@ -0,0 +1,143 @@
+//! Logic running on every update whatever the states are, like a screenshot key or a debug
+//! console toggle, which a background state would lose on a switch.
+
+use crate::{Allocator, ScopedStateMachine, StateTransition, TransitionKind};
+
+/// Called by a state machine at the start of its updates, see `StateMachine::add_global_hook`.
+pub type GlobalHook<S, E = ()> =
+    Box<maybe_send!(dyn FnMut(&mut S) -> Option<StateTransition<S, E>>)>;
+
+impl<'a, S: ?Sized, E, A: Allocator> ScopedStateMachine<'a, S, E, A> {
+    /// Adds a hook called on every update while the machine is running, after the hooks added
+    /// before it. The hooks are kept by switches, `stop` and `reset`.
+    ///
+    /// The hooks are called after the queued transitions are performed, before the events are
+    /// handled and the states are updated. When hooks return transitions, they are performed in
+    /// the order of the hooks once all of them were called, and they win over the states: the
+    /// events stay queued and the states are not updated on this frame. With `update_with_dt`,
+    /// the fixed updates still run, so the simulated time does not fall behind.
+    pub fn add_global_hook(&mut self, hook: GlobalHook<S, E>) {
+        self.global_hooks.push(hook);
+    }
+
+    /// Removes all the global hooks.
+    pub fn clear_global_hooks(&mut self) {
+        self.global_hooks.clear();
+    }
+
+    /// Calls the global hooks and performs the transitions they returned.
+    /// Returns `None` if no hook returned a transition, or the last transition performed
+    /// otherwise.
+    pub(crate) fn run_global_hooks(
+        &mut self,
+        state_data: &mut S,
+    ) -> Option<Option<TransitionKind>> {
+        if self.global_hooks.is_empty() || !self.is_running() {
+            return None;
+        }
+        let requests: Vec<_> = self
+            .global_hooks
+            .iter_mut()
+            .filter_map(|hook| hook(state_data))
+            .collect();
+        if requests.is_empty() {
+            return None;
+        }
+        let mut last = None;
+        for request in requests {
+            last = self.perform(request, state_data).or(last);
+            if last == Some(TransitionKind::Quit) {
+                break;
+            }
+        }
+        Some(last)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    /// The input of a frame, and the states updated.
+    #[derive(Default)]
+    struct Frame {
+        screenshot: bool,
+        quit: bool,
+        updated: Vec<&'static str>,
+        screenshots: u32,
+    }
+
+    struct Scene(&'static str);
+
+    impl State<Frame> for Scene {
+        fn name(&self) -> &str {
+            self.0
+        }
+
+        fn update(&mut self, frame: &mut Frame) -> StateTransition<Frame> {
+            frame.updated.push(self.0);
+            StateTransition::None
+        }
+    }
+
+    fn machine() -> StateMachine<Frame> {
+        let mut sm = StateMachine::default();
+        sm.add_global_hook(Box::new(|frame: &mut Frame| {
+            if std::mem::take(&mut frame.screenshot) {
+                frame.screenshots += 1;
+            }
+            None
+        }));
+        sm.add_global_hook(Box::new(|frame: &mut Frame| {
+            if std::mem::take(&mut frame.quit) {
+                Some(StateTransition::Switch(Box::new(Scene("title"))))
+            } else {
+                None
+            }
+        }));
+        sm
+    }
+
+    #[test]
+    fn fires_on_every_update() {
+        let mut frame = Frame::default();
+        let mut sm = machine();
+        frame.screenshot = true;
+        sm.update(&mut frame);
+        assert!(frame.screenshot);
+
+        sm.push(Box::new(Scene("game")), &mut frame);
+        sm.update(&mut frame);
+        sm.switch(Box::new(Scene("menu")), &mut frame);
+        frame.screenshot = true;
+        sm.update(&mut frame);
+        assert_eq!(frame.screenshots, 2);
+        assert_eq!(frame.updated, ["game", "menu"]);
+
+        sm.stop(&mut frame);
+        sm.reset(&mut frame);
+        sm.push(Box::new(Scene("game")), &mut frame);
+        frame.screenshot = true;
+        sm.update(&mut frame);
+        assert_eq!(frame.screenshots, 3);
+    }
+
+    #[test]
+    fn transition_wins() {
+        let mut frame = Frame::default();
+        let mut sm = machine();
+        sm.push(Box::new(Scene("game")), &mut frame);
+        frame.quit = true;
+        frame.screenshot = true;
+        assert_eq!(
+            sm.update(&mut frame),
+            MachineStatus::Transitioned(TransitionKind::Switch)
+        );
+        assert_eq!(sm.active_name(), Some("title"));
+        assert!(frame.updated.is_empty());
+        assert_eq!(frame.screenshots, 1);
+
+        sm.update(&mut frame);
+        assert_eq!(frame.updated, ["title"]);
+    }
+}
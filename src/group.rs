@@ -0,0 +1,250 @@
+//! Pushing states which appear and disappear together, like the simulation, the HUD and the
+//! pause listener of a level.
+
+use crate::{Allocator, ScopedStateMachine, State, TransitionKind};
+
+/// Identifies a group of states pushed together, see `StateMachine::push_group`.
+/// Each group gets a new identifier, so the identifiers of popped groups stay stale.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct GroupId(u64);
+
+impl<'a, S: ?Sized, E, A: Allocator> ScopedStateMachine<'a, S, E, A> {
+    /// Pushes the states in order as one group, like pushing them one by one: each state pauses
+    /// the one below it, unless it is transparent, then is started.
+    /// The transitions returned by their `on_start` are performed once all of them are pushed,
+    /// so the group is contiguous on the stack.
+    ///
+    /// The group is popped with `pop_group` or `StateTransition::PopGroup`. A `Pop` of its
+    /// topmost state only pops this state, unless it pops its group, see `State::pops_group`.
+    /// The states replacing a state of the group, like with a `Switch`, are not in the group.
+    pub fn push_group(&mut self, states: Vec<Box<dyn State<S, E>>>, state_data: &mut S) -> GroupId {
+        let group = GroupId(self.next_group);
+        self.next_group += 1;
+        self.recorded(TransitionKind::PushMany, |sm| {
+            for state in states {
+                let handle = sm.next_handle();
+                sm.push_boxed(state, state_data);
+                // The state is not on the stack if it panicked when started.
+                if let Some(index) = sm.index_of(handle) {
+                    sm.state_stack[index].group = Some(group);
+                }
+            }
+        });
+        self.settle(state_data);
+        group
+    }
+
+    /// Stops the states of the group, top-down, along with the states pushed above it since,
+    /// then resumes the state below it, like a `PopN` popping all of them.
+    /// A state inserted between the states of the group is also popped.
+    ///
+    /// Returns `false` and does nothing if no state of the group is on the stack anymore, or if
+    /// one of the popped states cancels it, see `State::on_stop_requested`.
+    pub fn pop_group(&mut self, group: GroupId, state_data: &mut S) -> bool {
+        let start = match self.group_start(group) {
+            Some(start) => start,
+            None => return false,
+        };
+        if self.vetoed(start, state_data) {
+            return false;
+        }
+        let popped = self.recorded(TransitionKind::PopGroup, |sm| {
+            sm.pop_group_states(group, state_data)
+        });
+        self.settle(state_data);
+        popped
+    }
+
+    /// Returns the group of the active state, if it was pushed in one.
+    pub fn active_group(&self) -> Option<GroupId> {
+        self.state_stack.last()?.group
+    }
+
+    /// Returns the index of the lowest state of the group on the stack.
+    pub(crate) fn group_start(&self, group: GroupId) -> Option<usize> {
+        self.state_stack
+            .iter()
+            .position(|entry| entry.group == Some(group))
+    }
+
+    /// Returns the group a `Pop` of the active state pops, see `State::pops_group`.
+    pub(crate) fn coupled_group(&self) -> Option<GroupId> {
+        let entry = self.state_stack.last()?;
+        if entry.state.as_ref().pops_group() {
+            entry.group
+        } else {
+            None
+        }
+    }
+
+    /// Pops the states from the lowest one of the group to the top.
+    /// Returns `false` if the group is not on the stack.
+    pub(crate) fn pop_group_states(&mut self, group: GroupId, state_data: &mut S) -> bool {
+        match self.group_start(group) {
+            Some(start) => {
+                self.pop_n(self.state_stack.len() - start, state_data);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    type Log = Vec<String>;
+
+    /// Logs its lifecycle, and returns its transition once when updated.
+    struct Member(&'static str, bool, Option<StateTransition<Log>>);
+
+    impl State<Log> for Member {
+        fn name(&self) -> &str {
+            self.0
+        }
+
+        fn pops_group(&self) -> bool {
+            self.1
+        }
+
+        fn on_start(&mut self, log: &mut Log) -> StateTransition<Log> {
+            log.push(format!("{}:start", self.0));
+            StateTransition::None
+        }
+
+        fn on_pause(&mut self, log: &mut Log) {
+            log.push(format!("{}:pause", self.0));
+        }
+
+        fn on_resume(&mut self, log: &mut Log) -> StateTransition<Log> {
+            log.push(format!("{}:resume", self.0));
+            StateTransition::None
+        }
+
+        fn update(&mut self, _: &mut Log) -> StateTransition<Log> {
+            self.2.take().unwrap_or(StateTransition::None)
+        }
+
+        fn on_stop(&mut self, log: &mut Log) -> StateTransition<Log> {
+            log.push(format!("{}:stop", self.0));
+            StateTransition::None
+        }
+    }
+
+    fn member(name: &'static str) -> Box<dyn State<Log>> {
+        Box::new(Member(name, false, None))
+    }
+
+    fn level(listener: Member) -> Vec<Box<dyn State<Log>>> {
+        vec![member("simulation"), member("hud"), Box::new(listener)]
+    }
+
+    fn names(sm: &StateMachine<Log>) -> Vec<&str> {
+        sm.iter().map(|state| state.name()).collect()
+    }
+
+    #[test]
+    fn pushed_and_popped_together() {
+        let mut log = Log::new();
+        let mut sm = StateMachine::<Log>::default();
+        sm.push(member("world"), &mut log);
+        log.clear();
+
+        let group = sm.push_group(level(Member("listener", false, None)), &mut log);
+        assert_eq!(
+            log,
+            [
+                "world:pause",
+                "simulation:start",
+                "simulation:pause",
+                "hud:start",
+                "hud:pause",
+                "listener:start",
+            ]
+        );
+        assert_eq!(sm.active_group(), Some(group));
+
+        log.clear();
+        assert!(sm.pop_group(group, &mut log));
+        assert_eq!(
+            log,
+            [
+                "listener:stop",
+                "hud:stop",
+                "simulation:stop",
+                "world:resume"
+            ]
+        );
+        assert_eq!(names(&sm), ["world"]);
+        assert_eq!(sm.active_group(), None);
+        assert!(!sm.pop_group(group, &mut log));
+        assert_eq!(
+            sm.transition(StateTransition::PopGroup(group), &mut log),
+            None
+        );
+    }
+
+    #[test]
+    fn interleaved() {
+        let mut log = Log::new();
+        let mut sm = StateMachine::<Log>::default();
+        sm.push(member("world"), &mut log);
+        let first = sm.push_group(level(Member("listener", false, None)), &mut log);
+        sm.push(member("dialog"), &mut log);
+        let second = sm.push_group(vec![member("minigame")], &mut log);
+        assert_ne!(first, second);
+        log.clear();
+
+        assert_eq!(
+            sm.transition(StateTransition::PopGroup(first), &mut log),
+            Some(TransitionKind::PopGroup)
+        );
+        assert_eq!(
+            log,
+            [
+                "minigame:stop",
+                "dialog:stop",
+                "listener:stop",
+                "hud:stop",
+                "simulation:stop",
+                "world:resume",
+            ]
+        );
+        assert_eq!(names(&sm), ["world"]);
+    }
+
+    #[test]
+    fn pop_of_a_member() {
+        let mut log = Log::new();
+        let mut sm = StateMachine::<Log>::default();
+        sm.push(member("world"), &mut log);
+        sm.push_group(
+            level(Member("listener", false, Some(StateTransition::Pop))),
+            &mut log,
+        );
+        log.clear();
+        sm.update(&mut log);
+        assert_eq!(log, ["listener:stop", "hud:resume"]);
+        assert_eq!(names(&sm), ["world", "simulation", "hud"]);
+
+        sm.pop(&mut log);
+        sm.pop(&mut log);
+        sm.push_group(
+            level(Member("listener", true, Some(StateTransition::Pop))),
+            &mut log,
+        );
+        log.clear();
+        sm.update(&mut log);
+        assert_eq!(
+            log,
+            [
+                "listener:stop",
+                "hud:stop",
+                "simulation:stop",
+                "world:resume"
+            ]
+        );
+        assert_eq!(names(&sm), ["world"]);
+    }
+}
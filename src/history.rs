@@ -0,0 +1,182 @@
+//! Keeping the last transitions of a state machine, to find out how it got where it is.
+
+use crate::{Allocator, ScopedStateMachine, TransitionKind};
+use std::collections::VecDeque;
+
+/// A transition kept in the history of a state machine.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TransitionRecord {
+    /// The kind of the transition.
+    pub kind: TransitionKind,
+    /// The name of the active state before the transition, if any.
+    pub from: Option<String>,
+    /// The name of the active state after the transition, if any.
+    pub to: Option<String>,
+    /// The number of updates of the state machine when the transition happened, counting the
+    /// update it happened in.
+    pub frame: u64,
+}
+
+/// The last transitions of a state machine, up to a capacity.
+#[derive(Clone)]
+pub(crate) struct History {
+    records: VecDeque<TransitionRecord>,
+    capacity: usize,
+}
+
+impl<'a, S: ?Sized, E, A: Allocator> ScopedStateMachine<'a, S, E, A> {
+    /// Starts keeping the last `capacity` transitions, both the ones returned by the states and
+    /// the ones done directly on the machine, like `stop`.
+    /// If the history was already kept, its oldest transitions are dropped to fit the capacity.
+    pub fn enable_history(&mut self, capacity: usize) {
+        let mut records = self
+            .history
+            .take()
+            .map(|history| history.records)
+            .unwrap_or_default();
+        while records.len() > capacity {
+            records.pop_front();
+        }
+        self.history = Some(History { records, capacity });
+    }
+
+    /// Stops keeping transitions and drops the ones that were kept.
+    pub fn disable_history(&mut self) {
+        self.history = None;
+    }
+
+    /// Iterates over the kept transitions, from the oldest to the newest.
+    pub fn history(&self) -> impl Iterator<Item = &TransitionRecord> + '_ {
+        self.history
+            .iter()
+            .flat_map(|history| history.records.iter())
+    }
+
+    /// Drops the kept transitions, but keeps the history enabled.
+    pub fn clear_history(&mut self) {
+        if let Some(history) = &mut self.history {
+            history.records.clear();
+        }
+    }
+
+    /// Returns the number of times the state machine was updated.
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    /// Returns the name of the active state, if the history is kept.
+    pub(crate) fn recorded_name(&self) -> Option<String> {
+        self.history.as_ref()?;
+        self.active_name().map(str::to_string)
+    }
+
+    /// Keeps a transition in the history, if it is enabled.
+    pub(crate) fn record_history(
+        &mut self,
+        kind: TransitionKind,
+        from: Option<String>,
+        to: Option<String>,
+    ) {
+        let frame = self.frame;
+        if let Some(history) = &mut self.history {
+            if history.capacity == 0 {
+                return;
+            }
+            if history.records.len() == history.capacity {
+                history.records.pop_front();
+            }
+            history.records.push_back(TransitionRecord {
+                kind,
+                from,
+                to,
+                frame,
+            });
+        }
+    }
+
+    /// Does an operation on the stack and keeps it in the history.
+    pub(crate) fn recorded<R>(
+        &mut self,
+        kind: TransitionKind,
+        operation: impl FnOnce(&mut Self) -> R,
+    ) -> R {
+        let from = self.recorded_name();
+        let result = operation(self);
+        let to = self.recorded_name();
+        self.record_history(kind, from, to);
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    struct Named(&'static str);
+
+    impl State<()> for Named {
+        fn name(&self) -> &str {
+            self.0
+        }
+
+        fn update(&mut self, _: &mut ()) -> StateTransition<()> {
+            match self.0 {
+                "intro" => StateTransition::Switch(Box::new(Named("menu"))),
+                _ => StateTransition::None,
+            }
+        }
+    }
+
+    fn record(
+        kind: TransitionKind,
+        from: Option<&str>,
+        to: Option<&str>,
+        frame: u64,
+    ) -> TransitionRecord {
+        TransitionRecord {
+            kind,
+            from: from.map(String::from),
+            to: to.map(String::from),
+            frame,
+        }
+    }
+
+    #[test]
+    fn returned_and_external() {
+        let mut sm = StateMachine::<()>::default();
+        sm.enable_history(8);
+        sm.push(Box::new(Named("intro")), &mut ());
+        sm.update(&mut ());
+        sm.update(&mut ());
+        sm.stop(&mut ());
+
+        assert_eq!(
+            sm.history().cloned().collect::<Vec<_>>(),
+            vec![
+                record(TransitionKind::Push, None, Some("intro"), 0),
+                record(TransitionKind::Switch, Some("intro"), Some("menu"), 1),
+                record(TransitionKind::Quit, Some("menu"), None, 2),
+            ]
+        );
+        sm.clear_history();
+        assert_eq!(sm.history().count(), 0);
+    }
+
+    #[test]
+    fn wraps_at_capacity() {
+        let mut sm = StateMachine::<()>::default();
+        sm.enable_history(3);
+        for _ in 0..5 {
+            sm.push(Box::new(Named("menu")), &mut ());
+            sm.update(&mut ());
+        }
+
+        let frames = sm.history().map(|record| record.frame).collect::<Vec<_>>();
+        assert_eq!(frames, vec![2, 3, 4]);
+        assert_eq!(sm.history().last().unwrap().kind, TransitionKind::Push);
+
+        sm.enable_history(1);
+        let frames = sm.history().map(|record| record.frame).collect::<Vec<_>>();
+        assert_eq!(frames, vec![4]);
+    }
+}
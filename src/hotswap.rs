@@ -0,0 +1,228 @@
+//! Replacing the implementation of a live state by its type, like reloading a state while
+//! iterating on it, without touching the states around it.
+
+use crate::{
+    any_of, from_global, guard, into_global, Caught, ExitReason, MachineEvent, PauseReason, State,
+    StateMachine, StateTransition,
+};
+use std::error::Error;
+use std::fmt;
+
+/// A state that could not be replaced, see `StateMachine::replace_state`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ReplaceError {
+    /// No state of this type is on the stack.
+    NotFound {
+        /// The name of the type, from `std::any::type_name`.
+        type_name: &'static str,
+    },
+}
+
+impl fmt::Display for ReplaceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReplaceError::NotFound { type_name } => {
+                write!(f, "no state of type {} is on the stack", type_name)
+            }
+        }
+    }
+}
+
+impl Error for ReplaceError {}
+
+impl<S: ?Sized, E> StateMachine<S, E> {
+    /// Replaces the lowest `T` on the stack with this state, and returns the old one.
+    /// The old state is stopped and the new one is started, then paused right away if the old
+    /// one was paused. The new state keeps the position, the handle and the group of the old
+    /// one, and the other states are neither paused nor resumed.
+    ///
+    /// The transitions returned by `on_stop` and `on_start` are performed once the new state is
+    /// in place. If the new state fails to start or panics, it is removed like a popped state,
+    /// and the old one is still returned. The error of its `try_start` is then given to the
+    /// `on_error` of the states left, see `State::try_start`.
+    pub fn replace_state<T: 'static>(
+        &mut self,
+        new_state: Box<dyn State<S, E>>,
+        state_data: &mut S,
+    ) -> Result<Box<dyn State<S, E>>, ReplaceError> {
+        let index = self
+            .state_stack
+            .iter()
+            .position(|entry| any_of(&entry.state).is_some_and(|state| state.is::<T>()))
+            .ok_or(ReplaceError::NotFound {
+                type_name: std::any::type_name::<T>(),
+            })?;
+        let catch = self.catches_panics();
+        let entry = &mut self.state_stack[index];
+        let mut old = std::mem::replace(&mut entry.state, from_global(new_state));
+
+        log_trace!("on_stop: {}", old.as_ref().name());
+        let stopped = guard(catch, || {
+            in_span!(
+                "state.on_stop",
+                old.as_ref().name(),
+                old.as_mut().on_stop(state_data)
+            )
+        });
+        self.follow_up(stopped);
+        self.emit(|_| MachineEvent::StatePopped {
+            name: old.as_ref().name().to_string(),
+        });
+
+        let entry = &mut self.state_stack[index];
+        log_trace!("on_start: {}", entry.state.as_ref().name());
+        let started = guard(catch, || {
+            in_span!(
+                "state.on_start",
+                entry.state.as_ref().name(),
+                entry.state.as_mut().try_start(state_data)
+            )
+        });
+        let started = match started {
+            Ok(Ok(trans)) => self.follow_up(Ok(trans)),
+            Ok(Err(error)) => {
+                self.failed_starts.push(error);
+                false
+            }
+            Err(caught) => self.follow_up(Err(caught)),
+        };
+        if started {
+            self.emit(|sm| MachineEvent::StatePushed {
+                name: sm.state_stack[index].state.as_ref().name().to_string(),
+                depth: index + 1,
+            });
+            if self.state_stack[index].paused {
+                let reason = if self.frozen {
+                    PauseReason::MachinePaused
+                } else {
+                    PauseReason::Covered
+                };
+                // The new state was never paused, even if the entry was.
+                self.state_stack[index].paused = false;
+                self.pause_at(index, reason, state_data);
+            }
+        } else {
+            self.state_stack.remove(index);
+            if self.state_stack.is_empty() {
+                self.exit_reason = Some(ExitReason::LastStatePopped);
+            }
+            self.resume_visible(state_data);
+        }
+        self.settle(state_data);
+        Ok(into_global(old))
+    }
+
+    /// Keeps the transition returned by a lifecycle callback to perform it once the operation is
+    /// done, or the panic it caught. Returns `false` if it panicked.
+    fn follow_up(&mut self, result: Result<StateTransition<S, E>, Caught>) -> bool {
+        match result {
+            Ok(StateTransition::None) => true,
+            Ok(trans) => {
+                self.follow_ups.push(trans);
+                true
+            }
+            Err(caught) => {
+                self.caught(caught);
+                false
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    type Log = Vec<String>;
+
+    struct Level(&'static str);
+
+    impl State<Log> for Level {
+        fn name(&self) -> &str {
+            self.0
+        }
+
+        fn on_start(&mut self, log: &mut Log) -> StateTransition<Log> {
+            log.push(format!("{}:start", self.0));
+            StateTransition::None
+        }
+
+        fn on_pause(&mut self, log: &mut Log) {
+            log.push(format!("{}:pause", self.0));
+        }
+
+        fn on_resume(&mut self, log: &mut Log) -> StateTransition<Log> {
+            log.push(format!("{}:resume", self.0));
+            StateTransition::None
+        }
+
+        fn on_stop(&mut self, log: &mut Log) -> StateTransition<Log> {
+            log.push(format!("{}:stop", self.0));
+            StateTransition::None
+        }
+
+        fn update(&mut self, log: &mut Log) -> StateTransition<Log> {
+            log.push(format!("{}:update", self.0));
+            StateTransition::None
+        }
+    }
+
+    struct Menu;
+
+    impl State<Log> for Menu {
+        fn name(&self) -> &str {
+            "menu"
+        }
+    }
+
+    #[test]
+    fn replace_active() {
+        let mut log = Log::new();
+        let mut sm = StateMachine::<Log>::default();
+        sm.push(Box::new(Menu), &mut log);
+        let handle = sm.push(Box::new(Level("v1")), &mut log);
+        log.clear();
+
+        let old = sm
+            .replace_state::<Level>(Box::new(Level("v2")), &mut log)
+            .unwrap();
+        assert_eq!(old.name(), "v1");
+        assert_eq!(log, ["v1:stop", "v2:start"]);
+        assert_eq!(sm.active_name(), Some("v2"));
+        assert!(sm.is_alive(handle));
+
+        sm.update(&mut log);
+        assert_eq!(log.last().unwrap(), "v2:update");
+    }
+
+    #[test]
+    fn replace_covered() {
+        let mut log = Log::new();
+        let mut sm = StateMachine::<Log>::default();
+        sm.push(Box::new(Level("v1")), &mut log);
+        sm.push(Box::new(Menu), &mut log);
+        log.clear();
+
+        sm.replace_state::<Level>(Box::new(Level("v2")), &mut log)
+            .unwrap();
+        assert_eq!(log, ["v1:stop", "v2:start", "v2:pause"]);
+        assert_eq!(sm.len(), 2);
+
+        sm.pop(&mut log);
+        assert_eq!(log.last().unwrap(), "v2:resume");
+    }
+
+    #[test]
+    fn not_found() {
+        let mut log = Log::new();
+        let mut sm = StateMachine::<Log>::default();
+        sm.push(Box::new(Menu), &mut log);
+        let error = sm
+            .replace_state::<Level>(Box::new(Level("v2")), &mut log)
+            .err()
+            .unwrap();
+        assert!(matches!(error, ReplaceError::NotFound { .. }));
+        assert!(log.is_empty());
+        assert_eq!(sm.len(), 1);
+    }
+}
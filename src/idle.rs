@@ -0,0 +1,98 @@
+//! Noticing that a state machine is updated while it has no state, like a host loop that keeps
+//! going after the machine stopped, and starting it again.
+
+use crate::{Allocator, MaybeSend, ScopedStateMachine, State, TransitionKind};
+
+/// Decides what to do when an empty state machine is updated, see
+/// `StateMachine::set_idle_handler`.
+pub type IdleHandler<S, E = ()> =
+    Box<maybe_send!(dyn FnMut(&mut S) -> Option<Box<dyn State<S, E>>>)>;
+
+impl<'a, S: ?Sized, E, A: Allocator> ScopedStateMachine<'a, S, E, A> {
+    /// Sets the handler that is called by the updates of the machine that find the stack empty,
+    /// once the queued transitions are performed. It returns the state to push and update, to
+    /// start the machine again, or `None` to stay idle.
+    pub fn set_idle_handler(
+        &mut self,
+        handler: impl FnMut(&mut S) -> Option<Box<dyn State<S, E>>> + MaybeSend + 'static,
+    ) {
+        self.idle_handler = Some(Box::new(handler));
+    }
+
+    /// Removes the idle handler, so the updates of an empty machine do nothing.
+    pub fn clear_idle_handler(&mut self) {
+        self.idle_handler = None;
+    }
+
+    /// Returns the number of updates that found the stack empty, with or without an idle
+    /// handler, since the machine was created or reset.
+    pub fn idle_updates(&self) -> u64 {
+        self.idle_updates
+    }
+
+    /// Counts the update if the stack is empty, then pushes the state of the idle handler, if
+    /// any. Returns `TransitionKind::Push` if it did.
+    pub(crate) fn run_idle(&mut self, state_data: &mut S) -> Option<TransitionKind> {
+        if !self.state_stack.is_empty() {
+            return None;
+        }
+        self.idle_updates += 1;
+        let mut handler = self.idle_handler.take()?;
+        let state = handler(state_data);
+        self.idle_handler.get_or_insert(handler);
+        self.push(state?, state_data);
+        Some(TransitionKind::Push)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    type Log = Vec<String>;
+
+    struct Title;
+
+    impl State<Log> for Title {
+        fn update(&mut self, log: &mut Log) -> StateTransition<Log> {
+            log.push("title:update".to_string());
+            StateTransition::Quit
+        }
+    }
+
+    #[test]
+    fn counts_idle_updates() {
+        let mut log = Log::new();
+        let mut sm = StateMachine::<Log>::default();
+        sm.update(&mut log);
+        sm.update(&mut log);
+        assert_eq!(sm.idle_updates(), 2);
+        assert!(!sm.is_running());
+
+        sm.push(Box::new(Title), &mut log);
+        sm.update(&mut log);
+        assert_eq!(sm.idle_updates(), 2);
+
+        sm.set_idle_handler(|_| None);
+        sm.update(&mut log);
+        assert_eq!(sm.idle_updates(), 3);
+        assert!(!sm.is_running());
+    }
+
+    #[test]
+    fn restart_from_idle() {
+        let mut log = Log::new();
+        let mut sm = StateMachine::<Log>::default();
+        sm.set_idle_handler(|log: &mut Log| {
+            log.push("idle".to_string());
+            Some(Box::new(Title))
+        });
+        sm.update(&mut log);
+        assert_eq!(log, ["idle", "title:update"]);
+        assert!(!sm.is_running());
+
+        sm.update(&mut log);
+        assert_eq!(log, ["idle", "title:update", "idle", "title:update"]);
+        assert_eq!(sm.idle_updates(), 2);
+    }
+}
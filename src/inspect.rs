@@ -0,0 +1,176 @@
+//! Capturing everything displayable about a state machine in one call, for a debug inspector
+//! drawing the stack every frame or sending it over a debug socket.
+
+use crate::{Allocator, ExitReason, ScopedStateMachine, StateHandle};
+#[cfg(feature = "time")]
+use std::time::Duration;
+
+/// A state of the stack as seen by a debug inspector, see `StateMachine::snapshot`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[non_exhaustive]
+pub struct EntrySnapshot {
+    /// The name of the state.
+    pub name: String,
+    /// The handle of the state.
+    pub handle: StateHandle,
+    /// The position of the state in the stack, 0 being the bottom.
+    pub index: usize,
+    /// If the state is the active one, on top of the stack.
+    pub is_active: bool,
+    /// If the state was paused and not resumed since.
+    pub is_paused: bool,
+    /// If the state lets the states below it be updated and rendered, see
+    /// `State::is_transparent`.
+    pub is_transparent: bool,
+    /// The tags of the state, see `State::tags`.
+    pub tags: Vec<&'static str>,
+    /// How many times the states with this name were updated while active, if the metrics are
+    /// enabled, see `StateMachineMetrics::state_updates`.
+    pub updates: Option<u64>,
+    /// How long the state was active, see `StateMachine::active_time`.
+    #[cfg(feature = "time")]
+    pub active_time: Duration,
+    /// How long ago the state was started, see `StateMachine::time_since_start`.
+    #[cfg(feature = "time")]
+    pub time_since_start: Duration,
+}
+
+/// The stack of a state machine and what it will do next, as seen by a debug inspector, see
+/// `StateMachine::snapshot`.
+///
+/// Unlike `StackSnapshot`, it only describes the states, and cannot be loaded back.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[non_exhaustive]
+pub struct MachineSnapshot {
+    /// The states of the stack, from the bottom to the top.
+    pub states: Vec<EntrySnapshot>,
+    /// The number of times the state machine was updated, see `StateMachine::frame`.
+    pub frame: u64,
+    /// How the machine stopped, see `StateMachine::exit_reason`.
+    pub exit_reason: Option<ExitReason>,
+    /// The number of transitions queued for the next update, see
+    /// `StateMachine::pending_transitions`.
+    pub queued_transitions: usize,
+    /// The number of transitions scheduled and not yet performed, see
+    /// `StateMachine::scheduled_count`.
+    pub scheduled_transitions: usize,
+}
+
+impl<'a, S: ?Sized, E, A: Allocator> ScopedStateMachine<'a, S, E, A> {
+    /// Captures the stack of the machine and what it will do next, without the state data.
+    pub fn snapshot(&self) -> MachineSnapshot {
+        let top = self.active_index();
+        let states = self
+            .state_stack
+            .iter()
+            .enumerate()
+            .map(|(index, entry)| {
+                let name = entry.state.name();
+                EntrySnapshot {
+                    name: name.to_string(),
+                    handle: entry.handle,
+                    index,
+                    is_active: top == Some(index),
+                    is_paused: entry.paused,
+                    is_transparent: entry.state.is_transparent(),
+                    tags: entry.state.tags().to_vec(),
+                    updates: if self.metrics.is_enabled() {
+                        Some(self.metrics.state_updates(name))
+                    } else {
+                        None
+                    },
+                    #[cfg(feature = "time")]
+                    active_time: entry.active_time,
+                    #[cfg(feature = "time")]
+                    time_since_start: entry.age,
+                }
+            })
+            .collect();
+        MachineSnapshot {
+            states,
+            frame: self.frame,
+            exit_reason: self.exit_reason,
+            queued_transitions: self.pending_transitions(),
+            scheduled_transitions: self.scheduled_count(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    struct Scene(&'static str, &'static [&'static str], bool);
+
+    impl State<()> for Scene {
+        fn name(&self) -> &str {
+            self.0
+        }
+
+        fn tags(&self) -> &[&'static str] {
+            self.1
+        }
+
+        fn is_transparent(&self) -> bool {
+            self.2
+        }
+    }
+
+    fn summary(snapshot: &MachineSnapshot) -> Vec<(&str, usize, bool, bool, bool)> {
+        snapshot
+            .states
+            .iter()
+            .map(|entry| {
+                (
+                    entry.name.as_str(),
+                    entry.index,
+                    entry.is_active,
+                    entry.is_paused,
+                    entry.is_transparent,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn push_pause_pop() {
+        let mut sm = StateMachine::<()>::default();
+        sm.enable_metrics();
+        sm.push(Box::new(Scene("game", &["gameplay"], false)), &mut ());
+        sm.update(&mut ());
+        sm.update(&mut ());
+        let snapshot = sm.snapshot();
+        assert_eq!(summary(&snapshot), [("game", 0, true, false, false)]);
+        assert_eq!(snapshot.states[0].tags, ["gameplay"]);
+        assert_eq!(snapshot.states[0].updates, Some(2));
+        assert_eq!(snapshot.frame, 2);
+
+        sm.push(Box::new(Scene("pause", &[], false)), &mut ());
+        sm.push(Box::new(Scene("hud", &[], true)), &mut ());
+        sm.queue_transition(StateTransition::Pop);
+        sm.schedule(3, StateTransition::Quit).unwrap();
+        let snapshot = sm.snapshot();
+        assert_eq!(
+            summary(&snapshot),
+            [
+                ("game", 0, false, true, false),
+                ("pause", 1, false, false, false),
+                ("hud", 2, true, false, true)
+            ]
+        );
+        assert_eq!(snapshot.states[2].updates, Some(0));
+        assert_eq!(snapshot.queued_transitions, 1);
+        assert_eq!(snapshot.scheduled_transitions, 1);
+        assert_eq!(snapshot.exit_reason, None);
+
+        sm.update(&mut ());
+        sm.pop(&mut ());
+        sm.pop(&mut ());
+        let snapshot = sm.snapshot();
+        assert!(snapshot.states.is_empty());
+        assert_eq!(snapshot.exit_reason, Some(ExitReason::LastStatePopped));
+        assert_eq!(snapshot.queued_transitions, 0);
+    }
+}
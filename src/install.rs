@@ -0,0 +1,210 @@
+//! Installing a whole stack the host built elsewhere, like the states of a save file or the
+//! states drained from another machine.
+
+use crate::{into_global, ExitReason, PauseReason, State, StateMachine};
+
+/// How `StateMachine::set_stack` installs its states.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum InstallPolicy {
+    /// Stop the states on the stack, top-down, then start the new states from the bottom to the
+    /// top, as if they were pushed one after the other.
+    Start {
+        /// Return the stopped states instead of dropping them.
+        keep_previous: bool,
+    },
+    /// Remove the states on the stack without stopping them, like `StateMachine::drain`, then
+    /// put the new states on it as they are, like `StateMachine::adopt`, since they are already
+    /// live, like when hot reloading.
+    Adopt {
+        /// Return the removed states instead of dropping them.
+        keep_previous: bool,
+    },
+}
+
+impl<S: ?Sized, E> StateMachine<S, E> {
+    /// Replaces the states on the stack with these states, from the bottom to the top, and
+    /// returns the previous states, from the bottom to the top, if the policy keeps them.
+    /// Loading a snapshot and building a machine from a configuration go through this, and
+    /// `adopt` is the `InstallPolicy::Adopt` of an empty machine.
+    ///
+    /// With `InstallPolicy::Start`, each new state but the last is paused right after it is
+    /// started, since it is covered. The transitions returned by `on_stop` are dropped, like
+    /// when the machine quits, and the ones returned by `on_start` are performed once all the
+    /// new states are in place. The states to stop cannot cancel it, see
+    /// `State::on_stop_requested`. The states which panicked while stopping are not returned.
+    ///
+    /// Installing no state stops the machine with `ExitReason::StoppedExternally`. If a new
+    /// state needs a state that is not below it in the new stack, nothing is installed, see
+    /// `State::required_below`.
+    pub fn set_stack(
+        &mut self,
+        states: Vec<Box<dyn State<S, E>>>,
+        state_data: &mut S,
+        policy: InstallPolicy,
+    ) -> Vec<Box<dyn State<S, E>>> {
+        if let Some(error) = self.missing_requirement(0, &states) {
+            self.reject(error, state_data);
+            return Vec::new();
+        }
+        let (previous, keep_previous) = match policy {
+            InstallPolicy::Start { keep_previous } => (
+                self.install(states, PauseReason::Covered, state_data),
+                keep_previous,
+            ),
+            InstallPolicy::Adopt { keep_previous } => {
+                let previous = if self.state_stack.is_empty() {
+                    Vec::new()
+                } else {
+                    self.drain()
+                };
+                if !states.is_empty() {
+                    self.adopt(states);
+                }
+                (previous, keep_previous)
+            }
+        };
+        self.settle(state_data);
+        if keep_previous {
+            previous
+        } else {
+            Vec::new()
+        }
+    }
+
+    /// Stops the states on the stack, top-down, then starts these ones, pausing the states they
+    /// cover for this reason. Returns the stopped states, from the bottom to the top.
+    pub(crate) fn install(
+        &mut self,
+        states: Vec<Box<dyn State<S, E>>>,
+        reason: PauseReason,
+        state_data: &mut S,
+    ) -> Vec<Box<dyn State<S, E>>> {
+        let mut previous = Vec::new();
+        while let Some(entry) = self.state_stack.pop() {
+            previous.extend(self.stop_keeping(entry, state_data).map(into_global));
+        }
+        previous.reverse();
+        self.forget_stopped(ExitReason::StoppedExternally);
+        for state in states {
+            if !state.as_ref().is_transparent() {
+                self.pause_visible_for(reason, state_data);
+            }
+            self.push_boxed(state, state_data);
+        }
+        previous
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    type Log = Vec<String>;
+
+    struct Scene(&'static str);
+
+    impl State<Log> for Scene {
+        fn name(&self) -> &str {
+            self.0
+        }
+
+        fn on_start(&mut self, log: &mut Log) -> StateTransition<Log> {
+            log.push(format!("{}:start", self.0));
+            StateTransition::None
+        }
+
+        fn on_pause(&mut self, log: &mut Log) {
+            log.push(format!("{}:pause", self.0));
+        }
+
+        fn on_resume(&mut self, log: &mut Log) -> StateTransition<Log> {
+            log.push(format!("{}:resume", self.0));
+            StateTransition::None
+        }
+
+        fn on_stop(&mut self, log: &mut Log) -> StateTransition<Log> {
+            log.push(format!("{}:stop", self.0));
+            StateTransition::None
+        }
+    }
+
+    fn scenes(names: &[&'static str]) -> Vec<Box<dyn State<Log>>> {
+        names
+            .iter()
+            .map(|name| Box::new(Scene(name)) as Box<dyn State<Log>>)
+            .collect()
+    }
+
+    fn names(states: &[Box<dyn State<Log>>]) -> Vec<&str> {
+        states.iter().map(|state| state.as_ref().name()).collect()
+    }
+
+    #[test]
+    fn start_policy() {
+        let mut log = Log::new();
+        let mut sm = StateMachine::<Log>::default();
+        sm.push_many(scenes(&["a", "b"]), &mut log);
+        log.clear();
+
+        let previous = sm.set_stack(
+            scenes(&["x", "y"]),
+            &mut log,
+            InstallPolicy::Start {
+                keep_previous: true,
+            },
+        );
+        assert_eq!(log, ["b:stop", "a:stop", "x:start", "x:pause", "y:start"]);
+        assert_eq!(names(&previous), ["a", "b"]);
+        assert_eq!(sm.active_name(), Some("y"));
+        assert!(sm.is_running());
+
+        log.clear();
+        let previous = sm.set_stack(
+            Vec::new(),
+            &mut log,
+            InstallPolicy::Start {
+                keep_previous: false,
+            },
+        );
+        assert_eq!(log, ["y:stop", "x:stop"]);
+        assert!(previous.is_empty());
+        assert_eq!(sm.exit_reason(), Some(ExitReason::StoppedExternally));
+    }
+
+    #[test]
+    fn adopt_policy() {
+        let mut log = Log::new();
+        let mut old = StateMachine::<Log>::default();
+        old.push_many(scenes(&["x", "y"]), &mut log);
+        let mut sm = StateMachine::<Log>::default();
+        sm.push(Box::new(Scene("a")), &mut log);
+        log.clear();
+
+        let previous = sm.set_stack(
+            old.drain(),
+            &mut log,
+            InstallPolicy::Adopt {
+                keep_previous: true,
+            },
+        );
+        assert!(log.is_empty());
+        assert_eq!(names(&previous), ["a"]);
+        assert_eq!(sm.active_name(), Some("y"));
+
+        sm.pop(&mut log);
+        assert_eq!(log, ["y:stop", "x:resume"]);
+
+        log.clear();
+        let previous = sm.set_stack(
+            scenes(&["z"]),
+            &mut log,
+            InstallPolicy::Adopt {
+                keep_previous: false,
+            },
+        );
+        assert!(previous.is_empty());
+        assert!(log.is_empty());
+        assert_eq!(sm.len(), 1);
+        assert!(sm.is_running());
+    }
+}
@@ -0,0 +1,315 @@
+//! Streaming what the state machine does as JSON lines, with the `instrument-json` feature, for
+//! the tools running outside of the game, like a debugger tailing a log file.
+//!
+//! Each line is a JSON object with these fields:
+//! - `t`: what happened, one of the types below.
+//! - `depth`: the number of states on the stack once it happened.
+//! - `frame`: the number of times the machine was updated, see `StateMachine::frame`.
+//!
+//! Along with the fields of its type:
+//! - `started`: a state was pushed while the machine was not running.
+//! - `push`, with `state`: the state was started and pushed.
+//! - `pause`, with `state`: the state was paused.
+//! - `resume`, with `state`: the state was resumed.
+//! - `pop`, with `state`: the state was stopped and removed from the stack.
+//! - `switch`, with `from` and `to`: the states were switched.
+//! - `stall`, with `state` and `updates`: the active state returned no transition for this many
+//!   updates in a row, see `StateMachine::set_stall_threshold`.
+//! - `dropped`, with `state` and `kind`: a transition returned by the state was dropped for
+//!   another one, see `StateMachine::set_transition_resolution`, the kind being a variant of
+//!   `TransitionKind` or `null` for an error or a message.
+//! - `stopped`, with `reason`: the machine stopped running, the reason being a variant of
+//!   `ExitReason`, like `"Quit"`.
+//! - `transition`, with `kind`, `from` and `to`: a transition was performed, the kind being a
+//!   variant of `TransitionKind`, like `"PopN"`.
+//!
+//! The `state` fields are names of states, see `State::name`, and the `from` and `to` fields are
+//! the names of the active states before and after, or `null` if there was none. For instance:
+//!
+//! ```text
+//! {"t":"push","state":"PauseMenu","depth":3,"frame":1042}
+//! {"t":"transition","kind":"Push","from":"Gameplay","to":"PauseMenu","depth":3,"frame":1042}
+//! ```
+//!
+//! Other types and fields may be added, so the tools should skip the ones they do not know.
+
+use crate::{Allocator, MachineEvent, MaybeSend, ScopedStateMachine, TransitionEvent};
+use serde::Serialize;
+use std::io::Write;
+
+/// Where the lines go.
+enum Sink {
+    Writer(Box<maybe_send!(dyn Write)>),
+    Callback(Box<maybe_send!(dyn FnMut(&str))>),
+}
+
+/// The sink of the lines of a state machine, with the lines it could not write.
+pub(crate) struct Instrument {
+    sink: Sink,
+    dropped: u64,
+}
+
+/// What happened, with the fields of its type.
+#[derive(Serialize)]
+#[serde(tag = "t", rename_all = "snake_case")]
+enum Record<'a> {
+    Started,
+    Push {
+        state: &'a str,
+    },
+    Pause {
+        state: &'a str,
+    },
+    Resume {
+        state: &'a str,
+    },
+    Pop {
+        state: &'a str,
+    },
+    Switch {
+        from: Option<&'a str>,
+        to: Option<&'a str>,
+    },
+    Stall {
+        state: &'a str,
+        updates: u64,
+    },
+    Dropped {
+        state: &'a str,
+        kind: Option<String>,
+    },
+    Stopped {
+        reason: String,
+    },
+    Transition {
+        kind: String,
+        from: Option<&'a str>,
+        to: Option<&'a str>,
+    },
+}
+
+#[derive(Serialize)]
+struct Line<'a> {
+    #[serde(flatten)]
+    record: Record<'a>,
+    depth: usize,
+    frame: u64,
+}
+
+impl<'a, S: ?Sized, E, A: Allocator> ScopedStateMachine<'a, S, E, A> {
+    /// Writes a JSON line for each transition and lifecycle call of the states to this writer,
+    /// see the module documentation for the schema. Replaces the previous writer or callback.
+    ///
+    /// The lines which cannot be written are dropped and counted, see `instrument_dropped`, so
+    /// the machine goes on when the writer fails. Each line is written with a single
+    /// `write_all`, and the writer is not flushed.
+    pub fn set_instrument_writer(&mut self, writer: Box<maybe_send!(dyn Write)>) {
+        self.set_instrument(Sink::Writer(writer));
+    }
+
+    /// Gives a JSON line, without the line break, to this callback for each transition and
+    /// lifecycle call of the states, like `set_instrument_writer`.
+    pub fn set_instrument_callback(&mut self, callback: impl FnMut(&str) + MaybeSend + 'static) {
+        self.set_instrument(Sink::Callback(Box::new(callback)));
+    }
+
+    /// Stops writing the JSON lines, dropping the writer or callback.
+    pub fn clear_instrument(&mut self) {
+        self.instrument = None;
+    }
+
+    /// Returns the number of lines that could not be written since the writer or callback was
+    /// set, or 0 if there is none.
+    pub fn instrument_dropped(&self) -> u64 {
+        self.instrument
+            .as_ref()
+            .map_or(0, |instrument| instrument.dropped)
+    }
+
+    fn set_instrument(&mut self, sink: Sink) {
+        self.track_running();
+        self.instrument = Some(Instrument { sink, dropped: 0 });
+    }
+
+    /// Writes the line of an event of the subscribers.
+    pub(crate) fn instrument_event(&mut self, event: &MachineEvent) {
+        let record = match event {
+            MachineEvent::Started => Record::Started,
+            MachineEvent::StatePushed { name, .. } => Record::Push { state: name },
+            MachineEvent::StatePaused { name } => Record::Pause { state: name },
+            MachineEvent::StateResumed { name } => Record::Resume { state: name },
+            MachineEvent::StatePopped { name } => Record::Pop { state: name },
+            MachineEvent::Switched { from, to } => Record::Switch {
+                from: from.as_deref(),
+                to: to.as_deref(),
+            },
+            MachineEvent::Stalled { name, updates } => Record::Stall {
+                state: name,
+                updates: *updates,
+            },
+            MachineEvent::TransitionDropped { name, kind } => Record::Dropped {
+                state: name,
+                kind: kind.map(|kind| format!("{:?}", kind)),
+            },
+            MachineEvent::Stopped { reason } => Record::Stopped {
+                reason: format!("{:?}", reason),
+            },
+        };
+        self.instrument_record(record);
+    }
+
+    /// Writes the line of a transition told to the observers.
+    pub(crate) fn instrument_transition(&mut self, event: &TransitionEvent) {
+        self.instrument_record(Record::Transition {
+            kind: format!("{:?}", event.kind),
+            from: event.from.as_deref(),
+            to: event.to.as_deref(),
+        });
+    }
+
+    fn instrument_record(&mut self, record: Record<'_>) {
+        let line = Line {
+            record,
+            depth: self.state_stack.len(),
+            frame: self.frame,
+        };
+        let instrument = match &mut self.instrument {
+            Some(instrument) => instrument,
+            None => return,
+        };
+        let written = match serde_json::to_string(&line) {
+            Ok(mut line) => match &mut instrument.sink {
+                Sink::Writer(writer) => {
+                    line.push('\n');
+                    writer.write_all(line.as_bytes()).is_ok()
+                }
+                Sink::Callback(callback) => {
+                    callback(&line);
+                    true
+                }
+            },
+            Err(_) => false,
+        };
+        if !written {
+            instrument.dropped += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use serde_json::Value;
+    use std::io;
+    use std::sync::{Arc, Mutex};
+
+    struct Scene(&'static str, Option<StateTransition<()>>);
+
+    impl State<()> for Scene {
+        fn name(&self) -> &str {
+            self.0
+        }
+
+        fn update(&mut self, _: &mut ()) -> StateTransition<()> {
+            self.1.take().unwrap_or(StateTransition::None)
+        }
+    }
+
+    /// Checks the fields of a line against the schema, and returns its type.
+    fn check_schema(line: &str) -> String {
+        let value: Value = serde_json::from_str(line).unwrap();
+        let object = value.as_object().unwrap();
+        assert!(object["depth"].is_u64(), "{}", line);
+        assert!(object["frame"].is_u64(), "{}", line);
+        let t = object["t"].as_str().unwrap().to_string();
+        let fields: &[&str] = match t.as_str() {
+            "started" => &[],
+            "push" | "pause" | "resume" | "pop" => &["state"],
+            "switch" => &["from", "to"],
+            "stopped" => &["reason"],
+            "transition" => &["kind", "from", "to"],
+            _ => panic!("unknown type in {}", line),
+        };
+        for field in fields {
+            let value = &object[*field];
+            assert!(value.is_string() || value.is_null(), "{}", line);
+        }
+        assert_eq!(object.len(), fields.len() + 3, "{}", line);
+        t
+    }
+
+    #[test]
+    fn scripted_run() {
+        let lines = Arc::new(Mutex::new(Vec::new()));
+        let mut sm = StateMachine::<()>::default();
+        let written = lines.clone();
+        sm.set_instrument_callback(move |line| written.lock().unwrap().push(line.to_string()));
+
+        sm.push(Box::new(Scene("menu", None)), &mut ());
+        sm.queue_transition(StateTransition::Push(Box::new(Scene(
+            "game",
+            Some(StateTransition::Quit),
+        ))));
+        sm.update(&mut ());
+
+        let lines = lines.lock().unwrap();
+        let types: Vec<String> = lines.iter().map(|line| check_schema(line)).collect();
+        assert_eq!(
+            types,
+            [
+                "started",
+                "push",
+                "pause",
+                "push",
+                "transition",
+                "pop",
+                "pop",
+                "transition",
+                "stopped"
+            ]
+        );
+        assert_eq!(
+            lines[1],
+            r#"{"t":"push","state":"menu","depth":1,"frame":0}"#
+        );
+        assert_eq!(
+            lines[4],
+            r#"{"t":"transition","kind":"Push","from":"menu","to":"game","depth":2,"frame":1}"#
+        );
+        assert_eq!(
+            lines[8],
+            r#"{"t":"stopped","reason":"Quit","depth":0,"frame":1}"#
+        );
+        assert_eq!(sm.instrument_dropped(), 0);
+    }
+
+    /// Fails every other write.
+    struct Flaky(bool);
+
+    impl io::Write for Flaky {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0 = !self.0;
+            if self.0 {
+                Err(io::Error::other("disk full"))
+            } else {
+                Ok(buf.len())
+            }
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn write_errors_are_counted() {
+        let mut sm = StateMachine::<()>::default();
+        sm.set_instrument_writer(Box::new(Flaky(false)));
+        sm.push(Box::new(Scene("menu", None)), &mut ());
+        sm.push(Box::new(Scene("game", None)), &mut ());
+        // Started, push, pause and push, every other one failing.
+        assert_eq!(sm.instrument_dropped(), 2);
+        sm.clear_instrument();
+        assert_eq!(sm.instrument_dropped(), 0);
+    }
+}
@@ -0,0 +1,138 @@
+//! Checking that the states get their lifecycle calls in order, with the `debug-invariants`
+//! feature, to catch the code changing the stack that forgets to pause or resume a state.
+//!
+//! A state is started before it is put on the stack and stopped after it is taken off it, so on
+//! the stack it is either active or paused: it is paused from `on_pause` to `on_resume`, only
+//! gets `shadow_update` while paused, and only gets `update` while active. Once the machine is done
+//! with an operation, the states below the visible ones are paused and the visible ones are all
+//! active, or all paused along with the machine. The machine panics as soon as one of these does not hold, naming the state.
+
+use crate::{Allocator, ScopedStateMachine};
+use std::fmt;
+
+/// Where a state of the stack is in its lifecycle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Phase {
+    Active,
+    Paused,
+}
+
+impl Phase {
+    pub(crate) fn of(paused: bool) -> Self {
+        if paused {
+            Phase::Paused
+        } else {
+            Phase::Active
+        }
+    }
+}
+
+impl fmt::Display for Phase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Phase::Active => "active",
+            Phase::Paused => "paused",
+        })
+    }
+}
+
+/// A lifecycle call the machine makes to a state of the stack.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Call {
+    Pause,
+    Resume,
+    Update,
+    ShadowUpdate,
+}
+
+impl Call {
+    /// The phase the state must be in to get the call.
+    fn allowed_in(self) -> Phase {
+        match self {
+            Call::Pause | Call::Update => Phase::Active,
+            Call::Resume | Call::ShadowUpdate => Phase::Paused,
+        }
+    }
+}
+
+impl fmt::Display for Call {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Call::Pause => "on_pause",
+            Call::Resume => "on_resume",
+            Call::Update => "update",
+            Call::ShadowUpdate => "shadow_update",
+        })
+    }
+}
+
+/// Panics if the state with this name cannot get this call, being paused or not.
+pub(crate) fn check_call(paused: bool, call: Call, name: &str) {
+    let phase = Phase::of(paused);
+    if phase != call.allowed_in() {
+        panic!(
+            "Lifecycle violation: {} called on the state {} while it is {}.",
+            call, name, phase
+        );
+    }
+}
+
+impl<'a, S: ?Sized, E, A: Allocator> ScopedStateMachine<'a, S, E, A> {
+    /// Panics if a state below the visible ones is active, or if the visible states are not all
+    /// active or all paused, which they are when the whole machine is paused, like a machine used
+    /// as a state.
+    pub(crate) fn check_invariants(&self) {
+        let visible = self.visible_start();
+        let top = self.state_stack.last().map(|entry| Phase::of(entry.paused));
+        for (index, entry) in self.state_stack.iter().enumerate() {
+            let expected = if index < visible {
+                Some(Phase::Paused)
+            } else {
+                top
+            };
+            let phase = Phase::of(entry.paused);
+            if Some(phase) != expected {
+                panic!(
+                    "Lifecycle violation: the state {} at index {} is {} once the stack \
+                     settled, while the top state is {}.",
+                    entry.state.as_ref().name(),
+                    index,
+                    phase,
+                    top.unwrap_or(phase)
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    struct Level;
+
+    impl State<()> for Level {
+        fn name(&self) -> &str {
+            "level"
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "on_pause called on the state level while it is paused")]
+    fn paused_twice() {
+        let mut sm = StateMachine::<()>::default();
+        sm.push(Box::new(Level), &mut ());
+        sm.push(Box::new(Level), &mut ());
+        sm.pause_at(0, PauseReason::Covered, &mut ());
+    }
+
+    #[test]
+    #[should_panic(expected = "the state level at index 0 is active once the stack settled")]
+    fn covered_and_active() {
+        let mut sm = StateMachine::<()>::default();
+        sm.push(Box::new(Level), &mut ());
+        sm.push(Box::new(Level), &mut ());
+        sm.resume_at(0, None, ResumeReason::Uncovered, &mut ());
+        sm.settle(&mut ());
+    }
+}
@@ -4,43 +4,1280 @@
 //! Only one state can run at once.
 #![deny(missing_docs)]
 
+use std::any::{Any, TypeId};
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fmt;
+use std::iter::FromIterator;
+#[cfg(feature = "catch_unwind")]
+use std::panic::AssertUnwindSafe;
+
+/// Logs a debug record with the `log` feature, and compiles to nothing without it.
+macro_rules! log_debug {
+    ($($arg:tt)*) => {
+        #[cfg(feature = "log")]
+        log::debug!($($arg)*);
+    };
+}
+
+/// Logs a warning record with the `log` feature, and compiles to nothing without it.
+macro_rules! log_warn {
+    ($($arg:tt)*) => {
+        #[cfg(feature = "log")]
+        log::warn!($($arg)*);
+    };
+}
+
+/// Logs a trace record with the `log` feature, and compiles to nothing without it.
+macro_rules! log_trace {
+    ($($arg:tt)*) => {
+        #[cfg(feature = "log")]
+        log::trace!($($arg)*);
+    };
+}
+
+/// Adds `Send` to a trait object type with the `send` feature, see `MaybeSend`.
+#[cfg(feature = "send")]
+macro_rules! maybe_send {
+    (dyn $($bounds:tt)+) => { dyn $($bounds)+ + Send };
+}
+
+#[cfg(not(feature = "send"))]
+macro_rules! maybe_send {
+    (dyn $($bounds:tt)+) => { dyn $($bounds)+ };
+}
+
+/// Runs a callback of a state in a span named after the callback with the `tracing` feature,
+/// and in a profiler scope with the `profiling` feature.
+/// The name of the state is only evaluated when the span or the scope is created.
+///
+/// The profilers need static scope names, so the scope is named after the callback and the name
+/// of the state is given as its data.
+macro_rules! in_span {
+    ($name:literal, $state:expr, $call:expr) => {{
+        #[cfg(feature = "tracing")]
+        let _span = tracing::trace_span!($name, state = $state).entered();
+        #[cfg(feature = "profiling")]
+        profiling::scope!($name, $state);
+        $call
+    }};
+}
+
+/// Keeps the items given to it with the `time` feature, for the code generated by
+/// `#[derive(State)]`.
+#[cfg(feature = "time")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __with_time {
+    ($($item:tt)*) => { $($item)* };
+}
+
+#[cfg(not(feature = "time"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __with_time {
+    ($($item:tt)*) => {};
+}
+
+/// Keeps the items given to it with the `serde` feature, for the code generated by
+/// `#[derive(State)]`.
+#[cfg(feature = "serde")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __with_serde {
+    ($($item:tt)*) => { $($item)* };
+}
+
+#[cfg(not(feature = "serde"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __with_serde {
+    ($($item:tt)*) => {};
+}
+
+/// Keeps the items given to it with the `parallel` feature, for the code generated by
+/// `#[derive(State)]`.
+#[cfg(feature = "parallel")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __with_parallel {
+    ($($item:tt)*) => { $($item)* };
+}
+
+#[cfg(not(feature = "parallel"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __with_parallel {
+    ($($item:tt)*) => {};
+}
+
+mod active;
+pub use active::*;
+mod allocator;
+use allocator::{
+    any_of, any_of_mut, downcastable, from_global, into_global, CustomRunner, StateBox,
+};
+pub use allocator_api2::alloc::{AllocError, Allocator, Global};
+mod array;
+pub use array::*;
+mod associated;
+pub use associated::*;
+#[cfg(feature = "async")]
+mod asynchronous;
+#[cfg(feature = "async")]
+pub use asynchronous::*;
+mod boxed;
+mod budget;
+mod checkpoint;
+pub use checkpoint::*;
+mod cloning;
+pub use cloning::*;
+mod combinators;
+pub use combinators::*;
+#[cfg(feature = "amethyst-compat")]
+pub mod compat;
+#[cfg(feature = "config")]
+mod config;
+#[cfg(feature = "config")]
+pub use config::*;
+mod debugtools;
+pub use debugtools::*;
+mod decorators;
+pub use decorators::*;
+mod duplicate;
+pub use duplicate::*;
+mod effect;
+pub use effect::*;
+mod enum_machine;
+pub use enum_machine::*;
+mod fallback;
+pub use fallback::*;
+#[cfg(feature = "ffi")]
+mod ffi;
+#[cfg(feature = "ffi")]
+pub use ffi::*;
+mod freeze;
+mod functional;
+pub use functional::*;
+#[cfg(feature = "time")]
+mod game_loop;
+#[cfg(feature = "time")]
+pub use game_loop::*;
+#[cfg(feature = "game_clock")]
+mod game_time;
+mod global;
+pub use global::*;
+mod group;
+pub use group::*;
+mod history;
+pub use history::*;
+mod hotswap;
+pub use hotswap::*;
+mod idle;
+pub use idle::*;
+mod inspect;
+pub use inspect::*;
+mod install;
+pub use install::*;
+#[cfg(feature = "instrument-json")]
+mod instrument;
+#[cfg(feature = "debug-invariants")]
+mod invariants;
+mod local;
+mod macros;
+pub use macros::*;
+mod mapped;
+pub use mapped::*;
+mod message;
+pub use message::*;
+mod metrics;
+pub use metrics::*;
+mod observer;
+pub use observer::*;
+mod nested;
+pub use nested::*;
+mod overlay;
+mod parallel;
+pub use parallel::*;
+#[cfg(feature = "planck_ecs")]
+mod planck;
+#[cfg(feature = "planck_ecs")]
+pub use planck::*;
+mod registry;
+pub use registry::*;
+mod reorder;
+pub use reorder::*;
+mod replay;
+pub use replay::*;
+mod requester;
+pub use requester::*;
+mod requirement;
+pub use requirement::*;
+mod resolution;
+pub use resolution::*;
+mod run;
+pub use run::*;
+mod save;
+mod schedule;
+pub use schedule::*;
+#[cfg(feature = "send")]
+mod shared;
+#[cfg(feature = "send")]
+pub use shared::*;
+#[cfg(feature = "serde")]
+mod snapshot;
+#[cfg(feature = "serde")]
+pub use snapshot::*;
+mod split;
+pub use split::*;
+mod stall;
+pub use stall::*;
+mod subscription;
+pub use subscription::*;
+mod substack;
+pub use substack::*;
+mod table;
+pub use table::*;
+#[cfg(any(test, feature = "test-util"))]
+mod testing;
+#[cfg(feature = "winit")]
+mod winit_app;
+/// Implements `State` for an enum by forwarding every method to the state of the current
+/// variant, see the `game_state_machine_derive` crate.
+#[cfg(feature = "derive")]
+pub use game_state_machine_derive::State;
+#[cfg(any(test, feature = "test-util"))]
+pub use testing::*;
+#[cfg(feature = "winit")]
+pub use winit_app::*;
+#[cfg(feature = "time")]
+mod clock;
+#[cfg(feature = "time")]
+pub use clock::*;
+#[cfg(feature = "time")]
+mod telemetry;
+#[cfg(feature = "time")]
+use std::time::Duration;
+#[cfg(feature = "time")]
+pub use telemetry::*;
+
+/// A predicate over a state of the stack, used to select states without knowing their position.
+/// It is only given the states that can be downcast, see `ScopedStateMachine`.
+pub type StatePredicate<S, E = ()> = Box<maybe_send!(dyn Fn(&(dyn State<S, E> + 'static)) -> bool)>;
+
+/// A bound which is `Send` with the `send` feature, and which all the types meet without it.
+/// The states, and the closures, observers and payloads given to a state machine meet it, so
+/// the state machine is `Send` with the feature when its events are.
+#[cfg(feature = "send")]
+pub trait MaybeSend: Send {}
+
+#[cfg(feature = "send")]
+impl<T: Send + ?Sized> MaybeSend for T {}
+
+/// A bound which is `Send` with the `send` feature, and which all the types meet without it.
+/// The states, and the closures, observers and payloads given to a state machine meet it, so
+/// the state machine is `Send` with the feature when its events are.
+#[cfg(not(feature = "send"))]
+pub trait MaybeSend {}
+
+#[cfg(not(feature = "send"))]
+impl<T: ?Sized> MaybeSend for T {}
+
+/// A value given back to a state when the state above it pops, see `StateTransition::PopWith`.
+/// It is `Send` with the `send` feature.
+pub type Payload = Box<maybe_send!(dyn Any)>;
+
+/// The error of a state, see `State::try_update`. It is `Send` and `Sync` with the `send`
+/// feature.
+#[cfg(feature = "send")]
+pub type BoxError = Box<dyn Error + Send + Sync>;
+
+/// The error of a state, see `State::try_update`. It is `Send` and `Sync` with the `send`
+/// feature.
+#[cfg(not(feature = "send"))]
+pub type BoxError = Box<dyn Error>;
+
+/// The state a `StateTransition::PopTo` unwinds the stack to.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum PopTarget {
+    /// The state with this name, as returned by `State::name`.
+    Name(String),
+    /// The state of this type.
+    Type(TypeId),
+}
+
+impl PopTarget {
+    /// Targets the state of type `T`.
+    pub fn of<T: 'static>() -> Self {
+        PopTarget::Type(TypeId::of::<T>())
+    }
+
+    /// Returns if this state is the target.
+    pub fn matches<S: ?Sized, E>(&self, state: &(dyn State<S, E> + 'static)) -> bool {
+        match self {
+            PopTarget::Name(name) => state.name() == name,
+            PopTarget::Type(id) => <dyn AsAny>::as_any(state).type_id() == *id,
+        }
+    }
+}
+
+/// Builds a state when the transition carrying it is performed, with the state data.
+pub type StateConstructor<S, E = ()> = Box<maybe_send!(dyn FnOnce(&mut S) -> Box<dyn State<S, E>>)>;
+
+/// A transition too specific for the other variants of `StateTransition`, running arbitrary
+/// operations on the state machine.
+pub type CustomTransition<S, E = ()> =
+    Box<maybe_send!(dyn FnOnce(&mut StateMachine<S, E>, &mut S))>;
+
+/// The result of a state handling an event.
+pub enum EventOutcome<S: ?Sized, E = ()> {
+    /// The event is passed down to the next state in the stack.
+    Ignored,
+    /// The event stops here and the transition is performed.
+    Consumed(StateTransition<S, E>),
+}
+
 /// A transition from one state to the other.
 /// ## Generics
 /// - S: State data, the data that is sent to states for them to do their operations.
-pub enum StateTransition<S> {
+/// - E: Events, the input that is sent to states through `handle_event`.
+pub enum StateTransition<S: ?Sized, E = ()> {
     /// Stay in the current state.
     None,
     /// End the current state and go to the previous state on the stack, if any.
     /// If we Pop the last state, the state machine exits.
+    /// If the current state pops its group, see `State::pops_group`, the whole group is ended
+    /// like with `PopGroup`.
     Pop,
+    /// End the current state and give this payload to the state below, through its
+    /// `on_resume_with`, to return a value to it, like the character picked in a selection
+    /// screen. If the stack ends up empty, the payload is kept, see
+    /// `StateMachine::take_exit_payload`. If the state below is not paused, because the current
+    /// state is transparent, the payload is dropped.
+    PopWith(Payload),
+    /// End the current state like `Pop`, keeping it for the host to push it again later instead
+    /// of building it anew, see `StateMachine::take_retained`.
+    PopRetain,
+    /// End the given number of states, top-down, in one go.
+    /// Only the state that ends up on top of the stack is resumed.
+    /// If we Pop more states than there are on the stack, the state machine exits.
+    PopN(usize),
+    /// End states from the top of the stack until one matches the predicate, then resume it.
+    /// States are checked top-down, starting with the current state.
+    /// If no state matches, the stack is left untouched.
+    PopUntil(StatePredicate<S, E>),
+    /// End all the states with this tag, top-down, wherever they are on the stack.
+    /// The state that ends up on top of the stack is resumed, if it was covered by one of them.
+    PopTagged(&'static str),
+    /// End states from the top of the stack until the target, then resume it.
+    /// If no state is the target, the stack is left untouched and the states are given a
+    /// `TransitionError::NoMatchingState`, like the errors they return, see `State::on_error`.
+    PopTo(PopTarget),
+    /// End the states of this group, along with the states pushed above it, top-down, then
+    /// resume the state below it, see `StateMachine::pop_group`.
+    /// If the group is not on the stack, the stack is left untouched and the transition is not
+    /// reported as performed.
+    PopGroup(GroupId),
     /// Push a new state on the stack.
-    Push(Box<dyn State<S>>),
+    Push(Box<dyn State<S, E>>),
+    /// Push a state built when the transition is performed, after the current state was paused,
+    /// so an expensive state is not built by the state requesting it.
+    /// If the built state is transparent, the states below are resumed right after it started.
+    PushWith(StateConstructor<S, E>),
+    /// Insert a state just below the current state, which it does not affect.
+    /// The inserted state is started then paused, since it is covered.
+    /// If the stack is empty, this acts like a Push.
+    InsertBelow(Box<dyn State<S, E>>),
+    /// Push these states on the stack in one go, from the bottom to the top.
+    /// Each state but the last is paused right after it is started, since it is covered.
+    PushMany(Vec<Box<dyn State<S, E>>>),
+    /// Insert a state at the bottom of the stack, under all the other states, which it does not
+    /// affect. The inserted state is started then paused, since it is covered.
+    /// If the stack is empty, this acts like a Push.
+    PushBottom(Box<dyn State<S, E>>),
+    /// Push a new instance of the state registered under this name in the state machine's
+    /// `StateRegistry`. If no state is registered under this name, the states are given a
+    /// `TransitionError::UnknownState`, like the errors they return, see `State::on_error`.
+    PushNamed(String),
     /// Pop all states on the stack and insert this one.
-    Switch(Box<dyn State<S>>),
+    /// While the machine is interrupted, only the states of the last interrupt are popped, see
+    /// `StateMachine::interrupt`.
+    Switch(Box<dyn State<S, E>>),
+    /// Switch to a state built when the transition is performed, after the states it replaces
+    /// were stopped with the default `SwitchOrdering`, like `PushWith`.
+    SwitchWith(StateConstructor<S, E>),
+    /// End the current state and insert this one in its place.
+    /// The states below are neither paused nor resumed.
+    /// If the stack is empty, this acts like a Push.
+    Replace(Box<dyn State<S, E>>),
+    /// Stop all the states on the stack, top-down, interrupts included, then push these states
+    /// from the bottom to the top, like loading a whole stack from a save or a level file with
+    /// states built by `StateRegistry::create`.
+    /// Each new state but the last is paused right after it is started, since it is covered.
+    /// An empty list stops the machine like popping the last state, with
+    /// `ExitReason::LastStatePopped`, so the fallback state is started if there is one, see
+    /// `StateMachine::set_fallback`.
+    ReplaceAll(Vec<Box<dyn State<S, E>>>),
     /// Pop all states and exit the state machine.
     Quit,
+    /// Same as `Quit`, keeping this value for the host to tell why the machine exited, like an
+    /// exit to the desktop or a corrupted save, see `StateMachine::take_exit_value`.
+    QuitWith(Payload),
+    /// Deliver this message to the targeted states right away, through their `on_message`,
+    /// without changing the stack, see `StateMachine::send_message`.
+    Message {
+        /// The states the message is delivered to.
+        target: MessageTarget,
+        /// The message, which the states downcast.
+        payload: Payload,
+    },
+    /// Perform the transition once the state returning it was updated this many more times, so
+    /// it keeps running meanwhile, like a `Pop` after the outro animation of the state.
+    /// The transitions the state returns meanwhile are performed as usual and drop the delayed
+    /// one, another `Delayed` taking its place. Removing the state from the stack drops it too.
+    /// When it is not returned by `update`, like from `handle_event`, the updates of the top
+    /// state are counted. A delay of 0 performs the transition right away.
+    Delayed {
+        /// The number of updates of the state to wait for.
+        after_updates: u32,
+        /// The transition performed after them.
+        then: Box<StateTransition<S, E>>,
+    },
+    /// Apply each transition in order, as if they had been returned one after the other.
+    /// A Quit ends the sequence, the transitions following it are ignored.
+    Sequence(Vec<StateTransition<S, E>>),
+    /// Run the closure with the state machine, so it can use its public operations.
+    /// The lifecycle callbacks of the states are called exactly as if the machine performed these
+    /// operations itself.
+    Custom(CustomTransition<S, E>),
+}
+
+/// Shows the variant with the names of the states it holds, and `..` for its closures and
+/// payloads, like `Push("menu")` or `PopWith(..)`.
+impl<S: ?Sized, E> fmt::Debug for StateTransition<S, E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StateTransition::None => f.write_str("None"),
+            StateTransition::Pop => f.write_str("Pop"),
+            StateTransition::PopWith(_) => f.write_str("PopWith(..)"),
+            StateTransition::PopRetain => f.write_str("PopRetain"),
+            StateTransition::PopN(n) => f.debug_tuple("PopN").field(n).finish(),
+            StateTransition::PopUntil(_) => f.write_str("PopUntil(..)"),
+            StateTransition::PopTagged(tag) => f.debug_tuple("PopTagged").field(tag).finish(),
+            StateTransition::PopTo(target) => f.debug_tuple("PopTo").field(target).finish(),
+            StateTransition::PopGroup(group) => f.debug_tuple("PopGroup").field(group).finish(),
+            StateTransition::Push(state) => {
+                f.debug_tuple("Push").field(&state.as_ref().name()).finish()
+            }
+            StateTransition::PushWith(_) => f.write_str("PushWith(..)"),
+            StateTransition::InsertBelow(state) => f
+                .debug_tuple("InsertBelow")
+                .field(&state.as_ref().name())
+                .finish(),
+            StateTransition::PushMany(states) => {
+                let names: Vec<&str> = states.iter().map(|state| state.as_ref().name()).collect();
+                f.debug_tuple("PushMany").field(&names).finish()
+            }
+            StateTransition::PushBottom(state) => f
+                .debug_tuple("PushBottom")
+                .field(&state.as_ref().name())
+                .finish(),
+            StateTransition::PushNamed(name) => f.debug_tuple("PushNamed").field(name).finish(),
+            StateTransition::Switch(state) => f
+                .debug_tuple("Switch")
+                .field(&state.as_ref().name())
+                .finish(),
+            StateTransition::SwitchWith(_) => f.write_str("SwitchWith(..)"),
+            StateTransition::Replace(state) => f
+                .debug_tuple("Replace")
+                .field(&state.as_ref().name())
+                .finish(),
+            StateTransition::ReplaceAll(states) => {
+                let names: Vec<&str> = states.iter().map(|state| state.as_ref().name()).collect();
+                f.debug_tuple("ReplaceAll").field(&names).finish()
+            }
+            StateTransition::Quit => f.write_str("Quit"),
+            StateTransition::QuitWith(_) => f.write_str("QuitWith(..)"),
+            StateTransition::Message { target, .. } => f
+                .debug_struct("Message")
+                .field("target", target)
+                .finish_non_exhaustive(),
+            StateTransition::Delayed {
+                after_updates,
+                then,
+            } => f
+                .debug_struct("Delayed")
+                .field("after_updates", after_updates)
+                .field("then", then)
+                .finish(),
+            StateTransition::Sequence(transitions) => {
+                f.debug_tuple("Sequence").field(transitions).finish()
+            }
+            StateTransition::Custom(_) => f.write_str("Custom(..)"),
+        }
+    }
+}
+
+impl<S: ?Sized, E> StateTransition<S, E> {
+    /// Returns the kind of this transition, or `None` if it does not change the stack.
+    pub fn kind(&self) -> Option<TransitionKind> {
+        match self {
+            StateTransition::None => None,
+            StateTransition::Pop | StateTransition::PopRetain => Some(TransitionKind::Pop),
+            StateTransition::PopWith(_) => Some(TransitionKind::PopWith),
+            StateTransition::PopN(_) => Some(TransitionKind::PopN),
+            StateTransition::PopUntil(_) => Some(TransitionKind::PopUntil),
+            StateTransition::PopTagged(_) => Some(TransitionKind::PopTagged),
+            StateTransition::PopTo(_) => Some(TransitionKind::PopTo),
+            StateTransition::PopGroup(_) => Some(TransitionKind::PopGroup),
+            StateTransition::Push(_)
+            | StateTransition::PushWith(_)
+            | StateTransition::PushNamed(_) => Some(TransitionKind::Push),
+            StateTransition::PushMany(states) if states.is_empty() => None,
+            StateTransition::PushMany(_) => Some(TransitionKind::PushMany),
+            StateTransition::Switch(_) | StateTransition::SwitchWith(_) => {
+                Some(TransitionKind::Switch)
+            }
+            StateTransition::Replace(_) => Some(TransitionKind::Replace),
+            StateTransition::ReplaceAll(_) => Some(TransitionKind::ReplaceAll),
+            StateTransition::InsertBelow(_) => Some(TransitionKind::InsertBelow),
+            StateTransition::PushBottom(_) => Some(TransitionKind::PushBottom),
+            StateTransition::Quit | StateTransition::QuitWith(_) => Some(TransitionKind::Quit),
+            StateTransition::Message { .. } | StateTransition::Delayed { .. } => None,
+            StateTransition::Sequence(_) => Some(TransitionKind::Sequence),
+            StateTransition::Custom(_) => Some(TransitionKind::Custom),
+        }
+    }
+
+    /// Pushes this state, boxing it.
+    /// A state that is already boxed is pushed with `push_boxed` instead, since this would box it
+    /// again. The states are `'static`, like the values downcast with `Any`.
+    pub fn push(state: impl State<S, E> + 'static) -> Self {
+        StateTransition::Push(Box::new(state))
+    }
+
+    /// Switches to this state, boxing it, see `push`.
+    pub fn switch(state: impl State<S, E> + 'static) -> Self {
+        StateTransition::Switch(Box::new(state))
+    }
+
+    /// Pushes a state that is already boxed, without boxing it again.
+    /// `Push(Box::new(boxed))` also works, since a boxed state is a state, but the state is then
+    /// boxed twice and cannot be downcast to its type.
+    pub fn push_boxed(state: Box<dyn State<S, E>>) -> Self {
+        StateTransition::Push(state)
+    }
+
+    /// Builds a `Custom` transition setting the result of the machine, see
+    /// `StateMachine::set_result`.
+    pub fn result<R: Any + MaybeSend>(result: R) -> Self {
+        StateTransition::Custom(Box::new(move |state_machine, _| {
+            state_machine.set_result(result);
+        }))
+    }
+
+    /// Returns the states this transition starts, from the bottom to the top.
+    /// The states built when the transition is performed, like the ones of `PushWith` or
+    /// `PushNamed`, and the ones of a `Sequence` are not known yet, so they are not returned.
+    pub fn states(&self) -> &[Box<dyn State<S, E>>] {
+        match self {
+            StateTransition::Push(state)
+            | StateTransition::InsertBelow(state)
+            | StateTransition::PushBottom(state)
+            | StateTransition::Switch(state)
+            | StateTransition::Replace(state) => std::slice::from_ref(state),
+            StateTransition::PushMany(states) | StateTransition::ReplaceAll(states) => states,
+            _ => &[],
+        }
+    }
+}
+
+/// The kind of a `StateTransition`, without the data it carries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub enum TransitionKind {
+    /// A `StateTransition::Pop`.
+    Pop,
+    /// A `StateTransition::PopWith`.
+    PopWith,
+    /// A `StateTransition::PopN`.
+    PopN,
+    /// A `StateTransition::PopUntil`.
+    PopUntil,
+    /// A `StateTransition::PopTagged`.
+    PopTagged,
+    /// A `StateTransition::PopTo`.
+    PopTo,
+    /// A `StateTransition::PopGroup`.
+    PopGroup,
+    /// A `StateTransition::Push`.
+    Push,
+    /// A `StateTransition::PushMany`.
+    PushMany,
+    /// A `StateTransition::Switch`.
+    Switch,
+    /// A `StateTransition::Replace`.
+    Replace,
+    /// A `StateTransition::ReplaceAll`.
+    ReplaceAll,
+    /// A `StateTransition::InsertBelow`.
+    InsertBelow,
+    /// A `StateTransition::PushBottom`.
+    PushBottom,
+    /// A `StateTransition::Quit`.
+    Quit,
+    /// A `StateTransition::Sequence`.
+    Sequence,
+    /// A `StateTransition::Custom`.
+    Custom,
+    /// The fallback state was started because the stack was left empty, see
+    /// `StateMachine::set_fallback`. It is not a transition the states return.
+    Fallback,
+}
+
+impl TransitionKind {
+    /// All the kinds of transitions the states return, to pick random ones from when fuzzing,
+    /// see `apply_kind`.
+    pub const ALL: [TransitionKind; 17] = [
+        TransitionKind::Pop,
+        TransitionKind::PopWith,
+        TransitionKind::PopN,
+        TransitionKind::PopUntil,
+        TransitionKind::PopTagged,
+        TransitionKind::PopTo,
+        TransitionKind::PopGroup,
+        TransitionKind::Push,
+        TransitionKind::PushMany,
+        TransitionKind::Switch,
+        TransitionKind::Replace,
+        TransitionKind::ReplaceAll,
+        TransitionKind::InsertBelow,
+        TransitionKind::PushBottom,
+        TransitionKind::Quit,
+        TransitionKind::Sequence,
+        TransitionKind::Custom,
+    ];
+}
+
+/// The status of the state machine, returned after it ran the states.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MachineStatus {
+    /// The machine is running and no transition happened.
+    Running,
+    /// The machine is running and this was the last transition that happened.
+    Transitioned(TransitionKind),
+    /// The machine has no running state.
+    Stopped(StopReason),
+    /// The update performed as many transitions as its budget allows, and left these many for
+    /// the next update, see `StateMachine::set_transition_budget`.
+    BudgetExhausted {
+        /// The number of transitions queued for the next update.
+        remaining: usize,
+    },
+}
+
+/// Why the state machine is not running.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum StopReason {
+    /// A state requested to quit.
+    Quit,
+    /// The stack is empty, for example because the last state was popped.
+    EmptyStack,
+    /// The machine was created with an initial state that was not started yet.
+    NotStarted,
+    /// A state failed and no state handled its error, see `StateMachine::take_error`.
+    Error,
+}
+
+/// How the state machine stopped running, see `StateMachine::exit_reason`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ExitReason {
+    /// A state returned `StateTransition::Quit`.
+    Quit,
+    /// The last state was popped or removed.
+    LastStatePopped,
+    /// The machine was stopped by `StateMachine::stop` or `StateMachine::force_stop`.
+    StoppedExternally,
+    /// A state failed and no state handled its error, see `StateMachine::take_error`.
+    Error,
+    /// A state panicked and the panic policy stopped the machine, with the `catch_unwind`
+    /// feature.
+    Panicked,
+}
+
+/// The result of a state handling the error of a state above it.
+pub enum ErrorOutcome<S: ?Sized, E = ()> {
+    /// The error is passed down to the next state in the stack.
+    Unhandled,
+    /// The error stops here and the transition is performed.
+    Handled(StateTransition<S, E>),
+}
+
+/// What a state decides when it is asked to stop, see `State::on_stop_requested`.
+pub enum StopDecision<S: ?Sized, E = ()> {
+    /// The state is stopped.
+    Allow,
+    /// The state is not stopped, and this transition is performed instead.
+    DenyWith(StateTransition<S, E>),
+}
+
+/// Why a state is paused, see `State::on_pause_with_reason`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum PauseReason {
+    /// A state was pushed, inserted or raised over this one, or this one was inserted under
+    /// other states. This includes interrupts, and the states started over this one by a
+    /// `Replace`, or by a `Switch` of the states of an interrupt.
+    Covered,
+    /// `StateMachine::load_stack` restored a state over this one.
+    Loaded,
+    /// The machine was frozen, see `StateMachine::freeze`.
+    MachinePaused,
+}
+
+/// Why a state is resumed, see `State::on_resume_with_reason`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ResumeReason {
+    /// The states covering this one were popped, switched or removed, including the end of an
+    /// interrupt.
+    Uncovered,
+    /// The machine was thawed, see `StateMachine::thaw`.
+    MachineResumed,
+}
+
+/// An operation that could not be performed on the state machine.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum TransitionError {
+    /// The operation needs a state on the stack, but the stack is empty.
+    EmptyStack,
+    /// The state machine is not running and has nothing to run.
+    MachineStopped,
+    /// No state is registered under this name in the `StateRegistry`.
+    UnknownState(String),
+    /// No state on the stack is the target of a `StateTransition::PopTo`.
+    NoMatchingState(PopTarget),
+    /// A state was pushed on a stack that is already full.
+    CapacityExceeded,
+    /// The constructor of a state in the `StateRegistry` rejected its parameters.
+    InvalidParams {
+        /// The name of the state.
+        state: String,
+        /// Why the parameters are invalid.
+        reason: String,
+    },
+    /// The states returned transitions from their lifecycle callbacks that made other states do
+    /// the same, more times in a row than the limit, see `StateMachine::set_max_start_chain`.
+    StartChainTooLong,
+    /// The transition check of the state machine rejected a transition of this state, see
+    /// `StateMachine::set_transition_check`.
+    NotAllowed {
+        /// The name of the active state.
+        from: String,
+        /// The name of the state it tried to go to.
+        to: String,
+    },
+    /// The transition table of the state machine does not allow this state to perform this kind
+    /// of transition, see `TransitionTable::forbid_pop`.
+    KindNotAllowed {
+        /// The name of the active state.
+        from: String,
+        /// The kind of the transition.
+        kind: TransitionKind,
+    },
+    /// A transition would have put more states on the stack than the limit, see
+    /// `StateMachine::set_max_depth`.
+    DepthLimitExceeded {
+        /// The maximum number of states on the stack.
+        limit: usize,
+    },
+    /// The state machine cannot perform this kind of transition, like a
+    /// `StateTransition::Custom` outside of the global heap, see `StateMachine::new_in`.
+    KindNotSupported(TransitionKind),
+    /// No state is at this position of the stack.
+    IndexOutOfRange {
+        /// The position, 0 being the bottom.
+        index: usize,
+        /// The number of states on the stack.
+        len: usize,
+    },
+    /// The state with this handle is not on the stack anymore.
+    StaleHandle(StateHandle),
+    /// A state was put on the stack without a state it needs below it, see
+    /// `State::required_below`.
+    MissingRequirement {
+        /// The name of the state.
+        state: String,
+        /// The type of the state it needs.
+        required: TypeId,
+    },
+}
+
+impl fmt::Display for TransitionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TransitionError::EmptyStack => write!(f, "the state stack is empty"),
+            TransitionError::MachineStopped => write!(f, "the state machine is stopped"),
+            TransitionError::CapacityExceeded => write!(f, "the state stack is full"),
+            TransitionError::UnknownState(name) => write!(f, "no state is named {}", name),
+            TransitionError::NoMatchingState(PopTarget::Name(name)) => {
+                write!(f, "no state named {} is on the stack", name)
+            }
+            TransitionError::NoMatchingState(PopTarget::Type(_)) => {
+                write!(f, "no state of the target type is on the stack")
+            }
+            TransitionError::InvalidParams { state, reason } => {
+                write!(f, "invalid parameters for the state {}: {}", state, reason)
+            }
+            TransitionError::StartChainTooLong => {
+                write!(
+                    f,
+                    "too many transitions returned by lifecycle callbacks in a row"
+                )
+            }
+            TransitionError::NotAllowed { from, to } => {
+                write!(f, "the state {} cannot go to the state {}", from, to)
+            }
+            TransitionError::KindNotSupported(kind) => {
+                write!(
+                    f,
+                    "the state machine cannot perform a {:?} transition",
+                    kind
+                )
+            }
+            TransitionError::KindNotAllowed { from, kind } => {
+                write!(
+                    f,
+                    "the state {} cannot perform a {:?} transition",
+                    from, kind
+                )
+            }
+            TransitionError::DepthLimitExceeded { limit } => {
+                write!(f, "the state stack cannot hold more than {} states", limit)
+            }
+            TransitionError::IndexOutOfRange { index, len } => {
+                write!(
+                    f,
+                    "no state at the index {} of a stack of {} states",
+                    index, len
+                )
+            }
+            TransitionError::StaleHandle(handle) => {
+                write!(f, "the state {:?} is not on the stack anymore", handle)
+            }
+            TransitionError::MissingRequirement { state, .. } => {
+                write!(
+                    f,
+                    "the state {} needs a state below it that is not on the stack",
+                    state
+                )
+            }
+        }
+    }
+}
+
+impl Error for TransitionError {}
+
+/// What the state machine does when a state panics in one of its callbacks.
+///
+/// When panics are caught, the state data is left as the panicking callback left it, so states
+/// must tolerate finding it partially modified.
+#[cfg(feature = "catch_unwind")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PanicPolicy {
+    /// The panic unwinds through the state machine.
+    Propagate,
+    /// The panicking state is removed from the stack without calling it again.
+    /// If it was the top state, the state below it is resumed.
+    RemoveState,
+    /// Like `RemoveState`, then the remaining states are stopped once the current operation of
+    /// the state machine is done.
+    Stop,
+}
+
+/// The order in which a switch or a replace stops the old states and starts the new one, see
+/// `StateMachine::set_switch_ordering`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SwitchOrdering {
+    /// The old states are stopped, then the new state is started.
+    StopThenStart,
+    /// The old states are removed from the stack, then the new state is started, then the old
+    /// states are stopped, top-down. So the new state can start loading while the old one is
+    /// still alive, like for a crossfade.
+    StartThenStop,
+}
+
+/// What the interceptor of a state machine decides to do with a transition.
+pub enum InterceptDecision<S: ?Sized, E = ()> {
+    /// Perform the transition.
+    Allow,
+    /// Drop the transition, as if the state had returned `StateTransition::None`.
+    Block,
+    /// Perform this transition instead.
+    ReplaceWith(StateTransition<S, E>),
+}
+
+/// What the quit handler of a state machine decides to do with a quit, see
+/// `StateMachine::set_quit_handler`.
+pub enum QuitDecision<S: ?Sized, E = ()> {
+    /// Quit.
+    Proceed,
+    /// Do not quit, and perform this transition instead, like pushing a confirmation dialog.
+    InterceptWith(StateTransition<S, E>),
+}
+
+/// Decides if a state machine quits, see `StateMachine::set_quit_handler`.
+pub type QuitHandler<S, E = ()> = Box<maybe_send!(dyn FnMut(&mut S) -> QuitDecision<S, E>)>;
+
+/// Decides what happens to the transitions of a state machine, see
+/// `StateMachine::set_interceptor`.
+pub type TransitionInterceptor<S, E = ()> =
+    Box<maybe_send!(dyn FnMut(&StateTransition<S, E>, &S) -> InterceptDecision<S, E>)>;
+
+/// Checks the transitions of a state machine against the active state, see
+/// `StateMachine::set_transition_check`.
+pub type TransitionCheck<S, E = ()> = Box<
+    maybe_send!(
+        dyn FnMut(
+            Option<&(dyn State<S, E> + 'static)>,
+            &StateTransition<S, E>,
+        ) -> Result<(), TransitionError>
+    ),
+>;
+
+/// Gives access to a state as `Any`, to downcast it to its concrete type.
+/// Implemented for all the types, but only the `'static` ones can be downcast, so the states
+/// borrowing from their environment can still be pushed, see `ScopedStateMachine`.
+///
+/// A `dyn State<S, E>` is `'static` only when `S` and `E` are, so code generic over them
+/// downcasts it as a `dyn AsAny` instead, with `<dyn AsAny>::as_any(state)`.
+pub trait AsAny {
+    /// Returns the value as `Any`.
+    fn as_any(&self) -> &dyn Any
+    where
+        Self: 'static;
+    /// Returns the value as mutable `Any`.
+    fn as_any_mut(&mut self) -> &mut dyn Any
+    where
+        Self: 'static;
+    /// Returns the boxed value as a boxed `Any`.
+    fn into_any(self: Box<Self>) -> Box<dyn Any>
+    where
+        Self: 'static;
+}
+
+impl<T> AsAny for T {
+    fn as_any(&self) -> &dyn Any
+    where
+        Self: 'static,
+    {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any
+    where
+        Self: 'static,
+    {
+        self
+    }
+
+    fn into_any(self: Box<Self>) -> Box<dyn Any>
+    where
+        Self: 'static,
+    {
+        self
+    }
 }
 
 /// Trait that states must implement.
 ///
 /// ## Generics
 /// - S: State data, the data that is sent to states for them to do their operations.
-pub trait State<S> {
+/// - E: Events, the input that is sent to states through `handle_event`.
+pub trait State<S: ?Sized, E = ()>: AsAny + MaybeSend {
+    /// The name of the state, used when displaying the stack.
+    /// Defaults to the name of the type implementing the state.
+    fn name(&self) -> &str {
+        std::any::type_name::<Self>()
+    }
+    /// If the states below this one stay active while it is on top of them, like a small
+    /// notification over the game.
+    /// A transparent state does not pause the state below it, and is updated along with it, see
+    /// `StateMachine::update`.
+    fn is_transparent(&self) -> bool {
+        false
+    }
+    /// The groups the state belongs to, like "ui" or "gameplay", to act on all of them at once.
+    fn tags(&self) -> &[&'static str] {
+        &[]
+    }
+    /// The types of the states that must be on the stack below this one, like a shop below its
+    /// trade dialog. The machine checks them when the state is pushed, inserted or installed,
+    /// and rejects the operation if one is missing, see `set_requirement_policy`.
+    fn required_below(&self) -> &[TypeId] {
+        &[]
+    }
+    /// If popping this state with a `Pop` while it is the active state pops the whole group it
+    /// was pushed in, see `StateMachine::push_group`. Otherwise only this state is popped, and
+    /// the other states of its group stay on the stack.
+    fn pops_group(&self) -> bool {
+        false
+    }
+    /// If a `Switch` or `Replace` to a state of the same type as this one restarts this state
+    /// with `on_restart`, instead of stopping it and starting the other one.
+    fn supports_restart(&self) -> bool {
+        false
+    }
     /// Called when the state is first inserted on the stack.
-    fn on_start(&mut self, _state_data: &mut S) {}
+    /// The returned transition is performed right after the operation that started the state,
+    /// so a state can redirect to another one without being updated, see
+    /// `StateMachine::set_max_start_chain`.
+    fn on_start(&mut self, _state_data: &mut S) -> StateTransition<S, E> {
+        StateTransition::None
+    }
+    /// Fallible version of `on_start`, which is what the state machine calls, for the states
+    /// that can fail to start, like a lobby connecting to a server.
+    /// Returning an error drops this state without pushing it or calling its `on_stop`, the
+    /// states it covered are resumed, and the error is given to the `on_error` of the states
+    /// on the stack, like an error of `try_update`. By default, calls `on_start`.
+    fn try_start(&mut self, state_data: &mut S) -> Result<StateTransition<S, E>, BoxError> {
+        Ok(self.on_start(state_data))
+    }
+    /// Called before the state is stopped by a `Pop`, `PopWith`, `PopN`, `Replace`, `Switch` or
+    /// `Quit` transition, or by `pop`, `switch` or `stop`, to cancel the operation, like an
+    /// editor asking to save its changes first. The states to stop are asked from the top down,
+    /// and the first one denying it cancels the whole operation.
+    /// The other ways of removing states, like `force_stop`, do not ask. See also
+    /// `StateMachine::set_stop_vetoes`.
+    fn on_stop_requested(&mut self, _state_data: &mut S) -> StopDecision<S, E> {
+        StopDecision::Allow
+    }
     /// Called when the state is popped from the stack.
-    fn on_stop(&mut self, _state_data: &mut S) {}
+    /// The returned transition is performed after the operation that stopped the state, like
+    /// the one of `on_start`. When the state is stopped by a `Switch`, it is performed over the
+    /// state switched to. When the machine quits or is stopped, it is ignored.
+    ///
+    /// The state is dropped once the operation or transition that stopped it is done, after the
+    /// states it uncovered were resumed and the transitions returned by their callbacks were
+    /// performed, so the `on_resume` of the state below it runs before its `Drop`.
+    fn on_stop(&mut self, _state_data: &mut S) -> StateTransition<S, E> {
+        StateTransition::None
+    }
+    /// Called on every state of the stack, paused or not, from the bottom up, when the machine
+    /// quits or is stopped, before any of them is stopped. So a state deep in the stack can use
+    /// the state data while the states above it are still alive, like a gameplay state saving the
+    /// game. Not called when the machine stops because the last state was popped, nor on an
+    /// error.
+    fn on_quit(&mut self, _state_data: &mut S) {}
+    /// Called instead of `on_stop`, and of the `on_start` of the incoming state, when a `Switch`
+    /// or a `Replace` replaces this state with a state of the same type, if `supports_restart`
+    /// returns `true`, like retrying a level without reloading what it keeps.
+    /// `incoming` is the state that would have replaced this one, to downcast to this type to
+    /// read its parameters. This state stays on the stack, with its handle.
+    ///
+    /// A switch restarts the state only when it is the only state the switch would stop, and
+    /// the states can still cancel it, see `on_stop_requested`.
+    fn on_restart(&mut self, _incoming: Box<dyn Any>, _state_data: &mut S) {}
     /// Called when a state is pushed over this one in the stack.
     fn on_pause(&mut self, _state_data: &mut S) {}
+    /// Called when the state is paused, with the reason why, which is what the state machine
+    /// calls. By default, calls `on_pause`.
+    fn on_pause_with_reason(&mut self, _reason: PauseReason, state_data: &mut S) {
+        self.on_pause(state_data);
+    }
     /// Called when the state just on top of this one in the stack is popped.
-    fn on_resume(&mut self, _state_data: &mut S) {}
+    /// The returned transition is performed after the operation that resumed the state, like
+    /// the one of `on_start`.
+    fn on_resume(&mut self, _state_data: &mut S) -> StateTransition<S, E> {
+        StateTransition::None
+    }
+    /// Called when the state is resumed, with the reason why, which is what the state machine
+    /// calls. By default, calls `on_resume`.
+    fn on_resume_with_reason(
+        &mut self,
+        _reason: ResumeReason,
+        state_data: &mut S,
+    ) -> StateTransition<S, E> {
+        self.on_resume(state_data)
+    }
+    /// Called instead of `on_resume_with_reason` when the state on top of this one is popped by
+    /// a `StateTransition::PopWith`, with its payload. By default, calls `on_resume_with_reason`
+    /// with `ResumeReason::Uncovered`.
+    fn on_resume_with(&mut self, _payload: Payload, state_data: &mut S) -> StateTransition<S, E> {
+        self.on_resume_with_reason(ResumeReason::Uncovered, state_data)
+    }
     /// Executed on every frame immediately, as fast as the engine will allow.
     /// If you need to execute logic at a predictable interval (for example, a physics engine)
     /// it is suggested to use the state data information to determine when to run such fixed timed
-    /// logic.
-    fn update(&mut self, _state_data: &mut S) -> StateTransition<S> {
+    /// logic, or to use `fixed_update` with the `time` feature.
+    fn update(&mut self, _state_data: &mut S) -> StateTransition<S, E> {
+        StateTransition::None
+    }
+    /// Fallible version of `update`, which is what the state machine calls.
+    /// Returning an error pops this state, and the error is given to the `on_error` of the states
+    /// below. By default, calls `update`.
+    fn try_update(&mut self, state_data: &mut S) -> Result<StateTransition<S, E>, BoxError> {
+        Ok(self.update(state_data))
+    }
+    /// Called right before the state machine updates this state, like to take a snapshot of
+    /// values that `post_update` reconciles, or for a decorator to act around the update of the
+    /// state it wraps. It is not called around `shadow_update` and `fixed_update`.
+    fn pre_update(&mut self, _state_data: &mut S) {}
+    /// Called right after the state machine updated this state, with the transition the update
+    /// returned, before it is given to the interceptor, performed and told to the observers.
+    /// It is not called if the update failed or panicked, nor around `shadow_update` and
+    /// `fixed_update`.
+    fn post_update(&mut self, _state_data: &mut S, _result: &StateTransition<S, E>) {}
+    /// Called when a state above this one failed in `try_update`, after the failing state was
+    /// stopped. States are called from the top down until one handles the error.
+    /// If none does, the state machine stops.
+    fn on_error(&mut self, _error: &dyn Error, _state_data: &mut S) -> ErrorOutcome<S, E> {
+        ErrorOutcome::Unhandled
+    }
+    /// Executed at a fixed interval when the state machine is driven by `update_with_dt`.
+    /// This is where logic that needs a predictable timestep, such as physics, should go.
+    #[cfg(feature = "time")]
+    fn fixed_update(&mut self, _state_data: &mut S) -> StateTransition<S, E> {
+        StateTransition::None
+    }
+    /// Version of `fixed_update` given the value passed to
+    /// `StateMachine::update_with_dt_and_extra`. By default, ignores the value and calls
+    /// `fixed_update`.
+    #[cfg(feature = "time")]
+    fn fixed_update_with_extra(
+        &mut self,
+        _extra: &dyn Any,
+        state_data: &mut S,
+    ) -> StateTransition<S, E> {
+        self.fixed_update(state_data)
+    }
+    /// The minimum time between two `update` or `shadow_update` calls of this state when the state
+    /// machine is driven by `update_with_dt`. `None` updates the state on every frame.
+    /// The time is counted from when the state is started, paused or resumed.
+    #[cfg(feature = "time")]
+    fn update_interval(&self) -> Option<Duration> {
+        None
+    }
+    /// If the time this state spends covered by other states counts as active time, for the
+    /// states which keep running in their `shadow_update`, see `StateMachine::active_time`.
+    #[cfg(feature = "time")]
+    fn counts_covered_time(&self) -> bool {
+        false
+    }
+    /// Executed on every frame while another state is on top of this one in the stack.
+    /// States are shadow updated from the bottom of the stack up, before the top state's update.
+    fn shadow_update(&mut self, _state_data: &mut S) {}
+    /// Executed by `StateMachine::par_shadow_update` while another state is on top of this one,
+    /// in parallel with the other covered states, so it only reads the state data.
+    #[cfg(feature = "parallel")]
+    fn par_shadow_update(&mut self, _state_data: &S) {}
+    /// Executed by `StateMachine::render` to draw the state, paused or not.
+    fn render(&mut self, _state_data: &mut S) {}
+    /// Version of `try_update` given where the state is in the machine, which is what the state
+    /// machine calls. By default, ignores the context and calls `try_update`.
+    fn try_update_with_context(
+        &mut self,
+        _context: &StateContext,
+        state_data: &mut S,
+    ) -> Result<StateTransition<S, E>, BoxError> {
+        self.try_update(state_data)
+    }
+    /// Version of `shadow_update` given where the state is in the machine, which is what the
+    /// state machine calls. By default, ignores the context and calls `shadow_update`.
+    fn shadow_update_with_context(&mut self, _context: &StateContext, state_data: &mut S) {
+        self.shadow_update(state_data)
+    }
+    /// Version of `shadow_update_with_context` returning a transition, which is what the state
+    /// machine calls, for a covered state that needs to change the stack, like a simulation
+    /// ending the game under the pause menu. When several states return a transition in the
+    /// same update, the machine picks one, see `StateMachine::set_transition_resolution`.
+    /// By default, calls `shadow_update_with_context` and returns `StateTransition::None`.
+    fn shadow_update_with_transition(
+        &mut self,
+        context: &StateContext,
+        state_data: &mut S,
+    ) -> StateTransition<S, E> {
+        self.shadow_update_with_context(context, state_data);
         StateTransition::None
     }
+    /// Version of `render` given where the state is in the machine, which is what the state
+    /// machine calls. By default, ignores the context and calls `render`.
+    fn render_with_context(&mut self, _context: &StateContext, state_data: &mut S) {
+        self.render(state_data)
+    }
+    /// Version of `try_update_with_context` given the value passed to
+    /// `StateMachine::update_with_extra`, like the input of the frame, which is what the state
+    /// machine calls when updated with one. The value is downcast to the type the state expects.
+    /// By default, ignores the value and calls `try_update_with_context`.
+    fn try_update_with_extra(
+        &mut self,
+        _extra: &dyn Any,
+        context: &StateContext,
+        state_data: &mut S,
+    ) -> Result<StateTransition<S, E>, BoxError> {
+        self.try_update_with_context(context, state_data)
+    }
+    /// If the states below this one are not rendered, like under a full-screen menu.
+    /// Such states are usually not transparent either.
+    fn blocks_rendering(&self) -> bool {
+        false
+    }
+    /// Returns the serializable form of this state, used to save the stack.
+    /// States that cannot be saved return `None`.
+    #[cfg(feature = "serde")]
+    fn save(&self) -> Option<Box<dyn SavedState>> {
+        None
+    }
+    /// Returns a copy of this state, for `StateMachine::try_clone`, or `None` if it cannot be
+    /// cloned. Usually `Some(Box::new(self.clone()))`.
+    fn clone_state(&self) -> Option<Box<dyn State<S, E>>> {
+        None
+    }
+    /// Returns this state as a `Checkpointable`, for `StateMachine::checkpoint`, if it
+    /// implements it.
+    fn as_checkpointable(&self) -> Option<&dyn Checkpointable<S, E>> {
+        None
+    }
+    /// Returns this state as a mutable `Checkpointable`, for `StateMachine::rollback`, if it
+    /// implements it.
+    fn as_checkpointable_mut(&mut self) -> Option<&mut dyn Checkpointable<S, E>> {
+        None
+    }
+    /// Called by `StateMachine::notify_saving` on every state of the stack, paused or not, so the
+    /// state writes what it keeps into the state data before the host saves the data.
+    fn on_save(&mut self, _state_data: &mut S) {}
+    /// Called by `StateMachine::notify_loaded` on every state of the stack once the host loaded
+    /// the state data, so the state rebuilds what it derives from it, like caches.
+    fn on_load(&mut self, _state_data: &mut S) {}
+    /// Called when an event is sent to the state machine, first on the top state then down the
+    /// stack until a state consumes it.
+    fn handle_event(&mut self, _event: &E, _state_data: &mut S) -> EventOutcome<S, E> {
+        EventOutcome::Ignored
+    }
+    /// Version of `handle_event` given the value passed to `StateMachine::handle_event_with_extra`,
+    /// or to the update handling the queued events. By default, ignores the value and calls
+    /// `handle_event`.
+    fn handle_event_with_extra(
+        &mut self,
+        event: &E,
+        _extra: &dyn Any,
+        state_data: &mut S,
+    ) -> EventOutcome<S, E> {
+        self.handle_event(event, state_data)
+    }
+    /// Called when a message targeting this state is delivered, paused or not, see
+    /// `StateTransition::Message`. The message is downcast to the types the state expects.
+    fn on_message(&mut self, _message: &dyn Any, _state_data: &mut S) -> MessageOutcome {
+        MessageOutcome::Ignored
+    }
+    /// The transitions this state may return, to document the flow of the states, see
+    /// `StateMachine::export_dot`. This is not checked by the state machine.
+    fn declared_transitions(&self) -> Vec<TransitionDecl> {
+        Vec::new()
+    }
 }
 
 /// A state machine that holds the stack of states and performs transitions between states.
@@ -48,120 +1285,5985 @@ pub trait State<S> {
 /// ```rust,ignore
 /// StateMachine::<()>::default()
 /// ```
+/// or with an initial state, which is started by calling `start`
+/// ```rust,ignore
+/// StateMachine::<()>::new(Box::new(MyState))
+/// ```
 /// ## Generics
 /// - S: State data, the data that is sent to states for them to do their operations.
-pub struct StateMachine<S> {
-    state_stack: Vec<Box<dyn State<S>>>,
+///   It may be unsized, like `dyn Any` or a trait of the game.
+/// - E: Events, the input that is sent to states through `handle_event`.
+/// - A: The allocator the stack grows in, the global heap by default, see `new_in`.
+///
+/// The states and the stack are allocated on the global heap, unless the machine is created
+/// with `new_in`. `ArrayStateMachine` does not allocate, its states living outside of it, for
+/// example in a frame arena.
+/// The states are `'static`, since they can be downcast, see `AsAny`. A `ScopedStateMachine`
+/// also takes states borrowing from their environment.
+pub type StateMachine<S, E = (), A = Global> = ScopedStateMachine<'static, S, E, A>;
+
+/// A `StateMachine` whose states may borrow data living for `'a`, like a cache of assets
+/// loaded for a level, see `push_scoped`. It is created with `new_in(Global)`, or `new_in` with
+/// another allocator.
+///
+/// The states borrowing data cannot be downcast: they are not found by their type, like by
+/// `find_state`, `contains_state` or `StateTransition::PopTo`, and they do not match the
+/// predicates of `StateTransition::PopUntil`. The transitions still carry `'static` states,
+/// and a `StateTransition::Custom` fails with `TransitionError::KindNotSupported`.
+///
+/// ```rust
+/// use game_state_machine::*;
+///
+/// struct Assets(Vec<&'static str>);
+///
+/// struct Level<'a>(&'a Assets);
+///
+/// type Loaded = Vec<&'static str>;
+///
+/// impl<'a> State<Loaded> for Level<'a> {
+///     fn on_start(&mut self, loaded: &mut Loaded) -> StateTransition<Loaded> {
+///         loaded.extend(&self.0 .0);
+///         StateTransition::None
+///     }
+/// }
+///
+/// let assets = Assets(vec!["tiles", "music"]);
+/// let mut loaded = Vec::new();
+/// let mut sm = ScopedStateMachine::new_in(Global);
+/// sm.push_scoped(Box::new(Level(&assets)), &mut loaded);
+/// assert_eq!(loaded, ["tiles", "music"]);
+/// assert!(!sm.contains_state::<Level<'static>>());
+/// sm.stop(&mut loaded);
+/// ```
+///
+/// Dropping what the states borrow while the machine still holds them does not compile:
+///
+/// ```rust,compile_fail
+/// use game_state_machine::*;
+///
+/// struct Assets(Vec<&'static str>);
+///
+/// struct Level<'a>(&'a Assets);
+///
+/// impl<'a> State<()> for Level<'a> {}
+///
+/// let mut sm = ScopedStateMachine::new_in(Global);
+/// {
+///     let assets = Assets(vec!["tiles"]);
+///     sm.push_scoped(Box::new(Level(&assets)), &mut ());
+/// }
+/// sm.update(&mut ());
+/// ```
+///
+/// ## Generics
+/// - 'a: How long the data borrowed by the states outlives the state machine.
+/// - S, E and A: see `StateMachine`.
+pub struct ScopedStateMachine<'a, S: ?Sized, E = (), A: Allocator = Global> {
+    alloc: A,
+    state_stack: Stack<'a, S, E, A>,
+    /// The states given to `new` or `extend`, started by `start`.
+    initial_states: Vec<Box<dyn State<S, E>>>,
+    events: VecDeque<E>,
+    queued_transitions: VecDeque<StateTransition<S, E>>,
+    /// The transitions requested through `TransitionRequester`, once one was obtained.
+    requests: Option<requester::Requests<S, E>>,
+    schedule: schedule::Schedule<S, E>,
+    effects: effect::Effects<S, E>,
+    /// The transitions returned by the states started or stopped since they were last performed.
+    follow_ups: Vec<StateTransition<S, E>>,
+    /// The errors of the states that failed to start since they were last reported.
+    failed_starts: Vec<BoxError>,
+    fallback: Option<FallbackFactory<S, E>>,
+    idle_handler: Option<IdleHandler<S, E>>,
+    idle_updates: u64,
+    transition_budget: usize,
+    stall: Option<stall::Stall<S, E>>,
+    requirement_policy: RequirementPolicy,
+    /// The transitions the update may still perform, while it runs.
+    budget_left: Option<usize>,
+    /// The transitions over the budget of the update, performed by the next one.
+    over_budget: VecDeque<StateTransition<S, E>>,
+    /// If the fallback state was started since a state last stayed on the stack.
+    fallback_started: bool,
+    max_start_chain: usize,
+    max_depth: usize,
+    depth_policy: ViolationPolicy,
+    duplicate_policy: DuplicatePolicy,
+    stop_vetoes: bool,
+    leak_check: bool,
+    shrink_on_stop: bool,
+    switch_ordering: SwitchOrdering,
+    active_depth: usize,
+    active_transitions: ActiveTransitions,
+    transition_resolution: TransitionResolution,
+    frozen: bool,
+    error: Option<BoxError>,
+    exit_payload: Option<Payload>,
+    result: Option<Payload>,
+    exit_value: Option<Payload>,
+    retained: Option<StateBox<'a, S, E, A>>,
+    exit_reason: Option<ExitReason>,
+    registry: StateRegistry<S, E>,
+    interceptor: Option<TransitionInterceptor<S, E>>,
+    quit_handler: Option<QuitHandler<S, E>>,
+    transition_check: Option<TransitionCheck<S, E>>,
+    transition_table: Option<TransitionTable>,
+    observers: Observers<S>,
+    global_hooks: Vec<GlobalHook<S, E>>,
+    /// The states stopped by the current operation, dropped once it is done.
+    stopped: allocator_api2::vec::Vec<StateBox<'a, S, E, A>, A>,
+    /// The results returned by the states during the current update, resolved once they all
+    /// ran.
+    results: allocator_api2::vec::Vec<resolution::UpdateResult<S, E>, A>,
+    /// Performs the `StateTransition::Custom`, on the machines on the global heap.
+    custom: Option<CustomRunner<'a, S, E, A>>,
+    subscribers: subscription::Subscribers,
+    #[cfg(feature = "instrument-json")]
+    instrument: Option<instrument::Instrument>,
+    #[cfg(feature = "serde")]
+    migrator: SnapshotMigrator,
+    metrics: StateMachineMetrics,
+    history: Option<History>,
+    overlays: Option<Box<StateMachine<S, E>>>,
+    frame: u64,
+    next_handle: u64,
+    next_group: u64,
+    #[cfg(feature = "time")]
+    telemetry: Telemetry,
+    /// The time read from the clock by the last `update_with_clock`.
+    #[cfg(feature = "time")]
+    last_tick: Option<Duration>,
+    #[cfg(feature = "time")]
+    fixed_timestep: Duration,
+    #[cfg(feature = "time")]
+    max_fixed_steps: usize,
+    #[cfg(feature = "time")]
+    accumulator: Duration,
+    #[cfg(feature = "catch_unwind")]
+    panic_policy: PanicPolicy,
+    #[cfg(feature = "catch_unwind")]
+    panic: Option<Caught>,
+    #[cfg(feature = "catch_unwind")]
+    stop_after_panic: bool,
 }
 
-impl<S> Default for StateMachine<S> {
-    fn default() -> Self {
-        Self {
-            state_stack: Vec::default(),
+/// The storage of the state stack, which keeps the first states inline with the `smallvec`
+/// feature.
+#[cfg(feature = "smallvec")]
+type Stack<'a, S, E, A> = smallvec::SmallVec<[StackEntry<'a, S, E, A>; 8]>;
+
+#[cfg(not(feature = "smallvec"))]
+type Stack<'a, S, E, A> = allocator_api2::vec::Vec<StackEntry<'a, S, E, A>, A>;
+
+/// Identifies a state pushed on a state machine, to find or remove it later.
+/// Each state started by a machine gets a new handle, so the handles of removed states stay
+/// stale.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct StateHandle(u64);
+
+/// Where a state is in the machine, given to the state when it is updated or rendered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub struct StateContext {
+    /// The handle of the state.
+    pub handle: StateHandle,
+    /// The position of the state in the stack, 0 being the bottom.
+    pub index: usize,
+    /// The number of states on the stack.
+    pub depth: usize,
+    /// If the state is the active one, on top of the stack.
+    pub is_active: bool,
+    /// The number of times the state machine was updated, see `StateMachine::frame`.
+    pub frame: u64,
+    /// How long the state was active, see `StateMachine::active_time`.
+    #[cfg(feature = "time")]
+    pub active_time: Duration,
+    /// How long ago the state was started, see `StateMachine::time_since_start`.
+    #[cfg(feature = "time")]
+    pub time_since_start: Duration,
+    /// The time accumulated towards the next fixed update, and the fixed timestep.
+    #[cfg(feature = "time")]
+    fixed_time: (Duration, Duration),
+}
+
+impl StateContext {
+    /// Returns how far the machine is from the last fixed update to the next one, see
+    /// `StateMachine::interpolation_alpha`.
+    pub fn interpolation_alpha(&self) -> f32 {
+        #[cfg(feature = "time")]
+        {
+            interpolation_alpha(self.fixed_time.0, self.fixed_time.1)
+        }
+        #[cfg(not(feature = "time"))]
+        {
+            0.0
         }
     }
 }
 
-impl<S> StateMachine<S> {
-    /// Returns if the state machine still has states in its stack.
-    pub fn is_running(&self) -> bool {
-        !self.state_stack.is_empty()
-    }
+/// Returns the fraction of the fixed timestep accumulated, below 1.
+#[cfg(feature = "time")]
+fn interpolation_alpha(accumulated: Duration, timestep: Duration) -> f32 {
+    let alpha = (accumulated.as_secs_f64() / timestep.as_secs_f64()) as f32;
+    // The largest `f32` below 1, which the division rounds to 1 when it is close enough.
+    alpha.min(1.0 - f32::EPSILON / 2.0)
+}
 
-    /// Updates the state at the top of the stack with the provided data.
-    /// If the states returns a transition, perform it.
-    pub fn update(&mut self, state_data: &mut S) {
-        let trans = match self.state_stack.last_mut() {
-            Some(state) => state.update(state_data),
-            None => StateTransition::None,
-        };
+/// A state on the stack, along with the information the state machine keeps about it.
+struct StackEntry<'a, S: ?Sized, E, A: Allocator> {
+    state: StateBox<'a, S, E, A>,
+    handle: StateHandle,
+    /// If the state was pushed by `StateMachine::interrupt`, or switched to in its place.
+    interrupt: bool,
+    /// If the state was paused and not resumed since.
+    paused: bool,
+    /// The group it was pushed in, see `StateMachine::push_group`.
+    group: Option<GroupId>,
+    /// The values kept for it, see `StateMachine::local_mut`.
+    locals: local::Locals,
+    /// The transition it returned with `StateTransition::Delayed`, with the remaining updates.
+    delayed: Option<(u32, StateTransition<S, E>)>,
+    /// The updates in a row it returned no transition, see `StateMachine::set_stall_threshold`.
+    stalled: u64,
+    /// The frame it was started in, see `StateMachine::frame`. A state started during an update
+    /// is not shadow updated by it.
+    started_frame: u64,
+    #[cfg(feature = "time")]
+    since_update: Duration,
+    /// The time the state was active for, see `StateMachine::active_time`.
+    #[cfg(feature = "time")]
+    active_time: Duration,
+    /// The time since the state was started, see `StateMachine::time_since_start`.
+    #[cfg(feature = "time")]
+    age: Duration,
+}
 
-        self.transition(trans, state_data);
+impl<'a, S: ?Sized, E, A: Allocator> StackEntry<'a, S, E, A> {
+    fn new(state: StateBox<'a, S, E, A>, handle: StateHandle) -> Self {
+        Self {
+            state,
+            handle,
+            interrupt: false,
+            paused: false,
+            group: None,
+            locals: local::Locals::default(),
+            delayed: None,
+            stalled: 0,
+            started_frame: 0,
+            #[cfg(feature = "time")]
+            since_update: Duration::default(),
+            #[cfg(feature = "time")]
+            active_time: Duration::default(),
+            #[cfg(feature = "time")]
+            age: Duration::default(),
+        }
     }
 
-    fn transition(&mut self, request: StateTransition<S>, state_data: &mut S) {
-        match request {
-            StateTransition::None => (),
-            StateTransition::Pop => self.pop(state_data),
-            StateTransition::Push(state) => self.push(state, state_data),
-            StateTransition::Switch(state) => self.switch(state, state_data),
-            StateTransition::Quit => self.stop(state_data),
+    /// Advances the time since the last update and returns if the state should be updated now.
+    #[cfg(feature = "time")]
+    fn tick(&mut self, dt: Duration) -> bool {
+        self.since_update += dt;
+        match self.state.as_ref().update_interval() {
+            Some(interval) if self.since_update < interval => false,
+            _ => {
+                self.since_update = Duration::default();
+                true
+            }
         }
     }
 
-    fn switch(&mut self, mut state: Box<dyn State<S>>, state_data: &mut S) {
-        if let Some(mut state) = self.state_stack.pop() {
-            state.on_stop(state_data)
+    /// Counts an update of the state for its delayed transition, and returns the transition once
+    /// it is due.
+    fn count_down(&mut self) -> Option<StateTransition<S, E>> {
+        let (remaining, _) = self.delayed.as_mut()?;
+        *remaining -= 1;
+        if *remaining == 0 {
+            self.delayed.take().map(|(_, trans)| trans)
+        } else {
+            None
         }
-
-        state.on_start(state_data);
-        self.state_stack.push(state);
     }
 
-    /// Push a state on the stack and start it.
-    /// Pauses any previously active state.
-    pub fn push(&mut self, mut state: Box<dyn State<S>>, state_data: &mut S) {
-        if let Some(state) = self.state_stack.last_mut() {
-            state.on_pause(state_data);
+    fn pause(&mut self, reason: PauseReason, state_data: &mut S) {
+        #[cfg(feature = "debug-invariants")]
+        invariants::check_call(
+            self.paused,
+            invariants::Call::Pause,
+            self.state.as_ref().name(),
+        );
+        self.state.as_mut().on_pause_with_reason(reason, state_data);
+        self.paused = true;
+        self.stalled = 0;
+        #[cfg(feature = "time")]
+        {
+            self.since_update = Duration::default();
         }
-
-        state.on_start(state_data);
-        self.state_stack.push(state);
     }
 
-    fn pop(&mut self, state_data: &mut S) {
-        if let Some(mut state) = self.state_stack.pop() {
-            state.on_stop(state_data);
+    fn resume(
+        &mut self,
+        payload: Option<Payload>,
+        reason: ResumeReason,
+        state_data: &mut S,
+    ) -> StateTransition<S, E> {
+        #[cfg(feature = "debug-invariants")]
+        invariants::check_call(
+            self.paused,
+            invariants::Call::Resume,
+            self.state.as_ref().name(),
+        );
+        let trans = match payload {
+            Some(payload) => self.state.as_mut().on_resume_with(payload, state_data),
+            None => self
+                .state
+                .as_mut()
+                .on_resume_with_reason(reason, state_data),
+        };
+        self.paused = false;
+        self.stalled = 0;
+        #[cfg(feature = "time")]
+        {
+            self.since_update = Duration::default();
         }
+        trans
+    }
+}
 
-        if let Some(state) = self.state_stack.last_mut() {
-            state.on_resume(state_data);
+/// Flags the states that are never stopped, if asked to with `StateMachine::set_leak_check`.
+impl<'a, S: ?Sized, E, A: Allocator> Drop for ScopedStateMachine<'a, S, E, A> {
+    fn drop(&mut self) {
+        if self.leak_check && !std::thread::panicking() {
+            let names: Vec<&str> = self.iter().map(|state| state.name()).collect();
+            debug_assert!(
+                names.is_empty(),
+                "The state machine was dropped with states that were not stopped: {:?}",
+                names
+            );
         }
     }
+}
 
-    /// Removes all currently running states from the stack.
-    pub fn stop(&mut self, state_data: &mut S) {
-        while let Some(mut state) = self.state_stack.pop() {
-            state.on_stop(state_data);
-        }
+impl<'a, S: ?Sized, E, A: Allocator> fmt::Debug for ScopedStateMachine<'a, S, E, A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names: Vec<&str> = self.iter().map(|state| state.name()).collect();
+        f.debug_struct("StateMachine")
+            .field("stack", &names)
+            .finish()
     }
 }
-#[cfg(test)]
-mod tests {
-    use crate::*;
 
-    type StateData = (isize, isize);
+/// Adds initial states, from the bottom up, without starting them, see `StateMachine::start`.
+impl<'a, S: ?Sized, E, A: Allocator> Extend<Box<dyn State<S, E>>>
+    for ScopedStateMachine<'a, S, E, A>
+{
+    fn extend<I: IntoIterator<Item = Box<dyn State<S, E>>>>(&mut self, states: I) {
+        self.initial_states.extend(states);
+    }
+}
 
-    pub struct Test;
+/// Creates a state machine with these initial states, from the bottom up, started by
+/// `StateMachine::start`.
+impl<S: ?Sized, E> FromIterator<Box<dyn State<S, E>>> for StateMachine<S, E> {
+    fn from_iter<I: IntoIterator<Item = Box<dyn State<S, E>>>>(states: I) -> Self {
+        let mut state_machine = Self::default();
+        state_machine.extend(states);
+        state_machine
+    }
+}
 
-    impl State<StateData> for Test {
-        fn on_start(&mut self, data: &mut StateData) {
-            data.0 += data.1;
-        }
+impl<S: ?Sized, E> Default for StateMachine<S, E> {
+    fn default() -> Self {
+        let mut state_machine = Self::new_in(Global);
+        state_machine.custom =
+            Some(|state_machine, custom, state_data| custom(state_machine, state_data));
+        state_machine
+    }
+}
 
-        fn on_resume(&mut self, data: &mut StateData) {
-            self.on_start(data);
+impl<'a, S: ?Sized, E, A: Allocator + Clone> ScopedStateMachine<'a, S, E, A> {
+    fn build(capacity: usize, alloc: A) -> Self {
+        Self {
+            alloc: alloc.clone(),
+            #[cfg(feature = "smallvec")]
+            state_stack: Stack::with_capacity(capacity),
+            #[cfg(not(feature = "smallvec"))]
+            state_stack: Stack::with_capacity_in(capacity, alloc.clone()),
+            initial_states: Vec::new(),
+            events: VecDeque::default(),
+            queued_transitions: VecDeque::default(),
+            requests: None,
+            schedule: schedule::Schedule::default(),
+            effects: effect::Effects::default(),
+            follow_ups: Vec::new(),
+            failed_starts: Vec::new(),
+            fallback: None,
+            idle_handler: None,
+            idle_updates: 0,
+            transition_budget: usize::MAX,
+            stall: None,
+            requirement_policy: RequirementPolicy::Reject,
+            budget_left: None,
+            over_budget: VecDeque::default(),
+            fallback_started: false,
+            max_start_chain: 16,
+            max_depth: usize::MAX,
+            depth_policy: ViolationPolicy::Block,
+            duplicate_policy: DuplicatePolicy::Allow,
+            stop_vetoes: true,
+            leak_check: false,
+            shrink_on_stop: false,
+            switch_ordering: SwitchOrdering::StopThenStart,
+            active_depth: 1,
+            active_transitions: ActiveTransitions::Top,
+            transition_resolution: TransitionResolution::TopWins,
+            frozen: false,
+            error: None,
+            exit_payload: None,
+            result: None,
+            exit_value: None,
+            retained: None,
+            exit_reason: None,
+            registry: StateRegistry::default(),
+            interceptor: None,
+            quit_handler: None,
+            transition_check: None,
+            transition_table: None,
+            observers: Observers::default(),
+            global_hooks: Vec::new(),
+            stopped: allocator_api2::vec::Vec::new_in(alloc.clone()),
+            results: allocator_api2::vec::Vec::new_in(alloc),
+            custom: None,
+            subscribers: subscription::Subscribers::default(),
+            #[cfg(feature = "instrument-json")]
+            instrument: None,
+            #[cfg(feature = "serde")]
+            migrator: SnapshotMigrator::default(),
+            metrics: StateMachineMetrics::default(),
+            history: None,
+            overlays: None,
+            frame: 0,
+            next_handle: 0,
+            next_group: 0,
+            #[cfg(feature = "time")]
+            telemetry: Telemetry::default(),
+            #[cfg(feature = "time")]
+            last_tick: None,
+            #[cfg(feature = "time")]
+            fixed_timestep: Duration::from_secs(1) / 60,
+            #[cfg(feature = "time")]
+            max_fixed_steps: 5,
+            #[cfg(feature = "time")]
+            accumulator: Duration::default(),
+            #[cfg(feature = "catch_unwind")]
+            panic_policy: PanicPolicy::Propagate,
+            #[cfg(feature = "catch_unwind")]
+            panic: None,
+            #[cfg(feature = "catch_unwind")]
+            stop_after_panic: false,
         }
+    }
+}
 
-        fn update(&mut self, _data: &mut StateData) -> StateTransition<StateData> {
-            StateTransition::Push(Box::new(Test))
+impl<S: ?Sized, E> StateMachine<S, E> {
+    /// Creates a state machine with an initial state.
+    /// The state is not started and the machine does not run until `start` is called.
+    pub fn new(initial_state: Box<dyn State<S, E>>) -> Self {
+        let mut state_machine = Self::default();
+        state_machine.initial_states.push(initial_state);
+        state_machine
+    }
+
+    /// Creates a state machine with room for this many states on the stack before it needs to
+    /// reallocate.
+    pub fn with_capacity(capacity: usize) -> Self {
+        let mut state_machine = Self::default();
+        state_machine.state_stack = Stack::with_capacity(capacity);
+        state_machine
+    }
+}
+
+impl<'a, S: ?Sized, E, A: Allocator> ScopedStateMachine<'a, S, E, A> {
+    /// Frees the memory the stack keeps for states that are no longer on it.
+    pub fn shrink_to_fit(&mut self) {
+        self.state_stack.shrink_to_fit();
+    }
+
+    /// Makes room for at least this many more states on the stack, so pushing them does not
+    /// reallocate.
+    pub fn reserve(&mut self, additional: usize) {
+        self.state_stack.reserve(additional);
+    }
+
+    /// Returns how many states the stack can hold before it needs to reallocate.
+    /// The stack keeps its memory when states are popped or switched, until `shrink_to_fit`.
+    pub fn capacity(&self) -> usize {
+        self.state_stack.capacity()
+    }
+
+    /// Sets if the memory of the stack is freed when the machine stops, with `stop`,
+    /// `force_stop` or a `Quit`, like `shrink_to_fit`. Defaults to `false`, keeping it for the
+    /// states pushed next.
+    pub fn set_shrink_on_stop(&mut self, enabled: bool) {
+        self.shrink_on_stop = enabled;
+    }
+
+    /// Starts the initial states given to `new` or `extend`, making the state machine run.
+    /// They are pushed one at a time from the bottom up, over the states already on the stack,
+    /// so each is started then paused by the next one, as with `push`.
+    /// Returns `false` and does nothing if there is no initial state to start, either because the
+    /// machine was created with `default` or because it was already started.
+    pub fn start(&mut self, state_data: &mut S) -> bool {
+        if self.initial_states.is_empty() {
+            return false;
+        }
+        for state in std::mem::take(&mut self.initial_states) {
+            self.push(state, state_data);
         }
+        true
     }
 
-    #[test]
-    fn sm_test() {
-        let mut sm = StateMachine::<StateData>::default();
+    /// Returns if the state machine still has states in its stack.
+    pub fn is_running(&self) -> bool {
+        !self.state_stack.is_empty()
+    }
 
-        let mut state_data = (0, 10);
+    /// Returns the number of states on the stack.
+    pub fn len(&self) -> usize {
+        self.state_stack.len()
+    }
 
-        sm.push(Box::new(Test), &mut state_data);
-        assert!(state_data.0 == 10);
+    /// Returns if there are no states on the stack.
+    pub fn is_empty(&self) -> bool {
+        self.state_stack.is_empty()
+    }
 
-        sm.update(&mut state_data);
-        assert!(state_data.0 == 20);
+    /// Iterates over the states of the stack, from the bottom to the top.
+    pub fn iter(&self) -> impl Iterator<Item = &(dyn State<S, E> + 'a)> + '_ {
+        self.state_stack.iter().map(|entry| entry.state.as_ref())
+    }
 
-        sm.stop(&mut state_data);
+    /// Iterates mutably over the states of the stack, from the bottom to the top.
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut (dyn State<S, E> + 'a)> + '_ {
+        self.state_stack
+            .iter_mut()
+            .map(|entry| entry.state.as_mut() as &mut (dyn State<S, E> + 'a))
+    }
+
+    /// Returns the index in the stack of the active state, the one on top, if any.
+    pub fn active_index(&self) -> Option<usize> {
+        self.state_stack.len().checked_sub(1)
+    }
+
+    /// Returns the active state, the one on top of the stack, if any.
+    pub fn active(&self) -> Option<&(dyn State<S, E> + 'a)> {
+        self.state_stack.last().map(|entry| entry.state.as_ref())
+    }
+
+    /// Returns the active state mutably, for example to call the methods of an extension trait
+    /// of `State`.
+    pub fn active_mut(&mut self) -> Option<&mut (dyn State<S, E> + 'a)> {
+        self.state_stack
+            .last_mut()
+            .map(|entry| entry.state.as_mut() as &mut (dyn State<S, E> + 'a))
+    }
+
+    /// Returns the name of the active state, if any.
+    pub fn active_name(&self) -> Option<&str> {
+        self.active().map(|state| state.name())
+    }
+
+    /// Returns the active state if it is a `T`.
+    pub fn active_state_as<T: 'static>(&self) -> Option<&T> {
+        any_of(&self.state_stack.last()?.state)?.downcast_ref()
+    }
+
+    /// Returns the active state mutably if it is a `T`.
+    pub fn active_state_as_mut<T: 'static>(&mut self) -> Option<&mut T> {
+        any_of_mut(&mut self.state_stack.last_mut()?.state)?.downcast_mut()
+    }
+
+    /// Returns if there is a `T` anywhere on the stack.
+    pub fn contains_state<T: 'static>(&self) -> bool {
+        self.position_of::<T>().is_some()
+    }
+
+    /// Returns the index of the topmost `T` on the stack, from the bottom like `active_index`.
+    /// The number of states above it, to give to `StateTransition::PopN`, is
+    /// `len() - 1 - index`.
+    pub fn position_of<T: 'static>(&self) -> Option<usize> {
+        self.state_stack
+            .iter()
+            .rposition(|entry| any_of(&entry.state).is_some_and(|state| state.is::<T>()))
+    }
+
+    /// Returns the topmost `T` on the stack, even if it is paused.
+    pub fn find_state<T: 'static>(&self) -> Option<&T> {
+        let index = self.position_of::<T>()?;
+        any_of(&self.state_stack[index].state)?.downcast_ref()
+    }
+
+    /// Returns the topmost `T` on the stack mutably, even if it is paused.
+    pub fn find_state_mut<T: 'static>(&mut self) -> Option<&mut T> {
+        let index = self.position_of::<T>()?;
+        any_of_mut(&mut self.state_stack[index].state)?.downcast_mut()
+    }
+
+    /// Queues an event to be handled during the next update.
+    pub fn push_event(&mut self, event: E) {
+        self.events.push_back(event);
+    }
+
+    /// Returns the events queued for the next update, oldest first.
+    pub fn pending_events(&self) -> &VecDeque<E> {
+        &self.events
+    }
+
+    /// Discards all the events queued for the next update.
+    pub fn clear_events(&mut self) {
+        self.events.clear();
+    }
+
+    /// Queues a transition to be performed at the start of the next update, before the states
+    /// are updated. Transitions are performed in the order they were queued.
+    /// If one of them quits, the following ones are dropped.
+    pub fn queue_transition(&mut self, transition: StateTransition<S, E>) {
+        self.queued_transitions.push_back(transition);
+    }
+
+    /// Returns the number of transitions queued for the next update.
+    pub fn pending_transitions(&self) -> usize {
+        self.queued_transitions.len()
+    }
+
+    fn perform_queued(&mut self, state_data: &mut S) -> Option<TransitionKind> {
+        #[cfg(feature = "time")]
+        self.remove_overruns(state_data);
+        self.receive_requests();
+        self.receive_scheduled();
+        let mut last = None;
+        while let Some(trans) = self.queued_transitions.pop_front() {
+            last = self.perform(trans, state_data).or(last);
+            if last == Some(TransitionKind::Quit) {
+                self.queued_transitions.clear();
+            }
+        }
+        last
+    }
+
+    /// Performs the queued transitions, then calls the global hooks, see `add_global_hook`.
+    /// Then handles the queued events in the order they were pushed.
+    /// If handling an event causes a transition, the following events are sent to the new stack.
+    /// Then shadow updates the states covered by the top of the stack, from the bottom up,
+    /// but the ones pushed during this update, and updates the state at the top of the stack
+    /// with the provided data. If the top states are transparent, they are updated from the bottom up along with the
+    /// first state below them. If the states return transitions, perform one of them, the
+    /// topmost one by default, see `set_transition_resolution`.
+    ///
+    /// The update intervals of the states are ignored, see `update_with_dt`.
+    ///
+    /// Then updates the overlays, see `push_overlay`.
+    ///
+    /// Does nothing while the machine is frozen, see `freeze`.
+    ///
+    /// Returns the status of the state machine after the update, with the last transition that
+    /// was performed if any. If the update performed more transitions than its budget, the
+    /// others are left for the next update, see `set_transition_budget`.
+    pub fn update(&mut self, state_data: &mut S) -> MachineStatus {
+        self.update_extra(None, state_data)
+    }
+
+    /// Same as `update`, but gives a value to the states along with the state data, like the
+    /// input of the frame, so it does not have to live in the state data.
+    /// The states being updated and handling the queued events get it, see
+    /// `State::try_update_with_extra` and `State::handle_event_with_extra`, as do the overlays.
+    pub fn update_with_extra(&mut self, extra: &dyn Any, state_data: &mut S) -> MachineStatus {
+        self.update_extra(Some(extra), state_data)
+    }
+
+    fn update_extra(&mut self, extra: Option<&dyn Any>, state_data: &mut S) -> MachineStatus {
+        if self.frozen {
+            return self.status(None);
+        }
+        self.frame += 1;
+        self.budget_left = Some(self.transition_budget);
+        let last = self.perform_queued(state_data);
+        let last = self.run_idle(state_data).or(last);
+        let status = match self.run_global_hooks(state_data) {
+            Some(hooked) => self.status(hooked.or(last)),
+            None => self.update_states(state_data, extra, last, |_| true),
+        };
+        let status = self.carry_over(status);
+        if let Some(overlays) = &mut self.overlays {
+            overlays.update_extra(extra, state_data);
+        }
+        status
+    }
+
+    /// Renders the states from the bottom of the stack up, so the top one is drawn last, starting
+    /// from the topmost state that blocks rendering. Then renders the overlays the same way.
+    /// Rendering does not perform transitions.
+    pub fn render(&mut self, state_data: &mut S) {
+        let catch = self.catches_panics();
+        let mut index = self
+            .state_stack
+            .iter()
+            .rposition(|entry| entry.state.as_ref().blocks_rendering())
+            .unwrap_or(0);
+        while index < self.state_stack.len() {
+            let context = self.context(index);
+            let entry = &mut self.state_stack[index];
+            if let Err(caught) = guard(catch, || {
+                in_span!(
+                    "state.render",
+                    entry.state.as_ref().name(),
+                    entry
+                        .state
+                        .as_mut()
+                        .render_with_context(&context, state_data)
+                )
+            }) {
+                if index + 1 == self.state_stack.len() {
+                    self.drop_top(caught, state_data);
+                } else {
+                    self.state_stack.remove(index);
+                    self.caught(caught);
+                }
+                continue;
+            }
+            index += 1;
+        }
+        self.settle(state_data);
+        if let Some(overlays) = &mut self.overlays {
+            overlays.render(state_data);
+        }
+    }
+
+    /// Same as `update`, but fails instead of doing nothing when the machine is not running and
+    /// has no queued transition that could make it run again.
+    pub fn try_update(&mut self, state_data: &mut S) -> Result<MachineStatus, TransitionError> {
+        if !self.is_running() && self.queued_transitions.is_empty() && !self.has_requests() {
+            return Err(TransitionError::MachineStopped);
+        }
+        Ok(self.update(state_data))
+    }
+
+    fn update_states(
+        &mut self,
+        state_data: &mut S,
+        extra: Option<&dyn Any>,
+        mut last: Option<TransitionKind>,
+        mut should_update: impl FnMut(&mut StackEntry<'a, S, E, A>) -> bool,
+    ) -> MachineStatus {
+        while let Some(event) = self.events.pop_front() {
+            last = self
+                .dispatch_event(&event, extra, state_data)
+                .flatten()
+                .or(last);
+        }
+
+        let catch = self.catches_panics();
+        let mut visible = self.visible_start();
+        let mut index = 0;
+        while index < visible {
+            let context = self.context(index);
+            let entry = &mut self.state_stack[index];
+            // The states pushed by this update, like by a queued transition, are covered since
+            // this frame.
+            if entry.started_frame != self.frame && should_update(entry) {
+                #[cfg(feature = "debug-invariants")]
+                invariants::check_call(
+                    entry.paused,
+                    invariants::Call::ShadowUpdate,
+                    entry.state.as_ref().name(),
+                );
+                let updated = guard(catch, || {
+                    in_span!(
+                        "state.shadow_update",
+                        entry.state.as_ref().name(),
+                        entry
+                            .state
+                            .as_mut()
+                            .shadow_update_with_transition(&context, state_data)
+                    )
+                });
+                match updated {
+                    Ok(StateTransition::None) => (),
+                    // Counted down once the state is updated again.
+                    Ok(StateTransition::Delayed {
+                        after_updates,
+                        then,
+                    }) if after_updates > 0 => entry.delayed = Some((after_updates, *then)),
+                    Ok(StateTransition::Delayed { then, .. }) => {
+                        entry.delayed = None;
+                        self.results.push((index, Ok(*then)));
+                    }
+                    Ok(trans) => {
+                        entry.delayed = None;
+                        self.results.push((index, Ok(trans)));
+                    }
+                    Err(caught) => {
+                        self.state_stack.remove(index);
+                        self.caught(caught);
+                        visible -= 1;
+                        continue;
+                    }
+                }
+            }
+            index += 1;
+        }
+
+        // The visible states are updated bottom-up, among the states that can return
+        // transitions, see `set_active_transitions`, then one result is picked, see
+        // `set_transition_resolution`.
+        let mut stalling = false;
+        let mut index = visible;
+        while index < self.state_stack.len() {
+            let context = self.context(index);
+            let ignored = self.active_depth > 1 && index < self.transitions_start();
+            let top = index + 1 == self.state_stack.len() && self.detects_stalls();
+            let entry = &mut self.state_stack[index];
+            if should_update(entry) {
+                #[cfg(feature = "debug-invariants")]
+                invariants::check_call(
+                    entry.paused,
+                    invariants::Call::Update,
+                    entry.state.as_ref().name(),
+                );
+                self.metrics.record_update(entry.state.as_ref().name());
+                #[cfg(feature = "time")]
+                let start = self.telemetry.start_update();
+                let updated = guard(catch, || {
+                    entry.state.as_mut().pre_update(state_data);
+                    let updated = in_span!(
+                        "state.update",
+                        entry.state.as_ref().name(),
+                        match extra {
+                            Some(extra) => entry
+                                .state
+                                .as_mut()
+                                .try_update_with_extra(extra, &context, state_data),
+                            None => entry
+                                .state
+                                .as_mut()
+                                .try_update_with_context(&context, state_data),
+                        }
+                    );
+                    if let Ok(trans) = &updated {
+                        entry.state.as_mut().post_update(state_data, trans);
+                    }
+                    updated
+                });
+                #[cfg(feature = "time")]
+                self.telemetry.record_update(
+                    start,
+                    entry.handle,
+                    entry.state.as_ref().name(),
+                    LifecyclePhase::Update,
+                );
+                if top {
+                    stalling = matches!(updated, Ok(Ok(StateTransition::None)));
+                    entry.stalled = if stalling { entry.stalled + 1 } else { 0 };
+                }
+                match updated {
+                    Ok(Ok(_)) if ignored => (),
+                    Ok(Ok(StateTransition::None)) => {
+                        if let Some(trans) = entry.count_down() {
+                            self.results.push((index, Ok(trans)));
+                        }
+                    }
+                    Ok(Ok(StateTransition::Delayed {
+                        after_updates,
+                        then,
+                    })) if after_updates > 0 => entry.delayed = Some((after_updates, *then)),
+                    Ok(Ok(StateTransition::Delayed { then, .. })) => {
+                        entry.delayed = None;
+                        self.results.push((index, Ok(*then)));
+                    }
+                    Ok(result) => {
+                        entry.delayed = None;
+                        self.results.push((index, result));
+                    }
+                    Err(caught) => {
+                        if index + 1 == self.state_stack.len() {
+                            self.drop_top(caught, state_data);
+                        } else {
+                            self.state_stack.remove(index);
+                            self.caught(caught);
+                        }
+                        continue;
+                    }
+                }
+            }
+            index += 1;
+        }
+        last = match self.resolve_results() {
+            Some((_, Ok(trans))) => self.perform(trans, state_data),
+            Some((index, Err(error))) => self.fail(index, error, state_data),
+            None if stalling => self.check_stall(state_data),
+            None => None,
+        }
+        .or(last);
+        self.settle(state_data);
+        self.status(last)
+    }
+
+    fn status(&self, last: Option<TransitionKind>) -> MachineStatus {
+        if self.is_running() {
+            match last {
+                Some(kind) => MachineStatus::Transitioned(kind),
+                None => MachineStatus::Running,
+            }
+        } else if !self.initial_states.is_empty() {
+            MachineStatus::Stopped(StopReason::NotStarted)
+        } else if self.error.is_some() {
+            MachineStatus::Stopped(StopReason::Error)
+        } else if last == Some(TransitionKind::Quit) {
+            MachineStatus::Stopped(StopReason::Quit)
+        } else {
+            MachineStatus::Stopped(StopReason::EmptyStack)
+        }
+    }
+
+    /// Sets the interval at which `fixed_update` is called by `update_with_dt`.
+    /// Defaults to 1/60th of a second.
+    ///
+    /// ## Panics
+    /// Panics if the timestep is zero.
+    #[cfg(feature = "time")]
+    pub fn set_fixed_timestep(&mut self, timestep: Duration) {
+        assert!(
+            timestep > Duration::default(),
+            "The fixed timestep must not be zero."
+        );
+        self.fixed_timestep = timestep;
+    }
+
+    /// Sets the maximum number of `fixed_update` calls done by a single `update_with_dt`.
+    /// When a frame takes too long, the time that could not be simulated is dropped instead of
+    /// making the next frames even longer. Defaults to 5.
+    #[cfg(feature = "time")]
+    pub fn set_max_fixed_steps(&mut self, max_steps: usize) {
+        self.max_fixed_steps = max_steps;
+    }
+
+    /// Returns how far the machine is from the last fixed update to the next one, as the fraction
+    /// of the fixed timestep accumulated by `update_with_dt` and not simulated yet, in `[0, 1)`.
+    /// The states render in between their last two fixed updates with it, like
+    /// `previous + (current - previous) * alpha`, see `StateContext::interpolation_alpha`.
+    ///
+    /// It is 0 until `update_with_dt` is called, and always without the `time` feature.
+    pub fn interpolation_alpha(&self) -> f32 {
+        #[cfg(feature = "time")]
+        {
+            interpolation_alpha(self.accumulator, self.fixed_timestep)
+        }
+        #[cfg(not(feature = "time"))]
+        {
+            0.0
+        }
+    }
+
+    /// Performs the queued transitions, then accumulates the time elapsed since the last frame and
+    /// calls `fixed_update` on the top state once for every fixed timestep that fits in it,
+    /// performing the returned transitions right away. Then does a regular `update`, skipping the
+    /// `update` and `shadow_update` of the states whose `update_interval` has not elapsed yet.
+    ///
+    /// The time elapsed is added to the states on the stack after the queued transitions, see
+    /// `active_time`.
+    ///
+    /// Does nothing while the machine is frozen, not even counting the time elapsed.
+    ///
+    /// Returns the status of the state machine after the update, like `update`.
+    #[cfg(feature = "time")]
+    pub fn update_with_dt(&mut self, dt: Duration, state_data: &mut S) -> MachineStatus {
+        self.update_with_dt_extra(dt, None, state_data)
+    }
+
+    /// Same as `update_with_dt`, but gives a value to the states along with the state data, like
+    /// `update_with_extra`. The fixed updates of the top state get it too, see
+    /// `State::fixed_update_with_extra`.
+    #[cfg(feature = "time")]
+    pub fn update_with_dt_and_extra(
+        &mut self,
+        dt: Duration,
+        extra: &dyn Any,
+        state_data: &mut S,
+    ) -> MachineStatus {
+        self.update_with_dt_extra(dt, Some(extra), state_data)
+    }
+
+    #[cfg(feature = "time")]
+    fn update_with_dt_extra(
+        &mut self,
+        dt: Duration,
+        extra: Option<&dyn Any>,
+        state_data: &mut S,
+    ) -> MachineStatus {
+        if self.frozen {
+            return self.status(None);
+        }
+        self.frame += 1;
+        self.budget_left = Some(self.transition_budget);
+        self.schedule.advance(dt);
+        let mut last = self.perform_queued(state_data);
+        last = self.run_idle(state_data).or(last);
+        let hooked = self.run_global_hooks(state_data);
+        self.advance_clocks(dt);
+        self.accumulator += dt;
+
+        let mut steps = 0;
+        while self.accumulator >= self.fixed_timestep && steps < self.max_fixed_steps {
+            self.accumulator -= self.fixed_timestep;
+            steps += 1;
+
+            last = self.fixed_update_top(extra, state_data).or(last);
+        }
+
+        if self.accumulator >= self.fixed_timestep {
+            let remainder = self.accumulator.as_nanos() % self.fixed_timestep.as_nanos();
+            self.accumulator = Duration::from_nanos(remainder as u64);
+        }
+
+        let status = match hooked {
+            Some(hooked) => self.status(hooked.or(last)),
+            None => self.update_states(state_data, extra, last, |entry| entry.tick(dt)),
+        };
+        let status = self.carry_over(status);
+        if let Some(overlays) = &mut self.overlays {
+            overlays.update_with_dt_extra(dt, extra, state_data);
+        }
+        status
+    }
+
+    /// Calls `fixed_update` on the top state and performs the transition it returns.
+    #[cfg(feature = "time")]
+    fn fixed_update_top(
+        &mut self,
+        extra: Option<&dyn Any>,
+        state_data: &mut S,
+    ) -> Option<TransitionKind> {
+        let catch = self.catches_panics();
+        let entry = self.state_stack.last_mut()?;
+        let start = self.telemetry.start_update();
+        let fixed_updated = guard(catch, || {
+            in_span!(
+                "state.fixed_update",
+                entry.state.as_ref().name(),
+                match extra {
+                    Some(extra) => entry
+                        .state
+                        .as_mut()
+                        .fixed_update_with_extra(extra, state_data),
+                    None => entry.state.as_mut().fixed_update(state_data),
+                }
+            )
+        });
+        self.telemetry.record_update(
+            start,
+            entry.handle,
+            entry.state.as_ref().name(),
+            LifecyclePhase::FixedUpdate,
+        );
+        match fixed_updated {
+            Ok(trans) => self.perform(trans, state_data),
+            Err(caught) => {
+                self.drop_top(caught, state_data);
+                None
+            }
+        }
+    }
+
+    /// Sends an event to the states of the stack, from the top down, until one consumes it.
+    /// The transition returned by the consuming state is performed on the stack as if it was
+    /// returned by the update of the top state.
+    ///
+    /// Returns the status of the state machine after the event was handled, like `update`.
+    pub fn handle_event(&mut self, event: &E, state_data: &mut S) -> MachineStatus {
+        let last = self.dispatch_event(event, None, state_data).flatten();
+        self.settle(state_data);
+        self.status(last)
+    }
+
+    /// Same as `handle_event`, but gives a value to the states along with the state data, like
+    /// `update_with_extra`, see `State::handle_event_with_extra`.
+    pub fn handle_event_with_extra(
+        &mut self,
+        event: &E,
+        extra: &dyn Any,
+        state_data: &mut S,
+    ) -> MachineStatus {
+        let last = self
+            .dispatch_event(event, Some(extra), state_data)
+            .flatten();
+        self.settle(state_data);
+        self.status(last)
+    }
+
+    /// Returns `None` if no state consumed the event, and else the transition performed, if any.
+    /// A state that panics while handling an event is removed from the stack and the event is
+    /// dropped, as if it consumed it.
+    fn dispatch_event(
+        &mut self,
+        event: &E,
+        extra: Option<&dyn Any>,
+        state_data: &mut S,
+    ) -> Option<Option<TransitionKind>> {
+        let catch = self.catches_panics();
+        for index in (0..self.state_stack.len()).rev() {
+            let entry = &mut self.state_stack[index];
+            match guard(catch, || {
+                in_span!(
+                    "state.handle_event",
+                    entry.state.as_ref().name(),
+                    match extra {
+                        Some(extra) => entry
+                            .state
+                            .as_mut()
+                            .handle_event_with_extra(event, extra, state_data),
+                        None => entry.state.as_mut().handle_event(event, state_data),
+                    }
+                )
+            }) {
+                Ok(EventOutcome::Ignored) => (),
+                Ok(EventOutcome::Consumed(trans)) => return Some(self.perform(trans, state_data)),
+                Err(caught) => {
+                    if index + 1 == self.state_stack.len() {
+                        self.drop_top(caught, state_data);
+                    } else {
+                        self.state_stack.remove(index);
+                        self.caught(caught);
+                    }
+                    return Some(None);
+                }
+            }
+        }
+        None
+    }
+
+    /// Removes the state at this index after it failed, then gives the error to the states below
+    /// it, from the top down, until one handles it. If none does, the machine is stopped and
+    /// keeps the error.
+    fn fail(
+        &mut self,
+        failed: usize,
+        error: BoxError,
+        state_data: &mut S,
+    ) -> Option<TransitionKind> {
+        if failed + 1 == self.state_stack.len() {
+            self.pop_state(state_data);
+        } else {
+            let entry = self.state_stack.remove(failed);
+            self.stop_entry(entry, state_data);
+            self.resume_visible(state_data);
+        }
+
+        let below = failed.min(self.state_stack.len());
+        self.report(below, error, state_data)
+            .or(Some(TransitionKind::Pop))
+    }
+
+    /// Gives the error to the states below this index, from the top down, until one handles it,
+    /// and performs the transition it returns. If none does, the machine is stopped and keeps
+    /// the error.
+    fn report(
+        &mut self,
+        below: usize,
+        error: BoxError,
+        state_data: &mut S,
+    ) -> Option<TransitionKind> {
+        let catch = self.catches_panics();
+        let mut index = below;
+        while index > 0 {
+            index -= 1;
+            let entry = &mut self.state_stack[index];
+            log_trace!("on_error: {}", entry.state.as_ref().name());
+            match guard(catch, || {
+                in_span!(
+                    "state.on_error",
+                    entry.state.as_ref().name(),
+                    entry.state.as_mut().on_error(error.as_ref(), state_data)
+                )
+            }) {
+                Ok(ErrorOutcome::Unhandled) => (),
+                Ok(ErrorOutcome::Handled(trans)) => return self.transition(trans, state_data),
+                Err(caught) => {
+                    if index + 1 == self.state_stack.len() {
+                        self.drop_top(caught, state_data);
+                    } else {
+                        self.state_stack.remove(index);
+                        self.caught(caught);
+                    }
+                    index = index.min(self.state_stack.len());
+                }
+            }
+        }
+
+        self.quit_states(ExitReason::Error, state_data);
+        self.error = Some(error);
+        None
+    }
+
+    /// Takes the error that stopped the state machine because no state handled it, if any.
+    pub fn take_error(&mut self) -> Option<BoxError> {
+        self.error.take()
+    }
+
+    /// Sets the interceptor that is given every transition before it is performed, and decides
+    /// if it is performed, dropped, or replaced by another transition.
+    /// The transitions of a `Sequence` are given to the interceptor one by one, after the
+    /// `Sequence` itself. The operations done by `Custom` transitions are not intercepted.
+    pub fn set_interceptor(
+        &mut self,
+        interceptor: impl FnMut(&StateTransition<S, E>, &S) -> InterceptDecision<S, E>
+            + MaybeSend
+            + 'static,
+    ) {
+        self.interceptor = Some(Box::new(interceptor));
+    }
+
+    /// Removes the interceptor, so all transitions are performed.
+    pub fn clear_interceptor(&mut self) {
+        self.interceptor = None;
+    }
+
+    /// Sets the handler that is asked before a `Quit` or `QuitWith` transition is performed,
+    /// whether it was returned by a state or queued, to cancel it and perform another transition
+    /// instead, like an "Are you sure?" dialog.
+    /// The handler is given the transitions allowed by the interceptor. It is not asked when the
+    /// last state is popped, for the alternative transition, nor by `stop` and `force_quit`,
+    /// which is how the dialog quits, from a `StateTransition::Custom`.
+    pub fn set_quit_handler(
+        &mut self,
+        handler: impl FnMut(&mut S) -> QuitDecision<S, E> + MaybeSend + 'static,
+    ) {
+        self.quit_handler = Some(Box::new(handler));
+    }
+
+    /// Removes the quit handler, so the machine quits when asked to.
+    pub fn clear_quit_handler(&mut self) {
+        self.quit_handler = None;
+    }
+
+    /// Returns the transition to perform instead of quitting, if the quit handler cancels it.
+    fn quit_alternative(&mut self, state_data: &mut S) -> Option<StateTransition<S, E>> {
+        let mut handler = self.quit_handler.take()?;
+        let decision = handler(state_data);
+        self.quit_handler = Some(handler);
+        match decision {
+            QuitDecision::Proceed => None,
+            QuitDecision::InterceptWith(alternative) => Some(alternative),
+        }
+    }
+
+    /// Sets the check that is given the active state and every transition allowed by the
+    /// interceptor, before it is performed.
+    /// A rejected transition is dropped, and its error is given to the states from the top
+    /// down through `State::on_error`, like an error returned by `State::try_update`, except
+    /// that the active state stays on the stack. If no state handles it, the machine is stopped
+    /// and keeps the error, see `take_error`.
+    /// Like the interceptor, the check is not given the operations called on the machine, such
+    /// as `push`, nor the ones done by `Custom` transitions.
+    /// An active state borrowing data, which cannot be downcast, is given as `None`, like an
+    /// empty stack, see `ScopedStateMachine`.
+    pub fn set_transition_check(
+        &mut self,
+        check: impl FnMut(
+                Option<&(dyn State<S, E> + 'static)>,
+                &StateTransition<S, E>,
+            ) -> Result<(), TransitionError>
+            + MaybeSend
+            + 'static,
+    ) {
+        self.transition_check = Some(Box::new(check));
+    }
+
+    /// Removes the transition check, so all transitions are performed.
+    pub fn clear_transition_check(&mut self) {
+        self.transition_check = None;
+    }
+
+    /// Returns the error of the transition check, if it rejects this transition.
+    fn check(&mut self, request: &StateTransition<S, E>) -> Option<TransitionError> {
+        let mut check = self.transition_check.take()?;
+        let active = self
+            .state_stack
+            .last()
+            .and_then(|entry| downcastable(&entry.state));
+        let result = check(active, request);
+        self.transition_check = Some(check);
+        result.err()
+    }
+
+    /// A replacement transition is given to the interceptor once more, and a replacement of the
+    /// replacement is performed as is.
+    fn intercept(
+        &mut self,
+        request: StateTransition<S, E>,
+        state_data: &S,
+    ) -> Option<StateTransition<S, E>> {
+        let mut interceptor = match self.interceptor.take() {
+            Some(interceptor) => interceptor,
+            None => return Some(request),
+        };
+
+        let decision = match interceptor(&request, state_data) {
+            InterceptDecision::Allow => Some(request),
+            InterceptDecision::Block => None,
+            InterceptDecision::ReplaceWith(replacement) => {
+                match interceptor(&replacement, state_data) {
+                    InterceptDecision::Allow => Some(replacement),
+                    InterceptDecision::Block => None,
+                    InterceptDecision::ReplaceWith(replacement) => Some(replacement),
+                }
+            }
+        };
+
+        self.interceptor = Some(interceptor);
+        decision
+    }
+
+    /// Performs the transition like `transition`, then the transitions returned by the states it
+    /// started, stopped or resumed. Returns the kind of the last transition that was performed.
+    fn perform(
+        &mut self,
+        request: StateTransition<S, E>,
+        state_data: &mut S,
+    ) -> Option<TransitionKind> {
+        let mut kind = match self.play_effect(request, state_data) {
+            Ok(kind) => kind,
+            Err(request) => self.transition(request, state_data),
+        };
+        // The held transition is performed before the ones returned by the effect state.
+        loop {
+            kind = self.release_effect(state_data).or(kind);
+            if self.follow_ups.is_empty() && self.failed_starts.is_empty() {
+                if self.start_fallback(state_data) {
+                    kind = Some(TransitionKind::Fallback);
+                    continue;
+                }
+                self.finish(state_data);
+                return kind;
+            }
+            kind = self.perform_follow_ups(state_data).or(kind);
+        }
+    }
+
+    /// Performs the transitions returned by the `on_start`, `on_stop` and `on_resume` of the
+    /// states since they were last performed, then the ones returned because of these
+    /// transitions, and so on.
+    /// If this goes on for more rounds than the chain limit, the machine is stopped with a
+    /// `TransitionError::StartChainTooLong` error.
+    fn perform_follow_ups(&mut self, state_data: &mut S) -> Option<TransitionKind> {
+        let mut last = None;
+        let mut chain = 0;
+        while !self.follow_ups.is_empty() || !self.failed_starts.is_empty() {
+            if chain == self.max_start_chain {
+                self.quit_states(ExitReason::Error, state_data);
+                self.error = Some(Box::new(TransitionError::StartChainTooLong));
+                return last;
+            }
+            chain += 1;
+            for error in std::mem::take(&mut self.failed_starts) {
+                let below = self.state_stack.len();
+                last = self.report(below, error, state_data).or(last);
+            }
+            for trans in std::mem::take(&mut self.follow_ups) {
+                last = self.transition(trans, state_data).or(last);
+            }
+        }
+        last
+    }
+
+    /// Sets how many rounds of transitions returned by `on_start`, `on_stop` and `on_resume` are
+    /// performed in a row, each round being the transitions returned because of the previous
+    /// one.
+    /// Past this limit, the machine stops and keeps a `TransitionError::StartChainTooLong` error,
+    /// to stop states redirecting to each other forever. Defaults to 16.
+    pub fn set_max_start_chain(&mut self, limit: usize) {
+        self.max_start_chain = limit;
+    }
+
+    /// Sets how many states the stack may hold. A transition that would push more states, like
+    /// a state pushing itself on every frame, is dropped before its states are started, so the
+    /// active state is not paused. Switches and pops are not limited. Defaults to no limit.
+    ///
+    /// The dropped transitions are logged with the `log` feature, or handled according to the
+    /// policy set with `set_depth_policy`, with a `TransitionError::DepthLimitExceeded` error.
+    /// Like the transition check, the limit is not applied to the operations called on the
+    /// machine, such as `push`, nor to the ones done by `Custom` transitions.
+    pub fn set_max_depth(&mut self, limit: usize) {
+        self.max_depth = limit;
+    }
+
+    /// Returns how many states the stack may hold, see `set_max_depth`.
+    /// It is `usize::MAX` when there is no limit.
+    pub fn max_depth(&self) -> usize {
+        self.max_depth
+    }
+
+    /// Returns how many more states the stack may hold, to warn before the limit is reached.
+    pub fn remaining_depth(&self) -> usize {
+        self.max_depth.saturating_sub(self.state_stack.len())
+    }
+
+    /// Sets what is done with the transitions that would go over the depth limit, see
+    /// `set_max_depth`. Defaults to `ViolationPolicy::Block`.
+    pub fn set_depth_policy(&mut self, policy: ViolationPolicy) {
+        self.depth_policy = policy;
+    }
+
+    /// Returns the error of this transition if it would go over the depth limit, with the
+    /// policy for it.
+    fn depth_violation(
+        &self,
+        request: &StateTransition<S, E>,
+    ) -> Option<(TransitionError, ViolationPolicy)> {
+        let added = match request {
+            StateTransition::Push(_)
+            | StateTransition::PushWith(_)
+            | StateTransition::PushNamed(_)
+            | StateTransition::InsertBelow(_)
+            | StateTransition::PushBottom(_) => 1,
+            StateTransition::PushMany(states) => states.len(),
+            StateTransition::ReplaceAll(states) => {
+                states.len().saturating_sub(self.state_stack.len())
+            }
+            _ => return None,
+        };
+        if added <= self.remaining_depth() {
+            return None;
+        }
+        let error = TransitionError::DepthLimitExceeded {
+            limit: self.max_depth,
+        };
+        Some((error, self.depth_policy))
+    }
+
+    /// Performs the transition, if the interceptor allows it.
+    /// Returns the kind of the transition that was performed, or `Quit` if it requested to quit.
+    fn transition(
+        &mut self,
+        request: StateTransition<S, E>,
+        state_data: &mut S,
+    ) -> Option<TransitionKind> {
+        if let StateTransition::None = request {
+            return None;
+        }
+        let request = self.spend_budget(request)?;
+        let request = self.intercept(request, state_data)?;
+        let violation = self
+            .depth_violation(&request)
+            .or_else(|| self.violation(&request));
+        if let Some((error, policy)) = violation {
+            match policy {
+                ViolationPolicy::Panic if cfg!(debug_assertions) => panic!("{}", error),
+                ViolationPolicy::Panic | ViolationPolicy::Block => {
+                    log_warn!("blocked transition: {}", error);
+                    return None;
+                }
+                ViolationPolicy::Report => {
+                    let below = self.state_stack.len();
+                    return self.report(below, Box::new(error), state_data);
+                }
+            }
+        }
+        if let Some(error) = self
+            .check(&request)
+            .or_else(|| self.unmet_requirement(&request))
+        {
+            let below = self.state_stack.len();
+            return self.report(below, Box::new(error), state_data);
+        }
+        if let StateTransition::Quit | StateTransition::QuitWith(_) = request {
+            if let Some(alternative) = self.quit_alternative(state_data) {
+                let handler = self.quit_handler.take();
+                let kind = self.transition(alternative, state_data);
+                self.quit_handler = handler;
+                return kind;
+            }
+        }
+        let stopped_from = match &request {
+            StateTransition::Pop => self
+                .coupled_group()
+                .and_then(|group| self.group_start(group))
+                .or_else(|| self.state_stack.len().checked_sub(1)),
+            StateTransition::PopGroup(group) => self.group_start(*group),
+            StateTransition::PopRetain
+            | StateTransition::PopWith(_)
+            | StateTransition::Replace(_) => self.state_stack.len().checked_sub(1),
+            StateTransition::PopN(0) => None,
+            StateTransition::PopN(n) => Some(self.state_stack.len().saturating_sub(*n)),
+            StateTransition::Switch(_) | StateTransition::SwitchWith(_) => {
+                Some(self.switched_from())
+            }
+            StateTransition::ReplaceAll(_)
+            | StateTransition::Quit
+            | StateTransition::QuitWith(_) => Some(0),
+            _ => None,
+        };
+        if let Some(alternative) = stopped_from.and_then(|index| self.veto(index, state_data)) {
+            return self.without_vetoes(|sm| sm.transition(alternative, state_data));
+        }
+        let kind = request.kind();
+        let from = self.observed_name();
+
+        match request {
+            StateTransition::None => (),
+            StateTransition::Pop => {
+                self.pop_active(state_data);
+            }
+            StateTransition::PopGroup(group) => {
+                if !self.pop_group_states(group, state_data) {
+                    return None;
+                }
+            }
+            StateTransition::PopRetain => {
+                if let Some(state) = self.pop_retaining(state_data) {
+                    self.retained = Some(state);
+                }
+            }
+            StateTransition::PopWith(payload) => self.pop_with(payload, state_data),
+            StateTransition::PopN(n) => self.pop_n(n, state_data),
+            StateTransition::PopUntil(predicate) => {
+                let matches = |entry: &StackEntry<'a, S, E, A>| {
+                    downcastable(&entry.state).is_some_and(&predicate)
+                };
+                self.pop_until(matches, state_data);
+            }
+            StateTransition::PopTo(target) => {
+                let matches = |entry: &StackEntry<'a, S, E, A>| match downcastable(&entry.state) {
+                    Some(state) => target.matches(state),
+                    None => matches!(&target, PopTarget::Name(name) if entry.state.name() == name),
+                };
+                if !self.pop_until(matches, state_data) {
+                    let below = self.state_stack.len();
+                    let error = TransitionError::NoMatchingState(target);
+                    return self.report(below, Box::new(error), state_data);
+                }
+            }
+            StateTransition::PopTagged(tag) => {
+                self.pop_tagged(tag, state_data);
+            }
+            StateTransition::Push(state) => match self.duplicate_of(state.as_ref()) {
+                Some(index) => {
+                    if !self.push_duplicate(index, state_data) {
+                        return None;
+                    }
+                }
+                None => self.push_boxed(state, state_data),
+            },
+            StateTransition::PushWith(constructor) => self.push_with(constructor, state_data),
+            StateTransition::PushMany(states) => self.push_many_states(states, state_data),
+            StateTransition::PushNamed(name) => match self.registry.create(&name) {
+                Ok(state) => {
+                    let len = self.state_stack.len();
+                    let states = std::slice::from_ref(&state);
+                    if let Some(error) = self.missing_requirement(len, states) {
+                        return self.report(len, Box::new(error), state_data);
+                    }
+                    match self.duplicate_of(state.as_ref()) {
+                        Some(index) => {
+                            if !self.push_duplicate(index, state_data) {
+                                return None;
+                            }
+                        }
+                        None => self.push_boxed(state, state_data),
+                    }
+                }
+                Err(error) => {
+                    let below = self.state_stack.len();
+                    return self.report(below, Box::new(error), state_data);
+                }
+            },
+            StateTransition::Switch(state) => self.switch_state(state, state_data),
+            StateTransition::SwitchWith(constructor) => self.switch_with(constructor, state_data),
+            StateTransition::Replace(state) => self.replace(state, state_data),
+            StateTransition::ReplaceAll(states) => self.replace_all_states(states, state_data),
+            StateTransition::InsertBelow(state) => {
+                let index = self.state_stack.len().saturating_sub(1);
+                self.insert_state(index, state, state_data);
+            }
+            StateTransition::PushBottom(state) => self.insert_state(0, state, state_data),
+            StateTransition::Quit => self.quit_all(ExitReason::Quit, state_data),
+            StateTransition::QuitWith(value) => {
+                self.quit_all(ExitReason::Quit, state_data);
+                self.exit_value = Some(value);
+            }
+            StateTransition::Message { target, payload } => {
+                self.send_message(&target, &*payload, state_data);
+            }
+            StateTransition::Delayed {
+                after_updates: 0,
+                then,
+            } => return self.transition(*then, state_data),
+            StateTransition::Delayed {
+                after_updates,
+                then,
+            } => {
+                if let Some(entry) = self.state_stack.last_mut() {
+                    entry.delayed = Some((after_updates, *then));
+                }
+            }
+            StateTransition::Sequence(requests) => {
+                // The transitions of the sequence are observed one by one.
+                self.metrics.record_transition(TransitionKind::Sequence);
+                for request in requests {
+                    if self.transition(request, state_data) == Some(TransitionKind::Quit) {
+                        return Some(TransitionKind::Quit);
+                    }
+                }
+                return kind;
+            }
+            StateTransition::Custom(custom) => match self.custom {
+                Some(run) => run(self, custom, state_data),
+                None => {
+                    let below = self.state_stack.len();
+                    let error = TransitionError::KindNotSupported(TransitionKind::Custom);
+                    return self.report(below, Box::new(error), state_data);
+                }
+            },
+        }
+        if let Some(kind) = kind {
+            self.reset_stall();
+            self.metrics.record_transition(kind);
+            self.notify(kind, from, state_data);
+        }
+        kind
+    }
+
+    /// Stops all the states on the stack, top-down, then pushes this state and starts it, or the
+    /// other way around, see `set_switch_ordering`.
+    /// While the machine is interrupted, only the states of the last interrupt are stopped, and
+    /// the new state takes the place of the interrupt.
+    ///
+    /// The states to stop can cancel the switch, see `State::on_stop_requested`.
+    pub fn switch(&mut self, state: Box<dyn State<S, E>>, state_data: &mut S) {
+        let from = self.switched_from();
+        if let Some(error) = self.missing_requirement(from, std::slice::from_ref(&state)) {
+            return self.reject(error, state_data);
+        }
+        if self.vetoed(from, state_data) {
+            return;
+        }
+        self.recorded(TransitionKind::Switch, |sm| {
+            sm.switch_state(state, state_data)
+        });
+        self.settle(state_data);
+    }
+
+    fn switch_state(&mut self, state: Box<dyn State<S, E>>, state_data: &mut S) {
+        if let Some(state) = self.restart_top(self.switched_from(), state, state_data) {
+            self.switch_with(|_| state, state_data);
+        }
+    }
+
+    /// Restarts the top state with the incoming state if it is the only state from this
+    /// index, it supports it and the incoming state has the same type, see
+    /// `State::supports_restart`. Returns the incoming state otherwise.
+    fn restart_top(
+        &mut self,
+        from: usize,
+        state: Box<dyn State<S, E>>,
+        state_data: &mut S,
+    ) -> Option<Box<dyn State<S, E>>> {
+        let len = self.state_stack.len();
+        let top = match self.state_stack.last_mut() {
+            Some(top) if len == from + 1 => top,
+            _ => return Some(state),
+        };
+        let same_type = any_of(&top.state).map(|top| top.type_id())
+            == Some(<dyn AsAny>::as_any(state.as_ref()).type_id());
+        if !same_type || !top.state.as_ref().supports_restart() {
+            return Some(state);
+        }
+        top.state
+            .as_mut()
+            .on_restart(<dyn AsAny>::into_any(state), state_data);
+        None
+    }
+
+    /// Switches to the state built after the states it replaces were stopped.
+    /// Returns the index of the lowest state a switch stops.
+    fn switched_from(&self) -> usize {
+        self.state_stack
+            .iter()
+            .rposition(|entry| entry.interrupt)
+            .unwrap_or(0)
+    }
+
+    fn switch_with(
+        &mut self,
+        constructor: impl FnOnce(&mut S) -> Box<dyn State<S, E>>,
+        state_data: &mut S,
+    ) {
+        let interrupt = self.state_stack.iter().rposition(|entry| entry.interrupt);
+        let from = interrupt.unwrap_or(0);
+        let switched_from = self
+            .subscribed()
+            .then(|| self.active_name().map(str::to_string));
+        let removed: Vec<_> = match self.switch_ordering {
+            SwitchOrdering::StopThenStart => {
+                while self.state_stack.len() > from {
+                    if let Some(entry) = self.state_stack.pop() {
+                        self.stop_entry(entry, state_data);
+                    }
+                }
+                Vec::new()
+            }
+            SwitchOrdering::StartThenStop => self.state_stack.drain(from..).collect(),
+        };
+        let state = from_global(constructor(state_data));
+        if interrupt.is_some() {
+            self.cover(state, true, state_data);
+        } else if !self.start_state(state, state_data) && !removed.is_empty() {
+            // The old states were not stopped yet, so they are put back as they were.
+            self.state_stack.extend(removed);
+            return;
+        }
+        for entry in removed.into_iter().rev() {
+            self.stop_entry(entry, state_data);
+        }
+        if let Some(from) = switched_from {
+            self.emit(|sm| MachineEvent::Switched {
+                from,
+                to: sm.active_name().map(str::to_string),
+            });
+        }
+    }
+
+    fn replace(&mut self, state: Box<dyn State<S, E>>, state_data: &mut S) {
+        let from = self.state_stack.len().saturating_sub(1);
+        let state = match self.restart_top(from, state, state_data) {
+            Some(state) => state,
+            None => return,
+        };
+        let mut removed = self.state_stack.pop();
+        let interrupt = removed.as_ref().is_some_and(|entry| entry.interrupt);
+        if self.switch_ordering == SwitchOrdering::StopThenStart {
+            if let Some(entry) = removed.take() {
+                self.stop_entry(entry, state_data);
+            }
+        }
+
+        self.cover(from_global(state), interrupt, state_data);
+        if let Some(entry) = removed {
+            self.stop_entry(entry, state_data);
+        }
+    }
+
+    /// Sets the order in which `Switch`, `SwitchWith` and `Replace` stop the old states and
+    /// start the new one. Defaults to `SwitchOrdering::StopThenStart`.
+    ///
+    /// With `SwitchOrdering::StartThenStop`, the old states are not on the stack anymore when
+    /// their `on_stop` is called, and the state of a `SwitchWith` is built before they are
+    /// stopped. The transitions returned by the `on_start` of the new state are performed once
+    /// the old states are stopped, like with the default ordering.
+    ///
+    /// When the new state fails to start, see `State::try_start`, or panics, a `Switch` or
+    /// `SwitchWith` with `SwitchOrdering::StartThenStop` puts the old states back on the stack,
+    /// without stopping them, before giving them the error. With the default ordering they are already
+    /// stopped, so the error goes to the states below an interrupt, or stops the machine with
+    /// `ExitReason::Error` if there are none.
+    pub fn set_switch_ordering(&mut self, ordering: SwitchOrdering) {
+        self.switch_ordering = ordering;
+    }
+
+    /// Pushes a state that interrupts the active one, like a "controller disconnected" overlay.
+    /// When the interrupt is popped, the interrupted state is resumed, even if the interrupt
+    /// pushed other states or switched to another state in the meantime: switching while
+    /// interrupted only replaces the states of the last interrupt.
+    ///
+    /// Returns the handle of the interrupt, like `push`.
+    pub fn interrupt(&mut self, state: Box<dyn State<S, E>>, state_data: &mut S) -> StateHandle {
+        let len = self.state_stack.len();
+        if let Some(error) = self.missing_requirement(len, std::slice::from_ref(&state)) {
+            return self.reject_state(error, state_data);
+        }
+        let handle = self.next_handle();
+        self.recorded(TransitionKind::Push, |sm| {
+            sm.cover(from_global(state), true, state_data)
+        });
+        self.settle(state_data);
+        handle
+    }
+
+    /// Returns if an interrupt is on the stack, see `interrupt`.
+    pub fn is_interrupted(&self) -> bool {
+        self.state_stack.iter().any(|entry| entry.interrupt)
+    }
+
+    /// Starts the state and pushes it on the stack as an interrupt.
+    /// Returns `false` if the state panicked and was dropped instead.
+    fn start_interrupt(&mut self, state: StateBox<'a, S, E, A>, state_data: &mut S) -> bool {
+        let started = self.start_state(state, state_data);
+        if started {
+            if let Some(entry) = self.state_stack.last_mut() {
+                entry.interrupt = true;
+            }
+        }
+        started
+    }
+
+    /// Push a state on the stack and start it.
+    /// Pauses any previously active state.
+    ///
+    /// Returns the handle of the pushed state, which is already stale if the state panicked
+    /// when started or was rejected because a state it needs is missing, see
+    /// `State::required_below`. If the state was not pushed because of the duplicate policy, returns the
+    /// handle of the state of the same type it found, see `set_duplicate_policy`.
+    pub fn push(&mut self, state: Box<dyn State<S, E>>, state_data: &mut S) -> StateHandle {
+        self.push_entry(from_global(state), state_data)
+    }
+
+    fn push_entry(&mut self, state: StateBox<'a, S, E, A>, state_data: &mut S) -> StateHandle {
+        let len = self.state_stack.len();
+        if let Some(error) = self.missing_requirement(len, std::slice::from_ref(&state)) {
+            return self.reject_state(error, state_data);
+        }
+        if let Some(index) = downcastable(&state).and_then(|state| self.duplicate_of(state)) {
+            let handle = self.state_stack[index].handle;
+            self.recorded(TransitionKind::Push, |sm| {
+                sm.push_duplicate(index, state_data)
+            });
+            self.settle(state_data);
+            return handle;
+        }
+        let handle = self.next_handle();
+        self.recorded(TransitionKind::Push, |sm| {
+            sm.cover(state, false, state_data)
+        });
+        self.settle(state_data);
+        handle
+    }
+
+    /// Boxes this state and pushes it, see `push`.
+    /// A state that is already boxed is given to `push` instead, since this would box it again.
+    pub fn push_state(
+        &mut self,
+        state: impl State<S, E> + 'static,
+        state_data: &mut S,
+    ) -> StateHandle {
+        self.push(Box::new(state), state_data)
+    }
+
+    /// Pushes these states on the stack, from the bottom to the top, and starts them.
+    /// Each state but the last is paused right after it is started, since it is covered.
+    pub fn push_many(&mut self, states: Vec<Box<dyn State<S, E>>>, state_data: &mut S) {
+        if let Some(error) = self.missing_requirement(self.state_stack.len(), &states) {
+            return self.reject(error, state_data);
+        }
+        self.push_many_states(states, state_data);
+        self.settle(state_data);
+    }
+
+    fn push_many_states(&mut self, states: Vec<Box<dyn State<S, E>>>, state_data: &mut S) {
+        for state in states {
+            self.push_boxed(state, state_data);
+        }
+    }
+
+    /// Stops all the states on the stack, top-down, then pushes these states from the bottom to
+    /// the top and starts them, see `StateTransition::ReplaceAll`.
+    /// The transitions returned by `on_stop` are performed once the new states are in place.
+    ///
+    /// The states to stop can cancel the replacement, see `State::on_stop_requested`.
+    pub fn replace_all(&mut self, states: Vec<Box<dyn State<S, E>>>, state_data: &mut S) {
+        if let Some(error) = self.missing_requirement(0, &states) {
+            return self.reject(error, state_data);
+        }
+        if self.vetoed(0, state_data) {
+            return;
+        }
+        self.recorded(TransitionKind::ReplaceAll, |sm| {
+            sm.replace_all_states(states, state_data)
+        });
+        self.settle(state_data);
+    }
+
+    fn replace_all_states(&mut self, states: Vec<Box<dyn State<S, E>>>, state_data: &mut S) {
+        self.stop_states(state_data);
+        self.push_many_states(states, state_data);
+    }
+
+    /// Inserts a state at this index of the stack, from the bottom, without affecting the other
+    /// states. The state is started then paused, since it is covered.
+    /// If the index is the length of the stack or more, this acts like `push`.
+    ///
+    /// Returns the handle of the inserted state, like `push`.
+    pub fn insert(
+        &mut self,
+        index: usize,
+        state: Box<dyn State<S, E>>,
+        state_data: &mut S,
+    ) -> StateHandle {
+        if let Some(error) = self.missing_requirement(index, std::slice::from_ref(&state)) {
+            return self.reject_state(error, state_data);
+        }
+        let handle = self.next_handle();
+        self.insert_state(index, state, state_data);
+        self.settle(state_data);
+        handle
+    }
+
+    /// Inserts a state at the bottom of the stack, like `insert` with an index of 0.
+    /// This is meant for background states, like a music manager, which keep being shadow
+    /// updated under the other states.
+    pub fn push_bottom(&mut self, state: Box<dyn State<S, E>>, state_data: &mut S) -> StateHandle {
+        self.insert(0, state, state_data)
+    }
+
+    fn insert_state(&mut self, index: usize, state: Box<dyn State<S, E>>, state_data: &mut S) {
+        let len = self.state_stack.len();
+        if index >= len {
+            self.push_boxed(state, state_data);
+            return;
+        }
+
+        // Started and paused on top, then moved under the states that cover it.
+        if self.start_state(from_global(state), state_data) {
+            self.pause_top(state_data);
+            if self.state_stack.len() > len {
+                if let Some(entry) = self.state_stack.pop() {
+                    self.state_stack.insert(index, entry);
+                }
+            }
+        }
+    }
+
+    /// Returns the handle that `start_state` gives to the next state it starts.
+    fn next_handle(&self) -> StateHandle {
+        StateHandle(self.next_handle)
+    }
+
+    /// Returns if the state with this handle is still on the stack.
+    pub fn is_alive(&self, handle: StateHandle) -> bool {
+        self.index_of(handle).is_some()
+    }
+
+    /// Returns the state with this handle, if it is still on the stack.
+    pub fn state(&self, handle: StateHandle) -> Option<&(dyn State<S, E> + 'a)> {
+        let index = self.index_of(handle)?;
+        Some(self.state_stack[index].state.as_ref())
+    }
+
+    /// Stops the state with this handle and removes it from the stack.
+    /// If it was the active state, the state below it is resumed, otherwise the other states are
+    /// neither paused nor resumed.
+    /// Returns `false` and does nothing if the state is not on the stack anymore.
+    pub fn remove(&mut self, handle: StateHandle, state_data: &mut S) -> bool {
+        let index = match self.index_of(handle) {
+            Some(index) => index,
+            None => return false,
+        };
+        if index + 1 == self.state_stack.len() {
+            self.pop_state(state_data);
+        } else {
+            let entry = self.state_stack.remove(index);
+            self.stop_entry(entry, state_data);
+            self.resume_visible(state_data);
+        }
+        self.settle(state_data);
+        true
+    }
+
+    /// Stops all the states for which the predicate returns `true`, top-down, and removes them
+    /// from the stack. The other states are neither paused nor resumed, unless the active state
+    /// was removed, uncovering the states below it.
+    /// Returns the number of removed states. Removing all of them stops the machine like popping
+    /// the last state, with `ExitReason::LastStatePopped`.
+    pub fn remove_where(
+        &mut self,
+        pred: impl FnMut(&dyn State<S, E>) -> bool,
+        state_data: &mut S,
+    ) -> usize {
+        let removed = self.remove_matching(pred, state_data);
+        self.settle(state_data);
+        removed
+    }
+
+    /// Returns where the state at this index of the stack is in the machine.
+    fn context(&self, index: usize) -> StateContext {
+        let entry = &self.state_stack[index];
+        StateContext {
+            handle: entry.handle,
+            index,
+            depth: self.state_stack.len(),
+            is_active: index + 1 == self.state_stack.len(),
+            frame: self.frame,
+            #[cfg(feature = "time")]
+            active_time: entry.active_time,
+            #[cfg(feature = "time")]
+            time_since_start: entry.age,
+            #[cfg(feature = "time")]
+            fixed_time: (self.accumulator, self.fixed_timestep),
+        }
+    }
+
+    fn index_of(&self, handle: StateHandle) -> Option<usize> {
+        self.state_stack
+            .iter()
+            .rposition(|entry| entry.handle == handle)
+    }
+
+    fn push_boxed(&mut self, state: Box<dyn State<S, E>>, state_data: &mut S) {
+        self.cover(from_global(state), false, state_data);
+    }
+
+    /// Pauses the visible states, then pushes the state built by the constructor.
+    fn push_with(&mut self, constructor: StateConstructor<S, E>, state_data: &mut S) {
+        self.pause_visible(state_data);
+        let state = constructor(state_data);
+        self.start_state(from_global(state), state_data);
+        self.resume_visible(state_data);
+    }
+
+    /// Pauses the visible states unless the state is transparent, then starts it on top, as an
+    /// interrupt if asked.
+    fn cover(&mut self, state: StateBox<'a, S, E, A>, interrupt: bool, state_data: &mut S) {
+        let visible = self.visible_start_pushing(state.as_ref().is_transparent());
+        for index in (self.visible_start()..visible).rev() {
+            if !self.state_stack[index].paused {
+                self.pause_at(index, PauseReason::Covered, state_data);
+            }
+        }
+        if interrupt {
+            self.start_interrupt(state, state_data);
+        } else {
+            self.start_state(state, state_data);
+        }
+        // Resumes the states below if the state panicked, or if it is transparent and replaced
+        // an opaque state.
+        self.resume_visible(state_data);
+    }
+
+    /// Stops the state on top of the stack and removes it, then resumes the state below it.
+    /// If the state pops its group, see `State::pops_group`, the whole group is popped like with
+    /// `pop_group`.
+    /// Returns `false` and does nothing if the stack is empty, or if the state cancels it, see
+    /// `State::on_stop_requested`.
+    pub fn pop(&mut self, state_data: &mut S) -> bool {
+        let coupled = self
+            .coupled_group()
+            .and_then(|group| self.group_start(group));
+        if let Some(index) = coupled.or_else(|| self.active_index()) {
+            if self.vetoed(index, state_data) {
+                return false;
+            }
+        }
+        let from = self.recorded_name();
+        let popped = self.pop_active(state_data);
+        if popped {
+            let to = self.recorded_name();
+            self.record_history(TransitionKind::Pop, from, to);
+        }
+        self.settle(state_data);
+        popped
+    }
+
+    /// Pops the active state, or its whole group if it pops it, see `State::pops_group`.
+    fn pop_active(&mut self, state_data: &mut S) -> bool {
+        match self.coupled_group() {
+            Some(group) => self.pop_group_states(group, state_data),
+            None => self.pop_state(state_data),
+        }
+    }
+
+    fn pop_state(&mut self, state_data: &mut S) -> bool {
+        match self.state_stack.pop() {
+            Some(entry) => self.stop_entry(entry, state_data),
+            None => return false,
+        }
+
+        self.resume_visible(state_data);
+        true
+    }
+
+    fn pop_retaining(&mut self, state_data: &mut S) -> Option<StateBox<'a, S, E, A>> {
+        let entry = self.state_stack.pop()?;
+        let state = self.stop_keeping(entry, state_data);
+        self.resume_visible(state_data);
+        state
+    }
+}
+
+/// Moving the states out of the machine as a `Box`, which only the machines on the global heap
+/// can do, see `StateMachine::new_in`.
+impl<S: ?Sized, E> StateMachine<S, E> {
+    /// Stops the state on top of the stack and removes it like `pop`, then gives it back
+    /// instead of dropping it, so an expensive state can be pushed again later.
+    /// Pushing it again starts it again, so a state keeping what it built must tell by itself if
+    /// it was already started.
+    ///
+    /// Returns `None` if the stack is empty, if the state cancels it, or if it panicked when
+    /// stopped and was dropped.
+    pub fn pop_retained(&mut self, state_data: &mut S) -> Option<Box<dyn State<S, E>>> {
+        if self.vetoed(self.active_index()?, state_data) {
+            return None;
+        }
+        let from = self.recorded_name();
+        let state = self.pop_retaining(state_data);
+        let to = self.recorded_name();
+        self.record_history(TransitionKind::Pop, from, to);
+        self.settle(state_data);
+        state.map(into_global)
+    }
+
+    /// Takes the state popped by the last `StateTransition::PopRetain`, if it was not taken yet.
+    /// A state retained before the last one was taken is dropped, without being stopped again.
+    pub fn take_retained(&mut self) -> Option<Box<dyn State<S, E>>> {
+        self.retained.take().map(into_global)
+    }
+}
+
+impl<'a, S: ?Sized, E, A: Allocator> ScopedStateMachine<'a, S, E, A> {
+    /// Pops the top state and gives the payload to the state below, see
+    /// `StateTransition::PopWith`.
+    fn pop_with(&mut self, payload: Payload, state_data: &mut S) {
+        if let Some(entry) = self.state_stack.pop() {
+            self.stop_entry(entry, state_data);
+            if self.state_stack.is_empty() {
+                self.exit_payload = Some(payload);
+            } else {
+                self.resume_visible_with(Some(payload), ResumeReason::Uncovered, state_data);
+            }
+        }
+    }
+
+    /// Takes the payload of the `StateTransition::PopWith` that emptied the stack, if any.
+    pub fn take_exit_payload(&mut self) -> Option<Payload> {
+        self.exit_payload.take()
+    }
+
+    /// Sets the result of the machine, a value for the host to take once the states are done,
+    /// like the game mode picked in a launcher. A state sets it by returning
+    /// `StateTransition::result`.
+    /// A result which was not taken yet is replaced, and returned.
+    pub fn set_result<R: Any + MaybeSend>(&mut self, result: R) -> Option<Payload> {
+        self.result.replace(Box::new(result))
+    }
+
+    /// Returns if the machine has a result that was not taken yet.
+    pub fn has_result(&self) -> bool {
+        self.result.is_some()
+    }
+
+    /// Takes the result of the machine if it is a `R`.
+    /// A result of another type is kept, and `None` is returned.
+    pub fn take_result<R: Any>(&mut self) -> Option<R> {
+        match self.result.take()?.downcast() {
+            Ok(result) => Some(*result),
+            Err(result) => {
+                self.result = Some(result);
+                None
+            }
+        }
+    }
+
+    /// Same as `pop`, but fails instead of doing nothing when the stack is empty.
+    pub fn try_pop(&mut self, state_data: &mut S) -> Result<(), TransitionError> {
+        if self.pop(state_data) {
+            Ok(())
+        } else {
+            Err(TransitionError::EmptyStack)
+        }
+    }
+
+    fn pop_n(&mut self, n: usize, state_data: &mut S) {
+        if n == 0 {
+            return;
+        }
+
+        for _ in 0..n {
+            match self.state_stack.pop() {
+                Some(entry) => self.stop_entry(entry, state_data),
+                None => break,
+            }
+        }
+
+        self.resume_visible(state_data);
+    }
+
+    /// Returns if a state matched the predicate.
+    fn pop_until(
+        &mut self,
+        predicate: impl Fn(&StackEntry<'a, S, E, A>) -> bool,
+        state_data: &mut S,
+    ) -> bool {
+        let found = self.state_stack.iter().rev().position(predicate);
+
+        match found {
+            Some(n) => {
+                self.pop_n(n, state_data);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Stops all the states with this tag, top-down, and removes them from the stack.
+    /// The state that ends up on top of the stack is resumed, if it was covered by one of them.
+    /// Returns the number of removed states.
+    pub fn pop_all_with_tag(&mut self, tag: &str, state_data: &mut S) -> usize {
+        let removed = self.pop_tagged(tag, state_data);
+        self.settle(state_data);
+        removed
+    }
+
+    fn pop_tagged(&mut self, tag: &str, state_data: &mut S) -> usize {
+        self.remove_matching(|state| state.tags().contains(&tag), state_data)
+    }
+
+    /// Stops the states matching the predicate, top-down, removes them from the stack, then
+    /// resumes the states they were covering.
+    fn remove_matching(
+        &mut self,
+        mut pred: impl FnMut(&dyn State<S, E>) -> bool,
+        state_data: &mut S,
+    ) -> usize {
+        let mut removed = 0;
+        for index in (0..self.state_stack.len()).rev() {
+            if pred(self.state_stack[index].state.as_ref()) {
+                let entry = self.state_stack.remove(index);
+                self.stop_entry(entry, state_data);
+                removed += 1;
+            }
+        }
+
+        self.resume_visible(state_data);
+        removed
+    }
+
+    /// Returns the number of states with this tag on the stack.
+    pub fn count_with_tag(&self, tag: &str) -> usize {
+        self.iter()
+            .filter(|state| state.tags().contains(&tag))
+            .count()
+    }
+
+    /// Removes all currently running states from the stack and drops the scheduled transitions.
+    /// The states can cancel it, see `State::on_stop_requested`, unlike with `force_stop`.
+    pub fn stop(&mut self, state_data: &mut S) {
+        if !self.vetoed(0, state_data) {
+            self.force_stop(state_data);
+        }
+    }
+
+    /// Removes all currently running states from the stack, without asking them.
+    pub fn force_stop(&mut self, state_data: &mut S) {
+        self.recorded(TransitionKind::Quit, |sm| {
+            sm.quit_all(ExitReason::StoppedExternally, state_data)
+        });
+        self.settle(state_data);
+    }
+
+    /// Quits like a `StateTransition::Quit`, without asking the quit handler nor the states, see
+    /// `set_quit_handler`.
+    pub fn force_quit(&mut self, state_data: &mut S) {
+        self.recorded(TransitionKind::Quit, |sm| {
+            sm.quit_all(ExitReason::Quit, state_data)
+        });
+        self.settle(state_data);
+    }
+
+    /// Stops the remaining states without letting them cancel it, along with the overlays, then
+    /// drops the machine. This is how a machine should be torn down, since dropping it cannot
+    /// call `on_stop` without the state data.
+    ///
+    /// Returns how the machine stopped, which is `ExitReason::StoppedExternally` if it was still
+    /// running.
+    pub fn finalize(mut self, state_data: &mut S) -> Option<ExitReason> {
+        if self.is_running() {
+            self.force_stop(state_data);
+        }
+        if let Some(overlays) = &mut self.overlays {
+            overlays.force_stop(state_data);
+        }
+        self.exit_reason
+    }
+
+    /// Stops the remaining states without letting them cancel it, and forgets what the machine
+    /// did: its queued events, its queued, requested and scheduled transitions, error, exit
+    /// payload, result, value and reason, retained state, held transition, kept transitions,
+    /// frame count and idle updates. The observers, global hooks, interceptor, quit handler, registry, metrics and
+    /// settings are kept, so the machine can be used again.
+    pub fn reset(&mut self, state_data: &mut S) {
+        if self.is_running() {
+            self.force_stop(state_data);
+        }
+        self.initial_states.clear();
+        self.events.clear();
+        self.queued_transitions.clear();
+        self.clear_requests();
+        self.schedule.rewind();
+        self.follow_ups.clear();
+        self.error = None;
+        self.exit_payload = None;
+        self.result = None;
+        self.exit_value = None;
+        self.retained = None;
+        self.effects.clear();
+        self.frozen = false;
+        self.exit_reason = None;
+        self.clear_history();
+        self.frame = 0;
+        self.idle_updates = 0;
+        #[cfg(feature = "time")]
+        {
+            self.accumulator = Duration::default();
+        }
+        #[cfg(feature = "catch_unwind")]
+        {
+            self.panic = None;
+            self.stop_after_panic = false;
+        }
+    }
+
+    /// Resets the machine, stopping its states first if it is still running, then pushes this
+    /// state to run it again, see `reset`.
+    pub fn restart(&mut self, initial_state: Box<dyn State<S, E>>, state_data: &mut S) {
+        self.reset(state_data);
+        self.push(initial_state, state_data);
+    }
+}
+
+/// Handing the states of the stack over as a `Box`, see `pop_retained`.
+impl<S: ?Sized, E> StateMachine<S, E> {
+    /// Removes the states of the stack without stopping them, from the bottom to the top, to
+    /// hand them over to something else. The overlays are dropped.
+    pub fn leak_states(mut self) -> Vec<Box<dyn State<S, E>>> {
+        self.state_stack
+            .drain(..)
+            .map(|entry| into_global(entry.state))
+            .collect()
+    }
+
+    /// Removes the states of the stack **without stopping them**, from the bottom to the top,
+    /// leaving the machine stopped, to move them to another machine with `adopt`, like when hot
+    /// reloading the registries. No lifecycle callback is called, so the states keep what they
+    /// hold, but the machine forgets their handles, clocks and delayed transitions.
+    pub fn drain(&mut self) -> Vec<Box<dyn State<S, E>>> {
+        let from = self.recorded_name();
+        let states = self
+            .state_stack
+            .drain(..)
+            .map(|entry| into_global(entry.state))
+            .collect();
+        self.record_history(TransitionKind::Quit, from, None);
+        if self.exit_reason.is_none() {
+            self.exit_reason = Some(ExitReason::StoppedExternally);
+        }
+        self.emit_stopped();
+        states
+    }
+}
+
+impl<'a, S: ?Sized, E, A: Allocator> ScopedStateMachine<'a, S, E, A> {
+    /// Puts these states on the stack as they are, from the bottom to the top, **without
+    /// starting, pausing or resuming them**, like states returned by `drain` or `leak_states`.
+    /// The states covered by the top ones are considered paused, so they are resumed when
+    /// uncovered, and each state gets a new handle.
+    ///
+    /// ## Panics
+    /// Panics if the stack is not empty, since the states on it would be covered without being
+    /// paused.
+    pub fn adopt(&mut self, states: Vec<Box<dyn State<S, E>>>) {
+        assert!(
+            self.state_stack.is_empty(),
+            "Only an empty state machine can adopt states."
+        );
+        for state in states {
+            let entry = StackEntry::new(from_global(state), self.next_handle());
+            self.next_handle += 1;
+            self.state_stack.push(entry);
+        }
+        let visible = self.visible_start();
+        for entry in &mut self.state_stack[..visible] {
+            entry.paused = true;
+        }
+        self.exit_reason = None;
+        self.metrics.record_depth(self.state_stack.len());
+        let to = self.recorded_name();
+        self.record_history(TransitionKind::Switch, None, to);
+    }
+
+    /// Sets if dropping the machine while states are on the stack panics in debug builds, with
+    /// the names of the states, to find the states whose `on_stop` is never called.
+    /// Defaults to `false`.
+    pub fn set_leak_check(&mut self, enabled: bool) {
+        self.leak_check = enabled;
+    }
+
+    /// Sets if the states can cancel being stopped, see `State::on_stop_requested`.
+    /// Defaults to `true`.
+    pub fn set_stop_vetoes(&mut self, enabled: bool) {
+        self.stop_vetoes = enabled;
+    }
+
+    /// Asks the states from this index to the top, from the top down, if they can be stopped.
+    /// Returns the transition of the first one denying it.
+    fn veto(&mut self, from: usize, state_data: &mut S) -> Option<StateTransition<S, E>> {
+        if !self.stop_vetoes {
+            return None;
+        }
+        let catch = self.catches_panics();
+        for index in (from..self.state_stack.len()).rev() {
+            let entry = &mut self.state_stack[index];
+            match guard(catch, || {
+                in_span!(
+                    "state.on_stop_requested",
+                    entry.state.as_ref().name(),
+                    entry.state.as_mut().on_stop_requested(state_data)
+                )
+            }) {
+                Ok(StopDecision::Allow) => (),
+                Ok(StopDecision::DenyWith(alternative)) => return Some(alternative),
+                // It is about to be stopped anyway.
+                Err(caught) => {
+                    self.state_stack.remove(index);
+                    self.caught(caught);
+                }
+            }
+        }
+        None
+    }
+
+    /// Asks the states from this index to the top if they can be stopped, like `veto`, and
+    /// performs the transition of the one denying it, if any. Returns if one denied it.
+    fn vetoed(&mut self, from: usize, state_data: &mut S) -> bool {
+        match self.veto(from, state_data) {
+            Some(alternative) => {
+                self.without_vetoes(|sm| sm.perform(alternative, state_data));
+                self.settle(state_data);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Does an operation without asking the states if they can be stopped, so the transition
+    /// given by a state denying it cannot be denied again forever.
+    fn without_vetoes<R>(&mut self, operation: impl FnOnce(&mut Self) -> R) -> R {
+        let vetoes = std::mem::replace(&mut self.stop_vetoes, false);
+        let result = operation(self);
+        self.stop_vetoes = vetoes;
+        result
+    }
+
+    /// Stops all the states, ignoring the transitions they return and the ones waiting to be
+    /// performed, since they would make the machine run again.
+    /// Calls `State::on_quit` on every state, bottom-up, then stops them top-down.
+    fn quit_all(&mut self, reason: ExitReason, state_data: &mut S) {
+        self.notify_quit(state_data);
+        self.quit_states(reason, state_data);
+    }
+
+    /// Calls `State::on_quit` on every state, bottom-up.
+    fn notify_quit(&mut self, state_data: &mut S) {
+        self.notify_all(state_data, |state, state_data| {
+            in_span!("state.on_quit", state.name(), state.on_quit(state_data))
+        });
+    }
+
+    fn quit_states(&mut self, reason: ExitReason, state_data: &mut S) {
+        self.stop_states(state_data);
+        self.forget_stopped(reason);
+    }
+
+    /// Drops what the states stopped by a quit left to do, and sets why the machine stopped.
+    fn forget_stopped(&mut self, reason: ExitReason) {
+        if self.shrink_on_stop {
+            self.state_stack.shrink_to_fit();
+        }
+        self.follow_ups.clear();
+        self.failed_starts.clear();
+        self.schedule.clear();
+        self.effects.clear();
+        self.frozen = false;
+        self.exit_reason = Some(reason);
+    }
+
+    /// Returns how the machine stopped, if it ran and is not running anymore.
+    /// Starting a state clears it.
+    pub fn exit_reason(&self) -> Option<ExitReason> {
+        self.exit_reason
+    }
+
+    /// Takes the value of the `StateTransition::QuitWith` that stopped the machine, if any.
+    /// It is set once all the states are stopped, and kept until it is taken.
+    pub fn take_exit_value(&mut self) -> Option<Payload> {
+        self.exit_value.take()
+    }
+
+    fn stop_states(&mut self, state_data: &mut S) {
+        while let Some(entry) = self.state_stack.pop() {
+            self.stop_entry(entry, state_data);
+        }
+    }
+
+    /// Starts the state and pushes it on the stack.
+    /// Returns `false` if the state failed to start or panicked, and was dropped instead.
+    fn start_state(&mut self, mut state: StateBox<'a, S, E, A>, state_data: &mut S) -> bool {
+        let handle = self.next_handle();
+        self.next_handle += 1;
+        log_trace!("on_start: {}", state.as_ref().name());
+        #[cfg(feature = "time")]
+        let start = self.telemetry.start();
+        let started = guard(self.catches_panics(), || {
+            in_span!(
+                "state.on_start",
+                state.as_ref().name(),
+                state.as_mut().try_start(state_data)
+            )
+        });
+        #[cfg(feature = "time")]
+        self.telemetry
+            .record(start, state.as_ref().name(), LifecyclePhase::Start);
+        match started {
+            Ok(Ok(trans)) => {
+                if !matches!(trans, StateTransition::None) {
+                    self.follow_ups.push(trans);
+                }
+                self.exit_reason = None;
+                let mut entry = StackEntry::new(state, handle);
+                entry.started_frame = self.frame;
+                self.state_stack.push(entry);
+                self.metrics.record_depth(self.state_stack.len());
+                self.emit_pushed();
+                true
+            }
+            Ok(Err(error)) => {
+                log_debug!("failed to start {}: {}", state.as_ref().name(), error);
+                self.failed_starts.push(error);
+                false
+            }
+            Err(caught) => {
+                self.caught(caught);
+                false
+            }
+        }
+    }
+
+    /// Stops a state that was removed from the stack.
+    /// Stops the state of the entry, and keeps it to drop it once the operation is done.
+    fn stop_entry(&mut self, entry: StackEntry<'a, S, E, A>, state_data: &mut S) {
+        if let Some(state) = self.stop_keeping(entry, state_data) {
+            self.stopped.push(state);
+        }
+    }
+
+    /// Drops the states stopped by the operation that is done, then tells the observers.
+    fn finish(&mut self, state_data: &mut S) {
+        #[cfg(feature = "debug-invariants")]
+        self.check_invariants();
+        // The guard of the fallback holds until a state stays on the stack.
+        if !self.state_stack.is_empty() {
+            self.fallback_started = false;
+        }
+        self.stopped.clear();
+        self.notify_complete(state_data);
+        self.emit_stopped();
+    }
+
+    /// Stops the state of the entry and returns it, unless it panicked.
+    fn stop_keeping(
+        &mut self,
+        mut entry: StackEntry<'a, S, E, A>,
+        state_data: &mut S,
+    ) -> Option<StateBox<'a, S, E, A>> {
+        log_trace!("on_stop: {}", entry.state.as_ref().name());
+        #[cfg(feature = "time")]
+        let start = self.telemetry.start();
+        let stopped = guard(self.catches_panics(), || {
+            in_span!(
+                "state.on_stop",
+                entry.state.as_ref().name(),
+                entry.state.as_mut().on_stop(state_data)
+            )
+        });
+        if self.state_stack.is_empty() {
+            self.exit_reason = Some(ExitReason::LastStatePopped);
+        }
+        #[cfg(feature = "time")]
+        self.telemetry
+            .record(start, entry.state.as_ref().name(), LifecyclePhase::Stop);
+        self.emit(|_| MachineEvent::StatePopped {
+            name: entry.state.as_ref().name().to_string(),
+        });
+        match stopped {
+            Ok(StateTransition::None) => (),
+            Ok(trans) => self.follow_ups.push(trans),
+            Err(caught) => {
+                self.caught(caught);
+                return None;
+            }
+        }
+        Some(entry.state)
+    }
+
+    /// Returns the index of the lowest visible state, the first one that is not transparent
+    /// from the top.
+    fn visible_start(&self) -> usize {
+        self.visible_start_at(self.active_depth)
+    }
+
+    /// Returns the index of the first visible state if the top `depth` states were active.
+    fn visible_start_at(&self, depth: usize) -> usize {
+        self.opaque_start(self.state_stack.len().saturating_sub(depth))
+    }
+
+    /// Returns the index of the first visible state once a state is pushed, transparent or not.
+    fn visible_start_pushing(&self, transparent: bool) -> usize {
+        let len = self.state_stack.len();
+        let lowest_active = (len + 1).saturating_sub(self.active_depth);
+        if lowest_active == len && !transparent {
+            len
+        } else {
+            self.opaque_start(lowest_active)
+        }
+    }
+
+    /// Returns the index of the first opaque state from this one down, the bottom if there is
+    /// none.
+    fn opaque_start(&self, index: usize) -> usize {
+        let end = (index + 1).min(self.state_stack.len());
+        self.state_stack[..end]
+            .iter()
+            .rposition(|entry| !entry.state.as_ref().is_transparent())
+            .unwrap_or(0)
+    }
+
+    /// Pauses the visible states that are not paused, top-down, because they are covered.
+    fn pause_visible(&mut self, state_data: &mut S) {
+        self.pause_visible_for(PauseReason::Covered, state_data);
+    }
+
+    /// Pauses the visible states that are not paused, top-down.
+    fn pause_visible_for(&mut self, reason: PauseReason, state_data: &mut S) {
+        for index in (self.visible_start()..self.state_stack.len()).rev() {
+            if !self.state_stack[index].paused {
+                self.pause_at(index, reason, state_data);
+            }
+        }
+    }
+
+    /// Resumes the paused visible states, bottom-up.
+    fn resume_visible(&mut self, state_data: &mut S) {
+        self.resume_visible_with(None, ResumeReason::Uncovered, state_data);
+    }
+
+    /// Resumes the paused visible states, bottom-up, giving the payload to the top one if it is
+    /// paused.
+    fn resume_visible_with(
+        &mut self,
+        mut payload: Option<Payload>,
+        reason: ResumeReason,
+        state_data: &mut S,
+    ) {
+        // Restarts after a state panicked, since it is removed and can uncover other states.
+        loop {
+            let start = self.visible_start();
+            match (start..self.state_stack.len()).find(|&index| self.state_stack[index].paused) {
+                Some(index) => {
+                    let top = index + 1 == self.state_stack.len();
+                    let payload = if top { payload.take() } else { None };
+                    self.resume_at(index, payload, reason, state_data);
+                }
+                None => return,
+            }
+        }
+    }
+
+    fn pause_top(&mut self, state_data: &mut S) {
+        if let Some(index) = self.active_index() {
+            self.pause_at(index, PauseReason::Covered, state_data);
+        }
+    }
+
+    fn pause_at(&mut self, index: usize, reason: PauseReason, state_data: &mut S) {
+        let catch = self.catches_panics();
+        if let Some(entry) = self.state_stack.get_mut(index) {
+            log_trace!("on_pause: {}", entry.state.as_ref().name());
+            #[cfg(feature = "time")]
+            let start = self.telemetry.start();
+            let paused = guard(catch, || {
+                in_span!(
+                    "state.on_pause",
+                    entry.state.as_ref().name(),
+                    entry.pause(reason, state_data)
+                )
+            });
+            #[cfg(feature = "time")]
+            self.telemetry
+                .record(start, entry.state.as_ref().name(), LifecyclePhase::Pause);
+            match paused {
+                Ok(()) => self.emit(|sm| MachineEvent::StatePaused {
+                    name: sm.state_stack[index].state.as_ref().name().to_string(),
+                }),
+                Err(caught) => {
+                    self.state_stack.remove(index);
+                    self.caught(caught);
+                }
+            }
+        }
+    }
+
+    /// Resumes the state at this index, or removes it if it panicked.
+    fn resume_at(
+        &mut self,
+        index: usize,
+        payload: Option<Payload>,
+        reason: ResumeReason,
+        state_data: &mut S,
+    ) {
+        let catch = self.catches_panics();
+        if let Some(entry) = self.state_stack.get_mut(index) {
+            log_trace!("on_resume: {}", entry.state.as_ref().name());
+            #[cfg(feature = "time")]
+            let start = self.telemetry.start();
+            let resumed = guard(catch, || {
+                in_span!(
+                    "state.on_resume",
+                    entry.state.as_ref().name(),
+                    entry.resume(payload, reason, state_data)
+                )
+            });
+            #[cfg(feature = "time")]
+            self.telemetry
+                .record(start, entry.state.as_ref().name(), LifecyclePhase::Resume);
+            match resumed {
+                Ok(trans) => {
+                    if !matches!(trans, StateTransition::None) {
+                        self.follow_ups.push(trans);
+                    }
+                    self.emit(|sm| MachineEvent::StateResumed {
+                        name: sm.state_stack[index].state.as_ref().name().to_string(),
+                    });
+                }
+                Err(caught) => {
+                    self.state_stack.remove(index);
+                    self.caught(caught);
+                }
+            }
+        }
+    }
+
+    /// Removes the top state after it panicked, without calling it again, and resumes the states
+    /// below it.
+    fn drop_top(&mut self, caught: Caught, state_data: &mut S) {
+        self.state_stack.pop();
+        self.caught(caught);
+        self.resume_visible(state_data);
+    }
+
+    #[cfg(feature = "catch_unwind")]
+    fn catches_panics(&self) -> bool {
+        self.panic_policy != PanicPolicy::Propagate
+    }
+
+    #[cfg(not(feature = "catch_unwind"))]
+    fn catches_panics(&self) -> bool {
+        false
+    }
+
+    #[cfg(feature = "catch_unwind")]
+    fn caught(&mut self, caught: Caught) {
+        self.panic = Some(caught);
+        if self.panic_policy == PanicPolicy::Stop {
+            self.stop_after_panic = true;
+        }
+    }
+
+    #[cfg(not(feature = "catch_unwind"))]
+    fn caught(&mut self, caught: Caught) {
+        match caught {}
+    }
+
+    /// Finishes an operation of the state machine, performing the transitions returned by the
+    /// states it started, then stopping it if a state panicked and the panic policy asks for it.
+    fn settle(&mut self, state_data: &mut S) {
+        self.release_effect(state_data);
+        self.perform_follow_ups(state_data);
+        if self.start_fallback(state_data) {
+            self.perform_follow_ups(state_data);
+        }
+        #[cfg(feature = "catch_unwind")]
+        while std::mem::take(&mut self.stop_after_panic) {
+            self.quit_states(ExitReason::Panicked, state_data);
+        }
+        self.finish(state_data);
+    }
+
+    /// Sets what happens when a state panics in one of its callbacks.
+    /// Defaults to `PanicPolicy::Propagate`.
+    #[cfg(feature = "catch_unwind")]
+    pub fn set_panic_policy(&mut self, policy: PanicPolicy) {
+        self.panic_policy = policy;
+    }
+
+    /// Takes the payload of the last panic caught by the state machine, if any.
+    #[cfg(feature = "catch_unwind")]
+    pub fn take_panic(&mut self) -> Option<Box<dyn Any + Send>> {
+        self.panic.take()
+    }
+}
+
+/// The payload of a panic caught in a state callback.
+#[cfg(feature = "catch_unwind")]
+type Caught = Box<dyn Any + Send>;
+
+/// Panics are never caught without the `catch_unwind` feature.
+#[cfg(not(feature = "catch_unwind"))]
+enum Caught {}
+
+/// Runs a callback of a state, catching its panic if asked to.
+#[cfg(feature = "catch_unwind")]
+fn guard<R>(catch: bool, callback: impl FnOnce() -> R) -> Result<R, Caught> {
+    if catch {
+        std::panic::catch_unwind(AssertUnwindSafe(callback))
+    } else {
+        Ok(callback())
+    }
+}
+
+#[cfg(not(feature = "catch_unwind"))]
+fn guard<R>(_catch: bool, callback: impl FnOnce() -> R) -> Result<R, Caught> {
+    Ok(callback())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use LifecycleCall::*;
+
+    type StateData = (isize, isize);
+
+    pub struct Test;
+
+    impl State<StateData> for Test {
+        fn on_start(&mut self, data: &mut StateData) -> StateTransition<StateData> {
+            data.0 += data.1;
+            StateTransition::None
+        }
+
+        fn on_resume(&mut self, data: &mut StateData) -> StateTransition<StateData> {
+            self.on_start(data)
+        }
+
+        fn update(&mut self, _data: &mut StateData) -> StateTransition<StateData> {
+            StateTransition::Push(Box::new(Test))
+        }
+    }
+
+    #[test]
+    fn sm_test() {
+        let mut sm = StateMachine::<StateData>::default();
+
+        let mut state_data = (0, 10);
+
+        sm.push(Box::new(Test), &mut state_data);
+        assert!(state_data.0 == 10);
+
+        sm.update(&mut state_data);
+        assert!(state_data.0 == 20);
+
+        sm.stop(&mut state_data);
         assert!(state_data.0 == 20);
         assert!(!sm.is_running())
     }
+
+    #[test]
+    fn max_depth() {
+        let mut sm = StateMachine::<StateData>::default();
+        let mut state_data = (0, 10);
+        sm.set_max_depth(3);
+        sm.push(Box::new(Test), &mut state_data);
+        for _ in 0..5 {
+            sm.update(&mut state_data);
+        }
+        assert_eq!(sm.len(), 3);
+        assert_eq!(sm.remaining_depth(), 0);
+        assert_eq!(state_data.0, 30);
+
+        sm.set_depth_policy(ViolationPolicy::Report);
+        assert_eq!(
+            sm.update(&mut state_data),
+            MachineStatus::Stopped(StopReason::Error)
+        );
+        let error = sm.take_error().unwrap();
+        assert_eq!(
+            error.downcast_ref(),
+            Some(&TransitionError::DepthLimitExceeded { limit: 3 })
+        );
+    }
+
+    type Log = Vec<String>;
+
+    /// Boxes a state with this name, recording its calls in this log.
+    fn recording<S: ?Sized + 'static>(name: &str, calls: &CallLog) -> Box<dyn State<S>> {
+        Box::new(RecordingState::new(name, calls))
+    }
+
+    /// Pushes recording states with these names, and returns the machine with their call log,
+    /// cleared of the calls made by the pushes.
+    fn recording_machine<S: Default + 'static>(names: &[&str]) -> (StateMachine<S>, CallLog) {
+        let calls = CallLog::default();
+        let mut sm = StateMachine::default();
+        for name in names {
+            sm.push(recording(name, &calls), &mut S::default());
+        }
+        calls.lock().unwrap().clear();
+        (sm, calls)
+    }
+
+    #[test]
+    fn transition_debug() {
+        let calls = CallLog::default();
+        let transitions: Vec<StateTransition<()>> = vec![
+            StateTransition::Push(recording("menu", &calls)),
+            StateTransition::PopWith(Box::new(3)),
+            StateTransition::PushMany(vec![recording("a", &calls), recording("b", &calls)]),
+            StateTransition::Delayed {
+                after_updates: 2,
+                then: Box::new(StateTransition::Switch(recording("game", &calls))),
+            },
+            StateTransition::Message {
+                target: MessageTarget::Below,
+                payload: Box::new(()),
+            },
+            StateTransition::Sequence(vec![StateTransition::PopN(2), StateTransition::Quit]),
+        ];
+        assert_eq!(
+            format!("{:?}", transitions),
+            "[Push(\"menu\"), PopWith(..), PushMany([\"a\", \"b\"]), \
+             Delayed { after_updates: 2, then: Switch(\"game\") }, \
+             Message { target: Below, .. }, Sequence([PopN(2), Quit])]"
+        );
+    }
+
+    #[test]
+    fn extend_then_start() {
+        let calls = CallLog::default();
+        let mut pushed = StateMachine::<()>::default();
+        for name in ["world", "hud", "tutorial"] {
+            pushed.push(Box::new(RecordingState::new(name, &calls)), &mut ());
+        }
+        let expected = std::mem::take(&mut *calls.lock().unwrap());
+
+        let mut sm: StateMachine<()> = ["world", "hud"]
+            .iter()
+            .map(|name| Box::new(RecordingState::new(*name, &calls)) as Box<dyn State<()>>)
+            .collect();
+        sm.extend(vec![
+            Box::new(RecordingState::new("tutorial", &calls)) as Box<dyn State<()>>
+        ]);
+        assert!(!sm.is_running());
+        assert_eq!(
+            sm.update(&mut ()),
+            MachineStatus::Stopped(StopReason::NotStarted)
+        );
+        assert!(calls.lock().unwrap().is_empty());
+
+        assert!(sm.start(&mut ()));
+        assert!(!sm.start(&mut ()));
+        assert_eq!(*calls.lock().unwrap(), expected);
+        assert_eq!(
+            expected,
+            [
+                ("world".to_string(), Start),
+                ("world".to_string(), Pause),
+                ("hud".to_string(), Start),
+                ("hud".to_string(), Pause),
+                ("tutorial".to_string(), Start),
+            ]
+        );
+        assert_eq!(format!("{:?}", sm), format!("{:?}", pushed));
+    }
+
+    #[test]
+    fn pop_n() {
+        let (mut sm, calls) = recording_machine(&["a", "b", "c", "d"]);
+
+        sm.transition(StateTransition::PopN(2), &mut ());
+        assert_calls(&calls, &[("d", Stop), ("c", Stop), ("b", Resume)]);
+        assert!(sm.is_running());
+
+        sm.transition(StateTransition::PopN(0), &mut ());
+        assert_calls(&calls, &[]);
+    }
+
+    #[test]
+    fn pop_until() {
+        use std::sync::atomic::{AtomicU32, Ordering};
+        use std::sync::Arc;
+
+        let (mut sm, calls) = recording_machine(&["a", "b", "c", "d"]);
+
+        // Matches the third state checked, which is "b".
+        let checked = Arc::new(AtomicU32::new(0));
+        let counter = checked.clone();
+        let predicate =
+            move |_: &(dyn State<()> + 'static)| counter.fetch_add(1, Ordering::Relaxed) + 1 == 3;
+        sm.transition(StateTransition::PopUntil(Box::new(predicate)), &mut ());
+        assert_calls(&calls, &[("d", Stop), ("c", Stop), ("b", Resume)]);
+        assert_eq!(checked.load(Ordering::Relaxed), 3);
+
+        sm.transition(StateTransition::PopUntil(Box::new(|_| false)), &mut ());
+        assert_calls(&calls, &[]);
+        assert!(sm.is_running());
+
+        sm.transition(StateTransition::PopUntil(Box::new(|_| true)), &mut ());
+        assert_calls(&calls, &[]);
+    }
+
+    #[test]
+    fn scoped_states_borrow_data() {
+        struct Base;
+
+        impl State<u32> for Base {}
+
+        struct Cached<'a>(&'a [u32]);
+
+        impl<'a> State<u32> for Cached<'a> {
+            fn update(&mut self, total: &mut u32) -> StateTransition<u32> {
+                *total += self.0.iter().sum::<u32>();
+                StateTransition::None
+            }
+        }
+
+        let cache = vec![1, 2, 3];
+        let mut total = 0;
+        let mut sm = ScopedStateMachine::new_in(Global);
+        sm.push(Box::new(Base), &mut total);
+        sm.push_scoped(Box::new(Cached(&cache)), &mut total);
+        sm.update(&mut total);
+        assert_eq!(total, 6);
+
+        // The borrowing state is not found by its type, unlike the one below it.
+        assert!(!sm.contains_state::<Cached<'static>>());
+        assert!(sm.active_state_as::<Cached<'static>>().is_none());
+        assert!(sm.find_state::<Base>().is_some());
+        let to_base = Box::new(|state: &(dyn State<u32> + 'static)| state.as_any().is::<Base>());
+        sm.transition(StateTransition::PopUntil(to_base), &mut total);
+        assert_eq!(sm.len(), 1);
+        sm.stop(&mut total);
+    }
+
+    #[test]
+    fn switch_clears_stack() {
+        let (mut sm, calls) = recording_machine(&["a", "b", "c"]);
+
+        let d = RecordingState::new("d", &calls);
+        sm.transition(StateTransition::Switch(Box::new(d)), &mut ());
+        assert_calls(
+            &calls,
+            &[("c", Stop), ("b", Stop), ("a", Stop), ("d", Start)],
+        );
+
+        sm.transition(StateTransition::Pop, &mut ());
+        assert_calls(&calls, &[("d", Stop)]);
+        assert!(!sm.is_running());
+    }
+
+    #[test]
+    fn replace() {
+        let (mut sm, calls) = recording_machine(&["a", "b"]);
+
+        let c = RecordingState::new("c", &calls);
+        sm.transition(StateTransition::Replace(Box::new(c)), &mut ());
+        assert_calls(&calls, &[("b", Stop), ("c", Start)]);
+
+        sm.transition(StateTransition::Pop, &mut ());
+        assert_calls(&calls, &[("c", Stop), ("a", Resume)]);
+    }
+
+    #[test]
+    fn replace_empty() {
+        let (mut sm, calls) = recording_machine(&[]);
+
+        let a = RecordingState::new("a", &calls);
+        sm.transition(StateTransition::Replace(Box::new(a)), &mut ());
+        assert_calls(&calls, &[("a", Start)]);
+        assert!(sm.is_running());
+    }
+
+    struct Level {
+        id: u32,
+        restartable: bool,
+        calls: CallLog,
+    }
+
+    impl Level {
+        fn record(&self, call: LifecycleCall) {
+            let name = format!("level {}", self.id);
+            self.calls.lock().unwrap().push((name, call));
+        }
+    }
+
+    impl State<Log> for Level {
+        fn supports_restart(&self) -> bool {
+            self.restartable
+        }
+
+        fn on_start(&mut self, _log: &mut Log) -> StateTransition<Log> {
+            self.record(Start);
+            StateTransition::None
+        }
+
+        fn on_stop(&mut self, _log: &mut Log) -> StateTransition<Log> {
+            self.record(Stop);
+            StateTransition::None
+        }
+
+        fn on_restart(&mut self, incoming: Box<dyn Any>, log: &mut Log) {
+            let incoming = incoming.downcast::<Level>().unwrap();
+            log.push(format!("restart {} with {}", self.id, incoming.id));
+            self.id = incoming.id;
+        }
+    }
+
+    fn level(id: u32, restartable: bool, calls: &CallLog) -> Box<dyn State<Log>> {
+        Box::new(Level {
+            id,
+            restartable,
+            calls: calls.clone(),
+        })
+    }
+
+    #[test]
+    fn restart_same_type() {
+        let mut log = Vec::new();
+        let calls = CallLog::default();
+        let mut sm = StateMachine::default();
+        let first = sm.push(level(1, true, &calls), &mut log);
+        assert_calls(&calls, &[("level 1", Start)]);
+
+        sm.transition(StateTransition::Switch(level(2, true, &calls)), &mut log);
+        sm.transition(StateTransition::Replace(level(3, false, &calls)), &mut log);
+        assert_eq!(log, ["restart 1 with 2", "restart 2 with 3"]);
+        assert_calls(&calls, &[]);
+        assert!(sm.is_alive(first));
+        assert_eq!(sm.find_state::<Level>().unwrap().id, 3);
+
+        sm.transition(StateTransition::Switch(recording("menu", &calls)), &mut log);
+        assert_calls(&calls, &[("level 3", Stop), ("menu", Start)]);
+        assert!(!sm.is_alive(first));
+    }
+
+    #[test]
+    fn restart_opted_out() {
+        let mut log = Vec::new();
+        let calls = CallLog::default();
+        let mut sm = StateMachine::default();
+        sm.push(level(1, false, &calls), &mut log);
+        sm.transition(StateTransition::Replace(level(2, true, &calls)), &mut log);
+        assert_calls(
+            &calls,
+            &[("level 1", Start), ("level 1", Stop), ("level 2", Start)],
+        );
+
+        sm.push(level(3, true, &calls), &mut log);
+        sm.transition(StateTransition::Switch(level(4, true, &calls)), &mut log);
+        assert_calls(
+            &calls,
+            &[
+                ("level 3", Start),
+                ("level 3", Stop),
+                ("level 2", Stop),
+                ("level 4", Start),
+            ],
+        );
+        assert!(log.is_empty());
+    }
+
+    #[test]
+    fn sequence() {
+        let (mut sm, calls) = recording_machine(&["a", "b", "c"]);
+
+        sm.transition(
+            StateTransition::Sequence(vec![
+                StateTransition::Pop,
+                StateTransition::Pop,
+                StateTransition::Push(Box::new(RecordingState::new("d", &calls))),
+            ]),
+            &mut (),
+        );
+        assert_calls(
+            &calls,
+            &[
+                ("c", Stop),
+                ("b", Resume),
+                ("b", Stop),
+                ("a", Resume),
+                ("a", Pause),
+                ("d", Start),
+            ],
+        );
+    }
+
+    #[test]
+    fn sequence_quit() {
+        let (mut sm, calls) = recording_machine(&["a", "b"]);
+
+        sm.transition(
+            StateTransition::Sequence(vec![
+                StateTransition::Pop,
+                StateTransition::Sequence(vec![StateTransition::Quit]),
+                StateTransition::Push(Box::new(RecordingState::new("c", &calls))),
+            ]),
+            &mut (),
+        );
+        assert_calls(
+            &calls,
+            &[("b", Stop), ("a", Resume), ("a", Quit), ("a", Stop)],
+        );
+        assert!(!sm.is_running());
+    }
+
+    #[test]
+    fn quit_before_stopping() {
+        let (mut sm, calls) = recording_machine(&["a", "b", "c"]);
+        sm.stop(&mut ());
+        assert_calls(
+            &calls,
+            &[
+                ("a", Quit),
+                ("b", Quit),
+                ("c", Quit),
+                ("c", Stop),
+                ("b", Stop),
+                ("a", Stop),
+            ],
+        );
+
+        let (mut sm, calls) = recording_machine(&["a", "b"]);
+        sm.pop(&mut ());
+        sm.pop(&mut ());
+        assert_calls(&calls, &[("b", Stop), ("a", Resume), ("a", Stop)]);
+        assert_eq!(sm.exit_reason(), Some(ExitReason::LastStatePopped));
+    }
+
+    pub struct Counter;
+
+    impl State<StateData> for Counter {
+        fn update(&mut self, data: &mut StateData) -> StateTransition<StateData> {
+            data.0 += 1;
+            StateTransition::Push(Box::new(Menu))
+        }
+
+        fn shadow_update(&mut self, data: &mut StateData) {
+            data.0 += 1;
+        }
+    }
+
+    pub struct Menu;
+
+    impl State<StateData> for Menu {
+        fn shadow_update(&mut self, data: &mut StateData) {
+            data.1 += 1;
+        }
+    }
+
+    #[test]
+    fn shadow_update() {
+        let mut sm = StateMachine::<StateData>::default();
+        let mut state_data = (0, 0);
+
+        sm.push(Box::new(Counter), &mut state_data);
+        sm.update(&mut state_data);
+        assert_eq!(state_data, (1, 0));
+
+        sm.update(&mut state_data);
+        sm.update(&mut state_data);
+        assert_eq!(state_data, (3, 0));
+    }
+
+    #[derive(PartialEq)]
+    pub enum Input {
+        Escape,
+        MouseMove,
+    }
+
+    pub struct Game;
+
+    impl State<Log, Input> for Game {
+        fn on_resume(&mut self, log: &mut Log) -> StateTransition<Log, Input> {
+            log.push("game:resume".to_string());
+            StateTransition::None
+        }
+
+        fn handle_event(&mut self, event: &Input, log: &mut Log) -> EventOutcome<Log, Input> {
+            match event {
+                Input::MouseMove => {
+                    log.push("game:mouse".to_string());
+                    EventOutcome::Consumed(StateTransition::None)
+                }
+                Input::Escape => EventOutcome::Ignored,
+            }
+        }
+    }
+
+    pub struct PauseMenu;
+
+    impl State<Log, Input> for PauseMenu {
+        fn handle_event(&mut self, event: &Input, log: &mut Log) -> EventOutcome<Log, Input> {
+            if *event == Input::Escape {
+                log.push("pause:escape".to_string());
+                EventOutcome::Consumed(StateTransition::Pop)
+            } else {
+                EventOutcome::Ignored
+            }
+        }
+    }
+
+    #[test]
+    fn handle_event() {
+        let mut sm = StateMachine::<Log, Input>::default();
+        let mut log = Log::new();
+        sm.push(Box::new(Game), &mut log);
+        sm.push(Box::new(PauseMenu), &mut log);
+
+        sm.handle_event(&Input::MouseMove, &mut log);
+        assert_eq!(log, vec!["game:mouse"]);
+
+        log.clear();
+        sm.handle_event(&Input::Escape, &mut log);
+        assert_eq!(log, vec!["pause:escape", "game:resume"]);
+
+        log.clear();
+        sm.handle_event(&Input::Escape, &mut log);
+        assert!(log.is_empty());
+        assert!(sm.is_running());
+    }
+
+    #[test]
+    fn queued_events() {
+        let mut sm = StateMachine::<Log, Input>::default();
+        let mut log = Log::new();
+        sm.push(Box::new(Game), &mut log);
+        sm.push(Box::new(PauseMenu), &mut log);
+
+        sm.push_event(Input::Escape);
+        sm.push_event(Input::Escape);
+        sm.push_event(Input::MouseMove);
+        assert_eq!(sm.pending_events().len(), 3);
+        assert!(log.is_empty());
+
+        // The second escape reaches the game once the pause menu is popped.
+        sm.update(&mut log);
+        assert_eq!(log, vec!["pause:escape", "game:resume", "game:mouse"]);
+        assert!(sm.pending_events().is_empty());
+
+        log.clear();
+        sm.push_event(Input::MouseMove);
+        sm.clear_events();
+        sm.update(&mut log);
+        assert!(log.is_empty());
+    }
+
+    /// The input of a frame, given to the states without being in the state data.
+    struct Snapshot {
+        jump: bool,
+    }
+
+    /// Jumps when the input of the frame says so.
+    struct Player;
+
+    impl Player {
+        fn read(&self, call: &str, extra: &dyn Any, log: &mut Log) {
+            if extra
+                .downcast_ref::<Snapshot>()
+                .is_some_and(|input| input.jump)
+            {
+                log.push(format!("player:{}:jump", call));
+            }
+        }
+    }
+
+    impl State<Log, Input> for Player {
+        fn try_update_with_extra(
+            &mut self,
+            extra: &dyn Any,
+            _context: &StateContext,
+            log: &mut Log,
+        ) -> Result<StateTransition<Log, Input>, BoxError> {
+            self.read("update", extra, log);
+            Ok(StateTransition::None)
+        }
+
+        #[cfg(feature = "time")]
+        fn fixed_update_with_extra(
+            &mut self,
+            extra: &dyn Any,
+            log: &mut Log,
+        ) -> StateTransition<Log, Input> {
+            self.read("fixed", extra, log);
+            StateTransition::None
+        }
+
+        fn handle_event_with_extra(
+            &mut self,
+            _event: &Input,
+            extra: &dyn Any,
+            log: &mut Log,
+        ) -> EventOutcome<Log, Input> {
+            self.read("event", extra, log);
+            EventOutcome::Consumed(StateTransition::None)
+        }
+    }
+
+    /// Drawn over the player, without caring about the input.
+    struct Hud;
+
+    impl State<Log, Input> for Hud {
+        fn is_transparent(&self) -> bool {
+            true
+        }
+
+        fn update(&mut self, log: &mut Log) -> StateTransition<Log, Input> {
+            log.push("hud:update".to_string());
+            StateTransition::None
+        }
+    }
+
+    #[test]
+    fn update_with_extra() {
+        let mut sm = StateMachine::<Log, Input>::default();
+        let mut log = Log::new();
+        sm.push(Box::new(Player), &mut log);
+        sm.push(Box::new(Hud), &mut log);
+
+        sm.push_event(Input::Escape);
+        sm.update_with_extra(&Snapshot { jump: true }, &mut log);
+        assert_eq!(
+            log,
+            vec!["player:event:jump", "player:update:jump", "hud:update"]
+        );
+
+        log.clear();
+        sm.update_with_extra(&Snapshot { jump: false }, &mut log);
+        sm.update(&mut log);
+        assert_eq!(log, vec!["hud:update", "hud:update"]);
+
+        log.clear();
+        sm.handle_event_with_extra(&Input::MouseMove, &Snapshot { jump: true }, &mut log);
+        sm.handle_event(&Input::MouseMove, &mut log);
+        assert_eq!(log, vec!["player:event:jump"]);
+    }
+
+    #[cfg(feature = "time")]
+    #[test]
+    fn fixed_update_with_extra() {
+        let mut sm = StateMachine::<Log, Input>::default();
+        let mut log = Log::new();
+        sm.set_fixed_timestep(Duration::from_millis(10));
+        sm.push(Box::new(Player), &mut log);
+        sm.update_with_dt_and_extra(
+            Duration::from_millis(20),
+            &Snapshot { jump: true },
+            &mut log,
+        );
+        assert_eq!(
+            log,
+            vec![
+                "player:fixed:jump",
+                "player:fixed:jump",
+                "player:update:jump"
+            ]
+        );
+    }
+
+    #[cfg(feature = "time")]
+    pub struct Physics;
+
+    #[cfg(feature = "time")]
+    impl State<StateData> for Physics {
+        fn fixed_update(&mut self, data: &mut StateData) -> StateTransition<StateData> {
+            data.0 += 1;
+            if data.0 == 4 {
+                StateTransition::Pop
+            } else {
+                StateTransition::None
+            }
+        }
+
+        fn update(&mut self, data: &mut StateData) -> StateTransition<StateData> {
+            data.1 += 1;
+            StateTransition::None
+        }
+    }
+
+    #[cfg(feature = "time")]
+    #[test]
+    fn fixed_update() {
+        let mut sm = StateMachine::<StateData>::default();
+        sm.set_fixed_timestep(Duration::from_millis(10));
+        let mut state_data = (0, 0);
+        sm.push(Box::new(Physics), &mut state_data);
+
+        sm.update_with_dt(Duration::from_millis(5), &mut state_data);
+        assert_eq!(state_data, (0, 1));
+
+        sm.update_with_dt(Duration::from_millis(7), &mut state_data);
+        assert_eq!(state_data, (1, 2));
+
+        sm.update_with_dt(Duration::from_millis(20), &mut state_data);
+        assert_eq!(state_data, (3, 3));
+
+        // Pops itself on the fourth step, the remaining steps are not run.
+        sm.update_with_dt(Duration::from_millis(30), &mut state_data);
+        assert_eq!(state_data, (4, 3));
+        assert!(!sm.is_running());
+    }
+
+    #[cfg(feature = "time")]
+    #[test]
+    fn fixed_update_max_steps() {
+        let mut sm = StateMachine::<StateData>::default();
+        sm.set_fixed_timestep(Duration::from_millis(10));
+        sm.set_max_fixed_steps(2);
+        let mut state_data = (-100, 0);
+        sm.push(Box::new(Physics), &mut state_data);
+
+        sm.update_with_dt(Duration::from_millis(55), &mut state_data);
+        assert_eq!(state_data, (-98, 1));
+
+        // Only the 5ms remainder was kept.
+        sm.update_with_dt(Duration::from_millis(5), &mut state_data);
+        assert_eq!(state_data, (-97, 2));
+    }
+
+    #[cfg(feature = "time")]
+    #[test]
+    fn interpolation_alpha() {
+        /// Keeps the alpha it was rendered with.
+        struct Interpolated;
+
+        impl State<Vec<f32>> for Interpolated {
+            fn render_with_context(&mut self, context: &StateContext, alphas: &mut Vec<f32>) {
+                alphas.push(context.interpolation_alpha());
+            }
+        }
+
+        let mut alphas = Vec::new();
+        let mut sm = StateMachine::<Vec<f32>>::default();
+        sm.set_fixed_timestep(Duration::from_millis(10));
+        sm.set_max_fixed_steps(2);
+        sm.push(Box::new(Interpolated), &mut alphas);
+        assert_eq!(sm.interpolation_alpha(), 0.0);
+
+        // The last one runs out of steps, and only keeps the remainder.
+        for dt in [4, 4, 4, 25, 10, 47] {
+            sm.update_with_dt(Duration::from_millis(dt), &mut alphas);
+            sm.render(&mut alphas);
+        }
+        let expected = [0.4, 0.8, 0.2, 0.7, 0.7, 0.4];
+        assert_eq!(alphas.len(), expected.len());
+        for (alpha, expected) in alphas.iter().zip(&expected) {
+            assert!((alpha - expected).abs() < 1e-6, "{} != {}", alpha, expected);
+        }
+        assert!((sm.interpolation_alpha() - 0.4).abs() < 1e-6);
+        sm.update_with_dt(Duration::from_nanos(5_999_999), &mut alphas);
+        assert!(sm.interpolation_alpha() < 1.0);
+    }
+
+    #[cfg(feature = "time")]
+    pub struct Autosave;
+
+    #[cfg(feature = "time")]
+    impl State<StateData> for Autosave {
+        fn update_interval(&self) -> Option<Duration> {
+            Some(Duration::from_millis(100))
+        }
+
+        fn update(&mut self, data: &mut StateData) -> StateTransition<StateData> {
+            data.0 += 1;
+            StateTransition::None
+        }
+
+        fn shadow_update(&mut self, data: &mut StateData) {
+            data.0 += 1;
+        }
+    }
+
+    #[cfg(feature = "time")]
+    pub struct Play;
+
+    #[cfg(feature = "time")]
+    impl State<StateData> for Play {
+        fn update(&mut self, data: &mut StateData) -> StateTransition<StateData> {
+            data.1 += 1;
+            StateTransition::None
+        }
+    }
+
+    #[cfg(feature = "time")]
+    #[test]
+    fn update_interval() {
+        let mut sm = StateMachine::<StateData>::default();
+        let mut state_data = (0, 0);
+        let frame = Duration::from_millis(30);
+        sm.push(Box::new(Autosave), &mut state_data);
+        sm.push(Box::new(Play), &mut state_data);
+
+        for _ in 0..10 {
+            sm.update_with_dt(frame, &mut state_data);
+        }
+        assert_eq!(state_data, (2, 10));
+
+        // Resuming restarts the interval, even if 60ms had already elapsed.
+        sm.transition(StateTransition::Pop, &mut state_data);
+        for _ in 0..3 {
+            sm.update_with_dt(frame, &mut state_data);
+        }
+        assert_eq!(state_data, (2, 10));
+        sm.update_with_dt(frame, &mut state_data);
+        assert_eq!(state_data, (3, 10));
+
+        // Without a frame time, the intervals are ignored.
+        sm.update(&mut state_data);
+        assert_eq!(state_data, (4, 10));
+    }
+
+    #[test]
+    fn pop_n_whole_stack() {
+        let (mut sm, calls) = recording_machine(&["a", "b"]);
+
+        sm.transition(StateTransition::PopN(2), &mut ());
+        assert_calls(&calls, &[("b", Stop), ("a", Stop)]);
+        assert!(!sm.is_running());
+    }
+
+    #[test]
+    fn pop_n_past_stack() {
+        let (mut sm, calls) = recording_machine(&["a", "b"]);
+
+        sm.transition(StateTransition::PopN(5), &mut ());
+        assert_calls(&calls, &[("b", Stop), ("a", Stop)]);
+        assert!(!sm.is_running());
+    }
+
+    #[test]
+    fn new_and_start() {
+        let calls = CallLog::default();
+        let mut sm = StateMachine::new(recording("a", &calls));
+        assert!(!sm.is_running());
+
+        sm.update(&mut ());
+        assert_calls(&calls, &[]);
+
+        assert!(sm.start(&mut ()));
+        assert_calls(&calls, &[("a", Start)]);
+        assert!(sm.is_running());
+
+        assert!(!sm.start(&mut ()));
+        assert_calls(&calls, &[]);
+        assert!(!StateMachine::<()>::default().start(&mut ()));
+    }
+
+    pub struct Popper;
+
+    impl<S: ?Sized> State<S> for Popper {
+        fn update(&mut self, _state_data: &mut S) -> StateTransition<S> {
+            StateTransition::Pop
+        }
+    }
+
+    pub struct Quitter;
+
+    impl<S: ?Sized> State<S> for Quitter {
+        fn update(&mut self, _state_data: &mut S) -> StateTransition<S> {
+            StateTransition::Sequence(vec![StateTransition::Quit])
+        }
+    }
+
+    #[test]
+    fn update_status() {
+        let (mut sm, calls) = recording_machine(&["a"]);
+        assert_eq!(sm.update(&mut ()), MachineStatus::Running);
+
+        sm.push(Box::new(Popper), &mut ());
+        assert_eq!(
+            sm.update(&mut ()),
+            MachineStatus::Transitioned(TransitionKind::Pop)
+        );
+        assert_eq!(sm.update(&mut ()), MachineStatus::Running);
+
+        sm.transition(StateTransition::Pop, &mut ());
+        assert_eq!(
+            sm.update(&mut ()),
+            MachineStatus::Stopped(StopReason::EmptyStack)
+        );
+
+        sm.push(Box::new(Quitter), &mut ());
+        assert_eq!(sm.update(&mut ()), MachineStatus::Stopped(StopReason::Quit));
+        assert_calls(
+            &calls,
+            &[
+                ("a", Update),
+                ("a", Pause),
+                ("a", ShadowUpdate),
+                ("a", Resume),
+                ("a", Update),
+                ("a", Stop),
+            ],
+        );
+
+        let mut sm = StateMachine::<()>::new(Box::new(Quitter));
+        assert_eq!(
+            sm.update(&mut ()),
+            MachineStatus::Stopped(StopReason::NotStarted)
+        );
+    }
+
+    #[test]
+    fn public_pop_and_switch() {
+        let calls = CallLog::default();
+        let mut sm = StateMachine::<()>::default();
+
+        sm.push(recording("a", &calls), &mut ());
+        sm.push(recording("b", &calls), &mut ());
+        assert_calls(&calls, &[("a", Start), ("a", Pause), ("b", Start)]);
+
+        assert!(sm.pop(&mut ()));
+        assert_calls(&calls, &[("b", Stop), ("a", Resume)]);
+
+        sm.switch(recording("c", &calls), &mut ());
+        assert_calls(&calls, &[("a", Stop), ("c", Start)]);
+
+        assert!(sm.pop(&mut ()));
+        assert!(!sm.pop(&mut ()));
+        assert_calls(&calls, &[("c", Stop)]);
+    }
+
+    pub struct Updated(&'static str);
+
+    impl State<Log> for Updated {
+        fn update(&mut self, log: &mut Log) -> StateTransition<Log> {
+            log.push(format!("{}:update", self.0));
+            StateTransition::None
+        }
+    }
+
+    #[test]
+    fn queue_transition() {
+        let mut log = Log::new();
+        let mut sm = StateMachine::<Log>::default();
+
+        // Queueing on a stopped machine starts it again on the next update.
+        sm.queue_transition(StateTransition::Push(Box::new(Updated("a"))));
+        assert_eq!(sm.pending_transitions(), 1);
+        assert!(log.is_empty());
+        assert_eq!(
+            sm.update(&mut log),
+            MachineStatus::Transitioned(TransitionKind::Push)
+        );
+        assert_eq!(log, vec!["a:update"]);
+        assert_eq!(sm.pending_transitions(), 0);
+
+        log.clear();
+        sm.queue_transition(StateTransition::Push(Box::new(Updated("b"))));
+        sm.queue_transition(StateTransition::Quit);
+        sm.queue_transition(StateTransition::Push(Box::new(Updated("c"))));
+        assert_eq!(
+            sm.update(&mut log),
+            MachineStatus::Stopped(StopReason::Quit)
+        );
+        assert!(log.is_empty());
+        assert_eq!(sm.pending_transitions(), 0);
+    }
+
+    #[test]
+    fn queued_push_not_shadow_updated() {
+        let (mut sm, calls) = recording_machine(&["world"]);
+        sm.queue_transition(StateTransition::Push(recording("dialog", &calls)));
+        sm.queue_transition(StateTransition::Push(recording("toast", &calls)));
+        sm.update(&mut ());
+        assert_calls(
+            &calls,
+            &[
+                ("world", Pause),
+                ("dialog", Start),
+                ("dialog", Pause),
+                ("toast", Start),
+                ("world", ShadowUpdate),
+                ("toast", Update),
+            ],
+        );
+
+        sm.update(&mut ());
+        assert_calls(
+            &calls,
+            &[
+                ("world", ShadowUpdate),
+                ("dialog", ShadowUpdate),
+                ("toast", Update),
+            ],
+        );
+    }
+
+    #[test]
+    fn fallible_operations() {
+        let (mut sm, calls) = recording_machine(&["a"]);
+
+        assert_eq!(sm.try_update(&mut ()), Ok(MachineStatus::Running));
+        assert_eq!(sm.try_pop(&mut ()), Ok(()));
+        assert_eq!(sm.try_pop(&mut ()), Err(TransitionError::EmptyStack));
+        assert_eq!(sm.try_update(&mut ()), Err(TransitionError::MachineStopped));
+
+        sm.queue_transition(StateTransition::Push(recording("b", &calls)));
+        assert_eq!(
+            sm.try_update(&mut ()),
+            Ok(MachineStatus::Transitioned(TransitionKind::Push))
+        );
+        assert_eq!(
+            TransitionError::EmptyStack.to_string(),
+            "the state stack is empty"
+        );
+    }
+
+    /// Panics in the named callback, records the others like a `RecordingState` named "p".
+    #[cfg(feature = "catch_unwind")]
+    pub struct Panicky(&'static str, RecordingState<()>);
+
+    #[cfg(feature = "catch_unwind")]
+    impl Panicky {
+        fn new(panics_in: &'static str, calls: &CallLog) -> Self {
+            Panicky(panics_in, RecordingState::new("p", calls))
+        }
+
+        fn call(&self, callback: &str) {
+            if self.0 == callback {
+                panic!("p panicked in {}", callback);
+            }
+        }
+    }
+
+    #[cfg(feature = "catch_unwind")]
+    impl State<()> for Panicky {
+        fn on_start(&mut self, state_data: &mut ()) -> StateTransition<()> {
+            self.call("start");
+            self.1.on_start(state_data)
+        }
+
+        fn on_stop(&mut self, state_data: &mut ()) -> StateTransition<()> {
+            self.call("stop");
+            self.1.on_stop(state_data)
+        }
+
+        fn on_pause(&mut self, state_data: &mut ()) {
+            self.call("pause");
+            self.1.on_pause(state_data);
+        }
+
+        fn on_resume(&mut self, state_data: &mut ()) -> StateTransition<()> {
+            self.call("resume");
+            self.1.on_resume(state_data)
+        }
+
+        fn update(&mut self, state_data: &mut ()) -> StateTransition<()> {
+            self.call("update");
+            self.1.update(state_data)
+        }
+
+        fn shadow_update(&mut self, state_data: &mut ()) {
+            self.call("shadow");
+            self.1.shadow_update(state_data);
+        }
+
+        fn handle_event(&mut self, _event: &(), _state_data: &mut ()) -> EventOutcome<()> {
+            self.call("event");
+            EventOutcome::Ignored
+        }
+    }
+
+    #[cfg(feature = "catch_unwind")]
+    fn panicky_machine(panics_in: &'static str) -> (StateMachine<()>, CallLog) {
+        let (mut sm, calls) = recording_machine(&["a"]);
+        sm.set_panic_policy(PanicPolicy::RemoveState);
+        sm.push(Box::new(Panicky::new(panics_in, &calls)), &mut ());
+        calls.lock().unwrap().clear();
+        (sm, calls)
+    }
+
+    #[cfg(feature = "catch_unwind")]
+    #[test]
+    fn panic_in_update() {
+        let (mut sm, calls) = panicky_machine("update");
+
+        sm.update(&mut ());
+        assert_calls(&calls, &[("a", ShadowUpdate), ("a", Resume)]);
+        assert!(sm.take_panic().is_some());
+        assert!(sm.take_panic().is_none());
+
+        sm.pop(&mut ());
+        assert_calls(&calls, &[("a", Stop)]);
+    }
+
+    #[cfg(feature = "catch_unwind")]
+    #[test]
+    fn panic_in_lifecycle() {
+        let (mut sm, calls) = recording_machine(&["a"]);
+        sm.set_panic_policy(PanicPolicy::RemoveState);
+        sm.push(Box::new(Panicky::new("start", &calls)), &mut ());
+        assert_calls(&calls, &[("a", Pause), ("a", Resume)]);
+        assert!(sm.take_panic().is_some());
+        assert!(sm.is_running());
+
+        let (mut sm, calls) = panicky_machine("stop");
+        sm.pop(&mut ());
+        assert_calls(&calls, &[("a", Resume)]);
+        assert!(sm.take_panic().is_some());
+
+        let (mut sm, calls) = panicky_machine("pause");
+        sm.push(recording("b", &calls), &mut ());
+        assert_calls(&calls, &[("b", Start)]);
+        sm.pop(&mut ());
+        assert_calls(&calls, &[("b", Stop), ("a", Resume)]);
+        assert!(sm.take_panic().is_some());
+
+        let (mut sm, calls) = panicky_machine("resume");
+        sm.push(recording("b", &calls), &mut ());
+        sm.pop(&mut ());
+        assert_calls(
+            &calls,
+            &[("p", Pause), ("b", Start), ("b", Stop), ("a", Resume)],
+        );
+        assert!(sm.take_panic().is_some());
+    }
+
+    #[cfg(feature = "catch_unwind")]
+    #[test]
+    fn panic_in_covered_state() {
+        let (mut sm, calls) = panicky_machine("shadow");
+        sm.push(recording("b", &calls), &mut ());
+        sm.update(&mut ());
+        sm.pop(&mut ());
+        assert_calls(
+            &calls,
+            &[
+                ("p", Pause),
+                ("b", Start),
+                ("a", ShadowUpdate),
+                ("b", Update),
+                ("b", Stop),
+                ("a", Resume),
+            ],
+        );
+        assert!(sm.take_panic().is_some());
+
+        let (mut sm, calls) = panicky_machine("event");
+        sm.handle_event(&(), &mut ());
+        assert_calls(&calls, &[("a", Resume)]);
+        assert!(sm.take_panic().is_some());
+    }
+
+    #[cfg(feature = "catch_unwind")]
+    #[test]
+    fn panic_stops_machine() {
+        let (mut sm, calls) = panicky_machine("update");
+        sm.set_panic_policy(PanicPolicy::Stop);
+
+        assert_eq!(
+            sm.update(&mut ()),
+            MachineStatus::Stopped(StopReason::EmptyStack)
+        );
+        assert_calls(&calls, &[("a", ShadowUpdate), ("a", Resume), ("a", Stop)]);
+        assert!(sm.take_panic().is_some());
+    }
+
+    pub struct Loader(CallLog);
+
+    impl<S: ?Sized> State<S> for Loader {
+        fn on_stop(&mut self, _state_data: &mut S) -> StateTransition<S> {
+            self.0.lock().unwrap().push(("loader".to_string(), Stop));
+            StateTransition::None
+        }
+
+        fn try_update(&mut self, _state_data: &mut S) -> Result<StateTransition<S>, BoxError> {
+            Err("corrupted save".into())
+        }
+    }
+
+    pub struct ErrorHandler(CallLog);
+
+    impl State<Log> for ErrorHandler {
+        fn on_error(&mut self, error: &dyn std::error::Error, log: &mut Log) -> ErrorOutcome<Log> {
+            log.push(format!("handler:error:{}", error));
+            ErrorOutcome::Handled(StateTransition::Push(recording("screen", &self.0)))
+        }
+    }
+
+    #[test]
+    fn on_error() {
+        let mut log = Log::new();
+        let calls = CallLog::default();
+        let mut sm = StateMachine::<Log>::default();
+        sm.push(Box::new(ErrorHandler(calls.clone())), &mut log);
+        sm.push(recording("a", &calls), &mut log);
+        sm.push(Box::new(Loader(calls.clone())), &mut log);
+        calls.lock().unwrap().clear();
+
+        assert_eq!(
+            sm.update(&mut log),
+            MachineStatus::Transitioned(TransitionKind::Push)
+        );
+        assert_calls(
+            &calls,
+            &[
+                ("a", ShadowUpdate),
+                ("loader", Stop),
+                ("a", Resume),
+                ("a", Pause),
+                ("screen", Start),
+            ],
+        );
+        assert_eq!(log, ["handler:error:corrupted save"]);
+        assert!(sm.take_error().is_none());
+    }
+
+    #[test]
+    fn unhandled_error() {
+        let (mut sm, calls) = recording_machine(&["a"]);
+        sm.push(Box::new(Loader(calls.clone())), &mut ());
+        calls.lock().unwrap().clear();
+
+        assert_eq!(
+            sm.update(&mut ()),
+            MachineStatus::Stopped(StopReason::Error)
+        );
+        assert_calls(
+            &calls,
+            &[
+                ("a", ShadowUpdate),
+                ("loader", Stop),
+                ("a", Resume),
+                ("a", Stop),
+            ],
+        );
+        assert_eq!(sm.take_error().unwrap().to_string(), "corrupted save");
+    }
+
+    /// Fails to connect to its server when it starts.
+    pub struct Matchmaking(CallLog);
+
+    impl Matchmaking {
+        fn record(&self, call: LifecycleCall) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(("matchmaking".to_string(), call));
+        }
+    }
+
+    impl<S: ?Sized> State<S> for Matchmaking {
+        fn try_start(&mut self, _state_data: &mut S) -> Result<StateTransition<S>, BoxError> {
+            self.record(Start);
+            Err("connection refused".into())
+        }
+
+        fn on_stop(&mut self, _state_data: &mut S) -> StateTransition<S> {
+            self.record(Stop);
+            StateTransition::None
+        }
+    }
+
+    #[test]
+    fn failed_push() {
+        let mut log = Log::new();
+        let calls = CallLog::default();
+        let mut sm = StateMachine::<Log>::default();
+        sm.push(Box::new(ErrorHandler(calls.clone())), &mut log);
+        sm.push(recording("a", &calls), &mut log);
+        calls.lock().unwrap().clear();
+
+        let matchmaking = Matchmaking(calls.clone());
+        sm.queue_transition(StateTransition::Push(Box::new(matchmaking)));
+        sm.update(&mut log);
+        assert_calls(
+            &calls,
+            &[
+                ("a", Pause),
+                ("matchmaking", Start),
+                ("a", Resume),
+                ("a", Pause),
+                ("screen", Start),
+                ("a", ShadowUpdate),
+                ("screen", Update),
+            ],
+        );
+        assert_eq!(log, ["handler:error:connection refused"]);
+        assert!(!sm.contains_state::<Matchmaking>());
+        assert_eq!(sm.len(), 3);
+    }
+
+    #[test]
+    fn failed_switch_restores_old_stack() {
+        let mut log = Log::new();
+        let calls = CallLog::default();
+        let mut sm = StateMachine::<Log>::default();
+        sm.set_switch_ordering(SwitchOrdering::StartThenStop);
+        sm.push(Box::new(ErrorHandler(calls.clone())), &mut log);
+        sm.push(recording("a", &calls), &mut log);
+        calls.lock().unwrap().clear();
+
+        sm.switch(Box::new(Matchmaking(calls.clone())), &mut log);
+        assert_calls(
+            &calls,
+            &[("matchmaking", Start), ("a", Pause), ("screen", Start)],
+        );
+        assert_eq!(log, ["handler:error:connection refused"]);
+        assert_eq!(sm.len(), 3);
+        assert!(sm.take_error().is_none());
+    }
+
+    #[test]
+    fn failed_switch_stops_machine() {
+        let (mut sm, calls) = recording_machine(&["a"]);
+
+        let matchmaking = Matchmaking(calls.clone());
+        sm.queue_transition(StateTransition::Switch(Box::new(matchmaking)));
+        assert_eq!(
+            sm.update(&mut ()),
+            MachineStatus::Stopped(StopReason::Error)
+        );
+        assert_calls(&calls, &[("a", Stop), ("matchmaking", Start)]);
+        assert_eq!(sm.take_error().unwrap().to_string(), "connection refused");
+    }
+
+    #[test]
+    fn replace_all_order() {
+        let (mut sm, calls) = recording_machine(&["a", "b", "c"]);
+        sm.queue_transition(StateTransition::ReplaceAll(vec![
+            recording("x", &calls),
+            recording("y", &calls),
+            recording("z", &calls),
+        ]));
+        assert_eq!(
+            sm.update(&mut ()),
+            MachineStatus::Transitioned(TransitionKind::ReplaceAll)
+        );
+        assert_calls(
+            &calls,
+            &[
+                ("c", Stop),
+                ("b", Stop),
+                ("a", Stop),
+                ("x", Start),
+                ("x", Pause),
+                ("y", Start),
+                ("y", Pause),
+                ("z", Start),
+                ("z", Update),
+            ],
+        );
+        let names: Vec<&str> = sm.iter().map(|state| state.name()).collect();
+        assert_eq!(names, ["x", "y", "z"]);
+
+        sm.pop(&mut ());
+        assert_calls(&calls, &[("z", Stop), ("y", Resume)]);
+    }
+
+    #[test]
+    fn replace_all_stops_interrupts() {
+        let (mut sm, calls) = recording_machine(&["a"]);
+        sm.interrupt(recording("alert", &calls), &mut ());
+        calls.lock().unwrap().clear();
+
+        sm.replace_all(
+            vec![recording("x", &calls), recording("y", &calls)],
+            &mut (),
+        );
+        assert_calls(
+            &calls,
+            &[
+                ("alert", Stop),
+                ("a", Stop),
+                ("x", Start),
+                ("x", Pause),
+                ("y", Start),
+            ],
+        );
+        assert_eq!(sm.len(), 2);
+        assert_eq!(sm.active_name(), Some("y"));
+    }
+
+    #[test]
+    fn replace_all_with_nothing() {
+        let (mut sm, calls) = recording_machine(&["a", "b"]);
+        sm.queue_transition(StateTransition::ReplaceAll(vec![]));
+        sm.update(&mut ());
+        assert_calls(&calls, &[("b", Stop), ("a", Stop)]);
+        assert!(!sm.is_running());
+        assert_eq!(sm.exit_reason(), Some(ExitReason::LastStatePopped));
+
+        let (mut sm, calls) = recording_machine(&["a"]);
+        let fallback = calls.clone();
+        sm.set_fallback(move || recording("menu", &fallback));
+        sm.replace_all(vec![], &mut ());
+        assert_calls(&calls, &[("a", Stop), ("menu", Start)]);
+        assert_eq!(sm.active_name(), Some("menu"));
+    }
+
+    #[test]
+    fn introspection() {
+        let sm = StateMachine::<()>::default();
+        assert!(sm.is_empty());
+        assert_eq!(sm.active_index(), None);
+        assert!(sm.active().is_none());
+
+        let (mut sm, calls) = recording_machine(&["a", "b", "c"]);
+        assert_eq!(sm.len(), 3);
+        assert!(!sm.is_empty());
+        assert_eq!(sm.active_index(), Some(2));
+        assert!(sm.active().is_some());
+
+        for state in sm.iter_mut() {
+            state.on_resume(&mut ());
+        }
+        assert_calls(&calls, &[("a", Resume), ("b", Resume), ("c", Resume)]);
+        assert_eq!(sm.iter().count(), 3);
+    }
+
+    #[test]
+    fn debug_names() {
+        let (mut sm, _calls) = recording_machine(&["a", "b"]);
+        sm.push(Box::new(Popper), &mut ());
+
+        assert_eq!(
+            format!("{:?}", sm),
+            r#"StateMachine { stack: ["a", "b", "game_state_machine::tests::Popper"] }"#
+        );
+    }
+
+    #[test]
+    fn custom_transition() {
+        let (mut sm, calls) = recording_machine(&["a", "b"]);
+
+        let pushed = calls.clone();
+        let rebuild = move |sm: &mut StateMachine<()>, state_data: &mut ()| {
+            sm.stop(state_data);
+            for name in ["c", "d", "e"] {
+                sm.push(recording(name, &pushed), state_data);
+            }
+        };
+        sm.transition(StateTransition::Custom(Box::new(rebuild)), &mut ());
+        assert_calls(
+            &calls,
+            &[
+                ("a", Quit),
+                ("b", Quit),
+                ("b", Stop),
+                ("a", Stop),
+                ("c", Start),
+                ("c", Pause),
+                ("d", Start),
+                ("d", Pause),
+                ("e", Start),
+            ],
+        );
+        assert_eq!(
+            format!("{:?}", sm),
+            r#"StateMachine { stack: ["c", "d", "e"] }"#
+        );
+    }
+
+    #[test]
+    fn intercept_quit() {
+        let (mut sm, calls) = recording_machine(&["a"]);
+        sm.push(Box::new(Quitter), &mut ());
+        calls.lock().unwrap().clear();
+
+        sm.set_interceptor(|request, _| match request {
+            StateTransition::Quit => InterceptDecision::Block,
+            _ => InterceptDecision::Allow,
+        });
+        // The sequence is allowed, but not the quit in it.
+        assert_eq!(
+            sm.update(&mut ()),
+            MachineStatus::Transitioned(TransitionKind::Sequence)
+        );
+        assert_calls(&calls, &[("a", ShadowUpdate)]);
+        assert_eq!(sm.len(), 2);
+
+        sm.clear_interceptor();
+        assert_eq!(sm.update(&mut ()), MachineStatus::Stopped(StopReason::Quit));
+    }
+
+    /// Pushes a confirmation dialog, unless the quit was confirmed.
+    fn confirm_quit(calls: &CallLog) -> impl FnMut(&mut Log) -> QuitDecision<Log> {
+        let calls = calls.clone();
+        move |log| {
+            log.push("asked".to_string());
+            if log.iter().any(|call| call == "confirmed") {
+                QuitDecision::Proceed
+            } else {
+                QuitDecision::InterceptWith(StateTransition::Push(recording("confirm", &calls)))
+            }
+        }
+    }
+
+    #[test]
+    fn quit_handler_intercept() {
+        let mut log = Log::new();
+        let (mut sm, calls) = recording_machine(&["game"]);
+        sm.set_quit_handler(confirm_quit(&calls));
+
+        sm.queue_transition(StateTransition::Quit);
+        assert_eq!(
+            sm.update(&mut log),
+            MachineStatus::Transitioned(TransitionKind::Push)
+        );
+        assert_eq!(log, ["asked"]);
+        assert_calls(
+            &calls,
+            &[
+                ("game", Pause),
+                ("confirm", Start),
+                ("game", ShadowUpdate),
+                ("confirm", Update),
+            ],
+        );
+
+        log.clear();
+        sm.transition(StateTransition::QuitWith(Box::new(1)), &mut log);
+        sm.transition(StateTransition::PopN(2), &mut log);
+        assert_eq!(log, ["asked"]);
+        assert_calls(
+            &calls,
+            &[
+                ("confirm", Pause),
+                ("confirm", Start),
+                ("confirm", Stop),
+                ("confirm", Stop),
+                ("game", Resume),
+            ],
+        );
+        assert_eq!(sm.exit_reason(), None);
+    }
+
+    #[test]
+    fn quit_handler_proceed() {
+        let mut log = Log::new();
+        let (mut sm, calls) = recording_machine(&["game"]);
+        sm.set_quit_handler(confirm_quit(&calls));
+        log.push("confirmed".to_string());
+        sm.transition(StateTransition::Quit, &mut log);
+        assert_eq!(log, ["confirmed", "asked"]);
+        assert_calls(&calls, &[("game", Quit), ("game", Stop)]);
+        assert_eq!(sm.exit_reason(), Some(ExitReason::Quit));
+
+        // Popping the last state does not quit.
+        log.clear();
+        sm.push(recording("game", &calls), &mut log);
+        sm.pop(&mut log);
+        assert!(log.is_empty());
+        assert_calls(&calls, &[("game", Start), ("game", Stop)]);
+    }
+
+    #[test]
+    fn quit_handler_force() {
+        let mut log = Log::new();
+        let (mut sm, calls) = recording_machine(&["game"]);
+        sm.set_quit_handler(confirm_quit(&calls));
+        sm.force_quit(&mut log);
+        assert_calls(&calls, &[("game", Quit), ("game", Stop)]);
+        assert_eq!(sm.exit_reason(), Some(ExitReason::Quit));
+
+        sm.push(recording("game", &calls), &mut log);
+        sm.stop(&mut log);
+        assert_calls(&calls, &[("game", Start), ("game", Quit), ("game", Stop)]);
+
+        sm.push(recording("game", &calls), &mut log);
+        sm.clear_quit_handler();
+        sm.transition(StateTransition::Quit, &mut log);
+        assert!(!log.contains(&"asked".to_string()));
+        assert!(!sm.is_running());
+    }
+
+    #[test]
+    fn intercept_replace() {
+        let (mut sm, calls) = recording_machine(&["a"]);
+
+        let replacements = calls.clone();
+        let mut seen = 0;
+        sm.set_interceptor(move |request, _| {
+            seen += 1;
+            match request {
+                StateTransition::Push(_) => InterceptDecision::ReplaceWith(
+                    StateTransition::Switch(recording("b", &replacements)),
+                ),
+                // The replacement is also intercepted, but not its own replacement.
+                StateTransition::Switch(_) if seen < 3 => InterceptDecision::ReplaceWith(
+                    StateTransition::Push(recording("c", &replacements)),
+                ),
+                _ => InterceptDecision::Allow,
+            }
+        });
+        sm.transition(StateTransition::Push(recording("x", &calls)), &mut ());
+        assert_calls(&calls, &[("a", Pause), ("c", Start)]);
+
+        sm.transition(StateTransition::Push(recording("x", &calls)), &mut ());
+        assert_calls(&calls, &[("c", Stop), ("a", Stop), ("b", Start)]);
+        assert_eq!(format!("{:?}", sm), r#"StateMachine { stack: ["b"] }"#);
+    }
+
+    /// Logs around its update, returning a push once.
+    struct Hooked(Option<StateTransition<Log>>);
+
+    impl State<Log> for Hooked {
+        fn pre_update(&mut self, log: &mut Log) {
+            log.push("pre".to_string());
+        }
+
+        fn update(&mut self, log: &mut Log) -> StateTransition<Log> {
+            log.push("update".to_string());
+            self.0.take().unwrap_or(StateTransition::None)
+        }
+
+        fn post_update(&mut self, log: &mut Log, result: &StateTransition<Log>) {
+            log.push(format!("post:{:?}", result));
+        }
+    }
+
+    #[test]
+    fn update_hooks_order() {
+        use std::sync::{Arc, Mutex};
+
+        let mut log = Log::new();
+        let calls = CallLog::default();
+        let mut sm = StateMachine::<Log>::default();
+        sm.push(
+            Box::new(Hooked(Some(StateTransition::Push(recording("b", &calls))))),
+            &mut log,
+        );
+        let intercepted = Arc::new(Mutex::new(Vec::new()));
+        let seen = intercepted.clone();
+        sm.set_interceptor(move |_, log: &Log| {
+            seen.lock().unwrap().push(log.len());
+            InterceptDecision::Allow
+        });
+        let started = calls.clone();
+        sm.add_observer(Box::new(move |event: &TransitionEvent, log: &mut Log| {
+            // The pushed state was started before the observer is told.
+            assert_calls(&started, &[("b", Start)]);
+            log.push(format!("observer:{:?}", event.kind));
+        }));
+
+        sm.update(&mut log);
+        assert_eq!(
+            log,
+            vec!["pre", "update", r#"post:Push("b")"#, "observer:Push"]
+        );
+        // The interceptor got the transition once `post_update` saw it.
+        assert_eq!(*intercepted.lock().unwrap(), [3]);
+
+        // The covered state is only shadow updated, without the hooks.
+        log.clear();
+        sm.update(&mut log);
+        assert!(log.is_empty());
+        assert_calls(&calls, &[("b", Update)]);
+    }
+
+    #[cfg(feature = "log")]
+    mod logging {
+        use super::*;
+        use std::cell::RefCell;
+
+        thread_local! {
+            static RECORDS: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+        }
+
+        /// Keeps the records of each test thread apart, since the logger is global.
+        struct Capture;
+
+        impl log::Log for Capture {
+            fn enabled(&self, _metadata: &log::Metadata) -> bool {
+                true
+            }
+
+            fn log(&self, record: &log::Record) {
+                let line = format!("{} {}", record.level(), record.args());
+                RECORDS.with(|records| records.borrow_mut().push(line));
+            }
+
+            fn flush(&self) {}
+        }
+
+        static CAPTURE: Capture = Capture;
+
+        #[test]
+        fn push_pop() {
+            let _ = log::set_logger(&CAPTURE);
+            log::set_max_level(log::LevelFilter::Trace);
+
+            let (mut sm, calls) = recording_machine(&["a"]);
+            RECORDS.with(|records| records.borrow_mut().clear());
+            sm.transition(StateTransition::Push(recording("b", &calls)), &mut ());
+            sm.transition(StateTransition::Pop, &mut ());
+            assert_eq!(
+                RECORDS.with(|records| records.borrow().clone()),
+                vec![
+                    "TRACE on_pause: a",
+                    "TRACE on_start: b",
+                    "DEBUG Push: a -> b, depth 2",
+                    "TRACE on_stop: b",
+                    "TRACE on_resume: a",
+                    "DEBUG Pop: b -> a, depth 1",
+                ]
+            );
+        }
+    }
+
+    #[cfg(feature = "tracing")]
+    mod spans {
+        use super::*;
+        use std::sync::atomic::{AtomicU64, Ordering};
+        use std::sync::{Arc, Mutex};
+        use tracing::field::{Field, Visit};
+        use tracing::span::{Attributes, Id, Record};
+        use tracing::{Event, Metadata};
+
+        /// Writes the spans and events it is given as `name{field=value ...}`.
+        #[derive(Default)]
+        struct Collect {
+            lines: Arc<Mutex<Vec<String>>>,
+            next_id: AtomicU64,
+        }
+
+        #[derive(Default)]
+        struct Fields(Vec<String>);
+
+        impl Visit for Fields {
+            fn record_str(&mut self, field: &Field, value: &str) {
+                self.0.push(format!("{}={}", field.name(), value));
+            }
+
+            fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+                self.0.push(format!("{}={:?}", field.name(), value));
+            }
+        }
+
+        impl Collect {
+            fn write(&self, name: &str, fields: Fields) {
+                let line = format!("{}{{{}}}", name, fields.0.join(" "));
+                self.lines.lock().unwrap().push(line);
+            }
+        }
+
+        impl tracing::Subscriber for Collect {
+            fn enabled(&self, _metadata: &Metadata<'_>) -> bool {
+                true
+            }
+
+            fn new_span(&self, span: &Attributes<'_>) -> Id {
+                let mut fields = Fields::default();
+                span.record(&mut fields);
+                self.write(span.metadata().name(), fields);
+                Id::from_u64(self.next_id.fetch_add(1, Ordering::Relaxed) + 1)
+            }
+
+            fn record(&self, _span: &Id, _values: &Record<'_>) {}
+
+            fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+            fn event(&self, event: &Event<'_>) {
+                let mut fields = Fields::default();
+                event.record(&mut fields);
+                self.write("event", fields);
+            }
+
+            fn enter(&self, _span: &Id) {}
+
+            fn exit(&self, _span: &Id) {}
+        }
+
+        #[test]
+        fn push_update_pop() {
+            let collect = Collect::default();
+            let lines = collect.lines.clone();
+
+            let (mut sm, calls) = recording_machine(&["a"]);
+            tracing::subscriber::with_default(collect, || {
+                sm.transition(StateTransition::Push(recording("b", &calls)), &mut ());
+                sm.update(&mut ());
+                sm.transition(StateTransition::Pop, &mut ());
+            });
+            assert_eq!(
+                *lines.lock().unwrap(),
+                vec![
+                    "state.on_pause{state=a}",
+                    "state.on_start{state=b}",
+                    "event{message=transition kind=Push from=a to=b depth=2}",
+                    "state.shadow_update{state=a}",
+                    "state.update{state=b}",
+                    "state.on_stop_requested{state=b}",
+                    "state.on_stop{state=b}",
+                    "state.on_resume{state=a}",
+                    "event{message=transition kind=Pop from=b to=a depth=1}",
+                ]
+            );
+        }
+    }
+
+    /// The scopes do not change the calls made to the states.
+    #[cfg(feature = "profiling")]
+    #[test]
+    fn profiling_scopes() {
+        let (mut sm, calls) = recording_machine(&["a"]);
+        sm.push(recording("b", &calls), &mut ());
+        sm.update(&mut ());
+        sm.render(&mut ());
+        sm.pop(&mut ());
+        assert_calls(
+            &calls,
+            &[
+                ("a", Pause),
+                ("b", Start),
+                ("a", ShadowUpdate),
+                ("b", Update),
+                ("b", Stop),
+                ("a", Resume),
+            ],
+        );
+    }
+
+    #[test]
+    fn boxing_constructors() {
+        let calls = CallLog::default();
+        let mut sm = StateMachine::<()>::default();
+        sm.push_state(RecordingState::new("a", &calls), &mut ());
+        let b = RecordingState::new("b", &calls);
+        sm.transition(StateTransition::push(b), &mut ());
+        let active = sm.active_state_as::<RecordingState<()>>();
+        assert_eq!(active.map(|state| state.name()), Some("b"));
+        let c = RecordingState::new("c", &calls);
+        sm.transition(StateTransition::switch(c), &mut ());
+        assert_eq!(sm.len(), 1);
+        let active = sm.active_state_as::<RecordingState<()>>();
+        assert_eq!(active.map(|state| state.name()), Some("c"));
+    }
+
+    #[test]
+    fn active_accessors() {
+        let calls = CallLog::default();
+        let mut sm = StateMachine::<()>::default();
+        assert!(sm.active().is_none());
+        assert!(sm.active_mut().is_none());
+        assert_eq!(sm.active_name(), None);
+
+        sm.push(recording("a", &calls), &mut ());
+        sm.push(recording("b", &calls), &mut ());
+        assert_eq!(sm.active_name(), Some("b"));
+        sm.pop(&mut ());
+        assert_eq!(sm.active().map(|state| state.name()), Some("a"));
+        sm.switch(recording("c", &calls), &mut ());
+        assert_eq!(sm.active_name(), Some("c"));
+        calls.lock().unwrap().clear();
+        sm.active_mut().unwrap().on_pause(&mut ());
+        assert_calls(&calls, &[("c", Pause)]);
+    }
+
+    #[test]
+    fn downcast_active() {
+        let (mut sm, calls) = recording_machine(&["a"]);
+        assert!(sm.active_state_as::<Popper>().is_none());
+
+        sm.push(recording("b", &calls), &mut ());
+        let active = sm.active_state_as::<RecordingState<()>>();
+        assert_eq!(active.map(|state| state.name()), Some("b"));
+        calls.lock().unwrap().clear();
+        sm.active_state_as_mut::<RecordingState<()>>()
+            .unwrap()
+            .on_pause(&mut ());
+        assert_calls(&calls, &[("b", Pause)]);
+
+        sm.switch(Box::new(Popper), &mut ());
+        assert!(sm.active_state_as::<Popper>().is_some());
+        assert!(sm.active_state_as_mut::<RecordingState<()>>().is_none());
+
+        sm.stop(&mut ());
+        assert!(sm.active_state_as::<Popper>().is_none());
+    }
+
+    #[test]
+    fn find_by_type() {
+        let (mut sm, calls) = recording_machine(&["a"]);
+        sm.push(Box::new(Popper), &mut ());
+        sm.push(recording("b", &calls), &mut ());
+        sm.push(Box::new(Popper), &mut ());
+        calls.lock().unwrap().clear();
+
+        assert!(sm.contains_state::<RecordingState<()>>());
+        assert!(!sm.contains_state::<Quitter>());
+        assert_eq!(sm.position_of::<RecordingState<()>>(), Some(2));
+        assert_eq!(sm.position_of::<Popper>(), Some(3));
+        let found = sm.find_state::<RecordingState<()>>();
+        assert_eq!(found.map(|state| state.name()), Some("b"));
+        assert!(sm.find_state::<Quitter>().is_none());
+
+        sm.find_state_mut::<RecordingState<()>>()
+            .unwrap()
+            .on_resume(&mut ());
+        assert_calls(&calls, &[("b", Resume)]);
+        let names = sm.iter().map(|state| state.name()).collect::<Vec<_>>();
+        assert_eq!(names[..3], ["a", "game_state_machine::tests::Popper", "b"]);
+    }
+
+    #[test]
+    fn handles() {
+        let calls = CallLog::default();
+        let mut sm = StateMachine::<()>::default();
+        let a = sm.push(recording("a", &calls), &mut ());
+        let b = sm.push(recording("b", &calls), &mut ());
+        let c = sm.push(recording("c", &calls), &mut ());
+        calls.lock().unwrap().clear();
+
+        assert!(sm.is_alive(b));
+        assert_eq!(sm.state(b).map(|state| state.name()), Some("b"));
+        assert!(sm.remove(b, &mut ()));
+        assert_calls(&calls, &[("b", Stop)]);
+        assert!(!sm.is_alive(b));
+        assert!(sm.state(b).is_none());
+        assert!(!sm.remove(b, &mut ()));
+        assert_calls(&calls, &[]);
+
+        assert!(sm.remove(c, &mut ()));
+        assert_calls(&calls, &[("c", Stop), ("a", Resume)]);
+
+        // A new state does not reuse the handle of a removed one.
+        let d = sm.push(recording("d", &calls), &mut ());
+        assert_ne!(d, c);
+        assert!(!sm.is_alive(c));
+        assert!(sm.is_alive(a));
+        assert_eq!(format!("{:?}", sm), r#"StateMachine { stack: ["a", "d"] }"#);
+    }
+
+    #[test]
+    fn pop_tagged() {
+        let calls = CallLog::default();
+        let tagged = |name: &str, tags: &[&'static str]| {
+            let state = RecordingState::new(name, &calls);
+            let state = tags.iter().fold(state, |state, tag| state.with_tag(tag));
+            Box::new(state.returning(StateTransition::PopTagged("ui")))
+        };
+        let mut sm = StateMachine::<()>::default();
+        sm.push(tagged("world", &["gameplay"]), &mut ());
+        sm.push(tagged("hud", &["ui"]), &mut ());
+        sm.push(recording("dialog", &calls), &mut ());
+        sm.push(tagged("inventory", &["ui", "menu"]), &mut ());
+        calls.lock().unwrap().clear();
+
+        assert_eq!(sm.count_with_tag("ui"), 2);
+        assert_eq!(sm.count_with_tag("menu"), 1);
+        assert_eq!(sm.count_with_tag("debug"), 0);
+        assert_eq!(sm.pop_all_with_tag("ui", &mut ()), 2);
+        assert_calls(
+            &calls,
+            &[("inventory", Stop), ("hud", Stop), ("dialog", Resume)],
+        );
+
+        // The active state is not tagged, so it is not resumed.
+        sm.push(tagged("map", &["ui"]), &mut ());
+        sm.push(recording("toast", &calls), &mut ());
+        calls.lock().unwrap().clear();
+        assert_eq!(sm.pop_all_with_tag("ui", &mut ()), 1);
+        assert_calls(&calls, &[("map", Stop)]);
+        assert_eq!(sm.pop_all_with_tag("ui", &mut ()), 0);
+
+        sm.switch(tagged("hud", &["ui"]), &mut ());
+        assert_eq!(
+            sm.update(&mut ()),
+            MachineStatus::Stopped(StopReason::EmptyStack)
+        );
+    }
+
+    #[test]
+    fn remove_where() {
+        let (mut sm, calls) = recording_machine(&["world", "menu", "settings", "audio"]);
+        let named = |name: &'static str| move |state: &dyn State<()>| state.name() == name;
+
+        assert_eq!(sm.remove_where(named("menu"), &mut ()), 1);
+        assert_calls(&calls, &[("menu", Stop)]);
+        assert_eq!(sm.remove_where(named("world"), &mut ()), 1);
+        assert_calls(&calls, &[("world", Stop)]);
+        assert_eq!(sm.remove_where(named("credits"), &mut ()), 0);
+        assert_calls(&calls, &[]);
+
+        sm.push(recording("video", &calls), &mut ());
+        calls.lock().unwrap().clear();
+        assert_eq!(sm.remove_where(named("video"), &mut ()), 1);
+        assert_calls(&calls, &[("video", Stop), ("audio", Resume)]);
+        assert_eq!(sm.active_name(), Some("audio"));
+
+        assert_eq!(sm.remove_where(|_| true, &mut ()), 2);
+        assert_calls(&calls, &[("audio", Stop), ("settings", Stop)]);
+        assert!(!sm.is_running());
+        assert_eq!(sm.exit_reason(), Some(ExitReason::LastStatePopped));
+    }
+
+    #[test]
+    fn pop_to() {
+        let (mut sm, calls) = recording_machine(&["menu", "settings", "audio"]);
+
+        let to = |name: &str| StateTransition::PopTo(PopTarget::Name(name.to_string()));
+        assert_eq!(
+            sm.transition(to("audio"), &mut ()),
+            Some(TransitionKind::PopTo)
+        );
+        assert_calls(&calls, &[]);
+
+        assert_eq!(
+            sm.transition(to("menu"), &mut ()),
+            Some(TransitionKind::PopTo)
+        );
+        assert_calls(
+            &calls,
+            &[("audio", Stop), ("settings", Stop), ("menu", Resume)],
+        );
+
+        sm.push(Box::new(Popper), &mut ());
+        calls.lock().unwrap().clear();
+        let to_recording = StateTransition::PopTo(PopTarget::of::<RecordingState<()>>());
+        assert_eq!(
+            sm.transition(to_recording, &mut ()),
+            Some(TransitionKind::PopTo)
+        );
+        assert_calls(&calls, &[("menu", Resume)]);
+        let to_quitter = StateTransition::PopTo(PopTarget::of::<Quitter>());
+        assert_eq!(sm.transition(to_quitter, &mut ()), None);
+        assert_calls(&calls, &[("menu", Stop)]);
+        let error = sm.take_error().unwrap();
+        assert_eq!(
+            error.downcast_ref::<TransitionError>(),
+            Some(&TransitionError::NoMatchingState(PopTarget::of::<Quitter>()))
+        );
+    }
+
+    /// Logs the errors given to it, and handles them without a transition.
+    pub struct Tolerant;
+
+    impl State<Log> for Tolerant {
+        fn on_error(&mut self, error: &dyn std::error::Error, log: &mut Log) -> ErrorOutcome<Log> {
+            log.push(format!("tolerant:{}", error));
+            ErrorOutcome::Handled(StateTransition::None)
+        }
+    }
+
+    #[test]
+    fn pop_to_missing() {
+        let calls = CallLog::default();
+        let mut log = Log::new();
+        let mut sm = StateMachine::<Log>::default();
+        sm.push(Box::new(Tolerant), &mut log);
+        sm.push(recording("settings", &calls), &mut log);
+        sm.push(recording("audio", &calls), &mut log);
+        calls.lock().unwrap().clear();
+
+        let to_credits = PopTarget::Name("credits".to_string());
+        sm.queue_transition(StateTransition::PopTo(to_credits));
+        assert_eq!(sm.update(&mut log), MachineStatus::Running);
+        assert_calls(&calls, &[("settings", ShadowUpdate), ("audio", Update)]);
+        assert_eq!(log, ["tolerant:no state named credits is on the stack"]);
+        assert_eq!(sm.len(), 3);
+    }
+
+    #[test]
+    fn insert_below() {
+        let calls = CallLog::default();
+        let mut sm = StateMachine::<()>::default();
+        sm.transition(
+            StateTransition::InsertBelow(recording("a", &calls)),
+            &mut (),
+        );
+        assert_calls(&calls, &[("a", Start)]);
+
+        sm.transition(
+            StateTransition::InsertBelow(recording("b", &calls)),
+            &mut (),
+        );
+        assert_calls(&calls, &[("b", Start), ("b", Pause)]);
+        assert_eq!(format!("{:?}", sm), r#"StateMachine { stack: ["b", "a"] }"#);
+
+        sm.insert(1, recording("c", &calls), &mut ());
+        sm.insert(9, recording("d", &calls), &mut ());
+        assert_calls(
+            &calls,
+            &[("c", Start), ("c", Pause), ("a", Pause), ("d", Start)],
+        );
+
+        sm.pop(&mut ());
+        sm.pop(&mut ());
+        assert_calls(
+            &calls,
+            &[("d", Stop), ("a", Resume), ("a", Stop), ("c", Resume)],
+        );
+        assert_eq!(format!("{:?}", sm), r#"StateMachine { stack: ["b", "c"] }"#);
+    }
+
+    #[test]
+    fn push_many() {
+        let (mut sm, calls) = recording_machine(&["menu"]);
+        let states = vec![
+            recording("world", &calls),
+            recording("hud", &calls),
+            recording("intro", &calls),
+        ];
+        assert_eq!(
+            sm.transition(StateTransition::PushMany(states), &mut ()),
+            Some(TransitionKind::PushMany)
+        );
+        assert_calls(
+            &calls,
+            &[
+                ("menu", Pause),
+                ("world", Start),
+                ("world", Pause),
+                ("hud", Start),
+                ("hud", Pause),
+                ("intro", Start),
+            ],
+        );
+
+        assert_eq!(
+            sm.transition(StateTransition::PushMany(vec![]), &mut ()),
+            None
+        );
+        sm.push_many(vec![], &mut ());
+        assert_calls(&calls, &[]);
+        assert_eq!(sm.len(), 4);
+    }
+
+    #[test]
+    fn push_bottom() {
+        let calls = CallLog::default();
+        let mut sm = StateMachine::<()>::default();
+        sm.push_bottom(recording("music", &calls), &mut ());
+        assert_calls(&calls, &[("music", Start)]);
+
+        sm.push(recording("menu", &calls), &mut ());
+        calls.lock().unwrap().clear();
+        sm.transition(
+            StateTransition::PushBottom(recording("autosave", &calls)),
+            &mut (),
+        );
+        assert_calls(&calls, &[("autosave", Start), ("autosave", Pause)]);
+
+        sm.push(recording("game", &calls), &mut ());
+        sm.pop(&mut ());
+        calls.lock().unwrap().clear();
+        assert_eq!(
+            format!("{:?}", sm),
+            r#"StateMachine { stack: ["autosave", "music", "menu"] }"#
+        );
+        sm.stop(&mut ());
+        assert_calls(
+            &calls,
+            &[
+                ("autosave", Quit),
+                ("music", Quit),
+                ("menu", Quit),
+                ("menu", Stop),
+                ("music", Stop),
+                ("autosave", Stop),
+            ],
+        );
+    }
+
+    #[test]
+    fn interrupts() {
+        let (mut sm, calls) = recording_machine(&["world", "game"]);
+        assert!(!sm.is_interrupted());
+
+        sm.interrupt(recording("disconnected", &calls), &mut ());
+        sm.push(recording("help", &calls), &mut ());
+        assert!(sm.is_interrupted());
+        calls.lock().unwrap().clear();
+
+        // Switching from inside the interrupt keeps the interrupted states.
+        sm.transition(StateTransition::Switch(recording("retry", &calls)), &mut ());
+        assert_calls(
+            &calls,
+            &[("help", Stop), ("disconnected", Stop), ("retry", Start)],
+        );
+        assert_eq!(
+            format!("{:?}", sm),
+            r#"StateMachine { stack: ["world", "game", "retry"] }"#
+        );
+
+        // A nested interrupt only switches its own states.
+        sm.interrupt(recording("low battery", &calls), &mut ());
+        calls.lock().unwrap().clear();
+        sm.transition(
+            StateTransition::Switch(recording("charge", &calls)),
+            &mut (),
+        );
+        assert_calls(&calls, &[("low battery", Stop), ("charge", Start)]);
+
+        sm.pop(&mut ());
+        assert_calls(&calls, &[("charge", Stop), ("retry", Resume)]);
+        assert!(sm.is_interrupted());
+
+        sm.pop(&mut ());
+        assert_calls(&calls, &[("retry", Stop), ("game", Resume)]);
+        assert!(!sm.is_interrupted());
+
+        sm.transition(StateTransition::Switch(recording("menu", &calls)), &mut ());
+        assert_calls(&calls, &[("game", Stop), ("world", Stop), ("menu", Start)]);
+    }
+
+    /// Logs the reasons it is paused and resumed for.
+    struct Why(&'static str);
+
+    impl State<Log> for Why {
+        fn on_pause_with_reason(&mut self, reason: PauseReason, log: &mut Log) {
+            log.push(format!("{}:pause:{:?}", self.0, reason));
+        }
+
+        fn on_resume_with_reason(
+            &mut self,
+            reason: ResumeReason,
+            log: &mut Log,
+        ) -> StateTransition<Log> {
+            log.push(format!("{}:resume:{:?}", self.0, reason));
+            StateTransition::None
+        }
+    }
+
+    #[test]
+    fn pause_and_resume_reasons() {
+        let calls = CallLog::default();
+        let mut log = Log::new();
+        let mut sm = StateMachine::<Log>::default();
+        sm.push(Box::new(Why("world")), &mut log);
+        sm.push(Box::new(Why("game")), &mut log);
+        sm.interrupt(Box::new(Why("disconnected")), &mut log);
+        sm.transition(StateTransition::Switch(Box::new(Why("retry"))), &mut log);
+        sm.transition(
+            StateTransition::Replace(recording("help", &calls)),
+            &mut log,
+        );
+        sm.transition(
+            StateTransition::InsertBelow(Box::new(Why("hint"))),
+            &mut log,
+        );
+        assert_eq!(
+            log,
+            [
+                "world:pause:Covered",
+                "game:pause:Covered",
+                "hint:pause:Covered",
+            ]
+        );
+        assert_calls(&calls, &[("help", Start)]);
+
+        log.clear();
+        sm.pop(&mut log);
+        sm.pop(&mut log);
+        sm.transition(StateTransition::PopWith(Box::new(7)), &mut log);
+        assert_eq!(
+            log,
+            [
+                "hint:resume:Uncovered",
+                "game:resume:Uncovered",
+                "world:resume:Uncovered",
+            ]
+        );
+        assert_calls(&calls, &[("help", Stop)]);
+    }
+
+    /// Logs its lifecycle and updates, and returns its transition once.
+    struct Overlay(&'static str, bool, Option<StateTransition<Log>>);
+
+    impl State<Log> for Overlay {
+        fn name(&self) -> &str {
+            self.0
+        }
+
+        fn is_transparent(&self) -> bool {
+            self.1
+        }
+
+        fn on_start(&mut self, log: &mut Log) -> StateTransition<Log> {
+            log.push(format!("{}:start", self.0));
+            StateTransition::None
+        }
+
+        fn on_stop(&mut self, log: &mut Log) -> StateTransition<Log> {
+            log.push(format!("{}:stop", self.0));
+            StateTransition::None
+        }
+
+        fn on_pause(&mut self, log: &mut Log) {
+            log.push(format!("{}:pause", self.0));
+        }
+
+        fn on_resume(&mut self, log: &mut Log) -> StateTransition<Log> {
+            log.push(format!("{}:resume", self.0));
+            StateTransition::None
+        }
+
+        fn update(&mut self, log: &mut Log) -> StateTransition<Log> {
+            log.push(format!("{}:update", self.0));
+            self.2.take().unwrap_or(StateTransition::None)
+        }
+    }
+
+    #[test]
+    fn transparent() {
+        let mut log = Log::new();
+        let mut sm = StateMachine::<Log>::default();
+        sm.push(Box::new(Overlay("game", false, None)), &mut log);
+        sm.push(Box::new(Overlay("toast", true, None)), &mut log);
+        assert_eq!(log, vec!["game:start", "toast:start"]);
+
+        log.clear();
+        sm.update(&mut log);
+        assert_eq!(log, vec!["game:update", "toast:update"]);
+
+        log.clear();
+        sm.pop(&mut log);
+        assert_eq!(log, vec!["toast:stop"]);
+    }
+
+    #[test]
+    fn transparent_topmost_transition_wins() {
+        let mut log = Log::new();
+        let mut sm = StateMachine::<Log>::default();
+        let push = StateTransition::Push(Box::new(Overlay("pause", false, None)));
+        sm.push(Box::new(Overlay("game", false, Some(push))), &mut log);
+        let toast = Overlay("toast", true, Some(StateTransition::Pop));
+        sm.push(Box::new(toast), &mut log);
+
+        log.clear();
+        sm.update(&mut log);
+        assert_eq!(log, vec!["game:update", "toast:update", "toast:stop"]);
+        assert_eq!(sm.active().unwrap().name(), "game");
+    }
+
+    #[test]
+    fn opaque_over_transparent() {
+        let mut log = Log::new();
+        let mut sm = StateMachine::<Log>::default();
+        sm.push(Box::new(Overlay("menu", false, None)), &mut log);
+        sm.push(Box::new(Overlay("game", false, None)), &mut log);
+        sm.push(Box::new(Overlay("toast", true, None)), &mut log);
+
+        log.clear();
+        sm.push(Box::new(Overlay("pause", false, None)), &mut log);
+        assert_eq!(log, vec!["toast:pause", "game:pause", "pause:start"]);
+
+        log.clear();
+        sm.update(&mut log);
+        assert_eq!(log, vec!["pause:update"]);
+
+        log.clear();
+        sm.pop(&mut log);
+        assert_eq!(log, vec!["pause:stop", "game:resume", "toast:resume"]);
+
+        log.clear();
+        sm.transition(
+            StateTransition::Replace(Box::new(Overlay("hint", false, None))),
+            &mut log,
+        );
+        assert_eq!(log, vec!["toast:stop", "game:pause", "hint:start"]);
+    }
+
+    /// Renders its name, and blocks the rendering of the states below if asked.
+    struct Drawn(&'static str, bool);
+
+    impl State<Log> for Drawn {
+        fn render(&mut self, log: &mut Log) {
+            log.push(self.0.to_string());
+        }
+
+        fn blocks_rendering(&self) -> bool {
+            self.1
+        }
+    }
+
+    #[test]
+    fn render() {
+        let mut log = Log::new();
+        let mut sm = StateMachine::<Log>::default();
+        sm.push(Box::new(Drawn("world", false)), &mut log);
+        sm.push(Box::new(Drawn("hud", false)), &mut log);
+        sm.render(&mut log);
+        assert_eq!(log, vec!["world", "hud"]);
+
+        log.clear();
+        sm.push(Box::new(Drawn("menu", true)), &mut log);
+        sm.push(Box::new(Drawn("tooltip", false)), &mut log);
+        sm.render(&mut log);
+        assert_eq!(log, vec!["menu", "tooltip"]);
+        assert_eq!(sm.len(), 4);
+    }
+
+    /// Redirects to the next level when started, until the last one.
+    struct Redirect(u32);
+
+    impl State<Log> for Redirect {
+        fn on_start(&mut self, log: &mut Log) -> StateTransition<Log> {
+            log.push(format!("{}:start", self.0));
+            match self.0 {
+                3 => StateTransition::None,
+                n => StateTransition::Replace(Box::new(Redirect(n + 1))),
+            }
+        }
+
+        fn update(&mut self, log: &mut Log) -> StateTransition<Log> {
+            log.push(format!("{}:update", self.0));
+            StateTransition::None
+        }
+    }
+
+    #[test]
+    fn start_redirects() {
+        let mut log = Log::new();
+        let mut sm = StateMachine::<Log>::default();
+        sm.push(Box::new(Redirect(0)), &mut log);
+        assert_eq!(log, vec!["0:start", "1:start", "2:start", "3:start"]);
+        assert_eq!(sm.len(), 1);
+
+        log.clear();
+        let mut sm = StateMachine::<Log>::default();
+        sm.queue_transition(StateTransition::Push(Box::new(Redirect(1))));
+        assert_eq!(
+            sm.update(&mut log),
+            MachineStatus::Transitioned(TransitionKind::Replace)
+        );
+        assert_eq!(log, vec!["1:start", "2:start", "3:start", "3:update"]);
+    }
+
+    /// Replaces itself when started, forever.
+    struct Loop;
+
+    impl State<()> for Loop {
+        fn on_start(&mut self, _: &mut ()) -> StateTransition<()> {
+            StateTransition::Replace(Box::new(Loop))
+        }
+    }
+
+    #[test]
+    fn start_chain_limit() {
+        let mut sm = StateMachine::<()>::default();
+        sm.set_max_start_chain(4);
+        sm.push(Box::new(Loop), &mut ());
+        assert!(!sm.is_running());
+        let error = sm.take_error().unwrap();
+        assert_eq!(
+            error.downcast_ref::<TransitionError>(),
+            Some(&TransitionError::StartChainTooLong)
+        );
+    }
+
+    /// Pushes a summary when stopped.
+    struct Mission(CallLog);
+
+    impl State<()> for Mission {
+        fn on_stop(&mut self, _: &mut ()) -> StateTransition<()> {
+            self.0.lock().unwrap().push(("mission".to_string(), Stop));
+            StateTransition::Push(recording("summary", &self.0))
+        }
+    }
+
+    #[test]
+    fn stop_follow_up() {
+        let (mut sm, calls) = recording_machine(&["menu"]);
+        sm.push(Box::new(Mission(calls.clone())), &mut ());
+        calls.lock().unwrap().clear();
+        sm.pop(&mut ());
+        assert_calls(
+            &calls,
+            &[
+                ("mission", Stop),
+                ("menu", Resume),
+                ("menu", Pause),
+                ("summary", Start),
+            ],
+        );
+
+        let (mut sm, calls) = recording_machine(&["menu"]);
+        sm.push(Box::new(Mission(calls.clone())), &mut ());
+        sm.transition(StateTransition::Switch(recording("hub", &calls)), &mut ());
+        sm.update(&mut ());
+        assert_eq!(
+            sm.iter().map(|state| state.name()).collect::<Vec<_>>(),
+            vec!["hub", "summary"]
+        );
+    }
+
+    #[test]
+    fn stop_follow_up_ignored_on_quit() {
+        let calls = CallLog::default();
+        let mut sm = StateMachine::<()>::default();
+        sm.push(Box::new(Mission(calls.clone())), &mut ());
+        sm.stop(&mut ());
+        assert!(!sm.is_running());
+
+        sm.push(Box::new(Mission(calls.clone())), &mut ());
+        sm.queue_transition(StateTransition::Quit);
+        sm.update(&mut ());
+        assert!(!sm.is_running());
+        assert_calls(&calls, &[("mission", Stop), ("mission", Stop)]);
+    }
+
+    /// Returns its transition when resumed.
+    struct Resumes(&'static str, CallLog, fn(&CallLog) -> StateTransition<()>);
+
+    impl State<()> for Resumes {
+        fn on_stop(&mut self, _: &mut ()) -> StateTransition<()> {
+            self.1.lock().unwrap().push((self.0.to_string(), Stop));
+            StateTransition::None
+        }
+
+        fn on_resume(&mut self, _: &mut ()) -> StateTransition<()> {
+            self.1.lock().unwrap().push((self.0.to_string(), Resume));
+            (self.2)(&self.1)
+        }
+    }
+
+    #[test]
+    fn resume_transition() {
+        let (mut sm, calls) = recording_machine(&["lobby"]);
+        let pop = |_: &CallLog| StateTransition::Pop;
+        sm.push(Box::new(Resumes("shop", calls.clone(), pop)), &mut ());
+        sm.push(recording("confirm", &calls), &mut ());
+        calls.lock().unwrap().clear();
+        sm.pop(&mut ());
+        assert_calls(
+            &calls,
+            &[
+                ("confirm", Stop),
+                ("shop", Resume),
+                ("shop", Stop),
+                ("lobby", Resume),
+            ],
+        );
+
+        let receipt = |calls: &CallLog| StateTransition::Push(recording("receipt", calls));
+        sm.push(Box::new(Resumes("shop", calls.clone(), receipt)), &mut ());
+        sm.push(recording("confirm", &calls), &mut ());
+        sm.pop(&mut ());
+        assert_eq!(sm.active().unwrap().name(), "receipt");
+    }
+
+    #[test]
+    fn resume_chain_limit() {
+        let calls = CallLog::default();
+        let mut sm = StateMachine::<()>::default();
+        sm.set_max_start_chain(2);
+        for name in ["a", "b", "c", "d"] {
+            let pop = |_: &CallLog| StateTransition::Pop;
+            sm.push(Box::new(Resumes(name, calls.clone(), pop)), &mut ());
+        }
+        sm.push(recording("top", &calls), &mut ());
+        calls.lock().unwrap().clear();
+        sm.pop(&mut ());
+        assert_calls(
+            &calls,
+            &[
+                ("top", Stop),
+                ("d", Resume),
+                ("d", Stop),
+                ("c", Resume),
+                ("c", Stop),
+                ("b", Resume),
+                ("b", Stop),
+                ("a", Stop),
+            ],
+        );
+        assert!(!sm.is_running());
+        assert!(sm.take_error().is_some());
+    }
+
+    #[derive(Debug, PartialEq)]
+    struct Character(&'static str);
+
+    /// Keeps the character picked in the state above it.
+    struct Lobby(Option<Character>);
+
+    impl State<()> for Lobby {
+        fn on_resume_with(&mut self, payload: Payload, _: &mut ()) -> StateTransition<()> {
+            self.0 = payload.downcast().ok().map(|character| *character);
+            StateTransition::None
+        }
+    }
+
+    struct CharacterSelect;
+
+    impl State<()> for CharacterSelect {
+        fn update(&mut self, _: &mut ()) -> StateTransition<()> {
+            StateTransition::PopWith(Box::new(Character("knight")))
+        }
+    }
+
+    #[test]
+    fn pop_with_payload() {
+        let mut sm = StateMachine::<()>::default();
+        sm.push(Box::new(Lobby(None)), &mut ());
+        sm.push(Box::new(CharacterSelect), &mut ());
+        assert_eq!(
+            sm.update(&mut ()),
+            MachineStatus::Transitioned(TransitionKind::PopWith)
+        );
+        let lobby = sm.active_state_as::<Lobby>().unwrap();
+        assert_eq!(lobby.0, Some(Character("knight")));
+
+        sm.push(Box::new(CharacterSelect), &mut ());
+        sm.transition(StateTransition::Pop, &mut ());
+        assert!(sm.take_exit_payload().is_none());
+        sm.transition(StateTransition::Pop, &mut ());
+        sm.push(Box::new(CharacterSelect), &mut ());
+        sm.update(&mut ());
+        assert!(!sm.is_running());
+        let payload = sm.take_exit_payload().unwrap();
+        assert_eq!(payload.downcast_ref(), Some(&Character("knight")));
+    }
+
+    /// An expensive world, which goes back to the menu once and keeps its chunks when retained.
+    struct World {
+        chunks: Vec<u32>,
+        starts: u32,
+    }
+
+    impl State<()> for World {
+        fn on_start(&mut self, _: &mut ()) -> StateTransition<()> {
+            self.starts += 1;
+            if self.chunks.is_empty() {
+                self.chunks = (0..4).collect();
+            }
+            StateTransition::None
+        }
+
+        fn update(&mut self, _: &mut ()) -> StateTransition<()> {
+            if self.starts == 1 {
+                StateTransition::PopRetain
+            } else {
+                StateTransition::None
+            }
+        }
+    }
+
+    #[test]
+    fn pop_retained_and_push_again() {
+        let mut sm = StateMachine::<()>::default();
+        sm.push(Box::new(Lobby(None)), &mut ());
+        sm.push(
+            Box::new(World {
+                chunks: Vec::new(),
+                starts: 0,
+            }),
+            &mut (),
+        );
+        assert_eq!(
+            sm.update(&mut ()),
+            MachineStatus::Transitioned(TransitionKind::Pop)
+        );
+        assert_eq!(sm.len(), 1);
+        let world = sm.take_retained().unwrap();
+        assert!(sm.take_retained().is_none());
+
+        sm.push(world, &mut ());
+        let world = sm.active_state_as::<World>().unwrap();
+        assert_eq!((world.chunks.len(), world.starts), (4, 2));
+        let world = sm.pop_retained(&mut ()).unwrap();
+        assert!(sm.active_state_as::<Lobby>().is_some());
+        let world = world.as_ref().as_any().downcast_ref::<World>().unwrap();
+        assert_eq!(world.chunks, [0, 1, 2, 3]);
+        assert!(sm.pop_retained(&mut ()).is_some());
+        assert!(sm.pop_retained(&mut ()).is_none());
+    }
+
+    #[derive(Debug, PartialEq)]
+    enum Exit {
+        Desktop,
+    }
+
+    #[test]
+    fn quit_with_value() {
+        let (mut sm, calls) = recording_machine(&["a", "b"]);
+        let quit = StateTransition::QuitWith(Box::new(Exit::Desktop));
+        sm.push(
+            Box::new(RecordingState::new("c", &calls).returning(quit)),
+            &mut (),
+        );
+        calls.lock().unwrap().clear();
+        assert_eq!(sm.update(&mut ()), MachineStatus::Stopped(StopReason::Quit));
+        assert_calls(
+            &calls,
+            &[
+                ("a", LifecycleCall::ShadowUpdate),
+                ("b", LifecycleCall::ShadowUpdate),
+                ("c", LifecycleCall::Update),
+                ("a", LifecycleCall::Quit),
+                ("b", LifecycleCall::Quit),
+                ("c", LifecycleCall::Quit),
+                ("c", LifecycleCall::Stop),
+                ("b", LifecycleCall::Stop),
+                ("a", LifecycleCall::Stop),
+            ],
+        );
+        assert_eq!(sm.exit_reason(), Some(ExitReason::Quit));
+        let value = sm.take_exit_value().unwrap();
+        assert_eq!(value.downcast_ref(), Some(&Exit::Desktop));
+        assert!(sm.take_exit_value().is_none());
+    }
+
+    /// Counts its updates in the state data, returning the transitions of its script in turn.
+    struct Outro(Vec<StateTransition<u32>>);
+
+    impl State<u32> for Outro {
+        fn update(&mut self, updates: &mut u32) -> StateTransition<u32> {
+            *updates += 1;
+            if self.0.is_empty() {
+                StateTransition::None
+            } else {
+                self.0.remove(0)
+            }
+        }
+    }
+
+    fn delayed(after_updates: u32, then: StateTransition<u32>) -> StateTransition<u32> {
+        StateTransition::Delayed {
+            after_updates,
+            then: Box::new(then),
+        }
+    }
+
+    #[test]
+    fn delayed_transition() {
+        let mut updates = 0;
+        let mut sm = StateMachine::<u32>::default();
+        sm.push(Box::new(Outro(Vec::new())), &mut updates);
+        sm.push(
+            Box::new(Outro(vec![delayed(3, StateTransition::Pop)])),
+            &mut updates,
+        );
+        for _ in 0..3 {
+            assert_eq!(sm.update(&mut updates), MachineStatus::Running);
+        }
+        assert_eq!(sm.len(), 2);
+        assert_eq!(
+            sm.update(&mut updates),
+            MachineStatus::Transitioned(TransitionKind::Pop)
+        );
+        assert_eq!(sm.len(), 1);
+        assert_eq!(updates, 4);
+
+        sm.transition(delayed(0, StateTransition::Quit), &mut updates);
+        assert!(!sm.is_running());
+    }
+
+    #[test]
+    fn delayed_transition_overridden() {
+        let mut updates = 0;
+        let mut sm = StateMachine::<u32>::default();
+        let script = vec![
+            delayed(3, StateTransition::Quit),
+            StateTransition::None,
+            StateTransition::Push(Box::new(Outro(Vec::new()))),
+        ];
+        sm.push(Box::new(Outro(script)), &mut updates);
+        for _ in 0..3 {
+            sm.update(&mut updates);
+        }
+        assert_eq!(sm.len(), 2);
+        sm.pop(&mut updates);
+        for _ in 0..5 {
+            sm.update(&mut updates);
+        }
+        assert!(sm.is_running());
+    }
+
+    #[test]
+    fn delayed_transition_dropped_with_state() {
+        let mut updates = 0;
+        let mut sm = StateMachine::<u32>::default();
+        sm.push(
+            Box::new(Outro(vec![delayed(2, StateTransition::Quit)])),
+            &mut updates,
+        );
+        sm.update(&mut updates);
+        sm.switch(Box::new(Outro(Vec::new())), &mut updates);
+        for _ in 0..5 {
+            sm.update(&mut updates);
+        }
+        assert!(sm.is_running());
+        assert_eq!(updates, 6);
+    }
+
+    #[test]
+    fn push_with_builds_late() {
+        let (mut sm, calls) = recording_machine(&["menu"]);
+        // Checks the calls made before the state is built.
+        let build = |before: &'static [(&'static str, LifecycleCall)]| {
+            let calls = calls.clone();
+            Box::new(move |_: &mut ()| {
+                assert_calls(&calls, before);
+                recording("world", &calls)
+            })
+        };
+        let push = StateTransition::PushWith(build(&[("menu", Pause)]));
+        assert_calls(&calls, &[]);
+        sm.transition(push, &mut ());
+        assert_calls(&calls, &[("world", Start)]);
+
+        let switch = StateTransition::SwitchWith(build(&[("world", Stop), ("menu", Stop)]));
+        sm.transition(switch, &mut ());
+        assert_calls(&calls, &[("world", Start)]);
+        assert_eq!(sm.len(), 1);
+    }
+
+    /// Asks to save its changes before being stopped, until they are saved.
+    struct Editor {
+        saved: bool,
+        calls: CallLog,
+    }
+
+    impl State<Log> for Editor {
+        fn on_stop_requested(&mut self, log: &mut Log) -> StopDecision<Log> {
+            if self.saved {
+                return StopDecision::Allow;
+            }
+            log.push("editor:unsaved".to_string());
+            self.saved = true;
+            StopDecision::DenyWith(StateTransition::Push(recording("save", &self.calls)))
+        }
+
+        fn on_stop(&mut self, _log: &mut Log) -> StateTransition<Log> {
+            self.calls
+                .lock()
+                .unwrap()
+                .push(("editor".to_string(), Stop));
+            StateTransition::None
+        }
+    }
+
+    fn editor(calls: &CallLog) -> Box<Editor> {
+        Box::new(Editor {
+            saved: false,
+            calls: calls.clone(),
+        })
+    }
+
+    #[test]
+    fn stop_veto() {
+        let calls = CallLog::default();
+        let mut log = Log::new();
+        let mut sm = StateMachine::<Log>::default();
+        sm.push(editor(&calls), &mut log);
+        assert_eq!(
+            sm.transition(StateTransition::Pop, &mut log),
+            Some(TransitionKind::Push)
+        );
+        assert_eq!(log, vec!["editor:unsaved"]);
+        assert_calls(&calls, &[("save", Start)]);
+
+        sm.transition(StateTransition::Pop, &mut log);
+        sm.transition(StateTransition::Pop, &mut log);
+        assert_calls(&calls, &[("save", Stop), ("editor", Stop)]);
+        assert!(!sm.is_running());
+    }
+
+    #[test]
+    fn forced_stop() {
+        let calls = CallLog::default();
+        let mut log = Log::new();
+        let mut sm = StateMachine::<Log>::default();
+        sm.push(editor(&calls), &mut log);
+        sm.push(recording("tools", &calls), &mut log);
+        sm.stop(&mut log);
+        assert_eq!(sm.len(), 3);
+
+        sm.force_stop(&mut log);
+        assert!(!sm.is_running());
+
+        sm.push(editor(&calls), &mut log);
+        sm.set_stop_vetoes(false);
+        calls.lock().unwrap().clear();
+        sm.transition(StateTransition::Quit, &mut log);
+        assert_calls(&calls, &[("editor", Stop)]);
+    }
+
+    #[test]
+    fn exit_reasons() {
+        let (mut sm, calls) = recording_machine(&["a"]);
+        assert_eq!(sm.exit_reason(), None);
+        sm.transition(StateTransition::Quit, &mut ());
+        assert_eq!(sm.exit_reason(), Some(ExitReason::Quit));
+
+        sm.push(recording("a", &calls), &mut ());
+        assert_eq!(sm.exit_reason(), None);
+        sm.push(recording("b", &calls), &mut ());
+        sm.transition(StateTransition::PopN(2), &mut ());
+        assert_eq!(sm.exit_reason(), Some(ExitReason::LastStatePopped));
+
+        sm.push(recording("a", &calls), &mut ());
+        sm.transition(StateTransition::Switch(recording("b", &calls)), &mut ());
+        assert_eq!(sm.exit_reason(), None);
+        sm.stop(&mut ());
+        assert_eq!(sm.exit_reason(), Some(ExitReason::StoppedExternally));
+    }
+
+    #[test]
+    fn finalize() {
+        let (mut sm, calls) = recording_machine::<()>(&["a", "b"]);
+        sm.set_leak_check(true);
+        assert_eq!(sm.finalize(&mut ()), Some(ExitReason::StoppedExternally));
+        assert_calls(
+            &calls,
+            &[("a", Quit), ("b", Quit), ("b", Stop), ("a", Stop)],
+        );
+
+        let (mut sm, _) = recording_machine::<()>(&["a"]);
+        sm.set_leak_check(true);
+        sm.transition(StateTransition::Quit, &mut ());
+        assert_eq!(sm.finalize(&mut ()), Some(ExitReason::Quit));
+
+        let (mut sm, calls) = recording_machine::<()>(&["a", "b"]);
+        sm.set_leak_check(true);
+        let states = sm.leak_states();
+        assert_eq!(
+            states.iter().map(|state| state.name()).collect::<Vec<_>>(),
+            vec!["a", "b"]
+        );
+        assert_calls(&calls, &[]);
+    }
+
+    #[test]
+    fn switch_ordering() {
+        let (mut sm, calls) = recording_machine(&["a", "b"]);
+        sm.transition(StateTransition::Switch(recording("c", &calls)), &mut ());
+        sm.transition(StateTransition::Replace(recording("d", &calls)), &mut ());
+        assert_calls(
+            &calls,
+            &[
+                ("b", Stop),
+                ("a", Stop),
+                ("c", Start),
+                ("c", Stop),
+                ("d", Start),
+            ],
+        );
+
+        let (mut sm, calls) = recording_machine(&["a", "b"]);
+        sm.set_switch_ordering(SwitchOrdering::StartThenStop);
+        sm.transition(StateTransition::Switch(recording("c", &calls)), &mut ());
+        sm.transition(StateTransition::Replace(recording("d", &calls)), &mut ());
+        assert_calls(
+            &calls,
+            &[
+                ("c", Start),
+                ("b", Stop),
+                ("a", Stop),
+                ("d", Start),
+                ("c", Stop),
+            ],
+        );
+        assert_eq!(sm.len(), 1);
+    }
+
+    #[test]
+    fn capacity() {
+        let mut data = (0, 1);
+        let mut sm = StateMachine::<StateData>::with_capacity(16);
+        assert!(sm.capacity() >= 16);
+        sm.reserve(1000);
+        let reserved = sm.capacity();
+        assert!(reserved >= 1000);
+        for _ in 0..1000 {
+            sm.push(Box::new(Test), &mut data);
+        }
+        assert_eq!(sm.capacity(), reserved);
+        sm.switch(Box::new(Test), &mut data);
+        sm.stop(&mut data);
+        assert_eq!(sm.capacity(), reserved);
+        sm.shrink_to_fit();
+        assert!(sm.capacity() < 1000);
+
+        sm.set_shrink_on_stop(true);
+        for _ in 0..1000 {
+            sm.push(Box::new(Test), &mut data);
+        }
+        sm.stop(&mut data);
+        assert!(sm.capacity() < 1000);
+    }
+
+    #[test]
+    fn drain_and_adopt() {
+        let (mut old, calls) = recording_machine(&["a", "b"]);
+        old.enable_history(4);
+        let states = old.drain();
+        assert!(!old.is_running());
+        assert_eq!(old.exit_reason(), Some(ExitReason::StoppedExternally));
+        assert_eq!(
+            old.history().last().map(|record| record.kind),
+            Some(TransitionKind::Quit)
+        );
+
+        let mut sm = StateMachine::<()>::default();
+        sm.enable_metrics();
+        sm.adopt(states);
+        assert_calls(&calls, &[]);
+        assert_eq!(sm.len(), 2);
+        assert_eq!(sm.metrics().max_depth(), 2);
+
+        sm.update(&mut ());
+        sm.push(recording("c", &calls), &mut ());
+        sm.pop(&mut ());
+        sm.pop(&mut ());
+        assert_calls(
+            &calls,
+            &[
+                ("a", ShadowUpdate),
+                ("b", Update),
+                ("b", Pause),
+                ("c", Start),
+                ("c", Stop),
+                ("b", Resume),
+                ("b", Stop),
+                ("a", Resume),
+            ],
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "Only an empty state machine can adopt states.")]
+    fn adopt_on_running_machine() {
+        let (mut sm, calls) = recording_machine::<()>(&["a"]);
+        sm.adopt(vec![recording("b", &calls)]);
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    #[should_panic(expected = "not stopped: [\"a\"]")]
+    fn leak_check() {
+        let (mut sm, _) = recording_machine::<()>(&["a"]);
+        sm.set_leak_check(true);
+        drop(sm);
+    }
+
+    #[test]
+    fn restart() {
+        let (mut sm, calls) = recording_machine(&["a", "b"]);
+        sm.enable_history(8);
+        sm.set_interceptor(|transition, _| match transition {
+            StateTransition::Quit => InterceptDecision::Block,
+            _ => InterceptDecision::Allow,
+        });
+        sm.update(&mut ());
+        sm.queue_transition(StateTransition::Pop);
+        calls.lock().unwrap().clear();
+
+        sm.restart(recording("c", &calls), &mut ());
+        assert_calls(
+            &calls,
+            &[
+                ("a", Quit),
+                ("b", Quit),
+                ("b", Stop),
+                ("a", Stop),
+                ("c", Start),
+            ],
+        );
+        assert_eq!(sm.exit_reason(), None);
+        assert_eq!(sm.frame(), 0);
+        assert_eq!(sm.history().count(), 1);
+
+        sm.transition(StateTransition::Quit, &mut ());
+        sm.update(&mut ());
+        assert_eq!(sm.len(), 1);
+
+        sm.reset(&mut ());
+        assert!(!sm.is_running());
+        assert_eq!(sm.exit_reason(), None);
+        assert_eq!(sm.history().count(), 0);
+    }
+
+    /// Keeps the contexts it is updated and rendered with.
+    struct Contexts;
+
+    impl State<Vec<(&'static str, StateContext)>> for Contexts {
+        fn try_update_with_context(
+            &mut self,
+            context: &StateContext,
+            seen: &mut Vec<(&'static str, StateContext)>,
+        ) -> Result<StateTransition<Vec<(&'static str, StateContext)>>, BoxError> {
+            seen.push(("update", *context));
+            Ok(StateTransition::None)
+        }
+
+        fn shadow_update_with_context(
+            &mut self,
+            context: &StateContext,
+            seen: &mut Vec<(&'static str, StateContext)>,
+        ) {
+            seen.push(("shadow", *context));
+        }
+
+        fn render_with_context(
+            &mut self,
+            context: &StateContext,
+            seen: &mut Vec<(&'static str, StateContext)>,
+        ) {
+            seen.push(("render", *context));
+        }
+    }
+
+    #[test]
+    fn state_context() {
+        let mut seen = Vec::new();
+        let mut sm = StateMachine::default();
+        let bottom = sm.push(Box::new(Contexts), &mut seen);
+        let top = sm.push(Box::new(Contexts), &mut seen);
+        sm.update(&mut seen);
+        sm.render(&mut seen);
+
+        let seen = seen
+            .iter()
+            .map(|(call, context)| {
+                assert_eq!((context.depth, context.frame), (2, 1));
+                assert_eq!(context.handle == top, context.is_active);
+                (*call, context.index, context.is_active)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            seen,
+            vec![
+                ("shadow", 0, false),
+                ("update", 1, true),
+                ("render", 0, false),
+                ("render", 1, true),
+            ]
+        );
+        assert!(sm.is_alive(bottom));
+    }
+
+    /// Counts its updates in the `u32` behind the state data.
+    struct Downcasting;
+
+    impl State<dyn Any> for Downcasting {
+        fn update(&mut self, data: &mut dyn Any) -> StateTransition<dyn Any> {
+            match data.downcast_mut::<u32>() {
+                Some(count) if *count < 2 => {
+                    *count += 1;
+                    StateTransition::None
+                }
+                _ => StateTransition::Pop,
+            }
+        }
+    }
+
+    #[test]
+    fn unsized_state_data() {
+        let mut count = 0u32;
+        let data: &mut dyn Any = &mut count;
+        let mut sm = StateMachine::<dyn Any>::default();
+        sm.push(Box::new(Downcasting), data);
+        while sm.is_running() {
+            sm.update(data);
+        }
+        assert_eq!(count, 2);
+    }
 }
@@ -0,0 +1,124 @@
+//! Scratch values kept by the machine for each state of the stack, like the scroll position of
+//! a menu, living as long as the state is on the stack without belonging to the state data.
+
+use crate::{Allocator, MaybeSend, ScopedStateMachine, StateHandle};
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+
+/// The values kept for one state of the stack, one for each type.
+#[derive(Default)]
+pub(crate) struct Locals {
+    values: HashMap<TypeId, Box<maybe_send!(dyn Any)>>,
+}
+
+impl Locals {
+    fn get<T: Any>(&self) -> Option<&T> {
+        self.values.get(&TypeId::of::<T>())?.downcast_ref()
+    }
+
+    fn get_or_default<T: Any + MaybeSend + Default>(&mut self) -> &mut T {
+        self.values
+            .entry(TypeId::of::<T>())
+            .or_insert_with(|| Box::new(T::default()))
+            .downcast_mut()
+            .expect("The locals are keyed by their type.")
+    }
+
+    fn remove<T: Any>(&mut self) -> Option<T> {
+        let value = self.values.remove(&TypeId::of::<T>())?;
+        value.downcast().ok().map(|value| *value)
+    }
+}
+
+impl<'a, S: ?Sized, E, A: Allocator> ScopedStateMachine<'a, S, E, A> {
+    /// Returns the value of type `T` kept for the state with this handle, see `local_mut`.
+    /// Returns `None` if the state has no such value, or is not on the stack anymore.
+    pub fn local<T: Any>(&self, handle: StateHandle) -> Option<&T> {
+        let index = self.index_of(handle)?;
+        self.state_stack[index].locals.get()
+    }
+
+    /// Returns the value of type `T` kept for the state with this handle, created with
+    /// `Default` the first time it is asked for.
+    /// The value stays while the state is paused and resumed, and is dropped along with the
+    /// others of the state once it leaves the stack, whether it is popped, switched, removed or
+    /// stopped. A state popped with `PopRetain` and pushed again starts without values.
+    ///
+    /// Returns `None` if the state is not on the stack anymore.
+    pub fn local_mut<T: Any + MaybeSend + Default>(
+        &mut self,
+        handle: StateHandle,
+    ) -> Option<&mut T> {
+        let index = self.index_of(handle)?;
+        Some(self.state_stack[index].locals.get_or_default())
+    }
+
+    /// Takes the value of type `T` kept for the state with this handle, if it has one.
+    pub fn remove_local<T: Any>(&mut self, handle: StateHandle) -> Option<T> {
+        let index = self.index_of(handle)?;
+        self.state_stack[index].locals.remove()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use std::sync::Arc;
+
+    struct Scene(&'static str);
+
+    impl State<()> for Scene {
+        fn name(&self) -> &str {
+            self.0
+        }
+    }
+
+    #[derive(Default)]
+    struct ScrollPos(u32);
+
+    /// Counts the live references to the value kept for a state.
+    type Tracked = Option<Arc<()>>;
+
+    #[test]
+    fn kept_while_paused() {
+        let mut sm = StateMachine::<()>::default();
+        let menu = sm.push_state(Scene("menu"), &mut ());
+        assert!(sm.local::<ScrollPos>(menu).is_none());
+        sm.local_mut::<ScrollPos>(menu).unwrap().0 = 3;
+
+        let dialog = sm.push_state(Scene("dialog"), &mut ());
+        assert_eq!(sm.local_mut::<ScrollPos>(dialog).unwrap().0, 0);
+        sm.local_mut::<ScrollPos>(menu).unwrap().0 += 1;
+        sm.pop(&mut ());
+        sm.update(&mut ());
+        assert_eq!(sm.local::<ScrollPos>(menu).unwrap().0, 4);
+        assert!(sm.local::<ScrollPos>(dialog).is_none());
+        assert!(sm.local_mut::<ScrollPos>(dialog).is_none());
+
+        assert_eq!(sm.remove_local::<ScrollPos>(menu).unwrap().0, 4);
+        assert!(sm.local::<ScrollPos>(menu).is_none());
+    }
+
+    #[test]
+    fn dropped_with_the_state() {
+        let value = Arc::new(());
+        let mut sm = StateMachine::<()>::default();
+        let game = sm.push_state(Scene("game"), &mut ());
+        let pause = sm.push_state(Scene("pause"), &mut ());
+        for handle in [game, pause] {
+            *sm.local_mut::<Tracked>(handle).unwrap() = Some(Arc::clone(&value));
+        }
+        assert_eq!(Arc::strong_count(&value), 3);
+
+        sm.pop(&mut ());
+        assert_eq!(Arc::strong_count(&value), 2);
+        sm.switch(Box::new(Scene("title")), &mut ());
+        assert_eq!(Arc::strong_count(&value), 1);
+
+        let options = sm.push_state(Scene("options"), &mut ());
+        *sm.local_mut::<Tracked>(options).unwrap() = Some(Arc::clone(&value));
+        assert_eq!(Arc::strong_count(&value), 2);
+        sm.stop(&mut ());
+        assert_eq!(Arc::strong_count(&value), 1);
+    }
+}
@@ -0,0 +1,301 @@
+//! A macro declaring the states of a small flow and the transitions allowed between them.
+
+use crate::{State, StateTransition};
+
+/// Declares that a state may go to the state `T`, to build its transitions with the helpers of
+/// this trait. Implemented by `state_machine!` for each allowed transition, so a transition it
+/// does not allow does not compile.
+pub trait TransitionTo<T> {
+    /// Pauses this state and pushes `state` on top of it.
+    fn push_to<S: ?Sized, E>(&self, state: T) -> StateTransition<S, E>
+    where
+        T: State<S, E> + 'static,
+    {
+        StateTransition::Push(Box::new(state))
+    }
+
+    /// Stops all the states and starts `state`.
+    fn switch_to<S: ?Sized, E>(&self, state: T) -> StateTransition<S, E>
+    where
+        T: State<S, E> + 'static,
+    {
+        StateTransition::Switch(Box::new(state))
+    }
+
+    /// Stops this state and starts `state` in its place.
+    fn replace_with<S: ?Sized, E>(&self, state: T) -> StateTransition<S, E>
+    where
+        T: State<S, E> + 'static,
+    {
+        StateTransition::Replace(Box::new(state))
+    }
+}
+
+/// Declares the states of a flow and the transitions allowed between them.
+///
+/// This generates an enum with a variant per state type, named after the types, and implements
+/// `TransitionTo` for each allowed transition, so `self.switch_to(Playing)` only compiles in the
+/// states allowed to go to `Playing`. The enum also gives a transition check, for the
+/// transitions built without these helpers, see `StateMachine::set_transition_check`.
+/// The state types are declared and implement `State` on their own.
+///
+/// ```rust
+/// use game_state_machine::*;
+///
+/// struct Menu;
+/// struct Playing;
+///
+/// state_machine! {
+///     pub enum Flow;
+///     data: ();
+///     states { Menu, Playing }
+///     transitions { Menu => Playing, Playing => Menu }
+/// }
+///
+/// impl State<()> for Menu {
+///     fn update(&mut self, _: &mut ()) -> StateTransition<()> {
+///         self.switch_to(Playing)
+///     }
+/// }
+///
+/// impl State<()> for Playing {}
+///
+/// let mut sm = StateMachine::<()>::default();
+/// Flow::install(&mut sm);
+/// sm.push_state(Menu, &mut ());
+/// sm.update(&mut ());
+/// assert_eq!(sm.active().and_then(Flow::of), Some(Flow::Playing));
+/// ```
+///
+/// Going to a state which is not allowed does not compile:
+///
+/// ```rust,compile_fail
+/// use game_state_machine::*;
+///
+/// struct Menu;
+/// struct Playing;
+/// struct Paused;
+///
+/// state_machine! {
+///     enum Flow;
+///     data: ();
+///     states { Menu, Playing, Paused }
+///     transitions { Menu => Playing, Playing => Paused }
+/// }
+///
+/// impl State<()> for Menu {
+///     fn update(&mut self, _: &mut ()) -> StateTransition<()> {
+///         self.push_to(Paused)
+///     }
+/// }
+/// ```
+#[macro_export]
+macro_rules! state_machine {
+    (
+        $(#[$meta:meta])*
+        $vis:vis enum $name:ident;
+        data: $data:ty;
+        states { $($state:ident),+ $(,)? }
+        transitions { $($from:ident => $($to:ident)|+),* $(,)? }
+    ) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+        $vis enum $name {
+            $(
+                #[allow(missing_docs)]
+                $state,
+            )+
+        }
+
+        impl $name {
+            /// All the states of the flow, in the order they were declared.
+            #[allow(dead_code)]
+            pub const ALL: &'static [$name] = &[$($name::$state),+];
+
+            /// Returns the variant of this state, or `None` if it is not a state of the flow.
+            #[allow(dead_code)]
+            pub fn of(
+                state: &(dyn $crate::State<$data> + 'static),
+            ) -> ::std::option::Option<Self> {
+                let id = $crate::AsAny::as_any(state).type_id();
+                $(
+                    if id == ::std::any::TypeId::of::<$state>() {
+                        return ::std::option::Option::Some($name::$state);
+                    }
+                )+
+                ::std::option::Option::None
+            }
+
+            /// Returns if this state is allowed to go to the other one.
+            #[allow(dead_code)]
+            pub fn can_go_to(self, to: Self) -> bool {
+                #[allow(unreachable_patterns)]
+                match (self, to) {
+                    $($(($name::$from, $name::$to) => true,)+)*
+                    _ => false,
+                }
+            }
+
+            /// Rejects the transitions starting a state of the flow that the active state is
+            /// not allowed to go to. The transitions of and to the other states are allowed.
+            #[allow(dead_code)]
+            pub fn check(
+                active: ::std::option::Option<&(dyn $crate::State<$data> + 'static)>,
+                request: &$crate::StateTransition<$data>,
+            ) -> ::std::result::Result<(), $crate::TransitionError> {
+                let from = match active {
+                    ::std::option::Option::Some(active) => active,
+                    ::std::option::Option::None => return ::std::result::Result::Ok(()),
+                };
+                let from_id = match $name::of(from) {
+                    ::std::option::Option::Some(id) => id,
+                    ::std::option::Option::None => return ::std::result::Result::Ok(()),
+                };
+                for to in request.states() {
+                    if let ::std::option::Option::Some(to_id) = $name::of(to.as_ref()) {
+                        if !from_id.can_go_to(to_id) {
+                            return ::std::result::Result::Err(
+                                $crate::TransitionError::NotAllowed {
+                                    from: from.name().to_string(),
+                                    to: to.name().to_string(),
+                                },
+                            );
+                        }
+                    }
+                }
+                ::std::result::Result::Ok(())
+            }
+
+            /// Sets `check` as the transition check of the state machine.
+            #[allow(dead_code)]
+            pub fn install(state_machine: &mut $crate::StateMachine<$data>) {
+                state_machine.set_transition_check($name::check);
+            }
+        }
+
+        $($(impl $crate::TransitionTo<$to> for $from {})+)*
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    #[derive(Default)]
+    struct Game {
+        paused: u32,
+        errors: u32,
+    }
+
+    struct Menu;
+    struct Playing;
+    struct Paused;
+    struct Credits;
+
+    state_machine! {
+        enum Flow;
+        data: Game;
+        states { Menu, Playing, Paused }
+        transitions { Menu => Playing, Playing => Paused, Paused => Playing | Menu }
+    }
+
+    impl State<Game> for Menu {
+        fn update(&mut self, _: &mut Game) -> StateTransition<Game> {
+            self.switch_to(Playing)
+        }
+    }
+
+    impl State<Game> for Playing {
+        fn update(&mut self, game: &mut Game) -> StateTransition<Game> {
+            game.paused += 1;
+            self.push_to(Paused)
+        }
+    }
+
+    impl State<Game> for Paused {
+        fn update(&mut self, _: &mut Game) -> StateTransition<Game> {
+            // Bypasses the helpers to go to a state it is not allowed to go to.
+            StateTransition::Push(Box::new(Paused))
+        }
+
+        fn on_error(&mut self, error: &dyn Error, game: &mut Game) -> ErrorOutcome<Game> {
+            assert_eq!(
+                error.to_string(),
+                format!(
+                    "the state {} cannot go to the state {}",
+                    self.name(),
+                    self.name()
+                )
+            );
+            game.errors += 1;
+            ErrorOutcome::Handled(self.push_to(Playing))
+        }
+    }
+
+    impl State<Game> for Credits {}
+
+    #[test]
+    fn expansion() {
+        assert_eq!(Flow::ALL, &[Flow::Menu, Flow::Playing, Flow::Paused]);
+        assert!(Flow::Menu.can_go_to(Flow::Playing));
+        assert!(Flow::Paused.can_go_to(Flow::Menu));
+        assert!(!Flow::Menu.can_go_to(Flow::Paused));
+        assert!(!Flow::Playing.can_go_to(Flow::Playing));
+        assert_eq!(Flow::of(&Paused), Some(Flow::Paused));
+        assert_eq!(Flow::of(&Credits), None);
+
+        let credits = StateTransition::Push(Box::new(Credits));
+        assert!(Flow::check(Some(&Menu), &credits).is_ok());
+        assert!(Flow::check(Some(&Credits), &StateTransition::Push(Box::new(Paused))).is_ok());
+        assert!(Flow::check(None, &StateTransition::Push(Box::new(Paused))).is_ok());
+        assert!(Flow::check(Some(&Menu), &StateTransition::Pop).is_ok());
+        assert_eq!(
+            Flow::check(Some(&Menu), &StateTransition::Switch(Box::new(Paused))),
+            Err(TransitionError::NotAllowed {
+                from: Menu.name().to_string(),
+                to: Paused.name().to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn rejected_at_runtime() {
+        let mut game = Game::default();
+        let mut sm = StateMachine::<Game>::default();
+        Flow::install(&mut sm);
+        sm.push(Box::new(Menu), &mut game);
+        sm.update(&mut game);
+        sm.update(&mut game);
+        assert_eq!(sm.len(), 2);
+
+        // The rejected push is dropped and handled by the paused state, which is kept.
+        sm.update(&mut game);
+        assert_eq!(game.errors, 1);
+        assert_eq!(sm.len(), 3);
+        assert_eq!(sm.active().and_then(Flow::of), Some(Flow::Playing));
+
+        sm.clear_transition_check();
+        sm.update(&mut game);
+        sm.update(&mut game);
+        assert_eq!(sm.len(), 5);
+        assert_eq!(game.paused, 2);
+    }
+
+    #[test]
+    fn unhandled_rejection_stops() {
+        let mut sm = StateMachine::<Game>::default();
+        sm.set_transition_check(|_, request| match request {
+            StateTransition::Quit => Ok(()),
+            _ => Err(TransitionError::NotAllowed {
+                from: String::new(),
+                to: String::new(),
+            }),
+        });
+        let mut game = Game::default();
+        sm.push(Box::new(Menu), &mut game);
+        sm.update(&mut game);
+        assert!(!sm.is_running());
+        assert_eq!(sm.exit_reason(), Some(ExitReason::Error));
+        assert!(sm.take_error().is_some());
+    }
+}
@@ -0,0 +1,485 @@
+//! Running the states written for a part of the state data in a state machine holding all of it,
+//! like reusable UI states in a game machine.
+
+use crate::{
+    AsAny, BoxError, ErrorOutcome, EventOutcome, MessageOutcome, PauseReason, Payload,
+    ResumeReason, State, StateContext, StateTransition, StopDecision, TransitionDecl,
+};
+use std::any::{Any, TypeId};
+use std::error::Error;
+#[cfg(feature = "time")]
+use std::time::Duration;
+
+/// Gives the part of the state data that the states of a `MappedState` are written for.
+pub type DataProjection<S, T> = fn(&mut S) -> &mut T;
+
+/// Wraps a state written for the state data `T`, so it runs in a machine of the state data `S`
+/// through a projection from `S` to `T`, like a field of `S`.
+///
+/// All the hooks are given to the wrapped state with the projected data, and the states it
+/// pushes or switches to in its transitions are wrapped with the same projection, so the states
+/// written for `T` keep going to each other. The predicates of `StateTransition::PopUntil` only
+/// match the states wrapped for `T`, and `StateTransition::PushNamed` creates the state with
+/// the registry of the machine, which knows the states of `S`.
+///
+/// Some hooks cannot be mapped, and do nothing: `StateTransition::Custom` is dropped since it
+/// runs on a machine of `T`, `par_shadow_update` only gets shared data, and the wrapped state is
+/// not saved in snapshots, checkpointed, nor restarted.
+/// ## Generics
+/// - S: State data of the machine the state runs in.
+/// - T: State data the wrapped state is written for.
+/// - E: Events, the same for both.
+pub struct MappedState<S: ?Sized, T: ?Sized, E = ()> {
+    inner: Box<dyn State<T, E>>,
+    project: DataProjection<S, T>,
+}
+
+impl<S: ?Sized, T: ?Sized, E> MappedState<S, T, E> {
+    /// Wraps a state, which is given the data returned by `project`.
+    pub fn new(inner: Box<dyn State<T, E>>, project: DataProjection<S, T>) -> Self {
+        Self { inner, project }
+    }
+
+    /// Returns the wrapped state.
+    pub fn inner(&self) -> &(dyn State<T, E> + 'static) {
+        self.inner.as_ref()
+    }
+}
+
+impl<S: ?Sized + 'static, T: ?Sized + 'static, E: 'static> MappedState<S, T, E> {
+    fn wrap(&self, state: Box<dyn State<T, E>>) -> Box<dyn State<S, E>> {
+        Box::new(Self::new(state, self.project))
+    }
+
+    /// Translates a transition of the wrapped state into one of the machine, wrapping the states
+    /// it starts.
+    fn map(&self, trans: StateTransition<T, E>) -> StateTransition<S, E> {
+        let project = self.project;
+        match trans {
+            StateTransition::None => StateTransition::None,
+            StateTransition::Pop => StateTransition::Pop,
+            StateTransition::PopWith(payload) => StateTransition::PopWith(payload),
+            StateTransition::PopRetain => StateTransition::PopRetain,
+            StateTransition::PopN(n) => StateTransition::PopN(n),
+            StateTransition::PopUntil(pred) => {
+                StateTransition::PopUntil(Box::new(move |state: &(dyn State<S, E> + 'static)| {
+                    <dyn AsAny>::as_any(state)
+                        .downcast_ref::<Self>()
+                        .is_some_and(|mapped| pred(mapped.inner()))
+                }))
+            }
+            StateTransition::PopTagged(tag) => StateTransition::PopTagged(tag),
+            StateTransition::PopTo(target) => StateTransition::PopTo(target),
+            StateTransition::PopGroup(group) => StateTransition::PopGroup(group),
+            StateTransition::Push(state) => StateTransition::Push(self.wrap(state)),
+            StateTransition::PushWith(constructor) => {
+                StateTransition::PushWith(Box::new(move |state_data: &mut S| {
+                    let state = constructor(project(state_data));
+                    Box::new(Self::new(state, project)) as Box<dyn State<S, E>>
+                }))
+            }
+            StateTransition::InsertBelow(state) => StateTransition::InsertBelow(self.wrap(state)),
+            StateTransition::PushMany(states) => StateTransition::PushMany(
+                states.into_iter().map(|state| self.wrap(state)).collect(),
+            ),
+            StateTransition::PushBottom(state) => StateTransition::PushBottom(self.wrap(state)),
+            StateTransition::PushNamed(name) => StateTransition::PushNamed(name),
+            StateTransition::Switch(state) => StateTransition::Switch(self.wrap(state)),
+            StateTransition::SwitchWith(constructor) => {
+                StateTransition::SwitchWith(Box::new(move |state_data: &mut S| {
+                    let state = constructor(project(state_data));
+                    Box::new(Self::new(state, project)) as Box<dyn State<S, E>>
+                }))
+            }
+            StateTransition::Replace(state) => StateTransition::Replace(self.wrap(state)),
+            StateTransition::ReplaceAll(states) => StateTransition::ReplaceAll(
+                states.into_iter().map(|state| self.wrap(state)).collect(),
+            ),
+            StateTransition::Quit => StateTransition::Quit,
+            StateTransition::QuitWith(payload) => StateTransition::QuitWith(payload),
+            StateTransition::Message { target, payload } => {
+                StateTransition::Message { target, payload }
+            }
+            StateTransition::Delayed {
+                after_updates,
+                then,
+            } => StateTransition::Delayed {
+                after_updates,
+                then: Box::new(self.map(*then)),
+            },
+            StateTransition::Sequence(transitions) => StateTransition::Sequence(
+                transitions
+                    .into_iter()
+                    .map(|trans| self.map(trans))
+                    .collect(),
+            ),
+            StateTransition::Custom(_) => {
+                log_warn!(
+                    "dropped a custom transition of {}, mapped to another state data",
+                    self.inner.name()
+                );
+                StateTransition::None
+            }
+        }
+    }
+}
+
+impl<S: ?Sized + 'static, T: ?Sized + 'static, E: 'static> State<S, E> for MappedState<S, T, E> {
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    fn is_transparent(&self) -> bool {
+        self.inner.is_transparent()
+    }
+
+    fn tags(&self) -> &[&'static str] {
+        self.inner.tags()
+    }
+
+    fn required_below(&self) -> &[TypeId] {
+        self.inner.required_below()
+    }
+
+    fn pops_group(&self) -> bool {
+        self.inner.pops_group()
+    }
+
+    fn on_start(&mut self, state_data: &mut S) -> StateTransition<S, E> {
+        let trans = self.inner.on_start((self.project)(state_data));
+        self.map(trans)
+    }
+
+    fn try_start(&mut self, state_data: &mut S) -> Result<StateTransition<S, E>, BoxError> {
+        let trans = self.inner.try_start((self.project)(state_data))?;
+        Ok(self.map(trans))
+    }
+
+    fn on_stop_requested(&mut self, state_data: &mut S) -> StopDecision<S, E> {
+        match self.inner.on_stop_requested((self.project)(state_data)) {
+            StopDecision::Allow => StopDecision::Allow,
+            StopDecision::DenyWith(trans) => StopDecision::DenyWith(self.map(trans)),
+        }
+    }
+
+    fn on_stop(&mut self, state_data: &mut S) -> StateTransition<S, E> {
+        let trans = self.inner.on_stop((self.project)(state_data));
+        self.map(trans)
+    }
+
+    fn on_quit(&mut self, state_data: &mut S) {
+        self.inner.on_quit((self.project)(state_data));
+    }
+
+    fn on_pause(&mut self, state_data: &mut S) {
+        self.inner.on_pause((self.project)(state_data));
+    }
+
+    fn on_resume(&mut self, state_data: &mut S) -> StateTransition<S, E> {
+        let trans = self.inner.on_resume((self.project)(state_data));
+        self.map(trans)
+    }
+
+    fn on_pause_with_reason(&mut self, reason: PauseReason, state_data: &mut S) {
+        self.inner
+            .on_pause_with_reason(reason, (self.project)(state_data));
+    }
+
+    fn on_resume_with_reason(
+        &mut self,
+        reason: ResumeReason,
+        state_data: &mut S,
+    ) -> StateTransition<S, E> {
+        let trans = self
+            .inner
+            .on_resume_with_reason(reason, (self.project)(state_data));
+        self.map(trans)
+    }
+
+    fn on_resume_with(&mut self, payload: Payload, state_data: &mut S) -> StateTransition<S, E> {
+        let trans = self
+            .inner
+            .on_resume_with(payload, (self.project)(state_data));
+        self.map(trans)
+    }
+
+    fn pre_update(&mut self, state_data: &mut S) {
+        self.inner.pre_update((self.project)(state_data))
+    }
+
+    // The inner state is given its transition before it is mapped, so `post_update` is called
+    // on it here rather than forwarded.
+    fn try_update_with_context(
+        &mut self,
+        context: &StateContext,
+        state_data: &mut S,
+    ) -> Result<StateTransition<S, E>, BoxError> {
+        let state_data = (self.project)(state_data);
+        let trans = self.inner.try_update_with_context(context, state_data)?;
+        self.inner.post_update(state_data, &trans);
+        Ok(self.map(trans))
+    }
+
+    fn try_update_with_extra(
+        &mut self,
+        extra: &dyn Any,
+        context: &StateContext,
+        state_data: &mut S,
+    ) -> Result<StateTransition<S, E>, BoxError> {
+        let state_data = (self.project)(state_data);
+        let trans = self
+            .inner
+            .try_update_with_extra(extra, context, state_data)?;
+        self.inner.post_update(state_data, &trans);
+        Ok(self.map(trans))
+    }
+
+    fn on_error(&mut self, error: &dyn Error, state_data: &mut S) -> ErrorOutcome<S, E> {
+        match self.inner.on_error(error, (self.project)(state_data)) {
+            ErrorOutcome::Unhandled => ErrorOutcome::Unhandled,
+            ErrorOutcome::Handled(trans) => ErrorOutcome::Handled(self.map(trans)),
+        }
+    }
+
+    #[cfg(feature = "time")]
+    fn fixed_update(&mut self, state_data: &mut S) -> StateTransition<S, E> {
+        let trans = self.inner.fixed_update((self.project)(state_data));
+        self.map(trans)
+    }
+
+    #[cfg(feature = "time")]
+    fn fixed_update_with_extra(
+        &mut self,
+        extra: &dyn Any,
+        state_data: &mut S,
+    ) -> StateTransition<S, E> {
+        let trans = self
+            .inner
+            .fixed_update_with_extra(extra, (self.project)(state_data));
+        self.map(trans)
+    }
+
+    #[cfg(feature = "time")]
+    fn update_interval(&self) -> Option<Duration> {
+        self.inner.update_interval()
+    }
+
+    #[cfg(feature = "time")]
+    fn counts_covered_time(&self) -> bool {
+        self.inner.counts_covered_time()
+    }
+
+    fn shadow_update_with_context(&mut self, context: &StateContext, state_data: &mut S) {
+        self.inner
+            .shadow_update_with_context(context, (self.project)(state_data));
+    }
+
+    fn shadow_update_with_transition(
+        &mut self,
+        context: &StateContext,
+        state_data: &mut S,
+    ) -> StateTransition<S, E> {
+        let trans = self
+            .inner
+            .shadow_update_with_transition(context, (self.project)(state_data));
+        self.map(trans)
+    }
+
+    fn render_with_context(&mut self, context: &StateContext, state_data: &mut S) {
+        self.inner
+            .render_with_context(context, (self.project)(state_data));
+    }
+
+    fn blocks_rendering(&self) -> bool {
+        self.inner.blocks_rendering()
+    }
+
+    fn clone_state(&self) -> Option<Box<dyn State<S, E>>> {
+        let state = self.inner.clone_state()?;
+        Some(self.wrap(state))
+    }
+
+    fn on_save(&mut self, state_data: &mut S) {
+        self.inner.on_save((self.project)(state_data));
+    }
+
+    fn on_load(&mut self, state_data: &mut S) {
+        self.inner.on_load((self.project)(state_data));
+    }
+
+    fn handle_event(&mut self, event: &E, state_data: &mut S) -> EventOutcome<S, E> {
+        let outcome = self.inner.handle_event(event, (self.project)(state_data));
+        self.map_outcome(outcome)
+    }
+
+    fn handle_event_with_extra(
+        &mut self,
+        event: &E,
+        extra: &dyn Any,
+        state_data: &mut S,
+    ) -> EventOutcome<S, E> {
+        let outcome = self
+            .inner
+            .handle_event_with_extra(event, extra, (self.project)(state_data));
+        self.map_outcome(outcome)
+    }
+
+    fn on_message(&mut self, message: &dyn Any, state_data: &mut S) -> MessageOutcome {
+        self.inner.on_message(message, (self.project)(state_data))
+    }
+
+    fn declared_transitions(&self) -> Vec<TransitionDecl> {
+        self.inner.declared_transitions()
+    }
+}
+
+impl<S: ?Sized + 'static, T: ?Sized + 'static, E: 'static> MappedState<S, T, E> {
+    fn map_outcome(&self, outcome: EventOutcome<T, E>) -> EventOutcome<S, E> {
+        match outcome {
+            EventOutcome::Ignored => EventOutcome::Ignored,
+            EventOutcome::Consumed(trans) => EventOutcome::Consumed(self.map(trans)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    #[derive(Default)]
+    struct UiData {
+        log: Vec<String>,
+    }
+
+    #[derive(Default)]
+    struct GameData {
+        score: u32,
+        ui: UiData,
+    }
+
+    fn ui(data: &mut GameData) -> &mut UiData {
+        &mut data.ui
+    }
+
+    /// A menu of a UI library, opening its submenu on its first update and closing itself on an
+    /// event.
+    struct Menu {
+        name: &'static str,
+        submenu: Option<Box<dyn State<UiData, u8>>>,
+    }
+
+    impl Menu {
+        fn new(name: &'static str, submenu: Option<Box<dyn State<UiData, u8>>>) -> Box<Self> {
+            Box::new(Self { name, submenu })
+        }
+    }
+
+    impl State<UiData, u8> for Menu {
+        fn name(&self) -> &str {
+            self.name
+        }
+
+        fn on_start(&mut self, ui: &mut UiData) -> StateTransition<UiData, u8> {
+            ui.log.push(format!("{}:start", self.name));
+            StateTransition::None
+        }
+
+        fn on_pause(&mut self, ui: &mut UiData) {
+            ui.log.push(format!("{}:pause", self.name));
+        }
+
+        fn update(&mut self, ui: &mut UiData) -> StateTransition<UiData, u8> {
+            ui.log.push(format!("{}:update", self.name));
+            match self.submenu.take() {
+                Some(submenu) => StateTransition::Push(submenu),
+                None => StateTransition::None,
+            }
+        }
+
+        fn handle_event(&mut self, _: &u8, _: &mut UiData) -> EventOutcome<UiData, u8> {
+            EventOutcome::Consumed(StateTransition::Pop)
+        }
+    }
+
+    /// A state of the game, scoring on each update.
+    struct Level;
+
+    impl State<GameData, u8> for Level {
+        fn update(&mut self, data: &mut GameData) -> StateTransition<GameData, u8> {
+            data.score += 1;
+            StateTransition::None
+        }
+    }
+
+    #[test]
+    fn forwards_projected_data() {
+        let mut data = GameData::default();
+        let mut sm = StateMachine::<GameData, u8>::default();
+        sm.push(Box::new(Level), &mut data);
+        sm.push(
+            Box::new(MappedState::new(Menu::new("main", None), ui)),
+            &mut data,
+        );
+        sm.update(&mut data);
+        assert_eq!(data.ui.log, ["main:start", "main:update"]);
+        assert_eq!(data.score, 0);
+
+        sm.handle_event(&0, &mut data);
+        sm.update(&mut data);
+        assert_eq!(data.score, 1);
+    }
+
+    #[test]
+    fn pushed_states_are_wrapped() {
+        let mut data = GameData::default();
+        let mut sm = StateMachine::<GameData, u8>::default();
+        let options = Menu::new("options", Some(Menu::new("audio", None)));
+        sm.push(
+            Box::new(MappedState::new(Menu::new("main", Some(options)), ui)),
+            &mut data,
+        );
+        sm.update(&mut data);
+        sm.update(&mut data);
+        assert_eq!(
+            sm.iter().map(|state| state.name()).collect::<Vec<_>>(),
+            ["main", "options", "audio"]
+        );
+        assert!(sm
+            .iter()
+            .all(|state| state.as_any().is::<MappedState<GameData, UiData, u8>>()));
+        assert_eq!(
+            data.ui.log,
+            [
+                "main:start",
+                "main:update",
+                "main:pause",
+                "options:start",
+                "options:update",
+                "options:pause",
+                "audio:start"
+            ]
+        );
+
+        sm.handle_event(&0, &mut data);
+        assert_eq!(sm.active_name(), Some("options"));
+    }
+
+    #[test]
+    fn pop_until_matches_inner_states() {
+        let mut data = GameData::default();
+        let mut sm = StateMachine::<GameData, u8>::default();
+        sm.push(Box::new(Level), &mut data);
+        sm.push(
+            Box::new(MappedState::new(Menu::new("main", None), ui)),
+            &mut data,
+        );
+        let mapped = MappedState::new(Menu::new("options", None), ui);
+        let trans = mapped.map(StateTransition::PopUntil(Box::new(|state| {
+            state.name() == "main"
+        })));
+        sm.push(Box::new(mapped), &mut data);
+        sm.queue_transition(trans);
+        sm.update(&mut data);
+        assert_eq!(sm.active_name(), Some("main"));
+    }
+}
@@ -0,0 +1,180 @@
+//! Telling the states of the stack something without waiting for them to be resumed, like a
+//! combat telling the paused overworld that a character died.
+
+use crate::{any_of, guard, Allocator, ScopedStateMachine};
+use std::any::{Any, TypeId};
+
+/// The states a message is delivered to, see `StateTransition::Message`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum MessageTarget {
+    /// The states below the top of the stack, from the top down, until one consumes it.
+    Below,
+    /// All the states below the top of the stack, from the top down, whether they consume it or
+    /// not.
+    Broadcast,
+    /// The states with this name, from the top of the stack down, until one consumes it.
+    ByName(String),
+    /// The states of this type, from the top of the stack down, until one consumes it.
+    ByType(TypeId),
+}
+
+impl MessageTarget {
+    /// Targets the states of the type `T`.
+    pub fn of<T: Any>() -> Self {
+        MessageTarget::ByType(TypeId::of::<T>())
+    }
+}
+
+/// What a state did with a message, see `State::on_message`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MessageOutcome {
+    /// The message is delivered to the next state targeted.
+    Ignored,
+    /// The message stops here, unless it is broadcast.
+    Consumed,
+}
+
+impl<'a, S: ?Sized, E, A: Allocator> ScopedStateMachine<'a, S, E, A> {
+    /// Delivers a message to the targeted states right away, paused or not, and returns if one
+    /// of them consumed it. The states reached by a `StateTransition::Message` are the same.
+    pub fn send_message(
+        &mut self,
+        target: &MessageTarget,
+        message: &dyn Any,
+        state_data: &mut S,
+    ) -> bool {
+        let catch = self.catches_panics();
+        let top = self.state_stack.len().saturating_sub(1);
+        let mut consumed = false;
+        for index in (0..self.state_stack.len()).rev() {
+            let entry = &mut self.state_stack[index];
+            let targeted = match target {
+                MessageTarget::Below | MessageTarget::Broadcast => index < top,
+                MessageTarget::ByName(name) => entry.state.name() == name,
+                MessageTarget::ByType(id) => {
+                    any_of(&entry.state).map(|state| state.type_id()) == Some(*id)
+                }
+            };
+            if !targeted {
+                continue;
+            }
+            match guard(catch, || {
+                in_span!(
+                    "state.on_message",
+                    entry.state.name(),
+                    entry.state.on_message(message, state_data)
+                )
+            }) {
+                Ok(MessageOutcome::Ignored) => (),
+                Ok(MessageOutcome::Consumed) => {
+                    consumed = true;
+                    if *target != MessageTarget::Broadcast {
+                        break;
+                    }
+                }
+                Err(caught) => {
+                    if index == top {
+                        self.drop_top(caught, state_data);
+                    } else {
+                        self.state_stack.remove(index);
+                        self.caught(caught);
+                    }
+                    break;
+                }
+            }
+        }
+        consumed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use std::any::Any;
+
+    /// A character dying in a combat.
+    struct Died(&'static str);
+
+    /// Keeps the messages it got, consuming them if it is told to.
+    struct Listener(&'static str, bool, Vec<&'static str>);
+
+    impl State<()> for Listener {
+        fn name(&self) -> &str {
+            self.0
+        }
+
+        fn on_message(&mut self, message: &dyn Any, _: &mut ()) -> MessageOutcome {
+            match message.downcast_ref::<Died>() {
+                Some(Died(who)) => {
+                    self.2.push(who);
+                    if self.1 {
+                        MessageOutcome::Consumed
+                    } else {
+                        MessageOutcome::Ignored
+                    }
+                }
+                None => MessageOutcome::Ignored,
+            }
+        }
+    }
+
+    /// Sends a message when it is updated.
+    struct Combat(Option<(MessageTarget, &'static str)>);
+
+    impl State<()> for Combat {
+        fn update(&mut self, _: &mut ()) -> StateTransition<()> {
+            match self.0.take() {
+                Some((target, who)) => StateTransition::Message {
+                    target,
+                    payload: Box::new(Died(who)),
+                },
+                None => StateTransition::None,
+            }
+        }
+    }
+
+    fn heard(sm: &StateMachine<()>) -> Vec<Vec<&'static str>> {
+        sm.iter()
+            .filter_map(|state| state.as_any().downcast_ref::<Listener>())
+            .map(|listener| listener.2.clone())
+            .collect()
+    }
+
+    fn machine(target: MessageTarget) -> StateMachine<()> {
+        let mut sm = StateMachine::default();
+        sm.push(Box::new(Listener("overworld", false, Vec::new())), &mut ());
+        sm.push(Box::new(Listener("town", true, Vec::new())), &mut ());
+        sm.push(Box::new(Listener("dungeon", false, Vec::new())), &mut ());
+        sm.push(Box::new(Combat(Some((target, "goblin")))), &mut ());
+        sm
+    }
+
+    #[test]
+    fn broadcast() {
+        let mut sm = machine(MessageTarget::Broadcast);
+        assert_eq!(sm.update(&mut ()), MachineStatus::Running);
+        assert_eq!(heard(&sm), [["goblin"], ["goblin"], ["goblin"]]);
+        assert_eq!(sm.len(), 4);
+    }
+
+    #[test]
+    fn below_until_consumed() {
+        let mut sm = machine(MessageTarget::Below);
+        sm.update(&mut ());
+        assert_eq!(
+            heard(&sm),
+            [Vec::<&str>::new(), vec!["goblin"], vec!["goblin"]]
+        );
+    }
+
+    #[test]
+    fn by_name_and_type() {
+        let mut sm = machine(MessageTarget::ByName("overworld".to_string()));
+        sm.update(&mut ());
+        assert_eq!(heard(&sm), [vec!["goblin"], Vec::<&str>::new(), Vec::new()]);
+
+        assert!(sm.send_message(&MessageTarget::of::<Listener>(), &Died("orc"), &mut ()));
+        assert_eq!(heard(&sm), [vec!["goblin"], vec!["orc"], vec!["orc"]]);
+        assert!(!sm.send_message(&MessageTarget::of::<Combat>(), &Died("orc"), &mut ()));
+    }
+}
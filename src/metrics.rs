@@ -0,0 +1,167 @@
+//! Counting what a state machine does, for soak tests and profiling.
+
+use crate::{Allocator, ScopedStateMachine, TransitionKind};
+use std::collections::HashMap;
+
+/// Counters of the transitions and updates of a state machine, see
+/// `StateMachine::enable_metrics`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StateMachineMetrics {
+    enabled: bool,
+    transitions: HashMap<TransitionKind, u64>,
+    updates: u64,
+    state_updates: HashMap<String, u64>,
+    max_depth: usize,
+    duplicates: u64,
+    dropped_transitions: u64,
+}
+
+impl StateMachineMetrics {
+    /// Returns how many transitions of this kind were performed.
+    /// The transitions of a `Sequence` are counted along with the `Sequence` itself.
+    pub fn transitions(&self, kind: TransitionKind) -> u64 {
+        self.transitions.get(&kind).copied().unwrap_or(0)
+    }
+
+    /// Returns how many times the active state was updated.
+    pub fn updates(&self) -> u64 {
+        self.updates
+    }
+
+    /// Returns how many times the states with this name were updated while active.
+    pub fn state_updates(&self, name: &str) -> u64 {
+        self.state_updates.get(name).copied().unwrap_or(0)
+    }
+
+    /// Returns the highest number of states that were on the stack at once.
+    pub fn max_depth(&self) -> usize {
+        self.max_depth
+    }
+
+    /// Returns how many states were pushed while a state of the same type was on the stack, and
+    /// were ignored or raised the existing one, see `StateMachine::set_duplicate_policy`.
+    pub fn duplicates(&self) -> u64 {
+        self.duplicates
+    }
+
+    /// Returns how many transitions and errors returned by the states were dropped for another
+    /// one returned in the same update, see `StateMachine::set_transition_resolution`.
+    pub fn dropped_transitions(&self) -> u64 {
+        self.dropped_transitions
+    }
+
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub(crate) fn record_transition(&mut self, kind: TransitionKind) {
+        if self.enabled {
+            *self.transitions.entry(kind).or_insert(0) += 1;
+        }
+    }
+
+    pub(crate) fn record_update(&mut self, name: &str) {
+        if self.enabled {
+            self.updates += 1;
+            match self.state_updates.get_mut(name) {
+                Some(count) => *count += 1,
+                None => {
+                    self.state_updates.insert(name.to_string(), 1);
+                }
+            }
+        }
+    }
+
+    pub(crate) fn record_duplicate(&mut self) {
+        if self.enabled {
+            self.duplicates += 1;
+        }
+    }
+
+    pub(crate) fn record_dropped(&mut self) {
+        if self.enabled {
+            self.dropped_transitions += 1;
+        }
+    }
+
+    pub(crate) fn record_depth(&mut self, depth: usize) {
+        if self.enabled {
+            self.max_depth = self.max_depth.max(depth);
+        }
+    }
+}
+
+impl<'a, S: ?Sized, E, A: Allocator> ScopedStateMachine<'a, S, E, A> {
+    /// Starts counting the transitions and updates of this state machine.
+    /// Without it, the metrics stay at zero and cost nothing.
+    pub fn enable_metrics(&mut self) {
+        self.metrics.enabled = true;
+        self.metrics.record_depth(self.len());
+    }
+
+    /// Returns the metrics counted since they were enabled or last reset.
+    pub fn metrics(&self) -> &StateMachineMetrics {
+        &self.metrics
+    }
+
+    /// Sets the metrics back to zero, with the current stack as the highest depth.
+    pub fn reset_metrics(&mut self) {
+        self.metrics = StateMachineMetrics {
+            enabled: self.metrics.enabled,
+            ..StateMachineMetrics::default()
+        };
+        self.metrics.record_depth(self.len());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    struct Named(&'static str);
+
+    impl State<()> for Named {
+        fn name(&self) -> &str {
+            self.0
+        }
+    }
+
+    #[test]
+    fn scripted() {
+        let mut sm = StateMachine::<()>::default();
+        sm.push(Box::new(Named("menu")), &mut ());
+        sm.update(&mut ());
+        assert_eq!(sm.metrics().updates(), 0);
+
+        sm.enable_metrics();
+        sm.transition(StateTransition::Push(Box::new(Named("game"))), &mut ());
+        sm.update(&mut ());
+        sm.update(&mut ());
+        sm.transition(
+            StateTransition::Sequence(vec![
+                StateTransition::Push(Box::new(Named("pause"))),
+                StateTransition::Pop,
+            ]),
+            &mut (),
+        );
+        sm.update(&mut ());
+        sm.transition(StateTransition::Pop, &mut ());
+        sm.update(&mut ());
+
+        let metrics = sm.metrics();
+        assert_eq!(metrics.transitions(TransitionKind::Push), 2);
+        assert_eq!(metrics.transitions(TransitionKind::Pop), 2);
+        assert_eq!(metrics.transitions(TransitionKind::Sequence), 1);
+        assert_eq!(metrics.transitions(TransitionKind::Quit), 0);
+        assert_eq!(metrics.updates(), 4);
+        assert_eq!(metrics.state_updates("game"), 3);
+        assert_eq!(metrics.state_updates("menu"), 1);
+        assert_eq!(metrics.state_updates("pause"), 0);
+        assert_eq!(metrics.max_depth(), 3);
+
+        sm.reset_metrics();
+        assert_eq!(sm.metrics().updates(), 0);
+        assert_eq!(sm.metrics().transitions(TransitionKind::Push), 0);
+        assert_eq!(sm.metrics().max_depth(), 1);
+    }
+}
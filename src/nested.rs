@@ -0,0 +1,541 @@
+//! Using a state machine as a state of another one, for hierarchical flows like a gameplay state
+//! with exploration, combat and dialogue sub-states.
+
+use crate::{
+    Allocator, BoxError, EventOutcome, ExitReason, MachineStatus, MaybeSend, ScopedStateMachine,
+    State, StateMachine, StateTransition, StopReason, TransitionKind,
+};
+use std::any::Any;
+
+/// A state machine used as a state drives its own stack, the child stack, with its own
+/// transitions.
+///
+/// Starting it starts its initial state, if it was created with one, and stopping it stops the
+/// child stack. When the parent machine quits, the child states are told with `on_quit` before
+/// any of them is stopped, and not when the machine is only popped. Pausing and resuming it pauses and resumes its active states. Updating it updates
+/// the child stack with `update`, so the update intervals of the child states are not used, and a
+/// fixed update of it is a fixed update of the top child state. The events it is given are sent
+/// to the child stack, and it consumes the ones a child state consumes. Saving and loading it
+/// notifies the child states. When the child stack stops,
+/// because a child state quit or the last one was popped, the machine pops itself from the
+/// parent stack, and when it stops on an error, it fails with that error in the parent stack.
+/// Use a `SubMachine` to do something else when it stops.
+impl<S: ?Sized + 'static, E: MaybeSend + 'static> State<S, E> for StateMachine<S, E> {
+    fn on_start(&mut self, state_data: &mut S) -> StateTransition<S, E> {
+        self.start(state_data);
+        StateTransition::None
+    }
+
+    fn on_stop(&mut self, state_data: &mut S) -> StateTransition<S, E> {
+        self.stop_nested(state_data);
+        StateTransition::None
+    }
+
+    fn on_quit(&mut self, state_data: &mut S) {
+        self.notify_quit(state_data);
+    }
+
+    fn on_pause(&mut self, state_data: &mut S) {
+        self.pause_visible(state_data);
+        self.settle(state_data);
+    }
+
+    fn on_resume(&mut self, state_data: &mut S) -> StateTransition<S, E> {
+        self.resume_visible(state_data);
+        self.settle(state_data);
+        StateTransition::None
+    }
+
+    fn try_update(&mut self, state_data: &mut S) -> Result<StateTransition<S, E>, BoxError> {
+        Ok(match self.update_nested(state_data)? {
+            Some(_) => StateTransition::Pop,
+            None => StateTransition::None,
+        })
+    }
+
+    #[cfg(feature = "time")]
+    fn fixed_update(&mut self, state_data: &mut S) -> StateTransition<S, E> {
+        self.fixed_update_nested(None, state_data, |_| StateTransition::Pop)
+    }
+
+    #[cfg(feature = "time")]
+    fn fixed_update_with_extra(
+        &mut self,
+        extra: &dyn Any,
+        state_data: &mut S,
+    ) -> StateTransition<S, E> {
+        self.fixed_update_nested(Some(extra), state_data, |_| StateTransition::Pop)
+    }
+
+    fn render(&mut self, state_data: &mut S) {
+        StateMachine::render(self, state_data);
+    }
+
+    fn on_save(&mut self, state_data: &mut S) {
+        self.notify_saving(state_data);
+    }
+
+    fn on_load(&mut self, state_data: &mut S) {
+        self.notify_loaded(state_data);
+    }
+
+    fn handle_event(&mut self, event: &E, state_data: &mut S) -> EventOutcome<S, E> {
+        self.handle_nested(event, None, state_data, |_| StateTransition::Pop)
+    }
+
+    fn handle_event_with_extra(
+        &mut self,
+        event: &E,
+        extra: &dyn Any,
+        state_data: &mut S,
+    ) -> EventOutcome<S, E> {
+        self.handle_nested(event, Some(extra), state_data, |_| StateTransition::Pop)
+    }
+}
+
+impl<'a, S: ?Sized, E, A: Allocator> ScopedStateMachine<'a, S, E, A> {
+    /// Stops the child stack of the machine used as a state, like `force_stop` but without
+    /// calling `on_quit`, which the parent machine calls when it quits.
+    fn stop_nested(&mut self, state_data: &mut S) {
+        self.recorded(TransitionKind::Quit, |sm| {
+            sm.quit_states(ExitReason::StoppedExternally, state_data)
+        });
+        self.settle(state_data);
+    }
+
+    /// Updates the machine used as a state, and returns why it stopped if it did.
+    fn update_nested(&mut self, state_data: &mut S) -> Result<Option<StopReason>, BoxError> {
+        match ScopedStateMachine::update(self, state_data) {
+            MachineStatus::Stopped(StopReason::Error) => match self.take_error() {
+                Some(error) => Err(error),
+                None => Ok(Some(StopReason::Error)),
+            },
+            MachineStatus::Stopped(reason) => Ok(Some(reason)),
+            _ => Ok(None),
+        }
+    }
+
+    /// Does a fixed update of the machine used as a state, and returns the transition `on_exit`
+    /// decides if the child stack stopped.
+    #[cfg(feature = "time")]
+    fn fixed_update_nested(
+        &mut self,
+        extra: Option<&dyn Any>,
+        state_data: &mut S,
+        on_exit: impl FnOnce(StopReason) -> StateTransition<S, E>,
+    ) -> StateTransition<S, E> {
+        if self.frozen {
+            return StateTransition::None;
+        }
+        let last = self.fixed_update_top(extra, state_data);
+        self.settle(state_data);
+        self.exit_transition(last, on_exit)
+    }
+
+    /// Sends an event to the machine used as a state. If a child state consumed it, returns the
+    /// transition `on_exit` decides if the child stack stopped.
+    fn handle_nested(
+        &mut self,
+        event: &E,
+        extra: Option<&dyn Any>,
+        state_data: &mut S,
+        on_exit: impl FnOnce(StopReason) -> StateTransition<S, E>,
+    ) -> EventOutcome<S, E> {
+        let consumed = self.dispatch_event(event, extra, state_data);
+        self.settle(state_data);
+        match consumed {
+            Some(last) => EventOutcome::Consumed(self.exit_transition(last, on_exit)),
+            None => EventOutcome::Ignored,
+        }
+    }
+
+    /// Returns the transition `on_exit` decides if the child stack stopped. When it stopped on an
+    /// error, the machine keeps it until its next update, which fails with it.
+    fn exit_transition(
+        &self,
+        last: Option<TransitionKind>,
+        on_exit: impl FnOnce(StopReason) -> StateTransition<S, E>,
+    ) -> StateTransition<S, E> {
+        match self.status(last) {
+            MachineStatus::Stopped(StopReason::Error) => StateTransition::None,
+            MachineStatus::Stopped(reason) => on_exit(reason),
+            _ => StateTransition::None,
+        }
+    }
+}
+
+/// Decides the transition of a `SubMachine` in the parent stack when its child stack stops.
+pub type ExitMapping<S, E = ()> = Box<maybe_send!(dyn FnMut(StopReason) -> StateTransition<S, E>)>;
+
+/// A state machine used as a state, like a `StateMachine` itself, which decides what happens in
+/// the parent stack when its child stack stops.
+///
+/// The reason given to the mapping is `StopReason::Quit` if a child state quit, and
+/// `StopReason::EmptyStack` if the last child state was popped. Without a mapping, the
+/// sub-machine pops itself. When the child stack stops on an error, the sub-machine fails with
+/// it instead.
+///
+/// ```rust,ignore
+/// let combat = SubMachine::new(StateMachine::new(Box::new(Fight)))
+///     .on_exit(|_| StateTransition::switch(Results));
+/// ```
+pub struct SubMachine<S: ?Sized, E = ()> {
+    state_machine: StateMachine<S, E>,
+    on_exit: Option<ExitMapping<S, E>>,
+}
+
+impl<S: ?Sized, E> SubMachine<S, E> {
+    /// Creates a sub-machine driving this machine, which pops itself when the machine stops.
+    pub fn new(state_machine: StateMachine<S, E>) -> Self {
+        Self {
+            state_machine,
+            on_exit: None,
+        }
+    }
+
+    /// Sets the transition performed in the parent stack when the child stack stops.
+    pub fn on_exit(
+        mut self,
+        on_exit: impl FnMut(StopReason) -> StateTransition<S, E> + MaybeSend + 'static,
+    ) -> Self {
+        self.on_exit = Some(Box::new(on_exit));
+        self
+    }
+
+    /// Returns the child state machine.
+    pub fn state_machine(&self) -> &StateMachine<S, E> {
+        &self.state_machine
+    }
+
+    /// Returns the child state machine, to act on the child stack.
+    pub fn state_machine_mut(&mut self) -> &mut StateMachine<S, E> {
+        &mut self.state_machine
+    }
+}
+
+impl<S: ?Sized + 'static, E: MaybeSend + 'static> State<S, E> for SubMachine<S, E> {
+    fn on_start(&mut self, state_data: &mut S) -> StateTransition<S, E> {
+        self.state_machine.on_start(state_data)
+    }
+
+    fn on_stop(&mut self, state_data: &mut S) -> StateTransition<S, E> {
+        State::on_stop(&mut self.state_machine, state_data)
+    }
+
+    fn on_quit(&mut self, state_data: &mut S) {
+        self.state_machine.notify_quit(state_data);
+    }
+
+    fn on_pause(&mut self, state_data: &mut S) {
+        self.state_machine.on_pause(state_data);
+    }
+
+    fn on_resume(&mut self, state_data: &mut S) -> StateTransition<S, E> {
+        self.state_machine.on_resume(state_data)
+    }
+
+    fn try_update(&mut self, state_data: &mut S) -> Result<StateTransition<S, E>, BoxError> {
+        Ok(match self.state_machine.update_nested(state_data)? {
+            Some(reason) => exit(&mut self.on_exit, reason),
+            None => StateTransition::None,
+        })
+    }
+
+    #[cfg(feature = "time")]
+    fn fixed_update(&mut self, state_data: &mut S) -> StateTransition<S, E> {
+        let Self {
+            state_machine,
+            on_exit,
+        } = self;
+        state_machine.fixed_update_nested(None, state_data, |reason| exit(on_exit, reason))
+    }
+
+    #[cfg(feature = "time")]
+    fn fixed_update_with_extra(
+        &mut self,
+        extra: &dyn Any,
+        state_data: &mut S,
+    ) -> StateTransition<S, E> {
+        let Self {
+            state_machine,
+            on_exit,
+        } = self;
+        state_machine.fixed_update_nested(Some(extra), state_data, |reason| exit(on_exit, reason))
+    }
+
+    fn render(&mut self, state_data: &mut S) {
+        StateMachine::render(&mut self.state_machine, state_data);
+    }
+
+    fn on_save(&mut self, state_data: &mut S) {
+        self.state_machine.notify_saving(state_data);
+    }
+
+    fn on_load(&mut self, state_data: &mut S) {
+        self.state_machine.notify_loaded(state_data);
+    }
+
+    fn handle_event(&mut self, event: &E, state_data: &mut S) -> EventOutcome<S, E> {
+        let Self {
+            state_machine,
+            on_exit,
+        } = self;
+        state_machine.handle_nested(event, None, state_data, |reason| exit(on_exit, reason))
+    }
+
+    fn handle_event_with_extra(
+        &mut self,
+        event: &E,
+        extra: &dyn Any,
+        state_data: &mut S,
+    ) -> EventOutcome<S, E> {
+        let Self {
+            state_machine,
+            on_exit,
+        } = self;
+        state_machine.handle_nested(event, Some(extra), state_data, |reason| {
+            exit(on_exit, reason)
+        })
+    }
+}
+
+/// Returns the transition of a `SubMachine` in the parent stack when its child stack stopped.
+fn exit<S: ?Sized, E>(
+    on_exit: &mut Option<ExitMapping<S, E>>,
+    reason: StopReason,
+) -> StateTransition<S, E> {
+    match on_exit {
+        Some(on_exit) => on_exit(reason),
+        None => StateTransition::Pop,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    type Log = Vec<String>;
+
+    /// Logs its lifecycle, and returns its transition on its first update.
+    struct Scripted(&'static str, Option<StateTransition<Log>>);
+
+    impl State<Log> for Scripted {
+        fn on_start(&mut self, log: &mut Log) -> StateTransition<Log> {
+            log.push(format!("{}:start", self.0));
+            StateTransition::None
+        }
+
+        fn on_stop(&mut self, log: &mut Log) -> StateTransition<Log> {
+            log.push(format!("{}:stop", self.0));
+            StateTransition::None
+        }
+
+        fn on_pause(&mut self, log: &mut Log) {
+            log.push(format!("{}:pause", self.0));
+        }
+
+        fn on_resume(&mut self, log: &mut Log) -> StateTransition<Log> {
+            log.push(format!("{}:resume", self.0));
+            StateTransition::None
+        }
+
+        fn update(&mut self, log: &mut Log) -> StateTransition<Log> {
+            log.push(format!("{}:update", self.0));
+            self.1.take().unwrap_or(StateTransition::None)
+        }
+    }
+
+    fn scripted(name: &'static str, transition: StateTransition<Log>) -> Box<Scripted> {
+        Box::new(Scripted(name, Some(transition)))
+    }
+
+    #[test]
+    fn two_levels() {
+        let mut log = Log::new();
+        let combat = StateMachine::new(scripted("attack", StateTransition::Quit));
+        let gameplay =
+            StateMachine::new(scripted("explore", StateTransition::Push(Box::new(combat))));
+        let mut sm = StateMachine::<Log>::default();
+        sm.push(Box::new(Scripted("menu", None)), &mut log);
+        sm.push(Box::new(gameplay), &mut log);
+        assert_eq!(log, vec!["menu:start", "menu:pause", "explore:start"]);
+
+        log.clear();
+        sm.update(&mut log);
+        assert_eq!(log, vec!["explore:update", "explore:pause", "attack:start"]);
+
+        log.clear();
+        sm.push(Box::new(Scripted("pause", None)), &mut log);
+        sm.pop(&mut log);
+        assert_eq!(
+            log,
+            vec!["attack:pause", "pause:start", "pause:stop", "attack:resume"]
+        );
+
+        log.clear();
+        sm.update(&mut log);
+        assert_eq!(log, vec!["attack:update", "attack:stop", "explore:resume"]);
+
+        log.clear();
+        sm.stop(&mut log);
+        assert_eq!(log, vec!["explore:stop", "menu:stop"]);
+    }
+
+    #[test]
+    fn empty_child_pops() {
+        let mut log = Log::new();
+        let child = StateMachine::new(scripted("last", StateTransition::Pop));
+        let mut sm = StateMachine::<Log>::default();
+        sm.push(Box::new(Scripted("menu", None)), &mut log);
+        sm.push(Box::new(child), &mut log);
+        sm.update(&mut log);
+        assert_eq!(sm.len(), 1);
+        assert_eq!(log.last().map(String::as_str), Some("menu:resume"));
+    }
+
+    /// Switches to the results when the child quits, and to the menu when it empties.
+    fn results(reason: StopReason) -> StateTransition<Log> {
+        match reason {
+            StopReason::Quit => StateTransition::Switch(Box::new(Scripted("results", None))),
+            _ => StateTransition::Switch(Box::new(Scripted("menu", None))),
+        }
+    }
+
+    #[test]
+    fn exit_mapping() {
+        for (exit, expected) in [
+            (StateTransition::Quit, "results:start"),
+            (StateTransition::Pop, "menu:start"),
+        ] {
+            let mut log = Log::new();
+            let child = StateMachine::new(scripted("combat", exit));
+            let mut sm = StateMachine::<Log>::default();
+            sm.push(Box::new(SubMachine::new(child).on_exit(results)), &mut log);
+            sm.update(&mut log);
+            assert_eq!(log.last().map(String::as_str), Some(expected));
+            assert_eq!(sm.len(), 1);
+        }
+    }
+
+    /// Logs the events and fixed updates it is given, and consumes the first event with its
+    /// transition.
+    struct Listener(&'static str, Option<StateTransition<Log>>);
+
+    impl State<Log> for Listener {
+        #[cfg(feature = "time")]
+        fn fixed_update(&mut self, log: &mut Log) -> StateTransition<Log> {
+            log.push(format!("{}:fixed_update", self.0));
+            StateTransition::None
+        }
+
+        fn handle_event(&mut self, _event: &(), log: &mut Log) -> EventOutcome<Log> {
+            log.push(format!("{}:event", self.0));
+            match self.1.take() {
+                Some(transition) => EventOutcome::Consumed(transition),
+                None => EventOutcome::Ignored,
+            }
+        }
+    }
+
+    #[test]
+    fn nested_events() {
+        let mut log = Log::new();
+        let mut dialogue =
+            StateMachine::new(Box::new(Listener("dialogue", Some(StateTransition::Quit))));
+        dialogue.queue_transition(StateTransition::Push(Box::new(Listener(
+            "line",
+            Some(StateTransition::None),
+        ))));
+        let mut sm = StateMachine::<Log>::default();
+        sm.push(Box::new(Listener("menu", None)), &mut log);
+        sm.push(
+            Box::new(
+                SubMachine::new(dialogue)
+                    .on_exit(|_| StateTransition::Replace(Box::new(Scripted("results", None)))),
+            ),
+            &mut log,
+        );
+        sm.update(&mut log);
+
+        sm.handle_event(&(), &mut log);
+        assert_eq!(log, vec!["line:event"]);
+
+        log.clear();
+        assert_eq!(
+            sm.handle_event(&(), &mut log),
+            MachineStatus::Transitioned(TransitionKind::Replace)
+        );
+        assert_eq!(log, vec!["line:event", "dialogue:event", "results:start"]);
+        assert_eq!(sm.len(), 2);
+
+        log.clear();
+        sm.handle_event(&(), &mut log);
+        assert_eq!(log, vec!["menu:event"]);
+    }
+
+    #[cfg(feature = "time")]
+    #[test]
+    fn nested_fixed_update() {
+        let mut log = Log::new();
+        let child = StateMachine::new(Box::new(Listener("line", None)));
+        let mut sm = StateMachine::<Log>::default();
+        sm.push(Box::new(Listener("menu", None)), &mut log);
+        sm.push(Box::new(child), &mut log);
+        sm.set_fixed_timestep(std::time::Duration::from_millis(10));
+        sm.update_with_dt(std::time::Duration::from_millis(20), &mut log);
+        assert_eq!(log, vec!["line:fixed_update", "line:fixed_update"]);
+    }
+
+    #[test]
+    fn nested_quit() {
+        let calls = CallLog::default();
+        let child = StateMachine::new(Box::new(RecordingState::new("line", &calls)));
+        let mut sm = StateMachine::<()>::default();
+        sm.push(Box::new(RecordingState::new("menu", &calls)), &mut ());
+        sm.push(Box::new(SubMachine::new(child)), &mut ());
+        calls.lock().unwrap().clear();
+
+        sm.queue_transition(StateTransition::Quit);
+        sm.update(&mut ());
+        assert_calls(
+            &calls,
+            &[
+                ("menu", LifecycleCall::Quit),
+                ("line", LifecycleCall::Quit),
+                ("line", LifecycleCall::Stop),
+                ("menu", LifecycleCall::Stop),
+            ],
+        );
+    }
+
+    #[test]
+    fn nested_pop_not_quit() {
+        let calls = CallLog::default();
+        let child = StateMachine::new(Box::new(RecordingState::new("line", &calls)));
+        let mut sm = StateMachine::<()>::default();
+        sm.push(Box::new(RecordingState::new("menu", &calls)), &mut ());
+        sm.push(Box::new(child), &mut ());
+        calls.lock().unwrap().clear();
+
+        sm.pop(&mut ());
+        assert_calls(
+            &calls,
+            &[
+                ("line", LifecycleCall::Stop),
+                ("menu", LifecycleCall::Resume),
+            ],
+        );
+    }
+
+    #[test]
+    fn exit_defaults_to_pop() {
+        let mut log = Log::new();
+        let child = StateMachine::new(scripted("combat", StateTransition::Quit));
+        let mut sm = StateMachine::<Log>::default();
+        sm.push(Box::new(Scripted("menu", None)), &mut log);
+        sm.push(Box::new(SubMachine::new(child)), &mut log);
+        sm.update(&mut log);
+        assert_eq!(sm.len(), 1);
+        assert!(sm.is_running());
+    }
+}
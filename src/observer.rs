@@ -0,0 +1,309 @@
+//! Reacting to the transitions of a state machine, for screen fades, sounds or analytics.
+
+use crate::{Allocator, MaybeSend, ScopedStateMachine, TransitionKind};
+
+/// A transition that was performed by a state machine.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TransitionEvent {
+    /// The kind of the transition.
+    pub kind: TransitionKind,
+    /// The name of the active state before the transition, if any.
+    pub from: Option<String>,
+    /// The name of the active state after the transition, if any.
+    pub to: Option<String>,
+    /// The number of states on the stack after the transition.
+    pub depth: usize,
+}
+
+/// Reacts to the transitions performed by a state machine.
+/// Closures taking a `&TransitionEvent` and the state data are observers.
+pub trait TransitionObserver<S: ?Sized>: MaybeSend {
+    /// Called after a transition was performed and the lifecycle methods it caused were called.
+    fn on_transition(&mut self, event: &TransitionEvent, state_data: &mut S);
+
+    /// Called once the transitions observed since the last call are done, along with the
+    /// transitions they caused, and the states they stopped were dropped.
+    /// Does nothing by default.
+    fn on_transition_complete(&mut self, _state_data: &mut S) {}
+}
+
+impl<S: ?Sized, F: FnMut(&TransitionEvent, &mut S) + MaybeSend> TransitionObserver<S> for F {
+    fn on_transition(&mut self, event: &TransitionEvent, state_data: &mut S) {
+        self(event, state_data)
+    }
+}
+
+/// Identifies an observer added to a state machine, to remove it later.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ObserverId(usize);
+
+/// The observers of a state machine, in the order they were added.
+pub(crate) struct Observers<S: ?Sized> {
+    observers: Vec<(ObserverId, Box<dyn TransitionObserver<S>>)>,
+    next_id: usize,
+    /// If the observers were told about a transition that is not complete yet.
+    pending: bool,
+}
+
+impl<S: ?Sized> Default for Observers<S> {
+    fn default() -> Self {
+        Self {
+            observers: Vec::new(),
+            next_id: 0,
+            pending: false,
+        }
+    }
+}
+
+impl<S: ?Sized> Observers<S> {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.observers.is_empty()
+    }
+}
+
+impl<'a, S: ?Sized, E, A: Allocator> ScopedStateMachine<'a, S, E, A> {
+    /// Adds an observer that is told about every transition the states of this machine return,
+    /// after the ones added before it.
+    /// The operations done directly on the machine, like `push`, are not observed.
+    pub fn add_observer(&mut self, observer: Box<dyn TransitionObserver<S>>) -> ObserverId {
+        let id = ObserverId(self.observers.next_id);
+        self.observers.next_id += 1;
+        self.observers.observers.push((id, observer));
+        id
+    }
+
+    /// Removes an observer, returning it if it was part of this machine.
+    pub fn remove_observer(&mut self, id: ObserverId) -> Option<Box<dyn TransitionObserver<S>>> {
+        let index = self
+            .observers
+            .observers
+            .iter()
+            .position(|(observer_id, _)| *observer_id == id)?;
+        Some(self.observers.observers.remove(index).1)
+    }
+
+    /// Returns if the transitions are observed, logged, traced or kept in the history.
+    fn observed(&self) -> bool {
+        #[cfg(feature = "log")]
+        let logged = log::log_enabled!(log::Level::Debug);
+        #[cfg(not(feature = "log"))]
+        let logged = false;
+        #[cfg(feature = "tracing")]
+        let traced = tracing::enabled!(tracing::Level::DEBUG);
+        #[cfg(not(feature = "tracing"))]
+        let traced = false;
+        #[cfg(feature = "instrument-json")]
+        let instrumented = self.instrument.is_some();
+        #[cfg(not(feature = "instrument-json"))]
+        let instrumented = false;
+        logged || traced || instrumented || self.history.is_some() || !self.observers.is_empty()
+    }
+
+    /// Returns the name of the active state, if the transitions are observed.
+    pub(crate) fn observed_name(&self) -> Option<String> {
+        if !self.observed() {
+            None
+        } else {
+            self.active_name().map(str::to_string)
+        }
+    }
+
+    /// Tells the observers about a transition that was performed, logs, traces and keeps it.
+    pub(crate) fn notify(
+        &mut self,
+        kind: TransitionKind,
+        from: Option<String>,
+        state_data: &mut S,
+    ) {
+        if !self.observed() {
+            return;
+        }
+        let event = TransitionEvent {
+            kind,
+            from,
+            to: self.active_name().map(str::to_string),
+            depth: self.len(),
+        };
+        log_debug!(
+            "{:?}: {} -> {}, depth {}",
+            event.kind,
+            event.from.as_deref().unwrap_or("none"),
+            event.to.as_deref().unwrap_or("none"),
+            event.depth
+        );
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            kind = ?event.kind,
+            from = event.from.as_deref(),
+            to = event.to.as_deref(),
+            depth = event.depth,
+            "transition"
+        );
+        #[cfg(feature = "instrument-json")]
+        self.instrument_transition(&event);
+        for (_, observer) in self.observers.observers.iter_mut() {
+            observer.on_transition(&event, state_data);
+        }
+        self.observers.pending = !self.observers.is_empty();
+        self.record_history(event.kind, event.from, event.to);
+    }
+
+    /// Tells the observers that the transitions they were told about are complete.
+    pub(crate) fn notify_complete(&mut self, state_data: &mut S) {
+        if std::mem::take(&mut self.observers.pending) {
+            for (_, observer) in self.observers.observers.iter_mut() {
+                observer.on_transition_complete(state_data);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use std::sync::{Arc, Mutex};
+
+    type Events = Arc<Mutex<Vec<TransitionEvent>>>;
+
+    struct Named(&'static str);
+
+    impl State<()> for Named {
+        fn name(&self) -> &str {
+            self.0
+        }
+    }
+
+    fn event(
+        kind: TransitionKind,
+        from: Option<&str>,
+        to: Option<&str>,
+        depth: usize,
+    ) -> TransitionEvent {
+        TransitionEvent {
+            kind,
+            from: from.map(String::from),
+            to: to.map(String::from),
+            depth,
+        }
+    }
+
+    #[test]
+    fn push_pop_quit() {
+        let mut sm = StateMachine::<()>::default();
+        sm.push(Box::new(Named("menu")), &mut ());
+
+        let events = Events::default();
+        let recorded = events.clone();
+        sm.add_observer(Box::new(move |event: &TransitionEvent, _: &mut ()| {
+            recorded.lock().unwrap().push(event.clone())
+        }));
+        sm.transition(
+            StateTransition::Sequence(vec![
+                StateTransition::Push(Box::new(Named("game"))),
+                StateTransition::Pop,
+                StateTransition::Quit,
+            ]),
+            &mut (),
+        );
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![
+                event(TransitionKind::Push, Some("menu"), Some("game"), 2),
+                event(TransitionKind::Pop, Some("game"), Some("menu"), 1),
+                event(TransitionKind::Quit, Some("menu"), None, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn order_and_removal() {
+        let mut sm = StateMachine::<Vec<&str>>::default();
+        let first = sm.add_observer(Box::new(|_: &TransitionEvent, log: &mut Vec<&str>| {
+            log.push("first")
+        }));
+        sm.add_observer(Box::new(|_: &TransitionEvent, log: &mut Vec<&str>| {
+            log.push("second")
+        }));
+
+        let mut log = Vec::new();
+        sm.transition(StateTransition::Push(Box::new(Blank)), &mut log);
+        assert_eq!(log, vec!["first", "second"]);
+
+        assert!(sm.remove_observer(first).is_some());
+        assert!(sm.remove_observer(first).is_none());
+        log.clear();
+        sm.transition(StateTransition::Pop, &mut log);
+        assert_eq!(log, vec!["second"]);
+    }
+
+    struct Blank;
+
+    impl State<Vec<&'static str>> for Blank {}
+
+    type Shared = Arc<Mutex<Vec<String>>>;
+
+    /// Logs when it is stopped, resumed and dropped, and pops itself when updated.
+    struct Dropped(&'static str, Shared);
+
+    impl State<()> for Dropped {
+        fn on_resume(&mut self, _: &mut ()) -> StateTransition<()> {
+            self.1.lock().unwrap().push(format!("{}:resume", self.0));
+            StateTransition::None
+        }
+
+        fn update(&mut self, _: &mut ()) -> StateTransition<()> {
+            StateTransition::Pop
+        }
+
+        fn on_stop(&mut self, _: &mut ()) -> StateTransition<()> {
+            self.1.lock().unwrap().push(format!("{}:stop", self.0));
+            StateTransition::None
+        }
+    }
+
+    impl Drop for Dropped {
+        fn drop(&mut self) {
+            self.1.lock().unwrap().push(format!("{}:drop", self.0));
+        }
+    }
+
+    struct Completion(Shared);
+
+    impl TransitionObserver<()> for Completion {
+        fn on_transition(&mut self, event: &TransitionEvent, _: &mut ()) {
+            self.0.lock().unwrap().push(format!("{:?}", event.kind));
+        }
+
+        fn on_transition_complete(&mut self, _: &mut ()) {
+            self.0.lock().unwrap().push("complete".to_string());
+        }
+    }
+
+    #[test]
+    fn dropped_once_complete() {
+        let log = Shared::default();
+        let mut sm = StateMachine::<()>::default();
+        sm.push(Box::new(Dropped("world", log.clone())), &mut ());
+        sm.push(Box::new(Dropped("dialog", log.clone())), &mut ());
+        sm.add_observer(Box::new(Completion(log.clone())));
+        sm.update(&mut ());
+        assert_eq!(
+            *log.lock().unwrap(),
+            [
+                "dialog:stop",
+                "world:resume",
+                "Pop",
+                "dialog:drop",
+                "complete"
+            ]
+        );
+
+        log.lock().unwrap().clear();
+        sm.push(Box::new(Dropped("dialog", log.clone())), &mut ());
+        sm.pop(&mut ());
+        assert_eq!(
+            *log.lock().unwrap(),
+            ["dialog:stop", "world:resume", "dialog:drop"]
+        );
+    }
+}
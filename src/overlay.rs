@@ -0,0 +1,134 @@
+//! A second stack of states drawn over the main one, for the HUD, a debug console or
+//! notifications, which must keep running when the main stack is paused.
+
+use crate::{
+    Allocator, InterceptDecision, ScopedStateMachine, State, StateMachine, StateTransition,
+};
+
+impl<S: ?Sized + 'static, E: 'static, A: Allocator> StateMachine<S, E, A> {
+    /// Pushes a state on the overlay stack.
+    ///
+    /// The overlay stack is updated right after the main stack and rendered over it. Its states
+    /// are paused, resumed and shadow updated among themselves like the ones of the main stack,
+    /// so only the top overlay is updated unless it is transparent, and the main stack never
+    /// pauses them. Their transitions only change the overlay stack, where `Quit` pops the overlay
+    /// returning it instead of stopping every overlay.
+    ///
+    /// The overlays do not count for `is_running`, and stay when the main stack is stopped.
+    pub fn push_overlay(&mut self, state: Box<dyn State<S, E>>, state_data: &mut S) {
+        self.overlays
+            .get_or_insert_with(|| {
+                let mut overlays = StateMachine::default();
+                overlays.set_interceptor(|transition, _| match transition {
+                    StateTransition::Quit | StateTransition::QuitWith(_) => {
+                        InterceptDecision::ReplaceWith(StateTransition::Pop)
+                    }
+                    _ => InterceptDecision::Allow,
+                });
+                Box::new(overlays)
+            })
+            .push(state, state_data);
+    }
+}
+
+impl<'a, S: ?Sized, E, A: Allocator> ScopedStateMachine<'a, S, E, A> {
+    /// Pops the top state of the overlay stack, see `push_overlay`.
+    pub fn pop_overlay(&mut self, state_data: &mut S) {
+        if let Some(overlays) = &mut self.overlays {
+            overlays.pop(state_data);
+        }
+    }
+
+    /// Returns the number of states on the overlay stack.
+    pub fn overlay_len(&self) -> usize {
+        self.overlays.as_ref().map_or(0, |overlays| overlays.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    type Log = Vec<String>;
+
+    /// Logs its lifecycle and updates, and returns its transition once.
+    struct Scripted(&'static str, Option<StateTransition<Log>>);
+
+    impl State<Log> for Scripted {
+        fn name(&self) -> &str {
+            self.0
+        }
+
+        fn on_start(&mut self, log: &mut Log) -> StateTransition<Log> {
+            log.push(format!("{}:start", self.0));
+            StateTransition::None
+        }
+
+        fn on_stop(&mut self, log: &mut Log) -> StateTransition<Log> {
+            log.push(format!("{}:stop", self.0));
+            StateTransition::None
+        }
+
+        fn on_pause(&mut self, log: &mut Log) {
+            log.push(format!("{}:pause", self.0));
+        }
+
+        fn update(&mut self, log: &mut Log) -> StateTransition<Log> {
+            log.push(format!("{}:update", self.0));
+            self.1.take().unwrap_or(StateTransition::None)
+        }
+
+        fn render(&mut self, log: &mut Log) {
+            log.push(format!("{}:render", self.0));
+        }
+    }
+
+    fn scripted(name: &'static str, transition: StateTransition<Log>) -> Box<Scripted> {
+        Box::new(Scripted(name, Some(transition)))
+    }
+
+    #[test]
+    fn survives_switch() {
+        let mut log = Log::new();
+        let mut sm = StateMachine::<Log>::default();
+        let switch = StateTransition::Switch(Box::new(Scripted("menu", None)));
+        sm.push(scripted("game", switch), &mut log);
+        sm.push_overlay(Box::new(Scripted("hud", None)), &mut log);
+
+        log.clear();
+        sm.update(&mut log);
+        assert_eq!(
+            log,
+            vec!["game:update", "game:stop", "menu:start", "hud:update"]
+        );
+
+        log.clear();
+        sm.render(&mut log);
+        assert_eq!(log, vec!["menu:render", "hud:render"]);
+        assert_eq!(sm.overlay_len(), 1);
+    }
+
+    #[test]
+    fn quit_pops_overlay() {
+        let mut log = Log::new();
+        let mut sm = StateMachine::<Log>::default();
+        sm.push(Box::new(Scripted("game", None)), &mut log);
+        sm.push_overlay(Box::new(Scripted("hud", None)), &mut log);
+        sm.push_overlay(scripted("console", StateTransition::Quit), &mut log);
+        assert_eq!(
+            log,
+            vec!["game:start", "hud:start", "hud:pause", "console:start"]
+        );
+
+        log.clear();
+        sm.update(&mut log);
+        assert_eq!(log, vec!["game:update", "console:update", "console:stop"]);
+        assert_eq!(sm.overlay_len(), 1);
+        assert!(sm.is_running());
+
+        sm.stop(&mut log);
+        assert!(!sm.is_running());
+        sm.pop_overlay(&mut log);
+        assert_eq!(sm.overlay_len(), 0);
+    }
+}
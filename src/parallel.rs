@@ -0,0 +1,262 @@
+//! Running several independent state machines over the same state data, for split-screen or to
+//! run the world simulation apart from the user interface, and shadow updating the covered
+//! states of a machine on several threads with the `parallel` feature.
+
+use crate::{MachineStatus, StateMachine, StopReason};
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+#[cfg(feature = "time")]
+use std::time::Duration;
+
+/// What a `ParallelStateMachine` does when a state of one of its lanes quits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum QuitPolicy {
+    /// Only the lane of the state is stopped.
+    StopLane,
+    /// All the lanes are stopped, without letting their states cancel it, and the lanes after it
+    /// are not updated this frame.
+    StopAll,
+}
+
+/// Named state machines, the lanes, updated one after the other with the same state data.
+/// The lanes are updated in the order they were added.
+pub struct ParallelStateMachine<S: ?Sized, E = ()> {
+    lanes: Vec<(String, StateMachine<S, E>)>,
+    quit_policy: QuitPolicy,
+}
+
+impl<S: ?Sized, E> Default for ParallelStateMachine<S, E> {
+    fn default() -> Self {
+        Self {
+            lanes: Vec::new(),
+            quit_policy: QuitPolicy::StopLane,
+        }
+    }
+}
+
+impl<S: ?Sized, E> ParallelStateMachine<S, E> {
+    /// Creates a machine without lanes, which stops only the lane of a state that quits.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets what happens when a state quits. Defaults to `QuitPolicy::StopLane`.
+    pub fn set_quit_policy(&mut self, policy: QuitPolicy) {
+        self.quit_policy = policy;
+    }
+
+    /// Adds a lane, updated after the ones added before it.
+    /// If a lane already has this name, its machine is replaced and returned, and the lane keeps
+    /// its place.
+    pub fn add_lane(
+        &mut self,
+        name: impl Into<String>,
+        state_machine: StateMachine<S, E>,
+    ) -> Option<StateMachine<S, E>> {
+        let name = name.into();
+        match self.lanes.iter_mut().find(|(lane, _)| *lane == name) {
+            Some((_, lane)) => Some(std::mem::replace(lane, state_machine)),
+            None => {
+                self.lanes.push((name, state_machine));
+                None
+            }
+        }
+    }
+
+    /// Removes a lane and returns its machine, without stopping it.
+    pub fn remove_lane(&mut self, name: &str) -> Option<StateMachine<S, E>> {
+        let index = self.lanes.iter().position(|(lane, _)| lane == name)?;
+        Some(self.lanes.remove(index).1)
+    }
+
+    /// Returns the machine of a lane, to push states on it.
+    pub fn lane(&mut self, name: &str) -> Option<&mut StateMachine<S, E>> {
+        self.lanes
+            .iter_mut()
+            .find(|(lane, _)| lane == name)
+            .map(|(_, lane)| lane)
+    }
+
+    /// Iterates over the names of the lanes, in the order they are updated.
+    pub fn lane_names(&self) -> impl Iterator<Item = &str> + '_ {
+        self.lanes.iter().map(|(name, _)| name.as_str())
+    }
+
+    /// Returns if the lane exists and is running.
+    pub fn is_lane_running(&self, name: &str) -> bool {
+        self.lanes
+            .iter()
+            .any(|(lane, state_machine)| lane == name && state_machine.is_running())
+    }
+
+    /// Returns if any lane is running.
+    pub fn is_running(&self) -> bool {
+        self.lanes
+            .iter()
+            .any(|(_, state_machine)| state_machine.is_running())
+    }
+
+    /// Updates the lanes in order, see `StateMachine::update`.
+    pub fn update(&mut self, state_data: &mut S) {
+        if self.update_lanes(|state_machine| state_machine.update(state_data)) {
+            self.force_stop(state_data);
+        }
+    }
+
+    /// Updates the lanes in order with the time elapsed since the last frame, see
+    /// `StateMachine::update_with_dt`.
+    #[cfg(feature = "time")]
+    pub fn update_with_dt(&mut self, dt: Duration, state_data: &mut S) {
+        if self.update_lanes(|state_machine| state_machine.update_with_dt(dt, state_data)) {
+            self.force_stop(state_data);
+        }
+    }
+
+    /// Renders the lanes in order, see `StateMachine::render`.
+    pub fn render(&mut self, state_data: &mut S) {
+        for (_, state_machine) in self.lanes.iter_mut() {
+            state_machine.render(state_data);
+        }
+    }
+
+    /// Stops all the lanes, in order, see `StateMachine::stop`.
+    pub fn stop(&mut self, state_data: &mut S) {
+        for (_, state_machine) in self.lanes.iter_mut() {
+            state_machine.stop(state_data);
+        }
+    }
+
+    /// Stops all the lanes, in order, without letting their states cancel it, see
+    /// `StateMachine::force_stop`.
+    pub fn force_stop(&mut self, state_data: &mut S) {
+        for (_, state_machine) in self.lanes.iter_mut() {
+            state_machine.force_stop(state_data);
+        }
+    }
+
+    /// Updates the lanes in order, and returns if they must all be stopped.
+    fn update_lanes(
+        &mut self,
+        mut update: impl FnMut(&mut StateMachine<S, E>) -> MachineStatus,
+    ) -> bool {
+        let stop_all = self.quit_policy == QuitPolicy::StopAll;
+        self.lanes.iter_mut().any(|(_, state_machine)| {
+            update(state_machine) == MachineStatus::Stopped(StopReason::Quit) && stop_all
+        })
+    }
+}
+
+#[cfg(feature = "parallel")]
+impl<S: ?Sized + Sync, E> StateMachine<S, E> {
+    /// Calls `State::par_shadow_update` on the states covered by the top of the stack, in
+    /// parallel on the threads of the rayon pool, then returns. The active state is not updated,
+    /// `update` is called after this to update it with a mutable access to the state data.
+    ///
+    /// The covered states run in no particular order, and may run at the same time, so their
+    /// results must not depend on each other. They only read the state data, so the ones
+    /// writing somewhere else, like to an atomic or a channel, must not rely on the order of
+    /// the writes either, or the frames run differently from one session to the next.
+    /// A panic in one of them is not caught and resumes in this call, once all the others ran.
+    pub fn par_shadow_update(&mut self, state_data: &S) {
+        let visible = self.visible_start();
+        self.state_stack[..visible]
+            .par_iter_mut()
+            .for_each(|entry| entry.state.par_shadow_update(state_data));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    type Log = Vec<String>;
+
+    /// Logs its updates, and returns its transition once.
+    struct Scripted(&'static str, Option<StateTransition<Log>>);
+
+    impl State<Log> for Scripted {
+        fn name(&self) -> &str {
+            self.0
+        }
+
+        fn update(&mut self, log: &mut Log) -> StateTransition<Log> {
+            log.push(self.0.to_string());
+            self.1.take().unwrap_or(StateTransition::None)
+        }
+    }
+
+    fn lanes(log: &mut Log) -> ParallelStateMachine<Log> {
+        let mut sm = ParallelStateMachine::new();
+        for (lane, name) in [("world", "simulation"), ("ui", "hud")] {
+            let mut lane_machine = StateMachine::default();
+            lane_machine.push(Box::new(Scripted(name, None)), log);
+            sm.add_lane(lane, lane_machine);
+        }
+        sm
+    }
+
+    #[test]
+    fn ordered_and_independent() {
+        let mut log = Log::new();
+        let mut sm = lanes(&mut log);
+        let pause = Scripted("pause", Some(StateTransition::Pop));
+        sm.lane("ui").unwrap().push(Box::new(pause), &mut log);
+
+        sm.update(&mut log);
+        sm.update(&mut log);
+        assert_eq!(log, vec!["simulation", "pause", "simulation", "hud"]);
+        assert_eq!(sm.lane("world").unwrap().len(), 1);
+        assert_eq!(sm.lane_names().collect::<Vec<_>>(), vec!["world", "ui"]);
+    }
+
+    #[test]
+    fn quit() {
+        let mut log = Log::new();
+        let mut sm = lanes(&mut log);
+        let quit = Scripted("credits", Some(StateTransition::Quit));
+        sm.lane("world").unwrap().push(Box::new(quit), &mut log);
+        sm.update(&mut log);
+        assert!(!sm.is_lane_running("world"));
+        assert!(sm.is_lane_running("ui"));
+        assert!(sm.is_running());
+
+        let mut log = Log::new();
+        let mut sm = lanes(&mut log);
+        sm.set_quit_policy(QuitPolicy::StopAll);
+        let quit = Scripted("credits", Some(StateTransition::Quit));
+        sm.lane("world").unwrap().push(Box::new(quit), &mut log);
+        sm.update(&mut log);
+        assert_eq!(log, vec!["credits"]);
+        assert!(!sm.is_running());
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn par_shadow_update() {
+        use crate::*;
+        use std::sync::atomic::{AtomicU32, Ordering};
+
+        /// Counts its parallel shadow updates at its index.
+        struct Simulation(usize);
+
+        impl State<Vec<AtomicU32>> for Simulation {
+            fn par_shadow_update(&mut self, counts: &Vec<AtomicU32>) {
+                counts[self.0].fetch_add(1, Ordering::Relaxed);
+            }
+        }
+
+        let mut counts: Vec<_> = (0..5).map(|_| AtomicU32::new(0)).collect();
+        let mut sm = StateMachine::default();
+        for index in 0..5 {
+            sm.push(Box::new(Simulation(index)), &mut counts);
+        }
+        sm.par_shadow_update(&counts);
+        sm.par_shadow_update(&counts);
+        let counts: Vec<_> = counts
+            .iter()
+            .map(|count| count.load(Ordering::Relaxed))
+            .collect();
+        // The top state is not covered.
+        assert_eq!(counts, [2, 2, 2, 2, 0]);
+    }
+}
@@ -0,0 +1,144 @@
+//! Storing a state machine as a resource of `planck_ecs`, and driving it from a system.
+//!
+//! The resources of a `World` are `Send` and `Sync`, so the feature enables `send`, and the
+//! machine is kept behind a `Mutex` which is never locked, since the systems borrow it mutably.
+//!
+//! A machine whose states take the other resources as state data is updated by
+//! `state_machine_system`. A machine whose states take the whole `World` cannot be updated by a
+//! system, which only borrows the world, so it is updated with `update_world` between the runs
+//! of the dispatcher instead.
+
+use crate::{MachineStatus, StateMachine};
+use planck_ecs::{EcsError, IntoSystem, System, World};
+use std::sync::{Mutex, PoisonError};
+
+/// A state machine stored as a resource.
+pub struct StateMachineRes<S, E = ()> {
+    state_machine: Mutex<StateMachine<S, E>>,
+}
+
+impl<S, E> StateMachineRes<S, E> {
+    /// Wraps this state machine to store it in a `World`.
+    pub fn new(state_machine: StateMachine<S, E>) -> Self {
+        Self {
+            state_machine: Mutex::new(state_machine),
+        }
+    }
+
+    /// Returns the state machine.
+    pub fn get_mut(&mut self) -> &mut StateMachine<S, E> {
+        self.state_machine
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Returns the state machine, taking it out of the resource.
+    pub fn into_inner(self) -> StateMachine<S, E> {
+        self.state_machine
+            .into_inner()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl<S, E> Default for StateMachineRes<S, E> {
+    fn default() -> Self {
+        Self::new(StateMachine::default())
+    }
+}
+
+/// Returns a system updating the `StateMachineRes<S, E>` resource with the `S` resource as state
+/// data. The resources are initialized by the dispatcher if they are missing, the machine having
+/// no state then.
+pub fn state_machine_system<S, E>() -> System
+where
+    S: Default + Send + Sync + 'static,
+    E: Send + 'static,
+{
+    (|state_machine: &mut StateMachineRes<S, E>, state_data: &mut S| {
+        state_machine.get_mut().update(state_data);
+        Ok(())
+    })
+    .system()
+}
+
+/// Updates the `StateMachineRes<World, E>` resource with the world as state data.
+/// The machine is taken out of the world during the update, the resource being left with an
+/// empty machine, then put back, so the states can use all the other resources.
+///
+/// Returns an error if the resource is missing or borrowed.
+pub fn update_world<E: Send + 'static>(world: &mut World) -> Result<MachineStatus, EcsError> {
+    let mut state_machine = std::mem::take(&mut *world.get_mut::<StateMachineRes<World, E>>()?);
+    let status = state_machine.get_mut().update(world);
+    *world.get_mut::<StateMachineRes<World, E>>()? = state_machine;
+    Ok(status)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use planck_ecs::{DispatcherBuilder, World};
+
+    #[derive(Default)]
+    struct Score(u32);
+
+    struct Playing;
+
+    impl State<Score> for Playing {
+        fn update(&mut self, score: &mut Score) -> StateTransition<Score> {
+            score.0 += 1;
+            if score.0 == 3 {
+                StateTransition::Quit
+            } else {
+                StateTransition::None
+            }
+        }
+    }
+
+    #[test]
+    fn system_in_a_dispatcher() {
+        let mut world = World::default();
+        let mut dispatcher = DispatcherBuilder::new()
+            .add_system(state_machine_system::<Score, ()>())
+            .build(&mut world);
+        let mut state_machine = StateMachine::new(Box::new(Playing));
+        state_machine.start(&mut *world.get_mut::<Score>().unwrap());
+        *world.get_mut::<StateMachineRes<Score>>().unwrap() = StateMachineRes::new(state_machine);
+
+        for _ in 0..5 {
+            dispatcher.run_seq(&world).unwrap();
+        }
+        assert_eq!(world.get::<Score>().unwrap().0, 3);
+        let mut state_machine = world.get_mut::<StateMachineRes<Score>>().unwrap();
+        assert_eq!(
+            state_machine.get_mut().exit_reason(),
+            Some(ExitReason::Quit)
+        );
+    }
+
+    struct Spawner;
+
+    impl State<World> for Spawner {
+        fn update(&mut self, world: &mut World) -> StateTransition<World> {
+            world.get_mut_or_default::<Score>().0 += 1;
+            let machine = world.get_mut::<StateMachineRes<World>>();
+            assert!(!machine.unwrap().get_mut().is_running());
+            StateTransition::None
+        }
+    }
+
+    #[test]
+    fn world_as_state_data() {
+        let mut world = World::default();
+        assert!(update_world::<()>(&mut world).is_err());
+        world.initialize::<StateMachineRes<World>>();
+        let mut state_machine = StateMachine::default();
+        state_machine.push_state(Spawner, &mut world);
+        *world.get_mut::<StateMachineRes<World>>().unwrap() = StateMachineRes::new(state_machine);
+
+        update_world::<()>(&mut world).unwrap();
+        update_world::<()>(&mut world).unwrap();
+        assert_eq!(world.get::<Score>().unwrap().0, 2);
+        let mut state_machine = world.get_mut::<StateMachineRes<World>>().unwrap();
+        assert!(state_machine.get_mut().is_running());
+    }
+}
@@ -0,0 +1,191 @@
+//! Creating states from their name, for data-driven games, debug consoles and scripting.
+
+use crate::{Allocator, MaybeSend, ScopedStateMachine, State, TransitionError};
+use std::collections::HashMap;
+
+/// Parameters given to the constructor of a state, by name.
+pub type StateParams = HashMap<String, String>;
+
+/// Creates a new instance of a registered state from its parameters, or fails with the reason
+/// why the parameters are invalid.
+pub type StateFactory<S, E = ()> =
+    Box<maybe_send!(dyn Fn(&StateParams) -> Result<Box<dyn State<S, E>>, String>)>;
+
+/// A set of named constructors for states.
+/// ## Generics
+/// - S: State data, the data that is sent to states for them to do their operations.
+/// - E: Events, the input that is sent to states through `handle_event`.
+pub struct StateRegistry<S: ?Sized, E = ()> {
+    factories: HashMap<String, StateFactory<S, E>>,
+}
+
+impl<S: ?Sized, E> Default for StateRegistry<S, E> {
+    fn default() -> Self {
+        Self {
+            factories: HashMap::default(),
+        }
+    }
+}
+
+impl<S: ?Sized, E> StateRegistry<S, E> {
+    /// Registers the constructor of a state under this name, replacing any previous one.
+    /// The constructor ignores the parameters it is given.
+    pub fn register(
+        &mut self,
+        name: impl Into<String>,
+        factory: impl Fn() -> Box<dyn State<S, E>> + MaybeSend + 'static,
+    ) {
+        self.register_with_params(name, move |_| Ok(factory()));
+    }
+
+    /// Registers the constructor of a state taking parameters under this name, replacing any
+    /// previous one. The constructor fails with the reason why the parameters are invalid.
+    pub fn register_with_params(
+        &mut self,
+        name: impl Into<String>,
+        factory: impl Fn(&StateParams) -> Result<Box<dyn State<S, E>>, String> + MaybeSend + 'static,
+    ) {
+        self.factories.insert(name.into(), Box::new(factory));
+    }
+
+    /// Returns if a state is registered under this name.
+    pub fn contains(&self, name: &str) -> bool {
+        self.factories.contains_key(name)
+    }
+
+    /// Creates the state registered under this name, without parameters.
+    pub fn create(&self, name: &str) -> Result<Box<dyn State<S, E>>, TransitionError> {
+        self.create_with_params(name, &StateParams::default())
+    }
+
+    /// Creates the state registered under this name with these parameters.
+    pub fn create_with_params(
+        &self,
+        name: &str,
+        params: &StateParams,
+    ) -> Result<Box<dyn State<S, E>>, TransitionError> {
+        let factory = self
+            .factories
+            .get(name)
+            .ok_or_else(|| TransitionError::UnknownState(name.to_string()))?;
+        factory(params).map_err(|reason| TransitionError::InvalidParams {
+            state: name.to_string(),
+            reason,
+        })
+    }
+}
+
+impl<'a, S: ?Sized, E, A: Allocator> ScopedStateMachine<'a, S, E, A> {
+    /// Returns the registry used to create states by name.
+    pub fn registry(&self) -> &StateRegistry<S, E> {
+        &self.registry
+    }
+
+    /// Returns the registry used to create states by name, to register states in it.
+    pub fn registry_mut(&mut self) -> &mut StateRegistry<S, E> {
+        &mut self.registry
+    }
+
+    /// Replaces the registry used to create states by name.
+    pub fn set_registry(&mut self, registry: StateRegistry<S, E>) {
+        self.registry = registry;
+    }
+
+    /// Creates the state registered under this name and pushes it, like `push`.
+    /// Fails without changing the stack if no state is registered under this name.
+    pub fn push_by_name(&mut self, name: &str, state_data: &mut S) -> Result<(), TransitionError> {
+        let state = self.registry.create(name)?;
+        self.push(state, state_data);
+        Ok(())
+    }
+
+    /// Creates the state registered under this name and switches to it, like `switch`.
+    /// Fails without changing the stack if no state is registered under this name.
+    pub fn switch_by_name(
+        &mut self,
+        name: &str,
+        state_data: &mut S,
+    ) -> Result<(), TransitionError> {
+        let state = self.registry.create(name)?;
+        self.switch(state, state_data);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    type Log = Vec<String>;
+
+    pub struct Named(&'static str);
+
+    impl State<Log> for Named {
+        fn on_start(&mut self, log: &mut Log) -> StateTransition<Log> {
+            log.push(format!("{}:start", self.0));
+            StateTransition::None
+        }
+
+        fn on_stop(&mut self, log: &mut Log) -> StateTransition<Log> {
+            log.push(format!("{}:stop", self.0));
+            StateTransition::None
+        }
+
+        fn update(&mut self, _log: &mut Log) -> StateTransition<Log> {
+            StateTransition::PushNamed("inventory".to_string())
+        }
+    }
+
+    fn machine() -> StateMachine<Log> {
+        let mut sm = StateMachine::<Log>::default();
+        sm.registry_mut()
+            .register("menu", || Box::new(Named("menu")));
+        sm.registry_mut()
+            .register("inventory", || Box::new(Named("inventory")));
+        sm
+    }
+
+    #[test]
+    fn by_name() {
+        let mut log = Log::new();
+        let mut sm = machine();
+
+        sm.push_by_name("menu", &mut log).unwrap();
+        sm.update(&mut log);
+        assert_eq!(log, vec!["menu:start", "inventory:start"]);
+
+        log.clear();
+        sm.switch_by_name("menu", &mut log).unwrap();
+        assert_eq!(log, vec!["inventory:stop", "menu:stop", "menu:start"]);
+    }
+
+    #[test]
+    fn unknown_name() {
+        let mut log = Log::new();
+        let mut sm = machine();
+
+        assert_eq!(
+            sm.push_by_name("shop", &mut log),
+            Err(TransitionError::UnknownState("shop".to_string()))
+        );
+        assert!(sm.is_empty());
+
+        sm.registry_mut()
+            .register("menu", || Box::new(Named("menu")));
+        sm.set_registry(StateRegistry::default());
+        assert!(!sm.registry().contains("menu"));
+        sm.push(Box::new(Named("game")), &mut log);
+        log.clear();
+        sm.queue_transition(StateTransition::PushNamed("menu".to_string()));
+        assert_eq!(
+            sm.update(&mut log),
+            MachineStatus::Stopped(StopReason::Error)
+        );
+        assert_eq!(log, vec!["game:stop"]);
+        let error = sm.take_error().unwrap();
+        assert_eq!(
+            error.downcast_ref::<TransitionError>(),
+            Some(&TransitionError::UnknownState("menu".to_string()))
+        );
+    }
+}
@@ -0,0 +1,178 @@
+//! Moving the states within the stack, like focusing a window of an in-game UI, without stopping
+//! or starting them.
+
+use crate::{Allocator, PauseReason, ScopedStateMachine, StateHandle, TransitionError};
+
+/// A state of the stack, by its handle or by its position, 0 being the bottom, see
+/// `StateMachine::bring_to_top`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum StackSlot {
+    /// The state with this handle.
+    Handle(StateHandle),
+    /// The state at this position.
+    Index(usize),
+}
+
+impl From<StateHandle> for StackSlot {
+    fn from(handle: StateHandle) -> Self {
+        StackSlot::Handle(handle)
+    }
+}
+
+impl From<usize> for StackSlot {
+    fn from(index: usize) -> Self {
+        StackSlot::Index(index)
+    }
+}
+
+impl<'a, S: ?Sized, E, A: Allocator> ScopedStateMachine<'a, S, E, A> {
+    /// Moves a state to the top of the stack, keeping the order of the others.
+    /// The states it uncovers are not paused anymore, so the state that was active is paused and
+    /// the moved state is resumed if it was covered. No state is started or stopped, and the
+    /// states staying covered or visible are neither paused nor resumed.
+    ///
+    /// This can be called from a `StateTransition::Custom`, to reorder the stack as a transition.
+    pub fn bring_to_top(
+        &mut self,
+        slot: impl Into<StackSlot>,
+        state_data: &mut S,
+    ) -> Result<(), TransitionError> {
+        let index = self.slot_index(slot.into())?;
+        let entry = self.state_stack.remove(index);
+        self.state_stack.push(entry);
+        self.refresh_visible(state_data);
+        Ok(())
+    }
+
+    /// Swaps the states at these positions of the stack, 0 being the bottom, pausing and resuming
+    /// them like `bring_to_top` when one of them is the active state.
+    pub fn swap(&mut self, i: usize, j: usize, state_data: &mut S) -> Result<(), TransitionError> {
+        self.slot_index(StackSlot::Index(i))?;
+        self.slot_index(StackSlot::Index(j))?;
+        self.state_stack.swap(i, j);
+        self.refresh_visible(state_data);
+        Ok(())
+    }
+
+    /// Returns the position of the state in the stack.
+    fn slot_index(&self, slot: StackSlot) -> Result<usize, TransitionError> {
+        let len = self.state_stack.len();
+        match slot {
+            StackSlot::Handle(handle) => self
+                .index_of(handle)
+                .ok_or(TransitionError::StaleHandle(handle)),
+            StackSlot::Index(index) if index < len => Ok(index),
+            StackSlot::Index(index) => Err(TransitionError::IndexOutOfRange { index, len }),
+        }
+    }
+
+    /// Pauses the states that a change of the stack covered, top-down, then resumes those it
+    /// uncovered, bottom-up.
+    pub(crate) fn refresh_visible(&mut self, state_data: &mut S) {
+        for index in (0..self.visible_start()).rev() {
+            if !self.state_stack[index].paused {
+                self.pause_at(index, PauseReason::Covered, state_data);
+            }
+        }
+        self.resume_visible(state_data);
+        self.settle(state_data);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    type Log = Vec<String>;
+
+    struct Panel(&'static str);
+
+    impl State<Log> for Panel {
+        fn name(&self) -> &str {
+            self.0
+        }
+
+        fn on_start(&mut self, log: &mut Log) -> StateTransition<Log> {
+            log.push(format!("{}:start", self.0));
+            StateTransition::None
+        }
+
+        fn on_pause(&mut self, log: &mut Log) {
+            log.push(format!("{}:pause", self.0));
+        }
+
+        fn on_resume(&mut self, log: &mut Log) -> StateTransition<Log> {
+            log.push(format!("{}:resume", self.0));
+            StateTransition::None
+        }
+
+        fn on_stop(&mut self, log: &mut Log) -> StateTransition<Log> {
+            log.push(format!("{}:stop", self.0));
+            StateTransition::None
+        }
+    }
+
+    fn names(sm: &StateMachine<Log>) -> Vec<&str> {
+        sm.iter().map(|state| state.name()).collect()
+    }
+
+    #[test]
+    fn minimal_lifecycle() {
+        let mut log = Log::new();
+        let mut sm = StateMachine::<Log>::default();
+        sm.push_state(Panel("world"), &mut log);
+        let inventory = sm.push_state(Panel("inventory"), &mut log);
+        sm.push_state(Panel("map"), &mut log);
+        sm.push_state(Panel("chat"), &mut log);
+        log.clear();
+
+        sm.bring_to_top(inventory, &mut log).unwrap();
+        assert_eq!(names(&sm), ["world", "map", "chat", "inventory"]);
+        assert_eq!(log, ["chat:pause", "inventory:resume"]);
+
+        log.clear();
+        sm.swap(0, 1, &mut log).unwrap();
+        sm.bring_to_top(3, &mut log).unwrap();
+        assert_eq!(names(&sm), ["map", "world", "chat", "inventory"]);
+        assert!(log.is_empty());
+
+        sm.swap(3, 2, &mut log).unwrap();
+        assert_eq!(names(&sm), ["map", "world", "inventory", "chat"]);
+        assert_eq!(log, ["inventory:pause", "chat:resume"]);
+    }
+
+    #[test]
+    fn out_of_range() {
+        let mut log = Log::new();
+        let mut sm = StateMachine::<Log>::default();
+        let world = sm.push_state(Panel("world"), &mut log);
+        sm.push_state(Panel("map"), &mut log);
+        assert_eq!(
+            sm.swap(0, 2, &mut log),
+            Err(TransitionError::IndexOutOfRange { index: 2, len: 2 })
+        );
+        sm.remove(world, &mut log);
+        assert_eq!(
+            sm.bring_to_top(world, &mut log),
+            Err(TransitionError::StaleHandle(world))
+        );
+        assert_eq!(names(&sm), ["map"]);
+    }
+
+    #[test]
+    fn from_a_transition() {
+        let mut log = Log::new();
+        let mut sm = StateMachine::<Log>::default();
+        sm.push_state(Panel("world"), &mut log);
+        sm.push_state(Panel("map"), &mut log);
+        log.clear();
+        sm.transition(
+            StateTransition::Custom(Box::new(|sm, log| {
+                sm.bring_to_top(0, log).unwrap();
+            })),
+            &mut log,
+        );
+        assert_eq!(names(&sm), ["map", "world"]);
+        assert_eq!(log, ["map:pause", "world:resume"]);
+    }
+}
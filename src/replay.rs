@@ -0,0 +1,350 @@
+//! Recording the transitions of a play session and replaying them, for deterministic tests.
+//!
+//! A `TransitionRecorder` is added as an observer of the machine to record. A
+//! `TransitionReplayer` is attached to another machine and replaces each transition its states
+//! return by the next recorded one, creating the states from their name with a registry.
+//! The transitions are replayed in order, not at the frame they were recorded at.
+//! The recordings can be saved with the `serde` feature.
+
+use crate::{
+    InterceptDecision, StateMachine, StateRegistry, StateTransition, TransitionEvent,
+    TransitionKind, TransitionObserver,
+};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// Locks a shared value, which a panic while it was locked cannot leave half updated.
+fn lock<T>(shared: &Mutex<T>) -> MutexGuard<'_, T> {
+    shared
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// A transition that was recorded.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RecordedTransition {
+    /// The kind of the transition.
+    pub kind: TransitionKind,
+    /// The name of the state that was pushed, switched to or replaced in, for these kinds.
+    pub state: Option<String>,
+    /// The number of states on the stack after the transition.
+    pub depth: usize,
+}
+
+impl RecordedTransition {
+    fn new(event: &TransitionEvent) -> Self {
+        let state = match event.kind {
+            TransitionKind::Push | TransitionKind::Switch | TransitionKind::Replace => {
+                event.to.clone()
+            }
+            _ => None,
+        };
+        Self {
+            kind: event.kind,
+            state,
+            depth: event.depth,
+        }
+    }
+}
+
+/// The transitions recorded during a play session, in the order they were performed.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Recording {
+    /// The recorded transitions.
+    pub transitions: Vec<RecordedTransition>,
+}
+
+/// An observer recording the transitions of a state machine.
+/// Its clones share the same recording, so one can be kept to read what the other records.
+#[derive(Debug, Clone, Default)]
+pub struct TransitionRecorder {
+    recording: Arc<Mutex<Recording>>,
+}
+
+impl TransitionRecorder {
+    /// Creates a recorder with an empty recording.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns a copy of what was recorded so far.
+    pub fn recording(&self) -> Recording {
+        lock(&self.recording).clone()
+    }
+}
+
+impl<S: ?Sized> TransitionObserver<S> for TransitionRecorder {
+    fn on_transition(&mut self, event: &TransitionEvent, _state_data: &mut S) {
+        lock(&self.recording)
+            .transitions
+            .push(RecordedTransition::new(event));
+    }
+}
+
+/// A transition returned by a live state that did not match the recording.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Divergence {
+    /// The index of the transition in the recording.
+    pub index: usize,
+    /// The transition that was recorded, or `None` if the recording had already ended.
+    pub recorded: Option<RecordedTransition>,
+    /// The kind of the transition returned by the live state.
+    pub live: TransitionKind,
+    /// The name of the state the live state asked to push, switch to or replace in, if any.
+    pub live_state: Option<String>,
+}
+
+struct Replay<S: ?Sized, E> {
+    recording: Recording,
+    registry: StateRegistry<S, E>,
+    next: usize,
+    divergences: Vec<Divergence>,
+    /// If the last transition was replaced, since the interceptor is given the replacement too.
+    replaced: bool,
+}
+
+impl<S: ?Sized + 'static, E: 'static> Replay<S, E> {
+    fn intercept(&mut self, live: &StateTransition<S, E>) -> InterceptDecision<S, E> {
+        if std::mem::take(&mut self.replaced) {
+            return InterceptDecision::Allow;
+        }
+        let (live, live_state) = match live {
+            // The transitions of the sequence are given one by one afterward.
+            StateTransition::Sequence(_) => return InterceptDecision::Allow,
+            StateTransition::Push(state)
+            | StateTransition::Switch(state)
+            | StateTransition::Replace(state) => (live.kind(), Some(state.name().to_string())),
+            StateTransition::PushNamed(name) => (live.kind(), Some(name.clone())),
+            _ => (live.kind(), None),
+        };
+        let live = match live {
+            Some(live) => live,
+            None => return InterceptDecision::Allow,
+        };
+
+        let index = self.next;
+        let recorded = self.recording.transitions.get(index).cloned();
+        self.next += 1;
+        let matches = match &recorded {
+            Some(recorded) => {
+                recorded.kind == live && (live_state.is_none() || recorded.state == live_state)
+            }
+            None => false,
+        };
+        if !matches {
+            self.divergences.push(Divergence {
+                index,
+                recorded: recorded.clone(),
+                live,
+                live_state,
+            });
+        }
+
+        match recorded.and_then(|recorded| self.transition(recorded)) {
+            Some(transition) => {
+                self.replaced = true;
+                InterceptDecision::ReplaceWith(transition)
+            }
+            None => InterceptDecision::Block,
+        }
+    }
+
+    /// Creates the recorded transition, or `None` if it cannot be replayed.
+    fn transition(&self, recorded: RecordedTransition) -> Option<StateTransition<S, E>> {
+        let state = || {
+            recorded
+                .state
+                .as_ref()
+                .and_then(|name| self.registry.create(name).ok())
+        };
+        let depth = recorded.depth;
+        let transition = match recorded.kind {
+            TransitionKind::Pop | TransitionKind::PopWith => StateTransition::Pop,
+            TransitionKind::PopN
+            | TransitionKind::PopUntil
+            | TransitionKind::PopTagged
+            | TransitionKind::PopTo
+            | TransitionKind::PopGroup => {
+                StateTransition::Custom(Box::new(move |sm: &mut StateMachine<S, E>, data| {
+                    let n = sm.len().saturating_sub(depth);
+                    sm.pop_n(n, data);
+                }))
+            }
+            TransitionKind::Push => StateTransition::Push(state()?),
+            TransitionKind::Switch => StateTransition::Switch(state()?),
+            TransitionKind::Replace => StateTransition::Replace(state()?),
+            TransitionKind::Quit => StateTransition::Quit,
+            TransitionKind::PushMany
+            | TransitionKind::ReplaceAll
+            | TransitionKind::InsertBelow
+            | TransitionKind::PushBottom
+            | TransitionKind::Sequence
+            | TransitionKind::Custom
+            | TransitionKind::Fallback => return None,
+        };
+        Some(transition)
+    }
+}
+
+/// Drives a state machine with a recording, see the module documentation.
+/// The states missing from the registry are skipped, along with the recorded `PushMany`,
+/// `ReplaceAll`, `InsertBelow`, `PushBottom` and `Custom` transitions, since the recording does
+/// not tell what they did. The recorded `PopWith` transitions are replayed as `Pop`, without their payload,
+/// and the recorded fallbacks are skipped, since the machine starts its own fallback state.
+pub struct TransitionReplayer<S: ?Sized, E = ()> {
+    replay: Arc<Mutex<Replay<S, E>>>,
+}
+
+impl<S: ?Sized + 'static, E: 'static> TransitionReplayer<S, E> {
+    /// Creates a replayer of this recording, creating the recorded states with this registry.
+    pub fn new(recording: Recording, registry: StateRegistry<S, E>) -> Self {
+        Self {
+            replay: Arc::new(Mutex::new(Replay {
+                recording,
+                registry,
+                next: 0,
+                divergences: Vec::new(),
+                replaced: false,
+            })),
+        }
+    }
+
+    /// Makes the state machine replay the recording, by setting its interceptor.
+    pub fn attach(&self, state_machine: &mut StateMachine<S, E>) {
+        let replay = self.replay.clone();
+        state_machine.set_interceptor(move |live, _| lock(&replay).intercept(live));
+    }
+
+    /// Returns the transitions of the live states that did not match the recording.
+    pub fn divergences(&self) -> Vec<Divergence> {
+        lock(&self.replay).divergences.clone()
+    }
+
+    /// Returns the number of recorded transitions that were not replayed yet.
+    pub fn remaining(&self) -> usize {
+        let replay = lock(&self.replay);
+        replay
+            .recording
+            .transitions
+            .len()
+            .saturating_sub(replay.next)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    /// Pushes, then pops, then quits from the `finish` state.
+    struct Scripted(&'static str, u32);
+
+    impl State<()> for Scripted {
+        fn name(&self) -> &str {
+            self.0
+        }
+
+        fn update(&mut self, _: &mut ()) -> StateTransition<()> {
+            self.1 += 1;
+            match (self.0, self.1) {
+                ("menu", 1) => StateTransition::Push(Box::new(Scripted("game", 0))),
+                ("game", 1) => StateTransition::Push(Box::new(Scripted("pause", 0))),
+                ("pause", 1) => StateTransition::Pop,
+                ("game", 2) => StateTransition::Switch(Box::new(Scripted("finish", 0))),
+                ("finish", 1) => StateTransition::Quit,
+                _ => StateTransition::None,
+            }
+        }
+    }
+
+    /// A refactored game, which pushes a different state while paused and never finishes.
+    struct Refactored(&'static str);
+
+    impl State<()> for Refactored {
+        fn name(&self) -> &str {
+            self.0
+        }
+
+        fn update(&mut self, _: &mut ()) -> StateTransition<()> {
+            match self.0 {
+                "game" => StateTransition::Push(Box::new(Refactored("options"))),
+                _ => StateTransition::Pop,
+            }
+        }
+    }
+
+    fn record() -> Recording {
+        let recorder = TransitionRecorder::new();
+        let mut sm = StateMachine::<()>::default();
+        sm.add_observer(Box::new(recorder.clone()));
+        sm.push(Box::new(Scripted("menu", 0)), &mut ());
+        while sm.is_running() {
+            sm.update(&mut ());
+        }
+        recorder.recording()
+    }
+
+    fn registry() -> StateRegistry<()> {
+        let mut registry = StateRegistry::default();
+        registry.register("game", || Box::new(Refactored("game")));
+        registry.register("pause", || Box::new(Refactored("pause")));
+        registry.register("finish", || Box::new(Refactored("finish")));
+        registry
+    }
+
+    #[test]
+    fn records() {
+        let kinds = record()
+            .transitions
+            .iter()
+            .map(|recorded| (recorded.kind, recorded.depth))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            vec![
+                (TransitionKind::Push, 2),
+                (TransitionKind::Push, 3),
+                (TransitionKind::Pop, 2),
+                (TransitionKind::Switch, 1),
+                (TransitionKind::Quit, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn replays() {
+        let recording = record();
+        let replayer = TransitionReplayer::new(recording.clone(), registry());
+        let recorder = TransitionRecorder::new();
+
+        let mut sm = StateMachine::<()>::default();
+        replayer.attach(&mut sm);
+        sm.add_observer(Box::new(recorder.clone()));
+        sm.push(Box::new(Refactored("menu")), &mut ());
+        while sm.is_running() {
+            sm.update(&mut ());
+        }
+
+        assert_eq!(recorder.recording(), recording);
+        assert_eq!(replayer.remaining(), 0);
+        let divergences = replayer.divergences();
+        assert_eq!(
+            divergences.iter().map(|d| d.index).collect::<Vec<_>>(),
+            vec![0, 1, 3, 4]
+        );
+        assert_eq!(divergences[0].live, TransitionKind::Pop);
+        assert_eq!(divergences[1].live_state.as_deref(), Some("options"));
+        assert_eq!(
+            divergences[1].recorded.as_ref().unwrap().state.as_deref(),
+            Some("pause")
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_round_trip() {
+        let recording = record();
+        let json = serde_json::to_string(&recording).unwrap();
+        assert_eq!(serde_json::from_str::<Recording>(&json).unwrap(), recording);
+    }
+}
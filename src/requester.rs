@@ -0,0 +1,183 @@
+//! Requesting transitions from outside of the states' callbacks, like from a system of the
+//! game which holds a handle in the state data.
+
+use crate::{Allocator, ScopedStateMachine, StateTransition};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+pub(crate) type Requests<S, E> = Arc<Mutex<VecDeque<StateTransition<S, E>>>>;
+
+pub(crate) fn lock<T>(shared: &Mutex<T>) -> MutexGuard<'_, T> {
+    shared
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// A handle queuing transitions on the machine it was obtained from, see
+/// `StateMachine::requester`.
+/// It does not borrow the machine, so it can be kept in the state data the machine is updated
+/// with, and cloned to be given to several systems.
+pub struct TransitionRequester<S: ?Sized, E = ()> {
+    requests: Requests<S, E>,
+}
+
+impl<S: ?Sized, E> Clone for TransitionRequester<S, E> {
+    fn clone(&self) -> Self {
+        Self {
+            requests: Arc::clone(&self.requests),
+        }
+    }
+}
+
+impl<S: ?Sized, E> TransitionRequester<S, E> {
+    /// Queues a transition, performed at the start of the next update of the machine.
+    /// A transition requested while the machine is updating, for example by a state through the
+    /// state data, is performed on the update after, never during the current one.
+    pub fn request(&self, transition: StateTransition<S, E>) {
+        lock(&self.requests).push_back(transition);
+    }
+
+    /// Returns the number of transitions requested and not yet received by the machine.
+    pub fn pending(&self) -> usize {
+        lock(&self.requests).len()
+    }
+}
+
+impl<'a, S: ?Sized, E, A: Allocator> ScopedStateMachine<'a, S, E, A> {
+    /// Returns a handle queuing transitions for the next update of this machine.
+    /// All the handles returned feed the same queue.
+    ///
+    /// At the start of an update, the requested transitions are moved after the ones queued
+    /// with `queue_transition`, and performed in the order they were requested. Then the states
+    /// are updated and the transition returned by the active state is performed. So when a
+    /// state both returns a transition and requests another during the same update, the
+    /// returned one is performed first, and the requested one at the start of the next update.
+    pub fn requester(&mut self) -> TransitionRequester<S, E> {
+        let requests = self.requests.get_or_insert_with(Requests::default);
+        TransitionRequester {
+            requests: Arc::clone(requests),
+        }
+    }
+
+    /// Moves the requested transitions to the queue of the machine.
+    pub(crate) fn receive_requests(&mut self) {
+        if let Some(requests) = &self.requests {
+            let mut requests = lock(requests);
+            self.queued_transitions.extend(requests.drain(..));
+        }
+    }
+
+    /// Returns if transitions were requested and not yet received.
+    pub(crate) fn has_requests(&self) -> bool {
+        self.requests
+            .as_ref()
+            .is_some_and(|requests| !lock(requests).is_empty())
+    }
+
+    /// Drops the requested transitions.
+    pub(crate) fn clear_requests(&mut self) {
+        if let Some(requests) = &self.requests {
+            lock(requests).clear();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    /// The state data, holding a requester like a system of the game would.
+    struct World {
+        requester: TransitionRequester<World>,
+        log: Vec<&'static str>,
+    }
+
+    /// Requests a pause from its update the first time, while returning its own transition.
+    struct Level(bool);
+
+    impl State<World> for Level {
+        fn update(&mut self, world: &mut World) -> StateTransition<World> {
+            world.log.push("level:update");
+            if self.0 {
+                return StateTransition::None;
+            }
+            self.0 = true;
+            world
+                .requester
+                .request(StateTransition::Push(Box::new(Pause)));
+            assert_eq!(world.requester.pending(), 1);
+            StateTransition::Push(Box::new(Cutscene))
+        }
+    }
+
+    struct Cutscene;
+
+    impl State<World> for Cutscene {
+        fn on_start(&mut self, world: &mut World) -> StateTransition<World> {
+            world.log.push("cutscene:start");
+            StateTransition::None
+        }
+
+        fn update(&mut self, world: &mut World) -> StateTransition<World> {
+            world.log.push("cutscene:update");
+            StateTransition::None
+        }
+    }
+
+    struct Pause;
+
+    impl State<World> for Pause {
+        fn on_start(&mut self, world: &mut World) -> StateTransition<World> {
+            world.log.push("pause:start");
+            StateTransition::None
+        }
+
+        fn update(&mut self, world: &mut World) -> StateTransition<World> {
+            world.log.push("pause:update");
+            StateTransition::None
+        }
+    }
+
+    #[test]
+    fn requested_from_update() {
+        let mut sm = StateMachine::<World>::default();
+        let mut world = World {
+            requester: sm.requester(),
+            log: Vec::new(),
+        };
+        sm.push(Box::new(Level(false)), &mut world);
+
+        assert_eq!(
+            sm.update(&mut world),
+            MachineStatus::Transitioned(TransitionKind::Push)
+        );
+        assert_eq!(world.log, ["level:update", "cutscene:start"]);
+        assert_eq!(sm.len(), 2);
+        assert_eq!(world.requester.pending(), 1);
+
+        world.log.clear();
+        sm.update(&mut world);
+        assert_eq!(world.log, ["pause:start", "pause:update"]);
+        assert_eq!(sm.len(), 3);
+        assert_eq!(world.requester.pending(), 0);
+    }
+
+    #[test]
+    fn after_queued_transitions() {
+        let mut sm = StateMachine::<World>::default();
+        let requester = sm.requester();
+        let mut world = World {
+            requester: requester.clone(),
+            log: Vec::new(),
+        };
+        sm.push(Box::new(Level(true)), &mut world);
+        requester.request(StateTransition::Push(Box::new(Pause)));
+        sm.queue_transition(StateTransition::Push(Box::new(Cutscene)));
+        sm.update(&mut world);
+        assert_eq!(world.log, ["cutscene:start", "pause:start", "pause:update"]);
+
+        requester.request(StateTransition::Pop);
+        sm.reset(&mut world);
+        assert_eq!(requester.pending(), 0);
+    }
+}
@@ -0,0 +1,269 @@
+//! Checking that the states a state needs below it are on the stack when it is put there, see
+//! `State::required_below`.
+
+use crate::{
+    any_of, Allocator, AsAny, ScopedStateMachine, State, StateBox, StateHandle, StateTransition,
+    TransitionError,
+};
+use std::any::TypeId;
+
+/// What a state machine does with a state whose requirements are not on the stack below it, see
+/// `State::required_below`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RequirementPolicy {
+    /// Rejects the operation before any state is started or stopped, and gives its error to the
+    /// states, like a rejection of the transition check, see
+    /// `StateMachine::set_transition_check`.
+    Reject,
+    /// Performs the operation anyway, logging the missing requirement with the `log` feature.
+    /// This is meant for debug builds while the requirements of the states are being declared.
+    Warn,
+}
+
+/// A boxed state whose requirements are checked, on the stack or on its way to it.
+pub(crate) trait Boxed<S: ?Sized, E> {
+    fn state(&self) -> &(dyn State<S, E> + '_);
+    /// The type of the state, unless it borrows data.
+    fn type_id(&self) -> Option<TypeId>;
+}
+
+impl<S: ?Sized, E> Boxed<S, E> for Box<dyn State<S, E>> {
+    fn state(&self) -> &(dyn State<S, E> + '_) {
+        self.as_ref()
+    }
+
+    fn type_id(&self) -> Option<TypeId> {
+        Some(<dyn AsAny>::as_any(self.as_ref()).type_id())
+    }
+}
+
+impl<'a, S: ?Sized, E, A: Allocator> Boxed<S, E> for StateBox<'a, S, E, A> {
+    fn state(&self) -> &(dyn State<S, E> + '_) {
+        self.as_ref()
+    }
+
+    fn type_id(&self) -> Option<TypeId> {
+        any_of(self).map(|state| state.type_id())
+    }
+}
+
+impl<'a, S: ?Sized, E, A: Allocator> ScopedStateMachine<'a, S, E, A> {
+    /// Sets what is done with the states whose requirements are missing when they are put on
+    /// the stack, see `State::required_below`. Defaults to `RequirementPolicy::Reject`.
+    pub fn set_requirement_policy(&mut self, policy: RequirementPolicy) {
+        self.requirement_policy = policy;
+    }
+
+    /// Returns the error of the first requirement missing for this transition, unless the
+    /// policy only warns about it.
+    pub(crate) fn unmet_requirement(
+        &self,
+        request: &StateTransition<S, E>,
+    ) -> Option<TransitionError> {
+        let len = self.state_stack.len();
+        let index = match request {
+            StateTransition::Push(_) | StateTransition::PushMany(_) => len,
+            StateTransition::InsertBelow(_) | StateTransition::Replace(_) => len.saturating_sub(1),
+            StateTransition::Switch(_) => self.switched_from(),
+            StateTransition::PushBottom(_) | StateTransition::ReplaceAll(_) => 0,
+            _ => return None,
+        };
+        self.missing_requirement(index, request.states())
+    }
+
+    /// Returns the error of the first requirement missing for these states, if they were put at
+    /// this index of the stack from the bottom to the top, unless the policy only warns about it.
+    /// The states below the index and the states before each one count.
+    pub(crate) fn missing_requirement<B: Boxed<S, E>>(
+        &self,
+        index: usize,
+        states: &[B],
+    ) -> Option<TransitionError> {
+        let below = &self.state_stack[..index.min(self.state_stack.len())];
+        for (position, state) in states.iter().enumerate() {
+            let present = |required: TypeId| {
+                below
+                    .iter()
+                    .map(|entry| Boxed::type_id(&entry.state))
+                    .chain(states[..position].iter().map(Boxed::type_id))
+                    .any(|id| id == Some(required))
+            };
+            let missing = state
+                .state()
+                .required_below()
+                .iter()
+                .find(|required| !present(**required));
+            if let Some(required) = missing {
+                let error = TransitionError::MissingRequirement {
+                    state: state.state().name().to_string(),
+                    required: *required,
+                };
+                match self.requirement_policy {
+                    RequirementPolicy::Reject => return Some(error),
+                    RequirementPolicy::Warn => {
+                        log_warn!("{}", error);
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    /// Gives the error of a rejected operation to the states, see `RequirementPolicy::Reject`.
+    pub(crate) fn reject(&mut self, error: TransitionError, state_data: &mut S) {
+        let below = self.state_stack.len();
+        self.report(below, Box::new(error), state_data);
+        self.settle(state_data);
+    }
+
+    /// Rejects the operation that would have put a state on the stack, returning a handle that
+    /// no state gets.
+    pub(crate) fn reject_state(
+        &mut self,
+        error: TransitionError,
+        state_data: &mut S,
+    ) -> StateHandle {
+        let handle = self.next_handle();
+        self.next_handle += 1;
+        self.reject(error, state_data);
+        handle
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use std::any::TypeId;
+
+    type Log = Vec<String>;
+
+    struct Shop;
+
+    impl State<Log> for Shop {
+        fn name(&self) -> &str {
+            "shop"
+        }
+
+        fn on_start(&mut self, log: &mut Log) -> StateTransition<Log> {
+            log.push("shop:start".to_string());
+            StateTransition::None
+        }
+
+        fn on_pause(&mut self, log: &mut Log) {
+            log.push("shop:pause".to_string());
+        }
+    }
+
+    struct TradeDialog;
+
+    impl State<Log> for TradeDialog {
+        fn name(&self) -> &str {
+            "trade"
+        }
+
+        fn required_below(&self) -> &[TypeId] {
+            const REQUIRED: &[TypeId] = &[TypeId::of::<Shop>()];
+            REQUIRED
+        }
+
+        fn on_start(&mut self, log: &mut Log) -> StateTransition<Log> {
+            log.push("trade:start".to_string());
+            StateTransition::None
+        }
+    }
+
+    /// Handles the errors of the states above it, logging them.
+    struct World;
+
+    impl State<Log> for World {
+        fn name(&self) -> &str {
+            "world"
+        }
+
+        fn on_error(&mut self, error: &dyn std::error::Error, log: &mut Log) -> ErrorOutcome<Log> {
+            log.push(format!("world:error:{}", error));
+            ErrorOutcome::Handled(StateTransition::None)
+        }
+    }
+
+    fn missing(error: &dyn std::error::Error) -> bool {
+        error.to_string() == "the state trade needs a state below it that is not on the stack"
+    }
+
+    #[test]
+    fn satisfied() {
+        let mut log = Log::new();
+        let mut sm = StateMachine::<Log>::default();
+        sm.push(Box::new(Shop), &mut log);
+        sm.queue_transition(StateTransition::Push(Box::new(TradeDialog)));
+        sm.update(&mut log);
+        assert_eq!(log, ["shop:start", "shop:pause", "trade:start"]);
+        assert_eq!(sm.active_name(), Some("trade"));
+    }
+
+    #[test]
+    fn unsatisfied() {
+        let mut log = Log::new();
+        let mut sm = StateMachine::<Log>::default();
+        sm.push(Box::new(World), &mut log);
+        sm.queue_transition(StateTransition::Push(Box::new(TradeDialog)));
+        sm.update(&mut log);
+        assert_eq!(log.len(), 1);
+        assert!(log[0].starts_with("world:error:the state trade"));
+        assert_eq!(sm.active_name(), Some("world"));
+
+        // Nobody handles the error of a rejected push on an empty machine.
+        let mut sm = StateMachine::<Log>::default();
+        sm.push(Box::new(TradeDialog), &mut log);
+        assert!(!sm.is_running());
+        assert!(missing(sm.take_error().unwrap().as_ref()));
+    }
+
+    #[test]
+    fn warn_policy() {
+        let mut log = Log::new();
+        let mut sm = StateMachine::<Log>::default();
+        sm.set_requirement_policy(RequirementPolicy::Warn);
+        sm.push(Box::new(TradeDialog), &mut log);
+        assert_eq!(log, ["trade:start"]);
+    }
+
+    #[test]
+    fn installed_stacks() {
+        let mut log = Log::new();
+        let mut sm = StateMachine::<Log>::default();
+        sm.push(Box::new(Shop), &mut log);
+        log.clear();
+
+        // The states below in the new stack count, not the ones it replaces.
+        sm.queue_transition(StateTransition::ReplaceAll(vec![Box::new(TradeDialog)]));
+        assert_eq!(
+            sm.update(&mut log),
+            MachineStatus::Stopped(StopReason::Error)
+        );
+        assert!(log.is_empty());
+        assert!(missing(sm.take_error().unwrap().as_ref()));
+
+        sm.set_stack(
+            vec![Box::new(Shop), Box::new(TradeDialog)],
+            &mut log,
+            InstallPolicy::Start {
+                keep_previous: false,
+            },
+        );
+        assert_eq!(log, ["shop:start", "shop:pause", "trade:start"]);
+
+        log.clear();
+        let previous = sm.set_stack(
+            vec![Box::new(TradeDialog)],
+            &mut log,
+            InstallPolicy::Adopt {
+                keep_previous: true,
+            },
+        );
+        assert!(previous.is_empty());
+        assert!(log.is_empty());
+        assert!(!sm.is_running());
+        assert!(missing(sm.take_error().unwrap().as_ref()));
+    }
+}
@@ -0,0 +1,241 @@
+//! Choosing between the transitions several states return in the same update, like a covered
+//! simulation pushing a game over screen while the menu on top of it pops itself.
+
+use crate::{Allocator, BoxError, MachineEvent, ScopedStateMachine, StateTransition};
+
+/// Which transition a state machine performs when several states return one in the same
+/// update, see `StateMachine::set_transition_resolution`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TransitionResolution {
+    /// The transition of the topmost state is performed, and the others are dropped.
+    TopWins,
+    /// The transition of the lowest state is performed, and the others are dropped.
+    FirstWins,
+    /// The transition of the topmost state is performed, and the others are queued for the next
+    /// update, from the bottom to the top, like with `StateMachine::queue_transition`.
+    Queue,
+}
+
+/// What a state returned from its update, with its index in the stack.
+pub(crate) type UpdateResult<S, E> = (usize, Result<StateTransition<S, E>, BoxError>);
+
+impl<'a, S: ?Sized, E, A: Allocator> ScopedStateMachine<'a, S, E, A> {
+    /// Sets which transition is performed when several states return one in the same update:
+    /// the covered states from `State::shadow_update_with_transition`, and the states updated
+    /// through transparent ones or kept active, see `set_active_depth`. Defaults to
+    /// `TransitionResolution::TopWins`.
+    ///
+    /// The errors the states return are picked like their transitions, and are dropped instead
+    /// of queued. Each dropped transition or error is logged with the `log` feature, published
+    /// as `MachineEvent::TransitionDropped` and counted, see
+    /// `StateMachineMetrics::dropped_transitions`. The queued transitions are performed on the
+    /// stack as it is at the next update, like the other queued transitions.
+    pub fn set_transition_resolution(&mut self, policy: TransitionResolution) {
+        self.transition_resolution = policy;
+    }
+
+    /// Returns which transition is performed when several states return one in the same update,
+    /// see `set_transition_resolution`.
+    pub fn transition_resolution(&self) -> TransitionResolution {
+        self.transition_resolution
+    }
+
+    /// Picks the result to perform among the ones the states returned in `results`, from the
+    /// bottom to the top, then drops or queues the others.
+    pub(crate) fn resolve_results(&mut self) -> Option<UpdateResult<S, E>> {
+        if self.results.len() < 2 {
+            return self.results.pop();
+        }
+        let winner = match self.transition_resolution {
+            TransitionResolution::FirstWins => self.results.remove(0),
+            TransitionResolution::TopWins | TransitionResolution::Queue => self.results.pop()?,
+        };
+        while !self.results.is_empty() {
+            let (index, result) = self.results.remove(0);
+            match result {
+                Ok(trans) if self.transition_resolution == TransitionResolution::Queue => {
+                    self.queued_transitions.push_back(trans);
+                }
+                result => {
+                    let kind = result.as_ref().ok().and_then(StateTransition::kind);
+                    let name = self.state_stack[index].state.as_ref().name().to_string();
+                    log_warn!(
+                        "dropped the {} returned by the state {}",
+                        match kind {
+                            Some(kind) => format!("{:?} transition", kind),
+                            None => "result".to_string(),
+                        },
+                        name
+                    );
+                    self.metrics.record_dropped();
+                    self.emit(|_| MachineEvent::TransitionDropped { name, kind });
+                }
+            }
+        }
+        Some(winner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    type Log = Vec<String>;
+
+    /// Returns its transition once, from its update or its shadow update.
+    struct Wants {
+        name: &'static str,
+        transparent: bool,
+        trans: Option<StateTransition<Log>>,
+    }
+
+    fn wants(
+        name: &'static str,
+        transparent: bool,
+        trans: StateTransition<Log>,
+    ) -> Box<dyn State<Log>> {
+        Box::new(Wants {
+            name,
+            transparent,
+            trans: Some(trans),
+        })
+    }
+
+    impl State<Log> for Wants {
+        fn name(&self) -> &str {
+            self.name
+        }
+
+        fn is_transparent(&self) -> bool {
+            self.transparent
+        }
+
+        fn on_stop(&mut self, log: &mut Log) -> StateTransition<Log> {
+            log.push(format!("{}:stop", self.name));
+            StateTransition::None
+        }
+
+        fn update(&mut self, _: &mut Log) -> StateTransition<Log> {
+            self.trans.take().unwrap_or(StateTransition::None)
+        }
+
+        fn shadow_update_with_transition(
+            &mut self,
+            _: &StateContext,
+            _: &mut Log,
+        ) -> StateTransition<Log> {
+            self.trans.take().unwrap_or(StateTransition::None)
+        }
+    }
+
+    struct Named(&'static str);
+
+    impl State<Log> for Named {
+        fn name(&self) -> &str {
+            self.0
+        }
+
+        fn on_start(&mut self, log: &mut Log) -> StateTransition<Log> {
+            log.push(format!("{}:start", self.0));
+            StateTransition::None
+        }
+    }
+
+    /// A covered simulation wanting to push the game over screen, a transparent overlay wanting
+    /// to push a toast and the menu on top wanting to pop itself.
+    fn contested(
+        policy: TransitionResolution,
+        log: &mut Log,
+    ) -> (StateMachine<Log>, MachineEventReceiver) {
+        let mut sm = StateMachine::<Log>::default();
+        sm.enable_metrics();
+        sm.set_transition_resolution(policy);
+        sm.push_many(
+            vec![
+                wants(
+                    "sim",
+                    false,
+                    StateTransition::Push(Box::new(Named("game_over"))),
+                ),
+                wants(
+                    "hud",
+                    false,
+                    StateTransition::Push(Box::new(Named("toast"))),
+                ),
+                wants("menu", true, StateTransition::Pop),
+            ],
+            log,
+        );
+        let events = sm.subscribe();
+        (sm, events)
+    }
+
+    fn dropped(events: &MachineEventReceiver) -> Vec<(String, Option<TransitionKind>)> {
+        std::iter::from_fn(|| events.try_recv())
+            .filter_map(|event| match event {
+                MachineEvent::TransitionDropped { name, kind } => Some((name, kind)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn names(sm: &StateMachine<Log>) -> Vec<&str> {
+        sm.iter().map(|state| state.name()).collect()
+    }
+
+    #[test]
+    fn top_wins() {
+        let mut log = Log::new();
+        let (mut sm, events) = contested(TransitionResolution::TopWins, &mut log);
+        assert_eq!(
+            sm.update(&mut log),
+            MachineStatus::Transitioned(TransitionKind::Pop)
+        );
+        assert_eq!(names(&sm), ["sim", "hud"]);
+        assert_eq!(
+            dropped(&events),
+            [
+                ("sim".to_string(), Some(TransitionKind::Push)),
+                ("hud".to_string(), Some(TransitionKind::Push)),
+            ]
+        );
+        assert_eq!(sm.metrics().dropped_transitions(), 2);
+        sm.update(&mut log);
+        assert_eq!(names(&sm), ["sim", "hud"]);
+    }
+
+    #[test]
+    fn first_wins() {
+        let mut log = Log::new();
+        let (mut sm, events) = contested(TransitionResolution::FirstWins, &mut log);
+        assert_eq!(
+            sm.update(&mut log),
+            MachineStatus::Transitioned(TransitionKind::Push)
+        );
+        assert_eq!(names(&sm), ["sim", "hud", "menu", "game_over"]);
+        assert_eq!(
+            dropped(&events),
+            [
+                ("hud".to_string(), Some(TransitionKind::Push)),
+                ("menu".to_string(), Some(TransitionKind::Pop)),
+            ]
+        );
+        assert_eq!(sm.metrics().dropped_transitions(), 2);
+    }
+
+    #[test]
+    fn queue() {
+        let mut log = Log::new();
+        let (mut sm, events) = contested(TransitionResolution::Queue, &mut log);
+        sm.update(&mut log);
+        assert_eq!(names(&sm), ["sim", "hud"]);
+        assert_eq!(sm.pending_transitions(), 2);
+        assert!(dropped(&events).is_empty());
+
+        log.clear();
+        sm.update(&mut log);
+        assert_eq!(names(&sm), ["sim", "hud", "game_over", "toast"]);
+        assert_eq!(log, ["game_over:start", "toast:start"]);
+        assert_eq!(sm.metrics().dropped_transitions(), 0);
+    }
+}
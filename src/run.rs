@@ -0,0 +1,293 @@
+//! Driving a state machine until it stops, for small tools, test harnesses and examples which do
+//! not have a game loop of their own.
+//!
+//! ```rust
+//! use game_state_machine::*;
+//!
+//! struct Ask;
+//! struct Confirm;
+//!
+//! impl State<Vec<&'static str>> for Ask {
+//!     fn update(&mut self, answers: &mut Vec<&'static str>) -> StateTransition<Vec<&'static str>> {
+//!         answers.push("name");
+//!         StateTransition::switch(Confirm)
+//!     }
+//! }
+//!
+//! impl State<Vec<&'static str>> for Confirm {
+//!     fn update(&mut self, answers: &mut Vec<&'static str>) -> StateTransition<Vec<&'static str>> {
+//!         answers.push("confirmed");
+//!         StateTransition::Quit
+//!     }
+//! }
+//!
+//! let mut answers = Vec::new();
+//! let mut sm = StateMachine::new(Box::new(Ask));
+//! sm.start(&mut answers);
+//! let reason = sm.run(&mut answers, |_| true);
+//! assert_eq!(reason, Some(ExitReason::Quit));
+//! assert_eq!(answers, vec!["name", "confirmed"]);
+//! ```
+
+use crate::{Allocator, ExitReason, MachineStatus, ScopedStateMachine};
+use std::any::Any;
+
+/// How `StateMachine::run_until` ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RunOutcome {
+    /// The predicate held.
+    PredicateMet,
+    /// The machine stopped before the predicate held.
+    Stopped,
+    /// The machine was updated the maximum number of times without the predicate holding.
+    MaxItersReached,
+}
+
+impl<'a, S: ?Sized, E, A: Allocator> ScopedStateMachine<'a, S, E, A> {
+    /// Updates the machine until it stops, calling `frame` before each update for the host to
+    /// poll its input or draw. If `frame` returns `false`, the machine is left as it is and
+    /// `None` is returned, otherwise how the machine stopped is returned.
+    pub fn run(
+        &mut self,
+        state_data: &mut S,
+        mut frame: impl FnMut(&mut S) -> bool,
+    ) -> Option<ExitReason> {
+        while self.is_running() {
+            if !frame(state_data) {
+                return None;
+            }
+            self.update(state_data);
+        }
+        self.exit_reason()
+    }
+
+    /// Same as `run`, also taking the result of the machine if it is a `R`, see
+    /// `StateMachine::set_result`.
+    pub fn run_with_result<R: Any>(
+        &mut self,
+        state_data: &mut S,
+        frame: impl FnMut(&mut S) -> bool,
+    ) -> (Option<ExitReason>, Option<R>) {
+        let reason = self.run(state_data, frame);
+        (reason, self.take_result())
+    }
+
+    /// Updates the machine at most this many times, and returns how it stopped, or `None` if it
+    /// is still running.
+    pub fn run_for(&mut self, frames: usize, state_data: &mut S) -> Option<ExitReason> {
+        for _ in 0..frames {
+            if !self.is_running() {
+                break;
+            }
+            self.update(state_data);
+        }
+        self.exit_reason()
+    }
+
+    /// Updates the machine at most `n` times, stopping early if it stops, and returns how many
+    /// times it was updated.
+    pub fn update_n(&mut self, n: usize, state_data: &mut S) -> usize {
+        self.update_n_while(n, state_data, |_| true)
+    }
+
+    /// Updates the machine at most `n` times, stopping early if it stops or after an update where
+    /// no transition happened, and returns how many times it was updated.
+    /// This lets a flow of states settle, like menus going through their screens in a test.
+    pub fn update_until_quiescent(&mut self, n: usize, state_data: &mut S) -> usize {
+        self.update_n_while(n, state_data, |status| {
+            matches!(
+                status,
+                MachineStatus::Transitioned(_) | MachineStatus::BudgetExhausted { .. }
+            )
+        })
+    }
+
+    /// Updates the machine at most `n` times while it is running and `next` holds for the
+    /// status of the last update, and returns how many times it was updated.
+    fn update_n_while(
+        &mut self,
+        n: usize,
+        state_data: &mut S,
+        next: impl Fn(MachineStatus) -> bool,
+    ) -> usize {
+        let mut updates = 0;
+        while updates < n && self.is_running() {
+            updates += 1;
+            if !next(self.update(state_data)) {
+                break;
+            }
+        }
+        updates
+    }
+
+    /// Updates the machine until the predicate holds, checking it before each update, for at
+    /// most `max_iters` updates.
+    ///
+    /// ```rust,ignore
+    /// let outcome = sm.run_until(&mut data, |sm, _| sm.contains_state::<GameOver>(), 10_000);
+    /// assert_eq!(outcome, RunOutcome::PredicateMet);
+    /// ```
+    pub fn run_until(
+        &mut self,
+        state_data: &mut S,
+        mut predicate: impl FnMut(&Self, &S) -> bool,
+        max_iters: usize,
+    ) -> RunOutcome {
+        for _ in 0..max_iters {
+            if predicate(self, state_data) {
+                return RunOutcome::PredicateMet;
+            }
+            if !self.is_running() {
+                return RunOutcome::Stopped;
+            }
+            self.update(state_data);
+        }
+        if predicate(self, state_data) {
+            RunOutcome::PredicateMet
+        } else if !self.is_running() {
+            RunOutcome::Stopped
+        } else {
+            RunOutcome::MaxItersReached
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    /// Counts its updates and quits after the given number of them.
+    struct Countdown(u32);
+
+    impl State<u32> for Countdown {
+        fn update(&mut self, updates: &mut u32) -> StateTransition<u32> {
+            *updates += 1;
+            self.0 -= 1;
+            match self.0 {
+                0 => StateTransition::Quit,
+                _ => StateTransition::None,
+            }
+        }
+    }
+
+    #[test]
+    fn run() {
+        let mut updates = 0;
+        let mut sm = StateMachine::<u32>::default();
+        sm.push(Box::new(Countdown(3)), &mut updates);
+        let mut frames = 0;
+        assert_eq!(
+            sm.run(&mut updates, |_| {
+                frames += 1;
+                true
+            }),
+            Some(ExitReason::Quit)
+        );
+        assert_eq!((frames, updates), (3, 3));
+
+        updates = 0;
+        sm.push(Box::new(Countdown(3)), &mut updates);
+        assert_eq!(sm.run(&mut updates, |updates| *updates < 2), None);
+        assert_eq!(updates, 2);
+        assert!(sm.is_running());
+    }
+
+    #[derive(Debug, PartialEq)]
+    enum Mode {
+        Coop,
+        Versus,
+    }
+
+    /// Picks a game mode, then quits.
+    struct Launcher;
+
+    impl State<u32> for Launcher {
+        fn update(&mut self, _: &mut u32) -> StateTransition<u32> {
+            StateTransition::Sequence(vec![
+                StateTransition::result(Mode::Versus),
+                StateTransition::result(Mode::Coop),
+                StateTransition::Quit,
+            ])
+        }
+    }
+
+    #[test]
+    fn run_with_result() {
+        let mut sm = StateMachine::<u32>::default();
+        sm.push(Box::new(Launcher), &mut 0);
+        // The second result replaced the first one.
+        assert_eq!(
+            sm.run_with_result::<Mode>(&mut 0, |_| true),
+            (Some(ExitReason::Quit), Some(Mode::Coop))
+        );
+        assert!(!sm.has_result());
+    }
+
+    #[test]
+    fn result_type_mismatch() {
+        let mut sm = StateMachine::<u32>::default();
+        assert!(sm.set_result(Mode::Coop).is_none());
+        assert_eq!(sm.take_result::<u32>(), None);
+        assert!(sm.has_result());
+        let previous = sm.set_result(Mode::Versus).unwrap();
+        assert_eq!(previous.downcast_ref(), Some(&Mode::Coop));
+        assert_eq!(sm.take_result(), Some(Mode::Versus));
+        assert_eq!(sm.take_result::<Mode>(), None);
+    }
+
+    #[test]
+    fn run_for() {
+        let mut updates = 0;
+        let mut sm = StateMachine::<u32>::default();
+        sm.push(Box::new(Countdown(3)), &mut updates);
+        assert_eq!(sm.run_for(2, &mut updates), None);
+        assert_eq!(sm.run_for(5, &mut updates), Some(ExitReason::Quit));
+        assert_eq!(updates, 3);
+    }
+
+    #[test]
+    fn run_until() {
+        let mut updates = 0;
+        let mut sm = StateMachine::<u32>::default();
+        sm.push(Box::new(Countdown(5)), &mut updates);
+        let outcome = sm.run_until(&mut updates, |_, updates| *updates == 2, 10);
+        assert_eq!((outcome, updates), (RunOutcome::PredicateMet, 2));
+
+        let outcome = sm.run_until(&mut updates, |_, _| false, 2);
+        assert_eq!((outcome, updates), (RunOutcome::MaxItersReached, 4));
+
+        let outcome = sm.run_until(&mut updates, |_, _| false, 10);
+        assert_eq!((outcome, updates), (RunOutcome::Stopped, 5));
+    }
+
+    #[test]
+    fn update_n() {
+        let mut updates = 0;
+        let mut sm = StateMachine::<u32>::default();
+        sm.push(Box::new(Countdown(3)), &mut updates);
+        assert_eq!(sm.update_n(10, &mut updates), 3);
+        assert!(!sm.is_running());
+        assert_eq!(sm.update_n(10, &mut updates), 0);
+    }
+
+    /// Switches to the next screen until the last one.
+    struct Screen(u32);
+
+    impl State<u32> for Screen {
+        fn update(&mut self, _: &mut u32) -> StateTransition<u32> {
+            match self.0 {
+                0 => StateTransition::None,
+                n => StateTransition::Switch(Box::new(Screen(n - 1))),
+            }
+        }
+    }
+
+    #[test]
+    fn until_quiescent() {
+        let mut sm = StateMachine::<u32>::default();
+        sm.push(Box::new(Screen(2)), &mut 0);
+        assert_eq!(sm.update_until_quiescent(10, &mut 0), 3);
+        assert!(sm.is_running());
+        assert_eq!(sm.update_until_quiescent(10, &mut 0), 1);
+    }
+}
@@ -0,0 +1,89 @@
+//! Letting the states write themselves into the state data before the host saves it, and rebuild
+//! from it once it is loaded, without serializing the states themselves.
+
+use crate::{guard, Allocator, ScopedStateMachine, State};
+
+impl<'a, S: ?Sized, E, A: Allocator> ScopedStateMachine<'a, S, E, A> {
+    /// Calls `State::on_save` on every state of the stack, paused or not, from the bottom up, so
+    /// the state data holds all of them once it returns. Saving the data is up to the host.
+    /// This composes with `save_stack`, which saves the states themselves.
+    ///
+    /// It is not named `save`, which a machine used as a state gets from `State::save`.
+    pub fn notify_saving(&mut self, state_data: &mut S) {
+        self.notify_all(state_data, |state, state_data| {
+            in_span!("state.on_save", state.name(), state.on_save(state_data))
+        });
+    }
+
+    /// Calls `State::on_load` on every state of the stack, paused or not, from the bottom up,
+    /// once the host loaded the state data.
+    pub fn notify_loaded(&mut self, state_data: &mut S) {
+        self.notify_all(state_data, |state, state_data| {
+            in_span!("state.on_load", state.name(), state.on_load(state_data))
+        });
+    }
+
+    /// Calls a hook on each state from the bottom up, removing the ones that panic.
+    pub(crate) fn notify_all(
+        &mut self,
+        state_data: &mut S,
+        hook: fn(&mut dyn State<S, E>, &mut S),
+    ) {
+        let catch = self.catches_panics();
+        let mut index = 0;
+        while index < self.state_stack.len() {
+            let entry = &mut self.state_stack[index];
+            if let Err(caught) = guard(catch, || hook(entry.state.as_mut(), state_data)) {
+                if index + 1 == self.state_stack.len() {
+                    self.drop_top(caught, state_data);
+                } else {
+                    self.state_stack.remove(index);
+                    self.caught(caught);
+                }
+                continue;
+            }
+            index += 1;
+        }
+        self.settle(state_data);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    /// The saved game, with what the states wrote and the caches they rebuilt.
+    #[derive(Default)]
+    struct SaveData {
+        saved: Vec<&'static str>,
+        loaded: Vec<&'static str>,
+    }
+
+    struct Layer(&'static str);
+
+    impl State<SaveData> for Layer {
+        fn on_save(&mut self, data: &mut SaveData) {
+            data.saved.push(self.0);
+        }
+
+        fn on_load(&mut self, data: &mut SaveData) {
+            data.loaded.push(self.0);
+        }
+    }
+
+    #[test]
+    fn bottom_up_with_paused_states() {
+        let mut data = SaveData::default();
+        let mut sm = StateMachine::<SaveData>::default();
+        sm.push(Box::new(Layer("world")), &mut data);
+        sm.push(Box::new(Layer("town")), &mut data);
+        sm.push(Box::new(Layer("shop")), &mut data);
+
+        sm.notify_saving(&mut data);
+        assert_eq!(data.saved, ["world", "town", "shop"]);
+        assert!(data.loaded.is_empty());
+
+        sm.notify_loaded(&mut data);
+        assert_eq!(data.loaded, ["world", "town", "shop"]);
+    }
+}
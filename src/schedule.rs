@@ -0,0 +1,229 @@
+//! Transitions performed a number of frames or an amount of time from now, like a banner pushed
+//! a while after a wave is complete, without a countdown in the states.
+
+use crate::{Allocator, ScopedStateMachine, StateTransition, TransitionError};
+#[cfg(feature = "time")]
+use std::time::Duration;
+
+/// Identifies a transition scheduled on a state machine, to cancel it later.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ScheduleId(u64);
+
+/// When a scheduled transition is due.
+enum Due {
+    /// At the start of the update of this frame.
+    Frame(u64),
+    /// At the start of the first update once this much time elapsed.
+    #[cfg(feature = "time")]
+    Time(Duration),
+}
+
+/// The transitions scheduled on a state machine, in the order they were scheduled.
+pub(crate) struct Schedule<S: ?Sized, E> {
+    scheduled: Vec<(ScheduleId, Due, StateTransition<S, E>)>,
+    next_id: u64,
+    /// The time counted by `update_with_dt` since the machine was created or reset.
+    #[cfg(feature = "time")]
+    elapsed: Duration,
+}
+
+impl<S: ?Sized, E> Default for Schedule<S, E> {
+    fn default() -> Self {
+        Self {
+            scheduled: Vec::new(),
+            next_id: 0,
+            #[cfg(feature = "time")]
+            elapsed: Duration::default(),
+        }
+    }
+}
+
+impl<S: ?Sized, E> Schedule<S, E> {
+    /// Drops the scheduled transitions.
+    pub(crate) fn clear(&mut self) {
+        self.scheduled.clear();
+    }
+
+    /// Drops the scheduled transitions and forgets the time elapsed, for a machine counting its
+    /// frames from 0 again.
+    pub(crate) fn rewind(&mut self) {
+        self.clear();
+        #[cfg(feature = "time")]
+        {
+            self.elapsed = Duration::default();
+        }
+    }
+
+    /// Adds the time elapsed since the last frame.
+    #[cfg(feature = "time")]
+    pub(crate) fn advance(&mut self, dt: Duration) {
+        self.elapsed += dt;
+    }
+
+    fn add(&mut self, due: Due, transition: StateTransition<S, E>) -> ScheduleId {
+        let id = ScheduleId(self.next_id);
+        self.next_id += 1;
+        self.scheduled.push((id, due, transition));
+        id
+    }
+}
+
+impl<'a, S: ?Sized, E, A: Allocator> ScopedStateMachine<'a, S, E, A> {
+    /// Schedules a transition to be performed at the start of the update `after_frames` updates
+    /// from now, 1 being the next one, with the transitions queued for it. A delay of 0 is the
+    /// same as 1. The transitions due on the same update are performed in the order they were
+    /// scheduled, after the ones queued with `queue_transition` or requested.
+    ///
+    /// Fails with `TransitionError::MachineStopped` if the machine is not running, since
+    /// stopping it drops the scheduled transitions, see `stop`.
+    pub fn schedule(
+        &mut self,
+        after_frames: u64,
+        transition: StateTransition<S, E>,
+    ) -> Result<ScheduleId, TransitionError> {
+        if !self.is_running() {
+            return Err(TransitionError::MachineStopped);
+        }
+        let due = Due::Frame(self.frame + after_frames.max(1));
+        Ok(self.schedule.add(due, transition))
+    }
+
+    /// Schedules a transition to be performed at the start of the first update once `delay`
+    /// elapsed, like `schedule`.
+    /// The time is only counted by `update_with_dt`, `update` does not know how long the frame
+    /// took. A delay of zero is due on the next update with a time.
+    #[cfg(feature = "time")]
+    pub fn schedule_after(
+        &mut self,
+        delay: Duration,
+        transition: StateTransition<S, E>,
+    ) -> Result<ScheduleId, TransitionError> {
+        if !self.is_running() {
+            return Err(TransitionError::MachineStopped);
+        }
+        let due = Due::Time(self.schedule.elapsed + delay);
+        Ok(self.schedule.add(due, transition))
+    }
+
+    /// Returns the number of scheduled transitions that were not performed yet.
+    pub fn scheduled_count(&self) -> usize {
+        self.schedule.scheduled.len()
+    }
+
+    /// Cancels a scheduled transition.
+    /// Returns `false` if it was already performed, cancelled or dropped.
+    pub fn cancel(&mut self, id: ScheduleId) -> bool {
+        let scheduled = &mut self.schedule.scheduled;
+        match scheduled
+            .iter()
+            .position(|(scheduled, _, _)| *scheduled == id)
+        {
+            Some(index) => {
+                scheduled.remove(index);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Moves the scheduled transitions that are due to the queue of the machine.
+    pub(crate) fn receive_scheduled(&mut self) {
+        let frame = self.frame;
+        #[cfg(feature = "time")]
+        let elapsed = self.schedule.elapsed;
+        let mut index = 0;
+        while index < self.schedule.scheduled.len() {
+            let due = match self.schedule.scheduled[index].1 {
+                Due::Frame(at) => at <= frame,
+                #[cfg(feature = "time")]
+                Due::Time(at) => at <= elapsed,
+            };
+            if due {
+                let (_, _, transition) = self.schedule.scheduled.remove(index);
+                self.queued_transitions.push_back(transition);
+            } else {
+                index += 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    /// A banner which counts how many times it was started.
+    struct Banner;
+
+    impl State<u32> for Banner {
+        fn on_start(&mut self, starts: &mut u32) -> StateTransition<u32> {
+            *starts += 1;
+            StateTransition::None
+        }
+    }
+
+    #[test]
+    fn exact_frame() {
+        let mut starts = 0;
+        let mut sm = StateMachine::<u32>::default();
+        assert_eq!(
+            sm.schedule(1, StateTransition::Pop).err(),
+            Some(TransitionError::MachineStopped)
+        );
+        sm.push(Box::new(Banner), &mut starts);
+        starts = 0;
+        sm.schedule(3, StateTransition::Push(Box::new(Banner)))
+            .unwrap();
+        sm.schedule(3, StateTransition::Pop).unwrap();
+        sm.schedule(2, StateTransition::Push(Box::new(Banner)))
+            .unwrap();
+        assert_eq!(sm.scheduled_count(), 3);
+
+        assert_eq!(sm.update(&mut starts), MachineStatus::Running);
+        assert_eq!(
+            sm.update(&mut starts),
+            MachineStatus::Transitioned(TransitionKind::Push)
+        );
+        assert_eq!(sm.len(), 2);
+        assert_eq!(
+            sm.update(&mut starts),
+            MachineStatus::Transitioned(TransitionKind::Pop)
+        );
+        assert_eq!(sm.len(), 2);
+        assert_eq!(starts, 2);
+        assert_eq!(sm.scheduled_count(), 0);
+    }
+
+    #[test]
+    fn cancel_and_stop() {
+        let mut starts = 0;
+        let mut sm = StateMachine::<u32>::default();
+        sm.push(Box::new(Banner), &mut starts);
+        let pop = sm.schedule(1, StateTransition::Pop).unwrap();
+        assert!(sm.cancel(pop));
+        assert!(!sm.cancel(pop));
+        sm.update(&mut starts);
+        assert_eq!(sm.len(), 1);
+
+        sm.schedule(5, StateTransition::Pop).unwrap();
+        sm.stop(&mut starts);
+        assert_eq!(sm.scheduled_count(), 0);
+    }
+
+    #[cfg(feature = "time")]
+    #[test]
+    fn after_duration() {
+        use std::time::Duration;
+
+        let mut starts = 0;
+        let mut sm = StateMachine::<u32>::default();
+        sm.push(Box::new(Banner), &mut starts);
+        sm.schedule_after(Duration::from_millis(30), StateTransition::Pop)
+            .unwrap();
+        sm.update(&mut starts);
+        sm.update_with_dt(Duration::from_millis(20), &mut starts);
+        assert!(sm.is_running());
+        sm.update_with_dt(Duration::from_millis(10), &mut starts);
+        assert!(!sm.is_running());
+    }
+}
@@ -0,0 +1,238 @@
+//! Sharing a state machine between threads, so a background thread, like an asset loader, can
+//! request a transition while the main thread updates the machine.
+
+use crate::{MachineStatus, StateMachine, StateTransition};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex, MutexGuard};
+#[cfg(feature = "time")]
+use std::time::Duration;
+
+/// Locks a mutex, which is used all the same if a thread panicked while holding it.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// A state machine behind a mutex, whose clones share the same machine, with a channel other
+/// threads send transitions to, see `sender`.
+///
+/// A thread which panics while it holds the machine poisons its mutex. The machine is still
+/// used after that, as it was when the thread panicked, which may be in the middle of a
+/// transition. `is_poisoned` tells if this happened.
+///
+/// ```rust
+/// use game_state_machine::*;
+///
+/// let shared = SharedStateMachine::new(StateMachine::<u32>::default());
+/// let sender = shared.sender();
+/// let loader = std::thread::spawn(move || {
+///     let game = StateTransition::push(|_: &mut u32| StateTransition::None);
+///     assert!(sender.request(game).is_ok());
+/// });
+/// loader.join().unwrap();
+/// shared.update(&mut 0);
+/// assert_eq!(shared.lock().len(), 1);
+/// ```
+pub struct SharedStateMachine<S: ?Sized, E = ()> {
+    machine: Arc<Mutex<StateMachine<S, E>>>,
+    requests: Arc<Mutex<Receiver<StateTransition<S, E>>>>,
+    sender: Sender<StateTransition<S, E>>,
+}
+
+impl<S: ?Sized, E> Clone for SharedStateMachine<S, E> {
+    fn clone(&self) -> Self {
+        Self {
+            machine: self.machine.clone(),
+            requests: self.requests.clone(),
+            sender: self.sender.clone(),
+        }
+    }
+}
+
+impl<S: ?Sized, E> SharedStateMachine<S, E> {
+    /// Shares this state machine.
+    pub fn new(state_machine: StateMachine<S, E>) -> Self {
+        let (sender, requests) = channel();
+        Self {
+            machine: Arc::new(Mutex::new(state_machine)),
+            requests: Arc::new(Mutex::new(requests)),
+            sender,
+        }
+    }
+
+    /// Returns a handle sending transitions to the machine from any thread.
+    pub fn sender(&self) -> TransitionSender<S, E> {
+        TransitionSender {
+            sender: self.sender.clone(),
+        }
+    }
+
+    /// Locks the state machine, to call the methods this wrapper does not have.
+    /// The requests sent since the last update are not performed until the next one.
+    pub fn lock(&self) -> MutexGuard<'_, StateMachine<S, E>> {
+        lock(&self.machine)
+    }
+
+    /// Returns if a thread panicked while it held the state machine.
+    pub fn is_poisoned(&self) -> bool {
+        self.machine.is_poisoned()
+    }
+
+    /// Queues the requests received so far on the machine, in the order they were sent.
+    fn receive(&self, state_machine: &mut StateMachine<S, E>) {
+        for request in lock(&self.requests).try_iter() {
+            state_machine.queue_transition(request);
+        }
+    }
+
+    /// Updates the state machine, see `StateMachine::update`.
+    ///
+    /// The transitions requested by the senders are performed first, in the order they were
+    /// sent, with the ones queued on the machine before them, see
+    /// `StateMachine::queue_transition`. Then the active state is updated, so the transition it
+    /// returns is performed after the requests. A request sent while the machine is updated
+    /// waits for the next update.
+    pub fn update(&self, state_data: &mut S) -> MachineStatus {
+        let mut state_machine = self.lock();
+        self.receive(&mut state_machine);
+        state_machine.update(state_data)
+    }
+
+    /// Updates the state machine with the time elapsed since the last update, performing the
+    /// transitions requested first like `update` does, see `StateMachine::update_with_dt`.
+    #[cfg(feature = "time")]
+    pub fn update_with_dt(&self, dt: Duration, state_data: &mut S) -> MachineStatus {
+        let mut state_machine = self.lock();
+        self.receive(&mut state_machine);
+        state_machine.update_with_dt(dt, state_data)
+    }
+}
+
+/// Sends transitions to a `SharedStateMachine` from any thread. Its clones send to the same
+/// machine.
+pub struct TransitionSender<S: ?Sized, E = ()> {
+    sender: Sender<StateTransition<S, E>>,
+}
+
+impl<S: ?Sized, E> Clone for TransitionSender<S, E> {
+    fn clone(&self) -> Self {
+        Self {
+            sender: self.sender.clone(),
+        }
+    }
+}
+
+impl<S: ?Sized, E> TransitionSender<S, E> {
+    /// Requests a transition, performed at the start of the next update of the machine.
+    /// Gives the transition back if the machine and all its clones were dropped.
+    pub fn request(&self, transition: StateTransition<S, E>) -> Result<(), StateTransition<S, E>> {
+        self.sender.send(transition).map_err(|error| error.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use std::thread;
+
+    /// Switches to the level once its update counter reaches 2.
+    struct Loading(u32);
+
+    impl State<Vec<&'static str>> for Loading {
+        fn name(&self) -> &str {
+            "loading"
+        }
+
+        fn update(&mut self, log: &mut Vec<&'static str>) -> StateTransition<Vec<&'static str>> {
+            log.push("loading");
+            self.0 += 1;
+            if self.0 == 2 {
+                StateTransition::Switch(Box::new(Named("from loading")))
+            } else {
+                StateTransition::None
+            }
+        }
+    }
+
+    struct Named(&'static str);
+
+    impl State<Vec<&'static str>> for Named {
+        fn name(&self) -> &str {
+            self.0
+        }
+
+        fn update(&mut self, log: &mut Vec<&'static str>) -> StateTransition<Vec<&'static str>> {
+            log.push(self.0);
+            StateTransition::None
+        }
+    }
+
+    #[test]
+    fn requests_from_thread() {
+        let mut log = Vec::new();
+        let shared = SharedStateMachine::new(StateMachine::default());
+        shared.lock().push(Box::new(Loading(0)), &mut log);
+        shared.update(&mut log);
+
+        let sender = shared.sender();
+        thread::spawn(move || {
+            let level = StateTransition::Switch(Box::new(Named("level")));
+            assert!(sender.request(level).is_ok());
+            assert!(sender
+                .request(StateTransition::Push(Box::new(Named("hud"))))
+                .is_ok());
+        })
+        .join()
+        .unwrap();
+
+        // The requests are performed in order before the active state is updated.
+        shared.update(&mut log);
+        assert_eq!(log, ["loading", "hud"]);
+        let machine = shared.lock();
+        let names: Vec<_> = machine.iter().map(|state| state.name()).collect();
+        assert_eq!(names, ["level", "hud"]);
+    }
+
+    #[test]
+    fn request_after_update() {
+        let mut log = Vec::new();
+        let shared = SharedStateMachine::new(StateMachine::default());
+        shared.lock().push(Box::new(Loading(1)), &mut log);
+        // The transition returned by the state is performed first, then the request on the
+        // next update.
+        shared.update(&mut log);
+        let sender = shared.sender();
+        assert!(sender.request(StateTransition::Pop).is_ok());
+        assert_eq!(
+            shared.lock().active().map(|state| state.name()),
+            Some("from loading")
+        );
+        shared.update(&mut log);
+        assert!(!shared.lock().is_running());
+    }
+
+    #[test]
+    fn dropped_machine() {
+        let shared = SharedStateMachine::<()>::new(StateMachine::default());
+        let sender = shared.sender();
+        drop(shared);
+        assert!(sender.request(StateTransition::Quit).is_err());
+    }
+
+    #[test]
+    fn poisoned() {
+        let mut log = Vec::new();
+        let shared = SharedStateMachine::new(StateMachine::default());
+        let other = shared.clone();
+        let _ = thread::spawn(move || {
+            let _machine = other.lock();
+            panic!("the loader failed");
+        })
+        .join();
+        assert!(shared.is_poisoned());
+        shared.lock().push(Box::new(Named("menu")), &mut log);
+        shared.update(&mut log);
+        assert_eq!(log, ["menu"]);
+    }
+}
@@ -0,0 +1,590 @@
+//! Saving the stack of a state machine, so it can be restored later, for example in a save game.
+//!
+//! States opt into being saved by returning themselves from `State::save`, and by implementing
+//! `SavedState` with `#[typetag::serde]`:
+//! ```rust,ignore
+//! #[derive(Clone, Serialize, Deserialize)]
+//! struct Shop {
+//!     gold: u32,
+//! }
+//!
+//! impl State<GameData> for Shop {
+//!     fn save(&self) -> Option<Box<dyn SavedState>> {
+//!         Some(Box::new(self.clone()))
+//!     }
+//! }
+//!
+//! #[typetag::serde]
+//! impl SavedState for Shop {
+//!     fn restore(&self) -> Box<dyn Any> {
+//!         restored::<GameData, ()>(self.clone())
+//!     }
+//! }
+//! ```
+//!
+//! The snapshots saved by older versions of a game can be upgraded when loaded, once the states
+//! they saved were renamed or removed, see `SnapshotMigrator`.
+
+use crate::{MaybeSend, PauseReason, State, StateMachine};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::any::Any;
+use std::error::Error;
+use std::fmt;
+
+/// The serializable form of a state.
+///
+/// ## Restoring
+/// `restore` must return the state to put back on the stack, created using `restored` with the
+/// generics of the state machine it will be loaded in.
+#[typetag::serde(tag = "state")]
+pub trait SavedState {
+    /// Creates the state to put back on the stack.
+    fn restore(&self) -> Box<dyn Any>;
+}
+
+/// Boxes a restored state for `SavedState::restore`.
+/// ## Generics
+/// - S: State data of the state machine the state will be loaded in.
+/// - E: Events of the state machine the state will be loaded in.
+pub fn restored<S: ?Sized + 'static, E: 'static>(
+    state: impl State<S, E> + 'static,
+) -> Box<dyn Any> {
+    let state: Box<dyn State<S, E>> = Box::new(state);
+    Box::new(state)
+}
+
+/// The saved stack of a state machine, from the bottom to the top, with the version of the
+/// states it was saved with, see `SnapshotMigrator`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StackSnapshot {
+    #[serde(default)]
+    version: u32,
+    states: Vec<SnapshotEntry>,
+}
+
+impl StackSnapshot {
+    /// Returns the number of states in the snapshot.
+    pub fn len(&self) -> usize {
+        self.states.len()
+    }
+
+    /// Returns if the snapshot has no states.
+    pub fn is_empty(&self) -> bool {
+        self.states.is_empty()
+    }
+
+    /// Returns the version of the states the snapshot was saved with, 0 for the snapshots saved
+    /// before they had one.
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    /// Returns the saved states, from the bottom to the top.
+    pub fn entries(&self) -> &[SnapshotEntry] {
+        &self.states
+    }
+}
+
+/// A saved state, before it is restored: the name its `SavedState` implementation is registered
+/// under with `#[typetag::serde]`, and its serialized fields.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SnapshotEntry {
+    /// The name of the type of the state, unless `#[typetag::serde(name = "...")]` changed it.
+    #[serde(rename = "state")]
+    pub name: String,
+    /// The fields of the state, as they were serialized.
+    #[serde(flatten)]
+    pub fields: Map<String, Value>,
+}
+
+impl SnapshotEntry {
+    /// Creates an entry for the state registered under this name, without fields.
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            fields: Map::new(),
+        }
+    }
+
+    /// Sets a field of the state, replacing the previous value.
+    pub fn with_field(mut self, name: impl Into<String>, value: impl Into<Value>) -> Self {
+        self.fields.insert(name.into(), value.into());
+        self
+    }
+}
+
+/// What a migration does to a state of an older snapshot, see `SnapshotMigrator::register`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MigrationAction {
+    /// Leaves the state as it is.
+    Keep,
+    /// Restores the state with the `SavedState` registered under this name, for a state that was
+    /// renamed.
+    Rename(String),
+    /// Leaves the state out of the stack, for a state that was removed.
+    Drop,
+    /// Restores this state instead, for a state that was split or merged into another.
+    Replace(SnapshotEntry),
+}
+
+/// A migration, given the version of the snapshot and a state it saved.
+pub type Migration = Box<maybe_send!(dyn Fn(u32, &SnapshotEntry) -> MigrationAction)>;
+
+/// The version of the states of a game, with the migrations upgrading the snapshots saved by
+/// older versions, so the save files keep loading after the states are renamed or removed.
+///
+/// `StateMachine::save_stack` writes the version of the migrator of the machine, and
+/// `StateMachine::load_stack` runs the migrations on the snapshots with a lower version.
+/// ```rust,ignore
+/// let mut migrator = SnapshotMigrator::new(2);
+/// // The shop was renamed to the store in version 2.
+/// migrator.register(|version, entry| match entry.name.as_str() {
+///     "Shop" if version < 2 => MigrationAction::Rename("Store".to_string()),
+///     _ => MigrationAction::Keep,
+/// });
+/// state_machine.set_migrator(migrator);
+/// ```
+#[derive(Default)]
+pub struct SnapshotMigrator {
+    version: u32,
+    migrations: Vec<Migration>,
+}
+
+impl SnapshotMigrator {
+    /// Creates a migrator for this version of the states, without migrations.
+    pub fn new(version: u32) -> Self {
+        Self {
+            version,
+            migrations: Vec::new(),
+        }
+    }
+
+    /// Returns the version of the states, 0 by default.
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    /// Adds a migration, called with the version of the snapshot on each of its states when the
+    /// snapshot is older than the migrator. The migrations are called in the order they were
+    /// added, each one seeing what the previous ones did, until one drops the state.
+    pub fn register(
+        &mut self,
+        migration: impl Fn(u32, &SnapshotEntry) -> MigrationAction + MaybeSend + 'static,
+    ) {
+        self.migrations.push(Box::new(migration));
+    }
+
+    /// Runs the migrations on a state of a snapshot of this version, returning `None` if the
+    /// state is dropped.
+    fn migrate(&self, version: u32, mut entry: SnapshotEntry) -> Option<SnapshotEntry> {
+        if version >= self.version {
+            return Some(entry);
+        }
+        for migration in &self.migrations {
+            match migration(version, &entry) {
+                MigrationAction::Keep => (),
+                MigrationAction::Rename(name) => entry.name = name,
+                MigrationAction::Drop => return None,
+                MigrationAction::Replace(replacement) => entry = replacement,
+            }
+        }
+        Some(entry)
+    }
+}
+
+/// A state of a snapshot that could not be restored, see `SnapshotError::Unresolved`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct UnresolvedEntry {
+    /// The index of the state in the snapshot, from the bottom.
+    pub index: usize,
+    /// The name of the state once migrated.
+    pub name: String,
+    /// Why it could not be restored, like an unknown name or a missing field.
+    pub reason: String,
+}
+
+/// A stack that could not be saved or loaded.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum SnapshotError {
+    /// The state at this index of the stack does not support being saved.
+    NotSerializable {
+        /// The index of the state in the stack, from the bottom.
+        index: usize,
+        /// The name of the state.
+        name: String,
+    },
+    /// The state at this index of the snapshot was restored for a state machine with other
+    /// generics.
+    WrongMachine {
+        /// The index of the state in the snapshot, from the bottom.
+        index: usize,
+    },
+    /// The state at this index of the stack could not be serialized.
+    Encoding {
+        /// The index of the state in the stack, from the bottom.
+        index: usize,
+        /// Why it could not be serialized.
+        reason: String,
+    },
+    /// These states of the snapshot could not be restored once migrated, from the bottom.
+    Unresolved(Vec<UnresolvedEntry>),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotError::NotSerializable { index, name } => {
+                write!(f, "the state {} at index {} cannot be saved", name, index)
+            }
+            SnapshotError::WrongMachine { index } => write!(
+                f,
+                "the state at index {} was restored for another kind of state machine",
+                index
+            ),
+            SnapshotError::Encoding { index, reason } => write!(
+                f,
+                "the state at index {} could not be serialized: {}",
+                index, reason
+            ),
+            SnapshotError::Unresolved(entries) => {
+                write!(f, "some states could not be restored:")?;
+                for entry in entries {
+                    write!(
+                        f,
+                        " the state {} at index {} ({});",
+                        entry.name, entry.index, entry.reason
+                    )?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Error for SnapshotError {}
+
+impl<S: ?Sized + 'static, E: 'static> StateMachine<S, E> {
+    /// Saves the states of the stack, with the version of the migrator of the machine.
+    /// Fails if one of the states does not support being saved.
+    pub fn save_stack(&self) -> Result<StackSnapshot, SnapshotError> {
+        let states = self
+            .iter()
+            .enumerate()
+            .map(|(index, state)| {
+                let saved = state.save().ok_or_else(|| SnapshotError::NotSerializable {
+                    index,
+                    name: state.name().to_string(),
+                })?;
+                serde_json::to_value(&saved)
+                    .and_then(serde_json::from_value)
+                    .map_err(|error| SnapshotError::Encoding {
+                        index,
+                        reason: error.to_string(),
+                    })
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(StackSnapshot {
+            version: self.migrator.version,
+            states,
+        })
+    }
+
+    /// Stops the states of the stack, then restores the saved states and pushes them, from the
+    /// bottom to the top, so that only the top state ends up not paused. The states covered by
+    /// the restored states are paused with `PauseReason::Loaded`.
+    ///
+    /// If the snapshot is older than the migrator of the machine, the migrations are run on its
+    /// states first, see `SnapshotMigrator`.
+    /// Fails without changing the stack if a state cannot be restored in this state machine,
+    /// listing all the states with a name or fields that no `SavedState` accepts.
+    pub fn load_stack(
+        &mut self,
+        snapshot: StackSnapshot,
+        state_data: &mut S,
+    ) -> Result<(), SnapshotError> {
+        let version = snapshot.version;
+        let mut states = Vec::new();
+        let mut unresolved = Vec::new();
+        for (index, entry) in snapshot.states.into_iter().enumerate() {
+            let entry = match self.migrator.migrate(version, entry) {
+                Some(entry) => entry,
+                None => continue,
+            };
+            let name = entry.name.clone();
+            match Self::resolve(entry) {
+                Ok(saved) => states.push((index, saved)),
+                Err(reason) => unresolved.push(UnresolvedEntry {
+                    index,
+                    name,
+                    reason,
+                }),
+            }
+        }
+        if !unresolved.is_empty() {
+            return Err(SnapshotError::Unresolved(unresolved));
+        }
+        let states = states
+            .into_iter()
+            .map(|(index, saved)| {
+                saved
+                    .restore()
+                    .downcast::<Box<dyn State<S, E>>>()
+                    .map(|state| *state)
+                    .map_err(|_| SnapshotError::WrongMachine { index })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        self.install(states, PauseReason::Loaded, state_data);
+        self.settle(state_data);
+        Ok(())
+    }
+
+    /// Deserializes a saved state with the `SavedState` registered under its name.
+    fn resolve(entry: SnapshotEntry) -> Result<Box<dyn SavedState>, String> {
+        let mut object = entry.fields;
+        object.insert("state".to_string(), Value::String(entry.name));
+        serde_json::from_value(Value::Object(object)).map_err(|error| error.to_string())
+    }
+
+    /// Returns the migrator of the snapshots, see `SnapshotMigrator`.
+    pub fn migrator(&self) -> &SnapshotMigrator {
+        &self.migrator
+    }
+
+    /// Returns the migrator of the snapshots mutably, to register migrations.
+    pub fn migrator_mut(&mut self) -> &mut SnapshotMigrator {
+        &mut self.migrator
+    }
+
+    /// Replaces the migrator of the snapshots, setting the version written by `save_stack`.
+    pub fn set_migrator(&mut self, migrator: SnapshotMigrator) {
+        self.migrator = migrator;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use serde::{Deserialize, Serialize};
+    use std::any::Any;
+
+    type Log = Vec<String>;
+
+    #[derive(Clone, Serialize, Deserialize)]
+    pub struct Overworld {
+        x: i32,
+        y: i32,
+    }
+
+    impl State<Log> for Overworld {
+        fn on_start(&mut self, log: &mut Log) -> StateTransition<Log> {
+            log.push(format!("overworld:start:{}:{}", self.x, self.y));
+            StateTransition::None
+        }
+
+        fn on_pause_with_reason(&mut self, reason: PauseReason, log: &mut Log) {
+            log.push(format!("overworld:pause:{:?}", reason));
+        }
+
+        fn save(&self) -> Option<Box<dyn SavedState>> {
+            Some(Box::new(self.clone()))
+        }
+    }
+
+    #[typetag::serde]
+    impl SavedState for Overworld {
+        fn restore(&self) -> Box<dyn Any> {
+            restored::<Log, ()>(self.clone())
+        }
+    }
+
+    #[derive(Clone, Serialize, Deserialize)]
+    pub struct Shop {
+        gold: u32,
+    }
+
+    impl State<Log> for Shop {
+        fn on_start(&mut self, log: &mut Log) -> StateTransition<Log> {
+            log.push(format!("shop:start:{}", self.gold));
+            StateTransition::None
+        }
+
+        fn on_stop(&mut self, log: &mut Log) -> StateTransition<Log> {
+            log.push("shop:stop".to_string());
+            StateTransition::None
+        }
+
+        fn save(&self) -> Option<Box<dyn SavedState>> {
+            Some(Box::new(self.clone()))
+        }
+    }
+
+    #[typetag::serde]
+    impl SavedState for Shop {
+        fn restore(&self) -> Box<dyn Any> {
+            restored::<Log, ()>(self.clone())
+        }
+    }
+
+    /// The shop, renamed in version 2.
+    #[derive(Clone, Serialize, Deserialize)]
+    pub struct Store {
+        gold: u32,
+    }
+
+    impl State<Log> for Store {
+        fn on_start(&mut self, log: &mut Log) -> StateTransition<Log> {
+            log.push(format!("store:start:{}", self.gold));
+            StateTransition::None
+        }
+
+        fn save(&self) -> Option<Box<dyn SavedState>> {
+            Some(Box::new(self.clone()))
+        }
+    }
+
+    #[typetag::serde]
+    impl SavedState for Store {
+        fn restore(&self) -> Box<dyn Any> {
+            restored::<Log, ()>(self.clone())
+        }
+    }
+
+    pub struct Unsaved;
+
+    impl State<Log> for Unsaved {}
+
+    #[test]
+    fn round_trip() {
+        let mut log = Log::new();
+        let mut sm = StateMachine::<Log>::default();
+        sm.push(Box::new(Overworld { x: 3, y: -2 }), &mut log);
+        sm.push(Box::new(Shop { gold: 50 }), &mut log);
+
+        let json = serde_json::to_string(&sm.save_stack().unwrap()).unwrap();
+        let snapshot: StackSnapshot = serde_json::from_str(&json).unwrap();
+        assert_eq!(snapshot.len(), 2);
+
+        log.clear();
+        let mut loaded = StateMachine::<Log>::default();
+        loaded.push(Box::new(Shop { gold: 0 }), &mut log);
+        loaded.load_stack(snapshot, &mut log).unwrap();
+        assert_eq!(
+            log,
+            vec![
+                "shop:start:0",
+                "shop:stop",
+                "overworld:start:3:-2",
+                "overworld:pause:Loaded",
+                "shop:start:50"
+            ]
+        );
+        assert_eq!(loaded.len(), 2);
+    }
+
+    #[test]
+    fn not_serializable() {
+        let mut log = Log::new();
+        let mut sm = StateMachine::<Log>::default();
+        sm.push(Box::new(Shop { gold: 1 }), &mut log);
+        sm.push(Box::new(Unsaved), &mut log);
+
+        match sm.save_stack() {
+            Err(SnapshotError::NotSerializable { index, .. }) => assert_eq!(index, 1),
+            _ => panic!("the stack should not be serializable"),
+        }
+    }
+
+    #[test]
+    fn wrong_machine() {
+        let mut log = Log::new();
+        let mut sm = StateMachine::<Log>::default();
+        sm.push(Box::new(Shop { gold: 1 }), &mut log);
+        let snapshot = sm.save_stack().unwrap();
+
+        let mut other = StateMachine::<Log, u8>::default();
+        assert_eq!(
+            other.load_stack(snapshot, &mut log).err(),
+            Some(SnapshotError::WrongMachine { index: 0 })
+        );
+    }
+
+    #[test]
+    fn migrated_round_trip() {
+        let mut log = Log::new();
+        let mut old = StateMachine::<Log>::default();
+        old.set_migrator(SnapshotMigrator::new(1));
+        old.push(Box::new(Overworld { x: 1, y: 2 }), &mut log);
+        old.push(Box::new(Shop { gold: 7 }), &mut log);
+        let json = serde_json::to_string(&old.save_stack().unwrap()).unwrap();
+
+        let mut sm = StateMachine::<Log>::default();
+        sm.set_migrator(SnapshotMigrator::new(2));
+        sm.migrator_mut()
+            .register(|version, entry| match entry.name.as_str() {
+                "Shop" if version < 2 => MigrationAction::Rename("Store".to_string()),
+                _ => MigrationAction::Keep,
+            });
+        let snapshot: StackSnapshot = serde_json::from_str(&json).unwrap();
+        assert_eq!(snapshot.version(), 1);
+        log.clear();
+        sm.load_stack(snapshot, &mut log).unwrap();
+        assert_eq!(
+            log,
+            vec![
+                "overworld:start:1:2",
+                "overworld:pause:Loaded",
+                "store:start:7"
+            ]
+        );
+        assert_eq!(sm.save_stack().unwrap().version(), 2);
+    }
+
+    #[test]
+    fn dropped_and_replaced() {
+        let mut log = Log::new();
+        let json = r#"{"version":1,"states":[
+            {"state":"Overworld","x":0,"y":0},
+            {"state":"Tutorial"},
+            {"state":"Market","coins":3}
+        ]}"#;
+        let mut sm = StateMachine::<Log>::default();
+        sm.set_migrator(SnapshotMigrator::new(2));
+        sm.migrator_mut()
+            .register(|_, entry| match entry.name.as_str() {
+                "Tutorial" => MigrationAction::Drop,
+                "Market" => MigrationAction::Replace(
+                    SnapshotEntry::new("Store").with_field("gold", entry.fields["coins"].clone()),
+                ),
+                _ => MigrationAction::Keep,
+            });
+        sm.load_stack(serde_json::from_str(json).unwrap(), &mut log)
+            .unwrap();
+        assert_eq!(log.last().unwrap(), "store:start:3");
+        assert_eq!(sm.len(), 2);
+    }
+
+    #[test]
+    fn unresolved_entries() {
+        let mut log = Log::new();
+        let json = r#"{"states":[
+            {"state":"Tutorial"},
+            {"state":"Shop","gold":1},
+            {"state":"Store"}
+        ]}"#;
+        let mut sm = StateMachine::<Log>::default();
+        sm.push(Box::new(Shop { gold: 0 }), &mut log);
+        match sm.load_stack(serde_json::from_str(json).unwrap(), &mut log) {
+            Err(SnapshotError::Unresolved(entries)) => {
+                let unresolved: Vec<_> = entries
+                    .iter()
+                    .map(|entry| (entry.index, entry.name.as_str()))
+                    .collect();
+                assert_eq!(unresolved, [(0, "Tutorial"), (2, "Store")]);
+            }
+            _ => panic!("the tutorial and the store should be unresolved"),
+        }
+        assert_eq!(sm.len(), 1);
+    }
+}
@@ -0,0 +1,342 @@
+//! A state machine whose states get a shared read-only context, like the configuration or the
+//! assets, next to the data they mutate, like the world.
+
+use crate::{MachineStatus, State, StateTransition, StopReason, TransitionKind};
+
+/// Trait that states of a `SplitStateMachine` must implement.
+/// It has the same lifecycle as `State`, with callbacks given the context along with the data.
+///
+/// ## Generics
+/// - C: Context, the data that is sent to states to read only.
+/// - S: State data, the data that is sent to states for them to do their operations.
+pub trait SplitState<C: ?Sized, S: ?Sized> {
+    /// Called when the state is first inserted on the stack.
+    fn on_start(&mut self, _context: &C, _state_data: &mut S) {}
+    /// Called when the state is popped from the stack.
+    fn on_stop(&mut self, _context: &C, _state_data: &mut S) {}
+    /// Called when a state is pushed over this one in the stack.
+    fn on_pause(&mut self, _context: &C, _state_data: &mut S) {}
+    /// Called when the state just on top of this one in the stack is popped.
+    fn on_resume(&mut self, _context: &C, _state_data: &mut S) {}
+    /// Executed on every frame immediately, as fast as the engine will allow.
+    fn update(&mut self, _context: &C, _state_data: &mut S) -> SplitTransition<C, S> {
+        SplitTransition::None
+    }
+}
+
+/// A transition from one state to the other in a `SplitStateMachine`.
+/// Behaves like the `StateTransition` variants of the same name.
+pub enum SplitTransition<C: ?Sized, S: ?Sized> {
+    /// Stay in the current state.
+    None,
+    /// End the current state and go to the previous state on the stack, if any.
+    Pop,
+    /// End the given number of states, top-down, in one go.
+    PopN(usize),
+    /// Push a new state on the stack.
+    Push(Box<dyn SplitState<C, S>>),
+    /// Pop all states on the stack and insert this one.
+    Switch(Box<dyn SplitState<C, S>>),
+    /// End the current state and insert this one in its place.
+    Replace(Box<dyn SplitState<C, S>>),
+    /// Pop all states and exit the state machine.
+    Quit,
+    /// Apply each transition in order. A Quit ends the sequence.
+    Sequence(Vec<SplitTransition<C, S>>),
+}
+
+impl<C: ?Sized, S: ?Sized> SplitTransition<C, S> {
+    fn kind(&self) -> Option<TransitionKind> {
+        match self {
+            SplitTransition::None => None,
+            SplitTransition::Pop => Some(TransitionKind::Pop),
+            SplitTransition::PopN(_) => Some(TransitionKind::PopN),
+            SplitTransition::Push(_) => Some(TransitionKind::Push),
+            SplitTransition::Switch(_) => Some(TransitionKind::Switch),
+            SplitTransition::Replace(_) => Some(TransitionKind::Replace),
+            SplitTransition::Quit => Some(TransitionKind::Quit),
+            SplitTransition::Sequence(_) => Some(TransitionKind::Sequence),
+        }
+    }
+}
+
+impl<C: ?Sized, S: ?Sized + 'static> From<StateTransition<S>> for SplitTransition<C, S> {
+    /// Converts the transition of a `State`, wrapping the states it carries in `Unsplit`.
+    /// `PopWith`, `PopUntil`, `PopTagged`, `PopGroup`, `PushNamed`, `PushWith`, `SwitchWith` and
+    /// `Custom` have no equivalent and become `None`, `QuitWith` becomes `Quit`, dropping its
+    /// value, and `PopRetain` becomes `Pop`, dropping the state. `ReplaceAll` becomes a switch
+    /// to its first state followed by pushes of the others, or `Quit` if it has none.
+    fn from(trans: StateTransition<S>) -> Self {
+        match trans {
+            StateTransition::Pop | StateTransition::PopRetain => SplitTransition::Pop,
+            StateTransition::PopN(n) => SplitTransition::PopN(n),
+            StateTransition::Push(state) => SplitTransition::Push(Box::new(Unsplit(state))),
+            StateTransition::PushMany(states) => SplitTransition::Sequence(
+                states
+                    .into_iter()
+                    .map(|state| SplitTransition::Push(Box::new(Unsplit(state))))
+                    .collect(),
+            ),
+            StateTransition::Switch(state) => SplitTransition::Switch(Box::new(Unsplit(state))),
+            StateTransition::ReplaceAll(states) if states.is_empty() => SplitTransition::Quit,
+            StateTransition::ReplaceAll(states) => SplitTransition::Sequence(
+                states
+                    .into_iter()
+                    .enumerate()
+                    .map(|(index, state)| match index {
+                        0 => SplitTransition::Switch(Box::new(Unsplit(state))),
+                        _ => SplitTransition::Push(Box::new(Unsplit(state))),
+                    })
+                    .collect(),
+            ),
+            StateTransition::Replace(state) => SplitTransition::Replace(Box::new(Unsplit(state))),
+            StateTransition::Quit | StateTransition::QuitWith(_) => SplitTransition::Quit,
+            StateTransition::Sequence(transitions) => {
+                SplitTransition::Sequence(transitions.into_iter().map(Into::into).collect())
+            }
+            _ => SplitTransition::None,
+        }
+    }
+}
+
+/// Adapts a `State` so it can live in a `SplitStateMachine`, ignoring the context.
+/// The transitions returned by its `on_start`, `on_stop` and `on_resume` are ignored.
+pub struct Unsplit<S: ?Sized>(pub Box<dyn State<S>>);
+
+impl<C: ?Sized, S: ?Sized + 'static> SplitState<C, S> for Unsplit<S> {
+    fn on_start(&mut self, _context: &C, state_data: &mut S) {
+        self.0.on_start(state_data);
+    }
+
+    fn on_stop(&mut self, _context: &C, state_data: &mut S) {
+        self.0.on_stop(state_data);
+    }
+
+    fn on_pause(&mut self, _context: &C, state_data: &mut S) {
+        self.0.on_pause(state_data);
+    }
+
+    fn on_resume(&mut self, _context: &C, state_data: &mut S) {
+        self.0.on_resume(state_data);
+    }
+
+    fn update(&mut self, _context: &C, state_data: &mut S) -> SplitTransition<C, S> {
+        self.0.update(state_data).into()
+    }
+}
+
+/// A state machine that holds a stack of `SplitState` and performs transitions between them,
+/// giving them a shared context to read along with the data to mutate.
+///
+/// ## Generics
+/// - C: Context, the data that is sent to states to read only.
+/// - S: State data, the data that is sent to states for them to do their operations.
+pub struct SplitStateMachine<C: ?Sized, S: ?Sized> {
+    state_stack: Vec<Box<dyn SplitState<C, S>>>,
+}
+
+impl<C: ?Sized, S: ?Sized> Default for SplitStateMachine<C, S> {
+    fn default() -> Self {
+        Self {
+            state_stack: Vec::default(),
+        }
+    }
+}
+
+impl<C: ?Sized, S: ?Sized> SplitStateMachine<C, S> {
+    /// Returns if the state machine still has states in its stack.
+    pub fn is_running(&self) -> bool {
+        !self.state_stack.is_empty()
+    }
+
+    /// Returns the number of states on the stack.
+    pub fn len(&self) -> usize {
+        self.state_stack.len()
+    }
+
+    /// Returns if there are no states on the stack.
+    pub fn is_empty(&self) -> bool {
+        self.state_stack.is_empty()
+    }
+
+    /// Updates the state at the top of the stack with the provided context and data.
+    /// If the states returns a transition, perform it.
+    /// Returns the status of the state machine after the update.
+    pub fn update_with(&mut self, context: &C, state_data: &mut S) -> MachineStatus {
+        let trans = match self.state_stack.last_mut() {
+            Some(state) => state.update(context, state_data),
+            None => SplitTransition::None,
+        };
+
+        let mut last = trans.kind();
+        let mut pending = vec![trans];
+        while let Some(trans) = pending.pop() {
+            match trans {
+                SplitTransition::None => (),
+                SplitTransition::Pop => self.pop_n(1, context, state_data),
+                SplitTransition::PopN(n) => self.pop_n(n, context, state_data),
+                SplitTransition::Push(state) => self.push(state, context, state_data),
+                SplitTransition::Switch(state) => self.switch(state, context, state_data),
+                SplitTransition::Replace(state) => {
+                    if let Some(mut top) = self.state_stack.pop() {
+                        top.on_stop(context, state_data);
+                    }
+                    self.start(state, context, state_data);
+                }
+                SplitTransition::Quit => {
+                    self.stop(context, state_data);
+                    pending.clear();
+                    last = Some(TransitionKind::Quit);
+                }
+                SplitTransition::Sequence(transitions) => {
+                    pending.extend(transitions.into_iter().rev());
+                }
+            }
+        }
+
+        if self.is_running() {
+            match last {
+                Some(kind) => MachineStatus::Transitioned(kind),
+                None => MachineStatus::Running,
+            }
+        } else if last == Some(TransitionKind::Quit) {
+            MachineStatus::Stopped(StopReason::Quit)
+        } else {
+            MachineStatus::Stopped(StopReason::EmptyStack)
+        }
+    }
+
+    /// Push a state on the stack and start it.
+    /// Pauses any previously active state.
+    pub fn push(&mut self, state: Box<dyn SplitState<C, S>>, context: &C, state_data: &mut S) {
+        if let Some(top) = self.state_stack.last_mut() {
+            top.on_pause(context, state_data);
+        }
+
+        self.start(state, context, state_data);
+    }
+
+    /// Stops the state on top of the stack and removes it, then resumes the state below it.
+    /// Returns `false` and does nothing if the stack is empty.
+    pub fn pop(&mut self, context: &C, state_data: &mut S) -> bool {
+        let popped = self.is_running();
+        self.pop_n(1, context, state_data);
+        popped
+    }
+
+    /// Stops all the states on the stack, top-down, then pushes this state and starts it.
+    pub fn switch(&mut self, state: Box<dyn SplitState<C, S>>, context: &C, state_data: &mut S) {
+        self.stop(context, state_data);
+        self.start(state, context, state_data);
+    }
+
+    /// Removes all currently running states from the stack.
+    pub fn stop(&mut self, context: &C, state_data: &mut S) {
+        while let Some(mut state) = self.state_stack.pop() {
+            state.on_stop(context, state_data);
+        }
+    }
+
+    fn start(&mut self, mut state: Box<dyn SplitState<C, S>>, context: &C, state_data: &mut S) {
+        state.on_start(context, state_data);
+        self.state_stack.push(state);
+    }
+
+    fn pop_n(&mut self, n: usize, context: &C, state_data: &mut S) {
+        if n == 0 {
+            return;
+        }
+
+        for _ in 0..n {
+            match self.state_stack.pop() {
+                Some(mut state) => state.on_stop(context, state_data),
+                None => break,
+            }
+        }
+
+        if let Some(top) = self.state_stack.last_mut() {
+            top.on_resume(context, state_data);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    struct Config {
+        gravity: i32,
+    }
+
+    #[derive(Default)]
+    struct World {
+        height: i32,
+        log: Vec<&'static str>,
+    }
+
+    /// Falls by the gravity of the configuration, then pauses when it hits the ground.
+    struct Falling;
+
+    impl SplitState<Config, World> for Falling {
+        fn update(&mut self, config: &Config, world: &mut World) -> SplitTransition<Config, World> {
+            world.height -= config.gravity;
+            if world.height <= 0 {
+                SplitTransition::Push(Box::new(Unsplit(Box::new(Landed))))
+            } else {
+                SplitTransition::None
+            }
+        }
+
+        fn on_resume(&mut self, _config: &Config, world: &mut World) {
+            world.log.push("falling:resume");
+        }
+    }
+
+    /// A state which does not need the context.
+    struct Landed;
+
+    impl State<World> for Landed {
+        fn on_start(&mut self, world: &mut World) -> StateTransition<World> {
+            world.log.push("landed:start");
+            StateTransition::None
+        }
+
+        fn update(&mut self, _world: &mut World) -> StateTransition<World> {
+            StateTransition::Pop
+        }
+    }
+
+    #[test]
+    fn context_and_data() {
+        let config = Config { gravity: 4 };
+        let mut world = World {
+            height: 10,
+            ..World::default()
+        };
+        let mut sm = SplitStateMachine::default();
+        sm.push(Box::new(Falling), &config, &mut world);
+        sm.update_with(&config, &mut world);
+        assert_eq!(world.height, 6);
+        sm.update_with(&config, &mut world);
+        assert_eq!(
+            sm.update_with(&config, &mut world),
+            MachineStatus::Transitioned(TransitionKind::Push)
+        );
+        assert_eq!(sm.len(), 2);
+        sm.update_with(&config, &mut world);
+        assert_eq!(world.height, -2);
+        assert_eq!(world.log, ["landed:start", "falling:resume"]);
+    }
+
+    #[test]
+    fn unsplit_states() {
+        let mut world = World::default();
+        let mut sm = SplitStateMachine::<(), World>::default();
+        sm.push(Box::new(Unsplit(Box::new(Landed))), &(), &mut world);
+        assert_eq!(
+            sm.update_with(&(), &mut world),
+            MachineStatus::Stopped(StopReason::EmptyStack)
+        );
+        assert_eq!(world.log, ["landed:start"]);
+    }
+}
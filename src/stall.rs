@@ -0,0 +1,219 @@
+//! Diagnosing an active state stuck returning no transition, like a loading screen waiting for a
+//! flag that is never set.
+
+use crate::{Allocator, MachineEvent, ScopedStateMachine, StateTransition, TransitionKind};
+
+/// Called with the name of a stalled state and the number of updates it stalled for.
+pub type StallCallback = Box<maybe_send!(dyn FnMut(&str, u64))>;
+
+/// Builds the transition performed when a state stalls, with the name of the state.
+pub type StallTransition<S, E = ()> = Box<maybe_send!(dyn FnMut(&str) -> StateTransition<S, E>)>;
+
+/// What a state machine does when its active state stalls, see
+/// `StateMachine::set_stall_threshold`. The stall is logged with the `log` feature and
+/// published as `MachineEvent::Stalled` whatever the action.
+pub enum StallAction<S: ?Sized, E = ()> {
+    /// Nothing more.
+    Warn,
+    /// Calls the closure.
+    Callback(StallCallback),
+    /// Performs the transition built by the closure, like a switch back to the main menu.
+    Transition(StallTransition<S, E>),
+}
+
+/// The stall detector of a state machine.
+pub(crate) struct Stall<S: ?Sized, E> {
+    threshold: u64,
+    action: StallAction<S, E>,
+}
+
+impl<'a, S: ?Sized, E, A: Allocator> ScopedStateMachine<'a, S, E, A> {
+    /// Detects an active state stalling: once its `update` returned `StateTransition::None`
+    /// this many times in a row, the action is taken, once. Replaces the previous threshold.
+    ///
+    /// The count of the state starts over when it returns another transition, when a
+    /// transition is performed and when the state is paused or resumed, so the action is taken
+    /// again if it stalls again. Only the updates of the top state are counted, not its shadow
+    /// and fixed updates, nor the updates of the states below a transparent one.
+    ///
+    /// ## Panics
+    /// Panics if the threshold is zero.
+    pub fn set_stall_threshold(&mut self, updates: u64, action: StallAction<S, E>) {
+        assert!(updates > 0, "The stall threshold must not be zero.");
+        self.stall = Some(Stall {
+            threshold: updates,
+            action,
+        });
+    }
+
+    /// Stops detecting stalled states, dropping the action.
+    pub fn clear_stall_threshold(&mut self) {
+        self.stall = None;
+    }
+
+    /// Returns if the updates of the top state are counted, see `set_stall_threshold`.
+    pub(crate) fn detects_stalls(&self) -> bool {
+        self.stall.is_some()
+    }
+
+    /// Takes the stall action if the top state just reached the threshold.
+    /// Returns the kind of the transition it performed, if any.
+    pub(crate) fn check_stall(&mut self, state_data: &mut S) -> Option<TransitionKind> {
+        let entry = self.state_stack.last()?;
+        let mut stall = self.stall.take()?;
+        let kind = if entry.stalled == stall.threshold {
+            let name = entry.state.as_ref().name().to_string();
+            let updates = entry.stalled;
+            log_warn!(
+                "the state {} returned no transition for {} updates",
+                name,
+                updates
+            );
+            self.emit(|_| MachineEvent::Stalled {
+                name: name.clone(),
+                updates,
+            });
+            match &mut stall.action {
+                StallAction::Warn => None,
+                StallAction::Callback(callback) => {
+                    callback(&name, updates);
+                    None
+                }
+                StallAction::Transition(build) => {
+                    let trans = build(&name);
+                    self.stall = Some(stall);
+                    return self.perform(trans, state_data);
+                }
+            }
+        } else {
+            None
+        };
+        self.stall = Some(stall);
+        kind
+    }
+
+    /// Starts the count of the top state over, after a transition.
+    pub(crate) fn reset_stall(&mut self) {
+        if let Some(entry) = self.state_stack.last_mut() {
+            entry.stalled = 0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use std::sync::{Arc, Mutex};
+
+    /// Waits for the flag of the state data, returning a transition once it is set.
+    struct Loading;
+
+    impl State<bool> for Loading {
+        fn name(&self) -> &str {
+            "loading"
+        }
+
+        fn update(&mut self, loaded: &mut bool) -> StateTransition<bool> {
+            if *loaded {
+                *loaded = false;
+                StateTransition::Push(Box::new(Popup))
+            } else {
+                StateTransition::None
+            }
+        }
+    }
+
+    struct Popup;
+
+    impl State<bool> for Popup {
+        fn update(&mut self, _: &mut bool) -> StateTransition<bool> {
+            StateTransition::Pop
+        }
+    }
+
+    struct Menu;
+
+    impl State<bool> for Menu {
+        fn name(&self) -> &str {
+            "menu"
+        }
+    }
+
+    fn watched(stalls: &Arc<Mutex<Vec<(String, u64)>>>) -> StateMachine<bool> {
+        let mut sm = StateMachine::<bool>::default();
+        let seen = stalls.clone();
+        sm.set_stall_threshold(
+            3,
+            StallAction::Callback(Box::new(move |name, updates| {
+                seen.lock().unwrap().push((name.to_string(), updates));
+            })),
+        );
+        sm
+    }
+
+    #[test]
+    fn fires_once_per_stall() {
+        let stalls = Arc::new(Mutex::new(Vec::new()));
+        let mut sm = watched(&stalls);
+        let mut loaded = false;
+        sm.push(Box::new(Loading), &mut loaded);
+        sm.update_n(10, &mut loaded);
+        assert_eq!(*stalls.lock().unwrap(), [("loading".to_string(), 3)]);
+
+        // Returning a transition, here pushing a popup that pops itself, starts over.
+        loaded = true;
+        sm.update(&mut loaded);
+        sm.update_n(3, &mut loaded);
+        assert_eq!(stalls.lock().unwrap().len(), 1);
+        sm.update(&mut loaded);
+        assert_eq!(stalls.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn resets() {
+        let stalls = Arc::new(Mutex::new(Vec::new()));
+        let mut sm = watched(&stalls);
+        let mut loaded = false;
+        sm.push(Box::new(Loading), &mut loaded);
+
+        // Pausing and resuming the state.
+        sm.update_n(2, &mut loaded);
+        sm.freeze(&mut loaded);
+        sm.thaw(&mut loaded);
+        sm.update_n(2, &mut loaded);
+        assert!(stalls.lock().unwrap().is_empty());
+
+        // A transition below the state.
+        sm.queue_transition(StateTransition::PushBottom(Box::new(Menu)));
+        sm.update_n(2, &mut loaded);
+        assert!(stalls.lock().unwrap().is_empty());
+        sm.update(&mut loaded);
+        assert_eq!(stalls.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn fallback_transition() {
+        let mut sm = StateMachine::<bool>::default();
+        sm.set_stall_threshold(
+            2,
+            StallAction::Transition(Box::new(|_| StateTransition::Switch(Box::new(Menu)))),
+        );
+        let events = sm.subscribe();
+        let mut loaded = false;
+        sm.push(Box::new(Loading), &mut loaded);
+        sm.update(&mut loaded);
+        assert_eq!(
+            sm.update(&mut loaded),
+            MachineStatus::Transitioned(TransitionKind::Switch)
+        );
+        assert_eq!(sm.active_name(), Some("menu"));
+        let stalled = std::iter::from_fn(|| events.try_recv()).any(|event| {
+            event
+                == MachineEvent::Stalled {
+                    name: "loading".to_string(),
+                    updates: 2,
+                }
+        });
+        assert!(stalled);
+    }
+}
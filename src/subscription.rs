@@ -0,0 +1,306 @@
+//! Streaming what the state machine does to other parts of the game, like the audio or the
+//! analytics, which poll the events on their own schedule instead of being called back.
+
+use crate::requester::lock;
+use crate::{Allocator, ExitReason, ScopedStateMachine, TransitionKind};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+/// Something that happened on a state machine, received through `MachineEventReceiver`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum MachineEvent {
+    /// A state was started while the machine was not running.
+    Started,
+    /// A state was started and pushed.
+    StatePushed {
+        /// The name of the state.
+        name: String,
+        /// The number of states on the stack once it was pushed.
+        depth: usize,
+    },
+    /// A state of the stack was paused.
+    StatePaused {
+        /// The name of the state.
+        name: String,
+    },
+    /// A state of the stack was resumed.
+    StateResumed {
+        /// The name of the state.
+        name: String,
+    },
+    /// A state was stopped and removed from the stack.
+    StatePopped {
+        /// The name of the state.
+        name: String,
+    },
+    /// The states were switched, once the new state is started and the old ones are stopped.
+    Switched {
+        /// The name of the active state before the switch.
+        from: Option<String>,
+        /// The name of the active state after the switch.
+        to: Option<String>,
+    },
+    /// The active state returned no transition for this many updates in a row, see
+    /// `StateMachine::set_stall_threshold`.
+    Stalled {
+        /// The name of the state.
+        name: String,
+        /// The number of updates.
+        updates: u64,
+    },
+    /// A transition or an error returned by a state was dropped for another one returned in the
+    /// same update, see `StateMachine::set_transition_resolution`.
+    TransitionDropped {
+        /// The name of the state which returned it.
+        name: String,
+        /// The kind of the transition, or `None` for an error or a message.
+        kind: Option<TransitionKind>,
+    },
+    /// The machine stopped running.
+    Stopped {
+        /// How it stopped.
+        reason: ExitReason,
+    },
+}
+
+/// The events received by one subscriber and not yet consumed.
+struct Buffer {
+    events: VecDeque<MachineEvent>,
+    capacity: usize,
+    overflowed: u64,
+}
+
+type SharedBuffer = Arc<Mutex<Buffer>>;
+
+/// The receiving end of a subscription to a state machine, see `StateMachine::subscribe`.
+/// It does not borrow the machine, and can be sent to another thread.
+pub struct MachineEventReceiver {
+    buffer: SharedBuffer,
+}
+
+impl MachineEventReceiver {
+    /// Takes the oldest event not yet consumed, without waiting for one.
+    pub fn try_recv(&self) -> Option<MachineEvent> {
+        lock(&self.buffer).events.pop_front()
+    }
+
+    /// Returns the number of events not yet consumed.
+    pub fn pending(&self) -> usize {
+        lock(&self.buffer).events.len()
+    }
+
+    /// Returns the number of events dropped because the buffer was full.
+    pub fn overflowed(&self) -> u64 {
+        lock(&self.buffer).overflowed
+    }
+}
+
+/// The buffers of the subscribers of a state machine.
+#[derive(Default)]
+pub(crate) struct Subscribers {
+    buffers: Vec<SharedBuffer>,
+    /// If `Started` was emitted and not `Stopped` yet.
+    running: bool,
+}
+
+impl Subscribers {
+    fn emit(&mut self, event: MachineEvent) {
+        // The buffers of the dropped receivers are only held by the machine.
+        self.buffers.retain(|buffer| Arc::strong_count(buffer) > 1);
+        for buffer in &self.buffers {
+            let mut buffer = lock(buffer);
+            if buffer.events.len() >= buffer.capacity {
+                buffer.overflowed += 1;
+                if buffer.events.pop_front().is_none() {
+                    continue;
+                }
+            }
+            buffer.events.push_back(event.clone());
+        }
+    }
+}
+
+impl<'a, S: ?Sized, E, A: Allocator> ScopedStateMachine<'a, S, E, A> {
+    /// Subscribes to the events of this machine, keeping the last 64 events until they are
+    /// consumed, see `subscribe_with_capacity`.
+    pub fn subscribe(&mut self) -> MachineEventReceiver {
+        self.subscribe_with_capacity(64)
+    }
+
+    /// Subscribes to the events of this machine, keeping up to `capacity` events until they are
+    /// consumed. When the buffer is full, the oldest event is dropped to make room for the new
+    /// one, and counted by `MachineEventReceiver::overflowed`.
+    ///
+    /// Each subscriber gets all the events emitted after it subscribed. The pushes, pauses,
+    /// resumes and pops are emitted as they happen, and `Stopped` is emitted once the operation
+    /// or transition that emptied the stack is done, so a switch does not stop the machine.
+    /// The events are only built while a receiver is alive.
+    pub fn subscribe_with_capacity(&mut self, capacity: usize) -> MachineEventReceiver {
+        let buffer = Arc::new(Mutex::new(Buffer {
+            events: VecDeque::with_capacity(capacity.min(64)),
+            capacity,
+            overflowed: 0,
+        }));
+        self.subscribers.running = self.is_running();
+        self.subscribers.buffers.push(Arc::clone(&buffer));
+        MachineEventReceiver { buffer }
+    }
+
+    /// Returns if a subscriber may receive events.
+    /// The JSON lines are written from the events, see `set_instrument_writer`.
+    pub(crate) fn subscribed(&self) -> bool {
+        #[cfg(feature = "instrument-json")]
+        if self.instrument.is_some() {
+            return true;
+        }
+        !self.subscribers.buffers.is_empty()
+    }
+
+    /// Remembers if the machine is running, before it starts emitting events.
+    #[cfg(feature = "instrument-json")]
+    pub(crate) fn track_running(&mut self) {
+        if !self.subscribed() {
+            self.subscribers.running = self.is_running();
+        }
+    }
+
+    /// Emits the event built by this function, if the machine has subscribers.
+    pub(crate) fn emit(&mut self, event: impl FnOnce(&Self) -> MachineEvent) {
+        if self.subscribed() {
+            let event = event(self);
+            self.publish(event);
+        }
+    }
+
+    fn publish(&mut self, event: MachineEvent) {
+        #[cfg(feature = "instrument-json")]
+        self.instrument_event(&event);
+        self.subscribers.emit(event);
+    }
+
+    /// Emits `Started` before the first push while the machine is not running.
+    pub(crate) fn emit_pushed(&mut self) {
+        if !self.subscribed() {
+            return;
+        }
+        if !std::mem::replace(&mut self.subscribers.running, true) {
+            self.publish(MachineEvent::Started);
+        }
+        let top = self.state_stack.len();
+        let name = self.state_stack[top - 1].state.name().to_string();
+        self.publish(MachineEvent::StatePushed { name, depth: top });
+    }
+
+    /// Emits `Stopped` if the machine stopped running since `Started` was emitted.
+    pub(crate) fn emit_stopped(&mut self) {
+        if self.subscribers.running && !self.is_running() {
+            self.subscribers.running = false;
+            let reason = self.exit_reason.unwrap_or(ExitReason::LastStatePopped);
+            self.publish(MachineEvent::Stopped { reason });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    struct Scene(&'static str, Option<StateTransition<()>>);
+
+    impl State<()> for Scene {
+        fn name(&self) -> &str {
+            self.0
+        }
+
+        fn update(&mut self, _: &mut ()) -> StateTransition<()> {
+            self.1.take().unwrap_or(StateTransition::None)
+        }
+    }
+
+    fn received(receiver: &MachineEventReceiver) -> Vec<MachineEvent> {
+        std::iter::from_fn(|| receiver.try_recv()).collect()
+    }
+
+    fn name(name: &str) -> String {
+        name.to_string()
+    }
+
+    #[test]
+    fn scripted_flow() {
+        let mut sm = StateMachine::<()>::default();
+        let receiver = sm.subscribe();
+        sm.push(Box::new(Scene("title", None)), &mut ());
+        sm.push(Box::new(Scene("menu", Some(StateTransition::Pop))), &mut ());
+        sm.update(&mut ());
+        sm.switch(
+            Box::new(Scene("game", Some(StateTransition::Quit))),
+            &mut (),
+        );
+        sm.update(&mut ());
+
+        assert_eq!(
+            received(&receiver),
+            [
+                MachineEvent::Started,
+                MachineEvent::StatePushed {
+                    name: name("title"),
+                    depth: 1
+                },
+                MachineEvent::StatePaused {
+                    name: name("title")
+                },
+                MachineEvent::StatePushed {
+                    name: name("menu"),
+                    depth: 2
+                },
+                MachineEvent::StatePopped { name: name("menu") },
+                MachineEvent::StateResumed {
+                    name: name("title")
+                },
+                MachineEvent::StatePopped {
+                    name: name("title")
+                },
+                MachineEvent::StatePushed {
+                    name: name("game"),
+                    depth: 1
+                },
+                MachineEvent::Switched {
+                    from: Some(name("title")),
+                    to: Some(name("game"))
+                },
+                MachineEvent::StatePopped { name: name("game") },
+                MachineEvent::Stopped {
+                    reason: ExitReason::Quit
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn bounded_buffers() {
+        let mut sm = StateMachine::<()>::default();
+        let small = sm.subscribe_with_capacity(2);
+        let large = sm.subscribe();
+        sm.push(Box::new(Scene("title", None)), &mut ());
+        sm.pop(&mut ());
+        assert_eq!(small.overflowed(), 2);
+        assert_eq!(
+            received(&small),
+            [
+                MachineEvent::StatePopped {
+                    name: name("title")
+                },
+                MachineEvent::Stopped {
+                    reason: ExitReason::LastStatePopped
+                },
+            ]
+        );
+        assert_eq!(large.pending(), 4);
+        assert_eq!(large.overflowed(), 0);
+
+        drop(large);
+        sm.push(Box::new(Scene("title", None)), &mut ());
+        assert_eq!(small.pending(), 2);
+    }
+}
@@ -0,0 +1,250 @@
+//! History states, like in statecharts: leaving the states of a part of the game, like the
+//! inventory over the map over the overworld, and coming back to exactly these states later.
+//!
+//! This is unrelated to the history of the transitions, see `StateMachine::enable_history`.
+
+use crate::{
+    Allocator, ExitReason, Global, PauseReason, StackEntry, State, StateBox, StateMachine,
+};
+
+/// What `StateMachine::save_history` does with the states it takes off the stack.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum HistoryMode {
+    /// The states are paused and kept as they are, with their handles, and resumed when
+    /// restored.
+    Keep,
+    /// The states are stopped, and started again when restored.
+    Stop,
+}
+
+enum Saved<'a, S: ?Sized, E, A: Allocator> {
+    Kept(Vec<StackEntry<'a, S, E, A>>),
+    Stopped(Vec<StateBox<'a, S, E, A>>),
+}
+
+/// The states taken off the stack by `StateMachine::save_history`, from the bottom to the top,
+/// to put back with `StateMachine::restore_history`.
+/// Dropping it drops the states without stopping them.
+pub struct HistoryToken<S: ?Sized, E = (), A: Allocator = Global> {
+    saved: Saved<'static, S, E, A>,
+}
+
+impl<S: ?Sized, E, A: Allocator> HistoryToken<S, E, A> {
+    /// Returns the number of saved states.
+    pub fn len(&self) -> usize {
+        match &self.saved {
+            Saved::Kept(entries) => entries.len(),
+            Saved::Stopped(states) => states.len(),
+        }
+    }
+
+    /// Returns if no state was saved.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the names of the saved states, from the bottom to the top.
+    pub fn names(&self) -> Vec<&str> {
+        match &self.saved {
+            Saved::Kept(entries) => entries
+                .iter()
+                .map(|entry| entry.state.as_ref().name())
+                .collect(),
+            Saved::Stopped(states) => states.iter().map(|state| state.as_ref().name()).collect(),
+        }
+    }
+}
+
+impl<S: ?Sized, E, A: Allocator> StateMachine<S, E, A> {
+    /// Takes the states from this depth to the top of the stack off it, 0 being the bottom, to
+    /// put them back later with `restore_history`. The state that ends up on top of the stack is
+    /// resumed. If no state is at this depth, nothing is saved.
+    ///
+    /// With `HistoryMode::Keep`, the visible states are paused first, top-down, and the states
+    /// keep their handles, although they are not alive until restored. With `HistoryMode::Stop`,
+    /// the states are stopped top-down, like with a `PopN`.
+    /// Saving all the states stops the machine, with `ExitReason::StoppedExternally` if they are
+    /// kept.
+    pub fn save_history(
+        &mut self,
+        depth: usize,
+        mode: HistoryMode,
+        state_data: &mut S,
+    ) -> HistoryToken<S, E, A> {
+        let token = self.take_history(depth, mode, state_data);
+        self.resume_visible(state_data);
+        if self.state_stack.is_empty() && self.exit_reason.is_none() {
+            self.exit_reason = Some(ExitReason::StoppedExternally);
+        }
+        self.settle(state_data);
+        token
+    }
+
+    /// Saves the states a `Switch` would stop, keeping them, then pushes this state, so the
+    /// states are restored once it is done, like a system menu opened over the gameplay.
+    pub fn switch_with_history(
+        &mut self,
+        state: Box<dyn State<S, E>>,
+        state_data: &mut S,
+    ) -> HistoryToken<S, E, A> {
+        let token = self.take_history(self.switched_from(), HistoryMode::Keep, state_data);
+        self.push_boxed(state, state_data);
+        self.settle(state_data);
+        token
+    }
+
+    /// Puts the saved states back on top of the stack, in their order.
+    /// The kept states are resumed when visible, after the states they cover are paused, while
+    /// the stopped states are pushed one by one, being started again.
+    pub fn restore_history(&mut self, token: HistoryToken<S, E, A>, state_data: &mut S) {
+        match token.saved {
+            Saved::Kept(entries) => {
+                if !entries.is_empty() {
+                    self.exit_reason = None;
+                }
+                self.state_stack.extend(entries);
+                self.refresh_visible(state_data);
+            }
+            Saved::Stopped(states) => {
+                for state in states {
+                    self.cover(state, false, state_data);
+                }
+                self.settle(state_data);
+            }
+        }
+    }
+
+    fn take_history(
+        &mut self,
+        depth: usize,
+        mode: HistoryMode,
+        state_data: &mut S,
+    ) -> HistoryToken<S, E, A> {
+        let depth = depth.min(self.state_stack.len());
+        let saved = match mode {
+            HistoryMode::Keep => {
+                for index in (self.visible_start().max(depth)..self.state_stack.len()).rev() {
+                    if !self.state_stack[index].paused {
+                        self.pause_at(index, PauseReason::Covered, state_data);
+                    }
+                }
+                let depth = depth.min(self.state_stack.len());
+                Saved::Kept(self.state_stack.drain(depth..).collect())
+            }
+            HistoryMode::Stop => {
+                let mut states = Vec::new();
+                while self.state_stack.len() > depth {
+                    let entry = self.state_stack.pop().expect("The stack is deeper.");
+                    states.extend(self.stop_keeping(entry, state_data));
+                }
+                states.reverse();
+                Saved::Stopped(states)
+            }
+        };
+        HistoryToken { saved }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    type Log = Vec<String>;
+
+    struct Scene(&'static str);
+
+    impl State<Log> for Scene {
+        fn name(&self) -> &str {
+            self.0
+        }
+
+        fn on_start(&mut self, log: &mut Log) -> StateTransition<Log> {
+            log.push(format!("{}:start", self.0));
+            StateTransition::None
+        }
+
+        fn on_pause(&mut self, log: &mut Log) {
+            log.push(format!("{}:pause", self.0));
+        }
+
+        fn on_resume(&mut self, log: &mut Log) -> StateTransition<Log> {
+            log.push(format!("{}:resume", self.0));
+            StateTransition::None
+        }
+
+        fn on_stop(&mut self, log: &mut Log) -> StateTransition<Log> {
+            log.push(format!("{}:stop", self.0));
+            StateTransition::None
+        }
+    }
+
+    fn names(sm: &StateMachine<Log>) -> Vec<&str> {
+        sm.iter().map(|state| state.name()).collect()
+    }
+
+    #[test]
+    fn switch_and_restore() {
+        let mut log = Log::new();
+        let mut sm = StateMachine::<Log>::default();
+        sm.push_state(Scene("overworld"), &mut log);
+        let map = sm.push_state(Scene("map"), &mut log);
+        sm.push_state(Scene("inventory"), &mut log);
+        log.clear();
+
+        let token = sm.switch_with_history(Box::new(Scene("menu")), &mut log);
+        assert_eq!(token.names(), ["overworld", "map", "inventory"]);
+        assert_eq!(log, ["inventory:pause", "menu:start"]);
+        assert_eq!(names(&sm), ["menu"]);
+        assert!(!sm.is_alive(map));
+
+        log.clear();
+        sm.transition(
+            StateTransition::Custom(Box::new(move |sm, log| {
+                sm.pop(log);
+                sm.restore_history(token, log);
+            })),
+            &mut log,
+        );
+        assert_eq!(log, ["menu:stop", "inventory:resume"]);
+        assert_eq!(names(&sm), ["overworld", "map", "inventory"]);
+        assert!(sm.is_alive(map));
+        assert!(sm.is_running());
+    }
+
+    #[test]
+    fn stopped_and_started_again() {
+        let mut log = Log::new();
+        let mut sm = StateMachine::<Log>::default();
+        sm.push_state(Scene("overworld"), &mut log);
+        sm.push_state(Scene("map"), &mut log);
+        sm.push_state(Scene("inventory"), &mut log);
+        log.clear();
+
+        let token = sm.save_history(1, HistoryMode::Stop, &mut log);
+        assert_eq!(token.len(), 2);
+        assert_eq!(log, ["inventory:stop", "map:stop", "overworld:resume"]);
+        assert!(sm.save_history(3, HistoryMode::Keep, &mut log).is_empty());
+
+        log.clear();
+        sm.restore_history(token, &mut log);
+        assert_eq!(
+            log,
+            [
+                "overworld:pause",
+                "map:start",
+                "map:pause",
+                "inventory:start"
+            ]
+        );
+        assert_eq!(names(&sm), ["overworld", "map", "inventory"]);
+
+        log.clear();
+        let token = sm.save_history(0, HistoryMode::Keep, &mut log);
+        assert_eq!(log, ["inventory:pause"]);
+        assert_eq!(sm.exit_reason(), Some(ExitReason::StoppedExternally));
+        log.clear();
+        sm.restore_history(token, &mut log);
+        assert_eq!(log, ["inventory:resume"]);
+        assert_eq!(sm.exit_reason(), None);
+    }
+}
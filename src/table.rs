@@ -0,0 +1,298 @@
+//! Restricting the states each state can go to, to catch the transitions a state should never
+//! return, like pushing the pause menu over the credits.
+
+use crate::{
+    Allocator, ScopedStateMachine, State, StateTransition, TransitionError, TransitionKind,
+};
+use std::collections::{HashMap, HashSet};
+
+/// What a state machine does with a transition its `TransitionTable` does not allow, or that
+/// goes over its depth limit, see `StateMachine::set_max_depth`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ViolationPolicy {
+    /// Panics in debug builds, and drops the transition like `Block` in release builds.
+    Panic,
+    /// Drops the transition, as if the state had returned `StateTransition::None`, and logs it
+    /// with the `log` feature.
+    Block,
+    /// Drops the transition and gives its error to the states, like a rejection of the
+    /// transition check, see `StateMachine::set_transition_check`.
+    Report,
+}
+
+/// The transitions allowed from a state of a `TransitionTable`.
+#[derive(Clone)]
+struct Rule {
+    targets: HashSet<String>,
+    pop: bool,
+    quit: bool,
+}
+
+impl Default for Rule {
+    fn default() -> Self {
+        Self {
+            targets: HashSet::new(),
+            pop: true,
+            quit: true,
+        }
+    }
+}
+
+/// The states each state is allowed to go to, by name, see `State::name`.
+///
+/// A state listed in the table may only start the states it is allowed to go to, by pushing,
+/// switching, replacing or inserting them. It may pop and quit unless this is forbidden.
+/// The states which are not listed are not restricted, and neither are the states built when
+/// the transition is performed, like the ones of `PushWith`, except for `PushNamed`.
+///
+/// ```rust,ignore
+/// let table = TransitionTable::new()
+///     .allow("menu", &["game", "options"])
+///     .allow("game", &["pause"])
+///     .allow("pause", &["options"])
+///     .forbid_quit("game")
+///     .on_violation(ViolationPolicy::Block);
+/// state_machine.set_transition_table(table);
+/// ```
+#[derive(Clone)]
+pub struct TransitionTable {
+    rules: HashMap<String, Rule>,
+    policy: ViolationPolicy,
+}
+
+impl Default for TransitionTable {
+    fn default() -> Self {
+        Self {
+            rules: HashMap::new(),
+            policy: ViolationPolicy::Report,
+        }
+    }
+}
+
+impl TransitionTable {
+    /// Creates a table restricting no state, which reports the transitions it does not allow.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Allows the state named `from` to go to these states, on top of the ones it was already
+    /// allowed to go to.
+    pub fn allow(mut self, from: &str, to: &[&str]) -> Self {
+        let rule = self.rules.entry(from.to_string()).or_default();
+        rule.targets.extend(to.iter().map(|name| name.to_string()));
+        self
+    }
+
+    /// Forbids the state named `from` to pop itself, with any of the pop transitions.
+    pub fn forbid_pop(mut self, from: &str) -> Self {
+        self.rules.entry(from.to_string()).or_default().pop = false;
+        self
+    }
+
+    /// Forbids the state named `from` to quit the state machine.
+    pub fn forbid_quit(mut self, from: &str) -> Self {
+        self.rules.entry(from.to_string()).or_default().quit = false;
+        self
+    }
+
+    /// Sets what the state machine does with the transitions the table does not allow.
+    /// Defaults to `ViolationPolicy::Report`.
+    pub fn on_violation(mut self, policy: ViolationPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Returns what the state machine does with the transitions the table does not allow.
+    pub fn policy(&self) -> ViolationPolicy {
+        self.policy
+    }
+
+    /// Returns if the state named `from` is allowed to go to the state named `to`.
+    pub fn allows(&self, from: &str, to: &str) -> bool {
+        match self.rules.get(from) {
+            Some(rule) => rule.targets.contains(to),
+            None => true,
+        }
+    }
+
+    /// Returns the error of this transition, if the state named `from` is not allowed to
+    /// perform it.
+    pub fn check<S: ?Sized, E>(
+        &self,
+        from: &str,
+        request: &StateTransition<S, E>,
+    ) -> Result<(), TransitionError> {
+        let rule = match self.rules.get(from) {
+            Some(rule) => rule,
+            None => return Ok(()),
+        };
+        let not_allowed = |to: &str| TransitionError::NotAllowed {
+            from: from.to_string(),
+            to: to.to_string(),
+        };
+        match request {
+            StateTransition::Pop
+            | StateTransition::PopRetain
+            | StateTransition::PopWith(_)
+            | StateTransition::PopN(_)
+            | StateTransition::PopUntil(_)
+            | StateTransition::PopTagged(_)
+            | StateTransition::PopTo(_)
+            | StateTransition::PopGroup(_)
+                if !rule.pop =>
+            {
+                Err(TransitionError::KindNotAllowed {
+                    from: from.to_string(),
+                    kind: request.kind().unwrap_or(TransitionKind::Pop),
+                })
+            }
+            StateTransition::Quit | StateTransition::QuitWith(_) if !rule.quit => {
+                Err(TransitionError::KindNotAllowed {
+                    from: from.to_string(),
+                    kind: TransitionKind::Quit,
+                })
+            }
+            StateTransition::PushNamed(name) if !rule.targets.contains(name) => {
+                Err(not_allowed(name))
+            }
+            _ => match request
+                .states()
+                .iter()
+                .find(|state| !rule.targets.contains(state.as_ref().name()))
+            {
+                Some(state) => Err(not_allowed(state.as_ref().name())),
+                None => Ok(()),
+            },
+        }
+    }
+}
+
+impl<'a, S: ?Sized, E, A: Allocator> ScopedStateMachine<'a, S, E, A> {
+    /// Sets the table of the states each state is allowed to go to.
+    /// It is given the transitions allowed by the interceptor, before the transition check,
+    /// and like them it is not given the operations called on the machine, such as `push`.
+    /// A blocked transition makes no lifecycle calls.
+    pub fn set_transition_table(&mut self, table: TransitionTable) {
+        self.transition_table = Some(table);
+    }
+
+    /// Removes the transition table, so all states may go to any state.
+    pub fn clear_transition_table(&mut self) {
+        self.transition_table = None;
+    }
+
+    /// Returns the transition table of the state machine, if any.
+    pub fn transition_table(&self) -> Option<&TransitionTable> {
+        self.transition_table.as_ref()
+    }
+
+    /// Returns the error of this transition if the transition table does not allow it for the
+    /// active state, with the policy of the table.
+    pub(crate) fn violation(
+        &self,
+        request: &StateTransition<S, E>,
+    ) -> Option<(TransitionError, ViolationPolicy)> {
+        let table = self.transition_table.as_ref()?;
+        let active: &dyn State<S, E> = self.state_stack.last()?.state.as_ref();
+        let error = table.check(active.name(), request).err()?;
+        Some((error, table.policy()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    fn table() -> TransitionTable {
+        TransitionTable::new()
+            .allow("menu", &["game", "options"])
+            .allow("game", &["pause"])
+            .forbid_pop("game")
+            .forbid_quit("pause")
+    }
+
+    fn state(name: &str, calls: &CallLog, trans: StateTransition<()>) -> Box<dyn State<()>> {
+        Box::new(RecordingState::new(name, calls).returning(trans))
+    }
+
+    #[test]
+    fn rules() {
+        let table = table();
+        assert!(table.allows("menu", "options"));
+        assert!(!table.allows("menu", "pause"));
+        assert!(table.allows("credits", "pause"));
+        let calls = CallLog::default();
+        let pause = StateTransition::Push(state("pause", &calls, StateTransition::None));
+        assert!(table.check("game", &pause).is_ok());
+        assert_eq!(
+            table.check("menu", &pause),
+            Err(TransitionError::NotAllowed {
+                from: "menu".to_string(),
+                to: "pause".to_string(),
+            })
+        );
+        let many = StateTransition::PushMany(vec![
+            state("game", &calls, StateTransition::None),
+            state("pause", &calls, StateTransition::None),
+        ]);
+        assert!(table.check("menu", &many).is_err());
+        let named = StateTransition::<()>::PushNamed("options".to_string());
+        assert!(table.check("menu", &named).is_ok());
+        assert!(table.check("game", &named).is_err());
+        assert!(table.check("menu", &StateTransition::<()>::PopN(2)).is_ok());
+        assert_eq!(
+            table.check("game", &StateTransition::<()>::PopN(2)),
+            Err(TransitionError::KindNotAllowed {
+                from: "game".to_string(),
+                kind: TransitionKind::PopN,
+            })
+        );
+        assert!(table.check("game", &StateTransition::<()>::Quit).is_ok());
+        assert!(table.check("pause", &StateTransition::<()>::Quit).is_err());
+    }
+
+    #[test]
+    fn blocked() {
+        let calls = CallLog::default();
+        let mut sm = StateMachine::<()>::default();
+        sm.set_transition_table(table().on_violation(ViolationPolicy::Block));
+        let credits = StateTransition::Replace(state("credits", &calls, StateTransition::None));
+        sm.push(state("menu", &calls, credits), &mut ());
+        assert_calls(&calls, &[("menu", LifecycleCall::Start)]);
+
+        sm.update(&mut ());
+        assert_calls(&calls, &[("menu", LifecycleCall::Update)]);
+        assert_eq!(sm.active().map(|state| state.name()), Some("menu"));
+        assert!(sm.take_error().is_none());
+
+        // The operations called on the machine are not restricted.
+        sm.push(state("pause", &calls, StateTransition::None), &mut ());
+        assert_eq!(sm.active().map(|state| state.name()), Some("pause"));
+    }
+
+    #[test]
+    fn reported() {
+        let calls = CallLog::default();
+        let mut sm = StateMachine::<()>::default();
+        sm.set_transition_table(table());
+        sm.push(state("game", &calls, StateTransition::Pop), &mut ());
+        sm.update(&mut ());
+        assert!(!sm.is_running());
+        assert_eq!(
+            sm.take_error().map(|error| error.to_string()),
+            Some("the state game cannot perform a Pop transition".to_string())
+        );
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    #[should_panic(expected = "the state menu cannot go to the state pause")]
+    fn panics() {
+        let calls = CallLog::default();
+        let mut sm = StateMachine::<()>::default();
+        sm.set_transition_table(table().on_violation(ViolationPolicy::Panic));
+        let pause = StateTransition::Push(state("pause", &calls, StateTransition::None));
+        sm.push(state("menu", &calls, pause), &mut ());
+        sm.update(&mut ());
+    }
+}
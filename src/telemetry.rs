@@ -0,0 +1,429 @@
+//! Timing the callbacks of states, to find the ones causing frame hitches.
+
+use crate::{Allocator, Clock, DefaultClock, MaybeSend, ScopedStateMachine, StateHandle};
+#[cfg(not(feature = "send"))]
+use std::cell::RefCell;
+use std::collections::HashMap;
+#[cfg(not(feature = "send"))]
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// A callback of a state that is timed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LifecyclePhase {
+    /// `State::on_start`.
+    Start,
+    /// `State::on_stop`.
+    Stop,
+    /// `State::on_pause`.
+    Pause,
+    /// `State::on_resume`.
+    Resume,
+    /// `State::update` or `State::try_update`.
+    Update,
+    /// `State::fixed_update`.
+    FixedUpdate,
+}
+
+/// Receives how long the callbacks of states took, see `StateMachine::set_telemetry_sink`.
+pub trait TelemetrySink: MaybeSend {
+    /// Called after a callback of a state returned, or panicked with the `catch_unwind` feature.
+    fn record(&mut self, state_name: &str, phase: LifecyclePhase, duration: Duration);
+}
+
+/// Shares a sink with the state machine, to read its measurements while the machine runs.
+#[cfg(not(feature = "send"))]
+impl<T: TelemetrySink + ?Sized> TelemetrySink for Rc<RefCell<T>> {
+    fn record(&mut self, state_name: &str, phase: LifecyclePhase, duration: Duration) {
+        self.borrow_mut().record(state_name, phase, duration);
+    }
+}
+
+/// Shares a sink with the state machine, which can be read from other threads.
+/// A sink whose mutex was poisoned is given the measurements all the same.
+impl<T: TelemetrySink + ?Sized> TelemetrySink for Arc<Mutex<T>> {
+    fn record(&mut self, state_name: &str, phase: LifecyclePhase, duration: Duration) {
+        self.lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .record(state_name, phase, duration);
+    }
+}
+
+/// The durations measured for a callback, by powers of two of microseconds.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Histogram {
+    count: u64,
+    total: Duration,
+    max: Duration,
+    buckets: [u64; Histogram::BUCKETS],
+}
+
+impl Histogram {
+    /// The number of buckets. The last one holds all the durations of 2^14 µs and above.
+    pub const BUCKETS: usize = 16;
+
+    /// Returns the number of measured durations.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Returns the sum of the measured durations.
+    pub fn total(&self) -> Duration {
+        self.total
+    }
+
+    /// Returns the longest measured duration.
+    pub fn max(&self) -> Duration {
+        self.max
+    }
+
+    /// Returns the average of the measured durations, or zero if none were measured.
+    pub fn mean(&self) -> Duration {
+        match self.count {
+            0 => Duration::default(),
+            count => Duration::from_nanos((self.total.as_nanos() / count as u128) as u64),
+        }
+    }
+
+    /// Returns how many durations fell in each bucket.
+    /// The first bucket holds the durations under 1 µs, and bucket `i` the ones from 2^(i-1)
+    /// up to 2^i µs.
+    pub fn buckets(&self) -> &[u64; Histogram::BUCKETS] {
+        &self.buckets
+    }
+
+    fn add(&mut self, duration: Duration) {
+        self.count += 1;
+        self.total += duration;
+        self.max = self.max.max(duration);
+        let micros = duration.as_micros();
+        let bucket = (128 - micros.leading_zeros()) as usize;
+        self.buckets[bucket.min(Histogram::BUCKETS - 1)] += 1;
+    }
+}
+
+/// A sink keeping a histogram of the durations of each callback of each state.
+#[derive(Debug, Clone, Default)]
+pub struct HistogramSink {
+    histograms: HashMap<(String, LifecyclePhase), Histogram>,
+}
+
+impl HistogramSink {
+    /// Creates a sink without measurements.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the durations measured for this callback of the states with this name.
+    pub fn get(&self, state_name: &str, phase: LifecyclePhase) -> Option<&Histogram> {
+        self.histograms.get(&(state_name.to_string(), phase))
+    }
+
+    /// Iterates over the histograms, by state name and callback.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, LifecyclePhase, &Histogram)> + '_ {
+        self.histograms
+            .iter()
+            .map(|((name, phase), histogram)| (name.as_str(), *phase, histogram))
+    }
+
+    /// Removes all the measurements.
+    pub fn clear(&mut self) {
+        self.histograms.clear();
+    }
+}
+
+impl TelemetrySink for HistogramSink {
+    fn record(&mut self, state_name: &str, phase: LifecyclePhase, duration: Duration) {
+        self.histograms
+            .entry((state_name.to_string(), phase))
+            .or_default()
+            .add(duration);
+    }
+}
+
+/// A closure told about the updates over the budget, see `WatchdogAction::Callback`.
+pub type WatchdogCallback = Box<maybe_send!(dyn FnMut(&str, LifecyclePhase, Duration))>;
+
+/// What the machine does when the `update` or `fixed_update` of a state takes longer than the
+/// budget, see `StateMachine::set_update_budget`.
+pub enum WatchdogAction {
+    /// Logs a warning with the `log` feature.
+    Warn,
+    /// Calls the closure with the name of the state, the callback and how long it took.
+    Callback(WatchdogCallback),
+    /// Removes the state at the start of the next update, like `StateMachine::remove`.
+    /// The state is stopped, but it may have left the state data in the middle of something.
+    ForcePop,
+}
+
+/// The budget of the updates of the states.
+struct Watchdog {
+    budget: Duration,
+    action: WatchdogAction,
+    /// The states over the budget, to remove on the next update with `WatchdogAction::ForcePop`.
+    overruns: Vec<StateHandle>,
+}
+
+/// The sink and watchdog of a state machine, if any, with the clock timing the callbacks.
+pub(crate) struct Telemetry {
+    sink: Option<Box<dyn TelemetrySink>>,
+    watchdog: Option<Watchdog>,
+    clock: Box<dyn Clock>,
+}
+
+impl Default for Telemetry {
+    fn default() -> Self {
+        Self {
+            sink: None,
+            watchdog: None,
+            clock: Box::new(DefaultClock::default()),
+        }
+    }
+}
+
+impl Telemetry {
+    /// Returns the time read from the clock, see `StateMachine::set_clock`.
+    pub(crate) fn now(&self) -> Duration {
+        self.clock.now()
+    }
+
+    pub(crate) fn set_clock(&mut self, clock: Box<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    /// Returns when a callback starts, if it is timed.
+    pub(crate) fn start(&self) -> Option<Duration> {
+        self.sink.as_ref().map(|_| self.now())
+    }
+
+    /// Returns when an update starts, if it is timed or watched.
+    pub(crate) fn start_update(&self) -> Option<Duration> {
+        if self.sink.is_some() || self.watchdog.is_some() {
+            Some(self.now())
+        } else {
+            None
+        }
+    }
+
+    /// Gives the duration of an update that started at `start` to the sink, and to the watchdog
+    /// if it is over the budget.
+    pub(crate) fn record_update(
+        &mut self,
+        start: Option<Duration>,
+        handle: StateHandle,
+        state_name: &str,
+        phase: LifecyclePhase,
+    ) {
+        let duration = match start {
+            Some(start) => self.now().saturating_sub(start),
+            None => return,
+        };
+        if let Some(sink) = self.sink.as_mut() {
+            sink.record(state_name, phase, duration);
+        }
+        let watchdog = match self.watchdog.as_mut() {
+            Some(watchdog) if duration > watchdog.budget => watchdog,
+            _ => return,
+        };
+        match &mut watchdog.action {
+            WatchdogAction::Warn => {
+                log_warn!(
+                    "{:?} of {} took {:?}, over the budget of {:?}",
+                    phase,
+                    state_name,
+                    duration,
+                    watchdog.budget
+                );
+            }
+            WatchdogAction::Callback(callback) => callback(state_name, phase, duration),
+            WatchdogAction::ForcePop => watchdog.overruns.push(handle),
+        }
+    }
+
+    /// Returns the states to remove because they were over the budget.
+    pub(crate) fn take_overruns(&mut self) -> Vec<StateHandle> {
+        match self.watchdog.as_mut() {
+            Some(watchdog) => std::mem::take(&mut watchdog.overruns),
+            None => Vec::new(),
+        }
+    }
+
+    /// Gives the duration of a callback that started at `start` to the sink.
+    pub(crate) fn record(
+        &mut self,
+        start: Option<Duration>,
+        state_name: &str,
+        phase: LifecyclePhase,
+    ) {
+        if let Some(start) = start {
+            let duration = self.now().saturating_sub(start);
+            if let Some(sink) = self.sink.as_mut() {
+                sink.record(state_name, phase, duration);
+            }
+        }
+    }
+}
+
+impl<'a, S: ?Sized, E, A: Allocator> ScopedStateMachine<'a, S, E, A> {
+    /// Sets the sink that is given how long each callback of the states takes.
+    /// Without a sink, the callbacks are not timed.
+    pub fn set_telemetry_sink(&mut self, sink: Box<dyn TelemetrySink>) {
+        self.telemetry.sink = Some(sink);
+    }
+
+    /// Removes the telemetry sink and returns it.
+    pub fn take_telemetry_sink(&mut self) -> Option<Box<dyn TelemetrySink>> {
+        self.telemetry.sink.take()
+    }
+
+    /// Sets how long the `update` and `fixed_update` of a state may take, and what to do when
+    /// one takes longer, like a pathfinding stuck on a pathological case.
+    /// Without a budget or a telemetry sink, the updates are not timed.
+    pub fn set_update_budget(&mut self, budget: Duration, action: WatchdogAction) {
+        self.telemetry.watchdog = Some(Watchdog {
+            budget,
+            action,
+            overruns: Vec::new(),
+        });
+    }
+
+    /// Removes the update budget, see `set_update_budget`.
+    pub fn clear_update_budget(&mut self) {
+        self.telemetry.watchdog = None;
+    }
+
+    /// Removes the states that went over the update budget, with `WatchdogAction::ForcePop`.
+    pub(crate) fn remove_overruns(&mut self, state_data: &mut S) {
+        for handle in self.telemetry.take_overruns() {
+            log_warn!("removing a state over the update budget");
+            self.remove(handle, state_data);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    type Phases = Vec<(String, LifecyclePhase)>;
+
+    struct Recording(Phases);
+
+    impl TelemetrySink for Recording {
+        fn record(&mut self, state_name: &str, phase: LifecyclePhase, _duration: Duration) {
+            self.0.push((state_name.to_string(), phase));
+        }
+    }
+
+    struct Named(&'static str);
+
+    impl State<()> for Named {
+        fn name(&self) -> &str {
+            self.0
+        }
+    }
+
+    #[test]
+    fn phase_order() {
+        let sink = Arc::new(Mutex::new(Recording(Phases::new())));
+        let mut sm = StateMachine::<()>::default();
+        sm.set_telemetry_sink(Box::new(sink.clone()));
+
+        sm.push(Box::new(Named("a")), &mut ());
+        sm.push(Box::new(Named("b")), &mut ());
+        sm.update(&mut ());
+        sm.set_fixed_timestep(Duration::from_millis(10));
+        sm.update_with_dt(Duration::from_millis(10), &mut ());
+        sm.pop(&mut ());
+        sm.stop(&mut ());
+
+        let phases = sink.lock().unwrap().0.clone();
+        let expected = vec![
+            ("a", LifecyclePhase::Start),
+            ("a", LifecyclePhase::Pause),
+            ("b", LifecyclePhase::Start),
+            ("b", LifecyclePhase::Update),
+            ("b", LifecyclePhase::FixedUpdate),
+            ("b", LifecyclePhase::Update),
+            ("b", LifecyclePhase::Stop),
+            ("a", LifecyclePhase::Resume),
+            ("a", LifecyclePhase::Stop),
+        ];
+        let expected: Phases = expected
+            .into_iter()
+            .map(|(name, phase)| (name.to_string(), phase))
+            .collect();
+        assert_eq!(phases, expected);
+    }
+
+    /// Busy-waits in its update for this long.
+    struct Spinning(Duration);
+
+    impl State<()> for Spinning {
+        fn name(&self) -> &str {
+            "spinning"
+        }
+
+        fn update(&mut self, _: &mut ()) -> StateTransition<()> {
+            let start = std::time::Instant::now();
+            while start.elapsed() < self.0 {}
+            StateTransition::None
+        }
+    }
+
+    #[test]
+    fn update_budget() {
+        let overruns = Arc::new(Mutex::new(Phases::new()));
+        let mut sm = StateMachine::<()>::default();
+        let callback = overruns.clone();
+        sm.set_update_budget(
+            Duration::from_millis(5),
+            WatchdogAction::Callback(Box::new(move |name, phase, duration| {
+                assert!(duration > Duration::from_millis(5));
+                callback.lock().unwrap().push((name.to_string(), phase));
+            })),
+        );
+        sm.push(Box::new(Spinning(Duration::default())), &mut ());
+        sm.update(&mut ());
+        assert!(overruns.lock().unwrap().is_empty());
+
+        sm.push(Box::new(Spinning(Duration::from_millis(10))), &mut ());
+        sm.update(&mut ());
+        assert_eq!(
+            *overruns.lock().unwrap(),
+            [("spinning".to_string(), LifecyclePhase::Update)]
+        );
+    }
+
+    #[test]
+    fn force_pop_over_budget() {
+        let mut sm = StateMachine::<()>::default();
+        sm.set_update_budget(Duration::from_millis(5), WatchdogAction::ForcePop);
+        sm.push(Box::new(Named("a")), &mut ());
+        sm.push(Box::new(Spinning(Duration::from_millis(10))), &mut ());
+        sm.update(&mut ());
+        assert_eq!(sm.len(), 2);
+        sm.update(&mut ());
+        assert_eq!(sm.len(), 1);
+        assert_eq!(sm.active().map(|state| state.name()), Some("a"));
+    }
+
+    #[test]
+    fn histogram() {
+        let mut sink = HistogramSink::new();
+        sink.record("a", LifecyclePhase::Update, Duration::from_nanos(500));
+        sink.record("a", LifecyclePhase::Update, Duration::from_micros(3));
+        sink.record("a", LifecyclePhase::Update, Duration::from_secs(1));
+
+        let histogram = sink.get("a", LifecyclePhase::Update).unwrap();
+        assert_eq!(histogram.count(), 3);
+        assert_eq!(histogram.max(), Duration::from_secs(1));
+        assert_eq!(histogram.buckets()[0], 1);
+        assert_eq!(histogram.buckets()[2], 1);
+        assert_eq!(histogram.buckets()[Histogram::BUCKETS - 1], 1);
+        assert!(sink.get("a", LifecyclePhase::Start).is_none());
+    }
+}
@@ -0,0 +1,952 @@
+//! Helpers to test state machines and the code driving them without writing states for each
+//! test.
+
+use crate::{
+    AsAny, MachineSnapshot, Payload, PopTarget, State, StateMachine, StateTransition,
+    TransitionEvent, TransitionKind, TransitionObserver,
+};
+use std::cell::Cell;
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fmt::{self, Write};
+use std::sync::{Arc, Mutex};
+
+/// How many times the hooks of a `ScriptedState` were called.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct HookCounts {
+    /// The calls of `State::on_start`.
+    pub starts: u32,
+    /// The calls of `State::on_stop`.
+    pub stops: u32,
+    /// The calls of `State::on_pause`.
+    pub pauses: u32,
+    /// The calls of `State::on_resume`.
+    pub resumes: u32,
+    /// The calls of `State::update`.
+    pub updates: u32,
+    /// The calls of `State::shadow_update`.
+    pub shadow_updates: u32,
+}
+
+/// Reads the hook counts of a `ScriptedState` after it was pushed on a machine.
+/// Its clones share the same counts.
+#[derive(Debug, Clone, Default)]
+pub struct HookCounter {
+    counts: Arc<Mutex<HookCounts>>,
+}
+
+impl HookCounter {
+    /// Returns the hook counts so far.
+    pub fn counts(&self) -> HookCounts {
+        *self.counts.lock().unwrap()
+    }
+
+    fn count(&self, hook: impl FnOnce(&mut HookCounts) -> &mut u32) {
+        *hook(&mut self.counts.lock().unwrap()) += 1;
+    }
+}
+
+/// A state returning the transitions of a script from its updates, one per update, and counting
+/// the calls of its hooks.
+///
+/// Once the script is over, the state returns the transition given to `then` once, then
+/// `StateTransition::None`.
+pub struct ScriptedState<S: ?Sized, E = ()> {
+    script: VecDeque<StateTransition<S, E>>,
+    then: Option<StateTransition<S, E>>,
+    counter: HookCounter,
+}
+
+impl<S: ?Sized, E> ScriptedState<S, E> {
+    /// Creates a state playing back this script.
+    pub fn new(script: Vec<StateTransition<S, E>>) -> Self {
+        Self {
+            script: script.into(),
+            then: None,
+            counter: HookCounter::default(),
+        }
+    }
+
+    /// Returns this transition from the first update after the script is over.
+    pub fn then(mut self, transition: StateTransition<S, E>) -> Self {
+        self.then = Some(transition);
+        self
+    }
+
+    /// Returns a counter reading the hook counts of this state.
+    pub fn counter(&self) -> HookCounter {
+        self.counter.clone()
+    }
+}
+
+impl<S: ?Sized + 'static, E: 'static> State<S, E> for ScriptedState<S, E> {
+    fn on_start(&mut self, _state_data: &mut S) -> StateTransition<S, E> {
+        self.counter.count(|counts| &mut counts.starts);
+        StateTransition::None
+    }
+
+    fn on_stop(&mut self, _state_data: &mut S) -> StateTransition<S, E> {
+        self.counter.count(|counts| &mut counts.stops);
+        StateTransition::None
+    }
+
+    fn on_pause(&mut self, _state_data: &mut S) {
+        self.counter.count(|counts| &mut counts.pauses);
+    }
+
+    fn on_resume(&mut self, _state_data: &mut S) -> StateTransition<S, E> {
+        self.counter.count(|counts| &mut counts.resumes);
+        StateTransition::None
+    }
+
+    fn update(&mut self, _state_data: &mut S) -> StateTransition<S, E> {
+        self.counter.count(|counts| &mut counts.updates);
+        self.script
+            .pop_front()
+            .or_else(|| self.then.take())
+            .unwrap_or(StateTransition::None)
+    }
+
+    fn shadow_update(&mut self, _state_data: &mut S) {
+        self.counter.count(|counts| &mut counts.shadow_updates);
+    }
+}
+
+/// A hook of a state, as recorded by a `RecordingState`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LifecycleCall {
+    /// `State::on_start`.
+    Start,
+    /// `State::on_stop`.
+    Stop,
+    /// `State::on_quit`.
+    Quit,
+    /// `State::on_pause`.
+    Pause,
+    /// `State::on_resume`.
+    Resume,
+    /// `State::update`.
+    Update,
+    /// `State::shadow_update`.
+    ShadowUpdate,
+}
+
+/// The hooks called on `RecordingState`s, in order, with the name of the state.
+pub type CallLog = Arc<Mutex<Vec<(String, LifecycleCall)>>>;
+
+/// A state recording the calls of its hooks in a log shared with other states, to check the order
+/// the machine calls them in.
+pub struct RecordingState<S: ?Sized, E = ()> {
+    name: String,
+    tags: Vec<&'static str>,
+    calls: CallLog,
+    update: Option<StateTransition<S, E>>,
+}
+
+impl<S: ?Sized, E> RecordingState<S, E> {
+    /// Creates a state with this name, recording its calls in this log.
+    pub fn new(name: impl Into<String>, calls: &CallLog) -> Self {
+        Self {
+            name: name.into(),
+            tags: Vec::new(),
+            calls: calls.clone(),
+            update: None,
+        }
+    }
+
+    /// Returns this transition from the first update of the state.
+    pub fn returning(mut self, transition: StateTransition<S, E>) -> Self {
+        self.update = Some(transition);
+        self
+    }
+
+    /// Adds a tag to the state, see `State::tags`.
+    pub fn with_tag(mut self, tag: &'static str) -> Self {
+        self.tags.push(tag);
+        self
+    }
+
+    fn record(&self, call: LifecycleCall) {
+        self.calls.lock().unwrap().push((self.name.clone(), call));
+    }
+}
+
+impl<S: ?Sized + 'static, E: 'static> State<S, E> for RecordingState<S, E> {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn tags(&self) -> &[&'static str] {
+        &self.tags
+    }
+
+    fn on_start(&mut self, _state_data: &mut S) -> StateTransition<S, E> {
+        self.record(LifecycleCall::Start);
+        StateTransition::None
+    }
+
+    fn on_stop(&mut self, _state_data: &mut S) -> StateTransition<S, E> {
+        self.record(LifecycleCall::Stop);
+        StateTransition::None
+    }
+
+    fn on_quit(&mut self, _state_data: &mut S) {
+        self.record(LifecycleCall::Quit);
+    }
+
+    fn on_pause(&mut self, _state_data: &mut S) {
+        self.record(LifecycleCall::Pause);
+    }
+
+    fn on_resume(&mut self, _state_data: &mut S) -> StateTransition<S, E> {
+        self.record(LifecycleCall::Resume);
+        StateTransition::None
+    }
+
+    fn update(&mut self, _state_data: &mut S) -> StateTransition<S, E> {
+        self.record(LifecycleCall::Update);
+        self.update.take().unwrap_or(StateTransition::None)
+    }
+
+    fn shadow_update(&mut self, _state_data: &mut S) {
+        self.record(LifecycleCall::ShadowUpdate);
+    }
+}
+
+/// Checks that the log holds these calls, then clears it so the next check only sees the calls
+/// made after this one.
+///
+/// Panics with the expected and recorded calls side by side if they differ, marking the lines
+/// which differ with `>`.
+#[track_caller]
+pub fn assert_calls(calls: &CallLog, expected: &[(&str, LifecycleCall)]) {
+    let recorded = std::mem::take(&mut *calls.lock().unwrap());
+    let same = recorded.len() == expected.len()
+        && recorded
+            .iter()
+            .zip(expected)
+            .all(|((name, call), (expected_name, expected_call))| {
+                name == expected_name && call == expected_call
+            });
+    if same {
+        return;
+    }
+
+    let expected: Vec<String> = expected
+        .iter()
+        .map(|(name, call)| format!("{}:{:?}", name, call))
+        .collect();
+    let recorded: Vec<String> = recorded
+        .iter()
+        .map(|(name, call)| format!("{}:{:?}", name, call))
+        .collect();
+    panic!(
+        "{}",
+        side_by_side("the recorded calls differ", &expected, &recorded)
+    );
+}
+
+/// Formats the expected and recorded lines side by side under the title, marking the lines which
+/// differ with `>`.
+fn side_by_side(title: &str, expected: &[String], recorded: &[String]) -> String {
+    let mut diff = format!("{}\n  expected                  recorded\n", title);
+    for index in 0..recorded.len().max(expected.len()) {
+        let expected = expected.get(index).map_or("", String::as_str);
+        let recorded = recorded.get(index).map_or("", String::as_str);
+        let marker = if expected == recorded { ' ' } else { '>' };
+        let _ = writeln!(diff, "{} {:<25} {}", marker, expected, recorded);
+    }
+    diff
+}
+
+/// Returns the name of a type without its path, like `Inventory` for `game::Inventory`, keeping
+/// its generic parameters. Other names are returned as they are.
+fn short_name(name: &str) -> &str {
+    let end = name.find('<').unwrap_or(name.len());
+    match name[..end].rfind("::") {
+        Some(index) => &name[index + 2..],
+        None => name,
+    }
+}
+
+/// Checks that the states of the machine have these names, from the bottom to the top, see
+/// `assert_stack!`. A state also matches the name of its type without the path, so the states
+/// keeping the default `State::name` match `"Inventory"` rather than `"game::Inventory"`.
+///
+/// Panics with the expected and actual stacks side by side if they differ, like `assert_calls`.
+#[track_caller]
+pub fn assert_stack_names<S: ?Sized, E>(state_machine: &StateMachine<S, E>, expected: &[&str]) {
+    let actual: Vec<&str> = state_machine.iter().map(|state| state.name()).collect();
+    let same = actual.len() == expected.len()
+        && actual
+            .iter()
+            .zip(expected)
+            .all(|(name, expected)| name == expected || short_name(name) == *expected);
+    if same {
+        return;
+    }
+
+    // The names which match are shown as expected, so only the differences are marked.
+    let recorded: Vec<String> = actual
+        .iter()
+        .enumerate()
+        .map(|(index, name)| match expected.get(index) {
+            Some(expected) if short_name(name) == *expected => expected.to_string(),
+            _ => name.to_string(),
+        })
+        .collect();
+    let expected: Vec<String> = expected.iter().map(|name| name.to_string()).collect();
+    panic!(
+        "{}",
+        side_by_side(
+            "the stack differs, from the bottom to the top",
+            &expected,
+            &recorded
+        )
+    );
+}
+
+/// Checks that the state machine has states with these names, from the bottom to the top, with
+/// `assert_stack_names`.
+///
+/// ```
+/// # use game_state_machine::*;
+/// struct Overworld;
+/// impl State<()> for Overworld {}
+/// struct Inventory;
+/// impl State<()> for Inventory {}
+///
+/// let mut sm = StateMachine::<()>::default();
+/// sm.push(Box::new(Overworld), &mut ());
+/// sm.push(Box::new(Inventory), &mut ());
+/// assert_stack!(sm, ["Overworld", "Inventory"]);
+/// ```
+#[macro_export]
+macro_rules! assert_stack {
+    ($state_machine:expr, [$($name:expr),* $(,)?]) => {
+        $crate::assert_stack_names(&$state_machine, &[$($name),*])
+    };
+}
+
+/// Checks that the active state of the machine is a `T`.
+///
+/// Panics with the name of the active state if it is not.
+#[track_caller]
+pub fn assert_active<T: 'static>(state_machine: &StateMachine<impl Sized, impl Sized>) {
+    let expected = std::any::type_name::<T>();
+    match state_machine.active() {
+        Some(state) if <dyn AsAny>::as_any(state).is::<T>() => (),
+        Some(state) => panic!(
+            "the active state is {}, not a {}",
+            state.name(),
+            short_name(expected)
+        ),
+        None => panic!(
+            "the machine has no state, instead of a {}",
+            short_name(expected)
+        ),
+    }
+}
+
+/// Records the kinds of the transitions a machine performs, see `assert_transitions`.
+struct KindRecorder(Arc<Mutex<Vec<TransitionKind>>>);
+
+impl<S: ?Sized> TransitionObserver<S> for KindRecorder {
+    fn on_transition(&mut self, event: &TransitionEvent, _state_data: &mut S) {
+        self.0.lock().unwrap().push(event.kind);
+    }
+}
+
+/// Runs the closure on the machine, then checks that it performed transitions of these kinds,
+/// in order, as reported to its observers, see `StateMachine::add_observer`. So the transitions
+/// returned by the states or queued are checked, while the direct calls like `StateMachine::pop`
+/// are not. Returns what the closure returned.
+///
+/// Panics with the expected and performed kinds side by side if they differ.
+#[track_caller]
+pub fn assert_transitions<S: ?Sized, E, R>(
+    state_machine: &mut StateMachine<S, E>,
+    expected: &[TransitionKind],
+    run: impl FnOnce(&mut StateMachine<S, E>) -> R,
+) -> R {
+    let kinds = Arc::new(Mutex::new(Vec::new()));
+    let id = state_machine.add_observer(Box::new(KindRecorder(kinds.clone())));
+    let result = run(state_machine);
+    state_machine.remove_observer(id);
+
+    let performed = std::mem::take(&mut *kinds.lock().unwrap());
+    if performed != expected {
+        let expected: Vec<String> = expected.iter().map(|kind| format!("{:?}", kind)).collect();
+        let performed: Vec<String> = performed.iter().map(|kind| format!("{:?}", kind)).collect();
+        panic!(
+            "{}",
+            side_by_side("the performed transitions differ", &expected, &performed)
+        );
+    }
+    result
+}
+
+/// Runs the closure on the machine, checking the kinds of the transitions it performed with
+/// `assert_transitions`.
+///
+/// ```
+/// # use game_state_machine::*;
+/// struct Menu;
+/// impl State<()> for Menu {}
+///
+/// let mut sm = StateMachine::<()>::default();
+/// expect_transitions!(sm, [TransitionKind::Push, TransitionKind::Pop], |sm| {
+///     sm.queue_transition(StateTransition::Push(Box::new(Menu)));
+///     sm.queue_transition(StateTransition::Pop);
+///     sm.update(&mut ());
+/// });
+/// ```
+#[macro_export]
+macro_rules! expect_transitions {
+    ($state_machine:expr, [$($kind:expr),* $(,)?], $run:expr) => {
+        $crate::assert_transitions(&mut $state_machine, &[$($kind),*], $run)
+    };
+}
+
+/// A check of a `Step`, with the description reported when it fails.
+type Check = (String, Box<dyn Fn(&MachineSnapshot) -> bool>);
+
+/// A step of a `Simulator`: a change of the state data, like pressing a button, then an update
+/// of the machine, then checks of the machine once updated.
+pub struct Step<S> {
+    change: Box<dyn FnOnce(&mut S)>,
+    checks: Vec<Check>,
+}
+
+impl<S> Step<S> {
+    /// Creates a step changing the state data before the update.
+    pub fn new(change: impl FnOnce(&mut S) + 'static) -> Self {
+        Self {
+            change: Box::new(change),
+            checks: Vec::new(),
+        }
+    }
+
+    /// Creates a step only updating the machine.
+    pub fn update() -> Self {
+        Self::new(|_| ())
+    }
+
+    /// Checks the snapshot of the machine after the update, see `StateMachine::snapshot`.
+    /// The description is reported if the check fails.
+    pub fn expect(
+        mut self,
+        description: impl Into<String>,
+        check: impl Fn(&MachineSnapshot) -> bool + 'static,
+    ) -> Self {
+        self.checks.push((description.into(), Box::new(check)));
+        self
+    }
+
+    /// Checks that the state with this name is active after the update.
+    pub fn expect_active(self, name: &'static str) -> Self {
+        self.expect(format!("the active state is {}", name), move |snapshot| {
+            snapshot
+                .states
+                .last()
+                .is_some_and(|entry| entry.name == name)
+        })
+    }
+
+    /// Checks that the machine is stopped after the update.
+    pub fn expect_stopped(self) -> Self {
+        self.expect("the machine is stopped", |snapshot| {
+            snapshot.states.is_empty()
+        })
+    }
+}
+
+/// A failed check of a `Simulator`.
+#[derive(Debug, Clone)]
+pub struct SimulationFailure {
+    /// The index of the step which failed.
+    pub step: usize,
+    /// The description of the check which failed.
+    pub check: String,
+    /// The snapshot of the machine the check failed on.
+    pub snapshot: MachineSnapshot,
+}
+
+impl fmt::Display for SimulationFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names: Vec<&str> = self
+            .snapshot
+            .states
+            .iter()
+            .map(|entry| entry.name.as_str())
+            .collect();
+        write!(
+            f,
+            "step {} failed: expected {}, with the stack {:?}",
+            self.step, self.check, names
+        )
+    }
+}
+
+impl Error for SimulationFailure {}
+
+/// Drives a state machine headlessly through a script of steps, to write the flows of the
+/// states, like a menu flow, as data.
+///
+/// ```rust,ignore
+/// let mut simulator = Simulator::new(state_machine, Input::default())
+///     .step(Step::new(|input| input.confirm = true).expect_active("menu"))
+///     .step(Step::new(|input| input.back = true).expect_active("title"));
+/// simulator.run().unwrap();
+/// ```
+pub struct Simulator<S, E = ()> {
+    state_machine: StateMachine<S, E>,
+    state_data: S,
+    steps: VecDeque<Step<S>>,
+    performed: usize,
+}
+
+impl<S, E> Simulator<S, E> {
+    /// Creates a simulator driving this machine with this state data.
+    pub fn new(state_machine: StateMachine<S, E>, state_data: S) -> Self {
+        Self {
+            state_machine,
+            state_data,
+            steps: VecDeque::new(),
+            performed: 0,
+        }
+    }
+
+    /// Adds a step after the others.
+    pub fn step(mut self, step: Step<S>) -> Self {
+        self.steps.push_back(step);
+        self
+    }
+
+    /// Performs the steps in order, and stops at the first failed check.
+    /// The performed steps are removed, so steps added afterwards are performed by the next run,
+    /// and their indices follow the ones of the steps already performed.
+    pub fn run(&mut self) -> Result<(), SimulationFailure> {
+        while let Some(step) = self.steps.pop_front() {
+            let index = self.performed;
+            self.performed += 1;
+            (step.change)(&mut self.state_data);
+            self.state_machine.update(&mut self.state_data);
+            let snapshot = self.state_machine.snapshot();
+            if let Some((check, _)) = step.checks.iter().find(|(_, check)| !check(&snapshot)) {
+                return Err(SimulationFailure {
+                    step: index,
+                    check: check.clone(),
+                    snapshot,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the machine driven by the simulator.
+    pub fn state_machine(&self) -> &StateMachine<S, E> {
+        &self.state_machine
+    }
+
+    /// Returns the machine driven by the simulator mutably, along with the state data, for example
+    /// to push a state between two runs.
+    pub fn parts_mut(&mut self) -> (&mut StateMachine<S, E>, &mut S) {
+        (&mut self.state_machine, &mut self.state_data)
+    }
+
+    /// Returns the state data.
+    pub fn state_data(&self) -> &S {
+        &self.state_data
+    }
+}
+
+/// Performs a transition of this kind on the machine, as if the active state returned it, with
+/// the states made by `make_state`. This drives a machine with the kinds picked by a fuzzer or a
+/// property test, see `TransitionKind::ALL`.
+///
+/// The transitions carry this data:
+/// - `PopWith` gives `()` to the state below.
+/// - `PopN` pops 2 states.
+/// - `PopUntil` pops until the second state from the top.
+/// - `PopTagged` pops the states tagged `"fuzz"`.
+/// - `PopTo` pops to the bottom state.
+/// - `PopGroup` pops the group of the active state, if it is in one.
+/// - `PushMany` pushes 2 states.
+/// - `ReplaceAll` replaces the stack with 2 states.
+/// - `Sequence` pops then pushes a state.
+/// - `Custom` pops with `StateMachine::pop`.
+/// - `Fallback` does nothing, since no state returns it.
+pub fn apply_kind<S: ?Sized + 'static, E: 'static>(
+    state_machine: &mut StateMachine<S, E>,
+    kind: TransitionKind,
+    state_data: &mut S,
+    make_state: &mut impl FnMut() -> Box<dyn State<S, E>>,
+) {
+    let transition = match kind {
+        TransitionKind::Pop => StateTransition::Pop,
+        TransitionKind::PopWith => StateTransition::PopWith(Box::new(()) as Payload),
+        TransitionKind::PopN => StateTransition::PopN(2),
+        TransitionKind::PopUntil => {
+            let checked = Cell::new(0);
+            StateTransition::PopUntil(Box::new(move |_| {
+                checked.set(checked.get() + 1);
+                checked.get() == 2
+            }))
+        }
+        TransitionKind::PopTagged => StateTransition::PopTagged("fuzz"),
+        TransitionKind::PopTo => {
+            let bottom = state_machine.iter().next().map(|state| state.name());
+            StateTransition::PopTo(PopTarget::Name(bottom.unwrap_or_default().to_string()))
+        }
+        TransitionKind::PopGroup => match state_machine.active_group() {
+            Some(group) => StateTransition::PopGroup(group),
+            None => StateTransition::None,
+        },
+        TransitionKind::Push => StateTransition::Push(make_state()),
+        TransitionKind::PushMany => StateTransition::PushMany(vec![make_state(), make_state()]),
+        TransitionKind::Switch => StateTransition::Switch(make_state()),
+        TransitionKind::Replace => StateTransition::Replace(make_state()),
+        TransitionKind::ReplaceAll => StateTransition::ReplaceAll(vec![make_state(), make_state()]),
+        TransitionKind::InsertBelow => StateTransition::InsertBelow(make_state()),
+        TransitionKind::PushBottom => StateTransition::PushBottom(make_state()),
+        TransitionKind::Quit => StateTransition::Quit,
+        TransitionKind::Sequence => StateTransition::Sequence(vec![
+            StateTransition::Pop,
+            StateTransition::Push(make_state()),
+        ]),
+        TransitionKind::Custom => StateTransition::Custom(Box::new(|state_machine, state_data| {
+            state_machine.pop(state_data);
+        })),
+        TransitionKind::Fallback => StateTransition::None,
+    };
+    state_machine.transition(transition, state_data);
+    state_machine.settle(state_data);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    /// The buttons pressed on this frame.
+    #[derive(Default)]
+    struct Input {
+        confirm: bool,
+        back: bool,
+    }
+
+    /// A screen of a menu flow, pushing or switching to the next screen on confirm, and popping
+    /// on back.
+    struct Screen(&'static str, fn() -> StateTransition<Input>);
+
+    impl State<Input> for Screen {
+        fn name(&self) -> &str {
+            self.0
+        }
+
+        fn update(&mut self, input: &mut Input) -> StateTransition<Input> {
+            if std::mem::take(&mut input.confirm) {
+                (self.1)()
+            } else if std::mem::take(&mut input.back) {
+                StateTransition::Pop
+            } else {
+                StateTransition::None
+            }
+        }
+    }
+
+    fn title() -> Box<dyn State<Input>> {
+        Box::new(Screen("title", || StateTransition::Push(options())))
+    }
+
+    fn options() -> Box<dyn State<Input>> {
+        Box::new(Screen("options", || StateTransition::Switch(credits())))
+    }
+
+    fn credits() -> Box<dyn State<Input>> {
+        Box::new(Screen("credits", || StateTransition::None))
+    }
+
+    #[test]
+    fn menu_flow() {
+        let mut sm = StateMachine::default();
+        sm.push(title(), &mut Input::default());
+        let mut simulator = Simulator::new(sm, Input::default())
+            .step(Step::update().expect_active("title"))
+            .step(
+                Step::new(|input: &mut Input| input.confirm = true)
+                    .expect_active("options")
+                    .expect("the title is paused", |snapshot| {
+                        snapshot.states[0].is_paused
+                    }),
+            )
+            .step(Step::new(|input: &mut Input| input.confirm = true).expect_active("credits"))
+            .step(Step::new(|input: &mut Input| input.back = true).expect_stopped());
+        assert!(simulator.run().is_ok());
+        assert!(!simulator.state_machine().is_running());
+
+        let (sm, input) = simulator.parts_mut();
+        sm.push(title(), input);
+        let failure = simulator
+            .step(Step::update().expect_active("title"))
+            .step(Step::new(|input: &mut Input| input.back = true).expect_active("title"))
+            .run()
+            .unwrap_err();
+        assert_eq!(failure.step, 5);
+        assert!(failure.snapshot.states.is_empty());
+        assert_eq!(
+            failure.to_string(),
+            "step 5 failed: expected the active state is title, with the stack []"
+        );
+    }
+
+    #[test]
+    fn host_loop_ends() {
+        let popped = ScriptedState::new(vec![StateTransition::Pop]);
+        let popped_counter = popped.counter();
+        let pushing = ScriptedState::new(vec![StateTransition::Push(Box::new(popped))])
+            .then(StateTransition::Quit);
+        let pushing_counter = pushing.counter();
+        let root = ScriptedState::new(vec![StateTransition::Push(Box::new(pushing))]);
+        let root_counter = root.counter();
+
+        let mut sm = StateMachine::<()>::new(Box::new(root));
+        sm.start(&mut ());
+        assert_eq!(sm.run(&mut (), |_| true), Some(ExitReason::Quit));
+
+        let pushing = pushing_counter.counts();
+        assert_eq!(
+            pushing,
+            HookCounts {
+                starts: 1,
+                stops: 1,
+                pauses: 1,
+                resumes: 1,
+                updates: 2,
+                shadow_updates: 1,
+            }
+        );
+        assert_eq!(popped_counter.counts().stops, 1);
+        assert_eq!(root_counter.counts().shadow_updates, 3);
+    }
+
+    #[test]
+    fn exhausted() {
+        let mut sm = StateMachine::<()>::default();
+        sm.push(Box::new(ScriptedState::new(Vec::new())), &mut ());
+        assert_eq!(sm.run_for(10, &mut ()), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "> b:Pause                   b:Stop")]
+    fn calls_diff() {
+        let calls = CallLog::default();
+        let mut sm = StateMachine::<()>::default();
+        sm.push(Box::new(RecordingState::new("a", &calls)), &mut ());
+        let b = RecordingState::new("b", &calls).returning(StateTransition::Pop);
+        sm.push(Box::new(b), &mut ());
+        sm.update(&mut ());
+        assert_calls(
+            &calls,
+            &[
+                ("a", LifecycleCall::Start),
+                ("a", LifecycleCall::Pause),
+                ("b", LifecycleCall::Start),
+                ("a", LifecycleCall::ShadowUpdate),
+                ("b", LifecycleCall::Update),
+                ("b", LifecycleCall::Pause),
+            ],
+        );
+    }
+
+    struct Overworld;
+
+    impl State<()> for Overworld {}
+
+    struct Inventory;
+
+    impl State<()> for Inventory {}
+
+    fn inventory_machine() -> StateMachine<()> {
+        let mut sm = StateMachine::<()>::default();
+        sm.push(Box::new(Overworld), &mut ());
+        sm.push(Box::new(Inventory), &mut ());
+        sm
+    }
+
+    #[test]
+    fn stack_names() {
+        let calls = CallLog::default();
+        let mut sm = inventory_machine();
+        sm.push(Box::new(RecordingState::new("tooltip", &calls)), &mut ());
+        assert_stack!(sm, ["Overworld", "Inventory", "tooltip"]);
+        assert_stack!(
+            sm,
+            [std::any::type_name::<Overworld>(), "Inventory", "tooltip",]
+        );
+        assert_eq!(
+            super::short_name("game::Pool<game::Item>"),
+            "Pool<game::Item>"
+        );
+        assert_eq!(super::short_name("pause menu"), "pause menu");
+    }
+
+    #[test]
+    #[should_panic(expected = "the stack differs, from the bottom to the top
+  expected                  recorded
+  Overworld                 Overworld
+> Tooltip                   game_state_machine::testing::tests::Inventory")]
+    fn stack_diff() {
+        assert_stack!(inventory_machine(), ["Overworld", "Tooltip"]);
+    }
+
+    #[test]
+    fn active_type() {
+        let mut sm = inventory_machine();
+        assert_active::<Inventory>(&sm);
+        sm.pop(&mut ());
+        assert_active::<Overworld>(&sm);
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "the active state is game_state_machine::testing::tests::Inventory, not a Overworld"
+    )]
+    fn active_type_differs() {
+        assert_active::<Overworld>(&inventory_machine());
+    }
+
+    #[test]
+    #[should_panic(expected = "the machine has no state, instead of a Inventory")]
+    fn no_active_state() {
+        assert_active::<Inventory>(&StateMachine::<()>::default());
+    }
+
+    #[test]
+    fn performed_transitions() {
+        let mut sm = inventory_machine();
+        let status = expect_transitions!(
+            sm,
+            [
+                TransitionKind::Push,
+                TransitionKind::PopN,
+                TransitionKind::Pop
+            ],
+            |sm: &mut StateMachine<()>| {
+                sm.queue_transition(StateTransition::Push(Box::new(Inventory)));
+                sm.queue_transition(StateTransition::PopN(2));
+                sm.pop(&mut ());
+                sm.queue_transition(StateTransition::Pop);
+                sm.update(&mut ())
+            }
+        );
+        assert_eq!(status, MachineStatus::Stopped(StopReason::EmptyStack));
+        expect_transitions!(sm, [], |_| ());
+    }
+
+    #[test]
+    #[should_panic(expected = "the performed transitions differ
+  expected                  recorded
+  Pop                       Pop
+> Pop                       ")]
+    fn transitions_diff() {
+        let mut sm = inventory_machine();
+        expect_transitions!(sm, [TransitionKind::Pop, TransitionKind::Pop], |sm| {
+            sm.queue_transition(StateTransition::Pop);
+            sm.update(&mut ());
+        });
+    }
+
+    /// Where a recorded state is in its lifecycle.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    enum Lifecycle {
+        Running,
+        Paused,
+        Stopped,
+    }
+
+    /// Checks the calls made since the last check against the lifecycle of each state, then
+    /// that the stack holds the running states, paused except the top one.
+    fn check(
+        calls: &CallLog,
+        lifecycles: &mut std::collections::HashMap<String, Lifecycle>,
+        sm: &StateMachine<()>,
+    ) -> Result<(), String> {
+        use Lifecycle::*;
+        for (name, call) in calls.lock().unwrap().drain(..) {
+            let lifecycle = lifecycles.get(&name).copied();
+            let next = match (lifecycle, call) {
+                (None, LifecycleCall::Start) => Running,
+                (Some(Running), LifecycleCall::Pause) => Paused,
+                (Some(Paused), LifecycleCall::Resume) => Running,
+                (Some(Running), LifecycleCall::Stop) | (Some(Paused), LifecycleCall::Stop) => {
+                    Stopped
+                }
+                (Some(Running), LifecycleCall::Update) => Running,
+                (Some(Paused), LifecycleCall::ShadowUpdate) => Paused,
+                (Some(lifecycle @ (Running | Paused)), LifecycleCall::Quit) => lifecycle,
+                (lifecycle, call) => {
+                    return Err(format!("{}: {:?} while {:?}", name, call, lifecycle));
+                }
+            };
+            lifecycles.insert(name, next);
+        }
+
+        let live = lifecycles
+            .values()
+            .filter(|lifecycle| **lifecycle != Stopped);
+        if live.count() != sm.len() {
+            return Err(format!("{} states on the stack", sm.len()));
+        }
+        for (index, state) in sm.iter().enumerate() {
+            let expected = if index + 1 == sm.len() {
+                Running
+            } else {
+                Paused
+            };
+            let lifecycle = lifecycles.get(state.name()).copied();
+            if lifecycle != Some(expected) {
+                return Err(format!("{} is {:?} at {}", state.name(), lifecycle, index));
+            }
+        }
+        Ok(())
+    }
+
+    proptest::proptest! {
+        #![proptest_config(proptest::prelude::ProptestConfig::with_cases(2000))]
+
+        #[test]
+        fn lifecycle_invariants(
+            steps in proptest::collection::vec(
+                (proptest::sample::select(TransitionKind::ALL.to_vec()), proptest::bool::ANY),
+                0..40,
+            ),
+        ) {
+            let calls = CallLog::default();
+            let mut lifecycles = std::collections::HashMap::new();
+            let mut made = 0;
+            let mut make_state = || -> Box<dyn State<()>> {
+                made += 1;
+                let state = RecordingState::new(format!("s{}", made), &calls);
+                match made % 3 {
+                    0 => Box::new(state.with_tag("fuzz")),
+                    _ => Box::new(state),
+                }
+            };
+
+            let mut sm = StateMachine::<()>::default();
+            sm.push(make_state(), &mut ());
+            for (kind, update) in steps {
+                apply_kind(&mut sm, kind, &mut (), &mut make_state);
+                if update {
+                    sm.update(&mut ());
+                }
+                let checked = check(&calls, &mut lifecycles, &sm);
+                proptest::prop_assert!(checked.is_ok(), "after {:?}: {:?}", kind, checked);
+            }
+            sm.force_stop(&mut ());
+            let checked = check(&calls, &mut lifecycles, &sm);
+            proptest::prop_assert!(checked.is_ok(), "after stopping: {:?}", checked);
+        }
+    }
+}
@@ -0,0 +1,134 @@
+//! Driving a state machine from the event loop of `winit`, which calls the application back
+//! instead of being polled.
+
+use crate::{State, StateMachine};
+use winit::application::ApplicationHandler;
+use winit::event::{DeviceEvent, DeviceId, WindowEvent};
+use winit::event_loop::{ActiveEventLoop, ControlFlow};
+use winit::window::WindowId;
+
+/// An event of `winit` given to the closure translating them into the events of the machine,
+/// see `StateMachineApp::new`.
+#[derive(Debug, Clone, Copy)]
+pub enum WinitEvent<'a> {
+    /// An event of a window.
+    Window(WindowId, &'a WindowEvent),
+    /// An event of a device, like the raw motion of the mouse.
+    Device(DeviceId, &'a DeviceEvent),
+}
+
+/// A `winit` application running a state machine, given to `EventLoop::run_app`.
+///
+/// The state data is built once the event loop is resumed, since the windows can only be created
+/// from then on, and the initial state is started with it. The events of `winit` are then
+/// translated and queued on the machine, to be handled during its next update, which happens with
+/// the render once the event loop is about to wait, polling continuously. Closing a window stops
+/// the machine, and the event loop exits once the machine stopped.
+pub struct StateMachineApp<S, E = ()> {
+    state_machine: StateMachine<S, E>,
+    state_data: Option<S>,
+    build: Box<dyn FnMut(&ActiveEventLoop) -> S>,
+    translate: Box<dyn FnMut(WinitEvent) -> Option<E>>,
+}
+
+impl<S, E> StateMachineApp<S, E> {
+    /// Creates an application starting with this state, building the state data with `build`
+    /// and translating the events of `winit` with `translate`, those translated to `None` being
+    /// dropped.
+    pub fn new(
+        initial_state: Box<dyn State<S, E>>,
+        build: impl FnMut(&ActiveEventLoop) -> S + 'static,
+        translate: impl FnMut(WinitEvent) -> Option<E> + 'static,
+    ) -> Self {
+        Self {
+            state_machine: StateMachine::new(initial_state),
+            state_data: None,
+            build: Box::new(build),
+            translate: Box::new(translate),
+        }
+    }
+
+    /// Returns the state machine, for example to know how it stopped once the event loop exited.
+    pub fn state_machine(&self) -> &StateMachine<S, E> {
+        &self.state_machine
+    }
+
+    /// Returns the state machine mutably.
+    pub fn state_machine_mut(&mut self) -> &mut StateMachine<S, E> {
+        &mut self.state_machine
+    }
+
+    /// Returns the state data, or `None` if the event loop was never resumed.
+    pub fn state_data(&self) -> Option<&S> {
+        self.state_data.as_ref()
+    }
+
+    /// Takes the state data, once the event loop exited.
+    pub fn into_state_data(self) -> Option<S> {
+        self.state_data
+    }
+
+    fn queue(&mut self, event: WinitEvent) {
+        if let Some(event) = (self.translate)(event) {
+            self.state_machine.push_event(event);
+        }
+    }
+}
+
+impl<S, E> ApplicationHandler for StateMachineApp<S, E> {
+    fn resumed(&mut self, event_loop: &ActiveEventLoop) {
+        if self.state_data.is_some() {
+            return;
+        }
+        event_loop.set_control_flow(ControlFlow::Poll);
+        let state_data = self.state_data.insert((self.build)(event_loop));
+        self.state_machine.start(state_data);
+    }
+
+    fn window_event(
+        &mut self,
+        _event_loop: &ActiveEventLoop,
+        window_id: WindowId,
+        event: WindowEvent,
+    ) {
+        self.queue(WinitEvent::Window(window_id, &event));
+        if event == WindowEvent::CloseRequested {
+            if let Some(state_data) = &mut self.state_data {
+                self.state_machine.stop(state_data);
+            }
+        }
+    }
+
+    fn device_event(
+        &mut self,
+        _event_loop: &ActiveEventLoop,
+        device_id: DeviceId,
+        event: DeviceEvent,
+    ) {
+        self.queue(WinitEvent::Device(device_id, &event));
+    }
+
+    fn about_to_wait(&mut self, event_loop: &ActiveEventLoop) {
+        let state_data = match &mut self.state_data {
+            Some(state_data) => state_data,
+            None => return,
+        };
+        if self.state_machine.is_running() {
+            self.state_machine.update(state_data);
+            self.state_machine.render(state_data);
+        }
+        if !self.state_machine.is_running() {
+            event_loop.exit();
+        }
+    }
+
+    /// Stops the states left when the event loop exits for another reason than the machine
+    /// stopping, without them being able to cancel it.
+    fn exiting(&mut self, _event_loop: &ActiveEventLoop) {
+        if let Some(state_data) = &mut self.state_data {
+            if self.state_machine.is_running() {
+                self.state_machine.force_stop(state_data);
+            }
+        }
+    }
+}
@@ -0,0 +1,91 @@
+//! Checks that a `StateMachine` created with `new_in` only allocates in its own allocator.
+//! This lives in its own test binary so the counting allocator only sees this test.
+
+use game_state_machine::*;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::{Cell, UnsafeCell};
+use std::ptr::NonNull;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::SeqCst);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// A bump allocator over a buffer, which never frees.
+struct Arena {
+    buffer: UnsafeCell<[u8; 4096]>,
+    used: Cell<usize>,
+    allocations: Cell<usize>,
+}
+
+unsafe impl Allocator for &Arena {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        let base = self.buffer.get() as usize;
+        let start = (base + self.used.get()).next_multiple_of(layout.align()) - base;
+        let end = start + layout.size();
+        if end > 4096 {
+            return Err(AllocError);
+        }
+        self.used.set(end);
+        self.allocations.set(self.allocations.get() + 1);
+        let ptr = unsafe { self.buffer.get().cast::<u8>().add(start) };
+        let slice = std::ptr::slice_from_raw_parts_mut(ptr, layout.size());
+        NonNull::new(slice).ok_or(AllocError)
+    }
+
+    unsafe fn deallocate(&self, _ptr: NonNull<u8>, _layout: Layout) {}
+}
+
+struct Counter(u32);
+
+impl State<u32> for Counter {
+    fn on_start(&mut self, data: &mut u32) -> StateTransition<u32> {
+        *data += self.0;
+        StateTransition::None
+    }
+
+    fn update(&mut self, _data: &mut u32) -> StateTransition<u32> {
+        StateTransition::Pop
+    }
+}
+
+#[test]
+fn machine_allocates_in_its_allocator() {
+    let arena = Arena {
+        buffer: UnsafeCell::new([0; 4096]),
+        used: Cell::new(0),
+        allocations: Cell::new(0),
+    };
+    let mut data = 0;
+
+    let before = ALLOCATIONS.load(Ordering::SeqCst);
+    let mut sm = StateMachine::new_in(&arena);
+    for step in 1..=3 {
+        sm.push_in(Counter(step), &mut data);
+    }
+    sm.pop(&mut data);
+    while sm.is_running() {
+        sm.update(&mut data);
+    }
+    drop(sm);
+    let after = ALLOCATIONS.load(Ordering::SeqCst);
+
+    assert_eq!(data, 6);
+    assert_eq!(before, after);
+    // The three states, the stack and the buffers of the machine.
+    assert!(arena.allocations.get() > 3);
+}
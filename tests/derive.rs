@@ -0,0 +1,113 @@
+//! Uses `#[derive(State)]` on enums of states.
+
+use game_state_machine::*;
+
+#[derive(Default)]
+struct GameData {
+    log: Vec<String>,
+}
+
+struct MenuState;
+
+impl State<GameData> for MenuState {
+    fn name(&self) -> &str {
+        "menu"
+    }
+
+    fn tags(&self) -> &[&'static str] {
+        &["menu"]
+    }
+
+    fn on_start(&mut self, data: &mut GameData) -> StateTransition<GameData> {
+        data.log.push("menu start".to_string());
+        StateTransition::None
+    }
+
+    fn update(&mut self, _data: &mut GameData) -> StateTransition<GameData> {
+        StateTransition::Push(Box::new(Screen::Game(GameState { frames: 2 })))
+    }
+
+    fn on_pause(&mut self, data: &mut GameData) {
+        data.log.push("menu pause".to_string());
+    }
+
+    fn on_resume(&mut self, data: &mut GameData) -> StateTransition<GameData> {
+        data.log.push("menu resume".to_string());
+        StateTransition::Quit
+    }
+}
+
+struct GameState {
+    frames: u32,
+}
+
+impl State<GameData> for GameState {
+    fn try_update(&mut self, data: &mut GameData) -> Result<StateTransition<GameData>, BoxError> {
+        data.log.push(format!("game {}", self.frames));
+        self.frames -= 1;
+        Ok(match self.frames {
+            0 => StateTransition::Pop,
+            _ => StateTransition::None,
+        })
+    }
+
+    fn on_stop(&mut self, data: &mut GameData) -> StateTransition<GameData> {
+        data.log.push("game stop".to_string());
+        StateTransition::None
+    }
+}
+
+#[derive(State)]
+#[state(data = GameData)]
+enum Screen {
+    Menu(MenuState),
+    Game(GameState),
+}
+
+/// A state for any data, to derive an enum which is a state for any data too.
+struct Idle;
+
+impl<S> State<S> for Idle {
+    fn name(&self) -> &str {
+        "idle"
+    }
+}
+
+#[derive(State)]
+enum Wrapper {
+    Idle(Idle),
+}
+
+#[test]
+fn forwards_to_the_variant() {
+    let mut data = GameData::default();
+    let mut sm = StateMachine::<GameData>::default();
+    sm.push(Box::new(Screen::Menu(MenuState)), &mut data);
+    assert_eq!(sm.active().map(|state| state.name()), Some("menu"));
+    assert_eq!(sm.count_with_tag("menu"), 1);
+
+    while sm.is_running() {
+        sm.update(&mut data);
+    }
+    assert_eq!(
+        data.log,
+        vec![
+            "menu start",
+            "menu pause",
+            "game 2",
+            "game 1",
+            "game stop",
+            "menu resume",
+        ]
+    );
+}
+
+#[test]
+fn generic_data() {
+    let mut sm = StateMachine::<u32>::default();
+    sm.push(Box::new(Wrapper::Idle(Idle)), &mut 0);
+    assert_eq!(sm.active().map(|state| state.name()), Some("idle"));
+    let mut sm = StateMachine::<String>::default();
+    sm.push(Box::new(Wrapper::Idle(Idle)), &mut String::new());
+    assert!(sm.is_running());
+}
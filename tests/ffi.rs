@@ -0,0 +1,84 @@
+//! Builds the crate as a static library, links the C host of `tests/ffi/host.c` against it with
+//! the header of `include/game_state_machine.h`, then runs the host. Only this test needs a C
+//! compiler, the library does not.
+
+#![cfg(unix)]
+
+use std::path::Path;
+use std::process::Command;
+
+/// The native libraries the standard library needs once linked in a static library, as printed
+/// by `rustc --print native-static-libs`.
+#[cfg(target_os = "linux")]
+const NATIVE_LIBS: &[&str] = &[
+    "-lgcc_s",
+    "-lutil",
+    "-lrt",
+    "-lpthread",
+    "-lm",
+    "-ldl",
+    "-lc",
+];
+#[cfg(not(target_os = "linux"))]
+const NATIVE_LIBS: &[&str] = &["-lc", "-lm"];
+
+/// Returns the target the tests are built for, which the host is built for too.
+fn target() -> String {
+    let output = Command::new(option_env!("RUSTC").unwrap_or("rustc"))
+        .arg("-vV")
+        .output()
+        .expect("rustc runs");
+    let version = String::from_utf8(output.stdout).expect("rustc prints UTF-8");
+    version
+        .lines()
+        .find_map(|line| line.strip_prefix("host: "))
+        .expect("rustc prints its host")
+        .to_string()
+}
+
+#[test]
+fn c_host() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let out = Path::new(env!("CARGO_TARGET_TMPDIR")).join("ffi");
+    let built = Command::new(env!("CARGO"))
+        .args([
+            "rustc",
+            "--lib",
+            "--features",
+            "ffi",
+            "--crate-type",
+            "staticlib",
+        ])
+        .arg("--target-dir")
+        .arg(&out)
+        .current_dir(root)
+        .status()
+        .expect("cargo runs");
+    assert!(built.success());
+
+    let target = target();
+    let compiler = cc::Build::new()
+        .target(&target)
+        .host(&target)
+        .opt_level(0)
+        .cargo_metadata(false)
+        .warnings(true)
+        .get_compiler();
+    let host = out.join("host");
+    let linked = compiler
+        .to_command()
+        .arg("-I")
+        .arg(root.join("include"))
+        .arg(root.join("tests/ffi/host.c"))
+        .arg(root.join("tests/ffi/main.c"))
+        .arg(out.join("debug/libgame_state_machine.a"))
+        .args(NATIVE_LIBS)
+        .arg("-o")
+        .arg(&host)
+        .status()
+        .expect("the C compiler runs");
+    assert!(linked.success());
+
+    let run = Command::new(&host).status().expect("the host runs");
+    assert_eq!(run.code(), Some(0));
+}
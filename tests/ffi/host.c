@@ -0,0 +1,259 @@
+/* A host driving a state machine through the C functions, run by `tests/ffi.rs`. */
+
+#include <string.h>
+
+#include "game_state_machine.h"
+
+typedef struct Log {
+    char calls[256];
+    int drops;
+} Log;
+
+static void record(Log *log, const char *call) {
+    strcat(log->calls, call);
+    strcat(log->calls, " ");
+}
+
+static GSM_Transition none(void) {
+    GSM_Transition transition = {GSM_TRANSITION_KIND_NONE, NULL, NULL};
+    return transition;
+}
+
+static GSM_Transition pause_update(void *user, void *data) {
+    GSM_Transition transition = {GSM_TRANSITION_KIND_POP, NULL, NULL};
+    (void)user;
+    record(data, "pause:update");
+    return transition;
+}
+
+static GSM_Transition pause_stop(void *user, void *data) {
+    (void)user;
+    record(data, "pause:stop");
+    return none();
+}
+
+static const GSM_StateVTable PAUSE = {
+    NULL, pause_stop, NULL, NULL, pause_update, NULL, NULL,
+};
+
+static GSM_Transition game_start(void *user, void *data) {
+    (void)user;
+    record(data, "game:start");
+    return none();
+}
+
+static GSM_Transition game_stop(void *user, void *data) {
+    (void)user;
+    record(data, "game:stop");
+    return none();
+}
+
+static void game_pause(void *user, void *data) {
+    (void)user;
+    record(data, "game:pause");
+}
+
+static GSM_Transition game_resume(void *user, void *data) {
+    (void)user;
+    record(data, "game:resume");
+    return none();
+}
+
+/* Pauses the game on its first update, and quits on the next one. */
+static GSM_Transition game_update(void *user, void *data) {
+    int *updates = user;
+    GSM_Transition transition = {GSM_TRANSITION_KIND_PUSH, &PAUSE, NULL};
+    record(data, "game:update");
+    *updates += 1;
+    if (*updates == 2) {
+        transition.kind = GSM_TRANSITION_KIND_QUIT;
+    }
+    return transition;
+}
+
+static int frames;
+
+static void game_render(void *user, void *data) {
+    (void)user;
+    (void)data;
+    frames += 1;
+}
+
+static Log *dropped_log;
+
+static void game_drop(void *user) {
+    (void)user;
+    dropped_log->drops += 1;
+}
+
+static GSM_Transition menu_start(void *user, void *data) {
+    (void)user;
+    record(data, "menu:start");
+    return none();
+}
+
+static GSM_Transition menu_stop(void *user, void *data) {
+    (void)user;
+    record(data, "menu:stop");
+    return none();
+}
+
+static void menu_pause(void *user, void *data) {
+    (void)user;
+    record(data, "menu:pause");
+}
+
+static GSM_Transition results_start(void *user, void *data) {
+    (void)user;
+    record(data, "results:start");
+    return none();
+}
+
+static GSM_Transition results_stop(void *user, void *data) {
+    (void)user;
+    record(data, "results:stop");
+    return none();
+}
+
+static const GSM_StateVTable RESULTS = {
+    results_start, results_stop, NULL, NULL, NULL, NULL, NULL,
+};
+
+static GSM_Transition level_start(void *user, void *data) {
+    (void)user;
+    record(data, "level:start");
+    return none();
+}
+
+static GSM_Transition level_stop(void *user, void *data) {
+    (void)user;
+    record(data, "level:stop");
+    return none();
+}
+
+/* Goes to the results on its first update, with the kind given as its user data. */
+static GSM_Transition level_update(void *user, void *data) {
+    GSM_Transition transition = {*(uint32_t *)user, &RESULTS, NULL};
+    record(data, "level:update");
+    return transition;
+}
+
+static const GSM_StateVTable MENU = {
+    menu_start, menu_stop, menu_pause, NULL, NULL, NULL, NULL,
+};
+
+static const GSM_StateVTable LEVEL = {
+    level_start, level_stop, NULL, NULL, level_update, NULL, NULL,
+};
+
+static const char REPLACED[] =
+    "menu:start menu:pause level:start level:update level:stop results:start ";
+
+/* A replace only replaces the top state, the menu below it stays. */
+static int replace_top(void) {
+    uint32_t kind = GSM_TRANSITION_KIND_REPLACE;
+    Log log = {{0}, 0};
+    GSM_Machine *machine = gsm_machine_new();
+
+    if (machine == NULL || gsm_machine_len(NULL) != 0 || !gsm_machine_push(machine, MENU, NULL) ||
+        !gsm_machine_push(machine, LEVEL, &kind)) {
+        return 6;
+    }
+    if (gsm_machine_update(machine, &log) != GSM_STATUS_RUNNING || gsm_machine_len(machine) != 2) {
+        return 7;
+    }
+    if (strcmp(log.calls, REPLACED) != 0) {
+        return 8;
+    }
+    gsm_machine_destroy(machine, &log);
+    if (strcmp(log.calls + strlen(REPLACED), "results:stop menu:stop ") != 0) {
+        return 9;
+    }
+    return 0;
+}
+
+static const char SWITCHED[] =
+    "menu:start menu:pause level:start level:update level:stop menu:stop results:start ";
+
+/* A switch removes all the states, the menu below included. */
+static int switch_all(void) {
+    uint32_t kind = GSM_TRANSITION_KIND_SWITCH;
+    Log log = {{0}, 0};
+    GSM_Machine *machine = gsm_machine_new();
+
+    if (machine == NULL || !gsm_machine_push(machine, MENU, NULL) ||
+        !gsm_machine_push(machine, LEVEL, &kind)) {
+        return 10;
+    }
+    if (gsm_machine_update(machine, &log) != GSM_STATUS_RUNNING || gsm_machine_len(machine) != 1) {
+        return 11;
+    }
+    if (strcmp(log.calls, SWITCHED) != 0) {
+        return 12;
+    }
+    gsm_machine_destroy(machine, &log);
+    return 0;
+}
+
+/* An unknown kind does nothing and is reported by the update. */
+static int unknown_kind(void) {
+    uint32_t kind = 42;
+    Log log = {{0}, 0};
+    GSM_Machine *machine = gsm_machine_new();
+
+    if (machine == NULL || !gsm_machine_push(machine, LEVEL, &kind)) {
+        return 13;
+    }
+    if (gsm_machine_update(machine, &log) != GSM_STATUS_UNKNOWN_TRANSITION ||
+        gsm_machine_len(machine) != 1 || !gsm_machine_is_running(machine)) {
+        return 14;
+    }
+    if (gsm_machine_update(machine, &log) != GSM_STATUS_UNKNOWN_TRANSITION) {
+        return 15;
+    }
+    gsm_machine_destroy(machine, &log);
+    return 0;
+}
+
+static const char EXPECTED[] =
+    "game:start game:update game:pause pause:update pause:stop game:resume game:update "
+    "game:stop ";
+
+int gsm_host_run(void) {
+    static const GSM_StateVTable GAME = {
+        game_start, game_stop, game_pause, game_resume, game_update, game_render, game_drop,
+    };
+    Log log = {{0}, 0};
+    int updates = 0;
+    int status;
+    GSM_Machine *machine;
+
+    if (gsm_machine_update(NULL, &log) != GSM_STATUS_INVALID || gsm_machine_is_running(NULL) ||
+        gsm_machine_push(NULL, GAME, &updates)) {
+        return 1;
+    }
+    gsm_machine_destroy(NULL, &log);
+
+    dropped_log = &log;
+    machine = gsm_machine_new();
+    if (machine == NULL || !gsm_machine_push(machine, GAME, &updates) ||
+        gsm_machine_is_running(machine)) {
+        return 2;
+    }
+    while ((status = gsm_machine_update(machine, &log)) == GSM_STATUS_RUNNING) {
+    }
+    if (status != GSM_STATUS_STOPPED || gsm_machine_is_running(machine)) {
+        return 3;
+    }
+    gsm_machine_destroy(machine, &log);
+    if (strcmp(log.calls, EXPECTED) != 0) {
+        return 4;
+    }
+    if (log.drops != 1 || frames != 2) {
+        return 5;
+    }
+    if ((status = replace_top()) != 0 || (status = switch_all()) != 0) {
+        return status;
+    }
+    return unknown_kind();
+}
@@ -0,0 +1,7 @@
+/* The entry point of the C host, built and run by `tests/ffi.rs`. */
+
+int gsm_host_run(void);
+
+int main(void) {
+    return gsm_host_run();
+}
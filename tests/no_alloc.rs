@@ -0,0 +1,64 @@
+//! Checks that the `ArrayStateMachine` never allocates.
+//! This lives in its own test binary so the counting allocator only sees this test.
+
+use game_state_machine::*;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::SeqCst);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+struct Counter<'a> {
+    next: Option<&'a mut dyn ArrayState<'a, u32>>,
+}
+
+impl<'a> ArrayState<'a, u32> for Counter<'a> {
+    fn on_start(&mut self, data: &mut u32) {
+        *data += 1;
+    }
+
+    fn update(&mut self, _data: &mut u32) -> ArrayTransition<'a, u32> {
+        match self.next.take() {
+            Some(next) => ArrayTransition::Push(next),
+            None => ArrayTransition::Quit,
+        }
+    }
+}
+
+#[test]
+fn array_machine_does_not_allocate() {
+    let mut data = 0;
+    let mut third = Counter { next: None };
+    let mut second = Counter {
+        next: Some(&mut third),
+    };
+    let mut first = Counter {
+        next: Some(&mut second),
+    };
+
+    let before = ALLOCATIONS.load(Ordering::SeqCst);
+    let mut sm = ArrayStateMachine::<u32, 4>::default();
+    sm.push(&mut first, &mut data).unwrap();
+    while sm.is_running() {
+        sm.update(&mut data).unwrap();
+    }
+    let after = ALLOCATIONS.load(Ordering::SeqCst);
+
+    assert_eq!(data, 3);
+    assert_eq!(before, after);
+}
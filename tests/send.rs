@@ -0,0 +1,29 @@
+//! Pins which types are `Send` with and without the `send` feature.
+
+use game_state_machine::*;
+use static_assertions::{assert_impl_all, assert_not_impl_any};
+use std::rc::Rc;
+
+#[cfg(feature = "send")]
+assert_impl_all!(StateMachine<u32>: Send);
+#[cfg(feature = "send")]
+assert_impl_all!(StateTransition<u32>: Send);
+#[cfg(feature = "send")]
+assert_impl_all!(Box<dyn State<u32>>: Send);
+#[cfg(feature = "send")]
+assert_impl_all!(Payload: Send);
+#[cfg(feature = "send")]
+assert_impl_all!(BoxError: Send, Sync);
+#[cfg(feature = "send")]
+assert_not_impl_any!(Rc<u32>: MaybeSend);
+#[cfg(feature = "send")]
+assert_impl_all!(SharedStateMachine<u32>: Send, Sync, Clone);
+#[cfg(feature = "send")]
+assert_impl_all!(TransitionSender<u32>: Send, Clone);
+
+#[cfg(not(feature = "send"))]
+assert_not_impl_any!(StateMachine<u32>: Send);
+#[cfg(not(feature = "send"))]
+assert_not_impl_any!(Box<dyn State<u32>>: Send);
+#[cfg(not(feature = "send"))]
+assert_impl_all!(Rc<u32>: MaybeSend);
@@ -0,0 +1,66 @@
+//! Drives a machine with the time-based features the way a browser game would. Runs natively,
+//! and on `wasm32-unknown-unknown` with `wasm-pack test --node -- --features wasm`.
+
+use game_state_machine::*;
+use std::time::Duration;
+
+#[derive(Default)]
+struct Frames {
+    fixed: u32,
+    updates: u32,
+}
+
+struct Game;
+
+impl State<Frames> for Game {
+    fn update(&mut self, frames: &mut Frames) -> StateTransition<Frames> {
+        frames.updates += 1;
+        StateTransition::None
+    }
+
+    fn fixed_update(&mut self, frames: &mut Frames) -> StateTransition<Frames> {
+        frames.fixed += 1;
+        StateTransition::None
+    }
+}
+
+struct Timings(u32);
+
+impl TelemetrySink for Timings {
+    fn record(&mut self, _state_name: &str, _phase: LifecyclePhase, _duration: Duration) {
+        self.0 += 1;
+    }
+}
+
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen_test::wasm_bindgen_test)]
+#[cfg_attr(not(target_arch = "wasm32"), test)]
+fn ticks_with_the_default_clock() {
+    let mut frames = Frames::default();
+    let mut sm = StateMachine::default();
+    sm.set_telemetry_sink(Box::new(Timings(0)));
+    let game = sm.push_state(Game, &mut frames);
+    for _ in 0..3 {
+        assert_eq!(sm.update_with_clock(&mut frames), MachineStatus::Running);
+    }
+    assert_eq!(frames.updates, 3);
+    assert!(sm.active_time(game).is_some());
+}
+
+#[cfg_attr(target_arch = "wasm32", wasm_bindgen_test::wasm_bindgen_test)]
+#[cfg_attr(not(target_arch = "wasm32"), test)]
+fn ticks_with_dt() {
+    let mut frames = Frames::default();
+    let mut sm = StateMachine::default();
+    sm.set_fixed_timestep(Duration::from_millis(10));
+    sm.push_state(Game, &mut frames);
+    sm.update_with_dt(Duration::from_millis(35), &mut frames);
+    assert_eq!(frames.fixed, 3);
+
+    let clock = ManualClock::new();
+    sm.set_clock(clock.clone());
+    sm.update_with_clock(&mut frames);
+    clock.advance(Duration::from_millis(25));
+    sm.update_with_clock(&mut frames);
+    assert_eq!(frames.fixed, 6);
+    assert_eq!(frames.updates, 3);
+}